@@ -0,0 +1,395 @@
+//! Derive macros of `wgpu_render_manager`, enabled with its `derive` feature.
+//!
+//! See `wgpu_render_manager::typed_bind_group` for the generated trait and examples.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, Ident, Lit, Meta, Result};
+
+/// Implement `TypedBindGroup` for a struct of bind group resources.
+///
+/// Every field is one binding, numbered in declaration order from 0, and needs one of
+/// these attributes:
+/// - `#[sampler]`, `#[sampler(comparison)]`, `#[sampler(non_filtering)]`
+/// - `#[texture]`, with optional `sample = "float" | "unfilterable" | "depth" | "sint" | "uint"`,
+///   `dimension = "1d" | "2d" | "2d_array" | "cube" | "cube_array" | "3d"` and `multisampled`
+/// - `#[storage_texture(format = "rgba8unorm")]`, with optional
+///   `access = "read" | "write" | "read_write"` (default `write`) and `dimension`
+/// - `#[uniform(ty = "Params")]`, the WGSL type of the buffer
+/// - `#[storage(ty = "array<Particle>")]`, with optional `read_only`
+///
+/// Buffers take `min_size = 64`, the size of the struct the shader declares, which
+/// buffers are checked against when the bind group is created. They take `dynamic`
+/// together with `size = 256`, the bytes bound at the dynamic offset passed to
+/// `set_bind_group`, which is also their minimum size.
+///
+/// Any of them takes `visibility = "vertex | fragment | compute"`.
+#[proc_macro_derive(BindGroupLayout, attributes(sampler, texture, storage_texture, uniform, storage))]
+pub fn derive_bind_group_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+enum Kind {
+    Sampler { comparison: bool, filtering: bool },
+    Texture { sample: String, dimension: String, multisampled: bool },
+    StorageTexture { format: String, access: String, dimension: String },
+    Uniform { ty: String, buffer: BufferOptions },
+    Storage { ty: String, read_only: bool, buffer: BufferOptions },
+}
+
+#[derive(Clone, Copy)]
+struct BufferOptions {
+    /// Binding size of a buffer bound at dynamic offsets.
+    dynamic: Option<u64>,
+    min_size: Option<u64>,
+}
+
+struct Binding {
+    ident: Ident,
+    kind: Kind,
+    visibility: Option<String>,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(input.span(), "BindGroupLayout can only be derived for structs"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new(input.span(), "BindGroupLayout needs a struct with named fields"));
+    };
+    let bindings = fields
+        .named
+        .iter()
+        .map(|field| parse_binding(field.ident.clone().unwrap(), &field.attrs, field.span()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let krate = quote!(::wgpu_render_manager::typed_bind_group);
+    let wgpu = quote!(#krate::wgpu);
+    let name = &input.ident;
+    let label = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut entries = Vec::new();
+    let mut declarations = Vec::new();
+    let mut resources = Vec::new();
+    for (index, binding) in bindings.iter().enumerate() {
+        let index = index as u32;
+        let ident = &binding.ident;
+        let (ty, writable, resource) = binding_type(&binding.kind, &wgpu, ident)?;
+        let stages = visibility(binding.visibility.as_deref(), writable, &wgpu, ident)?;
+        entries.push(quote! {
+            #wgpu::BindGroupLayoutEntry {
+                binding: #index,
+                visibility: #stages,
+                ty: #ty,
+                count: ::core::option::Option::None,
+            }
+        });
+        let declaration = format!("@binding({}) var{} {}: {};\n", index, address_space(&binding.kind), ident, wgsl_type(&binding.kind));
+        declarations.push(declaration);
+        let resource_ty = match resource {
+            "Sampler" => quote!(#wgpu::Sampler),
+            "View" => quote!(#wgpu::TextureView),
+            _ => quote!(#wgpu::Buffer),
+        };
+        let variant = Ident::new(resource, ident.span());
+        let resource = quote!(#krate::Resource::<#resource_ty>::resource(&self.#ident));
+        resources.push(match dynamic_size(&binding.kind) {
+            Some(size) => quote!(#krate::BindingRef::DynamicBuffer(#resource, #size)),
+            None => quote!(#krate::BindingRef::#variant(#resource)),
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics #krate::TypedBindGroup for #name #ty_generics #where_clause {
+            const LABEL: &'static str = #label;
+
+            fn layout_entries() -> ::std::vec::Vec<#wgpu::BindGroupLayoutEntry> {
+                ::std::vec![#(#entries),*]
+            }
+
+            fn wgsl(group: u32) -> ::std::string::String {
+                let mut wgsl = ::std::string::String::new();
+                #(
+                    wgsl.push_str(&::std::format!("@group({}) ", group));
+                    wgsl.push_str(#declarations);
+                )*
+                wgsl
+            }
+
+            fn resources(&self) -> ::std::vec::Vec<#krate::BindingRef<'_>> {
+                ::std::vec![#(#resources),*]
+            }
+        }
+    })
+}
+
+fn parse_binding(ident: Ident, attrs: &[Attribute], span: proc_macro2::Span) -> Result<Binding> {
+    let mut found = None;
+    for attr in attrs {
+        let Some(name) = attr.path().get_ident().map(Ident::to_string) else {
+            continue;
+        };
+        if !matches!(name.as_str(), "sampler" | "texture" | "storage_texture" | "uniform" | "storage") {
+            continue;
+        }
+        if found.is_some() {
+            return Err(Error::new(attr.span(), "a field is exactly one binding"));
+        }
+        found = Some(parse_attribute(ident.clone(), &name, attr)?);
+    }
+    found.ok_or_else(|| Error::new(span, "missing binding attribute: #[sampler], #[texture], #[storage_texture], #[uniform] or #[storage]"))
+}
+
+fn parse_attribute(ident: Ident, name: &str, attr: &Attribute) -> Result<Binding> {
+    let mut flags = Vec::new();
+    let mut values: Vec<(String, Lit)> = Vec::new();
+    if !matches!(attr.meta, Meta::Path(_)) {
+        attr.parse_nested_meta(|meta| {
+            let key = meta.path.get_ident().map(Ident::to_string).unwrap_or_default();
+            if meta.input.peek(syn::Token![=]) {
+                values.push((key, meta.value()?.parse()?));
+            } else {
+                flags.push(key);
+            }
+            Ok(())
+        })?;
+    }
+    let lit = |key: &str| values.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    let value = |key: &str| match lit(key) {
+        Some(Lit::Str(s)) => Ok(Some(s.value())),
+        Some(other) => Err(Error::new(other.span(), format!("`{}` takes a string", key))),
+        None => Ok(None),
+    };
+    let int = |key: &str| match lit(key) {
+        Some(Lit::Int(i)) => i.base10_parse::<u64>().map(Some),
+        Some(other) => Err(Error::new(other.span(), format!("`{}` takes an integer", key))),
+        None => Ok(None),
+    };
+    let known: &[&str] = match name {
+        "sampler" => &["comparison", "non_filtering", "visibility"],
+        "texture" => &["sample", "dimension", "multisampled", "visibility"],
+        "storage_texture" => &["format", "access", "dimension", "visibility"],
+        "uniform" => &["ty", "dynamic", "size", "min_size", "visibility"],
+        _ => &["ty", "read_only", "dynamic", "size", "min_size", "visibility"],
+    };
+    if let Some(key) = flags.iter().chain(values.iter().map(|(k, _)| k)).find(|key| !known.contains(&key.as_str())) {
+        return Err(Error::new(attr.span(), format!("unknown #[{}] option `{}`", name, key)));
+    }
+    let required = |key: &str| value(key)?.ok_or_else(|| Error::new(attr.span(), format!("#[{}] needs `{} = \"...\"`", name, key)));
+    let dynamic = match (flags.iter().any(|f| f == "dynamic"), int("size")?) {
+        (true, Some(0)) => return Err(Error::new(attr.span(), "dynamic buffers need a nonzero `size`")),
+        (true, Some(size)) => Some(size),
+        (true, None) => return Err(Error::new(attr.span(), format!("#[{}(dynamic)] needs `size = <bytes>`", name))),
+        (false, Some(_)) => return Err(Error::new(attr.span(), "`size` is the binding size of `dynamic` buffers")),
+        (false, None) => None,
+    };
+    let min_size = match (int("min_size")?, dynamic) {
+        (Some(0), _) => return Err(Error::new(attr.span(), "`min_size` must be nonzero")),
+        (Some(_), Some(_)) => return Err(Error::new(attr.span(), "dynamic buffers use `size` as their minimum size")),
+        (min_size, _) => min_size,
+    };
+    let buffer = BufferOptions { dynamic, min_size };
+    let kind = match name {
+        "sampler" => Kind::Sampler {
+            comparison: flags.iter().any(|f| f == "comparison"),
+            filtering: !flags.iter().any(|f| f == "non_filtering"),
+        },
+        "texture" => Kind::Texture {
+            sample: value("sample")?.unwrap_or_else(|| "float".to_string()),
+            dimension: value("dimension")?.unwrap_or_else(|| "2d".to_string()),
+            multisampled: flags.iter().any(|f| f == "multisampled"),
+        },
+        "storage_texture" => Kind::StorageTexture {
+            format: required("format")?,
+            access: value("access")?.unwrap_or_else(|| "write".to_string()),
+            dimension: value("dimension")?.unwrap_or_else(|| "2d".to_string()),
+        },
+        "uniform" => Kind::Uniform { ty: required("ty")?, buffer },
+        _ => Kind::Storage {
+            ty: required("ty")?,
+            read_only: flags.iter().any(|f| f == "read_only"),
+            buffer,
+        },
+    };
+    Ok(Binding {
+        ident,
+        kind,
+        visibility: value("visibility")?,
+    })
+}
+
+/// Layout binding type, whether the binding is writable and the `BindingRef` variant.
+fn binding_type(kind: &Kind, wgpu: &TokenStream2, ident: &Ident) -> Result<(TokenStream2, bool, &'static str)> {
+    let invalid = |message: String| Error::new(ident.span(), message);
+    Ok(match kind {
+        Kind::Sampler { comparison, filtering } => {
+            let ty = match (comparison, filtering) {
+                (true, _) => quote!(Comparison),
+                (false, true) => quote!(Filtering),
+                (false, false) => quote!(NonFiltering),
+            };
+            (quote!(#wgpu::BindingType::Sampler(#wgpu::SamplerBindingType::#ty)), false, "Sampler")
+        }
+        Kind::Texture { sample, dimension, multisampled } => {
+            let sample_type = match sample.as_str() {
+                "float" if *multisampled => quote!(Float { filterable: false }),
+                "float" => quote!(Float { filterable: true }),
+                "unfilterable" => quote!(Float { filterable: false }),
+                "depth" => quote!(Depth),
+                "sint" => quote!(Sint),
+                "uint" => quote!(Uint),
+                other => return Err(invalid(format!("unknown texture sample type `{}`", other))),
+            };
+            if *multisampled && dimension != "2d" {
+                return Err(invalid("multisampled textures are 2d".to_string()));
+            }
+            if sample == "depth" && !matches!(dimension.as_str(), "2d" | "2d_array" | "cube" | "cube_array") {
+                return Err(invalid(format!("depth textures cannot be {}", dimension)));
+            }
+            let view_dimension = view_dimension(dimension, wgpu).ok_or_else(|| invalid(format!("unknown texture dimension `{}`", dimension)))?;
+            let ty = quote! {
+                #wgpu::BindingType::Texture {
+                    sample_type: #wgpu::TextureSampleType::#sample_type,
+                    view_dimension: #view_dimension,
+                    multisampled: #multisampled,
+                }
+            };
+            (ty, false, "View")
+        }
+        Kind::StorageTexture { format, access, dimension } => {
+            let format_variant = storage_format(format).ok_or_else(|| invalid(format!("`{}` is not a WGSL storage texel format", format)))?;
+            let format_variant = Ident::new(format_variant, ident.span());
+            let access_variant = match access.as_str() {
+                "read" => quote!(ReadOnly),
+                "write" => quote!(WriteOnly),
+                "read_write" => quote!(ReadWrite),
+                other => return Err(invalid(format!("unknown storage texture access `{}`", other))),
+            };
+            if matches!(dimension.as_str(), "cube" | "cube_array") {
+                return Err(invalid("storage textures cannot be cube maps".to_string()));
+            }
+            let view_dimension = view_dimension(dimension, wgpu).ok_or_else(|| invalid(format!("unknown texture dimension `{}`", dimension)))?;
+            let ty = quote! {
+                #wgpu::BindingType::StorageTexture {
+                    access: #wgpu::StorageTextureAccess::#access_variant,
+                    format: #wgpu::TextureFormat::#format_variant,
+                    view_dimension: #view_dimension,
+                }
+            };
+            (ty, access != "read", "View")
+        }
+        Kind::Uniform { buffer, .. } => (buffer_type(quote!(Uniform), *buffer, wgpu), false, "Buffer"),
+        Kind::Storage { read_only, buffer, .. } => (buffer_type(quote!(Storage { read_only: #read_only }), *buffer, wgpu), !read_only, "Buffer"),
+    })
+}
+
+/// Binding size of a buffer bound at dynamic offsets.
+fn dynamic_size(kind: &Kind) -> Option<u64> {
+    match kind {
+        Kind::Uniform { buffer, .. } | Kind::Storage { buffer, .. } => buffer.dynamic,
+        _ => None,
+    }
+}
+
+fn buffer_type(ty: TokenStream2, buffer: BufferOptions, wgpu: &TokenStream2) -> TokenStream2 {
+    let has_dynamic_offset = buffer.dynamic.is_some();
+    let min_binding_size = match buffer.dynamic.or(buffer.min_size) {
+        Some(size) => quote!(#wgpu::BufferSize::new(#size)),
+        None => quote!(::core::option::Option::None),
+    };
+    quote! {
+        #wgpu::BindingType::Buffer {
+            ty: #wgpu::BufferBindingType::#ty,
+            has_dynamic_offset: #has_dynamic_offset,
+            min_binding_size: #min_binding_size,
+        }
+    }
+}
+
+fn view_dimension(dimension: &str, wgpu: &TokenStream2) -> Option<TokenStream2> {
+    let variant = match dimension {
+        "1d" => quote!(D1),
+        "2d" => quote!(D2),
+        "2d_array" => quote!(D2Array),
+        "cube" => quote!(Cube),
+        "cube_array" => quote!(CubeArray),
+        "3d" => quote!(D3),
+        _ => return None,
+    };
+    Some(quote!(#wgpu::TextureViewDimension::#variant))
+}
+
+/// `wgpu::TextureFormat` variant of a WGSL storage texel format.
+fn storage_format(format: &str) -> Option<&'static str> {
+    Some(match format {
+        "rgba8unorm" => "Rgba8Unorm",
+        "rgba8snorm" => "Rgba8Snorm",
+        "rgba8uint" => "Rgba8Uint",
+        "rgba8sint" => "Rgba8Sint",
+        "rgba16uint" => "Rgba16Uint",
+        "rgba16sint" => "Rgba16Sint",
+        "rgba16float" => "Rgba16Float",
+        "r32uint" => "R32Uint",
+        "r32sint" => "R32Sint",
+        "r32float" => "R32Float",
+        "rg32uint" => "Rg32Uint",
+        "rg32sint" => "Rg32Sint",
+        "rg32float" => "Rg32Float",
+        "rgba32uint" => "Rgba32Uint",
+        "rgba32sint" => "Rgba32Sint",
+        "rgba32float" => "Rgba32Float",
+        "bgra8unorm" => "Bgra8Unorm",
+        _ => return None,
+    })
+}
+
+fn visibility(stages: Option<&str>, writable: bool, wgpu: &TokenStream2, ident: &Ident) -> Result<TokenStream2> {
+    let Some(stages) = stages else {
+        // Vertex shaders cannot write, see `BindGroupBuilder`.
+        return Ok(if writable {
+            quote!(#wgpu::ShaderStages::FRAGMENT.union(#wgpu::ShaderStages::COMPUTE))
+        } else {
+            quote!(#wgpu::ShaderStages::VERTEX_FRAGMENT.union(#wgpu::ShaderStages::COMPUTE))
+        });
+    };
+    let stages = stages
+        .split('|')
+        .map(|stage| match stage.trim() {
+            "vertex" => Ok(quote!(VERTEX)),
+            "fragment" => Ok(quote!(FRAGMENT)),
+            "compute" => Ok(quote!(COMPUTE)),
+            other => Err(Error::new(ident.span(), format!("unknown shader stage `{}`", other))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote!(#wgpu::ShaderStages::empty() #(.union(#wgpu::ShaderStages::#stages))*))
+}
+
+fn address_space(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::Uniform { .. } => "<uniform>",
+        Kind::Storage { read_only: true, .. } => "<storage, read>",
+        Kind::Storage { read_only: false, .. } => "<storage, read_write>",
+        _ => "",
+    }
+}
+
+fn wgsl_type(kind: &Kind) -> String {
+    match kind {
+        Kind::Sampler { comparison: true, .. } => "sampler_comparison".to_string(),
+        Kind::Sampler { .. } => "sampler".to_string(),
+        Kind::Texture { sample, dimension, multisampled } => {
+            let dimension = if *multisampled { "multisampled_2d" } else { dimension.as_str() };
+            match sample.as_str() {
+                "depth" => format!("texture_depth_{}", dimension),
+                "sint" => format!("texture_{}<i32>", dimension),
+                "uint" => format!("texture_{}<u32>", dimension),
+                _ => format!("texture_{}<f32>", dimension),
+            }
+        }
+        Kind::StorageTexture { format, access, dimension } => format!("texture_storage_{}<{}, {}>", dimension, format, access),
+        Kind::Uniform { ty, .. } | Kind::Storage { ty, .. } => ty.clone(),
+    }
+}
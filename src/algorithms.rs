@@ -0,0 +1,459 @@
+//! Parallel reduce, exclusive prefix sum and radix sort over `u32` storage buffers.
+//!
+//! These are the building blocks of the GPU-driven subsystems: compacting culling
+//! results, allocating particle slots, sorting transparent draws by depth.
+//! [`GpuAlgorithms`] compiles each kernel on first use and keeps the intermediate
+//! buffers (partial sums, block sums, digit counts, sort ping-pong buffers) between
+//! calls, growing them as needed.
+//!
+//! All operations only record compute passes into the given encoder; they run in
+//! submission order like any other pass, so results can be consumed by later passes
+//! of the same encoder.
+//!
+//! ## Limits
+//! A single dispatch covers at most 65535 workgroups of 256 threads, so inputs are
+//! limited to `65535 * 256` (about 16.7M) elements.
+use std::collections::HashMap;
+use wgpu::util::DeviceExt;
+use wgpu::*;
+use crate::gpu_util;
+use crate::labels::namespaced;
+
+const WORKGROUP_SIZE: u32 = 256;
+/// Elements scanned per workgroup (two per thread).
+const SCAN_BLOCK: u32 = 512;
+const RADIX_BITS: u32 = 4;
+const RADIX_DIGITS: u32 = 1 << RADIX_BITS;
+
+const PARAMS: &str = r#"
+struct Params {
+    count: u32,
+    aux: u32,
+    block_count: u32,
+    flags: u32,
+};
+@group(0) @binding(0) var<uniform> params: Params;
+"#;
+
+const REDUCE_SHADER: &str = r#"
+@group(0) @binding(1) var<storage, read> input: array<u32>;
+@group(0) @binding(2) var<storage, read_write> output: array<u32>;
+
+var<workgroup> partial: array<u32, 256>;
+
+// params.aux: 0 = sum, 1 = min, 2 = max
+fn identity() -> u32 {
+    if params.aux == 1u {
+        return 0xffffffffu;
+    }
+    return 0u;
+}
+
+fn combine(a: u32, b: u32) -> u32 {
+    switch params.aux {
+        case 1u: { return min(a, b); }
+        case 2u: { return max(a, b); }
+        default: { return a + b; }
+    }
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(local_invocation_index) local: u32, @builtin(workgroup_id) group: vec3<u32>) {
+    let i = group.x * 256u + local;
+    var value = identity();
+    if i < params.count {
+        value = input[i];
+    }
+    partial[local] = value;
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride >>= 1u) {
+        if local < stride {
+            partial[local] = combine(partial[local], partial[local + stride]);
+        }
+        workgroupBarrier();
+    }
+    if local == 0u {
+        output[group.x] = partial[0];
+    }
+}
+"#;
+
+const SCAN_BLOCKS_SHADER: &str = r#"
+@group(0) @binding(1) var<storage, read_write> data: array<u32>;
+@group(0) @binding(2) var<storage, read_write> block_sums: array<u32>;
+
+var<workgroup> temp: array<u32, 512>;
+
+// Blelloch exclusive scan of one 512 element block.
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(local_invocation_index) local: u32, @builtin(workgroup_id) group: vec3<u32>) {
+    let a = group.x * 512u + 2u * local;
+    let b = a + 1u;
+    temp[2u * local] = 0u;
+    temp[2u * local + 1u] = 0u;
+    if a < params.count {
+        temp[2u * local] = data[a];
+    }
+    if b < params.count {
+        temp[2u * local + 1u] = data[b];
+    }
+
+    var offset = 1u;
+    for (var d = 256u; d > 0u; d >>= 1u) {
+        workgroupBarrier();
+        if local < d {
+            let ai = offset * (2u * local + 1u) - 1u;
+            let bi = offset * (2u * local + 2u) - 1u;
+            temp[bi] += temp[ai];
+        }
+        offset *= 2u;
+    }
+
+    if local == 0u {
+        block_sums[group.x] = temp[511];
+        temp[511] = 0u;
+    }
+
+    for (var d = 1u; d < 512u; d *= 2u) {
+        offset >>= 1u;
+        workgroupBarrier();
+        if local < d {
+            let ai = offset * (2u * local + 1u) - 1u;
+            let bi = offset * (2u * local + 2u) - 1u;
+            let t = temp[ai];
+            temp[ai] = temp[bi];
+            temp[bi] += t;
+        }
+    }
+    workgroupBarrier();
+
+    if a < params.count {
+        data[a] = temp[2u * local];
+    }
+    if b < params.count {
+        data[b] = temp[2u * local + 1u];
+    }
+}
+"#;
+
+const SCAN_ADD_SHADER: &str = r#"
+@group(0) @binding(1) var<storage, read_write> data: array<u32>;
+@group(0) @binding(2) var<storage, read> block_sums: array<u32>;
+
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < params.count {
+        data[id.x] += block_sums[id.x / 512u];
+    }
+}
+"#;
+
+const RADIX_HISTOGRAM_SHADER: &str = r#"
+@group(0) @binding(1) var<storage, read> keys: array<u32>;
+@group(0) @binding(2) var<storage, read_write> counts: array<u32>;
+
+var<workgroup> local_counts: array<atomic<u32>, 16>;
+
+// params.aux: bit shift of the current digit
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(local_invocation_index) local: u32, @builtin(workgroup_id) group: vec3<u32>) {
+    if local < 16u {
+        atomicStore(&local_counts[local], 0u);
+    }
+    workgroupBarrier();
+
+    let i = group.x * 256u + local;
+    if i < params.count {
+        let digit = (keys[i] >> params.aux) & 15u;
+        atomicAdd(&local_counts[digit], 1u);
+    }
+    workgroupBarrier();
+
+    // Digit-major, so an exclusive scan yields the global output offsets.
+    if local < 16u {
+        counts[local * params.block_count + group.x] = atomicLoad(&local_counts[local]);
+    }
+}
+"#;
+
+const RADIX_SCATTER_SHADER: &str = r#"
+@group(0) @binding(1) var<storage, read> keys_in: array<u32>;
+@group(0) @binding(2) var<storage, read> values_in: array<u32>;
+@group(0) @binding(3) var<storage, read> offsets: array<u32>;
+@group(0) @binding(4) var<storage, read_write> keys_out: array<u32>;
+@group(0) @binding(5) var<storage, read_write> values_out: array<u32>;
+
+var<workgroup> flags: array<u32, 256>;
+
+// params.aux: bit shift of the current digit, params.flags: 1 if values are sorted along
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(local_invocation_index) local: u32, @builtin(workgroup_id) group: vec3<u32>) {
+    let i = group.x * 256u + local;
+    let valid = i < params.count;
+    var key = 0u;
+    var digit = 16u;
+    if valid {
+        key = keys_in[i];
+        digit = (key >> params.aux) & 15u;
+    }
+
+    // Rank among the elements of this block with the same digit, keeping input order.
+    var rank = 0u;
+    for (var d = 0u; d < 16u; d++) {
+        flags[local] = select(0u, 1u, digit == d);
+        workgroupBarrier();
+        for (var offset = 1u; offset < 256u; offset *= 2u) {
+            var v = 0u;
+            if local >= offset {
+                v = flags[local - offset];
+            }
+            workgroupBarrier();
+            flags[local] += v;
+            workgroupBarrier();
+        }
+        if digit == d {
+            rank = flags[local] - 1u;
+        }
+        workgroupBarrier();
+    }
+
+    if valid {
+        let dst = offsets[digit * params.block_count + group.x] + rank;
+        keys_out[dst] = key;
+        if params.flags != 0u {
+            values_out[dst] = values_in[i];
+        }
+    }
+}
+"#;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    count: u32,
+    aux: u32,
+    block_count: u32,
+    flags: u32,
+}
+
+/// Combining operation of [`GpuAlgorithms::reduce`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReduceOp {
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kernel {
+    Reduce,
+    ScanBlocks,
+    ScanAdd,
+    RadixHistogram,
+    RadixScatter,
+}
+
+impl Kernel {
+    fn source(self) -> &'static str {
+        match self {
+            Kernel::Reduce => REDUCE_SHADER,
+            Kernel::ScanBlocks => SCAN_BLOCKS_SHADER,
+            Kernel::ScanAdd => SCAN_ADD_SHADER,
+            Kernel::RadixHistogram => RADIX_HISTOGRAM_SHADER,
+            Kernel::RadixScatter => RADIX_SCATTER_SHADER,
+        }
+    }
+
+    /// `read_only` flag of each storage binding after the params uniform.
+    fn storage_bindings(self) -> &'static [bool] {
+        match self {
+            Kernel::Reduce => &[true, false],
+            Kernel::ScanBlocks => &[false, false],
+            Kernel::ScanAdd => &[false, true],
+            Kernel::RadixHistogram => &[true, false],
+            Kernel::RadixScatter => &[true, true, true, false, false],
+        }
+    }
+}
+
+struct CachedKernel {
+    pipeline: ComputePipeline,
+    layout: BindGroupLayout,
+}
+
+/// Scratch buffer roles, each kept per recursion level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Scratch {
+    ReducePartials,
+    ScanBlockSums,
+    RadixCounts,
+    SortKeys,
+    SortValues,
+    /// Placeholder bindings when sorting keys only.
+    EmptyIn,
+    EmptyOut,
+}
+
+/// Cached kernels and scratch buffers for reduce, scan and radix sort.
+pub struct GpuAlgorithms {
+    device: Device,
+    kernels: HashMap<Kernel, CachedKernel>,
+    scratch: HashMap<(Scratch, u32), Buffer>,
+}
+
+impl GpuAlgorithms {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            kernels: HashMap::new(),
+            scratch: HashMap::new(),
+        }
+    }
+
+    /// Record reducing the first `count` values of `input` with `op` into `output[0]`.
+    ///
+    /// `input` needs `STORAGE` usage, `output` `STORAGE` and room for one `u32`.
+    /// Sums wrap on overflow.
+    pub fn reduce(&mut self, encoder: &mut CommandEncoder, input: &Buffer, count: u32, op: ReduceOp, output: &Buffer) {
+        let _span = trace_span!("gpu_reduce", count);
+        let op = match op {
+            ReduceOp::Sum => 0,
+            ReduceOp::Min => 1,
+            ReduceOp::Max => 2,
+        };
+        let mut source = input.clone();
+        let mut count = count.max(1);
+        let mut level = 0;
+        loop {
+            let groups = count.div_ceil(WORKGROUP_SIZE);
+            let target = if groups == 1 {
+                output.clone()
+            } else {
+                self.scratch(Scratch::ReducePartials, level, groups as u64 * 4)
+            };
+            let params = Params { count, aux: op, block_count: groups, flags: 0 };
+            self.dispatch(encoder, Kernel::Reduce, params, &[&source, &target], groups);
+            if groups == 1 {
+                break;
+            }
+            source = target;
+            count = groups;
+            level += 1;
+        }
+    }
+
+    /// Record an in-place exclusive prefix sum of the first `count` values of `data`.
+    ///
+    /// `data` needs `STORAGE` usage. Sums wrap on overflow.
+    pub fn exclusive_scan(&mut self, encoder: &mut CommandEncoder, data: &Buffer, count: u32) {
+        let _span = trace_span!("gpu_exclusive_scan", count);
+        self.scan_level(encoder, data, count, 0);
+    }
+
+    fn scan_level(&mut self, encoder: &mut CommandEncoder, data: &Buffer, count: u32, level: u32) {
+        if count == 0 {
+            return;
+        }
+        let blocks = count.div_ceil(SCAN_BLOCK);
+        let block_sums = self.scratch(Scratch::ScanBlockSums, level, blocks as u64 * 4);
+        let params = Params { count, aux: 0, block_count: blocks, flags: 0 };
+        self.dispatch(encoder, Kernel::ScanBlocks, params, &[data, &block_sums], blocks);
+
+        if blocks > 1 {
+            self.scan_level(encoder, &block_sums, blocks, level + 1);
+            self.dispatch(encoder, Kernel::ScanAdd, params, &[data, &block_sums], count.div_ceil(WORKGROUP_SIZE));
+        }
+    }
+
+    /// Record a stable ascending radix sort of the first `count` keys of `keys`.
+    ///
+    /// If `values` is given, its first `count` entries are reordered along with the keys.
+    /// Both buffers need `STORAGE` usage and are sorted in place. Only the lowest
+    /// `key_bits` bits of the keys are considered (rounded up to a multiple of 4),
+    /// pass 32 to sort full keys.
+    pub fn radix_sort(&mut self, encoder: &mut CommandEncoder, keys: &Buffer, values: Option<&Buffer>, count: u32, key_bits: u32) {
+        let _span = trace_span!("gpu_radix_sort", count, key_bits);
+        if count < 2 {
+            return;
+        }
+        // Even number of passes, so the result ends up back in the caller's buffers.
+        let passes = key_bits.clamp(1, 32).div_ceil(RADIX_BITS).next_multiple_of(2);
+        let blocks = count.div_ceil(WORKGROUP_SIZE);
+        let counts = self.scratch(Scratch::RadixCounts, 0, (RADIX_DIGITS * blocks) as u64 * 4);
+        let keys_tmp = self.scratch(Scratch::SortKeys, 0, count as u64 * 4);
+        let has_values = values.is_some() as u32;
+        let (values, values_tmp) = match values {
+            Some(values) => (values.clone(), self.scratch(Scratch::SortValues, 0, count as u64 * 4)),
+            None => (self.scratch(Scratch::EmptyIn, 0, 4), self.scratch(Scratch::EmptyOut, 0, 4)),
+        };
+
+        let mut buffers = [(keys.clone(), values.clone()), (keys_tmp, values_tmp)];
+        for pass in 0..passes {
+            let shift = pass * RADIX_BITS;
+            let (src_keys, src_values) = buffers[0].clone();
+            let (dst_keys, dst_values) = buffers[1].clone();
+
+            let params = Params { count, aux: shift, block_count: blocks, flags: has_values };
+            self.dispatch(encoder, Kernel::RadixHistogram, params, &[&src_keys, &counts], blocks);
+            self.exclusive_scan(encoder, &counts, RADIX_DIGITS * blocks);
+            self.dispatch(encoder, Kernel::RadixScatter, params, &[&src_keys, &src_values, &counts, &dst_keys, &dst_values], blocks);
+            buffers.swap(0, 1);
+        }
+    }
+
+    /// Drop all scratch buffers. They are recreated on the next call.
+    pub fn release_scratch(&mut self) {
+        self.scratch.clear();
+    }
+
+    /// Total size of the scratch buffers currently held, in bytes.
+    pub fn scratch_bytes(&self) -> u64 {
+        self.scratch.values().map(|b| b.size()).sum()
+    }
+
+    fn scratch(&mut self, role: Scratch, level: u32, size: u64) -> Buffer {
+        let size = size.max(16);
+        if let Some(buffer) = self.scratch.get(&(role, level)) {
+            if buffer.size() >= size {
+                return buffer.clone();
+            }
+        }
+        let buffer = gpu_util::buffer(&self.device, "gpu algorithm scratch", size.next_power_of_two(), BufferUsages::STORAGE);
+        trace_event!(role = ?role, level, size = buffer.size(), "created scratch buffer");
+        self.scratch.insert((role, level), buffer.clone());
+        buffer
+    }
+
+    fn kernel(&mut self, kernel: Kernel) -> &CachedKernel {
+        self.kernels.entry(kernel).or_insert_with(|| {
+            let _span = trace_span!("gpu_algorithm_kernel_miss", kernel = ?kernel);
+            let module = gpu_util::shader(&self.device, "gpu algorithm shader", &format!("{}{}", PARAMS, kernel.source()));
+            let mut entries = vec![gpu_util::uniform_entry(0, ShaderStages::COMPUTE)];
+            for (i, read_only) in kernel.storage_bindings().iter().enumerate() {
+                entries.push(gpu_util::storage_entry(i as u32 + 1, ShaderStages::COMPUTE, *read_only));
+            }
+            let layout = gpu_util::bind_group_layout(&self.device, "gpu algorithm layout", &entries);
+            let pipeline = gpu_util::compute_pipeline(&self.device, "gpu algorithm", &module, "main", &[&layout]);
+            CachedKernel { pipeline, layout }
+        })
+    }
+
+    fn dispatch(&mut self, encoder: &mut CommandEncoder, kernel: Kernel, params: Params, buffers: &[&Buffer], groups: u32) {
+        // A fresh uniform per dispatch: several dispatches are recorded before the
+        // submission, so a shared buffer written with `Queue::write_buffer` would only
+        // hold the last parameters.
+        let params = self.device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some(&namespaced("gpu algorithm params")),
+            contents: bytemuck::bytes_of(&params),
+            usage: BufferUsages::UNIFORM,
+        });
+        let device = self.device.clone();
+        let cached = self.kernel(kernel);
+
+        let mut resources = vec![params.as_entire_binding()];
+        resources.extend(buffers.iter().map(|b| b.as_entire_binding()));
+        let bind_group = gpu_util::bind_group(&device, "gpu algorithm", &cached.layout, &resources);
+        gpu_util::dispatch(encoder, "gpu algorithm", &cached.pipeline, &[&bind_group], [groups, 1, 1]);
+    }
+}
@@ -0,0 +1,481 @@
+//! Skeletal animation on the CPU, feeding the GPU [`SkinningPass`](crate::skinning::SkinningPass).
+//!
+//! - A [`Skeleton`] is a joint hierarchy with rest transforms and inverse bind matrices.
+//! - An [`AnimationClip`] holds keyframed translation, rotation and scale channels
+//!   targeting joints, with step, linear or cubic spline interpolation, and morph
+//!   target weight channels for the [`MorphPass`](crate::morph::MorphPass).
+//! - An [`AnimationPlayer`] advances the time of a clip and samples it into a [`Pose`].
+//! - [`Skeleton::upload_pose`] turns a pose into model-space joint matrices and writes
+//!   them into the joint buffer of a [`SkinnedMesh`].
+//!
+//! ## Example
+//! With the `gltf` feature, skeletons, clips and skinning inputs come from the importer:
+//! ```ignore
+//! let scene = load_gltf(&device, &queue, &mut meshes, "assets/character.glb")?;
+//! let skeleton = scene.skeleton(0);
+//! let walk = scene.animation_clip(0, 0);
+//! let primitive = &scene.skinned[&mesh];
+//! let mut skinned_mesh = skinning.create_mesh("character", &primitive.vertices, skeleton.inverse_bind());
+//! let mut player = AnimationPlayer::new();
+//!
+//! // Every frame
+//! player.advance(dt, &walk);
+//! skeleton.upload_pose(&queue, &player.sample(&walk, &skeleton), &skinned_mesh);
+//! let output = meshes.get(primitive.output).unwrap().vertex_buffer().clone();
+//! skinning.skin(&mut encoder, &mut skinned_mesh, &output, SkinOutput::TANGENT_VERTEX);
+//! // Inside a render pass
+//! meshes.draw(&mut pass, primitive.output, &options, 0..1);
+//! ```
+use wgpu::Queue;
+use crate::gpu_util::{mul_matrices, IDENTITY};
+use crate::skinning::SkinnedMesh;
+
+/// Translation, rotation and scale of a joint relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointTransform {
+    pub translation: [f32; 3],
+    /// Unit quaternion, `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for JointTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl JointTransform {
+    pub const IDENTITY: Self = Self {
+        translation: [0.0; 3],
+        rotation: [0.0, 0.0, 0.0, 1.0],
+        scale: [1.0; 3],
+    };
+
+    /// Decompose a column-major matrix without shear.
+    pub fn from_matrix(m: &[[f32; 4]; 4]) -> Self {
+        let length = |c: [f32; 4]| (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt();
+        let scale = [length(m[0]), length(m[1]), length(m[2])];
+        let axis = |i: usize| {
+            let s = if scale[i] > f32::EPSILON { 1.0 / scale[i] } else { 0.0 };
+            [m[i][0] * s, m[i][1] * s, m[i][2] * s]
+        };
+        let [x, y, z] = [axis(0), axis(1), axis(2)];
+        // Rotation matrix to quaternion, picking the largest diagonal term for stability.
+        let trace = x[0] + y[1] + z[2];
+        let rotation = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            [(y[2] - z[1]) / s, (z[0] - x[2]) / s, (x[1] - y[0]) / s, 0.25 * s]
+        } else if x[0] > y[1] && x[0] > z[2] {
+            let s = (1.0 + x[0] - y[1] - z[2]).sqrt() * 2.0;
+            [0.25 * s, (y[0] + x[1]) / s, (z[0] + x[2]) / s, (y[2] - z[1]) / s]
+        } else if y[1] > z[2] {
+            let s = (1.0 + y[1] - x[0] - z[2]).sqrt() * 2.0;
+            [(y[0] + x[1]) / s, 0.25 * s, (z[1] + y[2]) / s, (z[0] - x[2]) / s]
+        } else {
+            let s = (1.0 + z[2] - x[0] - y[1]).sqrt() * 2.0;
+            [(z[0] + x[2]) / s, (z[1] + y[2]) / s, 0.25 * s, (x[1] - y[0]) / s]
+        };
+        Self {
+            translation: [m[3][0], m[3][1], m[3][2]],
+            rotation: normalize(rotation),
+            scale,
+        }
+    }
+
+    /// Column-major `translation * rotation * scale`.
+    pub fn matrix(&self) -> [[f32; 4]; 4] {
+        let [x, y, z, w] = self.rotation;
+        let [sx, sy, sz] = self.scale;
+        let (x2, y2, z2) = (x + x, y + y, z + z);
+        let (xx, xy, xz) = (x * x2, x * y2, x * z2);
+        let (yy, yz, zz) = (y * y2, y * z2, z * z2);
+        let (wx, wy, wz) = (w * x2, w * y2, w * z2);
+        [
+            [(1.0 - (yy + zz)) * sx, (xy + wz) * sx, (xz - wy) * sx, 0.0],
+            [(xy - wz) * sy, (1.0 - (xx + zz)) * sy, (yz + wx) * sy, 0.0],
+            [(xz + wy) * sz, (yz - wx) * sz, (1.0 - (xx + yy)) * sz, 0.0],
+            [self.translation[0], self.translation[1], self.translation[2], 1.0],
+        ]
+    }
+
+    /// Interpolate towards `other`: linearly for translation and scale, spherically for rotation.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: lerp(self.translation, other.translation, t),
+            rotation: slerp(self.rotation, other.rotation, t),
+            scale: lerp(self.scale, other.scale, t),
+        }
+    }
+}
+
+/// A joint of a [`Skeleton`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    pub name: Option<String>,
+    /// Index of the parent joint, `None` for roots.
+    pub parent: Option<usize>,
+    /// Transform relative to the parent when no animation overrides it.
+    pub rest: JointTransform,
+}
+
+/// A joint hierarchy with the inverse bind matrices of a skinned mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+    inverse_bind: Vec<[[f32; 4]; 4]>,
+    root_transform: [[f32; 4]; 4],
+    /// Joint indices with every parent before its children.
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    /// Create a skeleton. `inverse_bind[i]` belongs to `joints[i]`.
+    ///
+    /// ## Panics
+    /// Panics if the lengths differ, a parent index is out of range or the parents form a cycle.
+    pub fn new(joints: Vec<Joint>, inverse_bind: Vec<[[f32; 4]; 4]>) -> Self {
+        assert_eq!(
+            joints.len(),
+            inverse_bind.len(),
+            "Skeleton has {} joints but {} inverse bind matrices",
+            joints.len(),
+            inverse_bind.len()
+        );
+        let mut children = vec![Vec::new(); joints.len()];
+        let mut stack = Vec::new();
+        for (index, joint) in joints.iter().enumerate() {
+            match joint.parent {
+                Some(parent) => {
+                    assert!(parent < joints.len(), "Joint {} has parent {} out of range", index, parent);
+                    children[parent].push(index);
+                }
+                None => stack.push(index),
+            }
+        }
+        let mut order = Vec::with_capacity(joints.len());
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(&children[index]);
+        }
+        assert_eq!(order.len(), joints.len(), "Skeleton joint parents form a cycle");
+        Self {
+            joints,
+            inverse_bind,
+            root_transform: IDENTITY,
+            order,
+        }
+    }
+
+    /// Transform applied above the root joints, e.g. the world transform of their parent node.
+    pub fn with_root_transform(mut self, transform: [[f32; 4]; 4]) -> Self {
+        self.root_transform = transform;
+        self
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn inverse_bind(&self) -> &[[[f32; 4]; 4]] {
+        &self.inverse_bind
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    /// Index of the first joint named `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name.as_deref() == Some(name))
+    }
+
+    /// Every joint at its rest transform.
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            joints: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    /// Model-space transforms of all joints in `pose`, as expected by
+    /// [`SkinnedMesh::set_pose`].
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<[[f32; 4]; 4]> {
+        let mut matrices = vec![IDENTITY; self.joints.len()];
+        for &index in &self.order {
+            let local = pose.joints.get(index).unwrap_or(&self.joints[index].rest).matrix();
+            let parent = self.joints[index].parent.map_or(self.root_transform, |parent| matrices[parent]);
+            matrices[index] = mul_matrices(&parent, &local);
+        }
+        matrices
+    }
+
+    /// Write the joint matrices of `pose` into `mesh` for its next skinning dispatch.
+    pub fn upload_pose(&self, queue: &Queue, pose: &Pose, mesh: &SkinnedMesh) {
+        let _span = trace_span!("upload_pose", joints = self.joints.len());
+        mesh.set_pose(queue, &self.joint_matrices(pose));
+    }
+}
+
+/// Local transforms of every joint of a skeleton.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub joints: Vec<JointTransform>,
+}
+
+impl Pose {
+    /// Blend towards `other` by `weight`, e.g. to cross-fade between two clips.
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (joint, target) in self.joints.iter_mut().zip(&other.joints) {
+            *joint = joint.lerp(target, weight);
+        }
+    }
+}
+
+/// How values between two keyframes are computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+    /// The previous keyframe's value.
+    Step,
+    /// Linear, spherical-linear for rotations.
+    #[default]
+    Linear,
+    /// Hermite spline. Every keyframe stores an in-tangent, the value and an
+    /// out-tangent, in that order (as in glTF).
+    CubicSpline,
+}
+
+/// Keyframe values of a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelValues {
+    Translation(Vec<[f32; 3]>),
+    /// Quaternions, `[x, y, z, w]`.
+    Rotation(Vec<[f32; 4]>),
+    Scale(Vec<[f32; 3]>),
+    /// Morph target weights, `count` values per keyframe (three blocks of `count` for
+    /// cubic splines). Sampled with [`AnimationClip::sample_weights`], ignored by poses.
+    Weights { count: usize, values: Vec<f32> },
+}
+
+/// Keyframes of one property of one joint (or of one morphable mesh for weights).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationChannel {
+    /// Index of the animated joint or morphable mesh.
+    pub target: usize,
+    pub interpolation: Interpolation,
+    /// Ascending keyframe times in seconds.
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+impl AnimationChannel {
+    /// Write the value at `time` into `joint`.
+    fn apply(&self, time: f32, joint: &mut JointTransform) {
+        if self.times.is_empty() {
+            return;
+        }
+        match &self.values {
+            ChannelValues::Translation(values) => joint.translation = sample(&self.times, values, self.interpolation, time),
+            ChannelValues::Rotation(values) => {
+                joint.rotation = match self.interpolation {
+                    Interpolation::Linear => {
+                        let (prev, next, t) = keyframes(&self.times, time);
+                        slerp(values[prev], values[next], t)
+                    }
+                    _ => normalize(sample(&self.times, values, self.interpolation, time)),
+                }
+            }
+            ChannelValues::Scale(values) => joint.scale = sample(&self.times, values, self.interpolation, time),
+            ChannelValues::Weights { .. } => {}
+        }
+    }
+}
+
+/// A named set of channels played together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    channels: Vec<AnimationChannel>,
+    duration: f32,
+}
+
+impl AnimationClip {
+    /// Create a clip lasting until its last keyframe.
+    pub fn new(name: Option<String>, channels: Vec<AnimationChannel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self { name, channels, duration }
+    }
+
+    pub fn channels(&self) -> &[AnimationChannel] {
+        &self.channels
+    }
+
+    /// Length in seconds.
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// Overwrite the joints animated by this clip with their values at `time`.
+    ///
+    /// Channels targeting joints outside `pose` are ignored.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            if let Some(joint) = pose.joints.get_mut(channel.target) {
+                channel.apply(time, joint);
+            }
+        }
+    }
+}
+
+impl AnimationClip {
+    /// Write the morph target weights of `target` at `time` into `weights`.
+    ///
+    /// Returns `false` if the clip has no weight channel for `target`.
+    pub fn sample_weights(&self, target: usize, time: f32, weights: &mut [f32]) -> bool {
+        let Some((channel, count, values)) = self.channels.iter().find_map(|channel| match &channel.values {
+            ChannelValues::Weights { count, values } if channel.target == target && !channel.times.is_empty() => {
+                Some((channel, *count, values))
+            }
+            _ => None,
+        }) else {
+            return false;
+        };
+        let (prev, next, t) = keyframes(&channel.times, time);
+        let key = |keyframe: usize, block: usize| {
+            let start = (keyframe * 3 + block) * count;
+            &values[start..start + count]
+        };
+        for (i, weight) in weights.iter_mut().enumerate().take(count) {
+            *weight = match channel.interpolation {
+                Interpolation::Step => values[prev * count + i],
+                Interpolation::Linear => values[prev * count + i] + (values[next * count + i] - values[prev * count + i]) * t,
+                Interpolation::CubicSpline => {
+                    let span = channel.times[next] - channel.times[prev];
+                    hermite(key(prev, 1)[i], key(prev, 2)[i] * span, key(next, 1)[i], key(next, 0)[i] * span, t)
+                }
+            };
+        }
+        true
+    }
+}
+
+/// Playback state of one clip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationPlayer {
+    /// Current time in seconds.
+    pub time: f32,
+    /// Playback rate, negative to play backwards.
+    pub speed: f32,
+    /// Wrap around at the ends instead of stopping there.
+    pub looping: bool,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+}
+
+impl AnimationPlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance by `dt` seconds of `clip`.
+    pub fn advance(&mut self, dt: f32, clip: &AnimationClip) {
+        let duration = clip.duration();
+        self.time += dt * self.speed;
+        self.time = if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.clamp(0.0, duration)
+        };
+    }
+
+    /// Whether a non-looping clip reached its end in the playback direction.
+    pub fn is_finished(&self, clip: &AnimationClip) -> bool {
+        !self.looping && if self.speed < 0.0 { self.time <= 0.0 } else { self.time >= clip.duration() }
+    }
+
+    /// The rest pose of `skeleton` with `clip` applied at the current time.
+    pub fn sample(&self, clip: &AnimationClip, skeleton: &Skeleton) -> Pose {
+        let mut pose = skeleton.rest_pose();
+        clip.sample(self.time, &mut pose);
+        pose
+    }
+}
+
+/// Surrounding keyframes of `time` and the position between them, clamped to the ends.
+fn keyframes(times: &[f32], time: f32) -> (usize, usize, f32) {
+    let last = times.len() - 1;
+    if time <= times[0] {
+        return (0, 0, 0.0);
+    }
+    if time >= times[last] {
+        return (last, last, 0.0);
+    }
+    let next = times.partition_point(|&t| t <= time);
+    let prev = next - 1;
+    let span = times[next] - times[prev];
+    let t = if span > 0.0 { (time - times[prev]) / span } else { 0.0 };
+    (prev, next, t)
+}
+
+fn sample<const N: usize>(times: &[f32], values: &[[f32; N]], interpolation: Interpolation, time: f32) -> [f32; N] {
+    let (prev, next, t) = keyframes(times, time);
+    match interpolation {
+        Interpolation::Step => values[prev],
+        Interpolation::Linear => lerp(values[prev], values[next], t),
+        Interpolation::CubicSpline => {
+            let span = times[next] - times[prev];
+            let (p0, m0) = (values[prev * 3 + 1], values[prev * 3 + 2]);
+            let (p1, m1) = (values[next * 3 + 1], values[next * 3]);
+            std::array::from_fn(|i| hermite(p0[i], m0[i] * span, p1[i], m1[i] * span, t))
+        }
+    }
+}
+
+/// Cubic Hermite spline between `p0` and `p1` with tangents `m0` and `m1`.
+fn hermite(p0: f32, m0: f32, p1: f32, m1: f32, t: f32) -> f32 {
+    let (t2, t3) = (t * t, t * t * t);
+    (2.0 * t3 - 3.0 * t2 + 1.0) * p0 + (t3 - 2.0 * t2 + t) * m0 + (-2.0 * t3 + 3.0 * t2) * p1 + (t3 - t2) * m1
+}
+
+fn lerp<const N: usize>(a: [f32; N], b: [f32; N], t: f32) -> [f32; N] {
+    std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+fn slerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+    // Take the shorter arc.
+    let b = if cos < 0.0 {
+        cos = -cos;
+        b.map(|v| -v)
+    } else {
+        b
+    };
+    if cos > 0.9995 {
+        return normalize(lerp(a, b, t));
+    }
+    let angle = cos.acos();
+    let sin = angle.sin();
+    let (wa, wb) = (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin);
+    std::array::from_fn(|i| a[i] * wa + b[i] * wb)
+}
+
+fn normalize(q: [f32; 4]) -> [f32; 4] {
+    let length = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if length > f32::EPSILON { q.map(|v| v / length) } else { [0.0, 0.0, 0.0, 1.0] }
+}
@@ -0,0 +1,316 @@
+//! Reference-counted dependencies between textures, materials, shaders and pipelines.
+//!
+//! An [`AssetGraph`] records which materials use which textures and which pipelines use
+//! which shaders. Every node counts the references held by the application and by its
+//! dependents. When [`release`](AssetGraph::release) drops the last one, the node frees
+//! its GPU resources (the texture, the cached material bind groups, the shader module
+//! and its pipelines) and releases its own dependencies in turn:
+//! ```ignore
+//! let mut graph = AssetGraph::new();
+//! let albedo = graph.add_texture(assets.remove(albedo_handle).unwrap());
+//! let normal = graph.add_texture(normal_texture);
+//! let brick = graph.add_material(&[albedo, normal]);
+//! let pbr = graph.add_shader("shaders/pbr.wgsl");
+//! let pipeline = graph.add_pipeline(pbr);
+//! // The material keeps its textures alive, the pipeline its shader.
+//! graph.release(albedo, &mut render_manager);
+//! graph.release(normal, &mut render_manager);
+//!
+//! // Inside a render pass
+//! render_manager.render_with_textures(&graph.material_views(brick).unwrap(), graph.shader_path(pbr).unwrap(), &options, &[&camera], &mut pass);
+//!
+//! // Level unloaded: frees the bind group, then both textures.
+//! graph.release(brick, &mut render_manager);
+//! ```
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use wgpu::TextureView;
+use crate::renderer::RenderManager;
+use crate::textures::LoadedTexture;
+
+/// A node of an [`AssetGraph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AssetId(u64);
+
+/// What a node owns.
+enum Resource {
+    Texture(LoadedTexture),
+    /// Bind groups created from the views of its texture dependencies.
+    Material,
+    Shader(PathBuf),
+    /// Pipelines of its shader dependency.
+    Pipeline,
+}
+
+struct Node {
+    resource: Resource,
+    references: u32,
+    dependencies: Vec<AssetId>,
+}
+
+/// Reference counts and dependency edges of GPU assets.
+#[derive(Default)]
+pub struct AssetGraph {
+    nodes: HashMap<AssetId, Node>,
+    shaders: HashMap<PathBuf, AssetId>,
+    next_id: u64,
+}
+
+impl AssetGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, resource: Resource, dependencies: &[AssetId]) -> AssetId {
+        for dependency in dependencies {
+            self.acquire(*dependency);
+        }
+        let id = AssetId(self.next_id);
+        self.next_id += 1;
+        self.nodes.insert(
+            id,
+            Node {
+                resource,
+                references: 1,
+                dependencies: dependencies.to_vec(),
+            },
+        );
+        id
+    }
+
+    /// Take ownership of a texture, with one reference held by the caller.
+    pub fn add_texture(&mut self, texture: LoadedTexture) -> AssetId {
+        self.insert(Resource::Texture(texture), &[])
+    }
+
+    /// A material bound from `textures` in this order, referencing each of them.
+    pub fn add_material(&mut self, textures: &[AssetId]) -> AssetId {
+        assert!(
+            textures.iter().all(|t| matches!(self.nodes.get(t).map(|n| &n.resource), Some(Resource::Texture(_)))),
+            "material dependencies must be live textures"
+        );
+        self.insert(Resource::Material, textures)
+    }
+
+    /// A render shader. Adding a path twice returns the same node with one more reference.
+    pub fn add_shader(&mut self, path: impl Into<PathBuf>) -> AssetId {
+        let path = path.into();
+        if let Some(&id) = self.shaders.get(&path) {
+            self.acquire(id);
+            return id;
+        }
+        let id = self.insert(Resource::Shader(path.clone()), &[]);
+        self.shaders.insert(path, id);
+        id
+    }
+
+    /// A pipeline of `shader`, keeping the shader alive while it is referenced.
+    pub fn add_pipeline(&mut self, shader: AssetId) -> AssetId {
+        assert!(
+            matches!(self.nodes.get(&shader).map(|n| &n.resource), Some(Resource::Shader(_))),
+            "pipeline dependency must be a live shader"
+        );
+        self.insert(Resource::Pipeline, &[shader])
+    }
+
+    /// Add a reference to `id`.
+    ///
+    /// # Panics
+    /// If `id` was already freed.
+    pub fn acquire(&mut self, id: AssetId) {
+        self.nodes.get_mut(&id).expect("asset was already released").references += 1;
+    }
+
+    /// Drop a reference to `id`. Frees it and releases its dependencies once no
+    /// references are left. Returns the number of freed nodes.
+    pub fn release(&mut self, id: AssetId, render_manager: &mut RenderManager) -> usize {
+        let mut freed = 0;
+        let mut queue = vec![id];
+        while let Some(id) = queue.pop() {
+            let node = self.nodes.get_mut(&id).expect("asset was already released");
+            node.references -= 1;
+            if node.references > 0 {
+                continue;
+            }
+            let views: Vec<TextureView> = self.material_views(id).unwrap_or_default().into_iter().cloned().collect();
+            let node = self.nodes.remove(&id).expect("checked above");
+            match node.resource {
+                Resource::Material => {
+                    render_manager.release_material(&views.iter().collect::<Vec<_>>());
+                }
+                Resource::Shader(path) => {
+                    render_manager.release_shader(&path);
+                    self.shaders.remove(&path);
+                }
+                Resource::Texture(_) | Resource::Pipeline => {}
+            }
+            trace_event!(id = id.0, "asset released");
+            freed += 1;
+            queue.extend(node.dependencies);
+        }
+        freed
+    }
+
+    /// References held on `id`, 0 once it was freed.
+    pub fn ref_count(&self, id: AssetId) -> u32 {
+        self.nodes.get(&id).map_or(0, |node| node.references)
+    }
+
+    pub fn contains(&self, id: AssetId) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    pub fn texture(&self, id: AssetId) -> Option<&LoadedTexture> {
+        match &self.nodes.get(&id)?.resource {
+            Resource::Texture(texture) => Some(texture),
+            _ => None,
+        }
+    }
+
+    /// Texture views of a material, in the order given to [`add_material`](Self::add_material).
+    pub fn material_views(&self, id: AssetId) -> Option<Vec<&TextureView>> {
+        let node = self.nodes.get(&id)?;
+        if !matches!(node.resource, Resource::Material) {
+            return None;
+        }
+        node.dependencies.iter().map(|texture| self.texture(*texture).map(|t| &t.view)).collect()
+    }
+
+    pub fn shader_path(&self, id: AssetId) -> Option<&Path> {
+        match &self.nodes.get(&id)?.resource {
+            Resource::Shader(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Nodes `id` references directly.
+    pub fn dependencies(&self, id: AssetId) -> &[AssetId] {
+        self.nodes.get(&id).map_or(&[], |node| &node.dependencies)
+    }
+
+    /// Nodes that reference `id` directly.
+    pub fn dependents(&self, id: AssetId) -> Vec<AssetId> {
+        let mut dependents: Vec<AssetId> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.dependencies.contains(&id))
+            .map(|(dependent, _)| *dependent)
+            .collect();
+        dependents.sort();
+        dependents
+    }
+
+    /// Number of live nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::diagnostics::CacheKind;
+    use crate::testing::noop_device;
+    use wgpu::{Device, Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
+
+    fn texture(device: &Device) -> LoadedTexture {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("asset graph test texture"),
+            size: Extent3d { width: 4, height: 4, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        LoadedTexture { texture, view }
+    }
+
+    fn material_bind_groups(render_manager: &RenderManager) -> usize {
+        render_manager.cache_entries().iter().filter(|e| e.kind == CacheKind::MaterialBindGroup).count()
+    }
+
+    #[test]
+    fn materials_keep_their_textures_alive() {
+        let (device, queue) = noop_device();
+        let mut render_manager = RenderManager::new(&device, &queue, "shaders".into());
+        let mut graph = AssetGraph::new();
+        let albedo = graph.add_texture(texture(&device));
+        let normal = graph.add_texture(texture(&device));
+        let brick = graph.add_material(&[albedo, normal]);
+        assert_eq!((graph.ref_count(albedo), graph.ref_count(brick)), (2, 1));
+        assert_eq!(graph.dependencies(brick), [albedo, normal]);
+        assert_eq!(graph.dependents(normal), [brick]);
+
+        assert_eq!(graph.release(albedo, &mut render_manager), 0);
+        assert_eq!(graph.release(normal, &mut render_manager), 0);
+        assert_eq!(graph.ref_count(albedo), 1);
+        let views = graph.material_views(brick).expect("textures are still alive");
+        render_manager.material_bind_group(&views, &[]);
+        assert_eq!(material_bind_groups(&render_manager), 1);
+
+        // The last reference frees the material, its bind group and both textures.
+        assert_eq!(graph.release(brick, &mut render_manager), 3);
+        assert_eq!(material_bind_groups(&render_manager), 0);
+        assert!(graph.is_empty());
+        assert!(!graph.contains(albedo));
+        assert_eq!(graph.ref_count(normal), 0);
+    }
+
+    #[test]
+    fn shared_textures_outlive_one_material() {
+        let (device, queue) = noop_device();
+        let mut render_manager = RenderManager::new(&device, &queue, "shaders".into());
+        let mut graph = AssetGraph::new();
+        let albedo = graph.add_texture(texture(&device));
+        let brick = graph.add_material(&[albedo]);
+        let wall = graph.add_material(&[albedo]);
+        graph.release(albedo, &mut render_manager);
+
+        assert_eq!(graph.release(brick, &mut render_manager), 1);
+        assert!(graph.texture(albedo).is_some());
+        assert_eq!(graph.dependents(albedo), [wall]);
+        assert_eq!(graph.release(wall, &mut render_manager), 2);
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn shaders_are_shared_by_path_and_held_by_pipelines() {
+        let (device, queue) = noop_device();
+        let mut render_manager = RenderManager::new(&device, &queue, "shaders".into());
+        let mut graph = AssetGraph::new();
+        let pbr = graph.add_shader("shaders/pbr.wgsl");
+        assert_eq!(graph.add_shader("shaders/pbr.wgsl"), pbr);
+        let opaque = graph.add_pipeline(pbr);
+        let transparent = graph.add_pipeline(pbr);
+        assert_eq!(graph.ref_count(pbr), 4);
+        assert_eq!(graph.dependents(pbr), [opaque, transparent]);
+
+        graph.release(pbr, &mut render_manager);
+        graph.release(pbr, &mut render_manager);
+        assert_eq!(graph.release(opaque, &mut render_manager), 1);
+        assert_eq!(graph.shader_path(pbr), Some(Path::new("shaders/pbr.wgsl")));
+        assert_eq!(graph.release(transparent, &mut render_manager), 2);
+        assert!(graph.shader_path(pbr).is_none());
+
+        // A freed path starts a new node.
+        assert_ne!(graph.add_shader("shaders/pbr.wgsl"), pbr);
+    }
+
+    #[test]
+    #[should_panic(expected = "asset was already released")]
+    fn releasing_a_freed_asset_panics() {
+        let (device, queue) = noop_device();
+        let mut render_manager = RenderManager::new(&device, &queue, "shaders".into());
+        let mut graph = AssetGraph::new();
+        let shader = graph.add_shader("shaders/pbr.wgsl");
+        graph.release(shader, &mut render_manager);
+        graph.release(shader, &mut render_manager);
+    }
+}
@@ -0,0 +1,389 @@
+//! Single-file asset packs (feature `pack`).
+//!
+//! A pack is an index followed by the blobs of all files, each LZ4-compressed unless
+//! that does not make it smaller (already compressed formats like baked textures often
+//! stay raw). [`PackWriter`] builds packs offline; [`AssetPack::open`] memory-maps one,
+//! so only the blobs that are read are paged in and raw blobs are returned without a
+//! copy:
+//! ```ignore
+//! // build step
+//! let mut writer = PackWriter::new();
+//! writer.add_directory("assets")?;
+//! writer.write("game.rmpak")?;
+//!
+//! // game
+//! assets.mount(AssetPack::open("game.rmpak")?);
+//! let albedo = assets.load::<LoadedTexture>("textures/brick.rmtex");
+//! ```
+//!
+//! Paths in a pack are relative, with `/` separators on every platform.
+//!
+//! ## Layout
+//! All integers are little-endian. The header is the magic `RMPK`, a `u32` version, a
+//! `u32` entry count and the `u64` offset of the index. Each index entry is a `u16` path
+//! length and the UTF-8 path, then the `u64` blob offset, `u64` stored length, `u64`
+//! unpacked length and a `u8` compression (0 raw, 1 LZ4 block).
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RMPK";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 20;
+
+/// A pack that could not be read or written, or a corrupt entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackError {
+    /// Path of the pack or of the entry.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "asset pack error in {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for PackError {}
+
+fn error(label: &str, message: impl fmt::Display) -> PackError {
+    PackError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Path of a file inside a pack: relative, `/`-separated.
+pub fn pack_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").trim_start_matches("./").to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PackEntry {
+    offset: u64,
+    stored: u64,
+    size: u64,
+    compressed: bool,
+}
+
+/// Largest unpacked-to-stored ratio LZ4 can produce, one extension byte per 255 bytes.
+const MAX_LZ4_RATIO: u64 = 255;
+
+impl PackEntry {
+    /// Whether `size` can be the unpacked length of `stored` bytes, so a corrupt index
+    /// cannot make [`AssetPack::read`] allocate more than the data could decompress to.
+    fn has_plausible_size(&self) -> bool {
+        if self.compressed {
+            self.size <= self.stored.saturating_mul(MAX_LZ4_RATIO)
+        } else {
+            self.size == self.stored
+        }
+    }
+}
+
+/// Collects files and writes them as one pack.
+#[derive(Default)]
+pub struct PackWriter {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl PackWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `bytes` under `path`, replacing an earlier file of the same path.
+    pub fn add(&mut self, path: impl AsRef<Path>, bytes: Vec<u8>) {
+        self.files.insert(pack_path(path.as_ref()), bytes);
+    }
+
+    /// Add every file below `directory`, with paths relative to it.
+    pub fn add_directory(&mut self, directory: impl AsRef<Path>) -> Result<usize, PackError> {
+        let directory = directory.as_ref();
+        let mut pending = vec![directory.to_path_buf()];
+        let mut added = 0;
+        while let Some(current) = pending.pop() {
+            let label = current.display().to_string();
+            for entry in std::fs::read_dir(&current).map_err(|e| error(&label, e))? {
+                let path = entry.map_err(|e| error(&label, e))?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let bytes = std::fs::read(&path).map_err(|e| error(&path.display().to_string(), e))?;
+                let relative = path.strip_prefix(directory).expect("walked below the directory");
+                self.add(relative, bytes);
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The pack as bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let _span = trace_span!("pack_write", files = self.files.len());
+        let mut blobs = Vec::new();
+        let mut index = Vec::new();
+        for (path, bytes) in &self.files {
+            let packed = lz4_flex::compress(bytes);
+            let compressed = packed.len() < bytes.len();
+            let stored: &[u8] = if compressed { &packed } else { bytes };
+            let offset = (HEADER_SIZE + blobs.len()) as u64;
+            blobs.extend_from_slice(stored);
+            index.extend_from_slice(&(path.len() as u16).to_le_bytes());
+            index.extend_from_slice(path.as_bytes());
+            for value in [offset, stored.len() as u64, bytes.len() as u64] {
+                index.extend_from_slice(&value.to_le_bytes());
+            }
+            index.push(compressed as u8);
+        }
+        let mut out = Vec::with_capacity(HEADER_SIZE + blobs.len() + index.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        out.extend_from_slice(&((HEADER_SIZE + blobs.len()) as u64).to_le_bytes());
+        out.extend_from_slice(&blobs);
+        out.extend_from_slice(&index);
+        out
+    }
+
+    /// Write the pack to a file.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), PackError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()).map_err(|e| error(&path.display().to_string(), e))
+    }
+}
+
+enum PackData {
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl PackData {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            PackData::Mapped(map) => &map[..],
+            PackData::Owned(bytes) => &bytes[..],
+        }
+    }
+}
+
+/// Split `len` bytes off the front of `cursor`.
+fn take<'a>(cursor: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let bytes: &'a [u8] = cursor;
+    let (head, tail) = (bytes.get(..len)?, bytes.get(len..)?);
+    *cursor = tail;
+    Some(head)
+}
+
+fn read_entry(index: &mut &[u8]) -> Option<(String, PackEntry)> {
+    let path_len = u16::from_le_bytes(take(index, 2)?.try_into().ok()?) as usize;
+    let path = String::from_utf8(take(index, path_len)?.to_vec()).ok()?;
+    let mut values = [0u64; 3];
+    for value in &mut values {
+        *value = u64::from_le_bytes(take(index, 8)?.try_into().ok()?);
+    }
+    let [offset, stored, size] = values;
+    let compressed = take(index, 1)?[0] == 1;
+    Some((path, PackEntry { offset, stored, size, compressed }))
+}
+
+/// A read-only pack, memory-mapped or in memory.
+pub struct AssetPack {
+    label: String,
+    data: PackData,
+    entries: BTreeMap<String, PackEntry>,
+}
+
+impl AssetPack {
+    /// Memory-map a pack file. The file must not be modified while the pack is open.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PackError> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let file = std::fs::File::open(path).map_err(|e| error(&label, e))?;
+        // SAFETY: the mapping is read-only and packs are not written while the game runs.
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| error(&label, e))?;
+        Self::new(label, PackData::Mapped(map))
+    }
+
+    /// A pack already in memory, e.g. fetched over the network on the web.
+    pub fn from_bytes(label: &str, bytes: Vec<u8>) -> Result<Self, PackError> {
+        Self::new(label.to_string(), PackData::Owned(bytes))
+    }
+
+    fn new(label: String, data: PackData) -> Result<Self, PackError> {
+        let bytes = data.bytes();
+        let corrupt = || error(&label, "truncated or corrupt pack");
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(error(&label, "not an asset pack"));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().expect("4 bytes"));
+        let version = u32_at(4);
+        if version != VERSION {
+            return Err(error(&label, format!("unsupported version {}", version)));
+        }
+        let count = u32_at(8) as usize;
+        let index_offset = u64::from_le_bytes(bytes[12..20].try_into().expect("8 bytes"));
+        let index_offset = usize::try_from(index_offset).map_err(|_| corrupt())?;
+        let mut index = bytes.get(index_offset..).ok_or_else(corrupt)?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let (path, entry) = read_entry(&mut index).ok_or_else(corrupt)?;
+            let end = entry.offset.checked_add(entry.stored).ok_or_else(corrupt)?;
+            if end > bytes.len() as u64 || !entry.has_plausible_size() {
+                return Err(corrupt());
+            }
+            entries.insert(path, entry);
+        }
+        trace_event!(label = %label, entries = entries.len(), "mounted asset pack");
+        Ok(Self { label, data, entries })
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries.contains_key(&pack_path(path))
+    }
+
+    /// Paths of all files, sorted.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Unpacked size of a file.
+    pub fn file_size(&self, path: &Path) -> Option<u64> {
+        self.entries.get(&pack_path(path)).map(|entry| entry.size)
+    }
+
+    /// The contents of a file, borrowed from the mapping if it is stored raw. `None` if
+    /// the pack has no such file.
+    pub fn read(&self, path: &Path) -> Option<Result<Cow<'_, [u8]>, PackError>> {
+        let key = pack_path(path);
+        let entry = self.entries.get(&key)?;
+        let stored = &self.data.bytes()[entry.offset as usize..(entry.offset + entry.stored) as usize];
+        if !entry.compressed {
+            return Some(Ok(Cow::Borrowed(stored)));
+        }
+        Some(
+            lz4_flex::decompress(stored, entry.size as usize)
+                .map(Cow::Owned)
+                .map_err(|e| error(&key, e)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes LZ4 cannot shrink.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn writer() -> PackWriter {
+        let mut writer = PackWriter::new();
+        writer.add("shaders/water.wgsl", b"fn main() {}\n".repeat(64));
+        writer.add("textures\\brick.rmtex", noise(4096));
+        writer.add("./empty.bin", Vec::new());
+        writer
+    }
+
+    fn read(pack: &AssetPack, path: &str) -> Vec<u8> {
+        pack.read(Path::new(path)).expect("file in pack").expect("readable").into_owned()
+    }
+
+    #[test]
+    fn round_trip_from_bytes() {
+        let pack = AssetPack::from_bytes("test", writer().to_bytes()).unwrap();
+        assert_eq!(pack.paths().collect::<Vec<_>>(), ["empty.bin", "shaders/water.wgsl", "textures/brick.rmtex"]);
+        assert_eq!(read(&pack, "shaders/water.wgsl"), b"fn main() {}\n".repeat(64));
+        assert_eq!(read(&pack, "textures/brick.rmtex"), noise(4096));
+        assert_eq!(read(&pack, "empty.bin"), Vec::<u8>::new());
+        assert_eq!(pack.file_size(Path::new("textures/brick.rmtex")), Some(4096));
+        assert!(pack.read(Path::new("missing.png")).is_none());
+    }
+
+    #[test]
+    fn compresses_only_when_smaller() {
+        let pack = AssetPack::from_bytes("test", writer().to_bytes()).unwrap();
+        let shader = &pack.entries["shaders/water.wgsl"];
+        assert!(shader.compressed && shader.stored < shader.size);
+        let texture = &pack.entries["textures/brick.rmtex"];
+        assert!(!texture.compressed && texture.stored == texture.size);
+        assert!(matches!(pack.read(Path::new("textures/brick.rmtex")), Some(Ok(Cow::Borrowed(_)))));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn round_trip_through_a_file() {
+        let path = std::env::temp_dir().join(format!("wgpu_render_manager_pack_{}.rmpak", std::process::id()));
+        writer().write(&path).unwrap();
+        let pack = AssetPack::open(&path).unwrap();
+        assert_eq!(read(&pack, "shaders/water.wgsl"), b"fn main() {}\n".repeat(64));
+        assert!(pack.contains(Path::new("./textures/brick.rmtex")));
+        drop(pack);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_foreign_and_truncated_data() {
+        assert_eq!(AssetPack::from_bytes("test", b"PK\x03\x04 not a pack at all".to_vec()).err().unwrap().message, "not an asset pack");
+        let mut bytes = writer().to_bytes();
+        bytes[4] = 2;
+        assert_eq!(AssetPack::from_bytes("test", bytes).err().unwrap().message, "unsupported version 2");
+        let mut bytes = writer().to_bytes();
+        bytes.truncate(bytes.len() - 3);
+        assert_eq!(AssetPack::from_bytes("test", bytes).err().unwrap().message, "truncated or corrupt pack");
+    }
+
+    /// Position of the `u64` offset, stored length and size of index entry `n`.
+    fn entry_values_at(bytes: &[u8], n: usize) -> usize {
+        let mut at = u64::from_le_bytes(bytes[12..20].try_into().unwrap()) as usize;
+        for _ in 0..n {
+            at += 2 + u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize + 3 * 8 + 1;
+        }
+        at + 2 + u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+    }
+
+    fn with_value(mut bytes: Vec<u8>, n: usize, field: usize, value: u64) -> Vec<u8> {
+        let at = entry_values_at(&bytes, n) + field * 8;
+        bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn rejects_corrupt_index_entries() {
+        let corrupt = |bytes| AssetPack::from_bytes("test", bytes).err().map(|e| e.message);
+        let expected = Some("truncated or corrupt pack".to_string());
+        // Entry 1 is the compressed shader, entry 2 the raw texture.
+        assert_eq!(corrupt(with_value(writer().to_bytes(), 1, 0, u64::MAX)), expected);
+        assert_eq!(corrupt(with_value(writer().to_bytes(), 1, 1, u64::MAX - 4)), expected);
+        assert_eq!(corrupt(with_value(writer().to_bytes(), 1, 2, u64::MAX)), expected);
+        assert_eq!(corrupt(with_value(writer().to_bytes(), 2, 2, 4097)), expected);
+        assert!(corrupt(with_value(writer().to_bytes(), 1, 2, 64 * 13)).is_none());
+    }
+}
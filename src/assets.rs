@@ -0,0 +1,705 @@
+//! Asynchronous asset loading behind handles (feature `assets`).
+//!
+//! [`AssetServer::load`] returns an [`AssetHandle`] immediately and reads the file on
+//! the [`ResourceWorkers`]: decoding images, parsing OBJ or glTF files. The GPU half
+//! (texture uploads, mesh uploads into the [`MeshManager`]) runs in
+//! [`update`](AssetServer::update), once per frame on the thread owning the managers.
+//! Until then, draw with a placeholder:
+//! ```ignore
+//! let mut assets = AssetServer::new(&device, &queue, render_manager.spawn_resource_workers(2));
+//! let albedo = assets.load::<LoadedTexture>("textures/brick.png");
+//! let house = assets.load::<GltfScene>("models/house.glb");
+//!
+//! // every frame
+//! assets.update(&mut meshes);
+//! let view = assets.get(albedo).map(|t| &t.view).unwrap_or(&placeholder.view);
+//! if let LoadState::Failed(e) = assets.load_state(house) {
+//!     eprintln!("{e}");
+//! }
+//! ```
+//!
+//! Loading a path again returns the same handle. A loading screen can block with
+//! [`wait`](AssetServer::wait), and async code can await
+//! [`loaded`](AssetServer::loaded) while the frame loop keeps calling `update`.
+//!
+//! With feature `pack`, asset packs added with `mount` are searched before
+//! the file system, from memory-mapped blobs.
+//!
+//! With [`set_disk_cache`](AssetServer::set_disk_cache), decoded images are kept in a
+//! [`DiskCache`] keyed by the file content, so later runs skip decoding them.
+//!
+//! With [`set_memory_budget`](AssetServer::set_memory_budget), assets over the budget are
+//! evicted by [`Priority`] and last use, and load again when their path is loaded.
+//!
+//! With [`set_reload_bus`](AssetServer::set_reload_bus), assets whose file changed are
+//! loaded again in the background and swapped in place, keeping their handles.
+//!
+//! Built-in assets are [`LoadedTexture`] (baked `.rmtex` files, `.basis` files transcoded
+//! for the device with feature `basis`, `.hdr`/`.exr` as `Rgba16Float` with feature `hdr`,
+//! or any other format of the `image` crate as `Rgba8UnormSrgb`), `ObjScene` (feature `obj`) and `GltfScene` (feature `gltf`).
+//! Other types implement [`Asset`].
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use wgpu::{Device, Features, Queue};
+#[cfg(feature = "pack")]
+use crate::asset_pack::AssetPack;
+use crate::disk_cache::{CacheKey, DiskCache};
+#[cfg(all(feature = "basis", not(target_arch = "wasm32")))]
+use crate::basis::transcode_for_device;
+#[cfg(feature = "hdr")]
+use crate::hdr_image::{is_hdr_path, HdrFormat, HdrImage};
+use crate::hot_reload::{AssetKind, ReloadBus, ReloadEvent, ReloadReceiver, ReloadStage};
+use crate::generator::texture_memory_bytes;
+use crate::meshes::MeshManager;
+use crate::residency::{Priority, ResidencyManager};
+use crate::texture_bake::BakedTexture;
+use crate::textures::LoadedTexture;
+use crate::workers::{Pending, ResourceWorkers};
+
+/// An asset that could not be read or created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetError {
+    /// Path of the asset.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+fn error(label: &str, message: impl fmt::Display) -> AssetError {
+    AssetError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// What [`Asset::finish`] gets to create GPU resources with.
+pub struct AssetContext<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub meshes: &'a mut MeshManager,
+    /// Path of the asset, for resource labels and errors.
+    pub label: &'a str,
+}
+
+/// A type the [`AssetServer`] can load from a file.
+pub trait Asset: Sized + 'static {
+    /// Kind of the [`ReloadEvent`]s published when the asset is reloaded.
+    const KIND: AssetKind = AssetKind::Other;
+
+    /// CPU-side data handed from the loader thread to [`finish`](Self::finish).
+    type Source: Send + 'static;
+
+    /// Name and version of the decoding, if sources are kept in the [`DiskCache`] set with
+    /// [`AssetServer::set_disk_cache`]. Bump the version when the decoding changes. Types
+    /// setting it are read through [`read_bytes`](Self::read_bytes) and implement
+    /// [`cache_source`](Self::cache_source) and [`uncache_source`](Self::uncache_source).
+    const CACHE_KIND: Option<&'static str> = None;
+
+    /// Read and decode the file. `features` are those of the server's device, for
+    /// sources transcoded into a format it supports. Runs on a worker thread.
+    fn read(path: &Path, features: Features) -> Result<Self::Source, AssetError>;
+
+    /// Decode the file from memory, for files in a mounted asset pack. `label` is the
+    /// path. Runs on a worker thread.
+    fn read_bytes(label: &str, bytes: &[u8], features: Features) -> Result<Self::Source, AssetError> {
+        let _ = (bytes, features);
+        Err(error(label, "this asset type can only be loaded from files"))
+    }
+
+    /// Serialize a decoded source for the disk cache, `None` if it is not worth caching.
+    fn cache_source(label: &str, source: &Self::Source) -> Option<Vec<u8>> {
+        let _ = (label, source);
+        None
+    }
+
+    /// Deserialize a source stored by [`cache_source`](Self::cache_source), `None` if the
+    /// entry is unusable.
+    fn uncache_source(label: &str, bytes: &[u8]) -> Option<Self::Source> {
+        let _ = (label, bytes);
+        None
+    }
+
+    /// Create the GPU resources. Runs in [`AssetServer::update`].
+    fn finish(source: Self::Source, context: &mut AssetContext) -> Result<Self, AssetError>;
+
+    /// GPU memory held by the asset, counted against
+    /// [`AssetServer::set_memory_budget`]. Assets reporting 0 are never evicted.
+    fn memory_bytes(&self) -> u64 {
+        0
+    }
+}
+
+fn has_extension(label: &str, extension: &str) -> bool {
+    Path::new(label).extension().is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+fn is_baked(label: &str) -> bool {
+    has_extension(label, "rmtex")
+}
+
+impl Asset for LoadedTexture {
+    const KIND: AssetKind = AssetKind::Texture;
+    type Source = BakedTexture;
+    const CACHE_KIND: Option<&'static str> = Some("decoded texture v1");
+
+    fn read(path: &Path, features: Features) -> Result<BakedTexture, AssetError> {
+        let label = path.display().to_string();
+        let bytes = std::fs::read(path).map_err(|e| error(&label, e))?;
+        Self::read_bytes(&label, &bytes, features)
+    }
+
+    fn read_bytes(label: &str, bytes: &[u8], features: Features) -> Result<BakedTexture, AssetError> {
+        if is_baked(label) {
+            return BakedTexture::from_bytes(label, bytes).map_err(|e| error(label, e.message));
+        }
+        #[cfg(all(feature = "basis", not(target_arch = "wasm32")))]
+        if has_extension(label, "basis") {
+            return transcode_for_device(label, bytes, features, true).map_err(|e| error(label, e.message));
+        }
+        let _ = features;
+        #[cfg(feature = "hdr")]
+        if is_hdr_path(Path::new(label)) {
+            let image = HdrImage::from_memory(label, bytes).map_err(|e| error(label, e.message))?;
+            return Ok(image.to_baked(HdrFormat::Rgba16Float));
+        }
+        let image = image::load_from_memory(bytes).map_err(|e| error(label, e))?.to_rgba8();
+        Ok(BakedTexture::rgba8(image.width(), image.height(), image.into_raw()))
+    }
+
+    fn cache_source(label: &str, source: &BakedTexture) -> Option<Vec<u8>> {
+        // Baked files load as fast as the cache entry would, transcoded ones depend on
+        // the device.
+        (!is_baked(label) && !has_extension(label, "basis")).then(|| source.to_bytes())
+    }
+
+    fn uncache_source(label: &str, bytes: &[u8]) -> Option<BakedTexture> {
+        BakedTexture::from_bytes(label, bytes).ok()
+    }
+
+    fn finish(source: BakedTexture, context: &mut AssetContext) -> Result<Self, AssetError> {
+        source
+            .upload(context.device, context.queue, context.label)
+            .map_err(|e| error(context.label, e.message))
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        texture_memory_bytes(&self.texture)
+    }
+}
+
+#[cfg(feature = "obj")]
+impl Asset for crate::obj_import::ObjScene {
+    const KIND: AssetKind = AssetKind::Scene;
+    type Source = crate::obj_import::ObjSource;
+
+    fn read(path: &Path, _features: Features) -> Result<Self::Source, AssetError> {
+        crate::obj_import::read_obj(path).map_err(|e| error(&e.label, e.message))
+    }
+
+    fn finish(source: Self::Source, context: &mut AssetContext) -> Result<Self, AssetError> {
+        Ok(crate::obj_import::upload_obj(context.device, context.queue, context.meshes, source))
+    }
+}
+
+#[cfg(feature = "gltf")]
+impl Asset for crate::gltf_import::GltfScene {
+    const KIND: AssetKind = AssetKind::Scene;
+    type Source = crate::gltf_import::GltfSource;
+
+    fn read(path: &Path, _features: Features) -> Result<Self::Source, AssetError> {
+        crate::gltf_import::read_gltf(path).map_err(|e| error(&e.label, e.message))
+    }
+
+    /// `.glb` files, or `.gltf` files with embedded buffers and images.
+    fn read_bytes(label: &str, bytes: &[u8], _features: Features) -> Result<Self::Source, AssetError> {
+        crate::gltf_import::read_gltf_slice(label, bytes).map_err(|e| error(&e.label, e.message))
+    }
+
+    fn finish(source: Self::Source, context: &mut AssetContext) -> Result<Self, AssetError> {
+        crate::gltf_import::upload_gltf(context.device, context.queue, context.meshes, source)
+            .map_err(|e| error(&e.label, e.message))
+    }
+}
+
+/// Typed handle to an asset of an [`AssetServer`].
+pub struct AssetHandle<T> {
+    id: u64,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for AssetHandle<T> {}
+
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for AssetHandle<T> {}
+
+impl<T> Hash for AssetHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AssetHandle({})", self.id)
+    }
+}
+
+/// Where an asset is in its loading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    /// Reading on a worker, or waiting for the next [`AssetServer::update`].
+    Loading,
+    Loaded,
+    Failed(AssetError),
+    /// Dropped to stay within the [memory budget](AssetServer::set_memory_budget).
+    /// Loading its path again reloads it under the same handle.
+    Evicted,
+    /// Removed with [`AssetServer::remove`].
+    NotLoaded,
+}
+
+type SourceSlot<T> = Mutex<Option<Result<<T as Asset>::Source, AssetError>>>;
+
+/// A read in flight, with its asset type erased.
+trait PendingLoad {
+    fn is_done(&self) -> bool;
+    fn wait(&self);
+    /// The asset and its [`memory_bytes`](Asset::memory_bytes).
+    fn finish(&self, context: &mut AssetContext) -> Result<(Box<dyn Any>, u64), AssetError>;
+}
+
+struct TypedLoad<T: Asset> {
+    pending: Pending<SourceSlot<T>>,
+}
+
+impl<T: Asset> PendingLoad for TypedLoad<T> {
+    fn is_done(&self) -> bool {
+        self.pending.is_done()
+    }
+
+    fn wait(&self) {
+        self.pending.wait();
+    }
+
+    fn finish(&self, context: &mut AssetContext) -> Result<(Box<dyn Any>, u64), AssetError> {
+        let Some(slot) = self.pending.get() else {
+            return Err(error(context.label, self.pending.error().unwrap_or("the loader panicked")));
+        };
+        let source = slot
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .expect("asset sources are finished once")?;
+        let asset = T::finish(source, context)?;
+        let bytes = asset.memory_bytes();
+        Ok((Box::new(asset), bytes))
+    }
+}
+
+/// Asset packs searched before the file system, last mounted first, the disk cache of
+/// decoded sources and the device features sources are read for.
+#[derive(Clone, Default)]
+struct Mounts {
+    #[cfg(feature = "pack")]
+    packs: Vec<Arc<AssetPack>>,
+    cache: Option<DiskCache>,
+    features: Features,
+}
+
+impl Mounts {
+    /// The file from the last pack containing it.
+    #[cfg(feature = "pack")]
+    fn pack_bytes(&self, path: &Path) -> Option<Result<Cow<'_, [u8]>, AssetError>> {
+        let pack = self.packs.iter().rev().find(|pack| pack.contains(path))?;
+        Some(pack.read(path)?.map_err(|e| error(&path.display().to_string(), e.message)))
+    }
+
+    #[cfg(not(feature = "pack"))]
+    fn pack_bytes(&self, _path: &Path) -> Option<Result<Cow<'_, [u8]>, AssetError>> {
+        None
+    }
+
+    fn read<T: Asset>(&self, path: &Path) -> Result<T::Source, AssetError> {
+        let label = path.display().to_string();
+        let (Some(cache), Some(kind)) = (&self.cache, T::CACHE_KIND) else {
+            return match self.pack_bytes(path) {
+                Some(bytes) => T::read_bytes(&label, &bytes?, self.features),
+                None => T::read(path, self.features),
+            };
+        };
+        let bytes = match self.pack_bytes(path) {
+            Some(bytes) => bytes?,
+            None => Cow::Owned(std::fs::read(path).map_err(|e| error(&label, e))?),
+        };
+        let key = CacheKey::new(kind).with(&bytes);
+        if let Some(source) = cache.get(key).and_then(|cached| T::uncache_source(&label, &cached)) {
+            return Ok(source);
+        }
+        let source = T::read_bytes(&label, &bytes, self.features)?;
+        if let Some(cached) = T::cache_source(&label, &source)
+            && let Err(error) = cache.put(key, &cached)
+        {
+            trace_warn!(label = %label, %error, "failed to cache decoded asset source");
+            let _ = error;
+        }
+        Ok(source)
+    }
+}
+
+fn start_load<T: Asset>(workers: &ResourceWorkers, path: &Path, mounts: &Mounts) -> Box<dyn PendingLoad> {
+    let read_path = path.to_path_buf();
+    let mounts = mounts.clone();
+    let pending = workers.spawn(&path.display().to_string(), move |_, _| {
+        let source = mounts.read::<T>(&read_path);
+        Mutex::new(Some(source))
+    });
+    Box::new(TypedLoad::<T> { pending })
+}
+
+struct Entry {
+    path: PathBuf,
+    type_id: TypeId,
+    kind: AssetKind,
+    start: fn(&ResourceWorkers, &Path, &Mounts) -> Box<dyn PendingLoad>,
+    /// The first load, or a reload while `asset` still holds the previous version.
+    pending: Option<Box<dyn PendingLoad>>,
+    asset: Option<Box<dyn Any>>,
+    error: Option<AssetError>,
+    priority: Priority,
+    evicted: bool,
+}
+
+/// Finished loads and the futures waiting for them, shared with [`LoadFuture`]s.
+#[derive(Default)]
+struct Completions {
+    finished: HashMap<u64, Result<(), AssetError>>,
+    wakers: HashMap<u64, Vec<Waker>>,
+}
+
+/// Resolves once [`AssetServer::update`] finished (or failed) an asset.
+///
+/// Never resolves if the asset is removed before.
+pub struct LoadFuture {
+    id: u64,
+    completions: Arc<Mutex<Completions>>,
+}
+
+impl Future for LoadFuture {
+    type Output = Result<(), AssetError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut completions = self.completions.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(result) = completions.finished.get(&self.id) {
+            return Poll::Ready(result.clone());
+        }
+        completions.wakers.entry(self.id).or_default().push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Loads assets of any [`Asset`] type by path, once each, behind typed handles.
+pub struct AssetServer {
+    device: Device,
+    queue: Queue,
+    workers: ResourceWorkers,
+    ids: HashMap<(TypeId, PathBuf), u64>,
+    entries: HashMap<u64, Entry>,
+    next_id: u64,
+    completions: Arc<Mutex<Completions>>,
+    reloads: Option<(ReloadBus, ReloadReceiver)>,
+    mounts: Mounts,
+    residency: ResidencyManager<u64>,
+}
+
+impl AssetServer {
+    /// Read files on `workers`, e.g. from
+    /// [`RenderManager::spawn_resource_workers`](crate::renderer::RenderManager::spawn_resource_workers).
+    pub fn new(device: &Device, queue: &Queue, workers: ResourceWorkers) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            workers,
+            ids: HashMap::new(),
+            entries: HashMap::new(),
+            next_id: 0,
+            completions: Arc::new(Mutex::new(Completions::default())),
+            reloads: None,
+            mounts: Mounts {
+                features: device.features(),
+                ..Mounts::default()
+            },
+            residency: ResidencyManager::new(u64::MAX),
+        }
+    }
+
+    /// Load assets again when `bus` reports their file [`Changed`](ReloadStage::Changed),
+    /// and publish [`Reloaded`](ReloadStage::Reloaded) once the new version replaced the
+    /// old one in [`update`](Self::update). Paths are compared as given to
+    /// [`load`](Self::load).
+    pub fn set_reload_bus(&mut self, bus: &ReloadBus) {
+        let receiver = bus.subscribe(|event| event.stage == ReloadStage::Changed);
+        self.reloads = Some((bus.clone(), receiver));
+    }
+
+    /// Switch to `device` after a device loss and load every asset that is not evicted
+    /// again, see [`RenderManager::recreate`](crate::renderer::RenderManager::recreate).
+    /// Handles stay valid; [`get`](Self::get) returns the old-device version of an asset
+    /// until [`update`](Self::update) finished its reload on the new device.
+    pub fn recreate(&mut self, device: &Device, queue: &Queue) {
+        trace_event!(assets = self.entries.len(), "reloading assets on a new device");
+        self.device = device.clone();
+        self.queue = queue.clone();
+        self.mounts.features = device.features();
+        for entry in self.entries.values_mut().filter(|entry| !entry.evicted) {
+            entry.pending = Some((entry.start)(&self.workers, &entry.path, &self.mounts));
+        }
+    }
+
+    /// Load files contained in `pack` from it instead of the file system. Packs mounted
+    /// later take precedence. Only loads started afterwards use it.
+    #[cfg(feature = "pack")]
+    pub fn mount(&mut self, pack: AssetPack) {
+        self.mounts.packs.push(Arc::new(pack));
+    }
+
+    /// Keep decoded sources of asset types with a [`CACHE_KIND`](Asset::CACHE_KIND) in
+    /// `cache`, keyed by the file content. Only loads started afterwards use it.
+    pub fn set_disk_cache(&mut self, cache: DiskCache) {
+        self.mounts.cache = Some(cache);
+    }
+
+    /// Keep loaded assets within `bytes` of GPU memory, as reported by
+    /// [`Asset::memory_bytes`]. Whenever [`update`](Self::update) finds them over budget it
+    /// evicts the lowest [`Priority`] first, least recently [`get`](Self::get) first among
+    /// equals, until they fit. Unlimited by default.
+    pub fn set_memory_budget(&mut self, bytes: u64) {
+        self.residency.set_budget(bytes);
+    }
+
+    pub fn memory_budget(&self) -> u64 {
+        self.residency.budget()
+    }
+
+    /// GPU memory of the loaded assets.
+    pub fn memory_usage(&self) -> u64 {
+        self.residency.resident_bytes()
+    }
+
+    /// Set how much `handle` is worth keeping under memory pressure. Assets start at
+    /// [`Priority::NEARBY`].
+    pub fn set_priority<T: Asset>(&mut self, handle: AssetHandle<T>, priority: Priority) {
+        if let Some(entry) = self.entries.get_mut(&handle.id) {
+            entry.priority = priority;
+            self.residency.set_priority(&handle.id, priority);
+        }
+    }
+
+    /// Start loading `path` as a `T`, or return the handle of an earlier load of it.
+    /// An [evicted](LoadState::Evicted) asset starts loading again.
+    pub fn load<T: Asset>(&mut self, path: impl AsRef<Path>) -> AssetHandle<T> {
+        let path = path.as_ref().to_path_buf();
+        let key = (TypeId::of::<T>(), path.clone());
+        if let Some(&id) = self.ids.get(&key) {
+            let entry = self.entries.get_mut(&id).expect("ids and entries match");
+            if entry.evicted && entry.pending.is_none() {
+                trace_event!(id, path = %path.display(), "evicted asset reloading");
+                entry.evicted = false;
+                entry.pending = Some((entry.start)(&self.workers, &entry.path, &self.mounts));
+            }
+            return AssetHandle { id, marker: PhantomData };
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        trace_event!(id, path = %path.display(), "asset load started");
+        let pending = start_load::<T>(&self.workers, &path, &self.mounts);
+        self.ids.insert(key, id);
+        self.entries.insert(
+            id,
+            Entry {
+                path,
+                type_id: TypeId::of::<T>(),
+                kind: T::KIND,
+                start: start_load::<T>,
+                pending: Some(pending),
+                asset: None,
+                error: None,
+                priority: Priority::default(),
+                evicted: false,
+            },
+        );
+        AssetHandle { id, marker: PhantomData }
+    }
+
+    /// Create the GPU resources of every asset read since the last update, and start
+    /// reloading changed ones. Call once per frame; returns the number of assets that
+    /// finished loading or failed.
+    ///
+    /// A reload that fails keeps the previous version, see [`error`](Self::error).
+    pub fn update(&mut self, meshes: &mut MeshManager) -> usize {
+        let _span = trace_span!("asset_update", pending = self.pending_count());
+        if let Some((_, receiver)) = &self.reloads {
+            for event in receiver.try_iter() {
+                for entry in self.entries.values_mut().filter(|entry| entry.path == event.path) {
+                    if entry.pending.is_none() {
+                        entry.pending = Some((entry.start)(&self.workers, &entry.path, &self.mounts));
+                    }
+                }
+            }
+        }
+        let mut finished = 0;
+        for (&id, entry) in &mut self.entries {
+            if !entry.pending.as_ref().is_some_and(|load| load.is_done()) {
+                continue;
+            }
+            let load = entry.pending.take().expect("checked above");
+            let label = entry.path.display().to_string();
+            let mut context = AssetContext {
+                device: &self.device,
+                queue: &self.queue,
+                meshes: &mut *meshes,
+                label: &label,
+            };
+            let result = load.finish(&mut context);
+            let mut completions = self.completions.lock().unwrap_or_else(PoisonError::into_inner);
+            completions.finished.insert(id, result.as_ref().map(|_| ()).map_err(Clone::clone));
+            for waker in completions.wakers.remove(&id).unwrap_or_default() {
+                waker.wake();
+            }
+            match result {
+                Ok((asset, bytes)) => {
+                    let reloaded = entry.asset.replace(asset).is_some();
+                    entry.error = None;
+                    entry.evicted = false;
+                    self.residency.remove(&id);
+                    if bytes > 0 {
+                        self.residency.insert(id, bytes, entry.priority);
+                    }
+                    if reloaded && let Some((bus, _)) = &self.reloads {
+                        bus.publish(ReloadEvent::reloaded(entry.path.clone(), entry.kind));
+                    }
+                }
+                Err(e) => {
+                    if entry.asset.is_some() {
+                        trace_warn!(error = %e, "asset reload failed, keeping the previous version");
+                    }
+                    entry.error = Some(e);
+                }
+            }
+            finished += 1;
+        }
+        for id in self.residency.evictions() {
+            let entry = self.entries.get_mut(&id).expect("resident assets have entries");
+            trace_event!(id, path = %entry.path.display(), "asset evicted");
+            entry.asset = None;
+            entry.evicted = true;
+        }
+        finished
+    }
+
+    /// Block until `handle` is read, then [`update`](Self::update) and return it.
+    pub fn wait<T: Asset>(&mut self, handle: AssetHandle<T>, meshes: &mut MeshManager) -> Result<&T, AssetError> {
+        if let Some(load) = self.entries.get(&handle.id).and_then(|entry| entry.pending.as_ref()) {
+            load.wait();
+        }
+        self.update(meshes);
+        match self.entries.get(&handle.id) {
+            Some(Entry { asset: Some(asset), .. }) => Ok(asset.downcast_ref().expect("handle type matches its entry")),
+            Some(Entry { error: Some(e), .. }) => Err(e.clone()),
+            _ => Err(error(&format!("asset {}", handle.id), "the asset was removed")),
+        }
+    }
+
+    /// A future resolving once `handle` is loaded, for async code running beside the
+    /// frame loop that calls [`update`](Self::update).
+    pub fn loaded<T: Asset>(&self, handle: AssetHandle<T>) -> LoadFuture {
+        LoadFuture {
+            id: handle.id,
+            completions: self.completions.clone(),
+        }
+    }
+
+    /// The asset, or `None` while it is loading, if it failed or was evicted. Counts as a
+    /// use for the memory budget.
+    pub fn get<T: Asset>(&self, handle: AssetHandle<T>) -> Option<&T> {
+        self.residency.touch(&handle.id);
+        self.entries.get(&handle.id)?.asset.as_ref()?.downcast_ref()
+    }
+
+    pub fn load_state<T: Asset>(&self, handle: AssetHandle<T>) -> LoadState {
+        match self.entries.get(&handle.id) {
+            Some(Entry { asset: Some(_), .. }) => LoadState::Loaded,
+            Some(Entry { pending: Some(_), .. }) => LoadState::Loading,
+            Some(Entry { error: Some(e), .. }) => LoadState::Failed(e.clone()),
+            Some(Entry { evicted: true, .. }) => LoadState::Evicted,
+            _ => LoadState::NotLoaded,
+        }
+    }
+
+    /// Why the last load or reload of `handle` failed. A failed reload keeps the previous
+    /// version [`Loaded`](LoadState::Loaded), this is where its error shows up.
+    pub fn error<T: Asset>(&self, handle: AssetHandle<T>) -> Option<&AssetError> {
+        self.entries.get(&handle.id).and_then(|entry| entry.error.as_ref())
+    }
+
+    pub fn is_loaded<T: Asset>(&self, handle: AssetHandle<T>) -> bool {
+        self.load_state(handle) == LoadState::Loaded
+    }
+
+    /// Path the asset was loaded from.
+    pub fn path<T: Asset>(&self, handle: AssetHandle<T>) -> Option<&Path> {
+        self.entries.get(&handle.id).map(|entry| entry.path.as_path())
+    }
+
+    /// Forget an asset and return it if it was loaded. Loading its path again starts a
+    /// new load with a new handle.
+    pub fn remove<T: Asset>(&mut self, handle: AssetHandle<T>) -> Option<T> {
+        let entry = self.entries.remove(&handle.id)?;
+        self.ids.remove(&(entry.type_id, entry.path));
+        self.residency.remove(&handle.id);
+        let mut completions = self.completions.lock().unwrap_or_else(PoisonError::into_inner);
+        completions.finished.remove(&handle.id);
+        completions.wakers.remove(&handle.id);
+        entry.asset?.downcast().ok().map(|asset| *asset)
+    }
+
+    /// Number of assets still loading, e.g. for a loading screen.
+    pub fn pending_count(&self) -> usize {
+        self.entries.values().filter(|entry| entry.pending.is_some()).count()
+    }
+
+    /// Number of assets, in any state.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -0,0 +1,146 @@
+//! Basis Universal transcoding (feature `basis`).
+//!
+//! A `.basis` file (ETC1S or UASTC) is shipped once and transcoded at load time into the
+//! best compressed format the device samples: BC7 on desktop, ASTC 4x4 on Apple and
+//! most mobile GPUs, ETC2 on the rest, and uncompressed RGBA8 as a last resort:
+//! ```ignore
+//! let baked = transcode_for_device("brick", &std::fs::read("textures/brick.basis")?, device.features(), true)?;
+//! let texture = baked.upload(&device, &queue, "brick")?;
+//! ```
+//! The `AssetServer` loads `.basis` files this way for its device.
+//!
+//! The transcoder is the C++ reference implementation, so building needs a C++
+//! compiler for the target. It does not build for `wasm32`, the module is native only
+//! and web builds should ship textures pre-transcoded, e.g. baked `.rmtex` files.
+use std::fmt;
+use basis_universal::{TranscodeParameters, Transcoder, TranscoderTextureFormat};
+use wgpu::{AstcBlock, AstcChannel, Features, TextureFormat};
+use crate::texture_bake::BakedTexture;
+
+/// A `.basis` file that could not be transcoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasisError {
+    /// Path or label of the texture.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for BasisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to transcode {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for BasisError {}
+
+fn error(label: &str, message: impl fmt::Display) -> BasisError {
+    BasisError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Format a `.basis` file is transcoded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TranscodeTarget {
+    /// Needs `TEXTURE_COMPRESSION_BC`.
+    Bc7,
+    /// Needs `TEXTURE_COMPRESSION_ASTC`.
+    Astc4x4,
+    /// Needs `TEXTURE_COMPRESSION_ETC2`.
+    Etc2,
+    /// Uncompressed, always supported.
+    Rgba8,
+}
+
+impl TranscodeTarget {
+    /// The best target among `features`, in the order of the variants.
+    pub fn for_features(features: Features) -> Self {
+        if features.contains(Features::TEXTURE_COMPRESSION_BC) {
+            TranscodeTarget::Bc7
+        } else if features.contains(Features::TEXTURE_COMPRESSION_ASTC) {
+            TranscodeTarget::Astc4x4
+        } else if features.contains(Features::TEXTURE_COMPRESSION_ETC2) {
+            TranscodeTarget::Etc2
+        } else {
+            TranscodeTarget::Rgba8
+        }
+    }
+
+    pub fn texture_format(self, srgb: bool) -> TextureFormat {
+        let (linear, srgb_format) = match self {
+            TranscodeTarget::Bc7 => (TextureFormat::Bc7RgbaUnorm, TextureFormat::Bc7RgbaUnormSrgb),
+            TranscodeTarget::Astc4x4 => (
+                TextureFormat::Astc { block: AstcBlock::B4x4, channel: AstcChannel::Unorm },
+                TextureFormat::Astc { block: AstcBlock::B4x4, channel: AstcChannel::UnormSrgb },
+            ),
+            TranscodeTarget::Etc2 => (TextureFormat::Etc2Rgba8Unorm, TextureFormat::Etc2Rgba8UnormSrgb),
+            TranscodeTarget::Rgba8 => (TextureFormat::Rgba8Unorm, TextureFormat::Rgba8UnormSrgb),
+        };
+        if srgb { srgb_format } else { linear }
+    }
+
+    fn transcoder_format(self) -> TranscoderTextureFormat {
+        match self {
+            TranscodeTarget::Bc7 => TranscoderTextureFormat::BC7_RGBA,
+            TranscodeTarget::Astc4x4 => TranscoderTextureFormat::ASTC_4x4_RGBA,
+            TranscodeTarget::Etc2 => TranscoderTextureFormat::ETC2_RGBA,
+            TranscodeTarget::Rgba8 => TranscoderTextureFormat::RGBA32,
+        }
+    }
+}
+
+/// Transcode the first image of a `.basis` file with all its mips into `target`.
+/// `srgb` selects the sRGB variant of the format, for color textures.
+pub fn transcode(label: &str, bytes: &[u8], target: TranscodeTarget, srgb: bool) -> Result<BakedTexture, BasisError> {
+    let _span = trace_span!("basis_transcode", label, target = ?target);
+    basis_universal::transcoder_init();
+    let mut transcoder = Transcoder::new();
+    if !transcoder.validate_header(bytes) {
+        return Err(error(label, "not a Basis Universal file"));
+    }
+    if transcoder.image_count(bytes) == 0 {
+        return Err(error(label, "no images"));
+    }
+    let level_count = transcoder.image_level_count(bytes, 0);
+    let description = transcoder
+        .image_level_description(bytes, 0, 0)
+        .ok_or_else(|| error(label, "no mip levels"))?;
+    transcoder.prepare_transcoding(bytes).map_err(|_| error(label, "corrupt file"))?;
+    let mips = (0..level_count)
+        .map(|level| {
+            transcoder
+                .transcode_image_level(
+                    bytes,
+                    target.transcoder_format(),
+                    TranscodeParameters {
+                        image_index: 0,
+                        level_index: level,
+                        decode_flags: None,
+                        output_row_pitch_in_blocks_or_pixels: None,
+                        output_rows_in_pixels: None,
+                    },
+                )
+                .map_err(|e| error(label, format!("mip {}: {:?}", level, e)))
+        })
+        .collect::<Result<Vec<_>, _>>();
+    transcoder.end_transcoding();
+    Ok(BakedTexture {
+        format: target.texture_format(srgb),
+        width: description.original_width,
+        height: description.original_height,
+        mips: mips?,
+    })
+}
+
+/// [`transcode`] into the best target for a device with `features`. Block-compressed
+/// textures need a size that is a multiple of 4, others are transcoded to RGBA8.
+pub fn transcode_for_device(label: &str, bytes: &[u8], features: Features, srgb: bool) -> Result<BakedTexture, BasisError> {
+    basis_universal::transcoder_init();
+    let transcoder = Transcoder::new();
+    let aligned = transcoder
+        .image_level_description(bytes, 0, 0)
+        .is_some_and(|level| level.original_width % 4 == 0 && level.original_height % 4 == 0);
+    let target = if aligned { TranscodeTarget::for_features(features) } else { TranscodeTarget::Rgba8 };
+    transcode(label, bytes, target, srgb)
+}
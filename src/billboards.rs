@@ -0,0 +1,359 @@
+//! Camera-facing quads in 3D scenes.
+//!
+//! [`Billboards`] draws textured quads that face the camera, either fully
+//! ([`BillboardMode::Full`], e.g. sprites, particles, markers) or rotating only around
+//! an axis ([`BillboardMode::AxisLocked`], e.g. trees, beams, candle flames). Quads are
+//! expanded in the vertex shader from per-instance data; billboards are grouped by
+//! texture, sorted back to front within each group and drawn with one instanced draw
+//! per texture, bound through the [`RenderManager`] material bind group cache.
+//!
+//! Billboards are alpha blended, tested against the scene depth and never write it,
+//! so draw them after the opaque geometry. The camera comes from a
+//! [`CameraManager`](crate::camera::CameraManager), bound at its fixed group.
+//! ```ignore
+//! let mut billboards = Billboards::new(&device, &queue, surface.scene_format(), Some(DEPTH_FORMAT), false);
+//! // Every frame
+//! billboards.clear();
+//! for light in &lights {
+//!     billboards.push(&glow, Billboard::new(light.position, [0.5, 0.5]).with_color(light.color));
+//! }
+//! billboards.push(&tree, Billboard::new(tree_base, [2.0, 4.0]).with_origin([0.5, 1.0]).with_mode(BillboardMode::AxisLocked([0.0, 1.0, 0.0])));
+//! billboards.upload(&mut render_manager, &camera);
+//! // Inside the scene pass, after opaque geometry
+//! billboards.render(&mut pass, &camera);
+//! ```
+use wgpu::*;
+use crate::camera::{CameraManager, CAMERA_GROUP, CAMERA_WGSL};
+use crate::gpu_util;
+use crate::pipelines::uniform_layout_entries;
+use crate::renderer::RenderManager;
+use crate::labels::namespaced;
+
+/// Appended to [`CAMERA_WGSL`].
+const BILLBOARD_SHADER: &str = r#"
+struct BillboardInstance {
+    @location(0) position: vec4<f32>,
+    @location(1) size: vec4<f32>,
+    @location(2) axis: vec4<f32>,
+    @location(3) uv_rect: vec4<f32>,
+    @location(4) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var s_billboard: sampler;
+@group(0) @binding(1) var t_billboard: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32, billboard: BillboardInstance) -> VertexOutput {
+    let corner = vec2<f32>(f32(idx & 1u), f32(idx >> 1u));
+    let center = billboard.position.xyz;
+    var right = vec3<f32>(camera.view[0][0], camera.view[1][0], camera.view[2][0]);
+    var up = vec3<f32>(camera.view[0][1], camera.view[1][1], camera.view[2][1]);
+    if billboard.size.w > 0.5 {
+        // Axis-locked: up is the axis, right faces the camera as far as the axis allows.
+        up = normalize(billboard.axis.xyz);
+        let side = cross(up, camera.position - center);
+        if dot(side, side) > 1e-8 {
+            right = normalize(side);
+        }
+    }
+    // Corner offset from the pivot in quad space, y up, rotated counter-clockwise as
+    // seen by the camera.
+    let pivot = vec2<f32>(billboard.size.z, billboard.axis.w);
+    let local = (vec2<f32>(corner.x, 1.0 - corner.y) - pivot) * billboard.size.xy;
+    let s = sin(billboard.position.w);
+    let c = cos(billboard.position.w);
+    let rotated = vec2<f32>(local.x * c - local.y * s, local.x * s + local.y * c);
+    let world = center + right * rotated.x + up * rotated.y;
+    var out: VertexOutput;
+    out.clip = camera.view_proj * vec4<f32>(world, 1.0);
+    out.uv = mix(billboard.uv_rect.xy, billboard.uv_rect.zw, corner);
+    out.color = billboard.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_billboard, s_billboard, in.uv) * in.color;
+}
+"#;
+
+const BILLBOARD_ATTRIBUTES: [VertexAttribute; 5] =
+    wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Float32x4, 4 => Float32x4];
+
+const BILLBOARD_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<BillboardInstance>() as u64,
+    step_mode: VertexStepMode::Instance,
+    attributes: &BILLBOARD_ATTRIBUTES,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BillboardInstance {
+    /// World position, then rotation.
+    position: [f32; 4],
+    /// Width, height, horizontal origin, 1 if axis-locked.
+    size: [f32; 4],
+    /// Lock axis, then vertical origin.
+    axis: [f32; 4],
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+/// How a [`Billboard`] turns towards the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BillboardMode {
+    /// Parallel to the view plane.
+    Full,
+    /// Upright along a world-space axis, turning only around it.
+    AxisLocked([f32; 3]),
+}
+
+/// One camera-facing quad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Billboard {
+    /// Where the [`origin`](Self::origin) lands, in world space.
+    pub position: [f32; 3],
+    /// Width and height in world units.
+    pub size: [f32; 2],
+    /// Rotation within the quad's plane in radians, counter-clockwise as seen by the
+    /// camera. Applied to full billboards only.
+    pub rotation: f32,
+    /// Pivot of position and rotation within the quad, from `[0, 0]` (top left) to
+    /// `[1, 1]` (bottom right).
+    pub origin: [f32; 2],
+    pub mode: BillboardMode,
+    /// UVs of the top left and bottom right corner.
+    pub uv_rect: [f32; 4],
+    /// Multiplied with the texture color, straight (not premultiplied) RGBA.
+    pub color: [f32; 4],
+}
+
+impl Billboard {
+    /// An untinted, camera-facing billboard of the whole texture centered on `position`.
+    pub fn new(position: [f32; 3], size: [f32; 2]) -> Self {
+        Self {
+            position,
+            size,
+            rotation: 0.0,
+            origin: [0.5, 0.5],
+            mode: BillboardMode::Full,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            color: [1.0; 4],
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_origin(mut self, origin: [f32; 2]) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: BillboardMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_uv_rect(mut self, uv_rect: [f32; 4]) -> Self {
+        self.uv_rect = uv_rect;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    fn instance(&self) -> BillboardInstance {
+        let (locked, axis, rotation) = match self.mode {
+            BillboardMode::Full => (0.0, [0.0, 1.0, 0.0], self.rotation),
+            BillboardMode::AxisLocked(axis) => (1.0, axis, 0.0),
+        };
+        let [x, y, z] = self.position;
+        BillboardInstance {
+            position: [x, y, z, rotation],
+            size: [self.size[0], self.size[1], self.origin[0], locked],
+            // The shader's quad space has y up, so the vertical origin is flipped.
+            axis: [axis[0], axis[1], axis[2], 1.0 - self.origin[1]],
+            uv_rect: self.uv_rect,
+            color: self.color,
+        }
+    }
+}
+
+/// Billboards sharing one texture.
+struct BillboardBatch {
+    texture: TextureView,
+    billboards: Vec<Billboard>,
+    bind_group: Option<BindGroup>,
+    first_instance: u32,
+}
+
+/// Collects billboards per texture and draws them facing the camera.
+pub struct Billboards {
+    device: Device,
+    queue: Queue,
+    format: TextureFormat,
+    depth_format: Option<TextureFormat>,
+    reversed_z: bool,
+    pipeline: Option<RenderPipeline>,
+    batches: Vec<BillboardBatch>,
+    instances: Buffer,
+    instance_capacity: u64,
+}
+
+impl Billboards {
+    /// Create a renderer drawing into targets of `format`, tested against a depth
+    /// buffer of `depth_format` if given. `reversed_z` must match the camera projection.
+    pub fn new(device: &Device, queue: &Queue, format: TextureFormat, depth_format: Option<TextureFormat>, reversed_z: bool) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            format,
+            depth_format,
+            reversed_z,
+            pipeline: None,
+            batches: Vec::new(),
+            instances: instance_buffer(device, 256),
+            instance_capacity: 256,
+        }
+    }
+
+    /// Add a billboard showing `texture`, a filterable 2D float texture.
+    pub fn push(&mut self, texture: &TextureView, billboard: Billboard) {
+        match self.batches.iter_mut().find(|batch| &batch.texture == texture) {
+            Some(batch) => batch.billboards.push(billboard),
+            None => self.batches.push(BillboardBatch {
+                texture: texture.clone(),
+                billboards: vec![billboard],
+                bind_group: None,
+                first_instance: 0,
+            }),
+        }
+    }
+
+    /// Remove all billboards.
+    pub fn clear(&mut self) {
+        self.batches.clear();
+    }
+
+    /// Number of billboards pushed since the last [`clear`](Self::clear).
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(|batch| batch.billboards.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Sort every batch back to front from `camera`, resolve the texture bind groups and
+    /// upload the instances.
+    ///
+    /// Written with `Queue::write_buffer`, so upload once per submission.
+    pub fn upload(&mut self, render_manager: &mut RenderManager, camera: &CameraManager) {
+        let _span = trace_span!("billboards_upload", count = self.len());
+        if self.pipeline.is_none() && let Some(batch) = self.batches.first() {
+            let material_layout = render_manager.material_layout(&[&batch.texture], &[]);
+            let camera_layout = gpu_util::bind_group_layout(&self.device, "billboard camera layout", &uniform_layout_entries(1));
+            self.pipeline = Some(create_pipeline(&self.device, &[&material_layout, &camera_layout], self.format, self.depth_format, self.reversed_z));
+        }
+
+        let eye = camera.uniform().position;
+        let distance = |position: [f32; 3]| (0..3).map(|i| (position[i] - eye[i]).powi(2)).sum::<f32>();
+        let mut instances = Vec::with_capacity(self.len());
+        for batch in &mut self.batches {
+            batch.billboards.sort_by(|a, b| distance(b.position).total_cmp(&distance(a.position)));
+            batch.bind_group = Some(render_manager.material_bind_group(&[&batch.texture], &[]));
+            batch.first_instance = instances.len() as u32;
+            instances.extend(batch.billboards.iter().map(Billboard::instance));
+        }
+
+        let count = instances.len() as u64;
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instances = instance_buffer(&self.device, self.instance_capacity);
+        }
+        if !instances.is_empty() {
+            self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&instances));
+        }
+    }
+
+    /// Draw the billboards of the last [`upload`](Self::upload) with `camera`.
+    pub fn render(&self, pass: &mut RenderPass, camera: &CameraManager) {
+        let Some(pipeline) = &self.pipeline else {
+            return;
+        };
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(CAMERA_GROUP, camera.bind_group(), &[]);
+        pass.set_vertex_buffer(0, self.instances.slice(..));
+        for batch in &self.batches {
+            let Some(bind_group) = &batch.bind_group else {
+                continue;
+            };
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..4, batch.first_instance..batch.first_instance + batch.billboards.len() as u32);
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    layouts: &[&BindGroupLayout],
+    format: TextureFormat,
+    depth_format: Option<TextureFormat>,
+    reversed_z: bool,
+) -> RenderPipeline {
+    let module = gpu_util::shader(device, "billboard shader", &format!("{CAMERA_WGSL}{BILLBOARD_SHADER}"));
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("billboard pipeline layout")),
+        bind_group_layouts: layouts,
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("billboard pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            buffers: &[BILLBOARD_LAYOUT],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: depth_format.map(|format| DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare: if reversed_z { CompareFunction::GreaterEqual } else { CompareFunction::LessEqual },
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+fn instance_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(device, "billboard instances", capacity * BILLBOARD_LAYOUT.array_stride, BufferUsages::VERTEX | BufferUsages::COPY_DST)
+}
@@ -0,0 +1,294 @@
+//! Explicit bind groups, built binding by binding.
+//!
+//! `render_with_textures` derives the material layout from a slice of views, which
+//! covers most materials. When a shader needs a different order, a comparison
+//! sampler of its own, buffers next to textures or a texture bound with a sample
+//! type other than the auto-detected one, the [`BindGroupBuilder`] spells it out.
+//! Bindings are numbered in call order from 0, and both the layout and the bind
+//! group are cached like every other bind group of the [`RenderManager`]:
+//! ```ignore
+//! let water = render_manager
+//!     .bind_group()
+//!     .sampler(&linear_sampler)
+//!     .texture(&normal_map)
+//!     .uniform(&water_params)
+//!     .min_binding_size(size_of::<WaterParams>() as u64)
+//!     .shadow(&shadow_sampler, &shadow_view)
+//!     .build();
+//! render_manager.render_with_layouts(shader, &[&water.layout], &[&water.bind_group], &options, &mut pass);
+//! ```
+//!
+//! Per-draw data goes into the same bind group through a buffer bound at dynamic
+//! offsets, e.g. a [`UniformRing`]:
+//! ```ignore
+//! let offsets: Vec<u32> = draws.iter().map(|d| ring.push(&d.params)).collect();
+//! ring.flush();
+//! let material = render_manager.bind_group().sampler(&sampler).texture(&albedo).uniform_ring(&ring).build();
+//! for offset in offsets {
+//!     pass.set_bind_group(0, &material.bind_group, &[offset]);
+//!     // draw
+//! }
+//! ```
+//!
+//! [`RenderManager`]: crate::renderer::RenderManager
+use wgpu::*;
+use crate::compute_bind_groups::{validate_buffer_sizes, ComputeBindGroups, ComputeBinding};
+use crate::error::CrmError;
+use crate::uniform_ring::UniformRing;
+
+/// Stages that see read-only bindings unless [`visibility`](BindGroupBuilder::visibility) says otherwise.
+const READ_VISIBILITY: ShaderStages = ShaderStages::VERTEX_FRAGMENT.union(ShaderStages::COMPUTE);
+/// Stages that see writable bindings, vertex shaders cannot write.
+const WRITE_VISIBILITY: ShaderStages = ShaderStages::FRAGMENT.union(ShaderStages::COMPUTE);
+
+/// A cached layout and the bind group created with it.
+#[derive(Debug, Clone)]
+pub struct BuiltBindGroup {
+    pub layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+}
+
+/// Collects bindings for one bind group, see the [module docs](self).
+///
+/// Created by [`RenderManager::bind_group`](crate::renderer::RenderManager::bind_group).
+pub struct BindGroupBuilder<'a> {
+    cache: &'a mut ComputeBindGroups,
+    features: Features,
+    label: &'a str,
+    visibility: Option<ShaderStages>,
+    entries: Vec<BindGroupLayoutEntry>,
+    bindings: Vec<ComputeBinding<'a>>,
+    /// First binding that cannot be created, reported by [`try_build`](Self::try_build).
+    error: Option<CrmError>,
+}
+
+impl<'a> BindGroupBuilder<'a> {
+    pub(crate) fn new(cache: &'a mut ComputeBindGroups, features: Features) -> Self {
+        Self {
+            cache,
+            features,
+            label: "builder bind group",
+            visibility: None,
+            entries: Vec::new(),
+            bindings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Label of the layout and bind group.
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Stages that see the bindings added after this call. By default read-only
+    /// bindings are visible to vertex, fragment and compute shaders, writable ones to
+    /// fragment and compute shaders.
+    pub fn visibility(mut self, stages: ShaderStages) -> Self {
+        self.visibility = Some(stages);
+        self
+    }
+
+    /// A filtering sampler.
+    pub fn sampler(self, sampler: &'a Sampler) -> Self {
+        self.push(BindingType::Sampler(SamplerBindingType::Filtering), READ_VISIBILITY, ComputeBinding::Sampler(sampler))
+    }
+
+    /// A sampler for `textureSampleCompare`, created with a `compare` function.
+    pub fn comparison_sampler(self, sampler: &'a Sampler) -> Self {
+        self.push(BindingType::Sampler(SamplerBindingType::Comparison), READ_VISIBILITY, ComputeBinding::Sampler(sampler))
+    }
+
+    /// A sampler that must not filter, for unfilterable float textures.
+    pub fn non_filtering_sampler(self, sampler: &'a Sampler) -> Self {
+        self.push(BindingType::Sampler(SamplerBindingType::NonFiltering), READ_VISIBILITY, ComputeBinding::Sampler(sampler))
+    }
+
+    /// A sampled texture, with sample type, dimension and multisampling detected from
+    /// the view as for material textures.
+    pub fn texture(mut self, view: &'a TextureView) -> Self {
+        let texture = view.texture();
+        let format = texture.format();
+        let Some(sample_type) = format
+            .sample_type(Some(TextureAspect::All), Some(self.features))
+            .or_else(|| format.sample_type(Some(TextureAspect::DepthOnly), Some(self.features)))
+        else {
+            let binding = self.entries.len();
+            self.error.get_or_insert(CrmError::UnsupportedFormat {
+                format,
+                usage: format!("{} binding {}", self.label, binding),
+            });
+            return self;
+        };
+        let multisampled = texture.sample_count() > 1;
+        let sample_type = match sample_type {
+            TextureSampleType::Float { .. } if multisampled => TextureSampleType::Float { filterable: false },
+            other => other,
+        };
+        self.texture_as(view, sample_type, view_dimension(texture))
+    }
+
+    /// A sampled texture with an explicit sample type and dimension, e.g. a float
+    /// texture read with `textureLoad` as unfilterable, or a cube map.
+    pub fn texture_as(self, view: &'a TextureView, sample_type: TextureSampleType, view_dimension: TextureViewDimension) -> Self {
+        let ty = BindingType::Texture {
+            sample_type,
+            view_dimension,
+            multisampled: view.texture().sample_count() > 1,
+        };
+        self.push(ty, READ_VISIBILITY, ComputeBinding::View(view))
+    }
+
+    /// A depth texture as `texture_depth_2d`, or `texture_depth_multisampled_2d` for an
+    /// MSAA depth buffer read sample by sample with `textureLoad`. Views of combined
+    /// depth-stencil formats must be created with `TextureAspect::DepthOnly`.
+    pub fn depth_texture(mut self, view: &'a TextureView) -> Self {
+        let texture = view.texture();
+        if !texture.format().has_depth_aspect() {
+            let binding = self.entries.len();
+            self.error.get_or_insert(CrmError::UnsupportedFormat {
+                format: texture.format(),
+                usage: format!("{} depth binding {}", self.label, binding),
+            });
+            return self;
+        }
+        self.texture_as(view, TextureSampleType::Depth, view_dimension(texture))
+    }
+
+    /// A storage texture of the view's format.
+    pub fn storage_texture(self, view: &'a TextureView, access: StorageTextureAccess) -> Self {
+        let texture = view.texture();
+        let ty = BindingType::StorageTexture {
+            access,
+            format: texture.format(),
+            view_dimension: view_dimension(texture),
+        };
+        let visibility = if access == StorageTextureAccess::ReadOnly { READ_VISIBILITY } else { WRITE_VISIBILITY };
+        self.push(ty, visibility, ComputeBinding::View(view))
+    }
+
+    /// A whole uniform buffer.
+    pub fn uniform(self, buffer: &'a Buffer) -> Self {
+        self.push(buffer_type(BufferBindingType::Uniform), READ_VISIBILITY, ComputeBinding::Buffer(buffer))
+    }
+
+    /// A whole storage buffer.
+    pub fn storage(self, buffer: &'a Buffer, read_only: bool) -> Self {
+        let visibility = if read_only { READ_VISIBILITY } else { WRITE_VISIBILITY };
+        self.push(buffer_type(BufferBindingType::Storage { read_only }), visibility, ComputeBinding::Buffer(buffer))
+    }
+
+    /// The shadow bindings of a material: a comparison sampler and a depth texture
+    /// array, visible to fragment shaders.
+    pub fn shadow(self, sampler: &'a Sampler, view: &'a TextureView) -> Self {
+        let depth = BindingType::Texture {
+            sample_type: TextureSampleType::Depth,
+            view_dimension: TextureViewDimension::D2Array,
+            multisampled: false,
+        };
+        self.push(BindingType::Sampler(SamplerBindingType::Comparison), ShaderStages::FRAGMENT, ComputeBinding::Sampler(sampler))
+            .push(depth, ShaderStages::FRAGMENT, ComputeBinding::View(view))
+    }
+
+    /// The cached layout and bind group of the bindings.
+    ///
+    /// # Panics
+    /// If a texture format cannot be sampled on this device, a buffer is too small or a
+    /// `min_binding_size` was set on a non-buffer binding, see [`try_build`](Self::try_build).
+    pub fn build(self) -> BuiltBindGroup {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`build`](Self::build), failing with [`CrmError::UnsupportedFormat`] if a
+    /// [`texture`](Self::texture) cannot be sampled on this device, with
+    /// [`CrmError::BufferTooSmall`] if a buffer is below its [`min_binding_size`](Self::min_binding_size),
+    /// or with [`CrmError::InvalidBinding`] for a `min_binding_size` on a non-buffer binding.
+    pub fn try_build(self) -> Result<BuiltBindGroup, CrmError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        validate_buffer_sizes(&self.entries, &self.bindings, self.label)?;
+        let layout = self.cache.layout(&self.entries, self.label).clone();
+        let bind_group = self.cache.get_or_create(&layout, &self.bindings, self.label).clone();
+        Ok(BuiltBindGroup { layout, bind_group })
+    }
+
+    /// A uniform buffer bound `binding_size` bytes at a time, at the offset passed to
+    /// `set_bind_group` per draw. See [`DynamicOffsets`](crate::uniform_ring::DynamicOffsets)
+    /// for aligned offsets.
+    pub fn dynamic_uniform(self, buffer: &'a Buffer, binding_size: u64) -> Self {
+        self.push(dynamic_buffer_type(BufferBindingType::Uniform, binding_size), READ_VISIBILITY, ComputeBinding::DynamicBuffer(buffer, binding_size))
+    }
+
+    /// A storage buffer bound `binding_size` bytes at a time, at a dynamic offset.
+    pub fn dynamic_storage(self, buffer: &'a Buffer, binding_size: u64, read_only: bool) -> Self {
+        let visibility = if read_only { READ_VISIBILITY } else { WRITE_VISIBILITY };
+        let ty = dynamic_buffer_type(BufferBindingType::Storage { read_only }, binding_size);
+        self.push(ty, visibility, ComputeBinding::DynamicBuffer(buffer, binding_size))
+    }
+
+    /// Require at least `bytes` from the buffer added last, usually the size of the
+    /// struct the shader declares for it. [`try_build`](Self::try_build) fails with
+    /// [`CrmError::BufferTooSmall`] for smaller buffers, and the layout carries the size
+    /// so pipelines check it against the shader. If the last binding is not a buffer,
+    /// [`try_build`](Self::try_build) fails with [`CrmError::InvalidBinding`].
+    pub fn min_binding_size(mut self, bytes: u64) -> Self {
+        let Some(entry) = self.entries.last_mut() else {
+            self.error.get_or_insert(CrmError::InvalidBinding(format!("{}: min_binding_size needs a buffer binding before it", self.label)));
+            return self;
+        };
+        let BindingType::Buffer { min_binding_size, .. } = &mut entry.ty else {
+            self.error.get_or_insert(CrmError::InvalidBinding(format!(
+                "{}: min_binding_size applies to buffer bindings, binding {} is {:?}",
+                self.label, entry.binding, entry.ty
+            )));
+            return self;
+        };
+        *min_binding_size = BufferSize::new(bytes);
+        self
+    }
+
+    /// The blocks of a [`UniformRing`], bound with the offsets returned by
+    /// [`push`](UniformRing::push). Build after the ring's last growing
+    /// [`flush`](UniformRing::flush) of the frame, growing replaces its buffer.
+    pub fn uniform_ring(self, ring: &'a UniformRing) -> Self {
+        self.dynamic_uniform(ring.buffer(), ring.binding_size())
+    }
+
+    fn push(mut self, ty: BindingType, default_visibility: ShaderStages, binding: ComputeBinding<'a>) -> Self {
+        self.entries.push(BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: self.visibility.unwrap_or(default_visibility),
+            ty,
+            count: None,
+        });
+        self.bindings.push(binding);
+        self
+    }
+}
+
+fn buffer_type(ty: BufferBindingType) -> BindingType {
+    BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    }
+}
+
+fn dynamic_buffer_type(ty: BufferBindingType, binding_size: u64) -> BindingType {
+    BindingType::Buffer {
+        ty,
+        has_dynamic_offset: true,
+        min_binding_size: BufferSize::new(binding_size),
+    }
+}
+
+fn view_dimension(texture: &Texture) -> TextureViewDimension {
+    if texture.dimension() == TextureDimension::D3 {
+        TextureViewDimension::D3
+    } else if texture.depth_or_array_layers() > 1 {
+        TextureViewDimension::D2Array
+    } else {
+        TextureViewDimension::D2
+    }
+}
@@ -1,229 +1,586 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{DefaultHasher, Hash, Hasher};
-use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Device, FilterMode, MipmapFilterMode, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, TextureAspect, TextureSampleType, TextureView, TextureViewDimension};
+use crate::cache_key::ErasedKey;
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, Tracked};
+use crate::error::CrmError;
+use crate::handles::MaterialRequest;
+use crate::pipelines::TextureAccess;
+use crate::labels::namespaced;
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Device, Features, FilterMode, MipmapFilterMode, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, TextureAspect, TextureDimension, TextureSampleType, TextureUsages, TextureView, TextureViewDimension};
 
-#[derive(Clone, Hash, PartialEq, Eq)]
-struct MaterialBindGroupKey {
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) struct MaterialBindGroupKey {
     views_hash: u64,
-    has_shadow: bool
+    has_shadow: bool,
+    /// Hash of the views alone, shared by every access variant of the material.
+    views_only_hash: u64,
 }
 
 impl MaterialBindGroupKey {
-    fn from_views(views: &[&TextureView], has_shadow: bool) -> Self {
-        let mut hasher = DefaultHasher::new();
-        for v in views {
-            v.hash(&mut hasher);
-        }
-        Self { views_hash: hasher.finish(), has_shadow }
+    pub(crate) fn from_views(views: &[&TextureView], access: &[TextureAccess], has_shadow: bool) -> Self {
+        let (views_only_hash, views_hash) = view_hashes(views, access);
+        Self { views_hash, has_shadow, views_only_hash }
+    }
+
+    /// Whether the key belongs to `views`, with any access modes and shadow bindings.
+    pub(crate) fn is_of_views(&self, views_only_hash: u64) -> bool {
+        self.views_only_hash == views_only_hash
     }
 }
-#[derive(Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub(crate) struct LayoutKey {
     layout_hash: u64,
     has_shadow: bool,
 }
 
 impl LayoutKey {
-    pub(crate) fn from_views(views: &[&TextureView], has_shadow: bool) -> Self {
-        let mut hasher = DefaultHasher::new();
-        for v in views {
-            v.hash(&mut hasher);
-        }
+    pub(crate) fn from_views(views: &[&TextureView], access: &[TextureAccess], has_shadow: bool) -> Self {
         Self {
-            layout_hash: hasher.finish(),
+            layout_hash: views_hash(views, access),
             has_shadow
         }
     }
 }
 
+/// Layout and bind group keys of a material, hashing its views once.
+pub(crate) fn material_keys(views: &[&TextureView], access: &[TextureAccess], has_shadow: bool) -> (LayoutKey, MaterialBindGroupKey) {
+    let (views_only_hash, hash) = view_hashes(views, access);
+    (LayoutKey { layout_hash: hash, has_shadow }, MaterialBindGroupKey { views_hash: hash, has_shadow, views_only_hash })
+}
+
+fn views_hash(views: &[&TextureView], access: &[TextureAccess]) -> u64 {
+    view_hashes(views, access).1
+}
+
+/// Hash of the views alone, and of the views with their access modes.
+pub(crate) fn view_hashes(views: &[&TextureView], access: &[TextureAccess]) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    for v in views {
+        v.hash(&mut hasher);
+    }
+    let views_only = hasher.finish();
+    hash_access(access, &mut hasher);
+    (views_only, hasher.finish())
+}
+
+/// Hashes the non-default access modes, so all-sampled materials keep their previous keys.
+fn hash_access(access: &[TextureAccess], hasher: &mut DefaultHasher) {
+    for (i, a) in access.iter().enumerate() {
+        if *a != TextureAccess::Sampled {
+            (i, a).hash(hasher);
+        }
+    }
+}
+
+/// A material bind group layout together with the entries it was created from.
+pub(crate) struct MaterialLayout {
+    pub(crate) layout: BindGroupLayout,
+    pub(crate) entries: Vec<BindGroupLayoutEntry>,
+}
+
+/// A material bind group cached under a user key, with the layout it was created
+/// for, so hits need neither the views nor the layout cache.
+pub(crate) struct KeyedMaterial {
+    pub(crate) layout: BindGroupLayout,
+    pub(crate) bind_group: BindGroup,
+    /// Entries of `layout`, so hits are validated without hashing the views.
+    pub(crate) entries: Vec<BindGroupLayoutEntry>,
+    access: Vec<TextureAccess>,
+    has_shadow: bool,
+}
+
 /// Manages material bind groups containing textures and samplers.
 pub(crate) struct MaterialBindGroups {
     device: Device,
     sampler: Sampler,
-    pub(crate) layouts: HashMap<LayoutKey, BindGroupLayout>,
-    bind_groups: HashMap<MaterialBindGroupKey, BindGroup>,
+    pub(crate) layouts: HashMap<LayoutKey, Tracked<MaterialLayout>>,
+    bind_groups: HashMap<MaterialBindGroupKey, Tracked<BindGroup>>,
+    /// Bind groups created while mutations are deferred, merged by [`commit_staged`](Self::commit_staged).
+    staged: HashMap<MaterialBindGroupKey, Tracked<BindGroup>>,
+    /// Bind groups keyed by a [`CacheKey`](crate::cache_key::CacheKey) instead of their views.
+    keyed: HashMap<Box<dyn ErasedKey>, Tracked<KeyedMaterial>>,
+    staged_keyed: HashMap<Box<dyn ErasedKey>, Tracked<KeyedMaterial>>,
+    deferred: bool,
+    frame: u64,
 }
 
 impl MaterialBindGroups {
     pub(crate) fn new(device: Device) -> Self {
-        let sampler = device.create_sampler(&SamplerDescriptor {
-            label: Some("material sampler"),
-            address_mode_u: AddressMode::Repeat,
-            address_mode_v: AddressMode::Repeat,
-            address_mode_w: AddressMode::Repeat,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: MipmapFilterMode::Linear,
-            ..Default::default()
-        });
+        let sampler = create_material_sampler(&device);
 
         Self {
             device,
             sampler,
             layouts: HashMap::new(),
             bind_groups: HashMap::new(),
+            staged: HashMap::new(),
+            keyed: HashMap::new(),
+            staged_keyed: HashMap::new(),
+            deferred: false,
+            frame: 0,
+        }
+    }
+
+    /// Set the frame index recorded on cache hits and insertions.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// While deferred, new bind groups are staged instead of inserted into the main map.
+    pub(crate) fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        if !deferred {
+            self.commit_staged();
         }
     }
 
-    /// Returns the bind group layout for the given texture count.
+    /// Move staged bind groups into the main map.
+    pub(crate) fn commit_staged(&mut self) {
+        self.bind_groups.extend(self.staged.drain());
+        self.keyed.extend(self.staged_keyed.drain());
+    }
+
+    /// Returns the bind group layout for the given texture views.
+    ///
+    /// `access` gives the binding type per view, missing entries are sampled.
     pub(crate) fn layout(
         &mut self,
         texture_views: &[&TextureView],
+        access: &[TextureAccess],
         has_shadow: bool,
-    ) -> &BindGroupLayout {
-
-        let key = LayoutKey::from_views(texture_views, has_shadow);
-
-        if !self.layouts.contains_key(&key) {
-            let mut entries = Vec::new();
-            let mut binding = 0;
-
-            // 0: material sampler
-            entries.push(BindGroupLayoutEntry {
-                binding,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                count: None,
-            });
-            binding += 1;
-
-            let device_features = self.device.features();
-            // 1..N: textures (auto-detect)
-            for view in texture_views {
-                let tex = view.texture();
-                let format = tex.format();
-                let is_multisampled = tex.sample_count() > 1;
-
-                let sample_type = format
-                    .sample_type(Some(TextureAspect::All), Some(device_features))
-                    // Fallback for combined depth-stencil: default to depth
-                    .or_else(|| format.sample_type(Some(TextureAspect::DepthOnly), Some(device_features)))
-                    .expect("Unsupported texture format");
-
-                // Multisampled textures cannot use filtering
-                let sample_type = if is_multisampled {
-                    match sample_type {
-                        TextureSampleType::Float { .. } => TextureSampleType::Float { filterable: false },
-                        other => other,
-                    }
-                } else {
-                    // println!("{:?}, {:?}, {:?}", sample_type, is_multisampled, view.texture().format());
-                    sample_type
-                };
-
-                entries.push(BindGroupLayoutEntry {
-                    binding,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        multisampled: is_multisampled,
-                        view_dimension: if tex.depth_or_array_layers() > 1 {
-                            TextureViewDimension::D2Array
-                        } else {
-                            TextureViewDimension::D2
-                        },
-                        sample_type,
-                    },
-                    count: None,
-                });
+    ) -> Result<&BindGroupLayout, CrmError> {
+        let key = LayoutKey::from_views(texture_views, access, has_shadow);
+        self.layout_for_key(key, texture_views, access, has_shadow)
+    }
 
-                binding += 1;
-            }
-            // Shadow (optional)
-            if has_shadow {
-                entries.push(BindGroupLayoutEntry {
-                    binding,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
-                    count: None,
-                });
-                binding += 1;
-
-                entries.push(BindGroupLayoutEntry {
-                    binding,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: TextureViewDimension::D2Array,
-                        sample_type: TextureSampleType::Depth,
-                    },
-                    count: None,
-                });
-            }
+    fn layout_for_key(
+        &mut self,
+        key: LayoutKey,
+        texture_views: &[&TextureView],
+        access: &[TextureAccess],
+        has_shadow: bool,
+    ) -> Result<&BindGroupLayout, CrmError> {
+        if let Some(entry) = self.layouts.get_mut(&key) {
+            entry.touch(self.frame);
+        } else {
+            let _span = trace_span!("material_layout_miss", textures = texture_views.len(), has_shadow);
+            let entries = material_layout_entries(&self.device, texture_views, access, has_shadow)?;
 
             let layout = self.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("material bind group layout"),
+                label: Some(&namespaced("material bind group layout")),
                 entries: &entries,
             });
+            trace_event!(entries = entries.len(), "created material bind group layout");
 
-            self.layouts.insert(key.clone(), layout);
+            let label = describe_material(texture_views.len(), has_shadow);
+            self.layouts.insert(key.clone(), Tracked::new(MaterialLayout { layout, entries }, self.frame, label));
         }
 
-        self.layouts.get(&key).unwrap()
+        Ok(&self.layouts.get(&key).unwrap().value.layout)
+    }
+
+    /// Layout entries of the material layout for the given views.
+    ///
+    /// Creates the layout if necessary, see [`layout`](Self::layout).
+    pub(crate) fn layout_entries(&mut self, texture_views: &[&TextureView], access: &[TextureAccess], has_shadow: bool) -> Result<&[BindGroupLayoutEntry], CrmError> {
+        self.layout(texture_views, access, has_shadow)?;
+        let key = LayoutKey::from_views(texture_views, access, has_shadow);
+        Ok(&self.layouts.get(&key).unwrap().value.entries)
     }
 
     /// Returns a bind group for the given texture views, creating it if necessary.
     pub(crate) fn get_or_create(
         &mut self,
         texture_views: &[&TextureView],
+        access: &[TextureAccess],
         shadow: Option<(&Sampler, &TextureView)>,
-    ) -> &BindGroup {
+    ) -> Result<&BindGroup, CrmError> {
         let has_shadow = shadow.is_some();
 
-        let key = MaterialBindGroupKey::from_views(texture_views, has_shadow);
+        let key = MaterialBindGroupKey::from_views(texture_views, access, has_shadow);
 
-        if !self.bind_groups.contains_key(&key) {
-            // Ensure layout exists
-            let layout = &self.layout(texture_views, has_shadow).clone();
+        if let Some(entry) = self.bind_groups.get_mut(&key) {
+            entry.touch(self.frame);
+            return Ok(&self.bind_groups.get(&key).unwrap().value);
+        }
+        if let Some(entry) = self.staged.get_mut(&key) {
+            entry.touch(self.frame);
+            return Ok(&self.staged.get(&key).unwrap().value);
+        }
 
-            let mut entries: Vec<BindGroupEntry> = Vec::new();
-            let mut binding: u32 = 0;
+        let _span = trace_span!("material_bind_group_miss", textures = texture_views.len(), has_shadow);
+        // Ensure layout exists
+        let layout = &self.layout(texture_views, access, has_shadow)?.clone();
 
-            // binding 0: material sampler
-            entries.push(BindGroupEntry {
-                binding,
-                resource: BindingResource::Sampler(&self.sampler),
-            });
-            binding += 1;
+        let bind_group = create_material_bind_group(&self.device, layout, &self.sampler, texture_views, shadow);
+        trace_event!(textures = texture_views.len(), has_shadow, "created material bind group");
 
-            // binding 1..N: textures
-            for view in texture_views {
-                entries.push(BindGroupEntry {
-                    binding,
-                    resource: BindingResource::TextureView(view),
-                });
-                binding += 1;
-            }
+        let label = describe_material(texture_views.len(), has_shadow);
+        let map = if self.deferred { &mut self.staged } else { &mut self.bind_groups };
+        Ok(&map.entry(key).or_insert(Tracked::new(bind_group, self.frame, label)).value)
+    }
 
-            // optional shadow
-            if let Some((shadow_sampler, shadow_view)) = shadow {
-                // comparison sampler
-                entries.push(BindGroupEntry {
-                    binding,
-                    resource: BindingResource::Sampler(shadow_sampler),
-                });
-                binding += 1;
+    /// Bind groups for a whole set of materials, in request order.
+    ///
+    /// Every request is hashed once; hits are touched first, then the layouts and bind
+    /// groups of all misses are created together. Fails on the first material whose
+    /// layout cannot be created. All layouts are created before any bind group, so after
+    /// a failure the layouts of the preceding misses stay cached, but none of their bind
+    /// groups.
+    pub(crate) fn get_or_create_many(&mut self, requests: &[MaterialRequest]) -> Result<Vec<BindGroup>, CrmError> {
+        let keys: Vec<_> = requests
+            .iter()
+            .map(|r| material_keys(r.texture_views, r.access, r.shadow.is_some()))
+            .collect();
 
-                // depth texture array
-                entries.push(BindGroupEntry {
-                    binding,
-                    resource: BindingResource::TextureView(shadow_view),
-                });
+        let mut missing = Vec::new();
+        let mut seen = HashSet::new();
+        for (i, (_, key)) in keys.iter().enumerate() {
+            match self.bind_groups.get_mut(key).or_else(|| self.staged.get_mut(key)) {
+                Some(entry) => entry.touch(self.frame),
+                None if seen.insert(key) => missing.push(i),
+                None => {}
             }
+        }
 
-            let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
-                label: Some("material bind group"),
-                layout,
-                entries: &entries,
-            });
+        if !missing.is_empty() {
+            let _span = trace_span!("material_bind_group_batch_miss", requests = requests.len(), missing = missing.len());
+            let mut created = Vec::with_capacity(missing.len());
+            for &i in &missing {
+                let request = &requests[i];
+                let has_shadow = request.shadow.is_some();
+                let layout = self.layout_for_key(keys[i].0.clone(), request.texture_views, request.access, has_shadow)?.clone();
+                created.push((i, layout));
+            }
+            let map = if self.deferred { &mut self.staged } else { &mut self.bind_groups };
+            for (i, layout) in created {
+                let request = &requests[i];
+                let bind_group = create_material_bind_group(&self.device, &layout, &self.sampler, request.texture_views, request.shadow);
+                let label = describe_material(request.texture_views.len(), request.shadow.is_some());
+                map.insert(keys[i].1.clone(), Tracked::new(bind_group, self.frame, label));
+            }
+            trace_event!(created = missing.len(), "created material bind groups in batch");
+        }
+
+        Ok(keys
+            .iter()
+            .map(|(_, key)| {
+                let entry = self.bind_groups.get(key).or_else(|| self.staged.get(key));
+                entry.expect("batched material bind group was just created").value.clone()
+            })
+            .collect())
+    }
 
-            self.bind_groups.insert(key.clone(), bind_group);
+    /// Returns the layout and bind group cached under `key`, creating them from
+    /// `texture_views` if the key is new or was used with other access modes or
+    /// shadow bindings.
+    pub(crate) fn get_or_create_keyed(
+        &mut self,
+        key: &(dyn ErasedKey + 'static),
+        texture_views: &[&TextureView],
+        access: &[TextureAccess],
+        shadow: Option<(&Sampler, &TextureView)>,
+    ) -> Result<&KeyedMaterial, CrmError> {
+        let has_shadow = shadow.is_some();
+        let matches = |entry: &KeyedMaterial| entry.has_shadow == has_shadow && entry.access == access;
+
+        if let Some(entry) = self.keyed.get_mut(key).filter(|entry| matches(&entry.value)) {
+            entry.touch(self.frame);
+            return Ok(&self.keyed.get(key).unwrap().value);
+        }
+        if let Some(entry) = self.staged_keyed.get_mut(key).filter(|entry| matches(&entry.value)) {
+            entry.touch(self.frame);
+            return Ok(&self.staged_keyed.get(key).unwrap().value);
         }
 
-        self.bind_groups.get(&key).unwrap()
+        let _span = trace_span!("keyed_material_bind_group_miss", key = ?key, textures = texture_views.len(), has_shadow);
+        let entries = self.layout_entries(texture_views, access, has_shadow)?.to_vec();
+        let layout = self.layout(texture_views, access, has_shadow)?.clone();
+        let bind_group = create_material_bind_group(&self.device, &layout, &self.sampler, texture_views, shadow);
+        trace_event!(key = ?key, textures = texture_views.len(), has_shadow, "created keyed material bind group");
+
+        let material = KeyedMaterial {
+            layout,
+            bind_group,
+            entries,
+            access: access.to_vec(),
+            has_shadow,
+        };
+        let label = describe_material(texture_views.len(), has_shadow);
+        let map = if self.deferred { &mut self.staged_keyed } else { &mut self.keyed };
+        map.insert(key.boxed(), Tracked::new(material, self.frame, label));
+        Ok(&map.get(key).unwrap().value)
+    }
+
+    /// Layout entries of the material cached under `key`.
+    #[cfg(debug_assertions)]
+    pub(crate) fn keyed_entries(&self, key: &(dyn ErasedKey + 'static)) -> Option<&[BindGroupLayoutEntry]> {
+        let entry = self.keyed.get(key).or_else(|| self.staged_keyed.get(key))?;
+        Some(&entry.value.entries)
+    }
+
+    /// Remove the bind group cached under `key`. Returns `true` if it existed.
+    pub(crate) fn evict_key(&mut self, key: &(dyn ErasedKey + 'static)) -> bool {
+        let removed = self.keyed.remove(key).is_some() | self.staged_keyed.remove(key).is_some();
+        trace_evict!("keyed_material_bind_groups", removed as usize);
+        removed
     }
 
     /// Clears all cached bind groups.
     pub fn clear(&mut self) {
+        trace_evict!("material_bind_groups", self.bind_groups.len() + self.staged.len());
+        trace_evict!("keyed_material_bind_groups", self.keyed.len() + self.staged_keyed.len());
         self.bind_groups.clear();
+        self.staged.clear();
+        self.keyed.clear();
+        self.staged_keyed.clear();
+    }
+
+    /// Remove the bind groups of exactly these views, with any access modes and with
+    /// and without shadow bindings. Returns `true` if any existed.
+    pub(crate) fn evict_views(&mut self, texture_views: &[&TextureView]) -> bool {
+        let (views_only_hash, _) = view_hashes(texture_views, &[]);
+        let before = self.bind_groups.len() + self.staged.len();
+        self.bind_groups.retain(|key, _| !key.is_of_views(views_only_hash));
+        self.staged.retain(|key, _| !key.is_of_views(views_only_hash));
+        let removed = before - self.bind_groups.len() - self.staged.len();
+        trace_evict!("material_bind_groups", removed);
+        removed > 0
+    }
+
+    /// Append diagnostics for all cached layouts and bind groups.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        for (key, entry) in &self.layouts {
+            let bindings: Vec<_> = entry.value.entries.iter().map(|e| e.ty).collect();
+            let details = format!("views hash {:#018x}, shadow: {}, bindings: {:?}", key.layout_hash, key.has_shadow, bindings);
+            out.push(entry.info(CacheKind::MaterialLayout, entry_id(key), details, 0));
+        }
+        for (key, entry) in self.bind_groups.iter().chain(&self.staged) {
+            let details = format!("views hash {:#018x}, shadow: {}", key.views_hash, key.has_shadow);
+            out.push(entry.info(CacheKind::MaterialBindGroup, entry_id(key), details, 0));
+        }
+        for (key, entry) in self.keyed.iter().chain(&self.staged_keyed) {
+            let bindings: Vec<_> = entry.value.entries.iter().map(|e| e.ty).collect();
+            let details = format!("key {:?}, shadow: {}, bindings: {:?}", key, entry.value.has_shadow, bindings);
+            out.push(entry.info(CacheKind::KeyedMaterialBindGroup, entry_id(key), details, 0));
+        }
+    }
+
+    /// Remove a single entry by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&mut self, kind: CacheKind, id: u64) -> bool {
+        let removed = match kind {
+            CacheKind::MaterialLayout => evict_by_id(&mut self.layouts, id),
+            CacheKind::MaterialBindGroup => evict_by_id(&mut self.bind_groups, id) | evict_by_id(&mut self.staged, id),
+            CacheKind::KeyedMaterialBindGroup => evict_by_id(&mut self.keyed, id) | evict_by_id(&mut self.staged_keyed, id),
+            _ => false,
+        };
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
+}
+
+/// Stages that can see the bindings of materials with storage textures, which are
+/// usually written by compute shaders. Sampled-only materials stay fragment-only.
+const STORAGE_MATERIAL_VISIBILITY: ShaderStages = ShaderStages::FRAGMENT.union(ShaderStages::COMPUTE);
+
+pub(crate) fn describe_material(texture_count: usize, has_shadow: bool) -> String {
+    if has_shadow {
+        format!("{} textures + shadow", texture_count)
+    } else {
+        format!("{} textures", texture_count)
+    }
+}
+
+/// Trilinear repeat sampler bound at `@binding(0)` of every material bind group.
+pub(crate) fn create_material_sampler(device: &Device) -> Sampler {
+    device.create_sampler(&SamplerDescriptor {
+        label: Some(&namespaced("material sampler")),
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        address_mode_w: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        mipmap_filter: MipmapFilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+/// Layout entries for a material with the given texture views, auto-detecting
+/// sample type, multisampling and dimension (2D, 2D array or 3D) of each view.
+///
+/// Views with a [`TextureAccess::Storage`] entry in `access` become storage
+/// texture bindings of the view's format instead. Entries are visible to fragment
+/// shaders, and to compute shaders too if any view is a storage texture, so those
+/// layouts serve compute pipelines.
+///
+/// Fails for views whose format cannot be sampled on `device`, or used as a storage
+/// texture where `access` asks for one.
+pub(crate) fn material_layout_entries(
+    device: &Device,
+    texture_views: &[&TextureView],
+    access: &[TextureAccess],
+    has_shadow: bool,
+) -> Result<Vec<BindGroupLayoutEntry>, CrmError> {
+    let mut entries = Vec::new();
+    let mut binding = 0;
+    let visibility = if access.iter().any(|a| matches!(a, TextureAccess::Storage(_))) {
+        STORAGE_MATERIAL_VISIBILITY
+    } else {
+        ShaderStages::FRAGMENT
+    };
+
+    // 0: material sampler
+    entries.push(BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+        count: None,
+    });
+    binding += 1;
+
+    let device_features = device.features();
+    // 1..N: textures (auto-detect)
+    for (i, view) in texture_views.iter().enumerate() {
+        let tex = view.texture();
+        let format = tex.format();
+        let is_multisampled = tex.sample_count() > 1;
+        let view_dimension = if tex.dimension() == TextureDimension::D3 {
+            TextureViewDimension::D3
+        } else if tex.depth_or_array_layers() > 1 {
+            TextureViewDimension::D2Array
+        } else {
+            TextureViewDimension::D2
+        };
+
+        if let Some(TextureAccess::Storage(storage_access)) = access.get(i) {
+            let format_features = format.guaranteed_format_features(device_features);
+            if !format_features.allowed_usages.contains(TextureUsages::STORAGE_BINDING)
+                && !device_features.contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            {
+                return Err(CrmError::UnsupportedFormat {
+                    format,
+                    usage: format!("material storage texture binding {}", binding),
+                });
+            }
+            entries.push(BindGroupLayoutEntry {
+                binding,
+                visibility,
+                ty: BindingType::StorageTexture {
+                    access: *storage_access,
+                    format,
+                    view_dimension,
+                },
+                count: None,
+            });
+            binding += 1;
+            continue;
+        }
+
+        let sample_type = format
+            .sample_type(Some(TextureAspect::All), Some(device_features))
+            // Fallback for combined depth-stencil: default to depth
+            .or_else(|| format.sample_type(Some(TextureAspect::DepthOnly), Some(device_features)))
+            .ok_or_else(|| CrmError::UnsupportedFormat {
+                format,
+                usage: format!("material texture binding {}", binding),
+            })?;
+
+        // Multisampled textures cannot use filtering
+        let sample_type = if is_multisampled {
+            match sample_type {
+                TextureSampleType::Float { .. } => TextureSampleType::Float { filterable: false },
+                other => other,
+            }
+        } else {
+            // println!("{:?}, {:?}, {:?}", sample_type, is_multisampled, view.texture().format());
+            sample_type
+        };
+
+        entries.push(BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: BindingType::Texture {
+                multisampled: is_multisampled,
+                view_dimension,
+                sample_type,
+            },
+            count: None,
+        });
+
+        binding += 1;
     }
-}
\ No newline at end of file
+    // Shadow (optional)
+    if has_shadow {
+        entries.push(BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Comparison),
+            count: None,
+        });
+        binding += 1;
+
+        entries.push(BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: TextureViewDimension::D2Array,
+                sample_type: TextureSampleType::Depth,
+            },
+            count: None,
+        });
+    }
+    Ok(entries)
+}
+
+/// Create a material bind group matching [`material_layout_entries`].
+pub(crate) fn create_material_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+    texture_views: &[&TextureView],
+    shadow: Option<(&Sampler, &TextureView)>,
+) -> BindGroup {
+    let mut entries: Vec<BindGroupEntry> = Vec::new();
+    let mut binding: u32 = 0;
+
+    // binding 0: material sampler
+    entries.push(BindGroupEntry {
+        binding,
+        resource: BindingResource::Sampler(sampler),
+    });
+    binding += 1;
+
+    // binding 1..N: textures
+    for view in texture_views {
+        entries.push(BindGroupEntry {
+            binding,
+            resource: BindingResource::TextureView(view),
+        });
+        binding += 1;
+    }
+
+    // optional shadow
+    if let Some((shadow_sampler, shadow_view)) = shadow {
+        // comparison sampler
+        entries.push(BindGroupEntry {
+            binding,
+            resource: BindingResource::Sampler(shadow_sampler),
+        });
+        binding += 1;
+
+        // depth texture array
+        entries.push(BindGroupEntry {
+            binding,
+            resource: BindingResource::TextureView(shadow_view),
+        });
+    }
+
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some(&namespaced("material bind group")),
+        layout,
+        entries: &entries,
+    })
+}
@@ -0,0 +1,132 @@
+//! Material bind groups keyed by your own material identity.
+//!
+//! [`render_with_textures`](crate::renderer::RenderManager::render_with_textures) finds
+//! its material bind group by hashing the texture views on every call. Callers that
+//! already identify materials, by an asset GUID, an ECS entity or an index into their
+//! own material table, can key the cache with that instead: on a hit the views are
+//! not looked at at all.
+//! ```ignore
+//! #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+//! struct MaterialId(u64);
+//!
+//! render_manager.render_with_material_key(&MaterialId(7), &[&albedo, &normal], shader, &options, &[&camera], &mut pass);
+//! // when material 7 changes its textures or is unloaded
+//! render_manager.release_material_key(&MaterialId(7));
+//! ```
+//! Any `Hash + Eq + Clone + Debug + Send + Sync + 'static` type is a [`CacheKey`], and
+//! keys of different types never collide. The key stands for its views: when they
+//! change, release the key, otherwise the old bind group keeps being used. The
+//! texture access modes and shadow bindings are checked on every hit and recreate
+//! the bind group if they differ.
+use std::any::{Any, TypeId};
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+
+/// A user-defined key of the material bind group cache, see the [module docs](self).
+pub trait CacheKey: Hash + Eq + Clone + Debug + Send + Sync + 'static {}
+
+impl<K: Hash + Eq + Clone + Debug + Send + Sync + 'static> CacheKey for K {}
+
+/// A [`CacheKey`] of any type, stored as `Box<dyn ErasedKey>` and looked up by
+/// `&dyn ErasedKey` without allocating.
+pub(crate) trait ErasedKey: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn hash_erased(&self, state: &mut dyn Hasher);
+    fn eq_erased(&self, other: &dyn ErasedKey) -> bool;
+    fn debug_erased(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+    fn boxed(&self) -> Box<dyn ErasedKey>;
+}
+
+impl<K: CacheKey> ErasedKey for K {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn hash_erased(&self, mut state: &mut dyn Hasher) {
+        TypeId::of::<K>().hash(&mut state);
+        self.hash(&mut state);
+    }
+
+    fn eq_erased(&self, other: &dyn ErasedKey) -> bool {
+        other.as_any().downcast_ref::<K>() == Some(self)
+    }
+
+    fn debug_erased(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({:?})", std::any::type_name::<K>(), self)
+    }
+
+    fn boxed(&self) -> Box<dyn ErasedKey> {
+        Box::new(self.clone())
+    }
+}
+
+impl Hash for dyn ErasedKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_erased(state);
+    }
+}
+
+impl PartialEq for dyn ErasedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_erased(other)
+    }
+}
+
+impl Eq for dyn ErasedKey {}
+
+impl Debug for dyn ErasedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.debug_erased(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::hash::{BuildHasher, RandomState};
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct MaterialId(u64);
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct EntityId(u64);
+
+    fn erased<K: CacheKey>(key: &K) -> Box<dyn ErasedKey> {
+        ErasedKey::boxed(key)
+    }
+
+    #[test]
+    fn equal_keys_of_one_type_match() {
+        let hasher = RandomState::new();
+        let (a, b) = (erased(&MaterialId(7)), erased(&MaterialId(7)));
+        assert!(*a == *b);
+        assert_eq!(hasher.hash_one(&*a), hasher.hash_one(&*b));
+        assert!(*a != *erased(&MaterialId(8)));
+    }
+
+    #[test]
+    fn keys_of_different_types_never_collide() {
+        let hasher = RandomState::new();
+        let (material, entity) = (erased(&MaterialId(7)), erased(&EntityId(7)));
+        assert!(*material != *entity);
+        assert_ne!(hasher.hash_one(&*material), hasher.hash_one(&*entity));
+    }
+
+    #[test]
+    fn boxed_keys_are_found_by_reference() {
+        let mut map: HashMap<Box<dyn ErasedKey>, &str> = HashMap::new();
+        map.insert(erased(&MaterialId(1)), "material");
+        map.insert(erased(&EntityId(1)), "entity");
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&erased(&MaterialId(1))), Some(&"material"));
+        assert_eq!(map.get(&erased(&EntityId(1))), Some(&"entity"));
+        assert_eq!(map.get(&erased(&EntityId(2))), None);
+    }
+
+    #[test]
+    fn debug_names_the_key_type() {
+        let debug = format!("{:?}", erased(&MaterialId(7)));
+        assert!(debug.ends_with("MaterialId(MaterialId(7))"), "{}", debug);
+    }
+}
@@ -0,0 +1,327 @@
+//! Camera uniform buffer with TAA jitter.
+//!
+//! A [`CameraManager`] owns one uniform buffer holding the [`CameraUniform`] of the
+//! frame. [`update`](CameraManager::update) derives the combined and inverse matrices
+//! from a view and a projection, keeps the previous frame's view-projection for motion
+//! vectors, and (with [`set_jitter`](CameraManager::set_jitter)) offsets the projection
+//! by a sub-pixel Halton sequence for temporal anti-aliasing.
+//!
+//! The camera is bound at the fixed group [`CAMERA_GROUP`] (`@group(1)`), binding 0,
+//! with the same layout as uniforms of
+//! [`render_with_textures`](crate::renderer::RenderManager::render_with_textures), so
+//! it can be the first uniform there or bound directly with
+//! [`render_with_layouts`](crate::renderer::RenderManager::render_with_layouts):
+//! ```ignore
+//! camera.update(&CameraData { view, projection, position }, [width, height]);
+//! // Inside a render pass
+//! render_manager.render_with_textures(&[&albedo], shader_path, &options, &[camera.buffer(), &model], &mut pass);
+//! ```
+//! Paste [`CAMERA_WGSL`] into the shader for the matching declaration.
+//!
+//! A [`Camera`] with a [`Projection`] produces the [`CameraData`] of a viewport; the
+//! controllers in [`camera_controller`](crate::camera_controller) move it from input.
+use wgpu::*;
+use crate::animation::JointTransform;
+use crate::gpu_util::{self, invert, invert_affine, mul_matrices};
+use crate::pipelines::uniform_layout_entries;
+
+/// Group index of the camera bind group.
+pub const CAMERA_GROUP: u32 = 1;
+
+/// WGSL declaration of [`CameraUniform`] at `@group(1) @binding(0)`.
+pub const CAMERA_WGSL: &str = r#"
+struct Camera {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    previous_view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+    frame: u32,
+    // Sub-pixel offset of this frame's projection, in pixels.
+    jitter: vec2<f32>,
+    viewport_size: vec2<f32>,
+};
+
+@group(1) @binding(0) var<uniform> camera: Camera;
+"#;
+
+/// View, projection and position of a camera, column-major.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraData {
+    pub view: [[f32; 4]; 4],
+    pub projection: [[f32; 4]; 4],
+    pub position: [f32; 3],
+}
+
+/// Projection of a [`Camera`], right-handed with wgpu's `0..1` clip depth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians.
+        fov_y: f32,
+        near: f32,
+        /// `None` for an infinite far plane.
+        far: Option<f32>,
+    },
+    Orthographic {
+        /// Height of the view volume in world units; the width follows the aspect ratio.
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    /// Column-major projection matrix for a viewport of `aspect_ratio` (width / height).
+    ///
+    /// With `reversed_z` the near plane maps to depth 1 and the far plane to 0.
+    pub fn matrix(&self, aspect_ratio: f32, reversed_z: bool) -> [[f32; 4]; 4] {
+        match *self {
+            Projection::Perspective { fov_y, near, far } => {
+                let f = 1.0 / (fov_y * 0.5).tan();
+                // Clip z = a * z + b with clip w = -z.
+                let (a, b) = match (far, reversed_z) {
+                    (Some(far), false) => (far / (near - far), near * far / (near - far)),
+                    (Some(far), true) => (near / (far - near), near * far / (far - near)),
+                    (None, false) => (-1.0, -near),
+                    (None, true) => (0.0, near),
+                };
+                [[f / aspect_ratio, 0.0, 0.0, 0.0], [0.0, f, 0.0, 0.0], [0.0, 0.0, a, -1.0], [0.0, 0.0, b, 0.0]]
+            }
+            Projection::Orthographic { height, near, far } => {
+                let (a, b) = if reversed_z {
+                    (1.0 / (far - near), far / (far - near))
+                } else {
+                    (1.0 / (near - far), near / (near - far))
+                };
+                let y = 2.0 / height;
+                [[y / aspect_ratio, 0.0, 0.0, 0.0], [0.0, y, 0.0, 0.0], [0.0, 0.0, a, 0.0], [0.0, 0.0, b, 1.0]]
+            }
+        }
+    }
+}
+
+/// Position, orientation and projection of a viewer looking down its local `-Z`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: [f32; 3],
+    /// Unit quaternion, `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub projection: Projection,
+    pub reversed_z: bool,
+}
+
+impl Default for Camera {
+    /// At the origin looking down `-Z`, 60° perspective from 0.1 to 1000.
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            projection: Projection::Perspective {
+                fov_y: 60f32.to_radians(),
+                near: 0.1,
+                far: Some(1000.0),
+            },
+            reversed_z: false,
+        }
+    }
+}
+
+impl Camera {
+    /// Point the camera at `target`, keeping `+Y` up.
+    pub fn look_at(&mut self, target: [f32; 3]) {
+        let d = std::array::from_fn::<f32, 3, _>(|i| target[i] - self.position[i]);
+        let horizontal = (d[0] * d[0] + d[2] * d[2]).sqrt();
+        if horizontal + d[1].abs() > f32::EPSILON {
+            self.rotation = yaw_pitch_rotation((-d[0]).atan2(-d[2]), d[1].atan2(horizontal));
+        }
+    }
+
+    /// Direction the camera looks at.
+    pub fn forward(&self) -> [f32; 3] {
+        rotate(self.rotation, [0.0, 0.0, -1.0])
+    }
+
+    pub fn right(&self) -> [f32; 3] {
+        rotate(self.rotation, [1.0, 0.0, 0.0])
+    }
+
+    pub fn up(&self) -> [f32; 3] {
+        rotate(self.rotation, [0.0, 1.0, 0.0])
+    }
+
+    /// World-to-view matrix, column-major.
+    pub fn view(&self) -> [[f32; 4]; 4] {
+        let transform = JointTransform {
+            translation: self.position,
+            rotation: self.rotation,
+            scale: [1.0; 3],
+        };
+        invert_affine(&transform.matrix())
+    }
+
+    /// Camera of a viewport of `aspect_ratio` (width / height), for [`CameraManager::update`].
+    pub fn data(&self, aspect_ratio: f32) -> CameraData {
+        CameraData {
+            view: self.view(),
+            projection: self.projection.matrix(aspect_ratio, self.reversed_z),
+            position: self.position,
+        }
+    }
+}
+
+/// Rotation by `yaw` around `+Y` after `pitch` around `+X`, in radians.
+pub(crate) fn yaw_pitch_rotation(yaw: f32, pitch: f32) -> [f32; 4] {
+    let (sy, cy) = (yaw * 0.5).sin_cos();
+    let (sp, cp) = (pitch * 0.5).sin_cos();
+    [cy * sp, sy * cp, -sy * sp, cy * cp]
+}
+
+/// Rotate `v` by the unit quaternion `q`.
+pub(crate) fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let cross = |a: [f32; 3], b: [f32; 3]| [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]];
+    let u = [q[0], q[1], q[2]];
+    let t = cross(u, v).map(|c| 2.0 * c);
+    let ut = cross(u, t);
+    std::array::from_fn(|i| v[i] + q[3] * t[i] + ut[i])
+}
+
+/// Contents of the camera uniform buffer, see [`CAMERA_WGSL`].
+///
+/// `projection`, `view_proj` and `inverse_view_proj` include the jitter,
+/// `previous_view_proj` is the unjittered view-projection of the previous update.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub view: [[f32; 4]; 4],
+    pub projection: [[f32; 4]; 4],
+    pub view_proj: [[f32; 4]; 4],
+    pub inverse_view_proj: [[f32; 4]; 4],
+    pub previous_view_proj: [[f32; 4]; 4],
+    pub position: [f32; 3],
+    pub frame: u32,
+    pub jitter: [f32; 2],
+    pub viewport_size: [f32; 2],
+}
+
+/// Owns the camera uniform buffer and its bind group.
+pub struct CameraManager {
+    queue: Queue,
+    buffer: Buffer,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    uniform: CameraUniform,
+    /// Unjittered view-projection of the last update.
+    previous_view_proj: Option<[[f32; 4]; 4]>,
+    jitter_length: Option<u32>,
+    frame: u32,
+}
+
+impl CameraManager {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let buffer = gpu_util::buffer(device, "camera", size_of::<CameraUniform>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let layout = gpu_util::bind_group_layout(device, "camera layout", &uniform_layout_entries(1));
+        let bind_group = gpu_util::bind_group(device, "camera", &layout, &[buffer.as_entire_binding()]);
+        Self {
+            queue: queue.clone(),
+            buffer,
+            layout,
+            bind_group,
+            uniform: bytemuck::Zeroable::zeroed(),
+            previous_view_proj: None,
+            jitter_length: None,
+            frame: 0,
+        }
+    }
+
+    /// Jitter the projection by a Halton(2, 3) sequence repeating every
+    /// `sequence_length` frames (8 or 16 are common for TAA), `None` to disable.
+    pub fn set_jitter(&mut self, sequence_length: Option<u32>) {
+        self.jitter_length = sequence_length.map(|length| length.max(1));
+    }
+
+    /// Sub-pixel offset applied by the last [`update`](Self::update), in pixels.
+    pub fn jitter(&self) -> [f32; 2] {
+        self.uniform.jitter
+    }
+
+    /// Write the camera of the next frame for a viewport of `viewport_size` pixels.
+    ///
+    /// The uniform is written with `Queue::write_buffer`, so update once per submission.
+    pub fn update(&mut self, camera: &CameraData, viewport_size: [u32; 2]) {
+        let view_proj = mul_matrices(&camera.projection, &camera.view);
+        let jitter = match self.jitter_length {
+            Some(length) => {
+                let index = self.frame % length + 1;
+                [halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+            }
+            None => [0.0; 2],
+        };
+
+        // Offset clip space by `jitter` pixels: x' = x + offset * w, for any projection.
+        let offset = [
+            2.0 * jitter[0] / viewport_size[0].max(1) as f32,
+            -2.0 * jitter[1] / viewport_size[1].max(1) as f32,
+        ];
+        let mut projection = camera.projection;
+        for column in &mut projection {
+            column[0] += offset[0] * column[3];
+            column[1] += offset[1] * column[3];
+        }
+        let jittered_view_proj = mul_matrices(&projection, &camera.view);
+
+        self.uniform = CameraUniform {
+            view: camera.view,
+            projection,
+            view_proj: jittered_view_proj,
+            inverse_view_proj: invert(&jittered_view_proj),
+            previous_view_proj: self.previous_view_proj.unwrap_or(view_proj),
+            position: camera.position,
+            frame: self.frame,
+            jitter,
+            viewport_size: [viewport_size[0] as f32, viewport_size[1] as f32],
+        };
+        self.queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.uniform));
+        self.previous_view_proj = Some(view_proj);
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// The values written by the last [`update`](Self::update).
+    pub fn uniform(&self) -> &CameraUniform {
+        &self.uniform
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Layout of the camera bind group, identical to a one-buffer uniform layout of the
+    /// [`RenderManager`](crate::renderer::RenderManager).
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    /// Bind group with the camera buffer at binding 0, for [`CAMERA_GROUP`].
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Forget the previous view-projection, e.g. after a camera cut, so motion vectors
+    /// of the next frame are zero.
+    pub fn reset_history(&mut self) {
+        self.previous_view_proj = None;
+    }
+}
+
+/// Element `index` of the Halton sequence of `base`, in `0..1`.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
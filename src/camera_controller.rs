@@ -0,0 +1,212 @@
+//! Fly, orbit and first-person controllers for a [`Camera`].
+//!
+//! Controllers keep their own yaw/pitch state and rewrite the camera's position and
+//! rotation from a window-system independent [`ControllerInput`] every frame:
+//! ```ignore
+//! let mut camera = Camera::default();
+//! let mut controller = OrbitController::new([0.0; 3], 10.0);
+//! // Every frame, from your window events
+//! let input = ControllerInput { look: mouse_delta, zoom: scroll, ..Default::default() };
+//! controller.update(&mut camera, &input, dt);
+//! camera_manager.update(&camera.data(width as f32 / height as f32), [width, height]);
+//! ```
+use std::f32::consts::FRAC_PI_2;
+use crate::camera::{rotate, yaw_pitch_rotation, Camera};
+
+/// Pitch limit keeping the view away from the poles.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Input of one frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ControllerInput {
+    /// Movement axes in `-1..=1`: `x` right, `y` up, `z` forward.
+    pub movement: [f32; 3],
+    /// Mouse movement in pixels, `+y` down.
+    pub look: [f32; 2],
+    /// Scroll in lines, positive to zoom in.
+    pub zoom: f32,
+    /// Pan in pixels, `+y` down; used by the [`OrbitController`].
+    pub pan: [f32; 2],
+    /// Move faster, e.g. while shift is held.
+    pub boost: bool,
+}
+
+/// Turns input into camera motion.
+pub trait CameraController {
+    /// Move `camera` by one frame of `input`, `dt` seconds long.
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, dt: f32);
+}
+
+/// Speeds shared by all controllers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControllerSettings {
+    /// Units per second.
+    pub speed: f32,
+    /// Speed multiplier while [`ControllerInput::boost`] is set.
+    pub boost_factor: f32,
+    /// Radians per pixel of mouse movement.
+    pub sensitivity: f32,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            speed: 5.0,
+            boost_factor: 4.0,
+            sensitivity: 0.003,
+        }
+    }
+}
+
+impl ControllerSettings {
+    fn speed(&self, input: &ControllerInput) -> f32 {
+        if input.boost { self.speed * self.boost_factor } else { self.speed }
+    }
+}
+
+/// Mouse look with movement along the view axes, no gravity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlyController {
+    pub settings: ControllerSettings,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl FlyController {
+    pub fn new() -> Self {
+        Self::from_camera(&Camera::default())
+    }
+
+    /// Start from the orientation of `camera`.
+    pub fn from_camera(camera: &Camera) -> Self {
+        let (yaw, pitch) = yaw_pitch(camera);
+        Self { settings: ControllerSettings::default(), yaw, pitch }
+    }
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, dt: f32) {
+        look(&mut self.yaw, &mut self.pitch, &self.settings, input);
+        camera.rotation = yaw_pitch_rotation(self.yaw, self.pitch);
+        let step = self.settings.speed(input) * dt;
+        let [x, y, z] = input.movement;
+        let (right, up, forward) = (camera.right(), camera.up(), camera.forward());
+        for i in 0..3 {
+            camera.position[i] += (right[i] * x + up[i] * y + forward[i] * z) * step;
+        }
+    }
+}
+
+/// First-person controller: walking stays horizontal whatever the pitch, `y` moves
+/// straight up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FpsController {
+    pub settings: ControllerSettings,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl FpsController {
+    pub fn new() -> Self {
+        Self::from_camera(&Camera::default())
+    }
+
+    pub fn from_camera(camera: &Camera) -> Self {
+        let (yaw, pitch) = yaw_pitch(camera);
+        Self { settings: ControllerSettings::default(), yaw, pitch }
+    }
+}
+
+impl Default for FpsController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraController for FpsController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, dt: f32) {
+        look(&mut self.yaw, &mut self.pitch, &self.settings, input);
+        camera.rotation = yaw_pitch_rotation(self.yaw, self.pitch);
+        let step = self.settings.speed(input) * dt;
+        let [x, y, z] = input.movement;
+        let (sin, cos) = self.yaw.sin_cos();
+        camera.position[0] += (cos * x - sin * z) * step;
+        camera.position[1] += y * step;
+        camera.position[2] += (-sin * x - cos * z) * step;
+    }
+}
+
+/// Rotates around a target point; zoom changes the distance, pan and movement shift
+/// the target in the view plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitController {
+    pub settings: ControllerSettings,
+    pub target: [f32; 3],
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Fraction of the distance changed per line of zoom.
+    pub zoom_speed: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl OrbitController {
+    /// Orbit `target` at `distance`, looking slightly down.
+    pub fn new(target: [f32; 3], distance: f32) -> Self {
+        Self {
+            settings: ControllerSettings::default(),
+            target,
+            distance,
+            min_distance: 0.01,
+            max_distance: f32::MAX,
+            zoom_speed: 0.1,
+            yaw: 0.0,
+            pitch: -0.4,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, dt: f32) {
+        look(&mut self.yaw, &mut self.pitch, &self.settings, input);
+        let rotation = yaw_pitch_rotation(self.yaw, self.pitch);
+        self.distance = (self.distance * (1.0 - input.zoom * self.zoom_speed).max(0.01))
+            .max(self.min_distance)
+            .min(self.max_distance);
+
+        // Panning keeps the target under the cursor: one pixel moves by the same
+        // fraction of the distance as one radian of look.
+        let pan = [
+            -input.pan[0] * self.settings.sensitivity * self.distance,
+            input.pan[1] * self.settings.sensitivity * self.distance,
+        ];
+        let step = self.settings.speed(input) * dt;
+        let [x, y, z] = input.movement;
+        let (right, up, forward) = (rotate(rotation, [1.0, 0.0, 0.0]), rotate(rotation, [0.0, 1.0, 0.0]), rotate(rotation, [0.0, 0.0, -1.0]));
+        for i in 0..3 {
+            self.target[i] += right[i] * (pan[0] + x * step) + up[i] * (pan[1] + y * step) + forward[i] * z * step;
+        }
+
+        camera.rotation = rotation;
+        camera.position = std::array::from_fn(|i| self.target[i] - forward[i] * self.distance);
+    }
+}
+
+/// Apply mouse look, clamping the pitch.
+fn look(yaw: &mut f32, pitch: &mut f32, settings: &ControllerSettings, input: &ControllerInput) {
+    *yaw -= input.look[0] * settings.sensitivity;
+    *pitch = (*pitch - input.look[1] * settings.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+}
+
+/// Yaw and pitch of the camera's forward direction.
+fn yaw_pitch(camera: &Camera) -> (f32, f32) {
+    let [x, y, z] = camera.forward();
+    ((-x).atan2(-z), y.clamp(-1.0, 1.0).asin().clamp(-MAX_PITCH, MAX_PITCH))
+}
@@ -0,0 +1,273 @@
+//! Frame capture to image sequences or a video encoder.
+//!
+//! A [`FrameCapture`] copies presented frames into a [`TextureReadback`] ring right
+//! before they are presented and hands the pixels to a [`CaptureSink`] a few frames
+//! later. The GPU is never waited on: when the ring is full, frames are dropped
+//! (counted in [`dropped`](FrameCapture::dropped)) instead of stalling. PNG sequences
+//! (feature `png`) are encoded and written on a background thread; a
+//! [`CaptureSink::Callback`] receives the raw frames, e.g. to pipe them into ffmpeg.
+//!
+//! The surface needs [`SurfaceOptions::capture`](crate::surface::SurfaceOptions::capture)
+//! so the swapchain textures can be copied from.
+//!
+//! ## Frame flow
+//! ```ignore
+//! let mut capture = FrameCapture::new(&device, CaptureSink::png_sequence("captures"), 3);
+//! // Every frame
+//! capture.poll();
+//! let frame = surface.acquire()?;
+//! // Render into `frame`
+//! capture.capture(&mut encoder, &frame.texture);
+//! queue.submit([encoder.finish()]);
+//! frame.present();
+//! // When done
+//! capture.finish()?;
+//! ```
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(feature = "png")]
+use std::path::PathBuf;
+#[cfg(feature = "png")]
+use std::sync::mpsc;
+#[cfg(feature = "png")]
+use std::thread::JoinHandle;
+use wgpu::*;
+use crate::readback::{ReadbackImage, TextureReadback};
+
+/// A captured frame that could not be written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureError {
+    /// Path of the file, or the frame number.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to write captured frame {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// A presented frame read back by a [`FrameCapture`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedFrame {
+    /// Number of the frame among all frames passed to [`FrameCapture::capture`],
+    /// including skipped and dropped ones.
+    pub frame: u64,
+    pub image: ReadbackImage,
+}
+
+/// Where a [`FrameCapture`] sends its frames.
+pub enum CaptureSink {
+    /// `frame_000000.png`, `frame_000001.png`, ... in a directory, numbered by
+    /// [`CapturedFrame::frame`].
+    #[cfg(feature = "png")]
+    PngSequence(PathBuf),
+    /// Raw frames, in order, from [`FrameCapture::poll`].
+    Callback(Box<dyn FnMut(CapturedFrame)>),
+}
+
+impl CaptureSink {
+    #[cfg(feature = "png")]
+    pub fn png_sequence(directory: impl Into<PathBuf>) -> Self {
+        CaptureSink::PngSequence(directory.into())
+    }
+
+    pub fn callback(callback: impl FnMut(CapturedFrame) + 'static) -> Self {
+        CaptureSink::Callback(Box::new(callback))
+    }
+}
+
+/// Background thread encoding and writing PNGs.
+#[cfg(feature = "png")]
+struct PngWriter {
+    sender: Option<mpsc::Sender<(PathBuf, CapturedFrame)>>,
+    /// Returns the first error; frames after it are discarded.
+    thread: Option<JoinHandle<Result<(), CaptureError>>>,
+}
+
+#[cfg(feature = "png")]
+impl PngWriter {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::<(PathBuf, CapturedFrame)>();
+        let thread = std::thread::Builder::new()
+            .name("frame capture writer".to_string())
+            .spawn(move || {
+                for (path, frame) in receiver {
+                    write_png(&path, &frame)?;
+                }
+                Ok(())
+            })
+            .expect("Failed to spawn frame capture writer thread");
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), CaptureError> {
+        self.sender = None;
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(error("writer", "writer thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+fn write_png(path: &std::path::Path, frame: &CapturedFrame) -> Result<(), CaptureError> {
+    let label = path.display().to_string();
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory).map_err(|e| error(&label, e))?;
+    }
+    let image = &frame.image;
+    let rgba = image
+        .to_rgba8()
+        .ok_or_else(|| error(&label, format!("unsupported format {:?}", image.format)))?;
+    image::save_buffer(path, &rgba, image.width, image.height, image::ExtendedColorType::Rgba8).map_err(|e| error(&label, e))
+}
+
+enum SinkState {
+    #[cfg(feature = "png")]
+    Png { directory: PathBuf, writer: PngWriter },
+    Callback(Box<dyn FnMut(CapturedFrame)>),
+}
+
+/// Throttled capture of presented frames.
+pub struct FrameCapture {
+    readback: TextureReadback,
+    sink: SinkState,
+    /// Capture every `interval`th frame.
+    interval: u64,
+    frame: u64,
+    /// Frame number of every pending readback id.
+    pending: VecDeque<(u64, u64)>,
+    captured: u64,
+    dropped: u64,
+}
+
+impl FrameCapture {
+    /// Capture into `sink`, reading back at most `max_in_flight` frames at a time.
+    /// Three covers the usual latency between submission and mapping.
+    pub fn new(device: &Device, sink: CaptureSink, max_in_flight: usize) -> Self {
+        let sink = match sink {
+            #[cfg(feature = "png")]
+            CaptureSink::PngSequence(directory) => SinkState::Png {
+                directory,
+                writer: PngWriter::new(),
+            },
+            CaptureSink::Callback(callback) => SinkState::Callback(callback),
+        };
+        Self {
+            readback: TextureReadback::new(device, max_in_flight),
+            sink,
+            interval: 1,
+            frame: 0,
+            pending: VecDeque::new(),
+            captured: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Capture only every `interval`th frame, e.g. 2 for 30 fps captures of a 60 fps game.
+    pub fn with_interval(mut self, interval: u32) -> Self {
+        self.interval = interval.max(1) as u64;
+        self
+    }
+
+    /// Copy `texture` for capture when `encoder` is submitted. Call once per frame,
+    /// after rendering and before presenting.
+    ///
+    /// Returns `false` if the frame is not captured, because of the interval or because
+    /// the readback ring is full.
+    pub fn capture(&mut self, encoder: &mut CommandEncoder, texture: &Texture) -> bool {
+        let frame = self.frame;
+        self.frame += 1;
+        if frame % self.interval != 0 {
+            return false;
+        }
+        match self.readback.copy(encoder, texture) {
+            Some(id) => {
+                self.pending.push_back((id, frame));
+                true
+            }
+            None => {
+                trace_event!(frame, "dropped captured frame");
+                self.dropped += 1;
+                false
+            }
+        }
+    }
+
+    /// Hand finished frames to the sink. Call once per frame; never blocks on the GPU.
+    pub fn poll(&mut self) {
+        let images = self.readback.poll();
+        self.deliver(images);
+    }
+
+    /// Wait for every pending frame, deliver it and, for PNG sequences, wait until
+    /// all files are written.
+    ///
+    /// Returns the first write error. The capture cannot be used afterwards.
+    pub fn finish(&mut self) -> Result<(), CaptureError> {
+        let images = self.readback.wait();
+        self.deliver(images);
+        match &mut self.sink {
+            #[cfg(feature = "png")]
+            SinkState::Png { writer, .. } => writer.finish(),
+            SinkState::Callback(_) => Ok(()),
+        }
+    }
+
+    /// Frames delivered to the sink so far.
+    pub fn captured(&self) -> u64 {
+        self.captured
+    }
+
+    /// Frames skipped because the readback ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Frames copied but not yet delivered.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn deliver(&mut self, images: Vec<ReadbackImage>) {
+        for image in images {
+            // Failed readbacks are skipped by the ring, so their ids never show up.
+            while self.pending.front().is_some_and(|&(id, _)| id < image.id) {
+                self.pending.pop_front();
+                self.dropped += 1;
+            }
+            let Some((_, frame)) = self.pending.pop_front() else {
+                continue;
+            };
+            self.captured += 1;
+            let frame = CapturedFrame { frame, image };
+            match &mut self.sink {
+                #[cfg(feature = "png")]
+                SinkState::Png { directory, writer } => {
+                    let path = directory.join(format!("frame_{:06}.png", frame.frame));
+                    if let Some(sender) = &writer.sender {
+                        // A closed channel means the writer already failed; `finish` reports it.
+                        let _ = sender.send((path, frame));
+                    }
+                }
+                SinkState::Callback(callback) => callback(frame),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+fn error(label: &str, message: impl fmt::Display) -> CaptureError {
+    CaptureError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
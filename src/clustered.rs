@@ -0,0 +1,394 @@
+//! Clustered forward lighting: point lights binned into view-space froxels on the GPU.
+//!
+//! The view frustum is split into `tiles_x * tiles_y` screen tiles and
+//! `depth_slices` exponentially spaced depth slices. [`ClusteredLighting::cull`]
+//! records a compute pass that tests every light against every cluster and writes
+//! a per-cluster light count and light index list into storage buffers. The forward
+//! pass then only shades the lights of the fragment's cluster.
+//!
+//! ## Frame flow
+//! ```ignore
+//! lighting.set_lights(&lights);
+//! lighting.cull(&mut encoder, &ClusterView { view, projection, z_near: 0.1, z_far: 500.0, viewport: [width, height] });
+//! // Inside the forward pass
+//! lighting.render(&mut render_manager, &mut pass, &[&albedo_view], shader_path, &options, &[&camera_buffer]);
+//! ```
+//!
+//! ## Shader side
+//! [`render`](ClusteredLighting::render) binds materials and uniforms like
+//! [`render_with_textures`](RenderManager::render_with_textures) and the light data
+//! as `@group(2)`. Paste [`CLUSTERED_LIGHTING_WGSL`] into the shader (or an
+//! `#include`d file) to get the bindings and lookup helpers:
+//! ```wgsl
+//! let cluster = cluster_index(in.clip_position, cluster_view_depth(in.world_position));
+//! for (var i = 0u; i < cluster_light_count(cluster); i++) {
+//!     let light = cluster_light(cluster, i);
+//!     // ...
+//! }
+//! ```
+//!
+//! ## Projection
+//! Cluster bounds are derived from the perspective terms of `projection`
+//! (column-major, right-handed view space looking down `-Z`), so off-center
+//! perspective projections work, orthographic ones don't. Reading storage buffers
+//! in fragment shaders is not available on WebGL2.
+use std::path::Path;
+use wgpu::*;
+use crate::gpu_util;
+use crate::pipelines::PipelineOptions;
+use crate::renderer::{ExtraBindGroup, RenderManager};
+
+/// The `PointLight` and `ClusterParams` declarations shared by all cluster shaders.
+macro_rules! cluster_structs {
+    () => {
+        r#"
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+};
+
+struct ClusterParams {
+    view: mat4x4<f32>,
+    // projection[0][0], projection[1][1], projection[2][0], projection[2][1]
+    projection: vec4<f32>,
+    screen_size: vec2<f32>,
+    z_near: f32,
+    z_far: f32,
+    grid: vec3<u32>,
+    light_count: u32,
+    max_lights_per_cluster: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+};
+"#
+    };
+}
+
+const CLUSTER_CULL_SHADER: &str = concat!(cluster_structs!(), r#"
+@group(0) @binding(0) var<uniform> params: ClusterParams;
+@group(0) @binding(1) var<storage, read> lights: array<PointLight>;
+@group(0) @binding(2) var<storage, read_write> light_grid: array<u32>;
+@group(0) @binding(3) var<storage, read_write> light_indices: array<u32>;
+
+fn slice_depth(slice: u32) -> f32 {
+    return params.z_near * pow(params.z_far / params.z_near, f32(slice) / f32(params.grid.z));
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id >= params.grid) {
+        return;
+    }
+    let cluster = id.x + params.grid.x * (id.y + params.grid.y * id.z);
+
+    // Tile bounds in NDC. Tile rows count down from the top of the screen.
+    let tiles = vec2<f32>(params.grid.xy);
+    let ndc_min = vec2<f32>(f32(id.x) / tiles.x * 2.0 - 1.0, 1.0 - f32(id.y + 1u) / tiles.y * 2.0);
+    let ndc_max = vec2<f32>(f32(id.x + 1u) / tiles.x * 2.0 - 1.0, 1.0 - f32(id.y) / tiles.y * 2.0);
+
+    // View-space xy per unit of depth along the tile corners.
+    let ray_min = (ndc_min + params.projection.zw) / params.projection.xy;
+    let ray_max = (ndc_max + params.projection.zw) / params.projection.xy;
+
+    let near = slice_depth(id.z);
+    let far = slice_depth(id.z + 1u);
+    let aabb_min = vec3<f32>(min(ray_min * near, ray_min * far), -far);
+    let aabb_max = vec3<f32>(max(ray_max * near, ray_max * far), -near);
+
+    let base = cluster * params.max_lights_per_cluster;
+    var count = 0u;
+    for (var i = 0u; i < params.light_count; i++) {
+        let light = lights[i];
+        let center = (params.view * vec4<f32>(light.position, 1.0)).xyz;
+        let delta = clamp(center, aabb_min, aabb_max) - center;
+        if dot(delta, delta) <= light.radius * light.radius {
+            light_indices[base + count] = i;
+            count += 1u;
+            if count == params.max_lights_per_cluster {
+                break;
+            }
+        }
+    }
+    light_grid[cluster] = count;
+}
+"#);
+
+/// WGSL declarations for shaders drawn with [`ClusteredLighting::render`].
+///
+/// Contains the `PointLight` and `ClusterParams` structs, the `@group(2)` bindings
+/// and the `cluster_view_depth`, `cluster_index`, `cluster_light_count` and
+/// `cluster_light` helpers.
+pub const CLUSTERED_LIGHTING_WGSL: &str = concat!(cluster_structs!(), r#"
+@group(2) @binding(0) var<uniform> cluster_params: ClusterParams;
+@group(2) @binding(1) var<storage, read> cluster_lights: array<PointLight>;
+@group(2) @binding(2) var<storage, read> cluster_light_grid: array<u32>;
+@group(2) @binding(3) var<storage, read> cluster_light_indices: array<u32>;
+
+// Positive distance in front of the camera of a world-space position.
+fn cluster_view_depth(world_position: vec3<f32>) -> f32 {
+    return -(cluster_params.view * vec4<f32>(world_position, 1.0)).z;
+}
+
+// Cluster of a fragment from its `@builtin(position)` and view depth.
+fn cluster_index(frag_coord: vec4<f32>, view_depth: f32) -> u32 {
+    let grid = cluster_params.grid;
+    let tile = min(vec2<u32>(frag_coord.xy / cluster_params.screen_size * vec2<f32>(grid.xy)), grid.xy - 1u);
+    let depth = clamp(view_depth, cluster_params.z_near, cluster_params.z_far);
+    let slice_f = log(depth / cluster_params.z_near) / log(cluster_params.z_far / cluster_params.z_near) * f32(grid.z);
+    let slice = min(u32(slice_f), grid.z - 1u);
+    return tile.x + grid.x * (tile.y + grid.y * slice);
+}
+
+fn cluster_light_count(cluster: u32) -> u32 {
+    return cluster_light_grid[cluster];
+}
+
+fn cluster_light(cluster: u32, i: u32) -> PointLight {
+    return cluster_lights[cluster_light_indices[cluster * cluster_params.max_lights_per_cluster + i]];
+}
+"#);
+
+/// A point light as stored in the light buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    /// World-space position.
+    pub position: [f32; 3],
+    /// Distance at which the light no longer contributes, used for culling.
+    pub radius: f32,
+    /// Linear RGB color.
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Resolution of the cluster grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClusterConfig {
+    pub tiles_x: u32,
+    pub tiles_y: u32,
+    /// Number of exponentially spaced slices between the near and far plane.
+    pub depth_slices: u32,
+    /// Lights beyond this count are dropped from a cluster.
+    pub max_lights_per_cluster: u32,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            tiles_x: 16,
+            tiles_y: 9,
+            depth_slices: 24,
+            max_lights_per_cluster: 128,
+        }
+    }
+}
+
+impl ClusterConfig {
+    /// Total number of clusters.
+    pub fn cluster_count(&self) -> u32 {
+        self.tiles_x * self.tiles_y * self.depth_slices
+    }
+}
+
+/// Camera the clusters are built for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterView {
+    /// World to view matrix, column-major.
+    pub view: [[f32; 4]; 4],
+    /// Perspective projection matrix, column-major.
+    pub projection: [[f32; 4]; 4],
+    /// Distance of the first depth slice. Lights closer than this use the first slice.
+    pub z_near: f32,
+    /// Distance of the last depth slice. Lights further away use the last slice.
+    pub z_far: f32,
+    /// Size of the render target in pixels.
+    pub viewport: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterParams {
+    view: [[f32; 4]; 4],
+    projection: [f32; 4],
+    screen_size: [f32; 2],
+    z_near: f32,
+    z_far: f32,
+    grid: [u32; 3],
+    light_count: u32,
+    max_lights_per_cluster: u32,
+    _pad: [u32; 3],
+}
+
+/// Light culling into a froxel grid and the bind group exposing the result.
+pub struct ClusteredLighting {
+    device: Device,
+    queue: Queue,
+    config: ClusterConfig,
+    params: Buffer,
+    lights: Buffer,
+    light_capacity: u32,
+    light_count: u32,
+    light_grid: Buffer,
+    light_indices: Buffer,
+    cull_layout: BindGroupLayout,
+    cull_pipeline: ComputePipeline,
+    cull_bind_group: BindGroup,
+    forward_entries: Vec<BindGroupLayoutEntry>,
+    forward_layout: BindGroupLayout,
+    forward_bind_group: BindGroup,
+}
+
+impl ClusteredLighting {
+    pub fn new(device: &Device, queue: &Queue, config: ClusterConfig) -> Self {
+        let module = gpu_util::shader(device, "cluster cull shader", CLUSTER_CULL_SHADER);
+        let cull_layout = gpu_util::bind_group_layout(device, "cluster cull layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(2, ShaderStages::COMPUTE, false),
+            gpu_util::storage_entry(3, ShaderStages::COMPUTE, false),
+        ]);
+        let cull_pipeline = gpu_util::compute_pipeline(device, "cluster cull", &module, "main", &[&cull_layout]);
+
+        let forward_entries = vec![
+            gpu_util::uniform_entry(0, ShaderStages::FRAGMENT),
+            gpu_util::storage_entry(1, ShaderStages::FRAGMENT, true),
+            gpu_util::storage_entry(2, ShaderStages::FRAGMENT, true),
+            gpu_util::storage_entry(3, ShaderStages::FRAGMENT, true),
+        ];
+        let forward_layout = gpu_util::bind_group_layout(device, "clustered lights layout", &forward_entries);
+
+        let params = gpu_util::buffer(device, "cluster params", size_of::<ClusterParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let light_capacity = 64;
+        let lights = light_buffer(device, light_capacity);
+        let light_grid = gpu_util::buffer(device, "cluster light grid", config.cluster_count() as u64 * 4, BufferUsages::STORAGE);
+        let light_indices = gpu_util::buffer(
+            device,
+            "cluster light indices",
+            config.cluster_count() as u64 * config.max_lights_per_cluster as u64 * 4,
+            BufferUsages::STORAGE,
+        );
+        let buffers = [&params, &lights, &light_grid, &light_indices];
+        let cull_bind_group = create_bind_group(device, "cluster cull", &cull_layout, buffers);
+        let forward_bind_group = create_bind_group(device, "clustered lights", &forward_layout, buffers);
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            config,
+            params,
+            lights,
+            light_capacity,
+            light_count: 0,
+            light_grid,
+            light_indices,
+            cull_layout,
+            cull_pipeline,
+            cull_bind_group,
+            forward_entries,
+            forward_layout,
+            forward_bind_group,
+        }
+    }
+
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    /// Number of lights uploaded by the last [`set_lights`](Self::set_lights).
+    pub fn light_count(&self) -> u32 {
+        self.light_count
+    }
+
+    /// Replace all lights. Takes effect at the next [`cull`](Self::cull).
+    ///
+    /// Growing past the current capacity replaces the light buffer and the bind group.
+    pub fn set_lights(&mut self, lights: &[PointLight]) {
+        let count = lights.len() as u32;
+        if count > self.light_capacity {
+            self.light_capacity = count.next_power_of_two();
+            self.lights = light_buffer(&self.device, self.light_capacity);
+            let buffers = [&self.params, &self.lights, &self.light_grid, &self.light_indices];
+            self.cull_bind_group = create_bind_group(&self.device, "cluster cull", &self.cull_layout, buffers);
+            self.forward_bind_group = create_bind_group(&self.device, "clustered lights", &self.forward_layout, buffers);
+        }
+        if !lights.is_empty() {
+            self.queue.write_buffer(&self.lights, 0, bytemuck::cast_slice(lights));
+        }
+        self.light_count = count;
+    }
+
+    /// Record the light culling pass for `view`.
+    pub fn cull(&mut self, encoder: &mut CommandEncoder, view: &ClusterView) {
+        let _span = trace_span!("cluster_cull", lights = self.light_count);
+        let p = &view.projection;
+        let params = ClusterParams {
+            view: view.view,
+            projection: [p[0][0], p[1][1], p[2][0], p[2][1]],
+            screen_size: [view.viewport[0].max(1) as f32, view.viewport[1].max(1) as f32],
+            z_near: view.z_near,
+            z_far: view.z_far.max(view.z_near * 1.001),
+            grid: [self.config.tiles_x, self.config.tiles_y, self.config.depth_slices],
+            light_count: self.light_count,
+            max_lights_per_cluster: self.config.max_lights_per_cluster,
+            _pad: [0; 3],
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+        gpu_util::dispatch(encoder, "cluster cull", &self.cull_pipeline, &[&self.cull_bind_group], [
+            self.config.tiles_x.div_ceil(4),
+            self.config.tiles_y.div_ceil(4),
+            self.config.depth_slices.div_ceil(4),
+        ]);
+    }
+
+    /// Layout of the light bind group, for pipelines built with
+    /// [`render_with_layouts`](RenderManager::render_with_layouts).
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.forward_layout
+    }
+
+    /// Bind group with the cluster params, lights, light grid and light indices at
+    /// bindings 0 to 3, see [`CLUSTERED_LIGHTING_WGSL`].
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.forward_bind_group
+    }
+
+    /// Set pipeline and bind groups like
+    /// [`render_with_textures`](RenderManager::render_with_textures), with the
+    /// clustered lights bound as `@group(2)`.
+    ///
+    /// Like `render_with_textures`, this does not issue a draw call.
+    pub fn render(
+        &self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+    ) {
+        let lights = ExtraBindGroup {
+            name: "clustered lights",
+            entries: &self.forward_entries,
+            layout: &self.forward_layout,
+            bind_group: &self.forward_bind_group,
+        };
+        manager.render_with_extra_groups(texture_views, shader_path, options, uniforms, &[lights], pass);
+    }
+}
+
+fn light_buffer(device: &Device, capacity: u32) -> Buffer {
+    gpu_util::buffer(
+        device,
+        "cluster lights",
+        capacity as u64 * size_of::<PointLight>() as u64,
+        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+    )
+}
+
+fn create_bind_group(device: &Device, label: &str, layout: &BindGroupLayout, buffers: [&Buffer; 4]) -> BindGroup {
+    let resources: Vec<BindingResource> = buffers.iter().map(|b| b.as_entire_binding()).collect();
+    gpu_util::bind_group(device, label, layout, &resources)
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, Tracked};
+use crate::error::CrmError;
+use crate::labels::namespaced;
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBinding, BufferSize, Device, Sampler, TextureView};
+
+/// Checks every buffer binding against the `min_binding_size` of its layout entry,
+/// so a buffer built for a smaller struct than the shader declares fails here with
+/// its binding instead of as a validation error at draw time.
+pub(crate) fn validate_buffer_sizes(entries: &[BindGroupLayoutEntry], bindings: &[ComputeBinding], label: &str) -> Result<(), CrmError> {
+    for (entry, binding) in entries.iter().zip(bindings) {
+        let BindingType::Buffer { min_binding_size: Some(min_binding_size), .. } = entry.ty else {
+            continue;
+        };
+        let (buffer, size) = match binding {
+            ComputeBinding::Buffer(buffer) => (buffer, buffer.size()),
+            ComputeBinding::DynamicBuffer(buffer, size) => (buffer, *size),
+            _ => continue,
+        };
+        let usage = || format!("{} binding {}", label, entry.binding);
+        if size < min_binding_size.get() {
+            return Err(CrmError::BufferTooSmall { usage: usage(), size, min_binding_size: min_binding_size.get() });
+        }
+        if buffer.size() < size {
+            return Err(CrmError::BufferTooSmall { usage: usage(), size: buffer.size(), min_binding_size: size });
+        }
+    }
+    Ok(())
+}
+
+/// A resource bound in a compute bind group.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ComputeBinding<'a> {
+    /// Sampled or storage texture.
+    View(&'a TextureView),
+    /// Whole uniform or storage buffer.
+    Buffer(&'a Buffer),
+    /// Uniform or storage buffer bound `size` bytes at a time, at a dynamic offset.
+    DynamicBuffer(&'a Buffer, u64),
+    Sampler(&'a Sampler),
+}
+
+impl ComputeBinding<'_> {
+    fn resource(&self) -> BindingResource<'_> {
+        match self {
+            ComputeBinding::View(view) => BindingResource::TextureView(view),
+            ComputeBinding::Buffer(buffer) => buffer.as_entire_binding(),
+            ComputeBinding::DynamicBuffer(buffer, size) => BindingResource::Buffer(BufferBinding {
+                buffer,
+                offset: 0,
+                size: BufferSize::new(*size),
+            }),
+            ComputeBinding::Sampler(sampler) => BindingResource::Sampler(sampler),
+        }
+    }
+
+    fn hash_into(&self, hasher: &mut DefaultHasher) {
+        match self {
+            ComputeBinding::View(view) => (0u8, view).hash(hasher),
+            ComputeBinding::Buffer(buffer) => (1u8, buffer).hash(hasher),
+            ComputeBinding::Sampler(sampler) => (2u8, sampler).hash(hasher),
+            ComputeBinding::DynamicBuffer(buffer, size) => (3u8, buffer, size).hash(hasher),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) struct ComputeLayoutKey(Vec<BindGroupLayoutEntry>);
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) struct ComputeBindGroupKey {
+    layout_hash: u64,
+    resources_hash: u64,
+}
+
+impl ComputeBindGroupKey {
+    fn new(layout: &BindGroupLayout, bindings: &[ComputeBinding]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        layout.hash(&mut hasher);
+        let layout_hash = hasher.finish();
+
+        let mut hasher = DefaultHasher::new();
+        for binding in bindings {
+            binding.hash_into(&mut hasher);
+        }
+        Self { layout_hash, resources_hash: hasher.finish() }
+    }
+}
+
+/// Manages compute bind group layouts and bind groups.
+///
+/// The compute counterpart of `MaterialBindGroups`: layouts are deduplicated by
+/// their entries, bind groups by layout and bound resources, so repeated dispatches
+/// with the same textures and buffers reuse the same bind groups. Also backs the
+/// [`BindGroupBuilder`](crate::bind_group_builder::BindGroupBuilder), under its own
+/// cache kinds.
+pub(crate) struct ComputeBindGroups {
+    device: Device,
+    layout_kind: CacheKind,
+    bind_group_kind: CacheKind,
+    layouts: HashMap<ComputeLayoutKey, Tracked<BindGroupLayout>>,
+    bind_groups: HashMap<ComputeBindGroupKey, Tracked<BindGroup>>,
+    /// Bind groups created while mutations are deferred, merged by [`commit_staged`](Self::commit_staged).
+    staged: HashMap<ComputeBindGroupKey, Tracked<BindGroup>>,
+    deferred: bool,
+    frame: u64,
+}
+
+impl ComputeBindGroups {
+    pub(crate) fn new(device: Device) -> Self {
+        Self::with_kinds(device, CacheKind::ComputeLayout, CacheKind::ComputeBindGroup)
+    }
+
+    /// A cache reporting its entries in diagnostics as `layout_kind` and `bind_group_kind`.
+    pub(crate) fn with_kinds(device: Device, layout_kind: CacheKind, bind_group_kind: CacheKind) -> Self {
+        Self {
+            device,
+            layout_kind,
+            bind_group_kind,
+            layouts: HashMap::new(),
+            bind_groups: HashMap::new(),
+            staged: HashMap::new(),
+            deferred: false,
+            frame: 0,
+        }
+    }
+
+    /// Set the frame index recorded on cache hits and insertions.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// While deferred, new bind groups are staged instead of inserted into the main map.
+    pub(crate) fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        if !deferred {
+            self.commit_staged();
+        }
+    }
+
+    /// Move staged bind groups into the main map.
+    pub(crate) fn commit_staged(&mut self) {
+        self.bind_groups.extend(self.staged.drain());
+    }
+
+    /// Returns the layout for `entries`, creating it if necessary.
+    pub(crate) fn layout(&mut self, entries: &[BindGroupLayoutEntry], label: &str) -> &BindGroupLayout {
+        let key = ComputeLayoutKey(entries.to_vec());
+
+        if let Some(entry) = self.layouts.get_mut(&key) {
+            entry.touch(self.frame);
+        } else {
+            let layout = self.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some(&namespaced(label)),
+                entries,
+            });
+            trace_event!(entries = entries.len(), label, "created compute bind group layout");
+            let description = format!("{} ({} bindings)", label, entries.len());
+            self.layouts.insert(key.clone(), Tracked::new(layout, self.frame, description));
+        }
+
+        &self.layouts.get(&key).unwrap().value
+    }
+
+    /// Returns a bind group with `bindings` at consecutive bindings starting at 0,
+    /// creating it if necessary.
+    pub(crate) fn get_or_create(&mut self, layout: &BindGroupLayout, bindings: &[ComputeBinding], label: &str) -> &BindGroup {
+        let key = ComputeBindGroupKey::new(layout, bindings);
+
+        if let Some(entry) = self.bind_groups.get_mut(&key) {
+            entry.touch(self.frame);
+            return &self.bind_groups.get(&key).unwrap().value;
+        }
+        if let Some(entry) = self.staged.get_mut(&key) {
+            entry.touch(self.frame);
+            return &self.staged.get(&key).unwrap().value;
+        }
+
+        let entries: Vec<BindGroupEntry> = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, binding)| BindGroupEntry {
+                binding: i as u32,
+                resource: binding.resource(),
+            })
+            .collect();
+        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
+            label: Some(&namespaced(label)),
+            layout,
+            entries: &entries,
+        });
+        trace_event!(bindings = bindings.len(), label, "created compute bind group");
+
+        let description = format!("{} ({} bindings)", label, bindings.len());
+        let map = if self.deferred { &mut self.staged } else { &mut self.bind_groups };
+        &map.entry(key).or_insert(Tracked::new(bind_group, self.frame, description)).value
+    }
+
+    /// Clears all cached bind groups. Layouts are kept.
+    pub(crate) fn clear(&mut self) {
+        trace_evict!("compute_bind_groups", self.bind_groups.len() + self.staged.len());
+        self.bind_groups.clear();
+        self.staged.clear();
+    }
+
+    /// Append diagnostics for all cached layouts and bind groups.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        for (key, entry) in &self.layouts {
+            let bindings: Vec<_> = key.0.iter().map(|e| e.ty).collect();
+            out.push(entry.info(self.layout_kind, entry_id(key), format!("bindings: {:?}", bindings), 0));
+        }
+        for (key, entry) in self.bind_groups.iter().chain(&self.staged) {
+            let details = format!("layout {:#018x}, resources hash {:#018x}", key.layout_hash, key.resources_hash);
+            out.push(entry.info(self.bind_group_kind, entry_id(key), details, 0));
+        }
+    }
+
+    /// Remove a single entry by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&mut self, kind: CacheKind, id: u64) -> bool {
+        let removed = if kind == self.layout_kind {
+            evict_by_id(&mut self.layouts, id)
+        } else if kind == self.bind_group_kind {
+            evict_by_id(&mut self.bind_groups, id) | evict_by_id(&mut self.staged, id)
+        } else {
+            false
+        };
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
+}
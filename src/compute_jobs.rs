@@ -0,0 +1,150 @@
+//! Ordering compute work before and after the graphics work of a frame.
+//!
+//! Compute jobs (mip generation, particle updates, culling, ...) are registered with
+//! a [`ComputeStage`] and optional dependencies on other jobs. On
+//! [`submit`](ComputeJobs::submit) each stage is recorded into its own command
+//! buffer and the frame is sent in one submission:
+//! `[before graphics] -> [graphics buffers] -> [after graphics]`.
+//!
+//! Everything runs on the device's single queue in submission order, wgpu's automatic
+//! barriers resolve the hazards between the stages and the graphics work. Jobs do not
+//! overlap with graphics, this only saves recording the stages by hand.
+use wgpu::{CommandBuffer, CommandEncoder, CommandEncoderDescriptor, Device, Queue, SubmissionIndex};
+use crate::submission::{SubmissionManager, SubmissionTicket};
+use crate::labels::namespaced;
+
+/// When a compute job runs relative to the frame's graphics work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ComputeStage {
+    /// Before any graphics buffer, e.g. particle simulation or skinning feeding the draws.
+    BeforeGraphics,
+    /// After the graphics buffers, e.g. Hi-Z building from this frame's depth.
+    AfterGraphics,
+}
+
+/// Identifies a job queued in [`ComputeJobs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComputeJobId(usize);
+
+type RecordFn = Box<dyn FnOnce(&mut CommandEncoder)>;
+
+struct ComputeJob {
+    label: String,
+    stage: ComputeStage,
+    record: RecordFn,
+}
+
+/// Collects compute jobs of one frame and submits them around the graphics work.
+pub struct ComputeJobs {
+    device: Device,
+    queue: Queue,
+    jobs: Vec<ComputeJob>,
+}
+
+impl ComputeJobs {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            jobs: Vec::new(),
+        }
+    }
+
+    /// Queue an independent job.
+    pub fn add(&mut self, label: &str, stage: ComputeStage, record: impl FnOnce(&mut CommandEncoder) + 'static) -> ComputeJobId {
+        self.add_after(label, stage, &[], record)
+    }
+
+    /// Queue a job that must run after `dependencies`.
+    ///
+    /// Jobs run in the order they were added within a stage, so dependencies on
+    /// earlier jobs of the same or an earlier stage are always satisfied.
+    ///
+    /// ## Panics
+    /// Panics if a dependency is unknown or runs in a later stage than the job.
+    pub fn add_after(
+        &mut self,
+        label: &str,
+        stage: ComputeStage,
+        dependencies: &[ComputeJobId],
+        record: impl FnOnce(&mut CommandEncoder) + 'static,
+    ) -> ComputeJobId {
+        for dependency in dependencies {
+            let job = self
+                .jobs
+                .get(dependency.0)
+                .unwrap_or_else(|| panic!("Compute job '{}' depends on unknown job {:?}", label, dependency));
+            assert!(
+                job.stage <= stage,
+                "Compute job '{}' ({:?}) can't depend on '{}' ({:?}), which runs later",
+                label,
+                stage,
+                job.label,
+                job.stage
+            );
+        }
+        self.jobs.push(ComputeJob {
+            label: label.to_string(),
+            stage,
+            record: Box::new(record),
+        });
+        ComputeJobId(self.jobs.len() - 1)
+    }
+
+    /// Number of jobs waiting for the next submission.
+    pub fn pending_jobs(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Record all queued jobs and submit them around `graphics` in one `Queue::submit`.
+    pub fn submit(&mut self, graphics: impl IntoIterator<Item = CommandBuffer>) -> SubmissionIndex {
+        let buffers = self.record_frame(graphics);
+        self.queue.submit(buffers)
+    }
+
+    /// Like [`submit`](Self::submit), but queues the buffers in a [`SubmissionManager`].
+    ///
+    /// Returns the ticket of the last buffer, which completes after the whole frame.
+    pub fn submit_to(
+        &mut self,
+        submissions: &mut SubmissionManager,
+        graphics: impl IntoIterator<Item = CommandBuffer>,
+    ) -> Option<SubmissionTicket> {
+        let mut ticket = None;
+        for buffer in self.record_frame(graphics) {
+            ticket = Some(submissions.push(buffer));
+        }
+        ticket
+    }
+
+    fn record_frame(&mut self, graphics: impl IntoIterator<Item = CommandBuffer>) -> Vec<CommandBuffer> {
+        let _span = trace_span!("compute_jobs", jobs = self.jobs.len());
+        let (before, after): (Vec<_>, Vec<_>) = self
+            .jobs
+            .drain(..)
+            .partition(|job| job.stage == ComputeStage::BeforeGraphics);
+
+        let mut buffers = Vec::new();
+        if let Some(buffer) = self.record_stage("compute before graphics", before) {
+            buffers.push(buffer);
+        }
+        buffers.extend(graphics);
+        if let Some(buffer) = self.record_stage("compute after graphics", after) {
+            buffers.push(buffer);
+        }
+        buffers
+    }
+
+    fn record_stage(&self, label: &str, jobs: Vec<ComputeJob>) -> Option<CommandBuffer> {
+        if jobs.is_empty() {
+            return None;
+        }
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor { label: Some(&namespaced(label)) });
+        for job in jobs {
+            encoder.push_debug_group(&job.label);
+            (job.record)(&mut encoder);
+            encoder.pop_debug_group();
+        }
+        Some(encoder.finish())
+    }
+}
@@ -1,10 +1,16 @@
 // compute_system.rs
 #![allow(dead_code)]
 use std::collections::{HashMap};
-use std::path::{PathBuf};
+use std::path::Path;
 use wgpu::*;
+use crate::compute_bind_groups::{ComputeBindGroups, ComputeBinding};
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, Tracked};
+use crate::error::CrmError;
+use crate::pipeline_stats::StatisticsQuery;
 use crate::pipelines::hash_defines;
-use crate::shader_preprocessing::compile_wgsl;
+use crate::profiler::PassTimestamps;
+use crate::shader_preprocessing::try_compile_wgsl;
+use crate::labels::namespaced;
 
 /// Options for compute dispatch
 pub struct ComputePipelineOptions {
@@ -19,7 +25,7 @@ impl Default for ComputePipelineOptions {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 struct PipelineKey {
     shader_path: String,
     input_specs: Vec<(TextureFormat, u32, bool)>, // (format, sample_count, is_filterable)
@@ -76,7 +82,10 @@ impl BufferSet {
 /// ## Design notes
 /// - Pipelines are cached based on shader path, texture formats,
 ///   MSAA sample counts, and uniform count
-/// - Bind group layouts are generated dynamically per pipeline
+/// - Bind group layouts are generated dynamically per pipeline and shared
+///   between pipelines with identical bindings
+/// - Bind groups are cached by layout and bound resources, so repeated
+///   dispatches with the same textures and buffers reuse them
 /// - This type owns its own command encoder per dispatch
 ///
 /// This is intended for procedural texture generation, post-processing,
@@ -84,9 +93,13 @@ impl BufferSet {
 pub struct ComputeSystem {
     device: Device,
     queue: Queue,
-    pipeline_cache: HashMap<PipelineKey, CachedPipeline>,
+    pipeline_cache: HashMap<PipelineKey, Tracked<CachedPipeline>>,
+    bind_groups: ComputeBindGroups,
     filtering_sampler: Sampler,
     non_filtering_sampler: Sampler,
+    frame: u64,
+    /// Command buffers of self-submitted dispatches, collected instead of submitted while batching.
+    batched: Option<Vec<CommandBuffer>>,
 }
 
 impl ComputeSystem {
@@ -99,7 +112,7 @@ impl ComputeSystem {
         let queue = queue.clone();
 
         let filtering_sampler = device.create_sampler(&SamplerDescriptor {
-            label: Some("compute_filtering_sampler"),
+            label: Some(&namespaced("compute_filtering_sampler")),
             mag_filter: FilterMode::Linear,
             min_filter: FilterMode::Linear,
             address_mode_u: AddressMode::ClampToEdge,
@@ -108,7 +121,7 @@ impl ComputeSystem {
         });
 
         let non_filtering_sampler = device.create_sampler(&SamplerDescriptor {
-            label: Some("compute_non_filtering_sampler"),
+            label: Some(&namespaced("compute_non_filtering_sampler")),
             mag_filter: FilterMode::Nearest,
             min_filter: FilterMode::Nearest,
             address_mode_u: AddressMode::ClampToEdge,
@@ -120,22 +133,36 @@ impl ComputeSystem {
             device,
             queue,
             pipeline_cache: HashMap::new(),
+            bind_groups: ComputeBindGroups::new(device.clone()),
+            frame: 0,
+            batched: None,
             filtering_sampler,
             non_filtering_sampler,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn compute(
         &mut self,
         encoder: Option<&mut CommandEncoder>,
         label: &str,
         input_views: Vec<&TextureView>,
         output_views: Vec<&TextureView>,
-        shader_path: &PathBuf,
+        shader_path: &Path,
         options: ComputePipelineOptions,
         buffer_sets: &[BufferSet],
         defines: &HashMap<String, bool>,
-    ) {
+        timestamps: Option<PassTimestamps>,
+        statistics: Option<StatisticsQuery>,
+    ) -> Result<(), CrmError> {
+        let max_workgroups = self.device.limits().max_compute_workgroups_per_dimension;
+        if let Some(&requested) = options.dispatch_size.iter().find(|size| **size > max_workgroups) {
+            return Err(CrmError::LimitExceeded {
+                limit: "max_compute_workgroups_per_dimension",
+                requested: requested as u64,
+                max: max_workgroups as u64,
+            });
+        }
         let encoder_is_none = encoder.is_none();
         #[cfg(debug_assertions)]
         {
@@ -168,10 +195,10 @@ impl ComputeSystem {
             .collect();
 
         let output_formats: Vec<_> = output_views.iter().map(|v| v.texture().format()).collect();
-        let buffer_bindings: Vec<_> = buffer_sets
+        let buffer_bindings = buffer_sets
             .iter()
-            .map(|b| buffer_binding_type(b))
-            .collect();
+            .map(buffer_binding_type)
+            .collect::<Result<Vec<_>, _>>()?;
 
         let key = PipelineKey {
             shader_path: shader_path.to_str().unwrap_or("").to_string(),
@@ -182,28 +209,34 @@ impl ComputeSystem {
         };
 
         // Get or create cached pipeline
-        if !self.pipeline_cache.contains_key(&key) {
+        if let Some(entry) = self.pipeline_cache.get_mut(&key) {
+            entry.touch(self.frame);
+        } else {
+            let _span = trace_span!("compute_pipeline_miss", shader = %shader_path.display());
             let cached = self.create_pipeline(
                 shader_path,
                 &input_specs,
                 &output_formats,
                 &buffer_bindings,
                 defines
-            );
+            )?;
 
-            self.pipeline_cache.insert(key.clone(), cached);
+            let label = shader_path.display().to_string();
+            self.pipeline_cache.insert(key.clone(), Tracked::new(cached, self.frame, label));
         }
-        let cached = self.pipeline_cache.get(&key).unwrap();
+        let cached = &self.pipeline_cache.get(&key).unwrap().value;
+        let pipeline = cached.pipeline.clone();
+        let layouts = cached.bind_group_layouts.clone();
 
         // Determine if we can use filtering sampler
         let use_filtering = input_specs.iter().all(|(format, sample_count, is_filterable)| {
             format.has_depth_aspect() || *sample_count > 1 || *is_filterable
         });
 
-        // Create bind groups
-        let input_bg = self.create_input_bind_group(&cached.bind_group_layouts[0], &input_views, use_filtering);
-        let output_bg = self.create_output_bind_group(&cached.bind_group_layouts[1], &output_views);
-        let uniform_bg = self.create_buffer_bind_group(&cached.bind_group_layouts[2], buffer_sets, Some("Compute Buffers Bind Group"));
+        // Get or create bind groups
+        let input_bg = self.input_bind_group(&layouts[0], &input_views, use_filtering);
+        let output_bg = self.output_bind_group(&layouts[1], &output_views);
+        let uniform_bg = self.buffer_bind_group(&layouts[2], buffer_sets);
 
         // Resolve encoder (external or create new)
         let mut owned_encoder = None;
@@ -212,7 +245,7 @@ impl ComputeSystem {
             None => {
                 owned_encoder = Some(
                     self.device
-                        .create_command_encoder(&CommandEncoderDescriptor { label: Some(label) }),
+                        .create_command_encoder(&CommandEncoderDescriptor { label: Some(&namespaced(label)) }),
                 );
                 owned_encoder.as_mut().unwrap()
             }
@@ -221,11 +254,14 @@ impl ComputeSystem {
         // Record the compute pass
         {
             let mut pass = enc.begin_compute_pass(&ComputePassDescriptor {
-                label: Some(label),
-                timestamp_writes: None,
+                label: Some(&namespaced(label)),
+                timestamp_writes: timestamps.as_ref().map(|t| t.compute_writes()),
             });
 
-            pass.set_pipeline(&cached.pipeline);
+            if let Some(query) = &statistics {
+                query.begin_compute(&mut pass);
+            }
+            pass.set_pipeline(&pipeline);
             pass.set_bind_group(0, &input_bg, &[]);
             pass.set_bind_group(1, &output_bg, &[]);
             pass.set_bind_group(2, &uniform_bg, &[]);
@@ -234,13 +270,22 @@ impl ComputeSystem {
                 options.dispatch_size[1],
                 options.dispatch_size[2],
             );
+            if statistics.is_some() {
+                pass.end_pipeline_statistics_query();
+            }
         }
 
         // If we created our own encoder, finish and submit it
         if encoder_is_none {
             let finished = owned_encoder.unwrap().finish();
-            self.queue.submit(std::iter::once(finished));
+            match &mut self.batched {
+                Some(batched) => batched.push(finished),
+                None => {
+                    self.queue.submit(std::iter::once(finished));
+                }
+            }
         }
+        Ok(())
     }
 
     // let mut compute = ComputeSystem::new(&device, &queue); // Caches device and queue so you don't have to pass it in again
@@ -264,14 +309,14 @@ impl ComputeSystem {
 
 
     fn create_pipeline(
-        &self,
-        shader_path: &PathBuf,
+        &mut self,
+        shader_path: &Path,
         input_specs: &[(TextureFormat, u32, bool)], // (format, sample_count, is_filterable)
         output_formats: &[TextureFormat],
         buffer_bindings: &[BufferBindingType],
         defines: &HashMap<String, bool>,
-    ) -> CachedPipeline {
-        let shader = compile_wgsl(&self.device, shader_path, defines);
+    ) -> Result<CachedPipeline, CrmError> {
+        let shader = try_compile_wgsl(&self.device, shader_path, defines)?;
 
         // Identify textures that can actually use a sampler (non-integer, non-multisampled)
         let samplable_textures: Vec<_> = input_specs
@@ -325,12 +370,7 @@ impl ComputeSystem {
             });
         }
 
-        let input_layout = self
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("compute_input_layout"),
-                entries: &input_entries,
-            });
+        let input_layout = self.bind_groups.layout(&input_entries, "compute_input_layout").clone();
 
         // Group 1: output storage textures
         let output_entries: Vec<BindGroupLayoutEntry> = output_formats
@@ -359,12 +399,7 @@ impl ComputeSystem {
             })
             .collect();
 
-        let output_layout = self
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("compute_output_layout"),
-                entries: &output_entries,
-            });
+        let output_layout = self.bind_groups.layout(&output_entries, "compute_output_layout").clone();
 
         // Group 2: buffers
         let buffer_entries: Vec<BindGroupLayoutEntry> = buffer_bindings
@@ -382,19 +417,14 @@ impl ComputeSystem {
             })
             .collect();
 
-        let buffer_layout = self
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("compute_buffer_layout"),
-                entries: &buffer_entries,
-            });
+        let buffer_layout = self.bind_groups.layout(&buffer_entries, "compute_buffer_layout").clone();
 
         let bind_group_layouts = [input_layout, output_layout, buffer_layout];
 
         let pipeline_layout = self
             .device
             .create_pipeline_layout(&PipelineLayoutDescriptor {
-                label: Some("compute_pipeline_layout"),
+                label: Some(&namespaced("compute_pipeline_layout")),
                 bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
                 immediate_size: 0,
             });
@@ -402,7 +432,7 @@ impl ComputeSystem {
         let pipeline = self
             .device
             .create_compute_pipeline(&ComputePipelineDescriptor {
-                label: Some(shader_path.to_str().unwrap_or("")),
+                label: Some(&namespaced(shader_path.to_str().unwrap_or(""))),
                 layout: Some(&pipeline_layout),
                 module: &shader,
                 entry_point: Some("main"),
@@ -410,26 +440,19 @@ impl ComputeSystem {
                 cache: None,
             });
 
-        CachedPipeline {
+        Ok(CachedPipeline {
             pipeline,
             bind_group_layouts,
-        }
+        })
     }
 
-    fn create_input_bind_group(
-        &self,
+    fn input_bind_group(
+        &mut self,
         layout: &BindGroupLayout,
         views: &[&TextureView],
         use_filtering: bool,
     ) -> BindGroup {
-        let mut entries: Vec<BindGroupEntry> = views
-            .iter()
-            .enumerate()
-            .map(|(i, view)| BindGroupEntry {
-                binding: i as u32,
-                resource: BindingResource::TextureView(view),
-            })
-            .collect();
+        let mut bindings: Vec<ComputeBinding> = views.iter().map(|view| ComputeBinding::View(view)).collect();
 
         if !views.is_empty() {
             let sampler = if use_filtering {
@@ -437,61 +460,28 @@ impl ComputeSystem {
             } else {
                 &self.non_filtering_sampler
             };
-
-            entries.push(BindGroupEntry {
-                binding: views.len() as u32,
-                resource: BindingResource::Sampler(sampler),
-            });
+            bindings.push(ComputeBinding::Sampler(sampler));
         }
 
-        self.device.create_bind_group(&BindGroupDescriptor {
-            label: Some("compute_input_bg"),
-            layout,
-            entries: &entries,
-        })
+        self.bind_groups.get_or_create(layout, &bindings, "compute_input_bg").clone()
     }
 
-    fn create_output_bind_group(
-        &self,
+    fn output_bind_group(
+        &mut self,
         layout: &BindGroupLayout,
         views: &[&TextureView],
     ) -> BindGroup {
-        let entries: Vec<BindGroupEntry> = views
-            .iter()
-            .enumerate()
-            .map(|(i, view)| BindGroupEntry {
-                binding: i as u32,
-                resource: BindingResource::TextureView(view),
-            })
-            .collect();
-
-        self.device.create_bind_group(&BindGroupDescriptor {
-            label: Some("compute_output_bg"),
-            layout,
-            entries: &entries,
-        })
+        let bindings: Vec<ComputeBinding> = views.iter().map(|view| ComputeBinding::View(view)).collect();
+        self.bind_groups.get_or_create(layout, &bindings, "compute_output_bg").clone()
     }
 
-    fn create_buffer_bind_group(
-        &self,
+    fn buffer_bind_group(
+        &mut self,
         layout: &BindGroupLayout,
         buffer_sets: &[BufferSet],
-        label: Option<&str>,
     ) -> BindGroup {
-        let entries: Vec<BindGroupEntry> = buffer_sets
-            .iter()
-            .enumerate()
-            .map(|(i, buffer_set)| BindGroupEntry {
-                binding: i as u32,
-                resource: buffer_set.buffer.as_entire_binding(),
-            })
-            .collect();
-
-        self.device.create_bind_group(&BindGroupDescriptor {
-            label,
-            layout,
-            entries: &entries,
-        })
+        let bindings: Vec<ComputeBinding> = buffer_sets.iter().map(|set| ComputeBinding::Buffer(&set.buffer)).collect();
+        self.bind_groups.get_or_create(layout, &bindings, "Compute Buffers Bind Group").clone()
     }
 
     /// Clear the internal compute pipeline cache.
@@ -499,8 +489,73 @@ impl ComputeSystem {
     /// Call this if compute shaders on disk have changed and pipelines
     /// need to be recreated.
     pub fn invalidate_cache(&mut self) {
+        trace_evict!("compute_pipelines", self.pipeline_cache.len());
         self.pipeline_cache.clear();
     }
+
+    /// Clear the cached compute bind groups.
+    ///
+    /// Bind groups hold on to their textures and buffers; call this after
+    /// replacing resources that were used in dispatches.
+    pub fn invalidate_bind_groups(&mut self) {
+        self.bind_groups.clear();
+    }
+
+    pub(crate) fn set_deferred(&mut self, deferred: bool) {
+        self.bind_groups.set_deferred(deferred);
+    }
+
+    pub(crate) fn commit_staged(&mut self) {
+        self.bind_groups.commit_staged();
+    }
+
+    /// Collect the command buffers of self-submitted dispatches instead of submitting them.
+    ///
+    /// Collected buffers are handed out by [`take_command_buffers`](Self::take_command_buffers).
+    pub fn set_batch_submissions(&mut self, enabled: bool) {
+        match (enabled, self.batched.is_some()) {
+            (true, false) => self.batched = Some(Vec::new()),
+            (false, true) => {
+                let pending = self.take_command_buffers();
+                if !pending.is_empty() {
+                    self.queue.submit(pending);
+                }
+                self.batched = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Command buffers collected while batching, in recording order.
+    pub fn take_command_buffers(&mut self) -> Vec<CommandBuffer> {
+        self.batched.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Set the frame index recorded on cache hits and insertions.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+        self.bind_groups.set_frame(frame);
+    }
+
+    /// Append diagnostics for all cached compute pipelines, layouts and bind groups.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        for (key, entry) in &self.pipeline_cache {
+            out.push(entry.info(CacheKind::ComputePipeline, entry_id(key), format!("{:?}", key), 0));
+        }
+        self.bind_groups.collect_entries(out);
+    }
+
+    /// Remove a single compute cache entry by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&mut self, kind: CacheKind, id: u64) -> bool {
+        if kind != CacheKind::ComputePipeline {
+            return self.bind_groups.evict(kind, id);
+        }
+        let removed = evict_by_id(&mut self.pipeline_cache, id);
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
     fn is_format_filterable(&self, format: TextureFormat, sample_count: u32) -> bool {
         if sample_count > 1 {
             // MSAA textures use textureLoad, filterability doesn't apply
@@ -523,11 +578,9 @@ impl ComputeSystem {
     }
 }
 pub(crate) fn figure_out_aspect(format: TextureFormat) -> Option<TextureAspect> {
-    if format.has_depth_aspect() && format.has_stencil_aspect() {
-        Some(TextureAspect::DepthOnly) // Only use depth somehow I guess, needs investigating wtf...
-        // panic!("Fullscreen Debug Render received a Depth texture with both Depth aspect and Stencil aspect, which wgpu doesn't allow to be used in shaders together. \n \n \
-        // Solution: Make another view of the same texture with TextureAspect::DepthOnly and pass that in instead. \n")
-    } else if format.has_depth_aspect() {
+    // Combined depth-stencil formats can only be sampled through one aspect at a time,
+    // so they fall through to DepthOnly as well.
+    if format.has_depth_aspect() {
         Some(TextureAspect::DepthOnly)
     } else if format.has_stencil_aspect() {
         Some(TextureAspect::StencilOnly)
@@ -535,19 +588,19 @@ pub(crate) fn figure_out_aspect(format: TextureFormat) -> Option<TextureAspect>
         None
     }
 }
-fn buffer_binding_type(buffer_set: &BufferSet) -> BufferBindingType {
+fn buffer_binding_type(buffer_set: &BufferSet) -> Result<BufferBindingType, CrmError> {
     let usage = buffer_set.buffer.usage();
 
     if usage.contains(BufferUsages::UNIFORM) {
-        BufferBindingType::Uniform
+        Ok(BufferBindingType::Uniform)
     } else if usage.contains(BufferUsages::STORAGE) {
-        BufferBindingType::Storage { read_only: buffer_set.read_only }
+        Ok(BufferBindingType::Storage { read_only: buffer_set.read_only })
     } else {
-        panic!(
-            "Buffer {:?} has unsupported usage {:?} for compute binding",
+        Err(CrmError::Device(format!(
+            "buffer {:?} has unsupported usage {:?} for compute binding",
             buffer_set,
             usage
-        );
+        )))
     }
 }
 /// Determines the correct TextureSampleType for any format
@@ -0,0 +1,419 @@
+// concurrent.rs
+//! Thread-safe caches for recording from several threads at once.
+//!
+//! The regular caches behind [`RenderManager`](crate::renderer::RenderManager) take
+//! `&mut self`, so they can only be used from the thread that owns the manager.
+//! The types in this module are `Send + Sync` and take `&self`: share them through
+//! an `Arc` and request bind groups from parallel encoding threads.
+//!
+//! ```ignore
+//! let materials = render_manager.shared_materials(); // Arc<SharedMaterialBindGroups>
+//! std::thread::scope(|s| {
+//!     for chunk in draws.chunks(256) {
+//!         let materials = &materials;
+//!         s.spawn(move || {
+//!             for draw in chunk {
+//!                 let bind_group = materials.get_or_create(&draw.views, None);
+//!                 // record into this thread's encoder / render bundle
+//!             }
+//!         });
+//!     }
+//! });
+//! ```
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use wgpu::{BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, Device, Sampler, TextureView};
+use crate::bind_groups::{create_material_bind_group, create_material_sampler, describe_material, material_layout_entries, view_hashes, LayoutKey, MaterialBindGroupKey, MaterialLayout};
+use crate::diagnostics::{entry_id, CacheEntryInfo, CacheKind, SharedTracked};
+use crate::error::CrmError;
+use crate::handles::MaterialRequest;
+use crate::labels::namespaced;
+use crate::pipelines::TextureAccess;
+
+/// Number of shards per map. Must be a power of two.
+const SHARD_COUNT: usize = 16;
+
+/// Hash map split into independently locked shards.
+///
+/// Threads touching different shards never contend. A poisoned shard is still
+/// used: cached GPU handles stay valid even if a thread panicked mid-lookup.
+pub(crate) struct ShardedMap<K, V> {
+    hasher: RandomState,
+    shards: Box<[RwLock<HashMap<K, V>>]>,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<HashMap<K, V>> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Index of the shard `key` falls into, for grouping batched lookups.
+    pub(crate) fn shard_index(&self, key: &K) -> usize {
+        self.hasher.hash_one(key) as usize & (SHARD_COUNT - 1)
+    }
+
+    pub(crate) fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, HashMap<K, V>> {
+        self.shards[index].read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn write_shard(&self, index: usize) -> RwLockWriteGuard<'_, HashMap<K, V>> {
+        self.shards[index].write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn read(&self, key: &K) -> RwLockReadGuard<'_, HashMap<K, V>> {
+        self.shard(key).read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn write(&self, key: &K) -> RwLockWriteGuard<'_, HashMap<K, V>> {
+        self.shard(key).write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run `f` on every shard in turn, holding its write lock.
+    pub(crate) fn for_each_shard_mut(&self, mut f: impl FnMut(&mut HashMap<K, V>)) {
+        for shard in self.shards.iter() {
+            f(&mut shard.write().unwrap_or_else(PoisonError::into_inner));
+        }
+    }
+
+    /// Run `f` on every shard in turn, holding its read lock.
+    pub(crate) fn for_each_shard(&self, mut f: impl FnMut(&HashMap<K, V>)) {
+        for shard in self.shards.iter() {
+            f(&shard.read().unwrap_or_else(PoisonError::into_inner));
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let mut len = 0;
+        self.for_each_shard(|shard| len += shard.len());
+        len
+    }
+}
+
+/// Thread-safe counterpart of the material bind group cache.
+///
+/// Produces the same layouts and bind groups as
+/// [`RenderManager::render_with_textures`](crate::renderer::RenderManager::render_with_textures)
+/// (see the crate-level binding layout), but every method takes `&self` so that
+/// several threads can request bind groups simultaneously.
+///
+/// ## Locking
+/// Layouts and bind groups live in maps sharded over [`SHARD_COUNT`] `RwLock`s.
+/// A cache hit only takes the **read** lock of the shard its key falls into and
+/// records usage with relaxed atomics, so concurrent hits never block each other.
+/// The write lock is reserved for insertions and evictions. On a miss, the GPU
+/// object is created **outside** the lock; if two threads race on the same key,
+/// the first insertion wins and both receive the same bind group.
+///
+/// Returned handles are clones, which is cheap: `wgpu` handles are reference counted.
+pub struct SharedMaterialBindGroups {
+    device: Device,
+    sampler: Sampler,
+    layouts: ShardedMap<LayoutKey, SharedTracked<MaterialLayout>>,
+    bind_groups: ShardedMap<MaterialBindGroupKey, SharedTracked<BindGroup>>,
+    frame: AtomicU64,
+}
+
+impl SharedMaterialBindGroups {
+    /// Create an empty cache.
+    pub fn new(device: Device) -> Self {
+        let sampler = create_material_sampler(&device);
+        Self {
+            device,
+            sampler,
+            layouts: ShardedMap::new(),
+            bind_groups: ShardedMap::new(),
+            frame: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the frame index recorded on cache hits and insertions.
+    ///
+    /// Done automatically by [`RenderManager::begin_frame`](crate::renderer::RenderManager::begin_frame)
+    /// for the instance returned by [`RenderManager::shared_materials`](crate::renderer::RenderManager::shared_materials).
+    pub fn set_frame(&self, frame: u64) {
+        self.frame.store(frame, Ordering::Relaxed);
+    }
+
+    /// Returns the material bind group layout for the given texture views, creating it if necessary.
+    ///
+    /// # Panics
+    /// If a view's format cannot be sampled, see [`try_layout`](Self::try_layout).
+    pub fn layout(&self, texture_views: &[&TextureView], has_shadow: bool) -> BindGroupLayout {
+        self.try_layout(texture_views, has_shadow).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`layout`](Self::layout), failing with [`CrmError::UnsupportedFormat`] for views
+    /// whose format cannot be sampled on this device.
+    pub fn try_layout(&self, texture_views: &[&TextureView], has_shadow: bool) -> Result<BindGroupLayout, CrmError> {
+        self.try_layout_with_access(texture_views, &[], has_shadow)
+    }
+
+    /// [`try_layout`](Self::try_layout) with storage bindings selected by `access`, as
+    /// for [`PipelineOptions::texture_access`](crate::pipelines::PipelineOptions::texture_access).
+    fn try_layout_with_access(&self, texture_views: &[&TextureView], access: &[TextureAccess], has_shadow: bool) -> Result<BindGroupLayout, CrmError> {
+        let key = LayoutKey::from_views(texture_views, access, has_shadow);
+        let frame = self.frame.load(Ordering::Relaxed);
+
+        if let Some(entry) = self.layouts.read(&key).get(&key) {
+            return Ok(entry.touch(frame).layout.clone());
+        }
+
+        let _span = trace_span!("shared_material_layout_miss", textures = texture_views.len(), has_shadow);
+        let entries = material_layout_entries(&self.device, texture_views, access, has_shadow)?;
+        let layout = self.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some(&namespaced("material bind group layout")),
+            entries: &entries,
+        });
+        trace_event!(entries = entries.len(), "created shared material bind group layout");
+
+        let label = format!("{} (shared)", describe_material(texture_views.len(), has_shadow));
+        Ok(self
+            .layouts
+            .write(&key)
+            .entry(key)
+            .or_insert_with(|| SharedTracked::new(MaterialLayout { layout, entries }, frame, label))
+            .value
+            .layout
+            .clone())
+    }
+
+    /// Returns a bind group for the given texture views, creating it if necessary.
+    ///
+    /// See [`RenderManager::render_with_textures`](crate::renderer::RenderManager::render_with_textures)
+    /// for the binding layout.
+    ///
+    /// # Panics
+    /// If a view's format cannot be sampled, see [`try_get_or_create`](Self::try_get_or_create).
+    pub fn get_or_create(&self, texture_views: &[&TextureView], shadow: Option<(&Sampler, &TextureView)>) -> BindGroup {
+        self.try_get_or_create(texture_views, shadow).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`get_or_create`](Self::get_or_create), failing with [`CrmError::UnsupportedFormat`]
+    /// for views whose format cannot be sampled on this device.
+    pub fn try_get_or_create(&self, texture_views: &[&TextureView], shadow: Option<(&Sampler, &TextureView)>) -> Result<BindGroup, CrmError> {
+        let has_shadow = shadow.is_some();
+        let key = MaterialBindGroupKey::from_views(texture_views, &[], has_shadow);
+        let frame = self.frame.load(Ordering::Relaxed);
+
+        if let Some(entry) = self.bind_groups.read(&key).get(&key) {
+            return Ok(entry.touch(frame).clone());
+        }
+
+        let _span = trace_span!("shared_material_bind_group_miss", textures = texture_views.len(), has_shadow);
+        let layout = self.try_layout(texture_views, has_shadow)?;
+        let bind_group = create_material_bind_group(&self.device, &layout, &self.sampler, texture_views, shadow);
+        trace_event!(textures = texture_views.len(), has_shadow, "created shared material bind group");
+
+        let label = format!("{} (shared)", describe_material(texture_views.len(), has_shadow));
+        Ok(self
+            .bind_groups
+            .write(&key)
+            .entry(key)
+            .or_insert_with(|| SharedTracked::new(bind_group, frame, label))
+            .value
+            .clone())
+    }
+
+    /// Bind groups for a whole set of materials, in request order.
+    ///
+    /// # Panics
+    /// If a view's format cannot be sampled, see [`try_get_or_create_many`](Self::try_get_or_create_many).
+    pub fn get_or_create_many(&self, requests: &[MaterialRequest]) -> Vec<BindGroup> {
+        self.try_get_or_create_many(requests).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`get_or_create_many`](Self::get_or_create_many), failing like
+    /// [`try_get_or_create`](Self::try_get_or_create), or with
+    /// [`CrmError::UnsupportedFormat`] for views that cannot be bound with their
+    /// requested [`access`](MaterialRequest::access).
+    ///
+    /// Requests are grouped by shard, so every shard is read-locked once for all hits
+    /// and write-locked once for all insertions, instead of once per material. Misses
+    /// are created outside the locks.
+    pub fn try_get_or_create_many(&self, requests: &[MaterialRequest]) -> Result<Vec<BindGroup>, CrmError> {
+        let frame = self.frame.load(Ordering::Relaxed);
+        let keys: Vec<_> = requests
+            .iter()
+            .map(|r| MaterialBindGroupKey::from_views(r.texture_views, r.access, r.shadow.is_some()))
+            .collect();
+        let mut by_shard = vec![Vec::new(); SHARD_COUNT];
+        for (i, key) in keys.iter().enumerate() {
+            by_shard[self.bind_groups.shard_index(key)].push(i);
+        }
+
+        let mut results: Vec<Option<BindGroup>> = vec![None; requests.len()];
+        let mut missing = Vec::new();
+        for (shard, indices) in by_shard.iter().enumerate().filter(|(_, indices)| !indices.is_empty()) {
+            let map = self.bind_groups.read_shard(shard);
+            for &i in indices {
+                match map.get(&keys[i]) {
+                    Some(entry) => results[i] = Some(entry.touch(frame).clone()),
+                    None => missing.push(i),
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            let _span = trace_span!("shared_material_bind_group_batch_miss", requests = requests.len(), missing = missing.len());
+            let mut created: HashMap<&MaterialBindGroupKey, BindGroup> = HashMap::new();
+            for &i in &missing {
+                if created.contains_key(&keys[i]) {
+                    continue;
+                }
+                let request = &requests[i];
+                let layout = self.try_layout_with_access(request.texture_views, request.access, request.shadow.is_some())?;
+                let bind_group = create_material_bind_group(&self.device, &layout, &self.sampler, request.texture_views, request.shadow);
+                created.insert(&keys[i], bind_group);
+            }
+            trace_event!(created = created.len(), "created shared material bind groups in batch");
+
+            let mut missing_by_shard = vec![Vec::new(); SHARD_COUNT];
+            for &i in &missing {
+                missing_by_shard[self.bind_groups.shard_index(&keys[i])].push(i);
+            }
+            for (shard, indices) in missing_by_shard.iter().enumerate().filter(|(_, indices)| !indices.is_empty()) {
+                let mut map = self.bind_groups.write_shard(shard);
+                for &i in indices {
+                    let request = &requests[i];
+                    let label = format!("{} (shared)", describe_material(request.texture_views.len(), request.shadow.is_some()));
+                    // a racing thread may have inserted it meanwhile, the first insertion wins
+                    let entry = map
+                        .entry(keys[i].clone())
+                        .or_insert_with(|| SharedTracked::new(created[&keys[i]].clone(), frame, label));
+                    results[i] = Some(entry.value.clone());
+                }
+            }
+        }
+
+        Ok(results.into_iter().map(|r| r.expect("every request is a hit or was created")).collect())
+    }
+
+    /// Clears all cached bind groups. Layouts are kept.
+    pub fn clear(&self) {
+        trace_evict!("shared_material_bind_groups", self.bind_groups.len());
+        self.bind_groups.for_each_shard_mut(HashMap::clear);
+    }
+
+    /// Remove the bind groups of exactly these views, with any access modes and with
+    /// and without shadow bindings. Returns `true` if any existed.
+    pub(crate) fn evict_views(&self, texture_views: &[&TextureView]) -> bool {
+        let (views_only_hash, _) = view_hashes(texture_views, &[]);
+        let mut removed = 0;
+        self.bind_groups.for_each_shard_mut(|shard| {
+            let before = shard.len();
+            shard.retain(|key, _| !key.is_of_views(views_only_hash));
+            removed += before - shard.len();
+        });
+        trace_evict!("shared_material_bind_groups", removed);
+        removed > 0
+    }
+
+    /// Append diagnostics for all cached layouts and bind groups.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        self.layouts.for_each_shard(|shard| {
+            for (key, entry) in shard {
+                let bindings: Vec<_> = entry.value.entries.iter().map(|e| e.ty).collect();
+                let details = format!("shared, {:?}, bindings: {:?}", key, bindings);
+                out.push(entry.info(CacheKind::MaterialLayout, entry_id(key), details, 0));
+            }
+        });
+        self.bind_groups.for_each_shard(|shard| {
+            for (key, entry) in shard {
+                let details = format!("shared, {:?}", key);
+                out.push(entry.info(CacheKind::MaterialBindGroup, entry_id(key), details, 0));
+            }
+        });
+    }
+
+    /// Remove a single entry by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&self, kind: CacheKind, id: u64) -> bool {
+        let mut removed = false;
+        match kind {
+            CacheKind::MaterialLayout => self.layouts.for_each_shard_mut(|shard| {
+                removed |= crate::diagnostics::evict_by_id(shard, id);
+            }),
+            CacheKind::MaterialBindGroup => self.bind_groups.for_each_shard_mut(|shard| {
+                removed |= crate::diagnostics::evict_by_id(shard, id);
+            }),
+            _ => {}
+        }
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn keys_stay_in_their_shard() {
+        let map = ShardedMap::new();
+        for i in 0..1000u32 {
+            map.write(&i).insert(i, i * 2);
+        }
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000u32 {
+            assert!(map.shard_index(&i) < SHARD_COUNT);
+            assert_eq!(map.read_shard(map.shard_index(&i)).get(&i), Some(&(i * 2)));
+            assert_eq!(map.read(&i).get(&i), Some(&(i * 2)));
+        }
+    }
+
+    #[test]
+    fn keys_spread_over_shards() {
+        let map: ShardedMap<u32, ()> = ShardedMap::new();
+        let mut used = [false; SHARD_COUNT];
+        for i in 0..1000u32 {
+            used[map.shard_index(&i)] = true;
+        }
+        assert!(used.iter().all(|&used| used));
+    }
+
+    #[test]
+    fn shard_iteration_sees_every_entry() {
+        let map = ShardedMap::new();
+        for i in 0..100u32 {
+            map.write(&i).insert(i, ());
+        }
+        map.for_each_shard_mut(|shard| shard.retain(|&key, _| key % 2 == 0));
+        let mut keys = Vec::new();
+        map.for_each_shard(|shard| keys.extend(shard.keys().copied()));
+        keys.sort_unstable();
+        assert_eq!(keys, (0..100).step_by(2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn concurrent_inserts_land_once() {
+        let map = ShardedMap::new();
+        let barrier = Barrier::new(8);
+        std::thread::scope(|s| {
+            for thread in 0..8u32 {
+                let (map, barrier) = (&map, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    for key in 0..256u32 {
+                        map.write(&key).entry(key).or_insert(thread);
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 256);
+        for key in 0..256u32 {
+            let first = *map.read(&key).get(&key).unwrap();
+            assert!(first < 8);
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! CPU frustum culling over a bounding volume hierarchy.
+//!
+//! For platforms where a compute culling pass (see [`occlusion`](crate::occlusion))
+//! doesn't fit the budget, a [`Bvh`] over the object bounds filters the draw list on the
+//! CPU before any command is encoded. Whole subtrees are rejected (or accepted) with one
+//! plane test, so the cost grows with the visible objects rather than the scene size:
+//! ```ignore
+//! let bvh = Bvh::build(&objects.iter().map(|o| o.bounds).collect::<Vec<_>>());
+//! // Every frame
+//! let frustum = Frustum::from_view_proj(&camera.uniform().view_proj);
+//! bvh.cull(&frustum, &mut visible);
+//! for &index in &visible {
+//!     let object = &objects[index as usize];
+//!     meshes.draw(&mut pass, object.mesh, &options, 0..1);
+//! }
+//! ```
+//! Build the hierarchy again (or [`refit`](Bvh::refit) it) when objects move.
+//!
+//! Every mesh gets local-space [`MeshBounds`] on upload (see
+//! [`MeshManager::bounds`](crate::meshes::MeshManager::bounds)); move them to world space with
+//! [`Aabb::transformed`] or [`MeshBounds::world_sphere`].
+
+/// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    /// Box containing nothing; the identity of [`union`](Self::union).
+    pub const EMPTY: Self = Self {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |aabb, p| aabb.union(&Self { min: p, max: p }))
+    }
+
+    pub fn from_sphere(center: [f32; 3], radius: f32) -> Self {
+        Self {
+            min: std::array::from_fn(|i| center[i] - radius),
+            max: std::array::from_fn(|i| center[i] + radius),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: std::array::from_fn(|i| self.min[i].min(other.min[i])),
+            max: std::array::from_fn(|i| self.max[i].max(other.max[i])),
+        }
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        std::array::from_fn(|i| (self.min[i] + self.max[i]) * 0.5)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+
+    /// Bounds of this box after a column-major affine transform.
+    pub fn transformed(&self, m: &[[f32; 4]; 4]) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        // Arvo's method: each matrix element moves min or max depending on its sign.
+        let mut min: [f32; 3] = std::array::from_fn(|i| m[3][i]);
+        let mut max = min;
+        for (column, (&lo, &hi)) in m.iter().zip(self.min.iter().zip(&self.max)) {
+            for i in 0..3 {
+                let a = column[i] * lo;
+                let b = column[i] * hi;
+                min[i] += a.min(b);
+                max[i] += a.max(b);
+            }
+        }
+        Self { min, max }
+    }
+}
+
+/// Local-space bounds of a mesh, computed on upload by the
+/// [`MeshManager`](crate::meshes::MeshManager).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshBounds {
+    pub aabb: Aabb,
+    /// Center of the bounding sphere, the center of `aabb`.
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+impl MeshBounds {
+    /// Bounds of a point set; the sphere is centered on the box and encloses every point.
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]> + Clone) -> Self {
+        let aabb = Aabb::from_points(points.clone());
+        let center = aabb.center();
+        let radius_squared = points
+            .into_iter()
+            .map(|p| (0..3).map(|i| (p[i] - center[i]) * (p[i] - center[i])).sum::<f32>())
+            .fold(0.0, f32::max);
+        Self { aabb, center, radius: radius_squared.sqrt() }
+    }
+
+    /// World-space bounding sphere under a column-major affine transform, as `xyz` =
+    /// center and `w` = radius, the format read by
+    /// `OcclusionCuller::cull`.
+    pub fn world_sphere(&self, m: &[[f32; 4]; 4]) -> [f32; 4] {
+        let c = self.center;
+        let center: [f32; 3] = std::array::from_fn(|i| m[0][i] * c[0] + m[1][i] * c[1] + m[2][i] * c[2] + m[3][i]);
+        let scale = (0..3)
+            .map(|j| (m[j][0] * m[j][0] + m[j][1] * m[j][1] + m[j][2] * m[j][2]).sqrt())
+            .fold(0.0, f32::max);
+        [center[0], center[1], center[2], self.radius * scale]
+    }
+}
+
+/// [`MeshBounds`] as stored in the mesh bounds buffer, see
+/// [`MeshManager::bounds_buffer`](crate::meshes::MeshManager::bounds_buffer).
+///
+/// ```wgsl
+/// struct MeshBounds {
+///     sphere: vec4<f32>,  // xyz = center, w = radius
+///     aabb_min: vec4<f32>,
+///     aabb_max: vec4<f32>,
+/// };
+/// ```
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMeshBounds {
+    pub sphere: [f32; 4],
+    pub aabb_min: [f32; 4],
+    pub aabb_max: [f32; 4],
+}
+
+impl GpuMeshBounds {
+    /// Bounds of meshes without a known position attribute, never culled.
+    pub const UNBOUNDED: Self = Self {
+        sphere: [0.0, 0.0, 0.0, f32::MAX],
+        aabb_min: [f32::MIN, f32::MIN, f32::MIN, 0.0],
+        aabb_max: [f32::MAX, f32::MAX, f32::MAX, 0.0],
+    };
+}
+
+impl From<&MeshBounds> for GpuMeshBounds {
+    fn from(bounds: &MeshBounds) -> Self {
+        let [x, y, z] = bounds.center;
+        let [min_x, min_y, min_z] = bounds.aabb.min;
+        let [max_x, max_y, max_z] = bounds.aabb.max;
+        Self {
+            sphere: [x, y, z, bounds.radius],
+            aabb_min: [min_x, min_y, min_z, 0.0],
+            aabb_max: [max_x, max_y, max_z, 0.0],
+        }
+    }
+}
+
+/// Where a volume lies relative to a [`Frustum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// The six clip planes of a view-projection, pointing inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// `xyz` = normal, `w` = distance; a point is inside if `dot(n, p) + w >= 0`.
+    pub planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of a column-major view-projection with wgpu's `0..1` clip depth.
+    ///
+    /// Works with reversed and infinite projections; a degenerate far plane accepts
+    /// everything.
+    pub fn from_view_proj(m: &[[f32; 4]; 4]) -> Self {
+        let row = |r: usize| [m[0][r], m[1][r], m[2][r], m[3][r]];
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let add = |a: [f32; 4], b: [f32; 4]| std::array::from_fn(|i| a[i] + b[i]);
+        let sub = |a: [f32; 4], b: [f32; 4]| std::array::from_fn(|i| a[i] - b[i]);
+        let planes = [add(w, x), sub(w, x), add(w, y), sub(w, y), z, sub(w, z)].map(|p: [f32; 4]| {
+            let length = (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+            if length > f32::EPSILON {
+                p.map(|v| v / length)
+            } else {
+                [0.0, 0.0, 0.0, 1.0]
+            }
+        });
+        Self { planes }
+    }
+
+    pub fn contains_sphere(&self, center: [f32; 3], radius: f32) -> bool {
+        self.planes.iter().all(|p| p[0] * center[0] + p[1] * center[1] + p[2] * center[2] + p[3] >= -radius)
+    }
+
+    pub fn test_aabb(&self, aabb: &Aabb) -> Containment {
+        let mut result = Containment::Inside;
+        for p in &self.planes {
+            // Corners farthest along and against the plane normal.
+            let corner = |along: bool| -> f32 {
+                (0..3)
+                    .map(|i| p[i] * if (p[i] >= 0.0) == along { aabb.max[i] } else { aabb.min[i] })
+                    .sum::<f32>()
+                    + p[3]
+            };
+            if corner(true) < 0.0 {
+                return Containment::Outside;
+            }
+            if corner(false) < 0.0 {
+                result = Containment::Intersecting;
+            }
+        }
+        result
+    }
+}
+
+/// Leaf size of the hierarchy.
+const MAX_LEAF_ITEMS: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    /// First item of a leaf, or the left child of an inner node (the right one follows).
+    first: u32,
+    /// Number of items of a leaf, 0 for an inner node.
+    count: u32,
+}
+
+/// Bounding volume hierarchy over object bounds, split at the median of the longest axis.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    /// Object indices in leaf order.
+    items: Vec<u32>,
+    bounds: Vec<Aabb>,
+}
+
+impl Bvh {
+    /// Build the hierarchy over one box per object; objects are referred to by index.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let _span = trace_span!("bvh_build", objects = bounds.len());
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * bounds.len().div_ceil(MAX_LEAF_ITEMS)),
+            items: (0..bounds.len() as u32).collect(),
+            bounds: bounds.to_vec(),
+        };
+        if !bounds.is_empty() {
+            bvh.nodes.push(BvhNode { bounds: Aabb::EMPTY, first: 0, count: 0 });
+            bvh.split(0, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn split(&mut self, node: usize, start: usize, end: usize) {
+        let items = &mut self.items[start..end];
+        let bounds = items.iter().fold(Aabb::EMPTY, |aabb, &i| aabb.union(&self.bounds[i as usize]));
+        if items.len() <= MAX_LEAF_ITEMS {
+            self.nodes[node] = BvhNode { bounds, first: start as u32, count: items.len() as u32 };
+            return;
+        }
+
+        let centers = Aabb::from_points(items.iter().map(|&i| self.bounds[i as usize].center()));
+        let extent: [f32; 3] = std::array::from_fn(|i| centers.max[i] - centers.min[i]);
+        let axis = (0..3).max_by(|&a, &b| extent[a].total_cmp(&extent[b])).unwrap();
+        let mid = items.len() / 2;
+        let all_bounds = &self.bounds;
+        items.select_nth_unstable_by(mid, |&a, &b| {
+            all_bounds[a as usize].center()[axis].total_cmp(&all_bounds[b as usize].center()[axis])
+        });
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::EMPTY, first: 0, count: 0 });
+        self.nodes.push(BvhNode { bounds: Aabb::EMPTY, first: 0, count: 0 });
+        self.nodes[node] = BvhNode { bounds, first: left as u32, count: 0 };
+        self.split(left, start, start + mid);
+        self.split(left + 1, start + mid, end);
+    }
+
+    /// Update the bounds of moved objects without changing the tree structure.
+    ///
+    /// Cheaper than [`build`](Self::build) but the tree gets looser as objects move apart.
+    ///
+    /// ## Panics
+    /// Panics if `bounds` has a different length than at build time.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        assert_eq!(bounds.len(), self.bounds.len(), "Bvh::refit with a different object count");
+        self.bounds.copy_from_slice(bounds);
+        // Children always come after their parent.
+        for node in (0..self.nodes.len()).rev() {
+            let BvhNode { first, count, .. } = self.nodes[node];
+            let first = first as usize;
+            self.nodes[node].bounds = if count > 0 {
+                self.items[first..first + count as usize]
+                    .iter()
+                    .fold(Aabb::EMPTY, |aabb, &i| aabb.union(&self.bounds[i as usize]))
+            } else {
+                self.nodes[first].bounds.union(&self.nodes[first + 1].bounds)
+            };
+        }
+    }
+
+    /// Replace the contents of `visible` with the indices of the objects inside or
+    /// intersecting `frustum`, in ascending order so draws keep their submission order.
+    pub fn cull(&self, frustum: &Frustum, visible: &mut Vec<u32>) {
+        let _span = trace_span!("bvh_cull", objects = self.bounds.len());
+        visible.clear();
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![(0usize, false)];
+        while let Some((node, inside)) = stack.pop() {
+            let BvhNode { bounds, first, count } = self.nodes[node];
+            let inside = inside || match frustum.test_aabb(&bounds) {
+                Containment::Outside => continue,
+                Containment::Intersecting => false,
+                Containment::Inside => true,
+            };
+            if count == 0 {
+                stack.push((first as usize, inside));
+                stack.push((first as usize + 1, inside));
+                continue;
+            }
+            let items = &self.items[first as usize..(first + count) as usize];
+            visible.extend(
+                items
+                    .iter()
+                    .filter(|&&i| inside || frustum.test_aabb(&self.bounds[i as usize]) != Containment::Outside),
+            );
+        }
+        visible.sort_unstable();
+    }
+
+    /// Keep only the draws whose object (same index as at build time) is visible.
+    pub fn filter<'a, T>(&self, frustum: &Frustum, draws: &'a [T]) -> Vec<&'a T> {
+        let mut visible = Vec::new();
+        self.cull(frustum, &mut visible);
+        visible.iter().filter_map(|&i| draws.get(i as usize)).collect()
+    }
+
+    /// Number of objects.
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Orthographic view-projection seeing `-10..10` on x and y and `0..10` on z.
+    const ORTHO: [[f32; 4]; 4] = [[0.1, 0.0, 0.0, 0.0], [0.0, 0.1, 0.0, 0.0], [0.0, 0.0, 0.1, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+    fn unit_box(center: [f32; 3]) -> Aabb {
+        Aabb::from_sphere(center, 0.5)
+    }
+
+    /// Objects on a grid from -20 to 20 on x and y, at depth 5.
+    fn grid() -> Vec<Aabb> {
+        (0..21)
+            .flat_map(|x| (0..21).map(move |y| unit_box([x as f32 * 2.0 - 20.0, y as f32 * 2.0 - 20.0, 5.0])))
+            .collect()
+    }
+
+    fn brute_force(frustum: &Frustum, bounds: &[Aabb]) -> Vec<u32> {
+        (0..bounds.len() as u32)
+            .filter(|&i| frustum.test_aabb(&bounds[i as usize]) != Containment::Outside)
+            .collect()
+    }
+
+    #[test]
+    fn frustum_classifies_boxes() {
+        let frustum = Frustum::from_view_proj(&ORTHO);
+        assert_eq!(frustum.test_aabb(&unit_box([0.0, 0.0, 5.0])), Containment::Inside);
+        assert_eq!(frustum.test_aabb(&unit_box([10.0, 0.0, 5.0])), Containment::Intersecting);
+        assert_eq!(frustum.test_aabb(&unit_box([0.0, 0.0, 10.2])), Containment::Intersecting);
+        assert_eq!(frustum.test_aabb(&unit_box([11.0, 0.0, 5.0])), Containment::Outside);
+        assert_eq!(frustum.test_aabb(&unit_box([0.0, -11.0, 5.0])), Containment::Outside);
+        assert_eq!(frustum.test_aabb(&unit_box([0.0, 0.0, -1.0])), Containment::Outside);
+    }
+
+    #[test]
+    fn frustum_contains_spheres() {
+        let frustum = Frustum::from_view_proj(&ORTHO);
+        assert!(frustum.contains_sphere([0.0, 0.0, 5.0], 1.0));
+        assert!(frustum.contains_sphere([10.5, 0.0, 5.0], 1.0));
+        assert!(!frustum.contains_sphere([12.0, 0.0, 5.0], 1.0));
+        assert!(!frustum.contains_sphere([0.0, 0.0, 12.0], 1.0));
+    }
+
+    #[test]
+    fn aabb_transform_translates_and_scales() {
+        let aabb = Aabb { min: [-1.0, -1.0, -1.0], max: [1.0, 1.0, 1.0] };
+        let m = [[2.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [5.0, 0.0, 0.0, 1.0]];
+        assert_eq!(aabb.transformed(&m), Aabb { min: [3.0, -1.0, -1.0], max: [7.0, 1.0, 1.0] });
+        assert!(Aabb::EMPTY.transformed(&m).is_empty());
+    }
+
+    #[test]
+    fn bvh_cull_matches_brute_force() {
+        let bounds = grid();
+        let bvh = Bvh::build(&bounds);
+        assert_eq!(bvh.len(), bounds.len());
+        let frustum = Frustum::from_view_proj(&ORTHO);
+        let mut visible = Vec::new();
+        bvh.cull(&frustum, &mut visible);
+        assert!(!visible.is_empty() && visible.len() < bounds.len());
+        assert_eq!(visible, brute_force(&frustum, &bounds));
+    }
+
+    #[test]
+    fn bvh_refit_follows_moved_objects() {
+        let mut bounds = grid();
+        let mut bvh = Bvh::build(&bounds);
+        let frustum = Frustum::from_view_proj(&ORTHO);
+        for aabb in &mut bounds {
+            *aabb = unit_box([aabb.center()[0] + 100.0, aabb.center()[1], 5.0]);
+        }
+        bvh.refit(&bounds);
+        let mut visible = vec![7];
+        bvh.cull(&frustum, &mut visible);
+        assert!(visible.is_empty());
+
+        bounds[3] = unit_box([0.0, 0.0, 5.0]);
+        bvh.refit(&bounds);
+        bvh.cull(&frustum, &mut visible);
+        assert_eq!(visible, vec![3]);
+        let draws: Vec<usize> = (0..bounds.len()).collect();
+        assert_eq!(bvh.filter(&frustum, &draws), vec![&3]);
+    }
+
+    #[test]
+    fn empty_bvh_culls_nothing() {
+        let bvh = Bvh::build(&[]);
+        assert!(bvh.is_empty());
+        let mut visible = vec![1, 2];
+        bvh.cull(&Frustum::from_view_proj(&ORTHO), &mut visible);
+        assert!(visible.is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+//! Fire-and-forget debug primitives.
+//!
+//! [`DebugDraw`] is a facade for debug output from anywhere in a frame:
+//! `debug.sphere(center, radius, color)`, `debug.text_3d(position, "label", 14.0, color)`.
+//! Primitives are accumulated until [`render`](DebugDraw::render), which draws all of
+//! them in one late pass and forgets them, so they have to be submitted again every
+//! frame. It owns its own line buffers, independent of any [`Gizmos`] the application
+//! keeps shapes in.
+//!
+//! 3D labels (feature `text`) are projected with the camera and drawn on top of
+//! everything, centered on their position; they need a font from
+//! `set_font` and a single-sampled target.
+//! ```ignore
+//! let mut debug = DebugDraw::new(&device, &queue, GizmoTarget::new(surface.scene_format(), Some(DEPTH_FORMAT)), false);
+//! debug.set_font(include_bytes!("fonts/Inter.ttf"))?;
+//! // Anywhere during the frame
+//! debug.sphere(light.position, light.radius, [1.0, 0.8, 0.2, 1.0]);
+//! debug.text_3d(enemy.position, &enemy.state_name(), 14.0, [1.0; 4]);
+//! // In the last pass writing the scene target, with the scene depth attached
+//! debug.render(&mut pass, &camera);
+//! ```
+use wgpu::*;
+use crate::camera::CameraManager;
+use crate::culling::Aabb;
+use crate::gizmos::{GizmoTarget, Gizmos};
+#[cfg(feature = "text")]
+use crate::text::{FontId, TextAlign, TextError, TextRenderer, TextStyle};
+
+/// A queued 3D label.
+#[cfg(feature = "text")]
+struct Label {
+    position: [f32; 3],
+    text: String,
+    size: f32,
+    color: [f32; 4],
+}
+
+/// Immediate-mode debug drawing, rendered and cleared once per frame.
+pub struct DebugDraw {
+    #[cfg(feature = "text")]
+    device: Device,
+    #[cfg(feature = "text")]
+    queue: Queue,
+    target: GizmoTarget,
+    shapes: Gizmos,
+    enabled: bool,
+    #[cfg(feature = "text")]
+    text: Option<(TextRenderer, FontId)>,
+    #[cfg(feature = "text")]
+    labels: Vec<Label>,
+}
+
+impl DebugDraw {
+    /// Draw into passes matching `target`; `reversed_z` must match the camera projection.
+    pub fn new(device: &Device, queue: &Queue, target: GizmoTarget, reversed_z: bool) -> Self {
+        Self {
+            #[cfg(feature = "text")]
+            device: device.clone(),
+            #[cfg(feature = "text")]
+            queue: queue.clone(),
+            target,
+            shapes: Gizmos::new(device, queue, reversed_z),
+            enabled: true,
+            #[cfg(feature = "text")]
+            text: None,
+            #[cfg(feature = "text")]
+            labels: Vec::new(),
+        }
+    }
+
+    /// Load the font of [`text_3d`](Self::text_3d) labels.
+    #[cfg(feature = "text")]
+    pub fn set_font(&mut self, bytes: &[u8]) -> Result<(), TextError> {
+        let mut text = TextRenderer::new(&self.device, &self.queue, self.target.format).with_depth_format(self.target.depth_format);
+        let font = text.add_font(bytes)?;
+        self.text = Some((text, font));
+        Ok(())
+    }
+
+    /// Ignore all primitives while disabled, e.g. to toggle debug output with a key.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Hide primitives submitted from now on behind scene geometry (the default), or
+    /// draw them on top. Labels are always on top.
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        self.shapes.set_depth_test(depth_test);
+    }
+
+    pub fn line(&mut self, a: [f32; 3], b: [f32; 3], color: [f32; 4]) {
+        if self.enabled {
+            self.shapes.line(a, b, color);
+        }
+    }
+
+    /// A line with an arrow head at `to`, e.g. a velocity or normal.
+    pub fn arrow(&mut self, from: [f32; 3], to: [f32; 3], color: [f32; 4]) {
+        if !self.enabled {
+            return;
+        }
+        self.shapes.line(from, to, color);
+        let direction: [f32; 3] = std::array::from_fn(|i| to[i] - from[i]);
+        let length = direction.iter().map(|d| d * d).sum::<f32>().sqrt();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let forward = direction.map(|d| d / length);
+        let helper = if forward[1].abs() < 0.9 { [0.0, 1.0, 0.0] } else { [1.0, 0.0, 0.0] };
+        let side = [
+            forward[1] * helper[2] - forward[2] * helper[1],
+            forward[2] * helper[0] - forward[0] * helper[2],
+            forward[0] * helper[1] - forward[1] * helper[0],
+        ];
+        let side_length = side.iter().map(|d| d * d).sum::<f32>().sqrt();
+        let head = length * 0.2;
+        for sign in [-1.0, 1.0] {
+            let tip = std::array::from_fn(|i| to[i] - forward[i] * head + side[i] / side_length * head * 0.5 * sign);
+            self.shapes.line(to, tip, color);
+        }
+    }
+
+    /// A small three-axis cross of `size` world units.
+    pub fn point(&mut self, position: [f32; 3], size: f32, color: [f32; 4]) {
+        if !self.enabled {
+            return;
+        }
+        for axis in 0..3 {
+            let mut a = position;
+            let mut b = position;
+            a[axis] -= size * 0.5;
+            b[axis] += size * 0.5;
+            self.shapes.line(a, b, color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        if self.enabled {
+            self.shapes.aabb(aabb, color);
+        }
+    }
+
+    pub fn sphere(&mut self, center: [f32; 3], radius: f32, color: [f32; 4]) {
+        if self.enabled {
+            self.shapes.sphere(center, radius, color);
+        }
+    }
+
+    /// The frustum of a view-projection, see [`Gizmos::frustum`].
+    pub fn frustum(&mut self, view_proj: &[[f32; 4]; 4], color: [f32; 4]) {
+        if self.enabled {
+            self.shapes.frustum(view_proj, color);
+        }
+    }
+
+    pub fn axes(&mut self, transform: &[[f32; 4]; 4], length: f32) {
+        if self.enabled {
+            self.shapes.axes(transform, length);
+        }
+    }
+
+    /// A label of `size` pixels centered on a world position. Without a font it is
+    /// dropped.
+    #[cfg(feature = "text")]
+    pub fn text_3d(&mut self, position: [f32; 3], text: &str, size: f32, color: [f32; 4]) {
+        if self.enabled && self.text.is_some() {
+            self.labels.push(Label {
+                position,
+                text: text.to_string(),
+                size,
+                color,
+            });
+        }
+    }
+
+    /// Upload and draw everything submitted since the last render with `camera`, then
+    /// clear it. `pass` must match the target given to [`new`](Self::new).
+    ///
+    /// Written with `Queue::write_buffer`, so render once per submission.
+    pub fn render(&mut self, pass: &mut RenderPass, camera: &CameraManager) {
+        let _span = trace_span!("debug_draw", lines = self.shapes.len());
+        self.shapes.upload();
+        self.shapes.render(pass, camera, &self.target);
+        #[cfg(feature = "text")]
+        self.render_labels(pass, camera);
+        self.clear();
+    }
+
+    /// Drop everything submitted since the last render.
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+        #[cfg(feature = "text")]
+        self.labels.clear();
+    }
+
+    #[cfg(feature = "text")]
+    fn render_labels(&mut self, pass: &mut RenderPass, camera: &CameraManager) {
+        let Some((text, font)) = &mut self.text else {
+            return;
+        };
+        if self.labels.is_empty() {
+            return;
+        }
+        let uniform = camera.uniform();
+        let [width, height] = uniform.viewport_size;
+        let m = &uniform.view_proj;
+        for label in &self.labels {
+            let p = [label.position[0], label.position[1], label.position[2], 1.0];
+            let clip: [f32; 4] = std::array::from_fn(|row| (0..4).map(|col| m[col][row] * p[col]).sum());
+            // Behind the camera.
+            if clip[3] <= f32::EPSILON {
+                continue;
+            }
+            let x = (clip[0] / clip[3] * 0.5 + 0.5) * width;
+            let y = (0.5 - clip[1] / clip[3] * 0.5) * height - label.size * 0.5;
+            let style = TextStyle::new(label.size).with_color(label.color).with_align(TextAlign::Center);
+            text.queue(*font, &label.text, [x, y], &style);
+        }
+        text.prepare(width as u32, height as u32);
+        text.render(pass);
+    }
+}
@@ -0,0 +1,102 @@
+//! Debug render modes swapping in alternate pipelines.
+//!
+//! A [`DebugRenderMode`] replaces how cached render pipelines draw without
+//! touching the material shaders: set it for everything with
+//! [`RenderManager::set_debug_mode`](crate::renderer::RenderManager::set_debug_mode),
+//! or per material with [`PipelineOptions::with_debug_mode`]. The pipeline cache
+//! keys pipelines by their mode, so switching back and forth reuses the variants
+//! built before.
+//!
+//! - [`Wireframe`](DebugRenderMode::Wireframe) keeps the material shader and draws
+//!   polygon edges. Requires [`Features::POLYGON_MODE_LINE`].
+//! - [`Normals`](DebugRenderMode::Normals) and [`UvChecker`](DebugRenderMode::UvChecker)
+//!   keep the material's vertex stage and replace the fragment stage with a built-in
+//!   shader reading the normal or UV from the given vertex output location. The
+//!   output must be a `vec3<f32>` (normal) or `vec2<f32>` (UV) with default
+//!   interpolation. Only the first color target is written.
+//!
+//! Depth-only pipelines are only affected by `Wireframe`; OIT pipelines are not
+//! affected by the fragment replacing modes.
+use wgpu::*;
+use crate::pipelines::PipelineOptions;
+
+/// Alternate way of drawing a material, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugRenderMode {
+    /// Draw polygon edges with the material's own shaders.
+    Wireframe,
+    /// Color fragments by the normal at vertex output `@location(location)`.
+    Normals { location: u32 },
+    /// Draw a checkerboard from the UV at vertex output `@location(location)`.
+    UvChecker { location: u32 },
+}
+
+impl DebugRenderMode {
+    /// Built-in fragment shader of the mode, `None` if it keeps the material's.
+    pub(crate) fn fragment_source(&self) -> Option<String> {
+        match *self {
+            DebugRenderMode::Wireframe => None,
+            DebugRenderMode::Normals { location } => Some(format!(
+                r#"
+@fragment
+fn fs_main(@location({location}) normal: vec3<f32>) -> @location(0) vec4<f32> {{
+    return vec4<f32>(normalize(normal) * 0.5 + 0.5, 1.0);
+}}
+"#
+            )),
+            DebugRenderMode::UvChecker { location } => Some(format!(
+                r#"
+@fragment
+fn fs_main(@location({location}) uv: vec2<f32>) -> @location(0) vec4<f32> {{
+    let cell = vec2<i32>(floor(uv * 8.0));
+    let checker = f32((cell.x + cell.y) & 1);
+    let tint = vec3<f32>(fract(uv), 0.5);
+    return vec4<f32>(tint * mix(0.35, 1.0, checker), 1.0);
+}}
+"#
+            )),
+        }
+    }
+
+    pub(crate) fn polygon_mode(&self) -> PolygonMode {
+        match self {
+            DebugRenderMode::Wireframe => PolygonMode::Line,
+            _ => PolygonMode::Fill,
+        }
+    }
+}
+
+/// The mode a pipeline built from `options` is drawn with, `None` if it is unaffected.
+///
+/// ## Panics
+/// Panics if the mode is `Wireframe` and the device lacks [`Features::POLYGON_MODE_LINE`].
+pub(crate) fn effective_mode(options: &PipelineOptions, global: Option<DebugRenderMode>, features: Features) -> Option<DebugRenderMode> {
+    let mode = options.debug_mode.or(global)?;
+    match mode {
+        DebugRenderMode::Wireframe => {
+            assert!(
+                features.contains(Features::POLYGON_MODE_LINE),
+                "DebugRenderMode::Wireframe requires Features::POLYGON_MODE_LINE"
+            );
+            Some(mode)
+        }
+        _ if options.vertex_only || options.oit => None,
+        _ => Some(mode),
+    }
+}
+
+/// Targets of a fragment replacing mode: the first target without blending, the
+/// others masked out since the debug shader doesn't write them.
+pub(crate) fn debug_targets(targets: &[Option<ColorTargetState>]) -> Vec<Option<ColorTargetState>> {
+    targets
+        .iter()
+        .enumerate()
+        .map(|(i, target)| {
+            target.as_ref().map(|target| ColorTargetState {
+                blend: None,
+                write_mask: if i == 0 { ColorWrites::ALL } else { ColorWrites::empty() },
+                ..target.clone()
+            })
+        })
+        .collect()
+}
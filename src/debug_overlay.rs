@@ -0,0 +1,163 @@
+// debug_overlay.rs
+//! egui panel for inspecting the crate's caches live (feature `egui`).
+//!
+//! ```ignore
+//! let mut overlay = CacheOverlay::new();
+//! // Every frame, inside your egui run:
+//! overlay.show(&egui_ctx, &mut render_manager);
+//! ```
+use egui::{Color32, Context, Grid, RichText, ScrollArea, Ui, Window};
+use crate::diagnostics::{CacheEntryInfo, CacheKind};
+use crate::renderer::RenderManager;
+
+/// Debug window listing every cached pipeline, layout, bind group and texture.
+///
+/// For each entry it shows when it was created, how many frames ago it was
+/// last used, how often it was hit and its estimated memory. Entries can be
+/// inspected (full key description) or evicted on the spot; evicted resources
+/// are simply recreated on their next use.
+///
+/// Frame numbers are only meaningful if you call
+/// [`RenderManager::begin_frame`] every frame.
+pub struct CacheOverlay {
+    /// Whether the window is open.
+    pub open: bool,
+    filter: String,
+    selected: Option<(CacheKind, u64)>,
+}
+
+impl Default for CacheOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheOverlay {
+    /// Create a new, open overlay.
+    pub fn new() -> Self {
+        Self {
+            open: true,
+            filter: String::new(),
+            selected: None,
+        }
+    }
+
+    /// Show the overlay as its own egui window.
+    pub fn show(&mut self, ctx: &Context, manager: &mut RenderManager) {
+        let mut open = self.open;
+        Window::new("wgpu_render_manager caches")
+            .open(&mut open)
+            .default_width(640.0)
+            .show(ctx, |ui| self.ui(ui, manager));
+        self.open = open;
+    }
+
+    /// Draw the overlay contents into an existing `Ui`, e.g. a side panel.
+    pub fn ui(&mut self, ui: &mut Ui, manager: &mut RenderManager) {
+        let frame = manager.frame_index();
+        let entries = manager.cache_entries();
+        let total_memory: u64 = entries.iter().map(|e| e.memory_bytes).sum();
+
+        ui.horizontal(|ui| {
+            ui.label(format!("frame {}", frame));
+            ui.separator();
+            ui.label(format!("{} entries", entries.len()));
+            ui.separator();
+            ui.label(format!("{} tracked", format_bytes(total_memory)));
+        });
+        ui.horizontal(|ui| {
+            ui.label("filter");
+            ui.text_edit_singleline(&mut self.filter);
+        });
+        ui.separator();
+
+        let filter = self.filter.to_lowercase();
+        let mut evict: Option<(CacheKind, u64)> = None;
+
+        ScrollArea::vertical().max_height(480.0).show(ui, |ui| {
+            for kind in CacheKind::ALL {
+                let mut rows: Vec<&CacheEntryInfo> = entries
+                    .iter()
+                    .filter(|e| e.kind == kind)
+                    .filter(|e| filter.is_empty() || e.label.to_lowercase().contains(&filter))
+                    .collect();
+                if rows.is_empty() {
+                    continue;
+                }
+                rows.sort_by(|a, b| b.last_used_frame.cmp(&a.last_used_frame).then(a.label.cmp(&b.label)));
+
+                let memory: u64 = rows.iter().map(|e| e.memory_bytes).sum();
+                let header = format!("{} ({}, {})", kind, rows.len(), format_bytes(memory));
+                ui.collapsing(header, |ui| {
+                    Grid::new(kind.name())
+                        .striped(true)
+                        .num_columns(6)
+                        .show(ui, |ui| {
+                            ui.strong("label");
+                            ui.strong("created");
+                            ui.strong("last used");
+                            ui.strong("hits");
+                            ui.strong("memory");
+                            ui.strong("");
+                            ui.end_row();
+
+                            for entry in rows {
+                                let idle = frame.saturating_sub(entry.last_used_frame);
+                                let color = if idle == 0 { Color32::LIGHT_GREEN } else { ui.visuals().text_color() };
+                                ui.label(RichText::new(&entry.label).color(color));
+                                ui.label(entry.created_frame.to_string());
+                                ui.label(format!("{} ({} ago)", entry.last_used_frame, idle));
+                                ui.label(entry.hits.to_string());
+                                ui.label(format_bytes(entry.memory_bytes));
+                                ui.horizontal(|ui| {
+                                    if ui.small_button("inspect").clicked() {
+                                        self.selected = Some((entry.kind, entry.id));
+                                    }
+                                    if ui.small_button("evict").clicked() {
+                                        evict = Some((entry.kind, entry.id));
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                        });
+                });
+            }
+        });
+
+        if let Some((kind, id)) = self.selected {
+            ui.separator();
+            match entries.iter().find(|e| e.kind == kind && e.id == id) {
+                Some(entry) => {
+                    ui.horizontal(|ui| {
+                        ui.strong(format!("{}: {}", entry.kind, entry.label));
+                        if ui.small_button("close").clicked() {
+                            self.selected = None;
+                        }
+                    });
+                    ui.monospace(format!("id {:#018x}", entry.id));
+                    ui.monospace(&entry.details);
+                }
+                None => self.selected = None,
+            }
+        }
+
+        if let Some((kind, id)) = evict {
+            manager.evict_cache_entry(kind, id);
+            if self.selected == Some((kind, id)) {
+                self.selected = None;
+            }
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const KIB: f64 = 1024.0;
+    let b = bytes as f64;
+    if b >= KIB * KIB {
+        format!("{:.1} MiB", b / (KIB * KIB))
+    } else if b >= KIB {
+        format!("{:.1} KiB", b / KIB)
+    } else {
+        format!("{} B", bytes)
+    }
+}
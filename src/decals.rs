@@ -0,0 +1,336 @@
+//! Projected box decals.
+//!
+//! Each [`Decal`] is a unit box (`-0.5..0.5` on every axis) placed by a world
+//! transform. [`DecalRenderer::render`] draws the boxes, reconstructs the scene
+//! position under every covered pixel from the depth buffer and projects the decal
+//! texture onto it along the box's local Y axis. Pixels whose scene position lies
+//! outside the box are discarded.
+//!
+//! Decals are grouped by texture; each group binds its texture through the
+//! [`RenderManager`]'s material bind group cache and is drawn with one instanced draw
+//! from a shared decal-box instance buffer.
+//!
+//! ## Forward and deferred
+//! The pass reads the scene depth as a texture, so it must not have the depth
+//! buffer attached. In a forward renderer, draw decals into the color target after
+//! the opaque geometry. In a deferred renderer, draw them into the G-buffer albedo
+//! target only (`GBuffer::albedo_view`, format `GBufferFormats::albedo`) with
+//! `GBuffer::depth_view` as depth, before the lighting pass.
+//!
+//! Decal colors are alpha blended; the decal texture alpha times [`Decal::color`]
+//! alpha is the opacity. The depth texture must be single-sampled.
+//!
+//! ## Example
+//! ```ignore
+//! decals.clear();
+//! decals.push(&bullet_hole_view, Decal { transform, color: [1.0; 4] });
+//! // Inside a pass writing the color target, without depth attachment
+//! decals.render(&mut render_manager, &mut pass, &depth_view, &DecalCamera { view_proj, inverse_view_proj }, target_format);
+//! ```
+use std::collections::HashMap;
+use wgpu::*;
+use crate::gpu_util;
+use crate::renderer::RenderManager;
+use crate::labels::namespaced;
+
+const DECAL_SHADER: &str = r#"
+struct DecalCamera {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+};
+
+struct Instance {
+    @location(0) model_0: vec4<f32>,
+    @location(1) model_1: vec4<f32>,
+    @location(2) model_2: vec4<f32>,
+    @location(3) model_3: vec4<f32>,
+    @location(4) inverse_0: vec4<f32>,
+    @location(5) inverse_1: vec4<f32>,
+    @location(6) inverse_2: vec4<f32>,
+    @location(7) inverse_3: vec4<f32>,
+    @location(8) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip: vec4<f32>,
+    @location(0) inverse_0: vec4<f32>,
+    @location(1) inverse_1: vec4<f32>,
+    @location(2) inverse_2: vec4<f32>,
+    @location(3) inverse_3: vec4<f32>,
+    @location(4) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var material_sampler: sampler;
+@group(0) @binding(1) var decal_texture: texture_2d<f32>;
+@group(1) @binding(0) var<uniform> camera: DecalCamera;
+@group(1) @binding(1) var scene_depth: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, instance: Instance) -> VertexOutput {
+    // 12 triangles of the unit box, corners encoded as bits (x = 1, y = 2, z = 4).
+    var corners = array<u32, 36>(
+        0u, 2u, 1u, 1u, 2u, 3u,
+        4u, 5u, 6u, 5u, 7u, 6u,
+        0u, 1u, 4u, 1u, 5u, 4u,
+        2u, 6u, 3u, 3u, 6u, 7u,
+        0u, 4u, 2u, 2u, 4u, 6u,
+        1u, 3u, 5u, 3u, 7u, 5u,
+    );
+    let c = corners[vertex];
+    let local = vec3<f32>(f32(c & 1u), f32((c >> 1u) & 1u), f32((c >> 2u) & 1u)) - 0.5;
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+
+    var out: VertexOutput;
+    out.clip = camera.view_proj * model * vec4<f32>(local, 1.0);
+    out.inverse_0 = instance.inverse_0;
+    out.inverse_1 = instance.inverse_1;
+    out.inverse_2 = instance.inverse_2;
+    out.inverse_3 = instance.inverse_3;
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(scene_depth));
+    let depth = textureLoad(scene_depth, vec2<i32>(in.clip.xy), 0);
+    let ndc = vec2<f32>(in.clip.x / size.x * 2.0 - 1.0, 1.0 - in.clip.y / size.y * 2.0);
+    let world = camera.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    let inverse_model = mat4x4<f32>(in.inverse_0, in.inverse_1, in.inverse_2, in.inverse_3);
+    let local = (inverse_model * vec4<f32>(world.xyz / world.w, 1.0)).xyz;
+
+    // Sample before discarding to stay in uniform control flow.
+    let uv = vec2<f32>(local.x + 0.5, 0.5 - local.z);
+    let color = textureSample(decal_texture, material_sampler, uv) * in.color;
+    if any(abs(local) > vec3<f32>(0.5)) {
+        discard;
+    }
+    return color;
+}
+"#;
+
+/// Size of one instance in the decal-box instance buffer.
+const INSTANCE_SIZE: u64 = size_of::<DecalInstance>() as u64;
+
+const INSTANCE_ATTRIBUTES: [VertexAttribute; 9] = wgpu::vertex_attr_array![
+    0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Float32x4,
+    4 => Float32x4, 5 => Float32x4, 6 => Float32x4, 7 => Float32x4,
+    8 => Float32x4,
+];
+
+/// A projected decal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decal {
+    /// Column-major transform of the unit box into world space. The texture is
+    /// projected along the box's local Y axis.
+    pub transform: [[f32; 4]; 4],
+    /// Tint multiplied with the decal texture.
+    pub color: [f32; 4],
+}
+
+/// Camera the decals are drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecalCamera {
+    /// `projection * view`, column-major.
+    pub view_proj: [[f32; 4]; 4],
+    /// Inverse of `view_proj`.
+    pub inverse_view_proj: [[f32; 4]; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalInstance {
+    model: [[f32; 4]; 4],
+    inverse: [[f32; 4]; 4],
+    color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalCameraUniform {
+    view_proj: [[f32; 4]; 4],
+    inverse_view_proj: [[f32; 4]; 4],
+}
+
+/// Decals sharing one texture.
+struct DecalBatch {
+    texture: TextureView,
+    instances: Vec<DecalInstance>,
+}
+
+/// Collects decals per texture and draws them onto the scene depth.
+pub struct DecalRenderer {
+    device: Device,
+    queue: Queue,
+    module: ShaderModule,
+    camera: Buffer,
+    scene_layout: BindGroupLayout,
+    /// Scene bind group and the depth view it was created for.
+    scene_bind_group: Option<(TextureView, BindGroup)>,
+    instances: Buffer,
+    instance_capacity: u64,
+    batches: Vec<DecalBatch>,
+    pipelines: HashMap<TextureFormat, RenderPipeline>,
+}
+
+impl DecalRenderer {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let scene_layout = gpu_util::bind_group_layout(device, "decal scene layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::VERTEX | ShaderStages::FRAGMENT),
+            gpu_util::texture_entry(1, ShaderStages::FRAGMENT, TextureSampleType::Depth, TextureViewDimension::D2),
+        ]);
+        let instance_capacity = 64;
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            module: gpu_util::shader(device, "decal shader", DECAL_SHADER),
+            camera: gpu_util::buffer(
+                device,
+                "decal camera",
+                size_of::<DecalCameraUniform>() as u64,
+                BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            ),
+            scene_layout,
+            scene_bind_group: None,
+            instances: instance_buffer(device, instance_capacity),
+            instance_capacity,
+            batches: Vec::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Add a decal showing `texture`. Decals are drawn in texture order of first use.
+    pub fn push(&mut self, texture: &TextureView, decal: Decal) {
+        let instance = DecalInstance {
+            model: decal.transform,
+            inverse: gpu_util::invert_affine(&decal.transform),
+            color: decal.color,
+        };
+        match self.batches.iter_mut().find(|b| &b.texture == texture) {
+            Some(batch) => batch.instances.push(instance),
+            None => self.batches.push(DecalBatch {
+                texture: texture.clone(),
+                instances: vec![instance],
+            }),
+        }
+    }
+
+    /// Remove all decals.
+    pub fn clear(&mut self) {
+        self.batches.clear();
+    }
+
+    /// Number of decals pushed since the last [`clear`](Self::clear).
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(|b| b.instances.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Upload all decals and draw them into a color target of `format`.
+    ///
+    /// `depth` is the single-sampled scene depth, which must not be attached to `pass`.
+    /// Uniforms and instances are written with `Queue::write_buffer`, so render once
+    /// per submission.
+    pub fn render(
+        &mut self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        depth: &TextureView,
+        camera: &DecalCamera,
+        format: TextureFormat,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+        let _span = trace_span!("decals", count = self.len());
+
+        let uniform = DecalCameraUniform {
+            view_proj: camera.view_proj,
+            inverse_view_proj: camera.inverse_view_proj,
+        };
+        self.queue.write_buffer(&self.camera, 0, bytemuck::bytes_of(&uniform));
+
+        let count = self.len() as u64;
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instances = instance_buffer(&self.device, self.instance_capacity);
+        }
+        let data: Vec<DecalInstance> = self.batches.iter().flat_map(|b| b.instances.iter().copied()).collect();
+        self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&data));
+
+        if self.scene_bind_group.as_ref().is_none_or(|(view, _)| view != depth) {
+            let bind_group = gpu_util::bind_group(&self.device, "decal scene", &self.scene_layout, &[
+                self.camera.as_entire_binding(),
+                BindingResource::TextureView(depth),
+            ]);
+            self.scene_bind_group = Some((depth.clone(), bind_group));
+        }
+
+        let first = &self.batches[0].texture;
+        let material_layout = manager.material_layout(&[first], &[]);
+        let pipeline = self.pipelines.entry(format).or_insert_with(|| {
+            let _span = trace_span!("decal_pipeline_miss");
+            create_decal_pipeline(&self.device, &self.module, &[&material_layout, &self.scene_layout], format)
+        });
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(1, &self.scene_bind_group.as_ref().unwrap().1, &[]);
+        pass.set_vertex_buffer(0, self.instances.slice(..));
+        let mut start = 0u32;
+        for batch in &self.batches {
+            let end = start + batch.instances.len() as u32;
+            pass.set_bind_group(0, &manager.material_bind_group(&[&batch.texture], &[]), &[]);
+            pass.draw(0..36, start..end);
+            start = end;
+        }
+    }
+}
+
+fn instance_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(device, "decal instances", capacity * INSTANCE_SIZE, BufferUsages::VERTEX | BufferUsages::COPY_DST)
+}
+
+fn create_decal_pipeline(device: &Device, module: &ShaderModule, layouts: &[&BindGroupLayout], format: TextureFormat) -> RenderPipeline {
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("decal pipeline layout")),
+        bind_group_layouts: layouts,
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("decal pipeline")),
+        layout: Some(&layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[VertexBufferLayout {
+                array_stride: INSTANCE_SIZE,
+                step_mode: VertexStepMode::Instance,
+                attributes: &INSTANCE_ATTRIBUTES,
+            }],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            // Back faces only, so decals still render with the camera inside the box.
+            cull_mode: Some(Face::Front),
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
@@ -0,0 +1,203 @@
+//! Parallel image decoding with serialized uploads (feature `decode`).
+//!
+//! Decoding PNG/JPEG files is CPU bound and easily dominates loading times.
+//! [`ImageBatch`] decodes all images of a batch on the rayon thread pool, while
+//! uploads stay on the thread that owns the queue and happen in small steps,
+//! so a loading screen can keep rendering and show [`LoadProgress`].
+//!
+//! ```ignore
+//! let mut batch = ImageBatch::from_paths(texture_paths);
+//! // every frame of the loading screen
+//! let progress = batch.upload_ready(&device, &queue);
+//! draw_progress_bar(progress.fraction());
+//! if progress.is_finished() {
+//!     let textures = batch.finish(&device, &queue);
+//! }
+//! ```
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use rayon::prelude::*;
+use wgpu::{Device, Queue};
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+/// Where an image of a batch comes from.
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    /// Read and decode a file.
+    Path(PathBuf),
+    /// Decode encoded bytes (e.g. from an archive), with a label for the texture.
+    Memory { label: String, bytes: Vec<u8> },
+}
+
+impl ImageSource {
+    fn label(&self) -> String {
+        match self {
+            ImageSource::Path(path) => path.display().to_string(),
+            ImageSource::Memory { label, .. } => label.clone(),
+        }
+    }
+}
+
+/// An image that could not be read or decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    /// Path or label of the image.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to decode {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Progress of an [`ImageBatch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoadProgress {
+    /// Number of images in the batch.
+    pub total: usize,
+    /// Images decoded by the worker threads, including failed ones.
+    pub decoded: usize,
+    /// Images uploaded to the GPU.
+    pub uploaded: usize,
+    /// Images that failed to decode.
+    pub failed: usize,
+}
+
+impl LoadProgress {
+    /// Fraction of the batch that is done, from `0.0` to `1.0`.
+    ///
+    /// Decoding and uploading count half each.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        let done = self.decoded + self.uploaded + self.failed;
+        done as f32 / (2 * self.total) as f32
+    }
+
+    /// Returns `true` once every image was uploaded or failed.
+    pub fn is_finished(&self) -> bool {
+        self.uploaded + self.failed == self.total
+    }
+}
+
+struct DecodedImage {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+/// A batch of images decoded in parallel and uploaded as `Rgba8UnormSrgb` textures.
+///
+/// Decoding starts on creation. Results are returned in the order of the sources.
+pub struct ImageBatch {
+    labels: Vec<String>,
+    receiver: Receiver<(usize, Result<DecodedImage, DecodeError>)>,
+    decoded: Arc<AtomicUsize>,
+    results: Vec<Option<Result<LoadedTexture, DecodeError>>>,
+    uploaded: usize,
+    failed: usize,
+}
+
+impl ImageBatch {
+    /// Start decoding `sources` on the rayon thread pool.
+    pub fn new(sources: Vec<ImageSource>) -> Self {
+        let labels: Vec<String> = sources.iter().map(ImageSource::label).collect();
+        let (sender, receiver) = mpsc::channel();
+        let decoded = Arc::new(AtomicUsize::new(0));
+
+        let counter = decoded.clone();
+        rayon::spawn(move || {
+            sources.into_par_iter().enumerate().for_each_with(sender, |sender, (i, source)| {
+                let image = decode(source);
+                counter.fetch_add(1, Ordering::Relaxed);
+                // The batch may have been dropped, the result is not needed anymore then.
+                let _ = sender.send((i, image));
+            });
+        });
+
+        Self {
+            results: labels.iter().map(|_| None).collect(),
+            labels,
+            receiver,
+            decoded,
+            uploaded: 0,
+            failed: 0,
+        }
+    }
+
+    /// Start decoding the given files.
+    pub fn from_paths(paths: Vec<PathBuf>) -> Self {
+        Self::new(paths.into_iter().map(ImageSource::Path).collect())
+    }
+
+    /// Current progress without uploading anything.
+    pub fn progress(&self) -> LoadProgress {
+        LoadProgress {
+            total: self.labels.len(),
+            decoded: self.decoded.load(Ordering::Relaxed),
+            uploaded: self.uploaded,
+            failed: self.failed,
+        }
+    }
+
+    /// Upload every image decoded so far, without waiting for the rest.
+    ///
+    /// Call once per frame from the thread owning the queue.
+    pub fn upload_ready(&mut self, device: &Device, queue: &Queue) -> LoadProgress {
+        while let Ok((index, image)) = self.receiver.try_recv() {
+            self.upload(device, queue, index, image);
+        }
+        self.progress()
+    }
+
+    /// Wait for all images and upload them. Results are in source order.
+    pub fn finish(mut self, device: &Device, queue: &Queue) -> Vec<Result<LoadedTexture, DecodeError>> {
+        while self.uploaded + self.failed < self.labels.len() {
+            let (index, image) = self.receiver.recv().expect("image decoding threads exited unexpectedly");
+            self.upload(device, queue, index, image);
+        }
+        self.results
+            .into_iter()
+            .map(|r| r.expect("every image has a result once the batch finished"))
+            .collect()
+    }
+
+    fn upload(&mut self, device: &Device, queue: &Queue, index: usize, image: Result<DecodedImage, DecodeError>) {
+        let result = image.map(|image| {
+            let _span = trace_span!("upload_decoded_image", label = %self.labels[index], width = image.width, height = image.height);
+            let request = TextureRequest::rgba8(self.labels[index].clone(), image.width, image.height, image.rgba);
+            create_texture(device, queue, &request)
+        });
+        if result.is_ok() {
+            self.uploaded += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.results[index] = Some(result);
+    }
+}
+
+fn decode(source: ImageSource) -> Result<DecodedImage, DecodeError> {
+    let label = source.label();
+    let _span = trace_span!("decode_image", label = %label);
+    let image = match source {
+        ImageSource::Path(path) => image::open(&path),
+        ImageSource::Memory { bytes, .. } => image::load_from_memory(&bytes),
+    }
+    .map_err(|e| DecodeError { label, message: e.to_string() })?;
+
+    let rgba = image.into_rgba8();
+    Ok(DecodedImage {
+        width: rgba.width(),
+        height: rgba.height(),
+        rgba: rgba.into_raw(),
+    })
+}
@@ -0,0 +1,211 @@
+//! Resolving multisampled depth buffers.
+//!
+//! wgpu resolves MSAA color attachments but not depth, while SSAO, soft particles,
+//! decals and the `HiZBuffer` read a single-sampled depth
+//! texture. [`DepthResolver`] runs a fullscreen pass that reads every sample of a
+//! `texture_depth_multisampled_2d` and writes one depth per pixel, into a depth target
+//! (through `frag_depth`) or an `R32Float` color target:
+//! ```ignore
+//! let mut resolver = DepthResolver::new(&device);
+//! // after the MSAA scene pass
+//! resolver.resolve(&mut encoder, &msaa_depth_view, &resolved_depth_view, DepthResolveMode::Nearest { reversed_z: true });
+//! // resolved_depth_view is a plain `texture_depth_2d` for SSAO and soft particles
+//! ```
+//! The source view must only cover the depth aspect, create views of combined
+//! depth-stencil formats with `aspect: TextureAspect::DepthOnly`. Materials and
+//! [`BindGroupBuilder::depth_texture`](crate::bind_group_builder::BindGroupBuilder::depth_texture)
+//! bind multisampled depth unresolved as a non-filterable `texture_depth_multisampled_2d`
+//! for shaders that read the samples themselves.
+use std::collections::HashMap;
+use wgpu::*;
+use crate::gpu_util;
+use crate::labels::namespaced;
+
+const RESOLVE_SHADER: &str = r#"
+@group(0) @binding(0) var t_depth: texture_depth_multisampled_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn combine(a: f32, b: f32) -> f32 {
+    return COMBINE;
+}
+
+fn resolve(coord: vec2<i32>) -> f32 {
+    var depth = textureLoad(t_depth, coord, 0);
+    for (var i = 1u; i < textureNumSamples(t_depth); i++) {
+        depth = combine(depth, textureLoad(t_depth, coord, i));
+    }
+    return depth;
+}
+
+@fragment
+fn fs_depth(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    return resolve(vec2<i32>(position.xy));
+}
+
+@fragment
+fn fs_color(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(resolve(vec2<i32>(position.xy)), 0.0, 0.0, 1.0);
+}
+"#;
+
+/// How the samples of a pixel are combined into one depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepthResolveMode {
+    /// The depth closest to the camera: the minimum, or the maximum with reversed Z.
+    /// Keeps thin foreground geometry, the usual choice for SSAO and soft particles.
+    Nearest { reversed_z: bool },
+    /// The depth farthest from the camera, conservative for occlusion culling.
+    Farthest { reversed_z: bool },
+    /// Sample 0 only, matching what a non-MSAA depth pre-pass would have written.
+    FirstSample,
+}
+
+impl DepthResolveMode {
+    fn combine(self) -> &'static str {
+        match self {
+            DepthResolveMode::Nearest { reversed_z: false } | DepthResolveMode::Farthest { reversed_z: true } => "min(a, b)",
+            DepthResolveMode::Nearest { reversed_z: true } | DepthResolveMode::Farthest { reversed_z: false } => "max(a, b)",
+            DepthResolveMode::FirstSample => "a",
+        }
+    }
+}
+
+/// Resolves multisampled depth into single-sampled targets, see the [module docs](self).
+pub struct DepthResolver {
+    device: Device,
+    layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    /// Pipelines per combine function and target format.
+    pipelines: HashMap<(&'static str, TextureFormat), RenderPipeline>,
+    /// Bind group of the last resolved source, sources change only on resize.
+    bind_group: Option<(TextureView, BindGroup)>,
+}
+
+impl DepthResolver {
+    pub fn new(device: &Device) -> Self {
+        let layout = gpu_util::bind_group_layout(device, "depth resolve layout", &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Depth,
+                view_dimension: TextureViewDimension::D2,
+                multisampled: true,
+            },
+            count: None,
+        }]);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(&namespaced("depth resolve pipeline layout")),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        Self {
+            device: device.clone(),
+            layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            bind_group: None,
+        }
+    }
+
+    /// Resolve the multisampled depth `source` into `target`, which has the same size
+    /// and is either a depth format or a single-channel float color format like `R32Float`.
+    ///
+    /// ## Panics
+    /// Panics if `source` is not multisampled or `target` is.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder, source: &TextureView, target: &TextureView, mode: DepthResolveMode) {
+        assert!(source.texture().sample_count() > 1, "depth resolve source is not multisampled");
+        assert!(target.texture().sample_count() == 1, "depth resolve target is multisampled");
+        let format = target.texture().format();
+        let is_depth = format.has_depth_aspect();
+        self.pipeline(mode, format);
+        let bind_group = self.bind_group(source);
+        let pipeline = &self.pipelines[&(mode.combine(), format)];
+
+        let color = [Some(RenderPassColorAttachment {
+            view: target,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::TRANSPARENT),
+                store: StoreOp::Store,
+            },
+        })];
+        let depth_stencil_attachment = is_depth.then(|| RenderPassDepthStencilAttachment {
+            view: target,
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(0.0),
+                store: StoreOp::Store,
+            }),
+            stencil_ops: None,
+        });
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(&namespaced("depth resolve pass")),
+            color_attachments: if is_depth { &[] } else { &color[..] },
+            depth_stencil_attachment,
+            ..Default::default()
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn pipeline(&mut self, mode: DepthResolveMode, format: TextureFormat) {
+        let key = (mode.combine(), format);
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+        let _span = trace_span!("depth_resolve_pipeline_miss", format = ?format);
+        let source = RESOLVE_SHADER.replace("COMBINE", mode.combine());
+        let module = gpu_util::shader(&self.device, "depth resolve shader", &source);
+        let is_depth = format.has_depth_aspect();
+        let color_targets = [Some(ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        })];
+        let pipeline = self.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(&namespaced("depth resolve pipeline")),
+            layout: Some(&self.pipeline_layout),
+            vertex: VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: Some(if is_depth { "fs_depth" } else { "fs_color" }),
+                targets: if is_depth { &[] } else { &color_targets[..] },
+                compilation_options: Default::default(),
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: is_depth.then(|| DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+        self.pipelines.insert(key, pipeline);
+    }
+
+    fn bind_group(&mut self, source: &TextureView) -> BindGroup {
+        match &self.bind_group {
+            Some((view, bind_group)) if view == source => bind_group.clone(),
+            _ => {
+                let bind_group = gpu_util::bind_group(&self.device, "depth resolve bind group", &self.layout, &[BindingResource::TextureView(source)]);
+                self.bind_group = Some((source.clone(), bind_group.clone()));
+                bind_group
+            }
+        }
+    }
+}
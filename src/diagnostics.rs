@@ -0,0 +1,375 @@
+// diagnostics.rs
+//! Introspection of the crate's internal caches.
+//!
+//! Every cache wraps its values in [`Tracked`], which records when an entry
+//! was created and last used. [`RenderManager::cache_entries`](crate::renderer::RenderManager::cache_entries)
+//! flattens all caches into a list of [`CacheEntryInfo`] for debug UIs and tooling.
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Which internal cache an entry belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CacheKind {
+    /// Render pipelines created by the [`PipelineCache`](crate::pipelines::PipelineCache).
+    RenderPipeline,
+    /// Uniform bind group layouts, keyed by buffer count.
+    UniformLayout,
+    /// Material bind group layouts, keyed by texture views.
+    MaterialLayout,
+    /// Material bind groups (sampler + textures + optional shadow).
+    MaterialBindGroup,
+    /// Material bind groups keyed by a user [`CacheKey`](crate::cache_key::CacheKey).
+    KeyedMaterialBindGroup,
+    /// Uniform bind groups, keyed by buffers.
+    UniformBindGroup,
+    /// Compute pipelines created by the [`ComputeSystem`](crate::compute_system::ComputeSystem).
+    ComputePipeline,
+    /// Compute bind group layouts, keyed by their entries.
+    ComputeLayout,
+    /// Compute bind groups (textures, storage textures, buffers), keyed by resources.
+    ComputeBindGroup,
+    /// Layouts created by the [`BindGroupBuilder`](crate::bind_group_builder::BindGroupBuilder), keyed by their entries.
+    BuilderLayout,
+    /// Bind groups created by the [`BindGroupBuilder`](crate::bind_group_builder::BindGroupBuilder), keyed by resources.
+    BuilderBindGroup,
+    /// Procedurally generated textures.
+    ProceduralTexture,
+    /// Fullscreen debug pipelines.
+    FullscreenPipeline,
+    /// Fullscreen debug bind groups.
+    FullscreenBindGroup,
+}
+
+impl CacheKind {
+    /// All cache kinds, in display order.
+    pub const ALL: [CacheKind; 14] = [
+        CacheKind::RenderPipeline,
+        CacheKind::UniformLayout,
+        CacheKind::MaterialLayout,
+        CacheKind::MaterialBindGroup,
+        CacheKind::KeyedMaterialBindGroup,
+        CacheKind::UniformBindGroup,
+        CacheKind::ComputePipeline,
+        CacheKind::ComputeLayout,
+        CacheKind::ComputeBindGroup,
+        CacheKind::BuilderLayout,
+        CacheKind::BuilderBindGroup,
+        CacheKind::ProceduralTexture,
+        CacheKind::FullscreenPipeline,
+        CacheKind::FullscreenBindGroup,
+    ];
+
+    /// Human readable name of the cache.
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheKind::RenderPipeline => "render pipelines",
+            CacheKind::UniformLayout => "uniform layouts",
+            CacheKind::MaterialLayout => "material layouts",
+            CacheKind::MaterialBindGroup => "material bind groups",
+            CacheKind::KeyedMaterialBindGroup => "keyed material bind groups",
+            CacheKind::UniformBindGroup => "uniform bind groups",
+            CacheKind::ComputePipeline => "compute pipelines",
+            CacheKind::ComputeLayout => "compute layouts",
+            CacheKind::ComputeBindGroup => "compute bind groups",
+            CacheKind::BuilderLayout => "builder layouts",
+            CacheKind::BuilderBindGroup => "builder bind groups",
+            CacheKind::ProceduralTexture => "procedural textures",
+            CacheKind::FullscreenPipeline => "fullscreen pipelines",
+            CacheKind::FullscreenBindGroup => "fullscreen bind groups",
+        }
+    }
+}
+
+impl fmt::Display for CacheKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Snapshot of a single cached resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CacheEntryInfo {
+    /// Cache the entry lives in.
+    pub kind: CacheKind,
+    /// Stable identifier of the entry within its cache (hash of its key).
+    ///
+    /// Pass it to [`RenderManager::evict_cache_entry`](crate::renderer::RenderManager::evict_cache_entry).
+    pub id: u64,
+    /// Short human readable description, usually the shader path or binding shape.
+    pub label: String,
+    /// Full description of the cache key, for inspection.
+    pub details: String,
+    /// Frame the entry was created in.
+    pub created_frame: u64,
+    /// Frame the entry was last requested in.
+    pub last_used_frame: u64,
+    /// Number of lookups that hit this entry after its creation.
+    pub hits: u64,
+    /// Estimated GPU memory owned by the entry in bytes, `0` if negligible or unknown.
+    pub memory_bytes: u64,
+}
+
+/// Snapshot of all caches, as written by [`RenderManager::dump_state`](crate::renderer::RenderManager::dump_state).
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheStateDump {
+    /// Version of this crate that produced the dump.
+    pub crate_version: String,
+    /// Frame the dump was taken in.
+    pub frame: u64,
+    /// Shader defines active at the time of the dump, sorted by name.
+    pub defines: std::collections::BTreeMap<String, bool>,
+    /// Per-cache totals, in [`CacheKind::ALL`] order. Empty caches are omitted.
+    pub caches: Vec<CacheSummary>,
+    /// Every cached entry, grouped by cache and sorted by label.
+    pub entries: Vec<CacheEntryInfo>,
+}
+
+/// Totals of a single cache inside a [`CacheStateDump`].
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheSummary {
+    /// The cache.
+    pub kind: CacheKind,
+    /// Number of entries.
+    pub count: usize,
+    /// Sum of the entries' estimated memory in bytes.
+    pub memory_bytes: u64,
+}
+
+#[cfg(feature = "serde")]
+impl CacheStateDump {
+    pub(crate) fn new(frame: u64, defines: &HashMap<String, bool>, mut entries: Vec<CacheEntryInfo>) -> Self {
+        entries.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.label.cmp(&b.label)).then(a.id.cmp(&b.id)));
+        let caches = CacheKind::ALL
+            .iter()
+            .filter_map(|&kind| {
+                let (count, memory_bytes) = entries
+                    .iter()
+                    .filter(|e| e.kind == kind)
+                    .fold((0, 0), |(count, memory), e| (count + 1, memory + e.memory_bytes));
+                (count > 0).then_some(CacheSummary { kind, count, memory_bytes })
+            })
+            .collect();
+
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            frame,
+            defines: defines.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            caches,
+            entries,
+        }
+    }
+}
+
+/// Cached value plus bookkeeping about its usage.
+pub(crate) struct Tracked<T> {
+    pub(crate) value: T,
+    pub(crate) label: String,
+    pub(crate) created_frame: u64,
+    pub(crate) last_used_frame: u64,
+    pub(crate) hits: u64,
+}
+
+impl<T> Tracked<T> {
+    pub(crate) fn new(value: T, frame: u64, label: String) -> Self {
+        Self {
+            value,
+            label,
+            created_frame: frame,
+            last_used_frame: frame,
+            hits: 0,
+        }
+    }
+
+    /// Record a cache hit and return the value.
+    pub(crate) fn touch(&mut self, frame: u64) -> &T {
+        self.last_used_frame = frame;
+        self.hits += 1;
+        &self.value
+    }
+
+    pub(crate) fn info(&self, kind: CacheKind, id: u64, details: String, memory_bytes: u64) -> CacheEntryInfo {
+        CacheEntryInfo {
+            kind,
+            id,
+            label: self.label.clone(),
+            details,
+            created_frame: self.created_frame,
+            last_used_frame: self.last_used_frame,
+            hits: self.hits,
+            memory_bytes,
+        }
+    }
+}
+
+/// [`Tracked`] for caches shared between threads.
+///
+/// Usage counters are atomics, so a cache hit only needs shared access to the entry.
+pub(crate) struct SharedTracked<T> {
+    pub(crate) value: T,
+    pub(crate) label: String,
+    pub(crate) created_frame: u64,
+    last_used_frame: AtomicU64,
+    hits: AtomicU64,
+}
+
+impl<T> SharedTracked<T> {
+    pub(crate) fn new(value: T, frame: u64, label: String) -> Self {
+        Self {
+            value,
+            label,
+            created_frame: frame,
+            last_used_frame: AtomicU64::new(frame),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a cache hit and return the value.
+    pub(crate) fn touch(&self, frame: u64) -> &T {
+        self.last_used_frame.fetch_max(frame, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        &self.value
+    }
+
+    pub(crate) fn info(&self, kind: CacheKind, id: u64, details: String, memory_bytes: u64) -> CacheEntryInfo {
+        CacheEntryInfo {
+            kind,
+            id,
+            label: self.label.clone(),
+            details,
+            created_frame: self.created_frame,
+            last_used_frame: self.last_used_frame.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            memory_bytes,
+        }
+    }
+}
+
+/// Identifier of a cache key, as exposed in [`CacheEntryInfo::id`].
+pub(crate) fn entry_id<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Remove the entry whose key hashes to `id`, see [`entry_id`].
+pub(crate) fn evict_by_id<K: Hash + Eq, V>(map: &mut HashMap<K, V>, id: u64) -> bool {
+    let before = map.len();
+    map.retain(|key, _| entry_id(key) != id);
+    map.len() != before
+}
+
+/// Thresholds used by the stale entry detector.
+///
+/// See [`RenderManager::enable_stale_entry_detection`](crate::renderer::RenderManager::enable_stale_entry_detection).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleEntryConfig {
+    /// Report entries that were never hit again this many frames after their creation.
+    ///
+    /// A bind group that is created every frame but never reused usually means its
+    /// cache key contains something that changes every frame (a fresh view, a new buffer).
+    pub never_reused_after: u64,
+    /// Report entries that have not been used for this many frames.
+    pub idle_after: u64,
+    /// How often (in frames) the caches are scanned.
+    pub check_interval: u64,
+}
+
+impl Default for StaleEntryConfig {
+    /// Never reused after 600 frames, idle after 5000 frames, checked every 300 frames.
+    fn default() -> Self {
+        Self {
+            never_reused_after: 600,
+            idle_after: 5000,
+            check_interval: 300,
+        }
+    }
+}
+
+/// Why an entry was reported as stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleReason {
+    /// The entry was created but never requested again.
+    NeverReused,
+    /// The entry was used, but not within the configured number of frames.
+    Idle {
+        /// Frames since the entry was last used.
+        frames: u64,
+    },
+}
+
+/// A cache entry flagged by the stale entry detector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleEntry {
+    /// The offending entry.
+    pub entry: CacheEntryInfo,
+    /// Why it was flagged.
+    pub reason: StaleReason,
+}
+
+/// Callback receiving the stale entries found by a scan.
+pub type StaleEntryCallback = Box<dyn FnMut(&[StaleEntry]) + Send>;
+
+/// Periodically scans cache entries for ones that are never reused or long idle.
+pub(crate) struct StaleEntryDetector {
+    config: StaleEntryConfig,
+    callback: StaleEntryCallback,
+    /// Reported entries and their `last_used_frame` at report time, so each
+    /// entry is only reported again if it was used in between.
+    reported: HashMap<(CacheKind, u64), u64>,
+}
+
+impl StaleEntryDetector {
+    pub(crate) fn new(config: StaleEntryConfig, callback: StaleEntryCallback) -> Self {
+        Self {
+            config,
+            callback,
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Whether a scan is due in `frame`.
+    pub(crate) fn is_due(&self, frame: u64) -> bool {
+        self.config.check_interval > 0 && frame.is_multiple_of(self.config.check_interval)
+    }
+
+    /// Scan `entries` and invoke the callback with newly stale ones.
+    pub(crate) fn check(&mut self, frame: u64, entries: Vec<CacheEntryInfo>) {
+        let mut stale = Vec::new();
+        let mut alive = HashMap::with_capacity(entries.len());
+
+        for entry in entries {
+            let key = (entry.kind, entry.id);
+            alive.insert(key, entry.last_used_frame);
+
+            let age = frame.saturating_sub(entry.created_frame);
+            let idle = frame.saturating_sub(entry.last_used_frame);
+            let reason = if entry.hits == 0 && age >= self.config.never_reused_after {
+                StaleReason::NeverReused
+            } else if idle >= self.config.idle_after {
+                StaleReason::Idle { frames: idle }
+            } else {
+                continue;
+            };
+
+            if self.reported.get(&key) == Some(&entry.last_used_frame) {
+                continue;
+            }
+            self.reported.insert(key, entry.last_used_frame);
+            stale.push(StaleEntry { entry, reason });
+        }
+
+        // Forget entries that were evicted or used again since they were reported.
+        self.reported.retain(|key, last_used| alive.get(key) == Some(last_used));
+
+        if !stale.is_empty() {
+            trace_event!(count = stale.len(), frame, "stale cache entries detected");
+            (self.callback)(&stale);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! On-disk cache of processed asset data, keyed by the content of its sources.
+//!
+//! Generating mips, compressing or transcoding textures, tessellating meshes and
+//! reflecting shaders can take longer than the rest of startup combined, and the
+//! results only change when the source does. A [`DiskCache`] stores such results as
+//! files named after a [`CacheKey`], a 128-bit hash of the source bytes and everything
+//! else that affects the result, so a second launch reads them back instead:
+//! ```ignore
+//! let cache = DiskCache::new(cache_dir.join("processed"));
+//! let source = std::fs::read("shaders/water.wgsl")?;
+//! let key = CacheKey::new("shader reflection v2").with(&source).with(defines_string.as_bytes());
+//! let metadata = cache.get_or_insert_with(key, || reflect(&source));
+//! ```
+//! `bake_image_cached` (feature `bake`) and the
+//! `AssetServer` use it for textures.
+//!
+//! Writes go to a temporary file that is renamed into place, so a crash never leaves
+//! a truncated entry. Errors are treated as misses: a cache that cannot be read or
+//! written only costs the processing time.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use bytemuck::Pod;
+
+const FNV_OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// 128-bit FNV-1a hash identifying a cache entry. Stable across runs, platforms and
+/// compiler versions, unlike `std::hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CacheKey(u128);
+
+impl CacheKey {
+    /// Start a key for one kind of processing, e.g. `"bc7 mips v1"`. Bump the name
+    /// when the processing changes to invalidate old entries.
+    pub fn new(kind: &str) -> Self {
+        Self(FNV_OFFSET).with(kind.as_bytes())
+    }
+
+    /// Mix in source bytes or options. Each part is length-prefixed, so `["ab", "c"]`
+    /// and `["a", "bc"]` give different keys.
+    pub fn with(self, bytes: &[u8]) -> Self {
+        let mut hash = self.0;
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            hash ^= *byte as u128;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        Self(hash)
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+fn collect<T: Pod>(bytes: &[u8]) -> Vec<T> {
+    let mut values = vec![T::zeroed(); bytes.len() / size_of::<T>()];
+    bytemuck::cast_slice_mut(&mut values).copy_from_slice(bytes);
+    values
+}
+
+/// A directory of processed data, one file per [`CacheKey`].
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    directory: PathBuf,
+}
+
+impl DiskCache {
+    /// Use `directory`, created on the first write.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self { directory: directory.into() }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn path(&self, key: CacheKey) -> PathBuf {
+        self.directory.join(format!("{}.bin", key))
+    }
+
+    pub fn contains(&self, key: CacheKey) -> bool {
+        self.path(key).is_file()
+    }
+
+    /// The cached bytes, or `None` on a miss.
+    pub fn get(&self, key: CacheKey) -> Option<Vec<u8>> {
+        let bytes = std::fs::read(self.path(key)).ok();
+        trace_event!(key = %key, hit = bytes.is_some(), "disk cache lookup");
+        bytes
+    }
+
+    /// Store `bytes` under `key`, replacing an older entry.
+    pub fn put(&self, key: CacheKey, bytes: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&temporary, bytes)?;
+        std::fs::rename(&temporary, &path)
+    }
+
+    /// The cached bytes, or the result of `process`, which is stored for the next run. A
+    /// failed write is traced as a warning and the result returned regardless.
+    pub fn get_or_insert_with(&self, key: CacheKey, process: impl FnOnce() -> Vec<u8>) -> Vec<u8> {
+        if let Some(bytes) = self.get(key) {
+            return bytes;
+        }
+        let bytes = process();
+        if let Err(error) = self.put(key, &bytes) {
+            trace_warn!(%key, %error, "failed to write disk cache entry");
+            let _ = error;
+        }
+        bytes
+    }
+
+    /// Vertices and indices of a processed mesh, e.g. a tessellated path or a simplified LOD.
+    pub fn get_mesh<V: Pod>(&self, key: CacheKey) -> Option<(Vec<V>, Vec<u32>)> {
+        let bytes = self.get(key)?;
+        let vertex_bytes = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?) as usize;
+        let vertices = bytes.get(8..8 + vertex_bytes)?;
+        let indices = bytes.get(8 + vertex_bytes..)?;
+        if vertices.len() % size_of::<V>() != 0 || indices.len() % 4 != 0 {
+            return None;
+        }
+        // Copied, since the file buffer is not aligned for `V`.
+        Some((collect(vertices), collect(indices)))
+    }
+
+    pub fn put_mesh<V: Pod>(&self, key: CacheKey, vertices: &[V], indices: &[u32]) -> std::io::Result<()> {
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(vertices);
+        let mut bytes = Vec::with_capacity(8 + vertex_bytes.len() + indices.len() * 4);
+        bytes.extend_from_slice(&(vertex_bytes.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(vertex_bytes);
+        bytes.extend_from_slice(bytemuck::cast_slice(indices));
+        self.put(key, &bytes)
+    }
+
+    pub fn remove(&self, key: CacheKey) -> bool {
+        std::fs::remove_file(self.path(key)).is_ok()
+    }
+
+    /// Delete all entries. Returns the number deleted.
+    pub fn clear(&self) -> usize {
+        self.entries().filter(|(path, _)| std::fs::remove_file(path).is_ok()).count()
+    }
+
+    /// Total size of all entries in bytes.
+    pub fn size_on_disk(&self) -> u64 {
+        self.entries().map(|(_, size)| size).sum()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (PathBuf, u64)> {
+        std::fs::read_dir(&self.directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "bin"))
+            .map(|entry| (entry.path(), entry.metadata().map_or(0, |metadata| metadata.len())))
+    }
+}
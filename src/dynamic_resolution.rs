@@ -0,0 +1,438 @@
+//! Dynamic resolution scaling.
+//!
+//! A [`DynamicResolution`] owns the internal color target the 3D scene renders into,
+//! sized to the output size times the render scale, and upscales it to the output in
+//! one fullscreen draw. The scale is set directly or adapted every frame from measured
+//! GPU frame times (e.g. the sum of the [`GpuProfiler`](crate::profiler::GpuProfiler)
+//! pass timings) towards a frame time budget; changes are quantized and rate limited so
+//! targets are not recreated every frame. Whenever the internal size changes, the
+//! scene's other screen-sized targets follow through
+//! [`resize_targets`](DynamicResolution::resize_targets).
+//!
+//! ## Frame flow
+//! ```ignore
+//! let mut resolution = DynamicResolution::new(&device, &queue, TextureFormat::Rgba16Float, surface.format(), surface.size(), UpscaleFilter::Sharpened { sharpness: 0.5 })
+//!     .with_adaptive(ScaleSettings { target_frame_ms: 16.0, ..Default::default() });
+//! // Every frame
+//! if resolution.update(gpu_frame_ms) {
+//!     resolution.resize_targets(&mut [&mut gbuffer, &mut hiz]);
+//! }
+//! {
+//!     let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!         color_attachments: &[Some(resolution.color_attachment(Color::BLACK))],
+//!         ..Default::default()
+//!     });
+//!     // Draw the scene at resolution.internal_size()
+//! }
+//! // Inside the pass writing the swapchain
+//! resolution.upscale(&mut pass);
+//! // UI at full resolution
+//! ```
+use wgpu::*;
+use crate::gpu_util;
+use crate::surface::ResizeTarget;
+use crate::labels::namespaced;
+
+const UPSCALE_SHADER: &str = r#"
+struct UpscaleParams {
+    source_size: vec2<f32>,
+    sharpness: f32,
+    _pad: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    var uvs = array<vec2<f32>, 4>(
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+    );
+    var out: VertexOutput;
+    out.position = vec4<f32>(positions[idx], 0.0, 1.0);
+    out.uv = uvs[idx];
+    return out;
+}
+
+@group(0) @binding(0) var s_source: sampler;
+@group(0) @binding(1) var t_source: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> params: UpscaleParams;
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// Catmull-Rom filter in 9 bilinear taps.
+fn catmull_rom(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / params.source_size;
+    let position = uv * params.source_size;
+    let center = floor(position - 0.5) + 0.5;
+    let f = position - center;
+    let w0 = f * (-0.5 + f * (1.0 - 0.5 * f));
+    let w1 = 1.0 + f * f * (-2.5 + 1.5 * f);
+    let w2 = f * (0.5 + f * (2.0 - 1.5 * f));
+    let w3 = f * f * (-0.5 + 0.5 * f);
+    let w12 = w1 + w2;
+    let uv0 = (center - 1.0) * texel;
+    let uv12 = (center + w2 / w12) * texel;
+    let uv3 = (center + 2.0) * texel;
+
+    var color = tap(vec2<f32>(uv0.x, uv0.y)) * w0.x * w0.y;
+    color += tap(vec2<f32>(uv12.x, uv0.y)) * w12.x * w0.y;
+    color += tap(vec2<f32>(uv3.x, uv0.y)) * w3.x * w0.y;
+    color += tap(vec2<f32>(uv0.x, uv12.y)) * w0.x * w12.y;
+    color += tap(vec2<f32>(uv12.x, uv12.y)) * w12.x * w12.y;
+    color += tap(vec2<f32>(uv3.x, uv12.y)) * w3.x * w12.y;
+    color += tap(vec2<f32>(uv0.x, uv3.y)) * w0.x * w3.y;
+    color += tap(vec2<f32>(uv12.x, uv3.y)) * w12.x * w3.y;
+    color += tap(vec2<f32>(uv3.x, uv3.y)) * w3.x * w3.y;
+    return max(color, vec3<f32>(0.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let alpha = textureSampleLevel(t_source, s_source, in.uv, 0.0).a;
+    if !SHARPEN {
+        return vec4<f32>(tap(in.uv), alpha);
+    }
+
+    // Contrast-adaptive sharpening of the upscaled color against its source neighbours:
+    // strong where local contrast is low, backing off near edges to avoid ringing.
+    let texel = 1.0 / params.source_size;
+    let c = catmull_rom(in.uv);
+    let n = tap(in.uv + vec2<f32>(0.0, -texel.y));
+    let s = tap(in.uv + vec2<f32>(0.0, texel.y));
+    let e = tap(in.uv + vec2<f32>(texel.x, 0.0));
+    let w = tap(in.uv + vec2<f32>(-texel.x, 0.0));
+    let lo = min(c, min(min(n, s), min(e, w)));
+    let hi = max(c, max(max(n, s), max(e, w)));
+    let amount = sqrt(clamp(min(lo, 1.0 - hi) / max(hi, vec3<f32>(1e-4)), vec3<f32>(0.0), vec3<f32>(1.0)));
+    let weight = amount * (-1.0 / mix(8.0, 5.0, params.sharpness));
+    let sharpened = (c + (n + s + e + w) * weight) / (1.0 + 4.0 * weight);
+    return vec4<f32>(max(sharpened, vec3<f32>(0.0)), alpha);
+}
+"#;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct UpscaleParams {
+    source_size: [f32; 2],
+    sharpness: f32,
+    _pad: f32,
+}
+
+/// How the internal target is scaled to the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpscaleFilter {
+    /// One bilinear tap; cheapest, soft below 75% scale.
+    Bilinear,
+    /// Catmull-Rom upscale followed by contrast-adaptive sharpening, in the spirit of FSR 1.
+    Sharpened {
+        /// 0 (subtle) to 1 (strong).
+        sharpness: f32,
+    },
+}
+
+/// Frame time budget and limits of adaptive scaling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleSettings {
+    /// GPU frame time to aim for in milliseconds, e.g. 16 for 60 fps with a little headroom.
+    pub target_frame_ms: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Granularity of the scale; smaller changes are ignored.
+    pub step: f32,
+    /// Frames to wait after a change before the next one, letting timings settle.
+    pub cooldown_frames: u32,
+}
+
+impl Default for ScaleSettings {
+    fn default() -> Self {
+        Self {
+            target_frame_ms: 16.0,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            step: 0.05,
+            cooldown_frames: 30,
+        }
+    }
+}
+
+struct ScaledTarget {
+    texture: Texture,
+    view: TextureView,
+    bind_group: BindGroup,
+}
+
+/// Scaled internal color target with an upscaling pass and optional adaptive scale.
+pub struct DynamicResolution {
+    device: Device,
+    queue: Queue,
+    format: TextureFormat,
+    output_size: (u32, u32),
+    scale: f32,
+    adaptive: Option<ScaleSettings>,
+    cooldown: u32,
+    filter: UpscaleFilter,
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    params: Buffer,
+    target: ScaledTarget,
+}
+
+impl DynamicResolution {
+    /// Render the scene into `format` targets and upscale into `output_format` targets of
+    /// `output_size`, starting at full scale.
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        output_format: TextureFormat,
+        output_size: (u32, u32),
+        filter: UpscaleFilter,
+    ) -> Self {
+        let sharpen = matches!(filter, UpscaleFilter::Sharpened { .. });
+        let module = gpu_util::shader(device, "upscale shader", &format!("const SHARPEN: bool = {sharpen};\n{UPSCALE_SHADER}"));
+        let layout = gpu_util::bind_group_layout(device, "upscale layout", &[
+            gpu_util::sampler_entry(0, ShaderStages::FRAGMENT, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(1, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: true }, TextureViewDimension::D2),
+            gpu_util::uniform_entry(2, ShaderStages::FRAGMENT),
+        ]);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(&namespaced("upscale pipeline layout")),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(&namespaced("upscale pipeline")),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(ColorTargetState {
+                    format: output_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+        let sampler = gpu_util::linear_sampler(device, "upscale sampler");
+        let params = gpu_util::buffer(device, "upscale params", size_of::<UpscaleParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let output_size = (output_size.0.max(1), output_size.1.max(1));
+        let target = create_target(device, &layout, &sampler, &params, format, output_size);
+
+        let resolution = Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            format,
+            output_size,
+            scale: 1.0,
+            adaptive: None,
+            cooldown: 0,
+            filter,
+            layout,
+            pipeline,
+            sampler,
+            params,
+            target,
+        };
+        resolution.write_params();
+        resolution
+    }
+
+    /// Adapt the scale to GPU frame times passed to [`update`](Self::update).
+    pub fn with_adaptive(mut self, settings: ScaleSettings) -> Self {
+        self.set_adaptive(Some(settings));
+        self
+    }
+
+    /// Turn adaptive scaling on or off; the current scale is kept.
+    pub fn set_adaptive(&mut self, settings: Option<ScaleSettings>) {
+        self.adaptive = settings;
+        self.cooldown = 0;
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Set the render scale, clamped to `0.25..=2.0`; above 1.0 supersamples.
+    ///
+    /// Returns `true` if the internal size changed.
+    pub fn set_scale(&mut self, scale: f32) -> bool {
+        self.scale = scale.clamp(0.25, 2.0);
+        self.recreate_if_resized()
+    }
+
+    /// Output size, e.g. after the window was resized.
+    ///
+    /// Returns `true` if the internal size changed.
+    pub fn set_output_size(&mut self, width: u32, height: u32) -> bool {
+        self.output_size = (width.max(1), height.max(1));
+        self.recreate_if_resized()
+    }
+
+    /// Feed the GPU time of the last measured frame. Pixel count, and with it most of
+    /// the frame time, grows with the square of the scale, which the adjustment
+    /// accounts for.
+    ///
+    /// Returns `true` if the internal size changed; does nothing without adaptive scaling.
+    pub fn update(&mut self, gpu_frame_ms: f32) -> bool {
+        let Some(settings) = self.adaptive else {
+            return false;
+        };
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return false;
+        }
+        if gpu_frame_ms <= 0.0 {
+            return false;
+        }
+        let step = settings.step.max(0.01);
+        let desired = self.scale * (settings.target_frame_ms / gpu_frame_ms).sqrt();
+        let desired = ((desired / step).round() * step).max(settings.min_scale).min(settings.max_scale);
+        if (desired - self.scale).abs() < step * 0.5 {
+            return false;
+        }
+        trace_event!(from = self.scale, to = desired, gpu_frame_ms, "render scale changed");
+        self.cooldown = settings.cooldown_frames;
+        self.set_scale(desired)
+    }
+
+    /// Size of the internal target: the output size times the scale.
+    pub fn internal_size(&self) -> (u32, u32) {
+        let scaled = |size: u32| ((size as f32 * self.scale).round() as u32).max(1);
+        (scaled(self.output_size.0), scaled(self.output_size.1))
+    }
+
+    pub fn output_size(&self) -> (u32, u32) {
+        self.output_size
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn filter(&self) -> UpscaleFilter {
+        self.filter
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.target.texture
+    }
+
+    /// The internal target to render the scene into.
+    pub fn view(&self) -> &TextureView {
+        &self.target.view
+    }
+
+    /// Color attachment of the internal target, cleared to `clear`.
+    pub fn color_attachment(&self, clear: Color) -> RenderPassColorAttachment<'_> {
+        RenderPassColorAttachment {
+            view: &self.target.view,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(clear),
+                store: StoreOp::Store,
+            },
+        }
+    }
+
+    /// Resize the scene's other screen-sized targets to the internal size.
+    pub fn resize_targets(&self, targets: &mut [&mut dyn ResizeTarget]) {
+        let (width, height) = self.internal_size();
+        for target in targets {
+            target.resize(width, height);
+        }
+    }
+
+    /// Draw the internal target scaled to the output into `pass`.
+    pub fn upscale(&self, pass: &mut RenderPass) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.target.bind_group, &[]);
+        pass.draw(0..4, 0..1);
+    }
+
+    fn recreate_if_resized(&mut self) -> bool {
+        let size = self.internal_size();
+        if size == (self.target.texture.width(), self.target.texture.height()) {
+            return false;
+        }
+        self.target = create_target(&self.device, &self.layout, &self.sampler, &self.params, self.format, size);
+        self.write_params();
+        true
+    }
+
+    /// Written with `Queue::write_buffer`, so the scale changes at most once per submission.
+    fn write_params(&self) {
+        let sharpness = match self.filter {
+            UpscaleFilter::Bilinear => 0.0,
+            UpscaleFilter::Sharpened { sharpness } => sharpness.clamp(0.0, 1.0),
+        };
+        let params = UpscaleParams {
+            source_size: [self.target.texture.width() as f32, self.target.texture.height() as f32],
+            sharpness,
+            _pad: 0.0,
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+    }
+}
+
+fn create_target(
+    device: &Device,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+    params: &Buffer,
+    format: TextureFormat,
+    (width, height): (u32, u32),
+) -> ScaledTarget {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced("dynamic resolution target")),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let bind_group = gpu_util::bind_group(device, "upscale", layout, &[
+        BindingResource::Sampler(sampler),
+        BindingResource::TextureView(&view),
+        params.as_entire_binding(),
+    ]);
+    ScaledTarget { texture, view, bind_group }
+}
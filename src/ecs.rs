@@ -0,0 +1,53 @@
+//! ECS adapters (features `bevy_ecs` and `hecs`).
+//!
+//! Entities with a [`RenderMesh`], a [`RenderMaterial`] and a [`RenderTransform`] are
+//! extracted into an [`InstanceBatcher`] once per frame, replacing the query loop every
+//! ECS-based renderer otherwise writes by hand. The components are plain data, so game
+//! systems update them like any other component:
+//! ```ignore
+//! world.spawn((RenderMesh(cube), RenderMaterial(brick.clone()), RenderTransform(model)));
+//! // Every frame
+//! batcher.clear();
+//! extract_hecs(&world, &mut batcher);
+//! batcher.upload();
+//! // Inside a render pass, with a pipeline from `InstanceBatcher::pipeline_options`
+//! batcher.draw(&mut pass, &meshes, &options);
+//! ```
+use wgpu::BindGroup;
+use crate::instancing::InstanceBatcher;
+use crate::meshes::MeshHandle;
+
+/// Mesh drawn for an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct RenderMesh(pub MeshHandle);
+
+/// Material bind group of an entity, bound at group 0 by the batcher.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct RenderMaterial(pub BindGroup);
+
+/// World matrix of an entity, column-major.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct RenderTransform(pub [[f32; 4]; 4]);
+
+/// Submit every renderable entity of a `bevy_ecs` world to `batcher`.
+#[cfg(feature = "bevy_ecs")]
+pub fn extract_bevy(world: &mut bevy_ecs::world::World, batcher: &mut InstanceBatcher) {
+    let _span = trace_span!("ecs_extract_bevy");
+    let mut query = world.query::<(&RenderMesh, &RenderMaterial, &RenderTransform)>();
+    for (mesh, material, transform) in query.iter(world) {
+        batcher.submit(mesh.0, &material.0, transform.0);
+    }
+}
+
+/// Submit every renderable entity of a `hecs` world to `batcher`.
+#[cfg(feature = "hecs")]
+pub fn extract_hecs(world: &hecs::World, batcher: &mut InstanceBatcher) {
+    let _span = trace_span!("ecs_extract_hecs");
+    let mut query = world.query::<(&RenderMesh, &RenderMaterial, &RenderTransform)>();
+    for (_, (mesh, material, transform)) in query.iter() {
+        batcher.submit(mesh.0, &material.0, transform.0);
+    }
+}
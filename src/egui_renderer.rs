@@ -0,0 +1,385 @@
+//! egui rendering without `egui-wgpu` (feature `egui`).
+//!
+//! An [`EguiRenderer`] draws the tessellated output of an egui frame. egui's font atlas
+//! and image textures are created with [`create_texture`](crate::textures::create_texture)
+//! and bound through the [`RenderManager`] material bind group cache, like every other
+//! material; textures the app renders itself are [registered](EguiRenderer::register_texture)
+//! as user textures, e.g. to show a render target inside an egui image.
+//! ```ignore
+//! let mut egui_renderer = EguiRenderer::new(&device, &queue, surface.ui_format());
+//! // Every frame
+//! let output = egui_ctx.run(raw_input, |ctx| overlay.show(ctx, &mut render_manager));
+//! let primitives = egui_ctx.tessellate(output.shapes, output.pixels_per_point);
+//! egui_renderer.prepare(&mut render_manager, &output.textures_delta, &primitives, [width, height], output.pixels_per_point);
+//! // Inside a render pass writing the target, after the scene
+//! egui_renderer.render(&mut pass);
+//! ```
+//!
+//! egui blends in gamma space: with the default [`SurfaceOptions`](crate::surface::SurfaceOptions)
+//! the UI view is linear-encoded, which matches egui. On sRGB-encoded views the colors
+//! are converted in the shader. Textures are sampled with the material sampler (linear,
+//! repeating); egui's per-texture filtering options are not applied. Paint callbacks
+//! are skipped.
+use std::collections::HashMap;
+use std::ops::Range;
+use egui::epaint::{ImageData, Primitive, TextureId, TexturesDelta};
+use egui::ClippedPrimitive;
+use wgpu::*;
+use crate::gpu_util;
+use crate::renderer::RenderManager;
+use crate::textures::{create_texture, TextureRequest};
+use crate::labels::namespaced;
+
+const EGUI_SHADER: &str = r#"
+struct Screen {
+    size_points: vec2<f32>,
+    _pad: vec2<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var s_egui: sampler;
+@group(0) @binding(1) var t_egui: texture_2d<f32>;
+@group(1) @binding(0) var<uniform> screen: Screen;
+
+fn linear_from_gamma(rgb: vec3<f32>) -> vec3<f32> {
+    let lo = rgb / 12.92;
+    let hi = pow((rgb + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(hi, lo, rgb < vec3<f32>(0.04045));
+}
+
+fn gamma_from_linear(rgb: vec3<f32>) -> vec3<f32> {
+    let lo = rgb * 12.92;
+    let hi = 1.055 * pow(rgb, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(hi, lo, rgb < vec3<f32>(0.0031308));
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4<f32>(in.position / screen.size_points * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Textures are sRGB and sampled as linear; egui works in gamma space.
+    let texel = textureSample(t_egui, s_egui, in.uv);
+    let color = in.color * vec4<f32>(gamma_from_linear(texel.rgb), texel.a);
+    if SRGB_TARGET {
+        return vec4<f32>(linear_from_gamma(color.rgb), color.a);
+    }
+    return color;
+}
+"#;
+
+const VERTEX_ATTRIBUTES: [VertexAttribute; 3] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Unorm8x4];
+
+const VERTEX_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<EguiVertex>() as u64,
+    step_mode: VertexStepMode::Vertex,
+    attributes: &VERTEX_ATTRIBUTES,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct EguiVertex {
+    /// Position in points.
+    position: [f32; 2],
+    uv: [f32; 2],
+    /// Premultiplied gamma-space RGBA.
+    color: [u8; 4],
+}
+
+struct EguiTexture {
+    /// `None` for user textures, which are never updated by egui.
+    texture: Option<Texture>,
+    view: TextureView,
+}
+
+/// One clipped mesh.
+struct EguiDraw {
+    texture: TextureId,
+    /// Scissor rectangle in pixels.
+    clip: [u32; 4],
+    indices: Range<u32>,
+    base_vertex: i32,
+}
+
+/// Renders egui output with the crate's texture creation and material bind groups.
+pub struct EguiRenderer {
+    device: Device,
+    queue: Queue,
+    format: TextureFormat,
+    pipeline: Option<RenderPipeline>,
+    screen: Buffer,
+    screen_bind_group: BindGroup,
+    textures: HashMap<TextureId, EguiTexture>,
+    /// Freed by egui during the last frame, removed at the next prepare.
+    pending_free: Vec<TextureId>,
+    next_user_texture: u64,
+    draws: Vec<EguiDraw>,
+    bind_groups: HashMap<TextureId, BindGroup>,
+    vertices: Buffer,
+    vertex_capacity: u64,
+    indices: Buffer,
+    index_capacity: u64,
+    target_size: [u32; 2],
+}
+
+impl EguiRenderer {
+    /// Create a renderer drawing into targets of `target_format`; sRGB formats convert
+    /// egui's gamma-space colors.
+    pub fn new(device: &Device, queue: &Queue, target_format: TextureFormat) -> Self {
+        let screen = gpu_util::buffer(device, "egui screen", 16, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let screen_layout = screen_layout(device);
+        let screen_bind_group = gpu_util::bind_group(device, "egui screen", &screen_layout, &[screen.as_entire_binding()]);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            format: target_format,
+            pipeline: None,
+            screen,
+            screen_bind_group,
+            textures: HashMap::new(),
+            pending_free: Vec::new(),
+            next_user_texture: 0,
+            draws: Vec::new(),
+            bind_groups: HashMap::new(),
+            vertices: geometry_buffer(device, "egui vertices", 1024 * VERTEX_LAYOUT.array_stride, BufferUsages::VERTEX),
+            vertex_capacity: 1024,
+            indices: geometry_buffer(device, "egui indices", 1024 * 4, BufferUsages::INDEX),
+            index_capacity: 1024,
+            target_size: [1, 1],
+        }
+    }
+
+    /// Make `view`, a filterable 2D float texture, available to egui images. Register
+    /// views of sRGB textures: they are converted to gamma space like egui's own.
+    pub fn register_texture(&mut self, view: &TextureView) -> TextureId {
+        let id = TextureId::User(self.next_user_texture);
+        self.next_user_texture += 1;
+        self.textures.insert(id, EguiTexture { texture: None, view: view.clone() });
+        id
+    }
+
+    /// Point a registered user texture at another view, e.g. after a resize.
+    pub fn update_user_texture(&mut self, id: TextureId, view: &TextureView) {
+        if let Some(texture) = self.textures.get_mut(&id) {
+            texture.view = view.clone();
+        }
+    }
+
+    pub fn free_user_texture(&mut self, id: TextureId) {
+        self.textures.remove(&id);
+    }
+
+    /// Apply egui's texture changes, upload the meshes of `primitives` and resolve their
+    /// bind groups for a target of `size` pixels at `pixels_per_point`.
+    ///
+    /// Written with `Queue::write_buffer`/`write_texture`, so prepare once per submission.
+    pub fn prepare(
+        &mut self,
+        render_manager: &mut RenderManager,
+        textures_delta: &TexturesDelta,
+        primitives: &[ClippedPrimitive],
+        size: [u32; 2],
+        pixels_per_point: f32,
+    ) {
+        let _span = trace_span!("egui_prepare", primitives = primitives.len());
+        for id in self.pending_free.drain(..) {
+            self.textures.remove(&id);
+        }
+        for (id, delta) in &textures_delta.set {
+            self.set_texture(*id, delta);
+        }
+        // egui frees textures after the frame that last uses them.
+        self.pending_free.extend_from_slice(&textures_delta.free);
+
+        self.target_size = size.map(|s| s.max(1));
+        let screen = [
+            self.target_size[0] as f32 / pixels_per_point,
+            self.target_size[1] as f32 / pixels_per_point,
+            0.0,
+            0.0,
+        ];
+        self.queue.write_buffer(&self.screen, 0, bytemuck::cast_slice(&screen));
+
+        self.draws.clear();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for ClippedPrimitive { clip_rect, primitive } in primitives {
+            let Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            if mesh.indices.is_empty() || !self.textures.contains_key(&mesh.texture_id) {
+                continue;
+            }
+            let min_x = (clip_rect.min.x * pixels_per_point).round().clamp(0.0, self.target_size[0] as f32) as u32;
+            let min_y = (clip_rect.min.y * pixels_per_point).round().clamp(0.0, self.target_size[1] as f32) as u32;
+            let max_x = (clip_rect.max.x * pixels_per_point).round().clamp(min_x as f32, self.target_size[0] as f32) as u32;
+            let max_y = (clip_rect.max.y * pixels_per_point).round().clamp(min_y as f32, self.target_size[1] as f32) as u32;
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+            self.draws.push(EguiDraw {
+                texture: mesh.texture_id,
+                clip: [min_x, min_y, max_x - min_x, max_y - min_y],
+                indices: indices.len() as u32..(indices.len() + mesh.indices.len()) as u32,
+                base_vertex: vertices.len() as i32,
+            });
+            vertices.extend(mesh.vertices.iter().map(|vertex| EguiVertex {
+                position: [vertex.pos.x, vertex.pos.y],
+                uv: [vertex.uv.x, vertex.uv.y],
+                color: vertex.color.to_array(),
+            }));
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if vertices.len() as u64 > self.vertex_capacity {
+            self.vertex_capacity = (vertices.len() as u64).next_power_of_two();
+            self.vertices = geometry_buffer(&self.device, "egui vertices", self.vertex_capacity * VERTEX_LAYOUT.array_stride, BufferUsages::VERTEX);
+        }
+        if indices.len() as u64 > self.index_capacity {
+            self.index_capacity = (indices.len() as u64).next_power_of_two();
+            self.indices = geometry_buffer(&self.device, "egui indices", self.index_capacity * 4, BufferUsages::INDEX);
+        }
+        if !vertices.is_empty() {
+            self.queue.write_buffer(&self.vertices, 0, bytemuck::cast_slice(&vertices));
+            // Index data must be a multiple of 4 bytes, which u32 indices always are.
+            self.queue.write_buffer(&self.indices, 0, bytemuck::cast_slice(&indices));
+        }
+
+        self.bind_groups.clear();
+        for draw in &self.draws {
+            if self.bind_groups.contains_key(&draw.texture) {
+                continue;
+            }
+            let view = &self.textures[&draw.texture].view;
+            if self.pipeline.is_none() {
+                let material_layout = render_manager.material_layout(&[view], &[]);
+                self.pipeline = Some(create_pipeline(&self.device, &material_layout, self.format));
+            }
+            self.bind_groups.insert(draw.texture, render_manager.material_bind_group(&[view], &[]));
+        }
+    }
+
+    /// Draw the meshes of the last [`prepare`](Self::prepare). Leaves the scissor
+    /// rectangle covering the whole target.
+    pub fn render(&self, pass: &mut RenderPass) {
+        let Some(pipeline) = &self.pipeline else {
+            return;
+        };
+        if self.draws.is_empty() {
+            return;
+        }
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(1, &self.screen_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.set_index_buffer(self.indices.slice(..), IndexFormat::Uint32);
+        let mut bound = None;
+        for draw in &self.draws {
+            if bound != Some(draw.texture) {
+                pass.set_bind_group(0, &self.bind_groups[&draw.texture], &[]);
+                bound = Some(draw.texture);
+            }
+            let [x, y, width, height] = draw.clip;
+            pass.set_scissor_rect(x, y, width, height);
+            pass.draw_indexed(draw.indices.clone(), draw.base_vertex, 0..1);
+        }
+        pass.set_scissor_rect(0, 0, self.target_size[0], self.target_size[1]);
+    }
+
+    /// Create or patch a managed texture from an egui image delta.
+    fn set_texture(&mut self, id: TextureId, delta: &egui::epaint::ImageDelta) {
+        let ImageData::Color(image) = &delta.image;
+        let [width, height] = image.size.map(|s| s as u32);
+        let pixels: Vec<u8> = image.pixels.iter().flat_map(|pixel| pixel.to_array()).collect();
+        match (delta.pos, self.textures.get(&id).and_then(|texture| texture.texture.as_ref())) {
+            (Some([x, y]), Some(texture)) => {
+                self.queue.write_texture(
+                    TexelCopyTextureInfo {
+                        texture,
+                        mip_level: 0,
+                        origin: Origin3d { x: x as u32, y: y as u32, z: 0 },
+                        aspect: TextureAspect::All,
+                    },
+                    &pixels,
+                    TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(width * 4),
+                        rows_per_image: Some(height),
+                    },
+                    Extent3d { width, height, depth_or_array_layers: 1 },
+                );
+            }
+            (Some(_), None) => trace_event!("egui patched a texture that does not exist"),
+            (None, _) => {
+                let label = match id {
+                    TextureId::Managed(n) => format!("egui texture {n}"),
+                    TextureId::User(n) => format!("egui user texture {n}"),
+                };
+                let loaded = create_texture(&self.device, &self.queue, &TextureRequest::rgba8(label, width, height, pixels));
+                self.textures.insert(id, EguiTexture {
+                    texture: Some(loaded.texture),
+                    view: loaded.view,
+                });
+            }
+        }
+    }
+}
+
+fn screen_layout(device: &Device) -> BindGroupLayout {
+    gpu_util::bind_group_layout(device, "egui screen layout", &[gpu_util::uniform_entry(0, ShaderStages::VERTEX)])
+}
+
+fn create_pipeline(device: &Device, material_layout: &BindGroupLayout, format: TextureFormat) -> RenderPipeline {
+    let source = format!("const SRGB_TARGET: bool = {};\n{EGUI_SHADER}", format.is_srgb());
+    let module = gpu_util::shader(device, "egui shader", &source);
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("egui pipeline layout")),
+        bind_group_layouts: &[material_layout, &screen_layout(device)],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("egui pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            buffers: &[VERTEX_LAYOUT],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+fn geometry_buffer(device: &Device, label: &str, size: u64, usage: BufferUsages) -> Buffer {
+    gpu_util::buffer(device, label, size, usage | BufferUsages::COPY_DST)
+}
@@ -0,0 +1,81 @@
+//! The error type of fallible resource creation.
+//!
+//! Creation paths have a `try_` variant returning [`CrmError`], e.g.
+//! [`RenderManager::try_render_with_textures`](crate::renderer::RenderManager::try_render_with_textures)
+//! or [`RenderManager::try_compute`](crate::renderer::RenderManager::try_compute). The plain
+//! variants panic with the same message, which suits startup code and tests. Frame
+//! code that must survive bad content, like a user-supplied shader or an HDR texture
+//! on a device without float filtering, matches on the error and falls back:
+//! ```ignore
+//! match render_manager.try_render_with_textures(&[&view], shader, &options, &[&camera], &mut pass) {
+//!     Ok(()) => pass.draw(0..3, 0..1),
+//!     Err(CrmError::UnsupportedFormat { .. } | CrmError::MissingFeature { .. }) => {
+//!         render_manager.render_with_textures(&[&fallback_view], shader, &options, &[&camera], &mut pass);
+//!         pass.draw(0..3, 0..1);
+//!     }
+//!     Err(e) => eprintln!("{e}"),
+//! }
+//! ```
+use std::fmt;
+use std::path::PathBuf;
+use wgpu::{Features, TextureFormat};
+
+/// Why a resource could not be created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrmError {
+    /// The format cannot be used the way it was asked for, e.g. sampled or as a storage
+    /// texture, on this device.
+    UnsupportedFormat {
+        format: TextureFormat,
+        /// What the format was needed for.
+        usage: String,
+    },
+    /// The device lacks features the resource needs.
+    MissingFeature {
+        features: Features,
+        /// What needed them.
+        needed_for: String,
+    },
+    /// A size or count is above a device limit.
+    LimitExceeded {
+        /// Name of the field in `wgpu::Limits`, or of the API limit, e.g. `dynamic offset`.
+        limit: &'static str,
+        requested: u64,
+        max: u64,
+    },
+    /// A buffer is smaller than the `min_binding_size` of the layout entry it is bound to.
+    BufferTooSmall {
+        /// Which binding the buffer was bound to.
+        usage: String,
+        size: u64,
+        min_binding_size: u64,
+    },
+    /// A bind group builder call does not apply to the binding it was made on, e.g. a
+    /// `min_binding_size` on a texture.
+    InvalidBinding(String),
+    /// The device rejected the resource.
+    Device(String),
+    /// A file could not be read.
+    Io { path: PathBuf, message: String },
+    /// A shader failed to preprocess or parse.
+    Shader { path: PathBuf, message: String },
+}
+
+impl fmt::Display for CrmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrmError::UnsupportedFormat { format, usage } => write!(f, "texture format {:?} is not supported for {}", format, usage),
+            CrmError::MissingFeature { features, needed_for } => write!(f, "{} needs device features {:?}", needed_for, features),
+            CrmError::LimitExceeded { limit, requested, max } => write!(f, "{} of {} exceeds the device limit {}", limit, requested, max),
+            CrmError::BufferTooSmall { usage, size, min_binding_size } => {
+                write!(f, "buffer of {} bytes bound to {} is smaller than its min_binding_size of {}", size, usage, min_binding_size)
+            }
+            CrmError::InvalidBinding(message) => write!(f, "invalid binding: {}", message),
+            CrmError::Device(message) => write!(f, "device error: {}", message),
+            CrmError::Io { path, message } => write!(f, "failed to read {}: {}", path.display(), message),
+            CrmError::Shader { path, message } => write!(f, "invalid shader {}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for CrmError {}
@@ -0,0 +1,446 @@
+//! Automatic exposure from a luminance histogram, and a tonemapping pass using it.
+//!
+//! [`AutoExposure::update`] records two compute passes: the first bins the log
+//! luminance of every pixel of the HDR target into a 256-bin histogram, the second
+//! averages the histogram, blends the result with the previous frames
+//! (exponential adaptation) and writes the exposure into a small buffer.
+//!
+//! That buffer is bound as a uniform by [`Tonemapper`], so the exposure never
+//! round-trips through the CPU:
+//! ```ignore
+//! auto_exposure.update(&mut encoder, &hdr_view, dt);
+//! // Inside the final render pass
+//! tonemapper.render(&mut pass, &hdr_view, &auto_exposure);
+//! ```
+//!
+//! With [`TonemapOutput::Hdr`] the tonemapper keeps highlights above paper white and
+//! writes linear scRGB (1.0 = 80 nits) for an `Rgba16Float` HDR swapchain, see
+//! [`SurfaceOptions::hdr_output`](crate::surface::SurfaceOptions::hdr_output).
+//!
+//! ## Exposure uniform
+//! ```wgsl
+//! struct Exposure {
+//!     luminance: f32, // adapted average scene luminance
+//!     exposure: f32,  // multiplier applied before tonemapping
+//! };
+//! ```
+use wgpu::util::DeviceExt;
+use wgpu::*;
+use crate::gpu_util;
+use crate::labels::namespaced;
+
+const HISTOGRAM_BINS: u32 = 256;
+
+const EXPOSURE_COMMON: &str = r#"
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    time_delta: f32,
+    adaptation_time: f32,
+    exposure_compensation: f32,
+    pixel_count: u32,
+    _pad0: u32,
+    _pad1: u32,
+};
+
+struct Exposure {
+    luminance: f32,
+    exposure: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+"#;
+
+const HISTOGRAM_SHADER: &str = r#"
+@group(0) @binding(0) var<uniform> params: ExposureParams;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2) var hdr: texture_2d<f32>;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+fn luminance_bin(luminance: f32) -> u32 {
+    // Bin 0 collects (near) black pixels, which are ignored by the average.
+    if luminance < 0.0001 {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    atomicStore(&local_bins[local], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(hdr);
+    if id.x < size.x && id.y < size.y {
+        let color = textureLoad(hdr, vec2<i32>(id.xy), 0).rgb;
+        let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+        atomicAdd(&local_bins[luminance_bin(luminance)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local], atomicLoad(&local_bins[local]));
+}
+"#;
+
+const AVERAGE_SHADER: &str = r#"
+@group(0) @binding(0) var<uniform> params: ExposureParams;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2) var<storage, read_write> state: Exposure;
+
+var<workgroup> weighted: array<u32, 256>;
+
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(local_invocation_index) local: u32) {
+    let count = atomicLoad(&histogram[local]);
+    weighted[local] = count * local;
+    // Reset for the next frame.
+    atomicStore(&histogram[local], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride >>= 1u) {
+        if local < stride {
+            weighted[local] += weighted[local + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if local == 0u {
+        // `count` is the number of black pixels here.
+        let lit_pixels = max(f32(params.pixel_count) - f32(count), 1.0);
+        let average_bin = f32(weighted[0]) / lit_pixels - 1.0;
+        let average_log = average_bin / 254.0 * params.log_luminance_range + params.min_log_luminance;
+        let target_luminance = exp2(average_log);
+
+        let blend = 1.0 - exp(-params.time_delta / max(params.adaptation_time, 0.0001));
+        let luminance = state.luminance + (target_luminance - state.luminance) * blend;
+        state.luminance = luminance;
+        state.exposure = exp2(params.exposure_compensation) * 0.18 / max(luminance, 0.0001);
+    }
+}
+"#;
+
+const TONEMAP_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    var uvs = array<vec2<f32>, 4>(
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+    );
+    var out: VertexOutput;
+    out.position = vec4<f32>(positions[idx], 0.0, 1.0);
+    out.uv = uvs[idx];
+    return out;
+}
+
+@group(0) @binding(0) var s_hdr: sampler;
+@group(0) @binding(1) var t_hdr: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> exposure: Exposure;
+
+// Narkowicz ACES fit.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Extended Reinhard on luminance, reaching 1.0 at `peak` (relative to paper white).
+fn reinhard_peak(x: vec3<f32>, peak: f32) -> vec3<f32> {
+    let luminance = dot(x, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let mapped = luminance * (1.0 + luminance / (peak * peak)) / (1.0 + luminance);
+    return x * (mapped / max(luminance, 1e-5));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.uv);
+    let color = hdr.rgb * exposure.exposure;
+    if HDR_OUTPUT {
+        // scRGB: 1.0 is 80 nits, paper white sits at PAPER_WHITE_SCALE.
+        return vec4<f32>(reinhard_peak(color, PEAK_RELATIVE) * PEAK_RELATIVE * PAPER_WHITE_SCALE, hdr.a);
+    }
+    return vec4<f32>(aces(color), hdr.a);
+}
+"#;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    time_delta: f32,
+    adaptation_time: f32,
+    exposure_compensation: f32,
+    pixel_count: u32,
+    _pad: [u32; 2],
+}
+
+/// Tuning of [`AutoExposure`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposureSettings {
+    /// Darkest luminance (log2) the histogram distinguishes.
+    pub min_log_luminance: f32,
+    /// Brightest luminance (log2) the histogram distinguishes.
+    pub max_log_luminance: f32,
+    /// Time constant of the adaptation in seconds. Larger values adapt slower.
+    pub adaptation_time: f32,
+    /// Exposure bias in EV (stops), applied on top of the metered exposure.
+    pub exposure_compensation: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            adaptation_time: 1.1,
+            exposure_compensation: 0.0,
+        }
+    }
+}
+
+/// Histogram-based eye adaptation running entirely on the GPU.
+pub struct AutoExposure {
+    device: Device,
+    queue: Queue,
+    settings: AutoExposureSettings,
+    params: Buffer,
+    histogram: Buffer,
+    exposure: Buffer,
+    histogram_layout: BindGroupLayout,
+    histogram_pipeline: ComputePipeline,
+    average_pipeline: ComputePipeline,
+    average_bind_group: BindGroup,
+    /// Histogram bind group and the HDR view it was created for.
+    histogram_bind_group: Option<(TextureView, BindGroup)>,
+}
+
+impl AutoExposure {
+    pub fn new(device: &Device, queue: &Queue, settings: AutoExposureSettings) -> Self {
+        let histogram_module = gpu_util::shader(device, "exposure histogram shader", &format!("{}{}", EXPOSURE_COMMON, HISTOGRAM_SHADER));
+        let histogram_layout = gpu_util::bind_group_layout(device, "exposure histogram layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, false),
+            gpu_util::texture_entry(2, ShaderStages::COMPUTE, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+        ]);
+        let histogram_pipeline = gpu_util::compute_pipeline(device, "exposure histogram", &histogram_module, "main", &[&histogram_layout]);
+
+        let average_module = gpu_util::shader(device, "exposure average shader", &format!("{}{}", EXPOSURE_COMMON, AVERAGE_SHADER));
+        let average_layout = gpu_util::bind_group_layout(device, "exposure average layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, false),
+            gpu_util::storage_entry(2, ShaderStages::COMPUTE, false),
+        ]);
+        let average_pipeline = gpu_util::compute_pipeline(device, "exposure average", &average_module, "main", &[&average_layout]);
+
+        let params = gpu_util::buffer(device, "exposure params", size_of::<ExposureParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let histogram = gpu_util::buffer(device, "luminance histogram", HISTOGRAM_BINS as u64 * 4, BufferUsages::STORAGE);
+        // Start adapted to a mid-grey scene.
+        let exposure = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some(&namespaced("exposure")),
+            contents: bytemuck::cast_slice(&[0.18f32, 1.0, 0.0, 0.0]),
+            usage: BufferUsages::STORAGE | BufferUsages::UNIFORM,
+        });
+        let average_bind_group = gpu_util::bind_group(device, "exposure average", &average_layout, &[
+            params.as_entire_binding(),
+            histogram.as_entire_binding(),
+            exposure.as_entire_binding(),
+        ]);
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            settings,
+            params,
+            histogram,
+            exposure,
+            histogram_layout,
+            histogram_pipeline,
+            average_pipeline,
+            average_bind_group,
+            histogram_bind_group: None,
+        }
+    }
+
+    pub fn settings(&self) -> &AutoExposureSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: AutoExposureSettings) {
+        self.settings = settings;
+    }
+
+    /// Record metering `hdr` and adapting the exposure by `dt` seconds.
+    ///
+    /// `hdr` must be a single-sampled float texture (e.g. `Rgba16Float`).
+    pub fn update(&mut self, encoder: &mut CommandEncoder, hdr: &TextureView, dt: f32) {
+        let size = hdr.texture().size();
+        let params = ExposureParams {
+            min_log_luminance: self.settings.min_log_luminance,
+            log_luminance_range: (self.settings.max_log_luminance - self.settings.min_log_luminance).max(0.001),
+            time_delta: dt,
+            adaptation_time: self.settings.adaptation_time,
+            exposure_compensation: self.settings.exposure_compensation,
+            pixel_count: size.width * size.height,
+            _pad: [0; 2],
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+
+        if self.histogram_bind_group.as_ref().is_none_or(|(view, _)| view != hdr) {
+            let bind_group = gpu_util::bind_group(&self.device, "exposure histogram", &self.histogram_layout, &[
+                self.params.as_entire_binding(),
+                self.histogram.as_entire_binding(),
+                BindingResource::TextureView(hdr),
+            ]);
+            self.histogram_bind_group = Some((hdr.clone(), bind_group));
+        }
+        let (_, histogram_bind_group) = self.histogram_bind_group.as_ref().unwrap();
+
+        gpu_util::dispatch(encoder, "exposure histogram", &self.histogram_pipeline, &[histogram_bind_group], [
+            size.width.div_ceil(16),
+            size.height.div_ceil(16),
+            1,
+        ]);
+        gpu_util::dispatch(encoder, "exposure average", &self.average_pipeline, &[&self.average_bind_group], [1, 1, 1]);
+    }
+
+    /// The `Exposure` buffer, usable as uniform in your own passes.
+    pub fn exposure_buffer(&self) -> &Buffer {
+        &self.exposure
+    }
+}
+
+/// What a [`Tonemapper`] writes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TonemapOutput {
+    /// ACES filmic curve into `0..1`, for SDR targets.
+    Sdr,
+    /// Linear scRGB for an `Rgba16Float` HDR swapchain.
+    Hdr {
+        /// Brightness of diffuse white in nits, e.g. 200.
+        paper_white_nits: f32,
+        /// Peak brightness of the display in nits; highlights roll off towards it.
+        max_nits: f32,
+    },
+}
+
+/// Fullscreen tonemapping of an HDR texture, scaled by an [`AutoExposure`].
+pub struct Tonemapper {
+    device: Device,
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    /// Bind group and the (HDR view, exposure buffer) it was created for.
+    bind_group: Option<(TextureView, Buffer, BindGroup)>,
+    output: TonemapOutput,
+}
+
+impl Tonemapper {
+    /// Create an SDR tonemapper writing into targets of `target_format`.
+    pub fn new(device: &Device, target_format: TextureFormat) -> Self {
+        Self::with_output(device, target_format, TonemapOutput::Sdr)
+    }
+
+    /// Create a tonemapper for `output`, writing into targets of `target_format`.
+    pub fn with_output(device: &Device, target_format: TextureFormat, output: TonemapOutput) -> Self {
+        let (hdr_output, paper_white_scale, peak_relative) = match output {
+            TonemapOutput::Sdr => (false, 1.0, 1.0),
+            TonemapOutput::Hdr { paper_white_nits, max_nits } => {
+                let paper_white = paper_white_nits.max(1.0);
+                (true, paper_white / 80.0, (max_nits / paper_white).max(1.0))
+            }
+        };
+        let constants = format!(
+            "const HDR_OUTPUT: bool = {hdr_output};\nconst PAPER_WHITE_SCALE: f32 = {paper_white_scale:?};\nconst PEAK_RELATIVE: f32 = {peak_relative:?};\n"
+        );
+        let module = gpu_util::shader(device, "tonemap shader", &format!("{}{}{}", constants, EXPOSURE_COMMON, TONEMAP_SHADER));
+        let layout = gpu_util::bind_group_layout(device, "tonemap layout", &[
+            gpu_util::sampler_entry(0, ShaderStages::FRAGMENT, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(1, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: true }, TextureViewDimension::D2),
+            gpu_util::uniform_entry(2, ShaderStages::FRAGMENT),
+        ]);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(&namespaced("tonemap pipeline layout")),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(&namespaced("tonemap pipeline")),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(ColorTargetState {
+                    format: target_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+
+        Self {
+            device: device.clone(),
+            layout,
+            pipeline,
+            sampler: gpu_util::linear_sampler(device, "tonemap sampler"),
+            bind_group: None,
+            output,
+        }
+    }
+
+    pub fn output(&self) -> TonemapOutput {
+        self.output
+    }
+
+    /// Draw `hdr` tonemapped with the current exposure of `exposure`.
+    pub fn render(&mut self, pass: &mut RenderPass, hdr: &TextureView, exposure: &AutoExposure) {
+        let stale = self
+            .bind_group
+            .as_ref()
+            .is_none_or(|(view, buffer, _)| view != hdr || buffer != exposure.exposure_buffer());
+        if stale {
+            let bind_group = gpu_util::bind_group(&self.device, "tonemap", &self.layout, &[
+                BindingResource::Sampler(&self.sampler),
+                BindingResource::TextureView(hdr),
+                exposure.exposure_buffer().as_entire_binding(),
+            ]);
+            self.bind_group = Some((hdr.clone(), exposure.exposure_buffer().clone(), bind_group));
+        }
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group.as_ref().unwrap().2, &[]);
+        pass.draw(0..4, 0..1);
+    }
+}
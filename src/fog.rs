@@ -0,0 +1,491 @@
+//! Froxel-based volumetric fog with light scattering.
+//!
+//! The view frustum is divided into a 3D grid of froxels. [`VolumetricFog::update`]
+//! records two compute passes:
+//! 1. **Scattering**: every froxel evaluates the height fog density and the light it
+//!    scatters towards the camera from all lights of a [`LightManager`]
+//!    (Henyey-Greenstein phase function, so forward-scattering fog glows around
+//!    lights and produces light shafts along their direction).
+//! 2. **Accumulation**: every froxel column is integrated front to back into
+//!    in-scattered light and transmittance.
+//!
+//! [`composite`](VolumetricFog::composite) then applies the result to the HDR target
+//! with a fullscreen pass (`color * transmittance + scattering`, via blending), so it
+//! slots into the post-process chain before tonemapping:
+//! ```ignore
+//! fog.update(&mut encoder, &lights, &FogCamera { inverse_view_proj, position });
+//! // Pass writing `hdr`, without depth attachment
+//! fog.composite(&mut pass, &depth_view);
+//! // Then e.g. auto exposure and tonemapping of `hdr`
+//! ```
+//!
+//! Froxel slices are distributed quadratically over the view distance, so the
+//! resolution is highest close to the camera. Light contributions are not
+//! shadowed. The accumulated volume is a 3D texture and can also be bound to
+//! material shaders through [`volume_view`](VolumetricFog::volume_view), as material
+//! layouts detect `texture_3d` bindings.
+use wgpu::*;
+use crate::gpu_util;
+use crate::lights::LightManager;
+use crate::labels::namespaced;
+
+const FOG_COMMON: &str = r#"
+struct FogParams {
+    inverse_view_proj: mat4x4<f32>,
+    camera_position: vec3<f32>,
+    max_distance: f32,
+    albedo: vec3<f32>,
+    density: f32,
+    ambient: vec3<f32>,
+    anisotropy: f32,
+    height_falloff: f32,
+    base_height: f32,
+    _pad0: f32,
+    _pad1: f32,
+    grid: vec3<u32>,
+    _pad2: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: FogParams;
+
+fn view_ray(uv: vec2<f32>) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.5, 1.0);
+    let world = params.inverse_view_proj * ndc;
+    return normalize(world.xyz / world.w - params.camera_position);
+}
+"#;
+
+const FOG_SCATTER_SHADER: &str = r#"
+struct GpuLight {
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    spot_inner_cos: f32,
+    spot_outer_cos: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+struct LightBuffer {
+    counts: vec4<u32>,
+    lights: array<GpuLight>,
+};
+
+@group(0) @binding(1) var scattering: texture_storage_3d<rgba16float, write>;
+@group(1) @binding(0) var<storage, read> light_buffer: LightBuffer;
+
+fn phase(cos_theta: f32) -> f32 {
+    let g = params.anisotropy;
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (12.566371 * pow(max(denom, 1e-4), 1.5));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id >= params.grid) {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(params.grid.xy);
+    let t = (f32(id.z) + 0.5) / f32(params.grid.z);
+    let ray = view_ray(uv);
+    let position = params.camera_position + ray * params.max_distance * t * t;
+
+    let density = params.density * exp(-params.height_falloff * max(position.y - params.base_height, 0.0));
+
+    var light = params.ambient;
+    for (var i = 0u; i < light_buffer.counts.w; i++) {
+        let l = light_buffer.lights[i];
+        var to_light = -l.direction;
+        var attenuation = 1.0;
+        if l.kind != 0u {
+            let offset = l.position - position;
+            let len = length(offset);
+            to_light = offset / max(len, 1e-4);
+            let falloff = clamp(1.0 - len / max(l.range, 1e-4), 0.0, 1.0);
+            attenuation = falloff * falloff;
+            if l.kind == 2u {
+                attenuation *= smoothstep(l.spot_outer_cos, l.spot_inner_cos, dot(-to_light, l.direction));
+            }
+        }
+        light += l.color * l.intensity * attenuation * phase(dot(ray, to_light));
+    }
+
+    textureStore(scattering, id, vec4<f32>(params.albedo * density * light, density));
+}
+"#;
+
+const FOG_ACCUMULATE_SHADER: &str = r#"
+@group(0) @binding(1) var scattering: texture_3d<f32>;
+@group(0) @binding(2) var accumulated: texture_storage_3d<rgba16float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= params.grid.xy) {
+        return;
+    }
+    var light = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    let slices = f32(params.grid.z);
+    for (var z = 0u; z < params.grid.z; z++) {
+        let t0 = f32(z) / slices;
+        let t1 = f32(z + 1u) / slices;
+        let len = params.max_distance * (t1 * t1 - t0 * t0);
+        let froxel = textureLoad(scattering, vec3<u32>(id.xy, z), 0);
+        let extinction = max(froxel.a, 1e-6);
+        let slice_transmittance = exp(-extinction * len);
+        // Energy-conserving integration of the scattering over the slice.
+        light += transmittance * froxel.rgb * (1.0 - slice_transmittance) / extinction;
+        transmittance *= slice_transmittance;
+        textureStore(accumulated, vec3<u32>(id.xy, z), vec4<f32>(light, transmittance));
+    }
+}
+"#;
+
+const FOG_COMPOSITE_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(1) var volume_sampler: sampler;
+@group(0) @binding(2) var volume: texture_3d<f32>;
+@group(0) @binding(3) var depth_texture: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    var out: VertexOutput;
+    out.position = vec4<f32>(positions[idx], 0.0, 1.0);
+    out.uv = positions[idx] * vec2<f32>(0.5, -0.5) + 0.5;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(depth_texture, vec2<i32>(in.position.xy), 0);
+    let ndc = vec4<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, depth, 1.0);
+    let world = params.inverse_view_proj * ndc;
+    let dist = distance(world.xyz / world.w, params.camera_position);
+    let t = sqrt(clamp(dist / params.max_distance, 0.0, 1.0));
+    // Blending computes `dst * transmittance + scattering`.
+    return textureSampleLevel(volume, volume_sampler, vec3<f32>(in.uv, t), 0.0);
+}
+"#;
+
+/// Appearance and resolution of [`VolumetricFog`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FogSettings {
+    /// Extinction per world unit at `base_height` and below.
+    pub density: f32,
+    /// Color of the light scattered by the fog.
+    pub albedo: [f32; 3],
+    /// Light scattered uniformly from all directions, e.g. sky light.
+    pub ambient: [f32; 3],
+    /// Henyey-Greenstein anisotropy in `-1..1`. Positive values scatter forward,
+    /// making lights in front of the camera glow.
+    pub anisotropy: f32,
+    /// Exponential decay of the density per world unit above `base_height`.
+    pub height_falloff: f32,
+    pub base_height: f32,
+    /// View distance covered by the froxel grid. Fog beyond it is not rendered.
+    pub max_distance: f32,
+    /// Froxel grid resolution (x, y, depth slices).
+    pub resolution: [u32; 3],
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.02,
+            albedo: [1.0; 3],
+            ambient: [0.02, 0.025, 0.03],
+            anisotropy: 0.6,
+            height_falloff: 0.1,
+            base_height: 0.0,
+            max_distance: 100.0,
+            resolution: [160, 90, 64],
+        }
+    }
+}
+
+/// Camera the froxels are aligned to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FogCamera {
+    /// Inverse of `projection * view`, column-major.
+    pub inverse_view_proj: [[f32; 4]; 4],
+    /// World-space camera position.
+    pub position: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct FogParams {
+    inverse_view_proj: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    max_distance: f32,
+    albedo: [f32; 3],
+    density: f32,
+    ambient: [f32; 3],
+    anisotropy: f32,
+    height_falloff: f32,
+    base_height: f32,
+    _pad0: [f32; 2],
+    grid: [u32; 3],
+    _pad1: u32,
+}
+
+/// Froxel volumes and the bind groups over them. Recreated when the resolution changes.
+struct FogVolumes {
+    resolution: [u32; 3],
+    accumulated_view: TextureView,
+    scatter_bind_group: BindGroup,
+    accumulate_bind_group: BindGroup,
+}
+
+/// Volumetric fog passes.
+pub struct VolumetricFog {
+    device: Device,
+    queue: Queue,
+    settings: FogSettings,
+    params: Buffer,
+    sampler: Sampler,
+    scatter_layout: BindGroupLayout,
+    scatter_pipeline: ComputePipeline,
+    accumulate_layout: BindGroupLayout,
+    accumulate_pipeline: ComputePipeline,
+    composite_layout: BindGroupLayout,
+    composite_pipeline: RenderPipeline,
+    volumes: FogVolumes,
+    /// Composite bind group and the (volume, depth) views it was created for.
+    composite_bind_group: Option<(TextureView, TextureView, BindGroup)>,
+}
+
+impl VolumetricFog {
+    /// Create the fog passes, compositing into targets of `target_format`.
+    ///
+    /// `lights` provides the light bind group layout; any [`LightManager`] of the same
+    /// device can be passed to [`update`](Self::update) afterwards.
+    pub fn new(device: &Device, queue: &Queue, lights: &LightManager, target_format: TextureFormat, settings: FogSettings) -> Self {
+        let scatter_module = gpu_util::shader(device, "fog scatter shader", &format!("{}{}", FOG_COMMON, FOG_SCATTER_SHADER));
+        let scatter_layout = gpu_util::bind_group_layout(device, "fog scatter layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_texture_entry(1, ShaderStages::COMPUTE, TextureFormat::Rgba16Float, StorageTextureAccess::WriteOnly, TextureViewDimension::D3),
+        ]);
+        let scatter_pipeline = gpu_util::compute_pipeline(device, "fog scatter", &scatter_module, "main", &[
+            &scatter_layout,
+            lights.bind_group_layout(),
+        ]);
+
+        let accumulate_module = gpu_util::shader(device, "fog accumulate shader", &format!("{}{}", FOG_COMMON, FOG_ACCUMULATE_SHADER));
+        let accumulate_layout = gpu_util::bind_group_layout(device, "fog accumulate layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::texture_entry(1, ShaderStages::COMPUTE, TextureSampleType::Float { filterable: false }, TextureViewDimension::D3),
+            gpu_util::storage_texture_entry(2, ShaderStages::COMPUTE, TextureFormat::Rgba16Float, StorageTextureAccess::WriteOnly, TextureViewDimension::D3),
+        ]);
+        let accumulate_pipeline = gpu_util::compute_pipeline(device, "fog accumulate", &accumulate_module, "main", &[&accumulate_layout]);
+
+        let composite_module = gpu_util::shader(device, "fog composite shader", &format!("{}{}", FOG_COMMON, FOG_COMPOSITE_SHADER));
+        let composite_layout = gpu_util::bind_group_layout(device, "fog composite layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::FRAGMENT),
+            gpu_util::sampler_entry(1, ShaderStages::FRAGMENT, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(2, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: true }, TextureViewDimension::D3),
+            gpu_util::texture_entry(3, ShaderStages::FRAGMENT, TextureSampleType::Depth, TextureViewDimension::D2),
+        ]);
+        let composite_pipeline = create_composite_pipeline(device, &composite_module, &composite_layout, target_format);
+
+        let params = gpu_util::buffer(device, "fog params", size_of::<FogParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let volumes = create_volumes(device, &params, &scatter_layout, &accumulate_layout, settings.resolution);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            settings,
+            params,
+            sampler: gpu_util::linear_sampler(device, "fog sampler"),
+            scatter_layout,
+            scatter_pipeline,
+            accumulate_layout,
+            accumulate_pipeline,
+            composite_layout,
+            composite_pipeline,
+            volumes,
+            composite_bind_group: None,
+        }
+    }
+
+    pub fn settings(&self) -> &FogSettings {
+        &self.settings
+    }
+
+    /// Change the fog. A new resolution recreates the froxel volumes.
+    pub fn set_settings(&mut self, settings: FogSettings) {
+        if settings.resolution != self.volumes.resolution {
+            self.volumes = create_volumes(&self.device, &self.params, &self.scatter_layout, &self.accumulate_layout, settings.resolution);
+            self.composite_bind_group = None;
+        }
+        self.settings = settings;
+    }
+
+    /// The accumulated volume: in-scattered light in `rgb`, transmittance in `a`,
+    /// indexed by screen uv and `sqrt(distance / max_distance)`.
+    pub fn volume_view(&self) -> &TextureView {
+        &self.volumes.accumulated_view
+    }
+
+    /// Record the scattering and accumulation passes for `camera`.
+    ///
+    /// Parameters are written with `Queue::write_buffer`, so update once per submission.
+    pub fn update(&mut self, encoder: &mut CommandEncoder, lights: &LightManager, camera: &FogCamera) {
+        let _span = trace_span!("volumetric_fog");
+        let s = &self.settings;
+        let params = FogParams {
+            inverse_view_proj: camera.inverse_view_proj,
+            camera_position: camera.position,
+            max_distance: s.max_distance.max(0.001),
+            albedo: s.albedo,
+            density: s.density,
+            ambient: s.ambient,
+            anisotropy: s.anisotropy.clamp(-0.99, 0.99),
+            height_falloff: s.height_falloff,
+            base_height: s.base_height,
+            _pad0: [0.0; 2],
+            grid: self.volumes.resolution,
+            _pad1: 0,
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+
+        let [x, y, z] = self.volumes.resolution;
+        gpu_util::dispatch(encoder, "fog scatter", &self.scatter_pipeline, &[&self.volumes.scatter_bind_group, lights.bind_group()], [
+            x.div_ceil(8),
+            y.div_ceil(8),
+            z,
+        ]);
+        gpu_util::dispatch(encoder, "fog accumulate", &self.accumulate_pipeline, &[&self.volumes.accumulate_bind_group], [
+            x.div_ceil(8),
+            y.div_ceil(8),
+            1,
+        ]);
+    }
+
+    /// Apply the fog onto the color target of `pass`.
+    ///
+    /// `depth` is the single-sampled scene depth, which must not be attached to `pass`.
+    pub fn composite(&mut self, pass: &mut RenderPass, depth: &TextureView) {
+        let volume = &self.volumes.accumulated_view;
+        let stale = self
+            .composite_bind_group
+            .as_ref()
+            .is_none_or(|(cached_volume, cached_depth, _)| cached_volume != volume || cached_depth != depth);
+        if stale {
+            let bind_group = gpu_util::bind_group(&self.device, "fog composite", &self.composite_layout, &[
+                self.params.as_entire_binding(),
+                BindingResource::Sampler(&self.sampler),
+                BindingResource::TextureView(volume),
+                BindingResource::TextureView(depth),
+            ]);
+            self.composite_bind_group = Some((volume.clone(), depth.clone(), bind_group));
+        }
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &self.composite_bind_group.as_ref().unwrap().2, &[]);
+        pass.draw(0..4, 0..1);
+    }
+}
+
+fn create_volumes(
+    device: &Device,
+    params: &Buffer,
+    scatter_layout: &BindGroupLayout,
+    accumulate_layout: &BindGroupLayout,
+    resolution: [u32; 3],
+) -> FogVolumes {
+    let resolution = resolution.map(|r| r.max(1));
+    let create = |label| {
+        device
+            .create_texture(&TextureDescriptor {
+                label: Some(&namespaced(label)),
+                size: Extent3d {
+                    width: resolution[0],
+                    height: resolution[1],
+                    depth_or_array_layers: resolution[2],
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D3,
+                format: TextureFormat::Rgba16Float,
+                usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default())
+    };
+    let scattering_view = create("fog scattering");
+    let accumulated_view = create("fog accumulated");
+    let scatter_bind_group = gpu_util::bind_group(device, "fog scatter", scatter_layout, &[
+        params.as_entire_binding(),
+        BindingResource::TextureView(&scattering_view),
+    ]);
+    let accumulate_bind_group = gpu_util::bind_group(device, "fog accumulate", accumulate_layout, &[
+        params.as_entire_binding(),
+        BindingResource::TextureView(&scattering_view),
+        BindingResource::TextureView(&accumulated_view),
+    ]);
+    FogVolumes {
+        resolution,
+        accumulated_view,
+        scatter_bind_group,
+        accumulate_bind_group,
+    }
+}
+
+fn create_composite_pipeline(device: &Device, module: &ShaderModule, layout: &BindGroupLayout, format: TextureFormat) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("fog composite pipeline layout")),
+        bind_group_layouts: &[layout],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("fog composite pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::SrcAlpha,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent {
+                        src_factor: BlendFactor::Zero,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                }),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
@@ -0,0 +1,186 @@
+//! Frame pacing, FPS limiting and frame timing statistics (feature `native`).
+//!
+//! A [`FramePacer`] brackets the CPU work of each frame. [`end_frame`](FramePacer::end_frame)
+//! sleeps until the next frame is due at the target frame rate, then spins for the
+//! last stretch, since OS sleeps overshoot by up to a millisecond or more. Frames that
+//! miss their deadline start the next one immediately instead of rushing to catch up.
+//! Without a target it only measures.
+//!
+//! CPU time, whole frame time and GPU time (fed from e.g. the
+//! [`GpuProfiler`](crate::profiler::GpuProfiler)) are kept over a rolling window for
+//! [`stats`](FramePacer::stats):
+//! ```ignore
+//! let mut pacer = FramePacer::new(Some(60.0));
+//! loop {
+//!     let dt = pacer.begin_frame();
+//!     update(dt);
+//!     render();
+//!     pacer.record_gpu_ms(profiler.results().iter().map(|pass| pass.duration_ms as f32).sum());
+//!     frame.present();
+//!     pacer.end_frame();
+//! }
+//! ```
+//! Pace with vsync off; with `Fifo` the swapchain already blocks.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Frames the statistics are computed over.
+const STATS_WINDOW: usize = 120;
+
+/// Statistics of one timing over the recent frames, in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimingStats {
+    pub last_ms: f32,
+    pub average_ms: f32,
+    pub min_ms: f32,
+    pub max_ms: f32,
+}
+
+/// Timings of the recent frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    /// Time from one [`FramePacer::begin_frame`] to the next, including waiting.
+    pub frame: TimingStats,
+    /// Time from [`FramePacer::begin_frame`] to [`FramePacer::end_frame`], without waiting.
+    pub cpu: TimingStats,
+    /// GPU times passed to [`FramePacer::record_gpu_ms`], if any.
+    pub gpu: Option<TimingStats>,
+    /// Frames per second from the average frame time.
+    pub fps: f32,
+}
+
+#[derive(Default)]
+struct TimingWindow {
+    samples: VecDeque<f32>,
+}
+
+impl TimingWindow {
+    fn push(&mut self, ms: f32) {
+        if self.samples.len() == STATS_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+    }
+
+    fn stats(&self) -> Option<TimingStats> {
+        let last_ms = *self.samples.back()?;
+        let (min_ms, max_ms, sum) = self
+            .samples
+            .iter()
+            .fold((f32::MAX, f32::MIN, 0.0), |(min, max, sum), &ms| (min.min(ms), max.max(ms), sum + ms));
+        Some(TimingStats {
+            last_ms,
+            average_ms: sum / self.samples.len() as f32,
+            min_ms,
+            max_ms,
+        })
+    }
+}
+
+/// Frame rate limiter with CPU, frame and GPU timing statistics.
+pub struct FramePacer {
+    period: Option<Duration>,
+    spin_threshold: Duration,
+    /// Start of the current frame.
+    frame_start: Option<Instant>,
+    /// When the last frame was released.
+    deadline: Option<Instant>,
+    frame: TimingWindow,
+    cpu: TimingWindow,
+    gpu: TimingWindow,
+}
+
+impl FramePacer {
+    /// Limit to `target_fps` frames per second, or only measure with `None`.
+    pub fn new(target_fps: Option<f32>) -> Self {
+        let mut pacer = Self {
+            period: None,
+            spin_threshold: Duration::from_millis(1),
+            frame_start: None,
+            deadline: None,
+            frame: TimingWindow::default(),
+            cpu: TimingWindow::default(),
+            gpu: TimingWindow::default(),
+        };
+        pacer.set_target_fps(target_fps);
+        pacer
+    }
+
+    /// Change the frame rate limit; `None` or a non-positive rate removes it.
+    pub fn set_target_fps(&mut self, target_fps: Option<f32>) {
+        self.period = target_fps.filter(|&fps| fps > 0.0).map(|fps| Duration::from_secs_f32(1.0 / fps));
+        self.deadline = None;
+    }
+
+    pub fn target_fps(&self) -> Option<f32> {
+        self.period.map(|period| 1.0 / period.as_secs_f32())
+    }
+
+    /// How long before a deadline sleeping stops and spinning starts. Zero never spins,
+    /// trading precision for CPU time, e.g. on battery.
+    pub fn set_spin_threshold(&mut self, threshold: Duration) {
+        self.spin_threshold = threshold;
+    }
+
+    /// Start a frame and return the seconds since the previous one started, 0 for the
+    /// first frame.
+    pub fn begin_frame(&mut self) -> f32 {
+        let now = Instant::now();
+        let dt = self.frame_start.map_or(0.0, |start| (now - start).as_secs_f32());
+        if self.frame_start.is_some() {
+            self.frame.push(dt * 1000.0);
+        }
+        self.frame_start = Some(now);
+        dt
+    }
+
+    /// Finish the CPU work of a frame and wait until the next frame is due.
+    pub fn end_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(start) = self.frame_start {
+            self.cpu.push((now - start).as_secs_f32() * 1000.0);
+        }
+        let Some(period) = self.period else {
+            return;
+        };
+        let deadline = match self.deadline {
+            Some(previous) if previous + period > now => previous + period,
+            // First frame or missed deadline: release now and pace from here.
+            _ => now,
+        };
+        self.deadline = Some(deadline);
+
+        let _span = trace_span!("frame_pacing_wait");
+        if let Some(sleep) = deadline.checked_duration_since(now + self.spin_threshold) {
+            std::thread::sleep(sleep);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Record the GPU time of a frame, e.g. the sum of its profiled passes. Results
+    /// usually arrive a few frames late, which is fine for statistics.
+    pub fn record_gpu_ms(&mut self, ms: f32) {
+        self.gpu.push(ms);
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let frame = self.frame.stats().unwrap_or_default();
+        FrameStats {
+            frame,
+            cpu: self.cpu.stats().unwrap_or_default(),
+            gpu: self.gpu.stats(),
+            fps: if frame.average_ms > 0.0 { 1000.0 / frame.average_ms } else { 0.0 },
+        }
+    }
+
+    /// Forget all timings and the pacing deadline, e.g. after a loading screen.
+    pub fn reset(&mut self) {
+        self.frame_start = None;
+        self.deadline = None;
+        self.frame = TimingWindow::default();
+        self.cpu = TimingWindow::default();
+        self.gpu = TimingWindow::default();
+    }
+}
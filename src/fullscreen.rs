@@ -4,6 +4,9 @@ use std::collections::HashMap;
 use wgpu::*;
 use wgpu::util::DeviceExt;
 use crate::compute_system::figure_out_aspect;
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, Tracked};
+use crate::error::CrmError;
+use crate::labels::namespaced;
 
 const FULLSCREEN_COLOR_SHADER: &str = r#"
 struct VertexOutput {
@@ -530,8 +533,9 @@ pub struct FullscreenRenderer {
     linear_sampler: Sampler,
     nearest_sampler: Sampler,
 
-    pipelines: HashMap<PipelineKey, RenderPipeline>,
-    bind_groups: HashMap<BindGroupKey, BindGroup>,
+    pipelines: HashMap<PipelineKey, Tracked<RenderPipeline>>,
+    bind_groups: HashMap<BindGroupKey, Tracked<BindGroup>>,
+    frame: u64,
 
     depth_params_buffer: Option<Buffer>,
     depth_params_bind_group: Option<BindGroup>,
@@ -540,7 +544,7 @@ pub struct FullscreenRenderer {
 impl FullscreenRenderer {
     pub fn new(device: Device, queue: Queue) -> Self {
         let depth_params_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("depth params bgl"),
+            label: Some(&namespaced("depth params bgl")),
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
                 visibility: ShaderStages::FRAGMENT,
@@ -553,48 +557,48 @@ impl FullscreenRenderer {
             }],
         });
         let color_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen color shader"),
+            label: Some(&namespaced("fullscreen color shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_COLOR_SHADER.into()),
         });
         let color_unfilterable_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen color unfilterable shader"),
+            label: Some(&namespaced("fullscreen color unfilterable shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_COLOR_UNFILTERABLE_SHADER.into()),
         });
         let color_msaa_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen color MSAA shader"),
+            label: Some(&namespaced("fullscreen color MSAA shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_COLOR_MSAA_SHADER.into()),
         });
         let red_to_grayscale_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen grayscale shader"),
+            label: Some(&namespaced("fullscreen grayscale shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_RED_TO_GRAYSCALE_SHADER.into()),
         });
         let red_to_grayscale_unfilterable_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen grayscale unfilterable shader"),
+            label: Some(&namespaced("fullscreen grayscale unfilterable shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_RED_TO_GRAYSCALE_UNFILTERABLE_SHADER.into()),
         });
         let red_to_grayscale_msaa_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen grayscale shader"),
+            label: Some(&namespaced("fullscreen grayscale shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_RED_TO_GRAYSCALE_MSAA_SHADER.into()),
         });
         let depth_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen depth shader"),
+            label: Some(&namespaced("fullscreen depth shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_DEPTH_SHADER.into()),
         });
         let depth_msaa_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen depth MSAA shader"),
+            label: Some(&namespaced("fullscreen depth MSAA shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_DEPTH_MSAA_SHADER.into()),
         });
         let linear_depth_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen linear depth shader"),
+            label: Some(&namespaced("fullscreen linear depth shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_LINEAR_DEPTH_SHADER.into()),
         });
         let linear_depth_unfilterable_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("fullscreen linear depth unfilterable shader"),
+            label: Some(&namespaced("fullscreen linear depth unfilterable shader")),
             source: ShaderSource::Wgsl(FULLSCREEN_LINEAR_DEPTH_UNFILTERABLE_SHADER.into()),
         });
 
         let color_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("fullscreen color bgl"),
+            label: Some(&namespaced("fullscreen color bgl")),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
@@ -616,7 +620,7 @@ impl FullscreenRenderer {
         });
 
         let color_unfilterable_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("fullscreen color unfilterable bgl"),
+            label: Some(&namespaced("fullscreen color unfilterable bgl")),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
@@ -632,7 +636,7 @@ impl FullscreenRenderer {
         });
 
         let color_msaa_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("fullscreen color msaa bgl"),
+            label: Some(&namespaced("fullscreen color msaa bgl")),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
@@ -648,7 +652,7 @@ impl FullscreenRenderer {
         });
 
         let depth_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("fullscreen depth bgl"),
+            label: Some(&namespaced("fullscreen depth bgl")),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
@@ -669,7 +673,7 @@ impl FullscreenRenderer {
             ],
         });
         let depth_msaa_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("fullscreen depth msaa bgl"),
+            label: Some(&namespaced("fullscreen depth msaa bgl")),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
@@ -685,14 +689,14 @@ impl FullscreenRenderer {
         });
 
         let linear_sampler = device.create_sampler(&SamplerDescriptor {
-            label: Some("Fullscreen Linear Sampler"),
+            label: Some(&namespaced("Fullscreen Linear Sampler")),
             mag_filter: FilterMode::Linear,
             min_filter: FilterMode::Linear,
             ..Default::default()
         });
 
         let nearest_sampler = device.create_sampler(&SamplerDescriptor {
-            label: Some("Fullscreen Nearest Sampler"),
+            label: Some(&namespaced("Fullscreen Nearest Sampler")),
             mag_filter: FilterMode::Nearest,
             min_filter: FilterMode::Nearest,
             ..Default::default()
@@ -721,6 +725,7 @@ impl FullscreenRenderer {
             nearest_sampler,
             pipelines: HashMap::new(),
             bind_groups: HashMap::new(),
+            frame: 0,
             depth_params_buffer: None,
             depth_params_bind_group: None,
         }
@@ -750,14 +755,15 @@ impl FullscreenRenderer {
     /// - `pass`: Active render pass to record commands into
     ///
     /// ### Panics
-    /// This function may panic if internal pipeline or bind group creation fails.
+    /// If the texture format cannot be sampled on this device, see
+    /// [`try_render`](Self::try_render).
     ///
     /// ### Errors
     /// wgpu validation errors may occur if the texture, target view,
     /// or render pass configuration are incompatible.
     ///
     /// ## Example
-    /// ```no_run
+    /// ```ignore
     /// // Inside a render pass
     /// fullscreen_renderer.render(
     ///     &color_view,
@@ -773,7 +779,20 @@ impl FullscreenRenderer {
         target_view: &TextureView,
         pass: &mut RenderPass,
     ) {
-        let binding_type = infer_texture_binding_type(texture, &self.device);
+        self.try_render(texture, visualization_type, target_view, pass)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`render`](Self::render), failing with [`CrmError::UnsupportedFormat`] before
+    /// recording anything if `texture` cannot be sampled on this device.
+    pub fn try_render(
+        &mut self,
+        texture: &TextureView,
+        visualization_type: DebugVisualization,
+        target_view: &TextureView,
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        let binding_type = infer_texture_binding_type(texture, &self.device)?;
         let sample_type = match binding_type {
             BindingType::Texture { sample_type, .. } => sample_type,
             _ => unreachable!("infer_texture_binding_type always returns BindingType::Texture"),
@@ -799,32 +818,62 @@ impl FullscreenRenderer {
         pass.set_bind_group(0, bind_group, &[]);
 
         // LinearDepth also needs depth params for normalization
-        if kind == PipelineKind::Depth || kind == PipelineKind::LinearDepth {
-            if let Some(bg) = &self.depth_params_bind_group {
-                pass.set_bind_group(1, bg, &[]);
-            }
+        if (kind == PipelineKind::Depth || kind == PipelineKind::LinearDepth)
+            && let Some(bg) = &self.depth_params_bind_group
+        {
+            pass.set_bind_group(1, bg, &[]);
         }
 
         pass.draw(0..4, 0..1);
+        Ok(())
     }
 
     /// Clear cached bind groups (call when textures are recreated).
     pub(crate) fn invalidate_bind_groups(&mut self) {
+        trace_evict!("fullscreen_bind_groups", self.bind_groups.len());
         self.bind_groups.clear();
     }
 
+    /// Set the frame index recorded on cache hits and insertions.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// Append diagnostics for all cached fullscreen pipelines and bind groups.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        for (key, entry) in &self.pipelines {
+            out.push(entry.info(CacheKind::FullscreenPipeline, entry_id(key), format!("{:?}", key), 0));
+        }
+        for (key, entry) in &self.bind_groups {
+            out.push(entry.info(CacheKind::FullscreenBindGroup, entry_id(key), format!("{:?}", key), 0));
+        }
+    }
+
+    /// Remove a single entry by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&mut self, kind: CacheKind, id: u64) -> bool {
+        let removed = match kind {
+            CacheKind::FullscreenPipeline => evict_by_id(&mut self.pipelines, id),
+            CacheKind::FullscreenBindGroup => evict_by_id(&mut self.bind_groups, id),
+            _ => false,
+        };
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
+
     pub(crate) fn update_depth_params(&mut self, params: DepthDebugParams) {
         if let Some(buf) = &self.depth_params_buffer {
             self.queue.write_buffer(buf, 0, bytemuck::bytes_of(&params));
         } else {
             let buf = self.device.create_buffer_init(&util::BufferInitDescriptor {
-                label: Some("depth params buffer"),
+                label: Some(&namespaced("depth params buffer")),
                 contents: bytemuck::bytes_of(&params),
                 usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             });
 
             let bg = self.device.create_bind_group(&BindGroupDescriptor {
-                label: Some("depth params bind group"),
+                label: Some(&namespaced("depth params bind group")),
                 layout: &self.depth_params_bgl,
                 entries: &[BindGroupEntry {
                     binding: 0,
@@ -854,12 +903,16 @@ impl FullscreenRenderer {
             target_sample_count,
         };
 
-        if !self.pipelines.contains_key(&key) {
+        if let Some(entry) = self.pipelines.get_mut(&key) {
+            entry.touch(self.frame);
+        } else {
+            let _span = trace_span!("fullscreen_pipeline_miss", kind = ?kind, format = ?target_format);
             let pipeline = self.create_pipeline(kind, target_format, target_sample_count, source_is_msaa, is_filterable);
-            self.pipelines.insert(key, pipeline);
+            let label = format!("{:?} -> {:?}", kind, target_format);
+            self.pipelines.insert(key, Tracked::new(pipeline, self.frame, label));
         }
 
-        self.pipelines.get(&key).unwrap()
+        &self.pipelines.get(&key).unwrap().value
     }
 
     fn create_pipeline(
@@ -899,13 +952,13 @@ impl FullscreenRenderer {
         };
 
         let layout = self.device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("fullscreen pipeline layout"),
+            label: Some(&namespaced("fullscreen pipeline layout")),
             bind_group_layouts: &bind_group_layouts,
             immediate_size: 0,
         });
 
         self.device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("fullscreen pipeline"),
+            label: Some(&namespaced("fullscreen pipeline")),
             layout: Some(&layout),
             vertex: VertexState {
                 module: shader,
@@ -952,12 +1005,15 @@ impl FullscreenRenderer {
 
         let is_msaa = view.texture().sample_count() > 1;
 
-        if !self.bind_groups.contains_key(&key) {
+        if let Some(entry) = self.bind_groups.get_mut(&key) {
+            entry.touch(self.frame);
+        } else {
+            trace_event!(kind = ?kind, msaa = is_msaa, is_filterable, "created fullscreen bind group");
             let bg = match (kind, is_msaa, is_filterable) {
                 // MSAA textures: no sampler, just texture at binding 0
                 (PipelineKind::Color | PipelineKind::RedToGrayscale | PipelineKind::LinearDepth, true, _) => {
                     self.device.create_bind_group(&BindGroupDescriptor {
-                        label: Some("Fullscreen Color MSAA Bind Group"),
+                        label: Some(&namespaced("Fullscreen Color MSAA Bind Group")),
                         layout: &self.color_msaa_bgl,
                         entries: &[BindGroupEntry {
                             binding: 0,
@@ -967,7 +1023,7 @@ impl FullscreenRenderer {
                 }
                 (PipelineKind::Depth, true, _) => {
                     self.device.create_bind_group(&BindGroupDescriptor {
-                        label: Some("Fullscreen Depth MSAA Bind Group"),
+                        label: Some(&namespaced("Fullscreen Depth MSAA Bind Group")),
                         layout: &self.depth_msaa_bgl,
                         entries: &[BindGroupEntry {
                             binding: 0,
@@ -979,7 +1035,7 @@ impl FullscreenRenderer {
                 // Non-MSAA unfilterable: no sampler, just texture at binding 0
                 (PipelineKind::Color | PipelineKind::RedToGrayscale | PipelineKind::LinearDepth, false, false) => {
                     self.device.create_bind_group(&BindGroupDescriptor {
-                        label: Some("Fullscreen Color Unfilterable Bind Group"),
+                        label: Some(&namespaced("Fullscreen Color Unfilterable Bind Group")),
                         layout: &self.color_unfilterable_bgl,
                         entries: &[BindGroupEntry {
                             binding: 0,
@@ -991,7 +1047,7 @@ impl FullscreenRenderer {
                 // Non-MSAA filterable: sampler + texture
                 (PipelineKind::Color | PipelineKind::RedToGrayscale | PipelineKind::LinearDepth, false, true) => {
                     self.device.create_bind_group(&BindGroupDescriptor {
-                        label: Some("Fullscreen Color Bind Group"),
+                        label: Some(&namespaced("Fullscreen Color Bind Group")),
                         layout: &self.color_bgl,
                         entries: &[
                             BindGroupEntry {
@@ -1009,7 +1065,7 @@ impl FullscreenRenderer {
                 // Depth non-MSAA: nearest sampler + texture
                 (PipelineKind::Depth, false, _) => {
                     self.device.create_bind_group(&BindGroupDescriptor {
-                        label: Some("Fullscreen Depth Bind Group"),
+                        label: Some(&namespaced("Fullscreen Depth Bind Group")),
                         layout: &self.depth_bgl,
                         entries: &[
                             BindGroupEntry {
@@ -1025,16 +1081,17 @@ impl FullscreenRenderer {
                 }
             };
 
-            self.bind_groups.insert(key, bg);
+            let label = format!("{:?} ({:?})", kind, view.texture().format());
+            self.bind_groups.insert(key, Tracked::new(bg, self.frame, label));
         }
 
-        self.bind_groups.get(&key).unwrap()
+        &self.bind_groups.get(&key).unwrap().value
     }
 }
 
 /// Infer a `BindingType::Texture` for a view + device.
 /// Automatically determines the correct aspect for depth-stencil formats.
-fn infer_texture_binding_type(view: &TextureView, device: &Device) -> BindingType {
+fn infer_texture_binding_type(view: &TextureView, device: &Device) -> Result<BindingType, CrmError> {
     let format = view.texture().format();
 
     // Determine the appropriate aspect for sampling
@@ -1042,18 +1099,16 @@ fn infer_texture_binding_type(view: &TextureView, device: &Device) -> BindingTyp
 
     let sample_type = format
         .sample_type(aspect, Some(device.features()))
-        .unwrap_or_else(|| {
-            panic!(
-                "unsupported texture format {:?} with aspect {:?} for sampling",
-                format, aspect
-            )
-        });
+        .ok_or_else(|| CrmError::UnsupportedFormat {
+            format,
+            usage: format!("fullscreen sampling with aspect {:?}", aspect),
+        })?;
 
     let multisampled = view.texture().sample_count() > 1;
 
-    BindingType::Texture {
+    Ok(BindingType::Texture {
         sample_type,
         view_dimension: TextureViewDimension::D2,
         multisampled,
-    }
+    })
 }
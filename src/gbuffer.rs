@@ -0,0 +1,237 @@
+//! G-buffer targets for deferred shading.
+//!
+//! A [`GBuffer`] owns the albedo, normal, material and depth targets of a deferred
+//! renderer. The geometry pass renders into [`color_attachments`](GBuffer::color_attachments)
+//! and [`depth_attachment`](GBuffer::depth_attachment) with pipelines configured by
+//! [`geometry_options`](GBuffer::geometry_options). The lighting pass then reads all
+//! four targets through a material bind group built by
+//! [`render_lighting`](GBuffer::render_lighting).
+//!
+//! ## Frame flow
+//! ```ignore
+//! let options = gbuffer.geometry_options(PipelineOptions::default().with_vertex_layout(layout));
+//! {
+//!     let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!         color_attachments: &gbuffer.color_attachments(),
+//!         depth_stencil_attachment: Some(gbuffer.depth_attachment()),
+//!         ..Default::default()
+//!     });
+//!     render_manager.render_with_textures(&[&albedo], geometry_shader, &options, &[&camera], &mut pass);
+//!     // draw meshes
+//! }
+//! // Inside the lighting pass, writing the HDR or swapchain target
+//! gbuffer.render_lighting(&mut render_manager, &mut pass, lighting_shader, &lighting_options, &[&lights]);
+//! ```
+//!
+//! ## Geometry pass outputs
+//! - `@location(0)`: albedo (rgb) and alpha
+//! - `@location(1)`: world-space normal (xyz), w free for the application
+//! - `@location(2)`: material parameters, e.g. metallic, roughness, occlusion
+//!
+//! ## Lighting pass bindings (group 0)
+//! ```wgsl
+//! @group(0) @binding(0) var gbuffer_sampler: sampler;
+//! @group(0) @binding(1) var gbuffer_albedo: texture_2d<f32>;
+//! @group(0) @binding(2) var gbuffer_normal: texture_2d<f32>;
+//! @group(0) @binding(3) var gbuffer_material: texture_2d<f32>;
+//! @group(0) @binding(4) var gbuffer_depth: texture_depth_2d;
+//! ```
+//! Uniforms passed to `render_lighting` follow as `@group(1)`, like every
+//! [`render_with_textures`](RenderManager::render_with_textures) call.
+use std::path::Path;
+use wgpu::*;
+use crate::pipelines::PipelineOptions;
+use crate::renderer::RenderManager;
+use crate::labels::namespaced;
+
+/// Formats of the G-buffer targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GBufferFormats {
+    pub albedo: TextureFormat,
+    pub normal: TextureFormat,
+    pub material: TextureFormat,
+    pub depth: TextureFormat,
+}
+
+impl Default for GBufferFormats {
+    fn default() -> Self {
+        Self {
+            albedo: TextureFormat::Rgba8UnormSrgb,
+            normal: TextureFormat::Rgba16Float,
+            material: TextureFormat::Rgba8Unorm,
+            depth: TextureFormat::Depth32Float,
+        }
+    }
+}
+
+struct GBufferTarget {
+    texture: Texture,
+    view: TextureView,
+}
+
+/// Albedo, normal, material and depth targets of a deferred renderer.
+pub struct GBuffer {
+    device: Device,
+    formats: GBufferFormats,
+    size: (u32, u32),
+    albedo: GBufferTarget,
+    normal: GBufferTarget,
+    material: GBufferTarget,
+    depth: GBufferTarget,
+}
+
+impl GBuffer {
+    pub fn new(device: &Device, width: u32, height: u32, formats: GBufferFormats) -> Self {
+        let size = (width.max(1), height.max(1));
+        Self {
+            device: device.clone(),
+            formats,
+            size,
+            albedo: create_target(device, "gbuffer albedo", formats.albedo, size),
+            normal: create_target(device, "gbuffer normal", formats.normal, size),
+            material: create_target(device, "gbuffer material", formats.material, size),
+            depth: create_target(device, "gbuffer depth", formats.depth, size),
+        }
+    }
+
+    /// Recreate the targets for a new size. Does nothing if the size is unchanged.
+    ///
+    /// Material bind groups of the old views are evicted by the usual cache aging.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = (width.max(1), height.max(1));
+        if size == self.size {
+            return;
+        }
+        *self = Self::new(&self.device, size.0, size.1, self.formats);
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn formats(&self) -> &GBufferFormats {
+        &self.formats
+    }
+
+    pub fn albedo_view(&self) -> &TextureView {
+        &self.albedo.view
+    }
+
+    pub fn normal_view(&self) -> &TextureView {
+        &self.normal.view
+    }
+
+    pub fn material_view(&self) -> &TextureView {
+        &self.material.view
+    }
+
+    pub fn depth_view(&self) -> &TextureView {
+        &self.depth.view
+    }
+
+    /// The depth texture, e.g. for building a `HiZBuffer`.
+    pub fn depth_texture(&self) -> &Texture {
+        &self.depth.texture
+    }
+
+    /// All views in lighting pass binding order: albedo, normal, material, depth.
+    pub fn views(&self) -> [&TextureView; 4] {
+        [&self.albedo.view, &self.normal.view, &self.material.view, &self.depth.view]
+    }
+
+    /// Color attachments of the geometry pass, cleared to zero.
+    pub fn color_attachments(&self) -> [Option<RenderPassColorAttachment<'_>>; 3] {
+        [&self.albedo.view, &self.normal.view, &self.material.view].map(|view| {
+            Some(RenderPassColorAttachment {
+                view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            })
+        })
+    }
+
+    /// Depth attachment of the geometry pass, cleared to the far plane (1.0).
+    pub fn depth_attachment(&self) -> RenderPassDepthStencilAttachment<'_> {
+        RenderPassDepthStencilAttachment {
+            view: &self.depth.view,
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(1.0),
+                store: StoreOp::Store,
+            }),
+            stencil_ops: None,
+        }
+    }
+
+    /// Geometry pass variant of `base`: the three G-buffer color targets without
+    /// blending, depth testing with `Less` and writes, no MSAA.
+    ///
+    /// Vertex layouts, topology and culling of `base` are kept.
+    pub fn geometry_options(&self, base: PipelineOptions) -> PipelineOptions {
+        let target = |format| ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        };
+        PipelineOptions {
+            msaa_samples: 1,
+            depth_stencil: Some(DepthStencilState {
+                format: self.formats.depth,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            targets: vec![
+                Some(target(self.formats.albedo)),
+                Some(target(self.formats.normal)),
+                Some(target(self.formats.material)),
+            ],
+            vertex_only: false,
+            ..base
+        }
+    }
+
+    /// Bind the G-buffer and `uniforms` for a fullscreen lighting shader and draw it.
+    ///
+    /// The vertex shader must emit a fullscreen triangle strip from `vertex_index`
+    /// (4 vertices). `options` describes the lighting target; its topology is forced
+    /// to `TriangleStrip`.
+    pub fn render_lighting(
+        &self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+    ) {
+        let options = PipelineOptions {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..options.clone()
+        };
+        manager.render_with_textures(&self.views(), shader_path, &options, uniforms, pass);
+        pass.draw(0..4, 0..1);
+    }
+}
+
+fn create_target(device: &Device, label: &str, format: TextureFormat, (width, height): (u32, u32)) -> GBufferTarget {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced(label)),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    GBufferTarget { texture, view }
+}
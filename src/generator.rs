@@ -32,6 +32,12 @@ use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 use wgpu::util::DeviceExt;
 use wgpu::{Device, Queue, TextureView};
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, Tracked};
+use crate::error::CrmError;
+use crate::pipeline_stats::{PipelineStatistics, StatisticsQuery};
+use crate::profiler::{GpuProfiler, PassTimestamps};
+use crate::shader_preprocessing::parse_wgsl;
+use crate::labels::namespaced;
 
 /// Parameters passed to procedural texture generation shaders.
 ///
@@ -211,7 +217,10 @@ pub struct TextureGenerator {
     queue: Queue,
     shader_dir: PathBuf,
     pipelines: HashMap<String, ComputePipeline>,
-    cache: HashMap<TextureKey, CachedTexture>,
+    cache: HashMap<TextureKey, Tracked<CachedTexture>>,
+    frame: u64,
+    /// Generation command buffers, collected instead of submitted while batching.
+    batched: Option<Vec<wgpu::CommandBuffer>>,
 }
 
 impl TextureGenerator {
@@ -225,6 +234,8 @@ impl TextureGenerator {
             shader_dir,
             pipelines: HashMap::new(),
             cache: HashMap::new(),
+            frame: 0,
+            batched: None,
         }
     }
 
@@ -240,21 +251,49 @@ impl TextureGenerator {
     ///
     /// ## Returns
     /// A [`TextureView`] referencing the generated texture.
+    ///
+    /// ## Panics
+    /// If the shader cannot be read or parsed, see [`try_get_or_create`](Self::try_get_or_create).
     pub fn get_or_create(&mut self, key: &TextureKey) -> &TextureView {
-        if !self.cache.contains_key(key) {
-            self.ensure_pipeline(&key.shader_id);
-            self.generate(key);
+        self.try_get_or_create(key).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`get_or_create`](Self::get_or_create), returning a [`CrmError::Io`] or
+    /// [`CrmError::Shader`] if the shader cannot be read or parsed.
+    pub fn try_get_or_create(&mut self, key: &TextureKey) -> Result<&TextureView, CrmError> {
+        self.get_or_create_profiled(key, None, None)
+    }
+
+    /// Same as [`get_or_create`](Self::get_or_create), but times the generation
+    /// pass with the given profiler and measures its pipeline statistics on a cache miss.
+    pub(crate) fn get_or_create_profiled(
+        &mut self,
+        key: &TextureKey,
+        profiler: Option<&mut GpuProfiler>,
+        statistics: Option<&mut PipelineStatistics>,
+    ) -> Result<&TextureView, CrmError> {
+        if let Some(entry) = self.cache.get_mut(key) {
+            entry.touch(self.frame);
+        } else {
+            self.ensure_pipeline(&key.shader_id)?;
+            let label = format!("generate {}", key.shader_id);
+            let timestamps = profiler.and_then(|p| p.begin_pass(&label));
+            let statistics = statistics.and_then(|s| s.begin_scope(&label));
+            self.generate(key, timestamps, statistics);
         }
-        &self.cache.get(key).expect("texture must exist after generation").view
+        Ok(&self.cache.get(key).expect("texture must exist after generation").value.view)
     }
 
     /// Clear all cached textures.
     pub fn clear_cache(&mut self) {
+        trace_evict!("procedural_textures", self.cache.len());
         self.cache.clear();
     }
 
     /// Reload all procedural texture shaders and invalidate caches.
     pub fn reload_shaders(&mut self) {
+        trace_evict!("procedural_pipelines", self.pipelines.len());
+        trace_evict!("procedural_textures", self.cache.len());
         self.pipelines.clear();
         self.cache.clear();
     }
@@ -264,27 +303,81 @@ impl TextureGenerator {
         self.cache.contains_key(key)
     }
 
+    /// Keys of all cached textures.
+    pub fn cached_keys(&self) -> Vec<TextureKey> {
+        self.cache.keys().cloned().collect()
+    }
+
     /// Get the shader directory.
     pub fn shader_dir(&self) -> &PathBuf {
         &self.shader_dir
     }
 
-    fn ensure_pipeline(&mut self, shader_id: &str) {
+    /// Collect generation command buffers instead of submitting them.
+    ///
+    /// Collected buffers are handed out by [`take_command_buffers`](Self::take_command_buffers).
+    /// Generated textures are only valid once those buffers are submitted.
+    pub fn set_batch_submissions(&mut self, enabled: bool) {
+        match (enabled, self.batched.is_some()) {
+            (true, false) => self.batched = Some(Vec::new()),
+            (false, true) => {
+                let pending = self.take_command_buffers();
+                if !pending.is_empty() {
+                    self.queue.submit(pending);
+                }
+                self.batched = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Command buffers collected while batching, in recording order.
+    pub fn take_command_buffers(&mut self) -> Vec<wgpu::CommandBuffer> {
+        self.batched.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Set the frame index recorded on cache hits and insertions.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// Append diagnostics for all generated textures.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        for (key, entry) in &self.cache {
+            let memory = texture_memory_bytes(&entry.value._texture);
+            out.push(entry.info(CacheKind::ProceduralTexture, entry_id(key), format!("{:?}", key), memory));
+        }
+    }
+
+    /// Remove a single generated texture by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&mut self, kind: CacheKind, id: u64) -> bool {
+        let removed = kind == CacheKind::ProceduralTexture && evict_by_id(&mut self.cache, id);
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
+
+    fn ensure_pipeline(&mut self, shader_id: &str) -> Result<(), CrmError> {
         if self.pipelines.contains_key(shader_id) {
-            return;
+            return Ok(());
         }
 
+        let _span = trace_span!("procedural_pipeline_miss", shader_id);
         let shader_path = self.shader_dir.join(format!("{}.wgsl", shader_id.to_lowercase()));
-        let shader_source = std::fs::read_to_string(&shader_path)
-            .unwrap_or_else(|e| panic!("Failed to read shader {:?}: {}", shader_path, e));
+        let shader_source = std::fs::read_to_string(&shader_path).map_err(|e| CrmError::Io {
+            path: shader_path.clone(),
+            message: e.to_string(),
+        })?;
+        parse_wgsl(&shader_path, &shader_source)?;
 
         let shader_module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some(shader_id),
+            label: Some(&namespaced(shader_id)),
             source: wgpu::ShaderSource::Wgsl(shader_source.into()),
         });
 
         let bind_group_layout = self.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some(&format!("{} bind group layout", shader_id)),
+            label: Some(&namespaced(&format!("{} bind group layout", shader_id))),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -310,13 +403,13 @@ impl TextureGenerator {
         });
 
         let pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some(&format!("{} pipeline layout", shader_id)),
+            label: Some(&namespaced(&format!("{} pipeline layout", shader_id))),
             bind_group_layouts: &[&bind_group_layout],
             immediate_size: 0,
         });
 
         let pipeline = self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some(&format!("{} compute pipeline", shader_id)),
+            label: Some(&namespaced(&format!("{} compute pipeline", shader_id))),
             layout: Some(&pipeline_layout),
             module: &shader_module,
             entry_point: Some("main"),
@@ -328,9 +421,10 @@ impl TextureGenerator {
             pipeline,
             bind_group_layout,
         });
+        Ok(())
     }
 
-    fn generate(&mut self, key: &TextureKey) {
+    fn generate(&mut self, key: &TextureKey, timestamps: Option<PassTimestamps>, statistics: Option<StatisticsQuery>) {
         let pipeline_entry = self.pipelines.get(&key.shader_id).unwrap();
 
         let size = wgpu::Extent3d {
@@ -339,9 +433,10 @@ impl TextureGenerator {
             depth_or_array_layers: 1,
         };
         let mip_count = size.max_mips(wgpu::TextureDimension::D2);
+        let _span = trace_span!("generate_texture", shader_id = %key.shader_id, resolution = key.resolution, mip_count);
 
         let texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some(&format!("procedural texture {}", key.shader_id)),
+            label: Some(&namespaced(&format!("procedural texture {}", key.shader_id))),
             size,
             mip_level_count: mip_count,
             sample_count: 1,
@@ -352,15 +447,18 @@ impl TextureGenerator {
         });
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("procedural texture generation"),
+            label: Some(&namespaced("procedural texture generation")),
         });
 
         {
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("generate texture mips"),
-                timestamp_writes: None,
+                label: Some(&namespaced("generate texture mips")),
+                timestamp_writes: timestamps.as_ref().map(|t| t.compute_writes()),
             });
 
+            if let Some(query) = &statistics {
+                query.begin_compute(&mut compute_pass);
+            }
             compute_pass.set_pipeline(&pipeline_entry.pipeline);
 
             let workgroup_size = 8u32;
@@ -403,14 +501,40 @@ impl TextureGenerator {
                     1,
                 );
             }
+            if statistics.is_some() {
+                compute_pass.end_pipeline_statistics_query();
+            }
         }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+        match &mut self.batched {
+            Some(batched) => batched.push(encoder.finish()),
+            None => {
+                self.queue.submit(std::iter::once(encoder.finish()));
+            }
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        self.cache.insert(key.clone(), CachedTexture {
+        let label = format!("{} @ {}px", key.shader_id, key.resolution);
+        self.cache.insert(key.clone(), Tracked::new(CachedTexture {
             _texture: texture,
             view,
-        });
+        }, self.frame, label));
     }
+}
+
+/// Estimated GPU memory of a texture including all mip levels and array layers.
+pub(crate) fn texture_memory_bytes(texture: &wgpu::Texture) -> u64 {
+    let format = texture.format();
+    let (block_w, block_h) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(4) as u64;
+    let size = texture.size();
+    (0..texture.mip_level_count())
+        .map(|mip| {
+            let w = (size.width >> mip).max(1).div_ceil(block_w) as u64;
+            let h = (size.height >> mip).max(1).div_ceil(block_h) as u64;
+            w * h * block_size
+        })
+        .sum::<u64>()
+        * size.depth_or_array_layers as u64
+        * texture.sample_count() as u64
 }
\ No newline at end of file
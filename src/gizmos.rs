@@ -0,0 +1,366 @@
+//! Debug lines and wireframe shapes.
+//!
+//! [`Gizmos`] is an immediate-mode line drawer for visual debugging: lines, boxes,
+//! spheres, circles, camera frusta and coordinate axes are turned into line segments
+//! when they are added, uploaded into a vertex buffer that grows as needed and drawn
+//! with one line-list draw per depth mode. Shapes are kept until
+//! [`clear`](Gizmos::clear), which is usually called every frame.
+//!
+//! Shapes added while [`set_depth_test`](Gizmos::set_depth_test) is on are hidden
+//! behind scene geometry (the depth buffer is read, never written); the others draw
+//! on top of everything. The camera comes from a
+//! [`CameraManager`](crate::camera::CameraManager), bound at its fixed group.
+//! ```ignore
+//! let mut gizmos = Gizmos::new(&device, &queue, false);
+//! // Every frame
+//! gizmos.clear();
+//! for object in &objects {
+//!     gizmos.aabb(&object.world_bounds, if visible.contains(&object.id) { GREEN } else { RED });
+//! }
+//! gizmos.set_depth_test(false);
+//! gizmos.frustum(&shadow_cascade_view_proj, [1.0, 1.0, 0.0, 1.0]);
+//! gizmos.upload();
+//! // Inside the scene pass, after opaque geometry
+//! gizmos.render(&mut pass, &camera, &GizmoTarget::new(surface.scene_format(), Some(DEPTH_FORMAT)));
+//! ```
+use std::collections::HashMap;
+use wgpu::*;
+use crate::camera::{CameraManager, CAMERA_GROUP, CAMERA_WGSL};
+use crate::culling::Aabb;
+use crate::gpu_util;
+use crate::pipelines::uniform_layout_entries;
+use crate::labels::namespaced;
+
+/// Appended to [`CAMERA_WGSL`].
+const GIZMO_SHADER: &str = r#"
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+"#;
+
+/// Segments of a full circle.
+const CIRCLE_SEGMENTS: usize = 32;
+
+const VERTEX_ATTRIBUTES: [VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+const VERTEX_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<GizmoVertex>() as u64,
+    step_mode: VertexStepMode::Vertex,
+    attributes: &VERTEX_ATTRIBUTES,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+/// Formats of the pass [`Gizmos::render`] draws into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GizmoTarget {
+    pub format: TextureFormat,
+    /// Depth attachment of the pass. Without one, depth-tested shapes draw on top too.
+    pub depth_format: Option<TextureFormat>,
+    pub sample_count: u32,
+}
+
+impl GizmoTarget {
+    /// A single-sampled target.
+    pub fn new(format: TextureFormat, depth_format: Option<TextureFormat>) -> Self {
+        Self {
+            format,
+            depth_format,
+            sample_count: 1,
+        }
+    }
+
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+/// Immediate-mode debug line renderer.
+pub struct Gizmos {
+    device: Device,
+    queue: Queue,
+    module: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    reversed_z: bool,
+    depth_test: bool,
+    /// Segments hidden by scene depth and segments drawn on top, as vertex pairs.
+    tested: Vec<GizmoVertex>,
+    overlay: Vec<GizmoVertex>,
+    vertices: Buffer,
+    vertex_capacity: u64,
+    /// Vertices of both lists at the last upload.
+    uploaded: (u32, u32),
+    /// Pipelines per target and depth test.
+    pipelines: HashMap<(GizmoTarget, bool), RenderPipeline>,
+}
+
+impl Gizmos {
+    /// `reversed_z` must match the projection of the cameras the gizmos are drawn with.
+    pub fn new(device: &Device, queue: &Queue, reversed_z: bool) -> Self {
+        // The camera sits at its fixed group; group 0 (materials elsewhere) stays empty.
+        let empty_layout = gpu_util::bind_group_layout(device, "gizmo empty layout", &[]);
+        let camera_layout = gpu_util::bind_group_layout(device, "gizmo camera layout", &uniform_layout_entries(1));
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(&namespaced("gizmo pipeline layout")),
+            bind_group_layouts: &[&empty_layout, &camera_layout],
+            immediate_size: 0,
+        });
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            module: gpu_util::shader(device, "gizmo shader", &format!("{CAMERA_WGSL}{GIZMO_SHADER}")),
+            pipeline_layout,
+            reversed_z,
+            depth_test: true,
+            tested: Vec::new(),
+            overlay: Vec::new(),
+            vertices: vertex_buffer(device, 1024),
+            vertex_capacity: 1024,
+            uploaded: (0, 0),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Hide shapes added from now on behind scene geometry (the default), or draw them
+    /// on top of it.
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        self.depth_test = depth_test;
+    }
+
+    /// A line from `a` to `b` in world space, color in straight RGBA.
+    pub fn line(&mut self, a: [f32; 3], b: [f32; 3], color: [f32; 4]) {
+        let lines = if self.depth_test { &mut self.tested } else { &mut self.overlay };
+        lines.push(GizmoVertex { position: a, color });
+        lines.push(GizmoVertex { position: b, color });
+    }
+
+    /// Lines through consecutive `points`, closing the loop if `closed`.
+    pub fn polyline(&mut self, points: &[[f32; 3]], closed: bool, color: [f32; 4]) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], color);
+        }
+        if closed && points.len() > 2 {
+            self.line(points[points.len() - 1], points[0], color);
+        }
+    }
+
+    /// The twelve edges of a box with corners `corners`, ordered by the bits of their
+    /// index: bit 0 selects the second x, bit 1 the second y and bit 2 the second z.
+    pub fn box_corners(&mut self, corners: &[[f32; 3]; 8], color: [f32; 4]) {
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corners[i], corners[i | axis], color);
+                }
+            }
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        if aabb.is_empty() {
+            return;
+        }
+        let corners = std::array::from_fn(|i| {
+            std::array::from_fn(|axis| if i & (1 << axis) == 0 { aabb.min[axis] } else { aabb.max[axis] })
+        });
+        self.box_corners(&corners, color);
+    }
+
+    /// A circle around `center` in the plane orthogonal to `normal`.
+    pub fn circle(&mut self, center: [f32; 3], normal: [f32; 3], radius: f32, color: [f32; 4]) {
+        let (u, v) = orthonormal_basis(normalize(normal));
+        let points: Vec<[f32; 3]> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let (sin, cos) = (i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU).sin_cos();
+                std::array::from_fn(|axis| center[axis] + (u[axis] * cos + v[axis] * sin) * radius)
+            })
+            .collect();
+        self.polyline(&points, true, color);
+    }
+
+    /// Three circles around the axes, enough to read a bounding sphere.
+    pub fn sphere(&mut self, center: [f32; 3], radius: f32, color: [f32; 4]) {
+        for normal in [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] {
+            self.circle(center, normal, radius, color);
+        }
+    }
+
+    /// The frustum of a column-major view-projection with wgpu's `0..1` clip depth, e.g.
+    /// a culling camera or a shadow cascade. The projection must have a finite far plane.
+    pub fn frustum(&mut self, view_proj: &[[f32; 4]; 4], color: [f32; 4]) {
+        let inverse = gpu_util::invert(view_proj);
+        let corners = std::array::from_fn(|i| {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            let p: [f32; 4] = std::array::from_fn(|row| (0..4).map(|col| inverse[col][row] * [x, y, z, 1.0][col]).sum());
+            [p[0] / p[3], p[1] / p[3], p[2] / p[3]]
+        });
+        self.box_corners(&corners, color);
+    }
+
+    /// The x (red), y (green) and z (blue) axes of a column-major transform, `length`
+    /// long in its local units.
+    pub fn axes(&mut self, transform: &[[f32; 4]; 4], length: f32) {
+        let origin = [transform[3][0], transform[3][1], transform[3][2]];
+        let colors = [[1.0, 0.2, 0.2, 1.0], [0.2, 1.0, 0.2, 1.0], [0.2, 0.4, 1.0, 1.0]];
+        for (axis, color) in colors.into_iter().enumerate() {
+            let end = std::array::from_fn(|i| origin[i] + transform[axis][i] * length);
+            self.line(origin, end, color);
+        }
+    }
+
+    /// Write the lines into the vertex buffer, growing it if needed.
+    ///
+    /// Written with `Queue::write_buffer`, so upload once per submission.
+    pub fn upload(&mut self) {
+        let count = (self.tested.len() + self.overlay.len()) as u64;
+        if count > self.vertex_capacity {
+            self.vertex_capacity = count.next_power_of_two();
+            self.vertices = vertex_buffer(&self.device, self.vertex_capacity);
+        }
+        if !self.tested.is_empty() {
+            self.queue.write_buffer(&self.vertices, 0, bytemuck::cast_slice(&self.tested));
+        }
+        if !self.overlay.is_empty() {
+            let offset = (self.tested.len() * size_of::<GizmoVertex>()) as u64;
+            self.queue.write_buffer(&self.vertices, offset, bytemuck::cast_slice(&self.overlay));
+        }
+        self.uploaded = (self.tested.len() as u32, self.overlay.len() as u32);
+    }
+
+    /// Draw the lines of the last [`upload`](Self::upload) with `camera`'s view-projection.
+    /// Can be called for several views.
+    pub fn render(&mut self, pass: &mut RenderPass, camera: &CameraManager, target: &GizmoTarget) {
+        let (tested, overlay) = self.uploaded;
+        if tested + overlay == 0 {
+            return;
+        }
+        let _span = trace_span!("gizmos", vertices = tested + overlay);
+        pass.set_bind_group(CAMERA_GROUP, camera.bind_group(), &[]);
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        for (depth_test, vertices) in [(true, 0..tested), (false, tested..tested + overlay)] {
+            if vertices.is_empty() {
+                continue;
+            }
+            let pipeline = self.pipelines.entry((*target, depth_test)).or_insert_with(|| {
+                let _span = trace_span!("gizmo_pipeline_miss");
+                create_pipeline(&self.device, &self.module, &self.pipeline_layout, target, depth_test, self.reversed_z)
+            });
+            pass.set_pipeline(pipeline);
+            pass.draw(vertices, 0..1);
+        }
+    }
+
+    /// Remove all shapes, keeping the vertex buffer and the last upload.
+    pub fn clear(&mut self) {
+        self.tested.clear();
+        self.overlay.clear();
+    }
+
+    /// Number of line segments added since the last [`clear`](Self::clear).
+    pub fn len(&self) -> usize {
+        (self.tested.len() + self.overlay.len()) / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tested.is_empty() && self.overlay.is_empty()
+    }
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > f32::EPSILON { v.map(|c| c / length) } else { [0.0, 1.0, 0.0] }
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+/// Two unit vectors orthogonal to `normal` and each other.
+fn orthonormal_basis(normal: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let helper = if normal[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+    let u = normalize(cross(normal, helper));
+    (u, cross(normal, u))
+}
+
+fn create_pipeline(
+    device: &Device,
+    module: &ShaderModule,
+    layout: &PipelineLayout,
+    target: &GizmoTarget,
+    depth_test: bool,
+    reversed_z: bool,
+) -> RenderPipeline {
+    let depth_compare = match (depth_test, reversed_z) {
+        (false, _) => CompareFunction::Always,
+        (true, false) => CompareFunction::LessEqual,
+        (true, true) => CompareFunction::GreaterEqual,
+    };
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("gizmo pipeline")),
+        layout: Some(layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[VERTEX_LAYOUT],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format: target.format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        depth_stencil: target.depth_format.map(|format| DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: target.sample_count,
+            ..Default::default()
+        },
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+fn vertex_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(device, "gizmo vertices", capacity * VERTEX_LAYOUT.array_stride, BufferUsages::VERTEX | BufferUsages::COPY_DST)
+}
@@ -0,0 +1,651 @@
+//! glTF 2.0 import (feature `gltf`).
+//!
+//! [`load_gltf`] reads a `.gltf` or `.glb` file and uploads everything needed to
+//! draw it: primitives go into the [`MeshManager`], images become textures, and
+//! the node hierarchy, materials and skins are returned as a [`GltfScene`].
+//! [`GltfScene::draws`] then flattens the default scene into one draw per
+//! primitive with its world transform and material textures:
+//! ```ignore
+//! let scene = load_gltf(&device, &queue, &mut meshes, "assets/helmet.glb")?;
+//! // Inside a render pass
+//! for draw in scene.draws() {
+//!     let options = meshes.layouts().pipeline_options(draw.layout, base_options.clone());
+//!     write_model_matrix(&queue, &model_buffer, draw.transform);
+//!     render_manager.render_with_textures(&draw.views, shader_path, &options, &[&camera, &model_buffer], &mut pass);
+//!     meshes.draw(&mut pass, draw.mesh, &options, 0..1);
+//! }
+//! ```
+//!
+//! Primitives with joints and weights use the [`SKINNED`](VertexLayoutId::SKINNED)
+//! layout, all others [`POSITION_NORMAL_UV_TANGENT`](VertexLayoutId::POSITION_NORMAL_UV_TANGENT).
+//! Missing normals are computed from the triangles and missing tangents from the UVs.
+//! Only triangle lists are imported; other primitive modes are skipped.
+//!
+//! ## Animation
+//! Skinned primitives are additionally prepared for the compute
+//! [`SkinningPass`](crate::skinning::SkinningPass): [`GltfScene::skinned`] holds their
+//! bind-pose [`SkinVertex`]es and an output mesh to skin into and draw.
+//! [`GltfScene::skeleton`] and [`GltfScene::animation_clip`] convert skins and
+//! animations for the [`animation`](crate::animation) module. Skinned output is in world
+//! space, so it is drawn without the node transform.
+//!
+//! Primitives with morph targets are prepared for the [`MorphPass`](crate::morph::MorphPass)
+//! in [`GltfScene::morphs`]; weight animations target the node of the mesh and are
+//! sampled with [`AnimationClip::sample_weights`]. Morphing and skinning the same
+//! primitive is not chained: each pass writes its own output mesh.
+//!
+//! ## Material textures
+//! [`GltfDraw::views`] binds, in this order, the base color (sRGB), normal and
+//! metallic-roughness textures. Missing textures are replaced by a 1x1 white
+//! texture (and a flat normal), so every material has the same bind group layout
+//! and the factors of [`GltfMaterial`] apply unchanged.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use wgpu::{BufferUsages, Device, Queue, TextureFormat, TextureView};
+use crate::animation::{AnimationChannel, AnimationClip, ChannelValues, Interpolation, Joint, JointTransform, Skeleton};
+use crate::gpu_util::{mul_matrices, IDENTITY};
+use crate::meshes::{MeshHandle, MeshManager, SkinnedVertex, TangentVertex, VertexLayoutId};
+use crate::morph::MorphTarget;
+use crate::primitives::{vertex_normals, MeshData};
+use crate::skinning::SkinVertex;
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+/// A glTF file that could not be read or imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GltfError {
+    /// Path or label of the file.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to import {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+/// How a material's alpha is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GltfAlphaMode {
+    Opaque,
+    /// Fragments with alpha below the cutoff are discarded.
+    Mask(f32),
+    Blend,
+}
+
+/// PBR metallic-roughness material of a glTF file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub emissive_factor: [f32; 3],
+    /// Index into [`GltfScene::textures`].
+    pub base_color_texture: Option<usize>,
+    /// Index into [`GltfScene::textures`].
+    pub normal_texture: Option<usize>,
+    /// Index into [`GltfScene::textures`].
+    pub metallic_roughness_texture: Option<usize>,
+    pub alpha_mode: GltfAlphaMode,
+    pub double_sided: bool,
+}
+
+/// One uploaded primitive of a glTF mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GltfPrimitive {
+    pub mesh: MeshHandle,
+    pub layout: VertexLayoutId,
+    /// Index into [`GltfScene::materials`], `None` for the glTF default material.
+    pub material: Option<usize>,
+}
+
+/// A glTF mesh: the primitives drawn together for a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<GltfPrimitive>,
+    /// Default morph target weights, empty without morph targets.
+    pub weights: Vec<f32>,
+}
+
+/// A node of the glTF hierarchy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Transform relative to the parent, column-major.
+    pub local_transform: [[f32; 4]; 4],
+    /// Index into [`GltfScene::meshes`].
+    pub mesh: Option<usize>,
+    /// Index into [`GltfScene::skins`].
+    pub skin: Option<usize>,
+}
+
+/// Joints of a skinned mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfSkin {
+    pub name: Option<String>,
+    /// Node indices of the joints, in the order the vertex joint indices refer to.
+    pub joints: Vec<usize>,
+    /// One matrix per joint, identity where the file has none.
+    pub inverse_bind_matrices: Vec<[[f32; 4]; 4]>,
+}
+
+/// Skinning input and output of a primitive with joints and weights.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfSkinnedPrimitive {
+    /// Bind-pose vertices for [`SkinningPass::create_mesh`](crate::skinning::SkinningPass::create_mesh).
+    pub vertices: Vec<SkinVertex>,
+    /// [`POSITION_NORMAL_UV_TANGENT`](VertexLayoutId::POSITION_NORMAL_UV_TANGENT) mesh with a
+    /// `STORAGE` vertex buffer, skinned with [`SkinOutput::TANGENT_VERTEX`](crate::skinning::SkinOutput::TANGENT_VERTEX).
+    pub output: MeshHandle,
+}
+
+/// Morph targets of a primitive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfMorphPrimitive {
+    /// Base positions and normals for [`MorphPass::create_mesh`](crate::morph::MorphPass::create_mesh).
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub targets: Vec<MorphTarget>,
+    /// [`POSITION_NORMAL_UV_TANGENT`](VertexLayoutId::POSITION_NORMAL_UV_TANGENT) mesh with a
+    /// `STORAGE` vertex buffer to morph into and draw.
+    pub output: MeshHandle,
+}
+
+/// One primitive of [`GltfScene::draws`], ready to bind and draw.
+pub struct GltfDraw<'a> {
+    /// Index into [`GltfScene::nodes`].
+    pub node: usize,
+    /// World transform of the node, column-major.
+    pub transform: [[f32; 4]; 4],
+    pub mesh: MeshHandle,
+    pub layout: VertexLayoutId,
+    pub material: Option<&'a GltfMaterial>,
+    /// Base color, normal and metallic-roughness textures.
+    pub views: [&'a TextureView; 3],
+}
+
+/// Everything imported from a glTF file.
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    /// One texture per glTF image.
+    pub textures: Vec<LoadedTexture>,
+    pub nodes: Vec<GltfNode>,
+    pub skins: Vec<GltfSkin>,
+    /// Animations with channels targeting node indices, see [`animation_clip`](Self::animation_clip).
+    pub animations: Vec<AnimationClip>,
+    /// Skinning data of skinned primitives, keyed by their [`GltfPrimitive::mesh`].
+    pub skinned: HashMap<MeshHandle, GltfSkinnedPrimitive>,
+    /// Morph targets of primitives that have any, keyed by their [`GltfPrimitive::mesh`].
+    pub morphs: HashMap<MeshHandle, GltfMorphPrimitive>,
+    /// Root nodes of the default scene (or the first scene if none is marked default).
+    pub roots: Vec<usize>,
+    white: LoadedTexture,
+    flat_normal: LoadedTexture,
+}
+
+impl GltfScene {
+    /// World transforms of all nodes, indexed like [`nodes`](Self::nodes).
+    ///
+    /// Nodes outside the scene of [`roots`](Self::roots) keep their local transform.
+    pub fn world_transforms(&self) -> Vec<[[f32; 4]; 4]> {
+        let mut world: Vec<_> = self.nodes.iter().map(|node| node.local_transform).collect();
+        let mut stack: Vec<(usize, [[f32; 4]; 4])> = self.roots.iter().map(|&root| (root, IDENTITY)).collect();
+        while let Some((index, parent)) = stack.pop() {
+            world[index] = mul_matrices(&parent, &self.nodes[index].local_transform);
+            stack.extend(self.nodes[index].children.iter().map(|&child| (child, world[index])));
+        }
+        world
+    }
+
+    /// All primitives of the nodes reachable from [`roots`](Self::roots).
+    pub fn draws(&self) -> Vec<GltfDraw<'_>> {
+        let world = self.world_transforms();
+        let mut draws = Vec::new();
+        let mut stack = self.roots.clone();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            stack.extend(&node.children);
+            let Some(mesh) = node.mesh else {
+                continue;
+            };
+            for primitive in &self.meshes[mesh].primitives {
+                let material = primitive.material.map(|m| &self.materials[m]);
+                draws.push(GltfDraw {
+                    node: index,
+                    transform: world[index],
+                    mesh: primitive.mesh,
+                    layout: primitive.layout,
+                    material,
+                    views: self.material_views(material),
+                });
+            }
+        }
+        draws
+    }
+
+    /// The joints of skin `skin` as a [`Skeleton`], rooted at the world transform of
+    /// the first root joint's parent node.
+    pub fn skeleton(&self, skin: usize) -> Skeleton {
+        let skin = &self.skins[skin];
+        let world = self.world_transforms();
+        // The nearest ancestor that is a joint of this skin.
+        let joint_parent = |node: usize| {
+            let mut parent = self.nodes[node].parent;
+            while let Some(index) = parent {
+                if let Some(joint) = skin.joints.iter().position(|&j| j == index) {
+                    return Some(joint);
+                }
+                parent = self.nodes[index].parent;
+            }
+            None
+        };
+        let joints: Vec<Joint> = skin
+            .joints
+            .iter()
+            .map(|&node| Joint {
+                name: self.nodes[node].name.clone(),
+                parent: joint_parent(node),
+                rest: JointTransform::from_matrix(&self.nodes[node].local_transform),
+            })
+            .collect();
+        let root_transform = joints
+            .iter()
+            .zip(&skin.joints)
+            .find(|(joint, _)| joint.parent.is_none())
+            .and_then(|(_, &node)| self.nodes[node].parent)
+            .map_or(IDENTITY, |parent| world[parent]);
+        Skeleton::new(joints, skin.inverse_bind_matrices.clone()).with_root_transform(root_transform)
+    }
+
+    /// Animation `animation` retargeted to the joints of skin `skin`.
+    ///
+    /// Channels of nodes that are not joints of the skin are dropped.
+    pub fn animation_clip(&self, animation: usize, skin: usize) -> AnimationClip {
+        let joints = &self.skins[skin].joints;
+        let clip = &self.animations[animation];
+        let channels = clip
+            .channels()
+            .iter()
+            .filter_map(|channel| {
+                let target = joints.iter().position(|&node| node == channel.target)?;
+                Some(AnimationChannel { target, ..channel.clone() })
+            })
+            .collect();
+        AnimationClip::new(clip.name.clone(), channels)
+    }
+
+    /// Base color, normal and metallic-roughness views of `material`, with fallbacks.
+    pub fn material_views(&self, material: Option<&GltfMaterial>) -> [&TextureView; 3] {
+        let texture = |index: Option<usize>| index.map(|i| &self.textures[i].view);
+        [
+            texture(material.and_then(|m| m.base_color_texture)).unwrap_or(&self.white.view),
+            texture(material.and_then(|m| m.normal_texture)).unwrap_or(&self.flat_normal.view),
+            texture(material.and_then(|m| m.metallic_roughness_texture)).unwrap_or(&self.white.view),
+        ]
+    }
+}
+
+/// Import a `.gltf` (with its external buffers and images) or `.glb` file.
+pub fn load_gltf(device: &Device, queue: &Queue, meshes: &mut MeshManager, path: impl AsRef<Path>) -> Result<GltfScene, GltfError> {
+    let source = read_gltf(path.as_ref())?;
+    upload_gltf(device, queue, meshes, source)
+}
+
+/// A glTF document with its buffers and images read on a loader thread of the `AssetServer` (feature `assets`).
+pub struct GltfSource {
+    label: String,
+    document: ::gltf::Document,
+    buffers: Vec<::gltf::buffer::Data>,
+    images: Vec<::gltf::image::Data>,
+}
+
+/// The file reading and image decoding half of [`load_gltf`], without GPU access.
+pub(crate) fn read_gltf(path: &Path) -> Result<GltfSource, GltfError> {
+    let label = path.display().to_string();
+    let (document, buffers, images) = ::gltf::import(path).map_err(|e| error(&label, e))?;
+    Ok(GltfSource {
+        label,
+        document,
+        buffers,
+        images,
+    })
+}
+
+/// The parsing and image decoding half of [`load_gltf_slice`].
+pub(crate) fn read_gltf_slice(label: &str, bytes: &[u8]) -> Result<GltfSource, GltfError> {
+    let (document, buffers, images) = ::gltf::import_slice(bytes).map_err(|e| error(label, e))?;
+    Ok(GltfSource {
+        label: label.to_string(),
+        document,
+        buffers,
+        images,
+    })
+}
+
+/// Create the textures and meshes of a [`read_gltf`] result.
+pub(crate) fn upload_gltf(device: &Device, queue: &Queue, meshes: &mut MeshManager, source: GltfSource) -> Result<GltfScene, GltfError> {
+    import(device, queue, meshes, &source.label, &source.document, &source.buffers, &source.images)
+}
+
+/// Import a `.glb` file, or a `.gltf` file with embedded data, from memory.
+pub fn load_gltf_slice(device: &Device, queue: &Queue, meshes: &mut MeshManager, label: &str, bytes: &[u8]) -> Result<GltfScene, GltfError> {
+    let source = read_gltf_slice(label, bytes)?;
+    upload_gltf(device, queue, meshes, source)
+}
+
+fn import(
+    device: &Device,
+    queue: &Queue,
+    meshes: &mut MeshManager,
+    label: &str,
+    document: &::gltf::Document,
+    buffers: &[::gltf::buffer::Data],
+    images: &[::gltf::image::Data],
+) -> Result<GltfScene, GltfError> {
+    let materials: Vec<GltfMaterial> = document.materials().map(import_material).collect();
+
+    // Color textures are sampled as sRGB, data textures (normals, metallic-roughness) linearly.
+    let mut srgb = vec![false; images.len()];
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        for info in [pbr.base_color_texture(), material.emissive_texture()].into_iter().flatten() {
+            srgb[info.texture().source().index()] = true;
+        }
+    }
+    let textures = images
+        .iter()
+        .enumerate()
+        .map(|(index, image)| {
+            let rgba = to_rgba8(image).ok_or_else(|| error(label, format!("image {} has unsupported format {:?}", index, image.format)))?;
+            let mut request = TextureRequest::rgba8(format!("{} image {}", label, index), image.width, image.height, rgba);
+            if !srgb[index] {
+                request.format = TextureFormat::Rgba8Unorm;
+            }
+            Ok(create_texture(device, queue, &request))
+        })
+        .collect::<Result<Vec<_>, GltfError>>()?;
+    // glTF texture indices refer to textures, which refer to images.
+    let texture_image: Vec<usize> = document.textures().map(|t| t.source().index()).collect();
+    let materials = materials
+        .into_iter()
+        .map(|material| GltfMaterial {
+            base_color_texture: material.base_color_texture.map(|t| texture_image[t]),
+            normal_texture: material.normal_texture.map(|t| texture_image[t]),
+            metallic_roughness_texture: material.metallic_roughness_texture.map(|t| texture_image[t]),
+            ..material
+        })
+        .collect();
+
+    let mut gltf_meshes = Vec::new();
+    let mut skinned = HashMap::new();
+    let mut morphs = HashMap::new();
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for (index, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let primitive_label = format!("{} {} #{}", label, mesh.name().unwrap_or("mesh"), index);
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<[f32; 3]> = positions.collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let normals: Vec<[f32; 3]> = match reader.read_normals() {
+                Some(normals) => normals.collect(),
+                None => vertex_normals(&positions, &indices),
+            };
+            let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+                Some(uvs) => uvs.into_f32().collect(),
+                None => vec![[0.0; 2]; positions.len()],
+            };
+            let mut data = MeshData {
+                vertices: positions
+                    .iter()
+                    .zip(&normals)
+                    .zip(&uvs)
+                    .map(|((&position, &normal), &uv)| TangentVertex {
+                        position,
+                        normal,
+                        uv,
+                        tangent: [0.0; 4],
+                    })
+                    .collect(),
+                indices,
+            };
+            match reader.read_tangents() {
+                Some(tangents) => data.vertices.iter_mut().zip(tangents).for_each(|(v, t)| v.tangent = t),
+                None => data = data.compute_tangents(),
+            }
+            meshes.prepare_import(&primitive_label, &mut data);
+
+            let skin = reader.read_joints(0).zip(reader.read_weights(0));
+            let (mesh_handle, layout) = match skin {
+                Some((joints, weights)) => {
+                    let vertices: Vec<SkinnedVertex> = data
+                        .vertices
+                        .iter()
+                        .zip(joints.into_u16())
+                        .zip(weights.into_f32())
+                        .map(|((v, joints), weights)| SkinnedVertex {
+                            position: v.position,
+                            normal: v.normal,
+                            uv: v.uv,
+                            tangent: v.tangent,
+                            joints: joints.map(u32::from),
+                            weights,
+                        })
+                        .collect();
+                    let layout = VertexLayoutId::SKINNED;
+                    let handle = meshes.upload(&primitive_label, layout, &vertices, &data.indices);
+                    let output = meshes.upload_with_usage(
+                        &format!("{} skinned", primitive_label),
+                        VertexLayoutId::POSITION_NORMAL_UV_TANGENT,
+                        &data.vertices,
+                        &data.indices,
+                        BufferUsages::STORAGE,
+                    );
+                    let vertices = vertices
+                        .iter()
+                        .map(|v| SkinVertex {
+                            position: [v.position[0], v.position[1], v.position[2], 1.0],
+                            normal: [v.normal[0], v.normal[1], v.normal[2], 0.0],
+                            joints: v.joints,
+                            weights: v.weights,
+                        })
+                        .collect();
+                    skinned.insert(handle, GltfSkinnedPrimitive { vertices, output });
+                    (handle, layout)
+                }
+                None => {
+                    let layout = VertexLayoutId::POSITION_NORMAL_UV_TANGENT;
+                    (meshes.upload(&primitive_label, layout, &data.vertices, &data.indices), layout)
+                }
+            };
+
+            let targets: Vec<MorphTarget> = reader
+                .read_morph_targets()
+                .map(|(positions, normals, _)| MorphTarget {
+                    position_deltas: positions.map_or_else(|| vec![[0.0; 3]; data.vertices.len()], |p| p.collect()),
+                    normal_deltas: normals.map_or_else(Vec::new, |n| n.collect()),
+                })
+                .collect();
+            if !targets.is_empty() {
+                let output = meshes.upload_with_usage(
+                    &format!("{} morphed", primitive_label),
+                    VertexLayoutId::POSITION_NORMAL_UV_TANGENT,
+                    &data.vertices,
+                    &data.indices,
+                    BufferUsages::STORAGE,
+                );
+                morphs.insert(mesh_handle, GltfMorphPrimitive {
+                    positions: data.vertices.iter().map(|v| v.position).collect(),
+                    normals: data.vertices.iter().map(|v| v.normal).collect(),
+                    targets,
+                    output,
+                });
+            }
+            primitives.push(GltfPrimitive {
+                mesh: mesh_handle,
+                layout,
+                material: primitive.material().index(),
+            });
+        }
+        gltf_meshes.push(GltfMesh {
+            name: mesh.name().map(str::to_string),
+            primitives,
+            weights: mesh.weights().map_or_else(Vec::new, <[f32]>::to_vec),
+        });
+    }
+
+    let mut nodes: Vec<GltfNode> = document
+        .nodes()
+        .map(|node| GltfNode {
+            name: node.name().map(str::to_string),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            local_transform: node.transform().matrix(),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            skin: node.skin().map(|skin| skin.index()),
+        })
+        .collect();
+    for index in 0..nodes.len() {
+        for child in nodes[index].children.clone() {
+            nodes[child].parent = Some(index);
+        }
+    }
+
+    let skins = document
+        .skins()
+        .map(|skin| {
+            let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+            let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+            let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                Some(matrices) => matrices.collect(),
+                None => vec![IDENTITY; joints.len()],
+            };
+            GltfSkin {
+                name: skin.name().map(str::to_string),
+                joints,
+                inverse_bind_matrices,
+            }
+        })
+        .collect();
+
+    let animations = document
+        .animations()
+        .map(|animation| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| import_channel(&channel, buffers))
+                .collect();
+            AnimationClip::new(animation.name().map(str::to_string), channels)
+        })
+        .collect();
+
+    let roots = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .map(|scene| scene.nodes().map(|node| node.index()).collect())
+        .unwrap_or_else(|| (0..nodes.len()).filter(|&i| nodes[i].parent.is_none()).collect());
+
+    let pixel = |name: &str, rgba: [u8; 4], format| {
+        let mut request = TextureRequest::rgba8(format!("{} {}", label, name), 1, 1, rgba.to_vec());
+        request.format = format;
+        create_texture(device, queue, &request)
+    };
+    Ok(GltfScene {
+        meshes: gltf_meshes,
+        materials,
+        textures,
+        nodes,
+        skins,
+        animations,
+        skinned,
+        morphs,
+        roots,
+        white: pixel("white", [255; 4], TextureFormat::Rgba8UnormSrgb),
+        flat_normal: pixel("flat normal", [128, 128, 255, 255], TextureFormat::Rgba8Unorm),
+    })
+}
+
+/// Material with texture indices still referring to glTF textures.
+fn import_material(material: ::gltf::Material) -> GltfMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    GltfMaterial {
+        name: material.name().map(str::to_string),
+        base_color_factor: pbr.base_color_factor(),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        emissive_factor: material.emissive_factor(),
+        base_color_texture: pbr.base_color_texture().map(|info| info.texture().index()),
+        normal_texture: material.normal_texture().map(|info| info.texture().index()),
+        metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| info.texture().index()),
+        alpha_mode: match material.alpha_mode() {
+            ::gltf::material::AlphaMode::Opaque => GltfAlphaMode::Opaque,
+            ::gltf::material::AlphaMode::Mask => GltfAlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+            ::gltf::material::AlphaMode::Blend => GltfAlphaMode::Blend,
+        },
+        double_sided: material.double_sided(),
+    }
+}
+
+/// Keyframes of a node channel, `None` for missing data.
+fn import_channel(channel: &::gltf::animation::Channel, buffers: &[::gltf::buffer::Data]) -> Option<AnimationChannel> {
+    use ::gltf::animation::util::ReadOutputs;
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let times: Vec<f32> = reader.read_inputs()?.collect();
+    let values = match reader.read_outputs()? {
+        ReadOutputs::Translations(values) => ChannelValues::Translation(values.collect()),
+        ReadOutputs::Rotations(values) => ChannelValues::Rotation(values.into_f32().collect()),
+        ReadOutputs::Scales(values) => ChannelValues::Scale(values.collect()),
+        ReadOutputs::MorphTargetWeights(values) => {
+            let values: Vec<f32> = values.into_f32().collect();
+            let per_keyframe = if channel.sampler().interpolation() == ::gltf::animation::Interpolation::CubicSpline { 3 } else { 1 };
+            let count = values.len() / (times.len() * per_keyframe).max(1);
+            ChannelValues::Weights { count, values }
+        }
+    };
+    Some(AnimationChannel {
+        target: channel.target().node().index(),
+        interpolation: match channel.sampler().interpolation() {
+            ::gltf::animation::Interpolation::Step => Interpolation::Step,
+            ::gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            ::gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        },
+        times,
+        values,
+    })
+}
+
+/// Expand 8-bit images to tightly packed RGBA.
+fn to_rgba8(image: &::gltf::image::Data) -> Option<Vec<u8>> {
+    use ::gltf::image::Format;
+    let pixels = &image.pixels;
+    Some(match image.format {
+        Format::R8G8B8A8 => pixels.clone(),
+        Format::R8G8B8 => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        Format::R8G8 => pixels.chunks_exact(2).flat_map(|p| [p[0], p[1], 0, 255]).collect(),
+        Format::R8 => pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        _ => return None,
+    })
+}
+
+fn error(label: &str, message: impl fmt::Display) -> GltfError {
+    GltfError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
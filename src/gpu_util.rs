@@ -0,0 +1,256 @@
+//! Small constructors shared by the built-in GPU passes.
+//!
+//! The passes in this crate (Hi-Z, particles, skinning, ...) all create their
+//! pipelines from embedded WGSL and hand-written layouts. These helpers keep
+//! those definitions short and uniform.
+use wgpu::*;
+use crate::labels::namespaced;
+
+/// Compile embedded WGSL source.
+pub(crate) fn shader(device: &Device, label: &str, source: &str) -> ShaderModule {
+    device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(&namespaced(label)),
+        source: ShaderSource::Wgsl(source.into()),
+    })
+}
+
+/// Bind group layout from a list of entries.
+pub(crate) fn bind_group_layout(device: &Device, label: &str, entries: &[BindGroupLayoutEntry]) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some(&namespaced(label)),
+        entries,
+    })
+}
+
+/// Bind group with `resources` bound at consecutive bindings starting at 0.
+pub(crate) fn bind_group(device: &Device, label: &str, layout: &BindGroupLayout, resources: &[BindingResource]) -> BindGroup {
+    let entries: Vec<BindGroupEntry> = resources
+        .iter()
+        .enumerate()
+        .map(|(i, resource)| BindGroupEntry {
+            binding: i as u32,
+            resource: resource.clone(),
+        })
+        .collect();
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some(&namespaced(label)),
+        layout,
+        entries: &entries,
+    })
+}
+
+/// Compute pipeline with an explicit layout.
+pub(crate) fn compute_pipeline(
+    device: &Device,
+    label: &str,
+    module: &ShaderModule,
+    entry_point: &str,
+    layouts: &[&BindGroupLayout],
+) -> ComputePipeline {
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced(label)),
+        bind_group_layouts: layouts,
+        immediate_size: 0,
+    });
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(&namespaced(label)),
+        layout: Some(&layout),
+        module,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+    })
+}
+
+/// Run a single compute dispatch in its own pass.
+pub(crate) fn dispatch(
+    encoder: &mut CommandEncoder,
+    label: &str,
+    pipeline: &ComputePipeline,
+    bind_groups: &[&BindGroup],
+    workgroups: [u32; 3],
+) {
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+        label: Some(&namespaced(label)),
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    for (i, bind_group) in bind_groups.iter().enumerate() {
+        pass.set_bind_group(i as u32, *bind_group, &[]);
+    }
+    pass.dispatch_workgroups(workgroups[0], workgroups[1], workgroups[2]);
+}
+
+pub(crate) fn uniform_entry(binding: u32, visibility: ShaderStages) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn storage_entry(binding: u32, visibility: ShaderStages, read_only: bool) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn texture_entry(
+    binding: u32,
+    visibility: ShaderStages,
+    sample_type: TextureSampleType,
+    view_dimension: TextureViewDimension,
+) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: BindingType::Texture {
+            sample_type,
+            view_dimension,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+#[cfg_attr(not(any(feature = "lighting", feature = "postfx", feature = "gpu_driven")), allow(dead_code))]
+pub(crate) fn storage_texture_entry(
+    binding: u32,
+    visibility: ShaderStages,
+    format: TextureFormat,
+    access: StorageTextureAccess,
+    view_dimension: TextureViewDimension,
+) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension,
+        },
+        count: None,
+    }
+}
+
+#[cfg_attr(not(any(feature = "lighting", feature = "postfx", feature = "text")), allow(dead_code))]
+pub(crate) fn sampler_entry(binding: u32, visibility: ShaderStages, ty: SamplerBindingType) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: BindingType::Sampler(ty),
+        count: None,
+    }
+}
+
+/// Buffer of `size` bytes (at least 16) with the given usage.
+pub(crate) fn buffer(device: &Device, label: &str, size: u64, usage: BufferUsages) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some(&namespaced(label)),
+        size: size.max(16),
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+/// Linear clamp-to-edge sampler.
+#[cfg_attr(not(any(feature = "lighting", feature = "postfx", feature = "text")), allow(dead_code))]
+pub(crate) fn linear_sampler(device: &Device, label: &str) -> Sampler {
+    device.create_sampler(&SamplerDescriptor {
+        label: Some(&namespaced(label)),
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        ..Default::default()
+    })
+}
+
+/// Inverse of an affine column-major matrix (rotation/scale + translation).
+pub(crate) fn invert_affine(m: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let [a, b, c] = [m[0], m[1], m[2]];
+    let t = m[3];
+    // Inverse of the upper 3x3 via the adjugate.
+    let det = a[0] * (b[1] * c[2] - c[1] * b[2]) - b[0] * (a[1] * c[2] - c[1] * a[2]) + c[0] * (a[1] * b[2] - b[1] * a[2]);
+    let inv_det = if det.abs() > f32::EPSILON { 1.0 / det } else { 0.0 };
+    let r = [
+        [
+            (b[1] * c[2] - c[1] * b[2]) * inv_det,
+            (c[1] * a[2] - a[1] * c[2]) * inv_det,
+            (a[1] * b[2] - b[1] * a[2]) * inv_det,
+        ],
+        [
+            (c[0] * b[2] - b[0] * c[2]) * inv_det,
+            (a[0] * c[2] - c[0] * a[2]) * inv_det,
+            (b[0] * a[2] - a[0] * b[2]) * inv_det,
+        ],
+        [
+            (b[0] * c[1] - c[0] * b[1]) * inv_det,
+            (c[0] * a[1] - a[0] * c[1]) * inv_det,
+            (a[0] * b[1] - b[0] * a[1]) * inv_det,
+        ],
+    ];
+    let translation = [
+        -(r[0][0] * t[0] + r[1][0] * t[1] + r[2][0] * t[2]),
+        -(r[0][1] * t[0] + r[1][1] * t[1] + r[2][1] * t[2]),
+        -(r[0][2] * t[0] + r[1][2] * t[1] + r[2][2] * t[2]),
+    ];
+    [
+        [r[0][0], r[0][1], r[0][2], 0.0],
+        [r[1][0], r[1][1], r[1][2], 0.0],
+        [r[2][0], r[2][1], r[2][2], 0.0],
+        [translation[0], translation[1], translation[2], 1.0],
+    ]
+}
+
+/// Inverse of a general column-major matrix, e.g. a projection. Singular matrices give zeros.
+pub(crate) fn invert(m: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    // Flatten so that a[i] is element (row i % 4, column i / 4).
+    let a: Vec<f32> = m.iter().flatten().copied().collect();
+    let mut inv = [0.0f32; 16];
+    inv[0] = a[5] * a[10] * a[15] - a[5] * a[11] * a[14] - a[9] * a[6] * a[15] + a[9] * a[7] * a[14] + a[13] * a[6] * a[11] - a[13] * a[7] * a[10];
+    inv[4] = -a[4] * a[10] * a[15] + a[4] * a[11] * a[14] + a[8] * a[6] * a[15] - a[8] * a[7] * a[14] - a[12] * a[6] * a[11] + a[12] * a[7] * a[10];
+    inv[8] = a[4] * a[9] * a[15] - a[4] * a[11] * a[13] - a[8] * a[5] * a[15] + a[8] * a[7] * a[13] + a[12] * a[5] * a[11] - a[12] * a[7] * a[9];
+    inv[12] = -a[4] * a[9] * a[14] + a[4] * a[10] * a[13] + a[8] * a[5] * a[14] - a[8] * a[6] * a[13] - a[12] * a[5] * a[10] + a[12] * a[6] * a[9];
+    inv[1] = -a[1] * a[10] * a[15] + a[1] * a[11] * a[14] + a[9] * a[2] * a[15] - a[9] * a[3] * a[14] - a[13] * a[2] * a[11] + a[13] * a[3] * a[10];
+    inv[5] = a[0] * a[10] * a[15] - a[0] * a[11] * a[14] - a[8] * a[2] * a[15] + a[8] * a[3] * a[14] + a[12] * a[2] * a[11] - a[12] * a[3] * a[10];
+    inv[9] = -a[0] * a[9] * a[15] + a[0] * a[11] * a[13] + a[8] * a[1] * a[15] - a[8] * a[3] * a[13] - a[12] * a[1] * a[11] + a[12] * a[3] * a[9];
+    inv[13] = a[0] * a[9] * a[14] - a[0] * a[10] * a[13] - a[8] * a[1] * a[14] + a[8] * a[2] * a[13] + a[12] * a[1] * a[10] - a[12] * a[2] * a[9];
+    inv[2] = a[1] * a[6] * a[15] - a[1] * a[7] * a[14] - a[5] * a[2] * a[15] + a[5] * a[3] * a[14] + a[13] * a[2] * a[7] - a[13] * a[3] * a[6];
+    inv[6] = -a[0] * a[6] * a[15] + a[0] * a[7] * a[14] + a[4] * a[2] * a[15] - a[4] * a[3] * a[14] - a[12] * a[2] * a[7] + a[12] * a[3] * a[6];
+    inv[10] = a[0] * a[5] * a[15] - a[0] * a[7] * a[13] - a[4] * a[1] * a[15] + a[4] * a[3] * a[13] + a[12] * a[1] * a[7] - a[12] * a[3] * a[5];
+    inv[14] = -a[0] * a[5] * a[14] + a[0] * a[6] * a[13] + a[4] * a[1] * a[14] - a[4] * a[2] * a[13] - a[12] * a[1] * a[6] + a[12] * a[2] * a[5];
+    inv[3] = -a[1] * a[6] * a[11] + a[1] * a[7] * a[10] + a[5] * a[2] * a[11] - a[5] * a[3] * a[10] - a[9] * a[2] * a[7] + a[9] * a[3] * a[6];
+    inv[7] = a[0] * a[6] * a[11] - a[0] * a[7] * a[10] - a[4] * a[2] * a[11] + a[4] * a[3] * a[10] + a[8] * a[2] * a[7] - a[8] * a[3] * a[6];
+    inv[11] = -a[0] * a[5] * a[11] + a[0] * a[7] * a[9] + a[4] * a[1] * a[11] - a[4] * a[3] * a[9] - a[8] * a[1] * a[7] + a[8] * a[3] * a[5];
+    inv[15] = a[0] * a[5] * a[10] - a[0] * a[6] * a[9] - a[4] * a[1] * a[10] + a[4] * a[2] * a[9] + a[8] * a[1] * a[6] - a[8] * a[2] * a[5];
+    let det = a[0] * inv[0] + a[1] * inv[4] + a[2] * inv[8] + a[3] * inv[12];
+    let inv_det = if det.abs() > f32::EPSILON * f32::EPSILON { 1.0 / det } else { 0.0 };
+    std::array::from_fn(|column| std::array::from_fn(|row| inv[column * 4 + row] * inv_det))
+}
+
+/// Column-major identity matrix.
+pub(crate) const IDENTITY: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+/// Column-major `a * b`.
+pub(crate) fn mul_matrices(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut out = [[0.0; 4]; 4];
+    for (column, b_column) in out.iter_mut().zip(b) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b_column[k]).sum();
+        }
+    }
+    out
+}
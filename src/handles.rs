@@ -0,0 +1,132 @@
+//! Copyable handles to cached bind groups.
+//!
+//! Cache lookups borrow the [`RenderManager`] mutably, so a `&BindGroup` returned by
+//! one lookup cannot be held across the next. Fetching a handle per bind group first
+//! and resolving them through `&self` while encoding avoids the conflict:
+//! ```ignore
+//! let material = render_manager.material_bind_group_id(&[&albedo, &normal], &[]);
+//! let camera = render_manager.uniform_bind_group_id(&[&camera_buffer]);
+//!
+//! let mut pass = encoder.begin_render_pass(&pass_descriptor);
+//! pass.set_pipeline(&pipeline);
+//! pass.set_bind_group(0, render_manager.resolve(material), &[]);
+//! pass.set_bind_group(1, render_manager.resolve(camera), &[]);
+//! ```
+//! Handles are valid until the next [`begin_frame`](RenderManager::begin_frame) and keep
+//! their bind group alive until then, even if the cache entry is evicted in between.
+//! They only resolve in the manager that handed them out: a handle from another window's
+//! or device's manager, or from before a [`recreate`](RenderManager::recreate), is rejected.
+//!
+//! [`get_or_create_many`](RenderManager::get_or_create_many) resolves a whole frame's
+//! materials at once, hashing each one once and creating all missing layouts and bind
+//! groups together:
+//! ```ignore
+//! let requests: Vec<_> = visible.iter().map(|m| MaterialRequest::new(&m.views)).collect();
+//! let ids = render_manager.get_or_create_many(&requests);
+//! ```
+//!
+//! [`RenderManager`]: crate::renderer::RenderManager
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use wgpu::{BindGroup, Sampler, TextureView};
+use crate::pipelines::TextureAccess;
+
+/// Handle to a bind group registered with the [`RenderManager`](crate::renderer::RenderManager)
+/// in the current frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindGroupId {
+    owner: u64,
+    frame: u64,
+    index: u32,
+}
+
+/// Source of the owner ids of [`BindGroupHandles`], unique per table in the process.
+static NEXT_OWNER: AtomicU64 = AtomicU64::new(0);
+
+/// One material of a batched lookup, see
+/// [`RenderManager::get_or_create_many`](crate::renderer::RenderManager::get_or_create_many).
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialRequest<'a> {
+    pub texture_views: &'a [&'a TextureView],
+    /// Binding type per view as in [`PipelineOptions::texture_access`](crate::pipelines::PipelineOptions::texture_access),
+    /// missing entries are sampled.
+    pub access: &'a [TextureAccess],
+    /// Comparison sampler and depth array of the shadow bindings.
+    pub shadow: Option<(&'a Sampler, &'a TextureView)>,
+}
+
+impl<'a> MaterialRequest<'a> {
+    /// A material of sampled `texture_views` without shadow bindings.
+    pub fn new(texture_views: &'a [&'a TextureView]) -> Self {
+        Self {
+            texture_views,
+            access: &[],
+            shadow: None,
+        }
+    }
+
+    pub fn with_access(mut self, access: &'a [TextureAccess]) -> Self {
+        self.access = access;
+        self
+    }
+
+    pub fn with_shadow(mut self, sampler: &'a Sampler, view: &'a TextureView) -> Self {
+        self.shadow = Some((sampler, view));
+        self
+    }
+}
+
+/// Bind groups handed out as [`BindGroupId`]s in the current frame.
+pub(crate) struct BindGroupHandles {
+    /// Stamped into every id, so ids of other tables are told apart.
+    owner: u64,
+    frame: u64,
+    bind_groups: Vec<BindGroup>,
+    indices: HashMap<BindGroup, u32>,
+}
+
+impl BindGroupHandles {
+    pub(crate) fn new() -> Self {
+        Self {
+            owner: NEXT_OWNER.fetch_add(1, Ordering::Relaxed),
+            frame: 0,
+            bind_groups: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Start a new frame, invalidating the handles of the previous one.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        if frame != self.frame {
+            self.frame = frame;
+            self.bind_groups.clear();
+            self.indices.clear();
+        }
+    }
+
+    /// Handle to `bind_group`, the same one for every registration in a frame.
+    pub(crate) fn insert(&mut self, bind_group: &BindGroup) -> BindGroupId {
+        let index = *self.indices.entry(bind_group.clone()).or_insert_with(|| {
+            self.bind_groups.push(bind_group.clone());
+            self.bind_groups.len() as u32 - 1
+        });
+        BindGroupId { owner: self.owner, frame: self.frame, index }
+    }
+
+    /// The bind group of `id`, `None` if it is from an earlier frame or another table.
+    pub(crate) fn get(&self, id: BindGroupId) -> Option<&BindGroup> {
+        if id.owner != self.owner || id.frame != self.frame {
+            return None;
+        }
+        self.bind_groups.get(id.index as usize)
+    }
+
+    pub(crate) fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Whether `id` was handed out by this table, in any frame.
+    pub(crate) fn owns(&self, id: BindGroupId) -> bool {
+        id.owner == self.owner
+    }
+}
@@ -0,0 +1,178 @@
+//! Radiance `.hdr` and OpenEXR image loading (feature `hdr`).
+//!
+//! HDR images keep their full range as `f32` RGBA and are stored on the GPU as
+//! `Rgba16Float`, or as `Rg11b10Ufloat` at half the size for data without alpha or
+//! negative values, like most environment maps:
+//! ```ignore
+//! let sky = HdrImage::open("environments/meadow.exr")?;
+//! let texture = sky.upload(&device, &queue, "meadow", HdrFormat::Rg11b10Ufloat);
+//! ```
+//! [`to_baked`](HdrImage::to_baked) converts to a [`BakedTexture`] instead, to be
+//! written as `.rmtex`. The `AssetServer` loads `.hdr` and `.exr` files as
+//! `Rgba16Float` textures.
+use std::fmt;
+use std::path::Path;
+use wgpu::{Device, Extent3d, Queue, TextureFormat, TextureUsages};
+use crate::texture_bake::BakedTexture;
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+/// An HDR image that could not be read or decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HdrError {
+    /// Path or label of the image.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to decode {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for HdrError {}
+
+fn error(label: &str, message: impl fmt::Display) -> HdrError {
+    HdrError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// GPU format of an HDR texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HdrFormat {
+    /// 8 bytes per texel, signed, with alpha.
+    Rgba16Float,
+    /// 4 bytes per texel, no alpha, negative values clamp to 0 and precision is 6 bits
+    /// of mantissa for red and green, 5 for blue.
+    Rg11b10Ufloat,
+}
+
+impl HdrFormat {
+    pub fn texture_format(self) -> TextureFormat {
+        match self {
+            HdrFormat::Rgba16Float => TextureFormat::Rgba16Float,
+            HdrFormat::Rg11b10Ufloat => TextureFormat::Rg11b10Ufloat,
+        }
+    }
+}
+
+/// Whether `path` has an extension this module decodes.
+pub fn is_hdr_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr") || extension.eq_ignore_ascii_case("exr"))
+}
+
+/// A decoded HDR image in linear `f32` RGBA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed RGBA, `width * height * 4` values.
+    pub rgba: Vec<f32>,
+}
+
+impl HdrImage {
+    /// Wrap already decoded pixels.
+    pub fn from_rgba32f(width: u32, height: u32, rgba: Vec<f32>) -> Self {
+        assert_eq!(rgba.len(), (width * height * 4) as usize, "RGBA32F data must be width * height * 4 values");
+        Self { width, height, rgba }
+    }
+
+    /// Read and decode a `.hdr` or `.exr` file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, HdrError> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let image = image::open(path).map_err(|e| error(&label, e))?.to_rgba32f();
+        Ok(Self::from_rgba32f(image.width(), image.height(), image.into_raw()))
+    }
+
+    /// Decode an encoded `.hdr` or `.exr` file from memory. The format is detected from
+    /// the content.
+    pub fn from_memory(label: &str, bytes: &[u8]) -> Result<Self, HdrError> {
+        let image = image::load_from_memory(bytes).map_err(|e| error(label, e))?.to_rgba32f();
+        Ok(Self::from_rgba32f(image.width(), image.height(), image.into_raw()))
+    }
+
+    /// Texel data in `format`, tightly packed.
+    pub fn encode(&self, format: HdrFormat) -> Vec<u8> {
+        let _span = trace_span!("hdr_encode", width = self.width, height = self.height, format = ?format);
+        match format {
+            HdrFormat::Rgba16Float => self.rgba.iter().flat_map(|value| f16_bits(*value).to_le_bytes()).collect(),
+            HdrFormat::Rg11b10Ufloat => self
+                .rgba
+                .chunks_exact(4)
+                .flat_map(|texel| {
+                    let packed = ufloat_bits(texel[0], 4) | ufloat_bits(texel[1], 4) << 11 | ufloat_bits(texel[2], 5) << 22;
+                    packed.to_le_bytes()
+                })
+                .collect(),
+        }
+    }
+
+    /// A single-mip [`BakedTexture`] in `format`.
+    pub fn to_baked(&self, format: HdrFormat) -> BakedTexture {
+        BakedTexture {
+            format: format.texture_format(),
+            width: self.width,
+            height: self.height,
+            mips: vec![self.encode(format)],
+        }
+    }
+
+    /// Create a sampled texture in `format`. It can also be copied from, e.g. by an
+    /// IBL prefilter pass.
+    pub fn upload(&self, device: &Device, queue: &Queue, label: &str, format: HdrFormat) -> LoadedTexture {
+        create_texture(
+            device,
+            queue,
+            &TextureRequest {
+                label: label.to_string(),
+                size: Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+                format: format.texture_format(),
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC,
+                data: Some(self.encode(format)),
+            },
+        )
+    }
+}
+
+/// IEEE half-precision bits of `value`, rounding to nearest (ties away from zero).
+/// Out of range values become infinity, tiny ones subnormals or zero.
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        let shift = (14 - half_exponent) as u32;
+        if shift > 24 {
+            return sign;
+        }
+        let full = mantissa | 0x80_0000;
+        return sign | ((full + (1 << (shift - 1))) >> shift) as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity.
+    let rounded = ((half_exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+    sign | rounded as u16
+}
+
+/// Bits of an unsigned 5-bit-exponent float with `10 - dropped` mantissa bits, as in
+/// `Rg11b10Ufloat`. Shares the exponent bias of half floats, so it is a truncated half.
+fn ufloat_bits(value: f32, dropped: u32) -> u32 {
+    (f16_bits(value.max(0.0)) >> dropped) as u32
+}
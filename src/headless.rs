@@ -0,0 +1,331 @@
+//! Rendering without a window.
+//!
+//! [`request_headless_device`] creates a device without a surface, and a
+//! [`HeadlessRenderer`] replaces the swapchain: every frame renders into a color
+//! target (and optional depth target) taken from a [`TargetPool`], and
+//! [`end_frame`](HeadlessRenderer::end_frame) queues its readback through a
+//! [`TextureReadback`]. This is the setup for server-side rendering, thumbnail
+//! generation and tests that compare rendered images.
+//!
+//! ## Frame flow
+//! ```ignore
+//! let (_adapter, device, queue) = pollster::block_on(request_headless_device(&Instance::default(), false))?;
+//! let mut headless = HeadlessRenderer::new(&device, &queue, TextureFormat::Rgba8UnormSrgb, Some(TextureFormat::Depth32Float), 3);
+//!
+//! // Streaming: one frame per tick, images arrive a few frames later
+//! let frame = headless.begin_frame(1920, 1080);
+//! {
+//!     let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!         color_attachments: &[Some(frame.color_attachment(Color::BLACK))],
+//!         depth_stencil_attachment: frame.depth_attachment(1.0),
+//!         ..Default::default()
+//!     });
+//!     // Draw
+//! }
+//! headless.end_frame(&mut encoder, frame);
+//! queue.submit([encoder.finish()]);
+//! for image in headless.poll() {
+//!     stream.send(image.data);
+//! }
+//!
+//! // Tests: render one image and wait for it
+//! let image = headless.render_image(64, 64, |encoder, frame| draw_scene(encoder, frame)).unwrap();
+//! assert_eq!(image.pixel(32, 32), Some(&[255, 0, 0, 255][..]));
+//! ```
+use std::collections::VecDeque;
+use std::fmt;
+use wgpu::*;
+use crate::readback::{ReadbackImage, TextureReadback};
+use crate::labels::namespaced;
+
+/// Errors of [`request_headless_device`].
+#[derive(Debug, Clone)]
+pub enum HeadlessError {
+    /// No adapter is available, not even a fallback one if requested.
+    NoAdapter,
+    /// The adapter refused to create a device.
+    RequestDevice(RequestDeviceError),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::NoAdapter => f.write_str("no adapter available"),
+            HeadlessError::RequestDevice(e) => write!(f, "failed to request device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {}
+
+/// Request a high-performance adapter without a surface and a device with the
+/// adapter's limits.
+///
+/// `force_fallback_adapter` selects a software adapter, e.g. for CI machines without a GPU.
+pub async fn request_headless_device(instance: &Instance, force_fallback_adapter: bool) -> Result<(Adapter, Device, Queue), HeadlessError> {
+    let adapter = instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            force_fallback_adapter,
+            compatible_surface: None,
+        })
+        .await
+        .map_err(|_| HeadlessError::NoAdapter)?;
+
+    let (device, queue) = adapter
+        .request_device(&DeviceDescriptor {
+            label: Some(&namespaced("wgpu_render_manager headless device")),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        })
+        .await
+        .map_err(HeadlessError::RequestDevice)?;
+
+    Ok((adapter, device, queue))
+}
+
+/// Render target handed out by a [`TargetPool`].
+#[derive(Debug)]
+pub struct PooledTarget {
+    texture: Texture,
+    view: TextureView,
+}
+
+impl PooledTarget {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.texture.width(), self.texture.height())
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.texture.format()
+    }
+}
+
+/// Reusable render targets, matched by size and format.
+///
+/// Targets can be rendered to, sampled and copied from.
+pub struct TargetPool {
+    device: Device,
+    free: Vec<PooledTarget>,
+}
+
+impl TargetPool {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Take a free target of this size and format, or create one.
+    pub fn acquire(&mut self, width: u32, height: u32, format: TextureFormat) -> PooledTarget {
+        let size = (width.max(1), height.max(1));
+        if let Some(index) = self.free.iter().position(|target| target.size() == size && target.format() == format) {
+            return self.free.swap_remove(index);
+        }
+        trace_event!(width = size.0, height = size.1, ?format, "created pooled target");
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: Some(&namespaced("pooled target")),
+            size: Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        PooledTarget { texture, view }
+    }
+
+    /// Return a target for reuse. Work recorded on it before stays valid, later users
+    /// are ordered after it on the queue.
+    pub fn release(&mut self, target: PooledTarget) {
+        self.free.push(target);
+    }
+
+    /// Number of free targets.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// Drop every free target, e.g. after the output size changed for good.
+    pub fn clear(&mut self) {
+        let evicted = self.free.len();
+        self.free.clear();
+        trace_evict!("target_pool", evicted);
+    }
+}
+
+/// Targets of one headless frame, from [`HeadlessRenderer::begin_frame`].
+#[derive(Debug)]
+pub struct HeadlessFrame {
+    color: PooledTarget,
+    depth: Option<PooledTarget>,
+}
+
+impl HeadlessFrame {
+    pub fn color(&self) -> &PooledTarget {
+        &self.color
+    }
+
+    pub fn depth(&self) -> Option<&PooledTarget> {
+        self.depth.as_ref()
+    }
+
+    /// The target to render the frame into, like a swapchain view.
+    pub fn view(&self) -> &TextureView {
+        &self.color.view
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.color.size()
+    }
+
+    /// Color attachment cleared to `clear`.
+    pub fn color_attachment(&self, clear: Color) -> RenderPassColorAttachment<'_> {
+        RenderPassColorAttachment {
+            view: &self.color.view,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(clear),
+                store: StoreOp::Store,
+            },
+        }
+    }
+
+    /// Depth attachment cleared to `clear`, if the renderer has a depth format.
+    pub fn depth_attachment(&self, clear: f32) -> Option<RenderPassDepthStencilAttachment<'_>> {
+        self.depth.as_ref().map(|depth| RenderPassDepthStencilAttachment {
+            view: &depth.view,
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(clear),
+                store: StoreOp::Store,
+            }),
+            stencil_ops: None,
+        })
+    }
+}
+
+/// Offscreen replacement of the swapchain with pooled targets and frame readback.
+pub struct HeadlessRenderer {
+    device: Device,
+    queue: Queue,
+    format: TextureFormat,
+    depth_format: Option<TextureFormat>,
+    pool: TargetPool,
+    readback: TextureReadback,
+    /// Images read back by [`render_image`](Self::render_image) that belong to earlier frames.
+    ready: VecDeque<ReadbackImage>,
+}
+
+impl HeadlessRenderer {
+    /// Render into `format` targets, with `depth_format` depth targets if given. At most
+    /// `max_in_flight` frames are read back at a time.
+    pub fn new(device: &Device, queue: &Queue, format: TextureFormat, depth_format: Option<TextureFormat>, max_in_flight: usize) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            format,
+            depth_format,
+            pool: TargetPool::new(device),
+            readback: TextureReadback::new(device, max_in_flight),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Color format for pipelines drawing into headless frames.
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn depth_format(&self) -> Option<TextureFormat> {
+        self.depth_format
+    }
+
+    pub fn pool(&mut self) -> &mut TargetPool {
+        &mut self.pool
+    }
+
+    /// Take the targets for a frame of this size.
+    pub fn begin_frame(&mut self, width: u32, height: u32) -> HeadlessFrame {
+        HeadlessFrame {
+            color: self.pool.acquire(width, height, self.format),
+            depth: self.depth_format.map(|format| self.pool.acquire(width, height, format)),
+        }
+    }
+
+    /// Queue the readback of `frame` for when `encoder` is submitted and return its
+    /// targets to the pool. Record after all passes of the frame.
+    ///
+    /// Returns the id of the [`ReadbackImage`], or `None` if the frame was not read back
+    /// because too many frames are still in flight.
+    pub fn end_frame(&mut self, encoder: &mut CommandEncoder, frame: HeadlessFrame) -> Option<u64> {
+        let id = self.readback.copy(encoder, &frame.color.texture);
+        self.pool.release(frame.color);
+        if let Some(depth) = frame.depth {
+            self.pool.release(depth);
+        }
+        id
+    }
+
+    /// Images of the frames read back so far, oldest first.
+    pub fn poll(&mut self) -> Vec<ReadbackImage> {
+        let mut images: Vec<ReadbackImage> = self.ready.drain(..).collect();
+        images.extend(self.readback.poll());
+        images
+    }
+
+    /// Block until every submitted frame was read back and return the images.
+    pub fn wait(&mut self) -> Vec<ReadbackImage> {
+        let mut images: Vec<ReadbackImage> = self.ready.drain(..).collect();
+        images.extend(self.readback.wait());
+        images
+    }
+
+    /// Render one frame with `record`, submit it and wait for its pixels.
+    ///
+    /// Blocks on the GPU, meant for tests and one-off renders. Images of earlier frames
+    /// finished on the way are kept for the next [`poll`](Self::poll).
+    pub fn render_image(&mut self, width: u32, height: u32, record: impl FnOnce(&mut CommandEncoder, &HeadlessFrame)) -> Option<ReadbackImage> {
+        let _span = trace_span!("headless_render_image", width, height);
+        // Make room in the readback ring so this frame is not skipped.
+        while self.readback.is_full() {
+            let finished = self.readback.wait();
+            self.ready.extend(finished);
+        }
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor { label: Some(&namespaced("headless frame")) });
+        let frame = self.begin_frame(width, height);
+        record(&mut encoder, &frame);
+        let id = self.end_frame(&mut encoder, frame)?;
+        self.queue.submit([encoder.finish()]);
+
+        let mut image = None;
+        for finished in self.readback.wait() {
+            if finished.id == id {
+                image = Some(finished);
+            } else {
+                self.ready.push_back(finished);
+            }
+        }
+        image
+    }
+}
@@ -0,0 +1,237 @@
+//! One change-notification bus for every hot-reloadable asset.
+//!
+//! Instead of each subsystem watching files, a single `FileWatcher` (feature `native`) polls the
+//! modification times of registered paths and publishes a [`ReloadEvent`] on a
+//! [`ReloadBus`]. Systems subscribe to the events they care about and rebuild exactly
+//! the GPU resources that depend on the changed asset:
+//! ```ignore
+//! let bus = ReloadBus::new();
+//! let mut watcher = FileWatcher::new(bus.clone());
+//! watcher.watch("shaders/water.wgsl", AssetKind::Shader);
+//! watcher.watch("textures/brick.png", AssetKind::Texture);
+//! let shaders = bus.subscribe_kind(AssetKind::Shader);
+//! assets.set_reload_bus(&bus);
+//!
+//! // every frame
+//! watcher.poll();
+//! for event in shaders.try_iter() {
+//!     // a shader saved mid-edit keeps the last good version
+//!     if let Err(e) = render_manager.handle_reload(&event) {
+//!         eprintln!("{e}");
+//!     }
+//! }
+//! assets.update(&mut meshes);
+//! ```
+//!
+//! A reload happens in two stages: the watcher publishes
+//! [`Changed`](ReloadStage::Changed) when the file is written, and the owner of the
+//! asset (e.g. the `AssetServer`) publishes [`Reloaded`](ReloadStage::Reloaded) once the
+//! new version replaced the old one, which is when derived resources like material bind
+//! groups should be rebuilt. Editors can [`publish`](ReloadBus::publish) events directly.
+#[cfg(feature = "native")]
+use std::collections::HashMap;
+#[cfg(feature = "native")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryIter};
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(feature = "native")]
+use std::time::{Duration, Instant, SystemTime};
+
+/// What a reloaded file contains, so subscribers can filter cheaply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    Shader,
+    Texture,
+    Material,
+    Mesh,
+    Scene,
+    Other,
+}
+
+/// How far a reload has progressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReloadStage {
+    /// The file changed on disk; its owner should load it again.
+    Changed,
+    /// The owner replaced the asset; resources derived from it are stale.
+    Reloaded,
+}
+
+/// "Asset `path` changed or was reloaded".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReloadEvent {
+    pub path: PathBuf,
+    pub kind: AssetKind,
+    pub stage: ReloadStage,
+}
+
+impl ReloadEvent {
+    pub fn changed(path: impl Into<PathBuf>, kind: AssetKind) -> Self {
+        Self {
+            path: path.into(),
+            kind,
+            stage: ReloadStage::Changed,
+        }
+    }
+
+    pub fn reloaded(path: impl Into<PathBuf>, kind: AssetKind) -> Self {
+        Self {
+            path: path.into(),
+            kind,
+            stage: ReloadStage::Reloaded,
+        }
+    }
+}
+
+type Filter = Box<dyn Fn(&ReloadEvent) -> bool + Send>;
+
+/// Events published on a [`ReloadBus`] that passed the filter of the subscription.
+///
+/// Dropping the receiver unsubscribes.
+pub struct ReloadReceiver {
+    receiver: Receiver<ReloadEvent>,
+}
+
+impl ReloadReceiver {
+    /// Events received since the last call, without blocking.
+    pub fn try_iter(&self) -> TryIter<'_, ReloadEvent> {
+        self.receiver.try_iter()
+    }
+}
+
+/// Fan-out of [`ReloadEvent`]s to any number of subscribers.
+///
+/// Cheap to clone, all clones publish to the same subscribers, from any thread.
+#[derive(Clone, Default)]
+pub struct ReloadBus {
+    subscribers: Arc<Mutex<Vec<(Filter, Sender<ReloadEvent>)>>>,
+}
+
+impl ReloadBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive every event for which `filter` returns `true`.
+    pub fn subscribe(&self, filter: impl Fn(&ReloadEvent) -> bool + Send + 'static) -> ReloadReceiver {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((Box::new(filter), sender));
+        ReloadReceiver { receiver }
+    }
+
+    /// Receive all events about assets of `kind`.
+    pub fn subscribe_kind(&self, kind: AssetKind) -> ReloadReceiver {
+        self.subscribe(move |event| event.kind == kind)
+    }
+
+    /// Receive all events about one file, e.g. the texture a material was built from.
+    pub fn subscribe_path(&self, path: impl Into<PathBuf>) -> ReloadReceiver {
+        let path = path.into();
+        self.subscribe(move |event| event.path == path)
+    }
+
+    /// Send `event` to every matching subscriber. Returns the number it was sent to.
+    pub fn publish(&self, event: ReloadEvent) -> usize {
+        trace_event!(path = %event.path.display(), kind = ?event.kind, stage = ?event.stage, "reload event");
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut sent = 0;
+        // Subscribers whose receiver was dropped fail to send and are removed.
+        subscribers.retain(|(filter, sender)| {
+            if !filter(&event) {
+                return true;
+            }
+            let alive = sender.send(event.clone()).is_ok();
+            sent += alive as usize;
+            alive
+        });
+        sent
+    }
+
+    /// Number of live subscriptions, as of the last publish.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+#[cfg(feature = "native")]
+struct WatchedFile {
+    kind: AssetKind,
+    modified: Option<SystemTime>,
+}
+
+/// Polls modification times of registered files and publishes
+/// [`Changed`](ReloadStage::Changed) events for the ones that were written.
+#[cfg(feature = "native")]
+pub struct FileWatcher {
+    bus: ReloadBus,
+    files: HashMap<PathBuf, WatchedFile>,
+    interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+#[cfg(feature = "native")]
+impl FileWatcher {
+    /// Publish on `bus`, checking files on every [`poll`](Self::poll).
+    pub fn new(bus: ReloadBus) -> Self {
+        Self {
+            bus,
+            files: HashMap::new(),
+            interval: Duration::ZERO,
+            last_poll: None,
+        }
+    }
+
+    /// Check files at most once per `interval`, so `poll` can be called every frame.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Start watching `path`. Watching a missing file publishes once it is created.
+    pub fn watch(&mut self, path: impl Into<PathBuf>, kind: AssetKind) {
+        let path = path.into();
+        let modified = modified(&path);
+        self.files.insert(path, WatchedFile { kind, modified });
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// Publish an event for every file modified since the last poll. Returns the number
+    /// of changed files.
+    pub fn poll(&mut self) -> usize {
+        let now = Instant::now();
+        if self.last_poll.is_some_and(|last| now - last < self.interval) {
+            return 0;
+        }
+        self.last_poll = Some(now);
+        let mut changed = 0;
+        for (path, file) in &mut self.files {
+            let modified = modified(path);
+            if modified.is_some() && modified != file.modified {
+                file.modified = modified;
+                self.bus.publish(ReloadEvent::changed(path.clone(), file.kind));
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    pub fn bus(&self) -> &ReloadBus {
+        &self.bus
+    }
+}
+
+#[cfg(feature = "native")]
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,221 @@
+//! GPU-driven multi-draw batches, one indirect argument buffer per material.
+//!
+//! [`MultiDrawBatches`] groups indexed draws by material (the texture views of the
+//! material bind group). Each batch owns a `DrawIndexedIndirectArgs` buffer and a
+//! draw count buffer, and [`draw`](MultiDrawBatches::draw) binds the material once
+//! and issues a single multi-draw for the whole batch.
+//!
+//! Draw arguments come from the CPU ([`push`](MultiDrawBatches::push)) or from
+//! compute passes writing into [`args_buffer`](MultiDrawBatches::args_buffer),
+//! e.g. [`copy_culled`](MultiDrawBatches::copy_culled) for an
+//! [`OcclusionCuller`]. Batches switched to [GPU counts](MultiDrawBatches::set_gpu_count)
+//! leave their count buffer to such passes as well.
+//!
+//! ## Device support
+//! `multi_draw_indexed_indirect` works everywhere (wgpu emulates it with single draws
+//! where the backend lacks it). Reading the draw count from a buffer needs
+//! `Features::MULTI_DRAW_INDIRECT_COUNT`; without it every slot up to the batch
+//! capacity is drawn, so culling passes must write `instance_count = 0` into the
+//! slots they reject.
+//!
+//! ## Example
+//! ```ignore
+//! let batch = batches.batch(&[&albedo_view]);
+//! batches.push(batch, DrawIndexedIndirectArgs { index_count, instance_count: 1, first_index, base_vertex, first_instance });
+//! batches.upload();
+//! // Inside a render pass, with the shared vertex/index buffers set
+//! batches.draw(&mut render_manager, &mut pass, shader_path, &options, &[&camera_buffer]);
+//! ```
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+use wgpu::util::DrawIndexedIndirectArgs;
+use wgpu::*;
+use crate::gpu_util;
+use crate::occlusion::OcclusionCuller;
+use crate::pipelines::PipelineOptions;
+use crate::renderer::RenderManager;
+
+/// Size of one `DrawIndexedIndirectArgs` entry in bytes.
+const ARGS_SIZE: u64 = size_of::<DrawIndexedIndirectArgs>() as u64;
+
+/// Identifies a material batch in [`MultiDrawBatches`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DrawBatchId(u64);
+
+struct MaterialBatch {
+    views: Vec<TextureView>,
+    commands: Vec<DrawIndexedIndirectArgs>,
+    args: Buffer,
+    count: Buffer,
+    capacity: u32,
+    gpu_count: bool,
+}
+
+/// Per-material indirect draw lists drawn with one multi-draw each.
+pub struct MultiDrawBatches {
+    device: Device,
+    queue: Queue,
+    supports_count: bool,
+    batches: HashMap<DrawBatchId, MaterialBatch>,
+    /// Batch ids in creation order, which is also the draw order.
+    order: Vec<DrawBatchId>,
+}
+
+impl MultiDrawBatches {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            supports_count: device.features().contains(Features::MULTI_DRAW_INDIRECT_COUNT),
+            batches: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if draw counts are read from the count buffers on the GPU.
+    pub fn supports_indirect_count(&self) -> bool {
+        self.supports_count
+    }
+
+    /// The batch of the material with these texture views, created on first use.
+    pub fn batch(&mut self, views: &[&TextureView]) -> DrawBatchId {
+        let mut hasher = DefaultHasher::new();
+        for view in views {
+            view.hash(&mut hasher);
+        }
+        let id = DrawBatchId(hasher.finish());
+        if !self.batches.contains_key(&id) {
+            let (args, count) = create_batch_buffers(&self.device, 64);
+            self.batches.insert(id, MaterialBatch {
+                views: views.iter().map(|v| (*v).clone()).collect(),
+                commands: Vec::new(),
+                args,
+                count,
+                capacity: 64,
+                gpu_count: false,
+            });
+            self.order.push(id);
+        }
+        id
+    }
+
+    /// Append a draw to a batch. Takes effect at the next [`upload`](Self::upload).
+    pub fn push(&mut self, id: DrawBatchId, args: DrawIndexedIndirectArgs) {
+        if let Some(batch) = self.batches.get_mut(&id) {
+            batch.commands.push(args);
+        }
+    }
+
+    /// Let GPU passes write the draw count of a batch instead of [`upload`](Self::upload).
+    ///
+    /// Such batches are drawn with their full capacity as maximum count.
+    pub fn set_gpu_count(&mut self, id: DrawBatchId, enabled: bool) {
+        if let Some(batch) = self.batches.get_mut(&id) {
+            batch.gpu_count = enabled;
+        }
+    }
+
+    /// Grow the argument buffer of a batch to hold at least `capacity` draws.
+    ///
+    /// Growing replaces the buffers, so GPU passes writing into them must fetch them again.
+    pub fn reserve(&mut self, id: DrawBatchId, capacity: u32) {
+        let Some(batch) = self.batches.get_mut(&id) else {
+            return;
+        };
+        if capacity > batch.capacity {
+            let capacity = capacity.next_power_of_two();
+            (batch.args, batch.count) = create_batch_buffers(&self.device, capacity);
+            batch.capacity = capacity;
+        }
+    }
+
+    /// The `DrawIndexedIndirectArgs` array of a batch.
+    pub fn args_buffer(&self, id: DrawBatchId) -> Option<&Buffer> {
+        self.batches.get(&id).map(|b| &b.args)
+    }
+
+    /// The `u32` draw count of a batch.
+    pub fn count_buffer(&self, id: DrawBatchId) -> Option<&Buffer> {
+        self.batches.get(&id).map(|b| &b.count)
+    }
+
+    /// Number of draws the argument buffer of a batch can hold.
+    pub fn capacity(&self, id: DrawBatchId) -> u32 {
+        self.batches.get(&id).map_or(0, |b| b.capacity)
+    }
+
+    /// Record copying the arguments written by `culler` into slot `slot` of a batch.
+    pub fn copy_culled(&self, encoder: &mut CommandEncoder, id: DrawBatchId, slot: u32, culler: &OcclusionCuller) {
+        let Some(batch) = self.batches.get(&id) else {
+            return;
+        };
+        debug_assert!(slot < batch.capacity, "Slot {} out of range of batch capacity {}", slot, batch.capacity);
+        encoder.copy_buffer_to_buffer(culler.indirect_args(), 0, &batch.args, slot as u64 * ARGS_SIZE, ARGS_SIZE);
+    }
+
+    /// Upload the pushed draws and counts of all batches.
+    pub fn upload(&mut self) {
+        for id in &self.order {
+            let batch = self.batches.get_mut(id).unwrap();
+            let len = batch.commands.len() as u32;
+            if len > batch.capacity {
+                let capacity = len.next_power_of_two();
+                (batch.args, batch.count) = create_batch_buffers(&self.device, capacity);
+                batch.capacity = capacity;
+            }
+            if len > 0 {
+                let bytes: Vec<u8> = batch.commands.iter().flat_map(|c| c.as_bytes().iter().copied()).collect();
+                self.queue.write_buffer(&batch.args, 0, &bytes);
+            }
+            if !batch.gpu_count {
+                self.queue.write_buffer(&batch.count, 0, bytemuck::bytes_of(&len));
+            }
+        }
+    }
+
+    /// Remove all pushed draws, keeping batches and their buffers.
+    pub fn clear(&mut self) {
+        for batch in self.batches.values_mut() {
+            batch.commands.clear();
+        }
+    }
+
+    /// Draw every batch: bind its material and issue one multi-draw.
+    ///
+    /// Pipeline and bind groups are set through
+    /// [`render_with_textures`](RenderManager::render_with_textures), so the shader
+    /// follows the usual material + uniform layout. Vertex and index buffers must
+    /// already be set on `pass`.
+    pub fn draw(
+        &self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+    ) {
+        for id in &self.order {
+            let batch = &self.batches[id];
+            let max_count = if batch.gpu_count { batch.capacity } else { batch.commands.len() as u32 };
+            if max_count == 0 {
+                continue;
+            }
+            let views: Vec<&TextureView> = batch.views.iter().collect();
+            manager.render_with_textures(&views, shader_path, options, uniforms, pass);
+
+            if self.supports_count {
+                pass.multi_draw_indexed_indirect_count(&batch.args, 0, &batch.count, 0, max_count);
+            } else {
+                pass.multi_draw_indexed_indirect(&batch.args, 0, max_count);
+            }
+        }
+    }
+}
+
+fn create_batch_buffers(device: &Device, capacity: u32) -> (Buffer, Buffer) {
+    let usage = BufferUsages::INDIRECT | BufferUsages::STORAGE | BufferUsages::COPY_DST;
+    let args = gpu_util::buffer(device, "multi-draw args", capacity as u64 * ARGS_SIZE, usage);
+    let count = gpu_util::buffer(device, "multi-draw count", 4, usage);
+    (args, count)
+}
@@ -0,0 +1,176 @@
+//! Automatic instancing of draws that share a mesh and a material.
+//!
+//! Instead of drawing every object on its own, [`submit`](InstanceBatcher::submit)
+//! them to an [`InstanceBatcher`]. Draws are grouped by (mesh, material bind group),
+//! their transforms are packed into one per-instance vertex buffer, and every group
+//! becomes a single instanced draw:
+//! ```ignore
+//! let options = InstanceBatcher::pipeline_options(
+//!     meshes.layouts().pipeline_options(VertexLayoutId::POSITION_NORMAL_UV, PipelineOptions::default()),
+//! );
+//! for object in &objects {
+//!     batcher.submit(object.mesh, &object.material, object.transform);
+//! }
+//! batcher.upload();
+//! // Inside a render pass, binding the pipeline and the uniforms once
+//! render_manager.render_with_textures(&[&albedo], shader_path, &options, &[&camera], &mut pass);
+//! batcher.draw(&mut pass, &meshes, &options);
+//! batcher.clear();
+//! ```
+//! Material bind groups come from
+//! [`material_bind_group`](crate::renderer::RenderManager::material_bind_group) and are
+//! bound at `@group(0)` per batch; batches are drawn in the order their first draw was
+//! submitted.
+//!
+//! ## Shader inputs
+//! The model matrix arrives as four `vec4<f32>` columns at
+//! `@location(8)..@location(11)`, after the mesh attributes:
+//! ```wgsl
+//! let model = mat4x4<f32>(in.model_0, in.model_1, in.model_2, in.model_3);
+//! ```
+use std::collections::HashMap;
+use wgpu::*;
+use crate::gpu_util;
+use crate::meshes::{MeshHandle, MeshManager};
+use crate::pipelines::PipelineOptions;
+
+/// First shader location of the per-instance model matrix.
+pub const INSTANCE_TRANSFORM_LOCATION: u32 = 8;
+
+const INSTANCE_ATTRIBUTES: [VertexAttribute; 4] =
+    wgpu::vertex_attr_array![8 => Float32x4, 9 => Float32x4, 10 => Float32x4, 11 => Float32x4];
+
+/// Per-instance vertex layout of [`InstanceBatcher`]: one column-major model matrix.
+pub const INSTANCE_TRANSFORM_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<[[f32; 4]; 4]>() as u64,
+    step_mode: VertexStepMode::Instance,
+    attributes: &INSTANCE_ATTRIBUTES,
+};
+
+struct Batch {
+    mesh: MeshHandle,
+    material: BindGroup,
+    transforms: Vec<[[f32; 4]; 4]>,
+    first_instance: u32,
+}
+
+/// Groups submitted draws by mesh and material into instanced draws.
+pub struct InstanceBatcher {
+    device: Device,
+    queue: Queue,
+    batches: Vec<Batch>,
+    lookup: HashMap<(MeshHandle, BindGroup), usize>,
+    instances: Buffer,
+    instance_capacity: u64,
+    uploaded: bool,
+}
+
+impl InstanceBatcher {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            batches: Vec::new(),
+            lookup: HashMap::new(),
+            instances: instance_buffer(device, 64),
+            instance_capacity: 64,
+            uploaded: false,
+        }
+    }
+
+    /// `base` with the per-instance transform layout added after its mesh layout.
+    pub fn pipeline_options(base: PipelineOptions) -> PipelineOptions {
+        base.with_vertex_layout(INSTANCE_TRANSFORM_LAYOUT)
+    }
+
+    /// Queue one draw of `mesh` with `material` bound and model matrix `transform`.
+    pub fn submit(&mut self, mesh: MeshHandle, material: &BindGroup, transform: [[f32; 4]; 4]) {
+        let index = *self.lookup.entry((mesh, material.clone())).or_insert_with(|| {
+            self.batches.push(Batch {
+                mesh,
+                material: material.clone(),
+                transforms: Vec::new(),
+                first_instance: 0,
+            });
+            self.batches.len() - 1
+        });
+        self.batches[index].transforms.push(transform);
+        self.uploaded = false;
+    }
+
+    /// Pack the transforms of all batches into the instance buffer.
+    ///
+    /// Instances are written with `Queue::write_buffer`, so upload once per submission.
+    pub fn upload(&mut self) {
+        let _span = trace_span!("instancing_upload", batches = self.batches.len(), instances = self.len());
+        let count = self.len() as u64;
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instances = instance_buffer(&self.device, self.instance_capacity);
+        }
+        let mut data = Vec::with_capacity(count as usize);
+        for batch in &mut self.batches {
+            batch.first_instance = data.len() as u32;
+            data.extend_from_slice(&batch.transforms);
+        }
+        if !data.is_empty() {
+            self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&data));
+        }
+        self.uploaded = true;
+    }
+
+    /// Draw every batch with one instanced draw, after the pipeline built from `options`
+    /// and the non-material bind groups have been bound.
+    ///
+    /// ## Panics
+    /// Panics if draws were submitted since the last [`upload`](Self::upload), or if a
+    /// batch's mesh is not in `meshes`.
+    pub fn draw(&self, pass: &mut RenderPass, meshes: &MeshManager, options: &PipelineOptions) {
+        assert!(self.uploaded || self.batches.is_empty(), "InstanceBatcher::draw called before upload");
+        pass.set_vertex_buffer(1, self.instances.slice(..));
+        let mut bound: Option<&BindGroup> = None;
+        for batch in &self.batches {
+            if bound != Some(&batch.material) {
+                pass.set_bind_group(0, &batch.material, &[]);
+                bound = Some(&batch.material);
+            }
+            let instances = batch.first_instance..batch.first_instance + batch.transforms.len() as u32;
+            meshes.draw(pass, batch.mesh, options, instances);
+        }
+    }
+
+    /// Remove all submitted draws, keeping the instance buffer.
+    pub fn clear(&mut self) {
+        self.batches.clear();
+        self.lookup.clear();
+        self.uploaded = false;
+    }
+
+    /// Number of submitted draws.
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(|b| b.transforms.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Number of draw calls [`draw`](Self::draw) issues.
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// The per-instance transforms, valid after [`upload`](Self::upload).
+    pub fn instance_buffer(&self) -> &Buffer {
+        &self.instances
+    }
+}
+
+fn instance_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(
+        device,
+        "instance transforms",
+        capacity * INSTANCE_TRANSFORM_LAYOUT.array_stride,
+        BufferUsages::VERTEX | BufferUsages::COPY_DST,
+    )
+}
@@ -0,0 +1,74 @@
+//! Namespaces for the labels of created resources.
+//!
+//! Every buffer, texture, layout, pipeline, encoder and pass the crate creates gets a
+//! label like `"material bind group"`. With several renderers, scenes or tool windows
+//! in one process, a capture or a validation message does not tell which of them it
+//! came from. While a [`LabelNamespace`] is entered, those labels are prefixed with it:
+//! ```ignore
+//! {
+//!     let _namespace = LabelNamespace::enter("minimap");
+//!     render_manager.render_with_textures(&[&terrain_view], shader, &options, &[&camera], &mut pass);
+//!     // pipelines, layouts and bind groups created here are labelled "minimap/..."
+//! }
+//! ```
+//! Namespaces nest as `outer/inner` and are per thread, jobs of the
+//! `ResourceWorkers` run in the namespace they were
+//! submitted in. Cached resources keep the label they were created with, so a layout
+//! first created by the minimap stays `minimap/...` when the main view reuses it.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+thread_local! {
+    static NAMESPACE: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Prefixes labels of resources created on this thread until dropped, see the
+/// [module docs](self).
+#[must_use = "the namespace is left when the guard is dropped"]
+pub struct LabelNamespace {
+    previous: Option<Arc<str>>,
+}
+
+impl LabelNamespace {
+    /// Enter `namespace`, nested in the current one if there is one.
+    pub fn enter(namespace: &str) -> Self {
+        let previous = current();
+        let namespace: Arc<str> = match &previous {
+            Some(outer) => format!("{}/{}", outer, namespace).into(),
+            None => namespace.into(),
+        };
+        Self::set(Some(namespace), previous)
+    }
+
+    /// Enter `namespace` as it is, e.g. one returned by [`current`] on another thread.
+    pub fn restore(namespace: Option<Arc<str>>) -> Self {
+        Self::set(namespace, current())
+    }
+
+    fn set(namespace: Option<Arc<str>>, previous: Option<Arc<str>>) -> Self {
+        NAMESPACE.with(|current| *current.borrow_mut() = namespace);
+        Self { previous }
+    }
+}
+
+impl Drop for LabelNamespace {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        NAMESPACE.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// The namespace of this thread, if one is entered.
+pub fn current() -> Option<Arc<str>> {
+    NAMESPACE.with(|current| current.borrow().clone())
+}
+
+/// `label` prefixed with the current namespace, for labelling your own resources
+/// like the crate's.
+pub fn namespaced(label: &str) -> Cow<'_, str> {
+    NAMESPACE.with(|current| match &*current.borrow() {
+        Some(namespace) => Cow::Owned(format!("{}/{}", namespace, label)),
+        None => Cow::Borrowed(label),
+    })
+}
@@ -8,6 +8,89 @@
 //! - Render anything using automatically procedurally generated textures
 //! - Procedurally generate textures using TextureKey and a shader
 //! - Make compute pipelines trivial using [`compute()`](compute_system::ComputeSystem::compute())
+//! - Measure GPU time of crate-managed passes with the [`GpuProfiler`](profiler::GpuProfiler)
+//! - Count shader invocations per material/pipeline with [`PipelineStatistics`](pipeline_stats::PipelineStatistics)
+//! - Hand out material bind groups to parallel encoding threads via [`SharedMaterialBindGroups`](concurrent::SharedMaterialBindGroups)
+//! - Create textures, bind groups and pipelines in the background with `ResourceWorkers`
+//! - Record draw lists on several threads with the `parallel` encoding helpers
+//! - Drive several devices with shared CPU-side assets through the [`MultiDeviceManager`](multi_device::MultiDeviceManager)
+//! - Batch queue submissions and wait on them like fences with the [`SubmissionManager`](submission::SubmissionManager)
+//! - Cull occluded instances on the GPU against a `HiZBuffer`, feeding indirect draws
+//! - Issue one multi-draw per material from GPU-written argument buffers with `MultiDrawBatches`
+//! - Simulate and draw GPU particles with the `ParticleSystem`
+//! - Deform skeletal meshes into plain vertex buffers with the compute [`SkinningPass`](skinning::SkinningPass)
+//! - Meter HDR scenes with a luminance histogram and tonemap them with `AutoExposure`
+//! - Reduce, prefix-sum and radix-sort `u32` buffers on the GPU with [`GpuAlgorithms`](algorithms::GpuAlgorithms)
+//! - Order compute jobs before and after the frame's graphics work with [`ComputeJobs`](compute_jobs::ComputeJobs)
+//! - Bin point lights into view-space clusters for forward shading with `ClusteredLighting`
+//! - Render deferred geometry into a `GBuffer` and light it with one fullscreen pass
+//! - Pack directional, point and spot lights into one GPU buffer with the `LightManager`
+//! - Draw a cubemap or baked procedural sky and expose it to materials with the `Skybox`
+//! - Project textured box decals onto the scene depth with the `DecalRenderer`
+//! - Add ray-marched `ScreenSpaceReflections` with an environment map fallback
+//! - Scatter light through froxel-based `VolumetricFog` and composite it onto HDR targets
+//! - Draw transparent materials in any order with weighted blended [`OitTargets`](oit::OitTargets)
+//! - Outline selected meshes with a jump-flooded `SelectionOutline`
+//! - Switch materials to wireframe, normal or UV checker [`DebugRenderMode`](debug_modes::DebugRenderMode)s without touching their shaders
+//! - Upload meshes into a [`MeshManager`](meshes::MeshManager) whose registered vertex layouts are checked against shaders and pipelines
+//! - Generate cubes, planes, spheres, cones, capsules and tori with tangents using the [`primitives`] module
+//! - Pick mesh LODs by distance or screen coverage, on the CPU with `LodChains` or after GPU culling
+//! - Merge draws sharing a mesh and material into instanced draws with the [`InstanceBatcher`](instancing::InstanceBatcher)
+//! - Sample skeletal [`AnimationClip`](animation::AnimationClip)s into poses and upload them for GPU skinning
+//! - Blend morph targets into mesh vertex buffers with the compute [`MorphPass`](morph::MorphPass)
+//! - Render heightmap `Terrain` as CDLOD chunks with splat-map materials on texture arrays
+//! - Keep the view/projection uniform, its history and TAA jitter in a [`CameraManager`](camera::CameraManager)
+//! - Propagate parent/child transforms in a [`SceneGraph`](scene::SceneGraph) and upload per-node uniforms into a
+//!   dynamic-offset [`UniformRing`](uniform_ring::UniformRing)
+//! - Frustum-cull draw lists on the CPU with a [`Bvh`](culling::Bvh) over object bounds
+//! - Pick objects under the cursor from an ID target with asynchronous readback in a `Picker`
+//! - Render split-screen and multi-viewport frames with per-view cameras in a [`ViewSet`](views::ViewSet)
+//! - Build view/projection matrices with [`Camera`](camera::Camera) and drive it with fly, orbit and FPS
+//!   [`camera_controller`]s
+//! - Keep per-object transforms, material indices and flags in one incrementally updated storage buffer,
+//!   the `ObjectTable`
+//! - Configure the swapchain, recover from outdated/lost surfaces and resize screen targets with a
+//!   [`SurfaceManager`](surface::SurfaceManager)
+//! - Render without a window into pooled targets and read frames back with a
+//!   [`HeadlessRenderer`](headless::HeadlessRenderer), for server-side rendering and image tests
+//! - Capture presented frames to PNG sequences or an encoder callback without stalling the GPU with a
+//!   [`FrameCapture`](capture::FrameCapture)
+//! - Render several windows with one device and shared caches, each with its own swapchain, views and
+//!   target pool, through a [`WindowSet`](windows::WindowSet)
+//! - Render the scene at a scale of the output resolution, adapted to GPU frame times, and upscale it
+//!   with a `DynamicResolution`
+//! - Limit the frame rate and collect CPU/GPU frame time statistics with a `FramePacer`
+//! - Draw rotated, tinted and scissored 2D sprites grouped by texture into instanced draws with a
+//!   `SpriteBatcher`
+//! - Scale UI panels and buttons as corner-preserving nine-patches from `NineSlice` borders
+//! - Draw debug lines, boxes, spheres, frusta and axes, depth-tested or on top, with `Gizmos`
+//! - Draw chunked 2D `Tilemap`s from storage buffers with one instanced draw per chunk and atlas page
+//! - Draw camera-facing and axis-locked quads in 3D scenes with `Billboards`
+//! - Submit spheres, arrows, boxes and 3D labels from anywhere and draw them in one late pass with `DebugDraw`
+//! - Tessellate SVG-like vector paths into crisp UI and map meshes with a `VectorMesh` (feature `lyon`)
+//! - Load textures and scenes asynchronously behind typed handles with an `AssetServer` (feature `assets`)
+//! - Watch shader, texture and scene files once and fan out change and reload events to every subscribed
+//!   system over a [`ReloadBus`](hot_reload::ReloadBus)
+//! - Refcount textures, materials, shaders and pipelines in an [`AssetGraph`](asset_graph::AssetGraph) that frees
+//!   GPU resources and their cached bind groups and pipelines when the last reference drops
+//! - Upload precompressed, mipmapped [`BakedTexture`](texture_bake::BakedTexture)s without decoding images at runtime
+//! - Ship all assets in one memory-mapped pack file written by a `PackWriter` (feature `pack`)
+//! - Skip reprocessing unchanged assets on later runs with a content-hash keyed [`DiskCache`](disk_cache::DiskCache)
+//! - Load Radiance HDR and OpenEXR environment maps into `Rgba16Float`/`Rg11b10Ufloat` textures (feature `hdr`)
+//! - Ship Basis Universal textures and transcode them to the best format of each native device (feature `basis`)
+//! - Stream textures coarse mips first, bindable at once and sharpening as detail arrives, with `TextureStreamer` (feature `native`)
+//! - Keep assets within a memory budget, evicting by [`Priority`](residency::Priority) (UI over nearby over distant) and last use
+//! - Fallible `try_` creation paths returning [`CrmError`](error::CrmError) (unsupported format, missing feature, limit exceeded, device, io, shader) instead of panicking
+//! - Build explicit, cached bind groups binding by binding with [`BindGroupBuilder`](bind_group_builder::BindGroupBuilder) (`bind_group().sampler(s).texture(view).uniform(buf).build()`)
+//! - Declare bind groups as Rust structs with [`TypedBindGroup`](typed_bind_group::TypedBindGroup), derived with `#[derive(BindGroupLayout)]` (feature `derive`) together with their WGSL declarations
+//! - Prefix the labels of every created resource with a per-thread [`LabelNamespace`](labels::LabelNamespace), e.g. per scene or tool window, to tell renderers apart in captures and validation messages
+//! - Key material bind groups by your own material identity (asset GUIDs, ECS entities) with a [`CacheKey`](cache_key::CacheKey) instead of hashing texture views on every draw
+//! - Fetch several cached bind groups as copyable [`BindGroupId`](handles::BindGroupId)s before a render pass and resolve them through `&self` while encoding
+//! - Resolve a frame's whole material set in one [`get_or_create_many`](renderer::RenderManager::get_or_create_many) pass
+//! - Keep the manager inside larger renderer structs as a [`SharedRenderManager`](shared::SharedRenderManager) whose lookups take `&self`
+//! - Bind per-draw buffers at dynamic offsets in builder and derived bind groups, with aligned offsets from [`DynamicOffsets`](uniform_ring::DynamicOffsets)
+//! - Declare a `min_binding_size` per buffer binding and get [`CrmError::BufferTooSmall`](error::CrmError::BufferTooSmall) for undersized buffers when the bind group is created
+//! - Resolve MSAA depth into a single-sampled depth or `R32Float` target for SSAO and soft particles with the [`DepthResolver`](depth_resolve::DepthResolver)
 //!
 //! This crate makes game development and rendering with fullscreen passes a breeze.
 //!
@@ -19,12 +102,14 @@
 //! ## Shader Binding layout
 //! - `@group(0) @binding(0)`: trilinear sampler
 //! - `@group(0) @binding(0..n)`: textures as texture_2d<f32> or texture_multisampled_2d<f32>
+//!   (depth textures as texture_depth_2d or texture_depth_multisampled_2d)
+//!   (or texture_storage_2d for textures marked with `PipelineOptions::with_texture_access`)
 //! - `@group(0) @binding(n+1)`: (optional) shadow_sampler
 //! - `@group(0) @binding(n+2)`: (optional) shadow textures as texture_depth_2d_array
 //! - `@group(1) @binding(0..n)`: uniforms, in the same order as input
 //!
 //! ## Basic usage
-//! ```no_run
+//! ```ignore
 //! // Inside a render pass
 //! render_manager.render_with_textures(
 //!     &texture_views.as_slice(), // Texture Views
@@ -35,12 +120,214 @@
 //! );
 //! ```
 //!
+//! ## Cargo features
+//! The core, [`RenderManager`](renderer::RenderManager) with its pipeline, bind group, compute and
+//! texture caches, meshes, cameras, surfaces and the scene graph, is always compiled. Subsystems on
+//! top of it have a feature each, all enabled by default; `default-features = false` builds only the
+//! core, plus whatever is listed:
+//!
+//! | Feature      | Modules |
+//! |--------------|---------|
+//! | `lighting`   | `lights`, `clustered`, `skybox`, `fog` |
+//! | `postfx`     | `exposure`, `dynamic_resolution`, `gbuffer`, `outline`, `ssr` (also needs `lighting`) |
+//! | `gpu_driven` | `occlusion`, `indirect`, `lod`, `object_table` |
+//! | `sprites`    | `sprites`, `tilemap`, `billboards` |
+//! | `effects`    | `particles`, `decals` |
+//! | `terrain`    | `terrain` |
+//! | `debug_draw` | `gizmos`, `debug_draw`, `picking` |
+//!
+//! The [`ResizeTarget`](surface::ResizeTarget) impls and
+//! `SurfaceManager::tonemap_output` follow the features of
+//! their types. CI checks the core alone and every feature on its own, so each row builds
+//! without the others.
+//!
+//! Optional integrations:
+//! - `tracing`: emits `tracing` spans/events for cache misses, layout and bind group
+//!   creation, texture generation and evictions, so frame hitches can be attributed
+//!   to the resource that was created.
+//! - `egui`: `CacheOverlay`, an egui window listing every cached
+//!   resource with its last-used frame and memory, with buttons to inspect or evict entries, and
+//!   the `EguiRenderer`, drawing egui output through the crate's
+//!   textures and material bind groups instead of `egui-wgpu`.
+//! - `native` (default): subsystems that need OS threads, `workers`, `parallel` and `frame_pacing`.
+//!   Disable default features when targeting `wasm32`.
+//! - `web`: async device creation and fetch-based texture loading for wasm32 / WebGPU
+//!   in the `web` module.
+//! - `decode`: `ImageBatch`, decoding PNG/JPEG files on all cores
+//!   with `rayon` while uploads stay on the queue thread, with progress for loading screens.
+//! - `serde`: `Serialize`/`Deserialize` for the [`diagnostics`] types and
+//!   `RenderManager::dump_state`, which writes all caches as JSON,
+//!   and `scene_file`, saving and loading scene graphs with assets referenced by path.
+//! - `gltf`: `load_gltf`, importing `.gltf`/`.glb` meshes into the
+//!   [`MeshManager`](meshes::MeshManager) together with their materials, node hierarchy, skins, morph targets and animations.
+//! - `obj`: `load_obj`, importing Wavefront OBJ geometry with its MTL
+//!   materials and their diffuse, normal and specular maps.
+//! - `meshopt`: [`MeshData::optimize`](primitives::MeshData::optimize), vertex cache and overdraw
+//!   optimization and simplification with meshoptimizer, applied by the importers with
+//!   before/after statistics once `MeshManager::set_import_optimization` is set.
+//! - `bevy_ecs` / `hecs`: the `ecs` adapters, extracting entities with mesh, material and transform
+//!   components into an [`InstanceBatcher`](instancing::InstanceBatcher) every frame.
+//! - `png`: PNG sequence output of [`FrameCapture`](capture::FrameCapture), written on a background thread.
+//! - `text`: the `TextRenderer`, glyph atlas text with alignment and wrapping,
+//!   as bitmaps or signed distance fields with outlines and shadows.
+//! - `lyon`: `VectorMesh`, filled and stroked vector paths tessellated
+//!   into meshes of the [`MeshManager`](meshes::MeshManager).
+//! - `assets`: the `AssetServer`, loading textures, OBJ and glTF scenes behind
+//!   handles on the resource workers, with load states that can be polled or awaited.
+//! - `bake`: `bake_file`, offline mip generation and BC1/3/4/5/7 or ASTC
+//!   compression into baked `.rmtex` textures.
+//! - `pack`: `AssetPack`, single-file packs of LZ4-compressed assets,
+//!   memory-mapped and mountable in the `AssetServer`.
+//! - `hdr`: `HdrImage`, Radiance `.hdr` and OpenEXR images decoded into
+//!   `Rgba16Float` or `Rg11b10Ufloat` textures for environment maps, also loaded by the `AssetServer`.
+//! - `basis`: `transcode_for_device`, Basis Universal textures transcoded
+//!   at load time into BC7, ASTC 4x4, ETC2 or RGBA8 depending on the device, also by the `AssetServer`.
+//!   Native only, the C++ transcoder does not build for `wasm32`.
+//! - `derive`: `#[derive(BindGroupLayout)]` for [`TypedBindGroup`](typed_bind_group::TypedBindGroup)
+//!   structs, generating the layout, the resources and the WGSL declarations of a bind group.
+//! - `testing`: `testing` helpers for CI without a window: a no-op or headless device, test
+//!   textures and assertions on how many layouts, bind groups and pipelines a call created.
+//! - `winit`: [`winit_app::run`], a window, device, surface and frame loop wired to the managers.
+//!
 //! Used in my game [Rusty Skylines](https://github.com/maxwag9/rusty_skylines)
 
+#[macro_use]
+mod trace;
+pub mod algorithms;
+pub mod animation;
+pub mod asset_graph;
+#[cfg(feature = "pack")]
+pub mod asset_pack;
+#[cfg(feature = "assets")]
+pub mod assets;
+#[cfg(all(feature = "basis", not(target_arch = "wasm32")))]
+pub mod basis;
+#[cfg(feature = "sprites")]
+pub mod billboards;
+pub mod bind_group_builder;
+pub mod cache_key;
+pub mod camera;
+pub mod camera_controller;
+pub mod capture;
+#[cfg(feature = "lighting")]
+pub mod clustered;
+pub mod compute_jobs;
 pub mod compute_system;
+pub mod concurrent;
+pub mod culling;
+#[cfg(feature = "debug_draw")]
+pub mod debug_draw;
+#[cfg(feature = "effects")]
+pub mod decals;
+#[cfg(feature = "decode")]
+pub mod decode;
+pub mod depth_resolve;
+#[cfg(any(feature = "bevy_ecs", feature = "hecs"))]
+pub mod ecs;
+pub mod debug_modes;
+#[cfg(feature = "egui")]
+pub mod debug_overlay;
+pub mod diagnostics;
+pub mod disk_cache;
+#[cfg(feature = "postfx")]
+pub mod dynamic_resolution;
+#[cfg(feature = "egui")]
+pub mod egui_renderer;
+pub mod error;
+#[cfg(feature = "postfx")]
+pub mod exposure;
+#[cfg(feature = "lighting")]
+pub mod fog;
+#[cfg(feature = "native")]
+pub mod frame_pacing;
+#[cfg(feature = "postfx")]
+pub mod gbuffer;
 pub mod generator;
+#[cfg(feature = "debug_draw")]
+pub mod gizmos;
+#[cfg(feature = "gltf")]
+pub mod gltf_import;
+pub mod handles;
+pub mod headless;
+#[cfg(feature = "hdr")]
+pub mod hdr_image;
+pub mod hot_reload;
+#[cfg(feature = "gpu_driven")]
+pub mod indirect;
+pub mod instancing;
+pub mod labels;
+#[cfg(feature = "lighting")]
+pub mod lights;
+#[cfg(feature = "gpu_driven")]
+pub mod lod;
+#[cfg(feature = "meshopt")]
+pub mod mesh_optimize;
+pub mod meshes;
+pub mod morph;
+pub mod multi_device;
+#[cfg(feature = "obj")]
+pub mod obj_import;
+#[cfg(feature = "gpu_driven")]
+pub mod object_table;
+#[cfg(feature = "gpu_driven")]
+pub mod occlusion;
+pub mod oit;
+#[cfg(feature = "postfx")]
+pub mod outline;
+#[cfg(feature = "effects")]
+pub mod particles;
+#[cfg(feature = "native")]
+pub mod parallel;
+#[cfg(feature = "debug_draw")]
+pub mod picking;
+pub mod pipeline_stats;
 pub mod pipelines;
 pub mod fullscreen;
+pub mod primitives;
+pub mod profiler;
+pub mod readback;
 pub mod renderer;
+pub mod residency;
+pub mod scene;
+#[cfg(feature = "serde")]
+pub mod scene_file;
+pub mod shared;
+pub mod skinning;
+#[cfg(feature = "lighting")]
+pub mod skybox;
+#[cfg(feature = "sprites")]
+pub mod sprites;
+#[cfg(all(feature = "postfx", feature = "lighting"))]
+pub mod ssr;
+pub mod submission;
+pub mod surface;
+#[cfg(feature = "terrain")]
+pub mod terrain;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod textures;
+pub mod texture_bake;
+pub mod texture_streaming;
+#[cfg(feature = "text")]
+pub mod text;
+#[cfg(feature = "sprites")]
+pub mod tilemap;
+pub mod typed_bind_group;
+pub mod uniform_ring;
+#[cfg(feature = "lyon")]
+pub mod vector_paths;
+pub mod views;
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub mod web;
+pub mod windows;
+#[cfg(feature = "winit")]
+pub mod winit_app;
+#[cfg(feature = "native")]
+pub mod workers;
 mod bind_groups;
+mod compute_bind_groups;
+mod gpu_util;
+mod queries;
 mod shader_preprocessing;
+#[cfg(debug_assertions)]
+mod validation;
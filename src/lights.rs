@@ -0,0 +1,317 @@
+//! Per-frame light data packed into one GPU buffer.
+//!
+//! [`LightManager`] collects directional, point and spot lights on the CPU and
+//! [`upload`](LightManager::upload) packs them, sorted by type, into a storage buffer
+//! with the counts in its header. Shading passes bind the buffer through
+//! [`render`](LightManager::render) (as `@group(2)`, next to the material and uniform
+//! groups), or through [`bind_group`](LightManager::bind_group) in custom pipelines
+//! and compute passes.
+//!
+//! ## Frame flow
+//! ```ignore
+//! lights.clear();
+//! lights.push(DirectionalLight { direction: [-0.3, -1.0, -0.2], color: [1.0; 3], intensity: 3.0 });
+//! lights.push(PointLight { position, radius: 8.0, color: [1.0, 0.6, 0.3], intensity: 20.0 });
+//! lights.upload();
+//! // Inside a render pass
+//! lights.render(&mut render_manager, &mut pass, &[&albedo_view], shader_path, &options, &[&camera_buffer]);
+//! ```
+//!
+//! Point lights use the same [`PointLight`] type as
+//! [`ClusteredLighting`](crate::clustered::ClusteredLighting), so
+//! [`point_lights`](LightManager::point_lights) can be handed to its culling pass.
+//!
+//! ## Shader side
+//! Paste [`LIGHTS_WGSL`] into the shader for the bindings and the `GpuLight` layout.
+//! Lights `0..counts.x` are directional, the next `counts.y` are point lights and
+//! the next `counts.z` spot lights; `counts.w` is the total.
+use std::path::Path;
+use wgpu::*;
+use crate::gpu_util;
+use crate::pipelines::PipelineOptions;
+use crate::renderer::{ExtraBindGroup, RenderManager};
+
+pub use crate::clustered::PointLight;
+
+/// WGSL declarations for shaders drawn with [`LightManager::render`].
+pub const LIGHTS_WGSL: &str = r#"
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct GpuLight {
+    position: vec3<f32>,
+    range: f32,
+    // Direction the light travels in, normalized. Unused for point lights.
+    direction: vec3<f32>,
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    // Cosines of the inner and outer spot cone half-angles.
+    spot_inner_cos: f32,
+    spot_outer_cos: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+struct LightBuffer {
+    // directional, point, spot, total
+    counts: vec4<u32>,
+    lights: array<GpuLight>,
+};
+
+@group(2) @binding(0) var<storage, read> light_buffer: LightBuffer;
+"#;
+
+/// Size of the `counts` header in front of the light array.
+const HEADER_SIZE: u64 = 16;
+
+/// A light infinitely far away, e.g. the sun.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    /// Direction the light travels in. Normalized on upload.
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// A cone light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    /// Direction the cone points in. Normalized on upload.
+    pub direction: [f32; 3],
+    pub range: f32,
+    /// Half-angle in radians where the falloff starts.
+    pub inner_angle: f32,
+    /// Half-angle in radians where the light reaches zero.
+    pub outer_angle: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Any light accepted by [`LightManager::push`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    Directional(DirectionalLight),
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Self {
+        Light::Directional(light)
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Self {
+        Light::Point(light)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(light: SpotLight) -> Self {
+        Light::Spot(light)
+    }
+}
+
+/// Number of lights of each type in the last upload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LightCounts {
+    pub directional: u32,
+    pub point: u32,
+    pub spot: u32,
+}
+
+impl LightCounts {
+    pub fn total(&self) -> u32 {
+        self.directional + self.point + self.spot
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuLight {
+    position: [f32; 3],
+    range: f32,
+    direction: [f32; 3],
+    kind: u32,
+    color: [f32; 3],
+    intensity: f32,
+    spot_inner_cos: f32,
+    spot_outer_cos: f32,
+    _pad: [f32; 2],
+}
+
+/// Collects the lights of a frame and uploads them into a storage buffer.
+pub struct LightManager {
+    device: Device,
+    queue: Queue,
+    directional: Vec<DirectionalLight>,
+    point: Vec<PointLight>,
+    spot: Vec<SpotLight>,
+    counts: LightCounts,
+    buffer: Buffer,
+    capacity: u32,
+    entries: Vec<BindGroupLayoutEntry>,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+}
+
+impl LightManager {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let entries = vec![gpu_util::storage_entry(
+            0,
+            ShaderStages::VERTEX | ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+            true,
+        )];
+        let layout = gpu_util::bind_group_layout(device, "light layout", &entries);
+        let capacity = 16;
+        let buffer = light_buffer(device, capacity);
+        let bind_group = gpu_util::bind_group(device, "lights", &layout, &[buffer.as_entire_binding()]);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            directional: Vec::new(),
+            point: Vec::new(),
+            spot: Vec::new(),
+            counts: LightCounts::default(),
+            buffer,
+            capacity,
+            entries,
+            layout,
+            bind_group,
+        }
+    }
+
+    /// Add a light. Takes effect at the next [`upload`](Self::upload).
+    pub fn push(&mut self, light: impl Into<Light>) {
+        match light.into() {
+            Light::Directional(light) => self.directional.push(light),
+            Light::Point(light) => self.point.push(light),
+            Light::Spot(light) => self.spot.push(light),
+        }
+    }
+
+    /// Remove all lights pushed since the last clear.
+    pub fn clear(&mut self) {
+        self.directional.clear();
+        self.point.clear();
+        self.spot.clear();
+    }
+
+    /// The point lights pushed so far, e.g. for [`ClusteredLighting::set_lights`](crate::clustered::ClusteredLighting::set_lights).
+    pub fn point_lights(&self) -> &[PointLight] {
+        &self.point
+    }
+
+    /// Counts of the last [`upload`](Self::upload).
+    pub fn counts(&self) -> LightCounts {
+        self.counts
+    }
+
+    /// Pack all pushed lights into the light buffer.
+    ///
+    /// Growing past the current capacity replaces the buffer and the bind group.
+    pub fn upload(&mut self) {
+        let counts = LightCounts {
+            directional: self.directional.len() as u32,
+            point: self.point.len() as u32,
+            spot: self.spot.len() as u32,
+        };
+        let total = counts.total();
+        if total > self.capacity {
+            self.capacity = total.next_power_of_two();
+            self.buffer = light_buffer(&self.device, self.capacity);
+            self.bind_group = gpu_util::bind_group(&self.device, "lights", &self.layout, &[self.buffer.as_entire_binding()]);
+        }
+
+        let mut lights = Vec::with_capacity(total as usize);
+        lights.extend(self.directional.iter().map(|l| GpuLight {
+            direction: normalize(l.direction),
+            kind: 0,
+            color: l.color,
+            intensity: l.intensity,
+            ..Default::default()
+        }));
+        lights.extend(self.point.iter().map(|l| GpuLight {
+            position: l.position,
+            range: l.radius,
+            kind: 1,
+            color: l.color,
+            intensity: l.intensity,
+            ..Default::default()
+        }));
+        lights.extend(self.spot.iter().map(|l| GpuLight {
+            position: l.position,
+            range: l.range,
+            direction: normalize(l.direction),
+            kind: 2,
+            color: l.color,
+            intensity: l.intensity,
+            spot_inner_cos: l.inner_angle.cos(),
+            spot_outer_cos: l.outer_angle.cos(),
+            _pad: [0.0; 2],
+        }));
+
+        let header = [counts.directional, counts.point, counts.spot, total];
+        self.queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&header));
+        if !lights.is_empty() {
+            self.queue.write_buffer(&self.buffer, HEADER_SIZE, bytemuck::cast_slice(&lights));
+        }
+        self.counts = counts;
+    }
+
+    /// The packed light buffer.
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Layout of the light bind group, visible to vertex, fragment and compute shaders.
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    /// Bind group with the light buffer at binding 0, see [`LIGHTS_WGSL`].
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Set pipeline and bind groups like
+    /// [`render_with_textures`](RenderManager::render_with_textures), with the
+    /// lights bound as `@group(2)`.
+    ///
+    /// Like `render_with_textures`, this does not issue a draw call.
+    pub fn render(
+        &self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+    ) {
+        let lights = ExtraBindGroup {
+            name: "lights",
+            entries: &self.entries,
+            layout: &self.layout,
+            bind_group: &self.bind_group,
+        };
+        manager.render_with_extra_groups(texture_views, shader_path, options, uniforms, &[lights], pass);
+    }
+}
+
+fn light_buffer(device: &Device, capacity: u32) -> Buffer {
+    gpu_util::buffer(
+        device,
+        "lights",
+        HEADER_SIZE + capacity as u64 * size_of::<GpuLight>() as u64,
+        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+    )
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if len > 0.0 { [v[0] / len, v[1] / len, v[2] / len] } else { [0.0, -1.0, 0.0] }
+}
@@ -0,0 +1,428 @@
+//! Level-of-detail selection for meshes.
+//!
+//! A [`LodSettings`] describes when a mesh switches to its next, coarser level:
+//! by camera distance or by the fraction of the screen height its bounding sphere
+//! covers, optionally cross-fading both levels over a band before each switch.
+//!
+//! - On the CPU, [`LodChains`] maps a [`MeshHandle`] to its chain of LOD meshes
+//!   and [`select`](LodChains::select)s the mesh (or the two meshes and their fade
+//!   weights) to draw for an instance.
+//! - On the GPU, [`GpuLodSelector`] runs after [`OcclusionCuller::cull`]: it sorts
+//!   the surviving instances into one visible list and one `draw_indexed_indirect`
+//!   argument entry per level, so indirect draws pick the right LOD without a readback.
+//!
+//! ## GPU frame flow
+//! ```ignore
+//! culler.cull(&mut encoder, &hiz, &bounds, instance_count, view_proj, levels[0]);
+//! selector.select(&mut encoder, &culler, &bounds, instance_count, &camera, &settings, &levels);
+//! // Inside a render pass, with the shared vertex/index buffers and bind groups set
+//! selector.draw(&mut pass);
+//! ```
+//! The vertex shader binds [`lod_instances`](GpuLodSelector::lod_instances) and
+//! reads `lod_instances[instance_index]`, a `LodInstance { instance: u32, fade: f32 }`:
+//! the real instance index and the weight of this level (1 outside fade bands),
+//! e.g. for dithered cross-fading in the fragment shader.
+use std::collections::HashMap;
+use wgpu::util::DrawIndexedIndirectArgs;
+use wgpu::*;
+use crate::gpu_util;
+use crate::meshes::MeshHandle;
+use crate::occlusion::{DrawIndexedRange, OcclusionCuller};
+
+/// Maximum number of levels per chain.
+pub const MAX_LOD_LEVELS: usize = 8;
+
+/// Size of one `DrawIndexedIndirectArgs` entry in bytes.
+const ARGS_SIZE: u64 = size_of::<DrawIndexedIndirectArgs>() as u64;
+
+const LOD_SELECT_SHADER: &str = r#"
+struct LodParams {
+    camera_position: vec3<f32>,
+    projection_scale: f32,
+    level_count: u32,
+    metric: u32,
+    fade_range: f32,
+    capacity: u32,
+    // Switch values, negated for screen coverage so that both metrics grow with distance.
+    thresholds: array<vec4<f32>, 2>,
+};
+
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct LodInstance {
+    instance: u32,
+    fade: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: LodParams;
+@group(0) @binding(1) var<storage, read> bounds: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read> visible: array<u32>;
+@group(0) @binding(3) var<storage, read> culled_args: array<u32>;
+@group(0) @binding(4) var<storage, read_write> lod_instances: array<LodInstance>;
+@group(0) @binding(5) var<storage, read_write> args: array<DrawArgs>;
+
+fn threshold(level: u32) -> f32 {
+    return params.thresholds[level / 4u][level % 4u];
+}
+
+fn emit(level: u32, instance: u32, fade: f32) {
+    let slot = atomicAdd(&args[level].instance_count, 1u);
+    lod_instances[level * params.capacity + slot] = LodInstance(instance, fade);
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    // Instance count written by the occlusion culler.
+    if id.x >= culled_args[1] {
+        return;
+    }
+    let instance = visible[id.x];
+    let sphere = bounds[instance];
+    let dist = distance(sphere.xyz, params.camera_position);
+    var value = dist;
+    if params.metric == 1u {
+        value = -sphere.w * params.projection_scale / max(dist, 1e-4);
+    }
+
+    var level = params.level_count - 1u;
+    for (var i = 0u; i + 1u < params.level_count; i++) {
+        if value < threshold(i) {
+            level = i;
+            break;
+        }
+    }
+
+    var fade = 1.0;
+    if params.fade_range > 0.0 && level + 1u < params.level_count {
+        let t = clamp((value - threshold(level) + params.fade_range) / params.fade_range, 0.0, 1.0);
+        if t > 0.0 {
+            emit(level + 1u, instance, t);
+            fade = 1.0 - t;
+        }
+    }
+    emit(level, instance, fade);
+}
+"#;
+
+/// How the LOD level of an instance is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LodMetric {
+    /// World-space distance from the camera to the bounding sphere center.
+    #[default]
+    Distance,
+    /// Bounding sphere radius relative to the half screen height,
+    /// `radius * projection_scale / distance`.
+    ScreenCoverage,
+}
+
+/// Switch points of a LOD chain.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LodSettings {
+    pub metric: LodMetric,
+    /// `thresholds[i]` is where level `i` switches to level `i + 1`: the distance
+    /// at which to switch for [`LodMetric::Distance`] (ascending), the coverage below
+    /// which to switch for [`LodMetric::ScreenCoverage`] (descending).
+    pub thresholds: Vec<f32>,
+    /// Width of the cross-fade band before each threshold, in the metric's unit.
+    /// Zero switches levels instantly.
+    pub fade_range: f32,
+}
+
+impl LodSettings {
+    /// Level and fade of an instance: `(level, fade, next)`, where `next` is the
+    /// weight of level `level + 1` inside a fade band.
+    fn pick(&self, camera: &LodCamera, sphere: [f32; 4], level_count: usize) -> (usize, f32, Option<f32>) {
+        let value = self.value(camera, sphere);
+        let thresholds: Vec<f32> = self.thresholds.iter().map(|&t| self.signed(t)).collect();
+        let last = level_count.saturating_sub(1);
+        let level = (0..last).find(|&i| thresholds.get(i).is_some_and(|&t| value < t)).unwrap_or(last);
+        if self.fade_range > 0.0 && level < last {
+            let t = ((value - thresholds[level] + self.fade_range) / self.fade_range).clamp(0.0, 1.0);
+            if t > 0.0 {
+                return (level, 1.0 - t, Some(t));
+            }
+        }
+        (level, 1.0, None)
+    }
+
+    /// The metric of `sphere`, oriented to grow with distance.
+    fn value(&self, camera: &LodCamera, sphere: [f32; 4]) -> f32 {
+        let d = [sphere[0] - camera.position[0], sphere[1] - camera.position[1], sphere[2] - camera.position[2]];
+        let dist = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+        match self.metric {
+            LodMetric::Distance => dist,
+            LodMetric::ScreenCoverage => -sphere[3] * camera.projection_scale / dist.max(1e-4),
+        }
+    }
+
+    fn signed(&self, threshold: f32) -> f32 {
+        match self.metric {
+            LodMetric::Distance => threshold,
+            LodMetric::ScreenCoverage => -threshold,
+        }
+    }
+}
+
+/// Camera data needed for LOD selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodCamera {
+    pub position: [f32; 3],
+    /// `projection[1][1]` of the projection matrix, `1 / tan(fov_y / 2)` for perspective.
+    pub projection_scale: f32,
+}
+
+/// A chain of meshes from finest (level 0) to coarsest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodChain {
+    pub levels: Vec<MeshHandle>,
+    pub settings: LodSettings,
+}
+
+/// The meshes to draw for one instance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodPick {
+    pub level: usize,
+    pub mesh: MeshHandle,
+    /// Weight of `mesh`, below 1 while fading to the next level.
+    pub fade: f32,
+    /// The next level's mesh and weight inside a fade band.
+    pub next: Option<(MeshHandle, f32)>,
+}
+
+/// LOD chains keyed by the handle of their finest mesh.
+#[derive(Debug, Clone, Default)]
+pub struct LodChains {
+    chains: HashMap<MeshHandle, LodChain>,
+}
+
+impl LodChains {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the chain of `mesh`. `chain.levels[0]` is usually `mesh` itself.
+    ///
+    /// ## Panics
+    /// Panics if the chain is empty or longer than [`MAX_LOD_LEVELS`].
+    pub fn insert(&mut self, mesh: MeshHandle, chain: LodChain) {
+        assert!(
+            (1..=MAX_LOD_LEVELS).contains(&chain.levels.len()),
+            "LOD chain of {:?} has {} levels, expected 1..={}",
+            mesh,
+            chain.levels.len(),
+            MAX_LOD_LEVELS
+        );
+        self.chains.insert(mesh, chain);
+    }
+
+    pub fn remove(&mut self, mesh: MeshHandle) -> Option<LodChain> {
+        self.chains.remove(&mesh)
+    }
+
+    pub fn get(&self, mesh: MeshHandle) -> Option<&LodChain> {
+        self.chains.get(&mesh)
+    }
+
+    /// Pick the level of `mesh` for an instance with bounding sphere `sphere`
+    /// (`xyz` = world-space center, `w` = radius). Meshes without a chain always
+    /// pick themselves.
+    pub fn select(&self, mesh: MeshHandle, camera: &LodCamera, sphere: [f32; 4]) -> LodPick {
+        let Some(chain) = self.chains.get(&mesh) else {
+            return LodPick { level: 0, mesh, fade: 1.0, next: None };
+        };
+        let (level, fade, next) = chain.settings.pick(camera, sphere, chain.levels.len());
+        LodPick {
+            level,
+            mesh: chain.levels[level],
+            fade,
+            next: next.map(|weight| (chain.levels[level + 1], weight)),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct LodParams {
+    camera_position: [f32; 3],
+    projection_scale: f32,
+    level_count: u32,
+    metric: u32,
+    fade_range: f32,
+    capacity: u32,
+    thresholds: [f32; MAX_LOD_LEVELS],
+}
+
+/// Per-level visible lists and indirect draws built from an [`OcclusionCuller`]'s output.
+///
+/// Parameters and argument templates are written with `Queue::write_buffer`, so a
+/// selector can be used once per submission.
+pub struct GpuLodSelector {
+    device: Device,
+    queue: Queue,
+    pipeline: ComputePipeline,
+    layout: BindGroupLayout,
+    params: Buffer,
+    lod_instances: Buffer,
+    args: Buffer,
+    capacity: u32,
+    level_count: u32,
+    /// Bounds, culled instances and culled args buffers the bind group was created for.
+    bind_group: Option<([Buffer; 3], BindGroup)>,
+}
+
+impl GpuLodSelector {
+    /// Create a selector for up to `max_instances` instances.
+    pub fn new(device: &Device, queue: &Queue, max_instances: u32) -> Self {
+        let module = gpu_util::shader(device, "lod select shader", LOD_SELECT_SHADER);
+        let layout = gpu_util::bind_group_layout(device, "lod select layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(2, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(3, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(4, ShaderStages::COMPUTE, false),
+            gpu_util::storage_entry(5, ShaderStages::COMPUTE, false),
+        ]);
+        let pipeline = gpu_util::compute_pipeline(device, "lod select", &module, "main", &[&layout]);
+        let params = gpu_util::buffer(device, "lod params", size_of::<LodParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let args = gpu_util::buffer(
+            device,
+            "lod indirect args",
+            ARGS_SIZE * MAX_LOD_LEVELS as u64,
+            BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        );
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            pipeline,
+            layout,
+            params,
+            lod_instances: lod_instance_buffer(device, max_instances),
+            args,
+            capacity: max_instances,
+            level_count: 0,
+            bind_group: None,
+        }
+    }
+
+    /// Record the LOD selection of the instances that survived `culler`.
+    ///
+    /// `bounds` and `instance_count` are the ones passed to
+    /// [`OcclusionCuller::cull`]; `levels` are the index ranges of the LOD meshes
+    /// in the shared vertex/index buffers, finest first.
+    ///
+    /// ## Panics
+    /// Panics if `levels` is empty or longer than [`MAX_LOD_LEVELS`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn select(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        culler: &OcclusionCuller,
+        bounds: &Buffer,
+        instance_count: u32,
+        camera: &LodCamera,
+        settings: &LodSettings,
+        levels: &[DrawIndexedRange],
+    ) {
+        let _span = trace_span!("lod_select", instance_count);
+        assert!(
+            (1..=MAX_LOD_LEVELS).contains(&levels.len()),
+            "{} LOD levels given, expected 1..={}",
+            levels.len(),
+            MAX_LOD_LEVELS
+        );
+        if instance_count > self.capacity {
+            self.capacity = instance_count;
+            self.lod_instances = lod_instance_buffer(&self.device, instance_count);
+            self.bind_group = None;
+        }
+        self.level_count = levels.len() as u32;
+
+        let mut thresholds = [f32::MAX; MAX_LOD_LEVELS];
+        for (slot, &threshold) in thresholds.iter_mut().zip(&settings.thresholds) {
+            *slot = settings.signed(threshold);
+        }
+        let params = LodParams {
+            camera_position: camera.position,
+            projection_scale: camera.projection_scale,
+            level_count: self.level_count,
+            metric: (settings.metric == LodMetric::ScreenCoverage) as u32,
+            fade_range: settings.fade_range.max(0.0),
+            capacity: self.capacity,
+            thresholds,
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+
+        // Each level's instances start at `level * capacity`, which the vertex shader
+        // sees through `first_instance`.
+        let args: Vec<DrawIndexedIndirectArgs> = levels
+            .iter()
+            .enumerate()
+            .map(|(level, range)| DrawIndexedIndirectArgs {
+                index_count: range.index_count,
+                instance_count: 0,
+                first_index: range.first_index,
+                base_vertex: range.base_vertex,
+                first_instance: level as u32 * self.capacity,
+            })
+            .collect();
+        let bytes: Vec<u8> = args.iter().flat_map(|a| a.as_bytes().to_vec()).collect();
+        self.queue.write_buffer(&self.args, 0, &bytes);
+
+        let inputs = [bounds, culler.visible_instances(), culler.indirect_args()];
+        let stale = self
+            .bind_group
+            .as_ref()
+            .is_none_or(|(cached, _)| cached.iter().zip(inputs).any(|(a, b)| a != b));
+        if stale {
+            let bind_group = gpu_util::bind_group(&self.device, "lod select", &self.layout, &[
+                self.params.as_entire_binding(),
+                bounds.as_entire_binding(),
+                culler.visible_instances().as_entire_binding(),
+                culler.indirect_args().as_entire_binding(),
+                self.lod_instances.as_entire_binding(),
+                self.args.as_entire_binding(),
+            ]);
+            self.bind_group = Some((inputs.map(Buffer::clone), bind_group));
+        }
+
+        let (_, bind_group) = self.bind_group.as_ref().unwrap();
+        gpu_util::dispatch(encoder, "lod select", &self.pipeline, &[bind_group], [instance_count.div_ceil(64), 1, 1]);
+    }
+
+    /// `array<LodInstance>` with the instances of level `l` starting at `l * capacity`.
+    pub fn lod_instances(&self) -> &Buffer {
+        &self.lod_instances
+    }
+
+    /// One `DrawIndexedIndirectArgs` per level, filled by [`select`](Self::select).
+    pub fn indirect_args(&self) -> &Buffer {
+        &self.args
+    }
+
+    /// Byte offset of level `level`'s arguments in [`indirect_args`](Self::indirect_args).
+    pub fn args_offset(level: u32) -> u64 {
+        level as u64 * ARGS_SIZE
+    }
+
+    /// Issue the indirect draw of every level. Pipeline, bind groups and buffers must already be set.
+    pub fn draw(&self, pass: &mut RenderPass) {
+        for level in 0..self.level_count {
+            pass.draw_indexed_indirect(&self.args, Self::args_offset(level));
+        }
+    }
+}
+
+fn lod_instance_buffer(device: &Device, capacity: u32) -> Buffer {
+    gpu_util::buffer(
+        device,
+        "lod instances",
+        capacity as u64 * MAX_LOD_LEVELS as u64 * 8,
+        BufferUsages::STORAGE,
+    )
+}
@@ -0,0 +1,117 @@
+//! Mesh optimization with meshoptimizer (feature `meshopt`).
+//!
+//! [`MeshData::optimize`] reorders (and optionally simplifies) the triangles of a mesh
+//! for the GPU:
+//! - simplification to a fraction of the triangles within an error bound,
+//! - vertex cache optimization, so shared vertices are shaded once,
+//! - overdraw optimization, so front faces are drawn first where the cache allows.
+//!
+//! Only indices change, so vertex data shared with skinning or morph targets stays valid.
+//!
+//! Importers optimize every mesh they load once
+//! [`MeshManager::set_import_optimization`](crate::meshes::MeshManager::set_import_optimization)
+//! is set, and record the before/after statistics:
+//! ```ignore
+//! meshes.set_import_optimization(Some(MeshOptimizeOptions::default()));
+//! let scene = load_gltf(&device, &queue, &mut meshes, "assets/city.glb")?;
+//! for report in meshes.take_import_reports() {
+//!     println!("{}: ACMR {:.2} -> {:.2}", report.label, report.before.acmr, report.after.acmr);
+//! }
+//! ```
+use crate::meshes::TangentVertex;
+use crate::primitives::MeshData;
+
+/// Triangle simplification target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimplifyTarget {
+    /// Fraction of the triangles to keep, in `0..=1`.
+    pub ratio: f32,
+    /// Maximum deviation relative to the mesh extents, e.g. `0.01` for 1%.
+    pub max_error: f32,
+    /// Keep vertices on open borders in place, so adjacent meshes stay sealed.
+    pub lock_border: bool,
+}
+
+/// Which optimizations [`MeshData::optimize`] runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshOptimizeOptions {
+    pub vertex_cache: bool,
+    /// Overdraw optimization, allowed to make the vertex cache efficiency worse by this
+    /// factor (e.g. `1.05`). Requires `vertex_cache`.
+    pub overdraw_threshold: Option<f32>,
+    pub simplify: Option<SimplifyTarget>,
+}
+
+impl Default for MeshOptimizeOptions {
+    /// Vertex cache and overdraw optimization, no simplification.
+    fn default() -> Self {
+        Self {
+            vertex_cache: true,
+            overdraw_threshold: Some(1.05),
+            simplify: None,
+        }
+    }
+}
+
+/// GPU efficiency estimate of a triangle list.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MeshStats {
+    pub triangle_count: u32,
+    /// Average vertex shader invocations per triangle for a 16-entry FIFO cache
+    /// (0.5 is ideal, 3 is the worst case).
+    pub acmr: f32,
+    /// Average fragments shaded per covered pixel, over views from all directions.
+    pub overdraw: f32,
+}
+
+/// Statistics of one optimized mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshOptimizeReport {
+    pub label: String,
+    pub before: MeshStats,
+    pub after: MeshStats,
+}
+
+impl MeshData {
+    /// Optimize the triangle order, returning the statistics before and after.
+    pub fn optimize(&mut self, options: &MeshOptimizeOptions) -> (MeshStats, MeshStats) {
+        let _span = trace_span!("mesh_optimize", triangles = self.indices.len() / 3);
+        let before = self.stats();
+        let vertex_count = self.vertices.len();
+        let bytes: &[u8] = bytemuck::cast_slice(&self.vertices);
+        let Ok(positions) = meshopt::VertexDataAdapter::new(bytes, size_of::<TangentVertex>(), 0) else {
+            return (before, before);
+        };
+
+        if let Some(target) = options.simplify {
+            let target_count = ((self.indices.len() / 3) as f32 * target.ratio.clamp(0.0, 1.0)) as usize * 3;
+            let flags = if target.lock_border {
+                meshopt::SimplifyOptions::LockBorder
+            } else {
+                meshopt::SimplifyOptions::empty()
+            };
+            self.indices = meshopt::simplify(&self.indices, &positions, target_count, target.max_error, flags, None);
+        }
+        if options.vertex_cache {
+            self.indices = meshopt::optimize_vertex_cache(&self.indices, vertex_count);
+            if let Some(threshold) = options.overdraw_threshold {
+                meshopt::optimize_overdraw_in_place(&mut self.indices, &positions, threshold);
+            }
+        }
+
+        let after = self.stats();
+        (before, after)
+    }
+
+    /// Statistics of the current triangle order.
+    pub fn stats(&self) -> MeshStats {
+        let bytes: &[u8] = bytemuck::cast_slice(&self.vertices);
+        let overdraw = meshopt::VertexDataAdapter::new(bytes, size_of::<TangentVertex>(), 0)
+            .map_or(0.0, |positions| meshopt::analyze_overdraw(&self.indices, &positions).overdraw);
+        MeshStats {
+            triangle_count: (self.indices.len() / 3) as u32,
+            acmr: meshopt::analyze_vertex_cache(&self.indices, self.vertices.len(), 16, 0, 0).acmr,
+            overdraw,
+        }
+    }
+}
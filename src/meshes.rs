@@ -0,0 +1,477 @@
+//! GPU meshes tied to registered vertex layouts.
+//!
+//! Vertex layouts are registered once in a [`VertexLayoutRegistry`] and referenced by
+//! [`VertexLayoutId`] afterwards. The registry comes with the common layouts
+//! ([`VertexLayoutId::POSITION`], [`POSITION_NORMAL_UV`](VertexLayoutId::POSITION_NORMAL_UV),
+//! [`POSITION_NORMAL_UV_TANGENT`](VertexLayoutId::POSITION_NORMAL_UV_TANGENT) and
+//! [`SKINNED`](VertexLayoutId::SKINNED)) and their vertex structs.
+//!
+//! Every mesh in the [`MeshManager`] remembers its layout, and pipelines built from
+//! [`pipeline_options`](VertexLayoutRegistry::pipeline_options) carry the id in the
+//! pipeline cache key. Mismatches surface loudly instead of as garbage geometry:
+//! - [`upload`](MeshManager::upload) panics if the vertex type doesn't have the layout's stride
+//! - [`draw`](MeshManager::draw) panics if the pipeline was built for another layout
+//! - debug builds check the shader's `vs_main` inputs against the layout's attributes
+//!   when the pipeline is first used
+//!
+//! Uploads also compute the mesh's [`MeshBounds`] from the position attribute
+//! (location 0), and the manager keeps them in a storage buffer indexed by
+//! [`Mesh::bounds_index`] for culling compute passes.
+//!
+//! ## Example
+//! ```ignore
+//! let cube = meshes.upload("cube", VertexLayoutId::POSITION_NORMAL_UV, &vertices, &indices);
+//! let options = meshes.layouts().pipeline_options(VertexLayoutId::POSITION_NORMAL_UV, PipelineOptions::default());
+//! // Inside a render pass
+//! render_manager.render_with_textures(&[&albedo], shader_path, &options, &[&camera], &mut pass);
+//! meshes.draw(&mut pass, cube, &options, 0..1);
+//! ```
+use std::collections::HashMap;
+use std::ops::Range;
+use wgpu::util::DeviceExt;
+use wgpu::*;
+use crate::culling::{GpuMeshBounds, MeshBounds};
+use crate::pipelines::PipelineOptions;
+#[cfg(feature = "meshopt")]
+use crate::mesh_optimize::{MeshOptimizeOptions, MeshOptimizeReport};
+use crate::labels::namespaced;
+
+/// Identifies a layout in a [`VertexLayoutRegistry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VertexLayoutId(u32);
+
+impl VertexLayoutId {
+    /// [`PositionVertex`]: position at location 0.
+    pub const POSITION: Self = Self(0);
+    /// [`MeshVertex`]: position, normal and UV at locations 0..3.
+    pub const POSITION_NORMAL_UV: Self = Self(1);
+    /// [`TangentVertex`]: position, normal, UV and tangent at locations 0..4.
+    pub const POSITION_NORMAL_UV_TANGENT: Self = Self(2);
+    /// [`SkinnedVertex`]: [`TangentVertex`] plus joints and weights at locations 4 and 5.
+    pub const SKINNED: Self = Self(3);
+}
+
+/// Vertex of [`VertexLayoutId::POSITION`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PositionVertex {
+    pub position: [f32; 3],
+}
+
+/// Vertex of [`VertexLayoutId::POSITION_NORMAL_UV`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Vertex of [`VertexLayoutId::POSITION_NORMAL_UV_TANGENT`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TangentVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    /// Tangent direction in `xyz`, bitangent sign in `w`.
+    pub tangent: [f32; 4],
+}
+
+/// Vertex of [`VertexLayoutId::SKINNED`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub tangent: [f32; 4],
+    /// Indices of up to four influencing joints.
+    pub joints: [u32; 4],
+    /// Weights of `joints`, summing to 1.
+    pub weights: [f32; 4],
+}
+
+const POSITION_ATTRIBUTES: [VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+const MESH_ATTRIBUTES: [VertexAttribute; 3] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2];
+const TANGENT_ATTRIBUTES: [VertexAttribute; 4] =
+    wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2, 3 => Float32x4];
+const SKINNED_ATTRIBUTES: [VertexAttribute; 6] = wgpu::vertex_attr_array![
+    0 => Float32x3, 1 => Float32x3, 2 => Float32x2, 3 => Float32x4, 4 => Uint32x4, 5 => Float32x4
+];
+
+struct RegisteredLayout {
+    name: String,
+    layout: VertexBufferLayout<'static>,
+}
+
+/// Vertex layouts registered once and referenced by id.
+pub struct VertexLayoutRegistry {
+    layouts: Vec<RegisteredLayout>,
+}
+
+impl Default for VertexLayoutRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VertexLayoutRegistry {
+    /// A registry holding the built-in layouts.
+    pub fn new() -> Self {
+        let mut registry = Self { layouts: Vec::new() };
+        let builtins: [(&str, u64, &'static [VertexAttribute]); 4] = [
+            ("position", size_of::<PositionVertex>() as u64, &POSITION_ATTRIBUTES),
+            ("position_normal_uv", size_of::<MeshVertex>() as u64, &MESH_ATTRIBUTES),
+            ("position_normal_uv_tangent", size_of::<TangentVertex>() as u64, &TANGENT_ATTRIBUTES),
+            ("skinned", size_of::<SkinnedVertex>() as u64, &SKINNED_ATTRIBUTES),
+        ];
+        for (name, array_stride, attributes) in builtins {
+            registry.register(name, VertexBufferLayout {
+                array_stride,
+                step_mode: VertexStepMode::Vertex,
+                attributes,
+            });
+        }
+        registry
+    }
+
+    /// Register `layout` under `name`.
+    ///
+    /// Registering a layout equal to an existing one returns the existing id.
+    pub fn register(&mut self, name: &str, layout: VertexBufferLayout<'static>) -> VertexLayoutId {
+        if let Some(index) = self.layouts.iter().position(|registered| registered.layout == layout) {
+            return VertexLayoutId(index as u32);
+        }
+        self.layouts.push(RegisteredLayout {
+            name: name.to_string(),
+            layout,
+        });
+        VertexLayoutId(self.layouts.len() as u32 - 1)
+    }
+
+    /// ## Panics
+    /// Panics if `id` was not handed out by this registry.
+    pub fn layout(&self, id: VertexLayoutId) -> &VertexBufferLayout<'static> {
+        &self.registered(id).layout
+    }
+
+    /// ## Panics
+    /// Panics if `id` was not handed out by this registry.
+    pub fn name(&self, id: VertexLayoutId) -> &str {
+        &self.registered(id).name
+    }
+
+    /// Ids of all registered layouts.
+    pub fn ids(&self) -> impl Iterator<Item = VertexLayoutId> {
+        (0..self.layouts.len() as u32).map(VertexLayoutId)
+    }
+
+    /// Variant of `base` drawing vertices of layout `id`.
+    ///
+    /// Replaces the vertex layouts of `base` and keys the pipeline by `id`.
+    pub fn pipeline_options(&self, id: VertexLayoutId, base: PipelineOptions) -> PipelineOptions {
+        PipelineOptions {
+            vertex_layouts: vec![self.layout(id).clone()],
+            vertex_layout_id: Some(id),
+            ..base
+        }
+    }
+
+    fn registered(&self, id: VertexLayoutId) -> &RegisteredLayout {
+        self.layouts
+            .get(id.0 as usize)
+            .unwrap_or_else(|| panic!("{:?} is not registered", id))
+    }
+}
+
+/// Identifies a mesh in a [`MeshManager`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MeshHandle(u64);
+
+/// Vertex and index buffers of one mesh.
+pub struct Mesh {
+    layout: VertexLayoutId,
+    vertex_buffer: Buffer,
+    vertex_count: u32,
+    index_buffer: Option<Buffer>,
+    index_count: u32,
+    bounds: Option<MeshBounds>,
+    bounds_index: u32,
+}
+
+impl Mesh {
+    pub fn layout(&self) -> VertexLayoutId {
+        self.layout
+    }
+
+    pub fn vertex_buffer(&self) -> &Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    /// The `u32` index buffer, `None` for non-indexed meshes.
+    pub fn index_buffer(&self) -> Option<&Buffer> {
+        self.index_buffer.as_ref()
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// Local-space bounds of the uploaded vertices, `None` if the layout has no
+    /// `Float32x3`/`Float32x4` attribute at location 0.
+    ///
+    /// Meshes deformed on the GPU (skinning, morph targets) keep their rest pose bounds.
+    pub fn bounds(&self) -> Option<&MeshBounds> {
+        self.bounds.as_ref()
+    }
+
+    /// Index of the mesh in [`MeshManager::bounds_buffer`].
+    pub fn bounds_index(&self) -> u32 {
+        self.bounds_index
+    }
+}
+
+/// Owns uploaded meshes and the vertex layouts they use.
+pub struct MeshManager {
+    device: Device,
+    layouts: VertexLayoutRegistry,
+    meshes: HashMap<MeshHandle, Mesh>,
+    next_handle: u64,
+    /// One slot per mesh, indexed by [`Mesh::bounds_index`].
+    bounds: Vec<GpuMeshBounds>,
+    free_bounds: Vec<u32>,
+    /// `None` after meshes were added or removed.
+    bounds_buffer: Option<Buffer>,
+    #[cfg(feature = "meshopt")]
+    import_optimization: Option<MeshOptimizeOptions>,
+    #[cfg(feature = "meshopt")]
+    import_reports: Vec<MeshOptimizeReport>,
+}
+
+impl MeshManager {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            layouts: VertexLayoutRegistry::new(),
+            meshes: HashMap::new(),
+            next_handle: 0,
+            bounds: Vec::new(),
+            free_bounds: Vec::new(),
+            bounds_buffer: None,
+            #[cfg(feature = "meshopt")]
+            import_optimization: None,
+            #[cfg(feature = "meshopt")]
+            import_reports: Vec::new(),
+        }
+    }
+
+    pub fn layouts(&self) -> &VertexLayoutRegistry {
+        &self.layouts
+    }
+
+    /// Register a custom vertex layout, see [`VertexLayoutRegistry::register`].
+    pub fn register_layout(&mut self, name: &str, layout: VertexBufferLayout<'static>) -> VertexLayoutId {
+        self.layouts.register(name, layout)
+    }
+
+    /// Upload a mesh of layout `layout`. Empty `indices` make a non-indexed mesh.
+    ///
+    /// ## Panics
+    /// Panics if `V` doesn't have the stride of `layout`.
+    pub fn upload<V: bytemuck::Pod>(&mut self, label: &str, layout: VertexLayoutId, vertices: &[V], indices: &[u32]) -> MeshHandle {
+        self.upload_with_usage(label, layout, vertices, indices, BufferUsages::empty())
+    }
+
+    /// [`upload`](Self::upload) with `usage` added to the vertex buffer, e.g.
+    /// `STORAGE` for meshes written by the [`SkinningPass`](crate::skinning::SkinningPass).
+    ///
+    /// ## Panics
+    /// Panics if `V` doesn't have the stride of `layout`.
+    pub fn upload_with_usage<V: bytemuck::Pod>(
+        &mut self,
+        label: &str,
+        layout: VertexLayoutId,
+        vertices: &[V],
+        indices: &[u32],
+        usage: BufferUsages,
+    ) -> MeshHandle {
+        let stride = self.layouts.layout(layout).array_stride;
+        assert_eq!(
+            size_of::<V>() as u64,
+            stride,
+            "Mesh `{}`: vertex type is {} bytes, but layout `{}` has a stride of {} bytes",
+            label,
+            size_of::<V>(),
+            self.layouts.name(layout),
+            stride
+        );
+
+        let vertex_buffer = self.device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some(&namespaced(label)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | usage,
+        });
+        let index_buffer = (!indices.is_empty()).then(|| {
+            self.device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some(&namespaced(label)),
+                contents: bytemuck::cast_slice(indices),
+                usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+            })
+        });
+
+        let bounds = position_bounds(self.layouts.layout(layout), bytemuck::cast_slice(vertices));
+        let slot = bounds.as_ref().map_or(GpuMeshBounds::UNBOUNDED, GpuMeshBounds::from);
+        let bounds_index = match self.free_bounds.pop() {
+            Some(index) => {
+                self.bounds[index as usize] = slot;
+                index
+            }
+            None => {
+                self.bounds.push(slot);
+                self.bounds.len() as u32 - 1
+            }
+        };
+        self.bounds_buffer = None;
+
+        let handle = MeshHandle(self.next_handle);
+        self.next_handle += 1;
+        self.meshes.insert(handle, Mesh {
+            layout,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+            index_buffer,
+            index_count: indices.len() as u32,
+            bounds,
+            bounds_index,
+        });
+        handle
+    }
+
+    /// Optimize meshes loaded by the importers with `options`, `None` to import them unchanged.
+    #[cfg(feature = "meshopt")]
+    pub fn set_import_optimization(&mut self, options: Option<MeshOptimizeOptions>) {
+        self.import_optimization = options;
+    }
+
+    /// Statistics of the meshes optimized on import since the last
+    /// [`take_import_reports`](Self::take_import_reports).
+    #[cfg(feature = "meshopt")]
+    pub fn import_reports(&self) -> &[MeshOptimizeReport] {
+        &self.import_reports
+    }
+
+    #[cfg(feature = "meshopt")]
+    pub fn take_import_reports(&mut self) -> Vec<MeshOptimizeReport> {
+        std::mem::take(&mut self.import_reports)
+    }
+
+    /// Apply the import optimization (if any) to a mesh about to be uploaded by an importer.
+    #[cfg(any(feature = "gltf", feature = "obj"))]
+    #[cfg_attr(not(feature = "meshopt"), allow(unused_variables))]
+    pub(crate) fn prepare_import(&mut self, label: &str, data: &mut crate::primitives::MeshData) {
+        #[cfg(feature = "meshopt")]
+        if let Some(options) = &self.import_optimization {
+            let (before, after) = data.optimize(options);
+            self.import_reports.push(MeshOptimizeReport {
+                label: label.to_string(),
+                before,
+                after,
+            });
+        }
+    }
+
+    pub fn get(&self, handle: MeshHandle) -> Option<&Mesh> {
+        self.meshes.get(&handle)
+    }
+
+    /// Local-space bounds of a mesh, see [`Mesh::bounds`].
+    pub fn bounds(&self, handle: MeshHandle) -> Option<&MeshBounds> {
+        self.meshes.get(&handle)?.bounds()
+    }
+
+    /// Storage buffer of [`GpuMeshBounds`], `array<MeshBounds>` indexed by
+    /// [`Mesh::bounds_index`]. Slots of removed meshes are unbounded until reused.
+    ///
+    /// Recreated on the first call after meshes were added or removed.
+    pub fn bounds_buffer(&mut self) -> &Buffer {
+        let slots = &self.bounds;
+        self.bounds_buffer.get_or_insert_with(|| {
+            let contents: &[u8] = if slots.is_empty() {
+                bytemuck::bytes_of(&GpuMeshBounds::UNBOUNDED)
+            } else {
+                bytemuck::cast_slice(slots)
+            };
+            self.device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some(&namespaced("mesh bounds")),
+                contents,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            })
+        })
+    }
+
+    /// Remove a mesh. Its buffers are freed once no submitted work uses them.
+    pub fn remove(&mut self, handle: MeshHandle) -> Option<Mesh> {
+        let mesh = self.meshes.remove(&handle)?;
+        self.bounds[mesh.bounds_index as usize] = GpuMeshBounds::UNBOUNDED;
+        self.free_bounds.push(mesh.bounds_index);
+        self.bounds_buffer = None;
+        Some(mesh)
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Set the mesh's buffers and draw it, after the pipeline has been bound.
+    ///
+    /// `options` are the options the bound pipeline was requested with.
+    ///
+    /// ## Panics
+    /// Panics if `handle` is unknown, or if `options` were not built for the mesh's
+    /// layout with [`VertexLayoutRegistry::pipeline_options`].
+    pub fn draw(&self, pass: &mut RenderPass, handle: MeshHandle, options: &PipelineOptions, instances: Range<u32>) {
+        let mesh = self.meshes.get(&handle).unwrap_or_else(|| panic!("{:?} is not in the mesh manager", handle));
+        assert!(
+            options.vertex_layout_id == Some(mesh.layout),
+            "{:?} has vertex layout `{}`, but the pipeline was built for {}",
+            handle,
+            self.layouts.name(mesh.layout),
+            options
+                .vertex_layout_id
+                .map_or("an unregistered layout".to_string(), |id| format!("`{}`", self.layouts.name(id)))
+        );
+        pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        match &mesh.index_buffer {
+            Some(index_buffer) => {
+                pass.set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.index_count, 0, instances);
+            }
+            None => pass.draw(0..mesh.vertex_count, instances),
+        }
+    }
+}
+
+/// Bounds of the `Float32x3`/`Float32x4` position attribute at location 0.
+fn position_bounds(layout: &VertexBufferLayout, vertices: &[u8]) -> Option<MeshBounds> {
+    let attribute = layout
+        .attributes
+        .iter()
+        .find(|a| a.shader_location == 0 && matches!(a.format, VertexFormat::Float32x3 | VertexFormat::Float32x4))?;
+    let (stride, offset) = (layout.array_stride as usize, attribute.offset as usize);
+    if stride == 0 || vertices.len() < stride {
+        return None;
+    }
+    let positions = vertices
+        .chunks_exact(stride)
+        .map(|vertex| bytemuck::pod_read_unaligned::<[f32; 3]>(&vertex[offset..offset + 12]));
+    Some(MeshBounds::from_points(positions))
+}
@@ -0,0 +1,258 @@
+//! Compute-based morph targets (blend shapes).
+//!
+//! A [`MorphMesh`] stores the base positions and normals of a mesh and one block of
+//! per-vertex deltas per target. [`MorphPass::apply`] adds the deltas scaled by the
+//! current weights and writes the result into a [`MeshManager`] mesh, which is then
+//! drawn like any static mesh:
+//! ```ignore
+//! let face = meshes.upload_with_usage("face", VertexLayoutId::POSITION_NORMAL_UV, &vertices, &indices, BufferUsages::STORAGE);
+//! let mut morph = morph_pass.create_mesh("face", &positions, &normals, &[smile, blink]);
+//! // Every frame
+//! morph.set_weights(&queue, &[0.8, blink_weight]);
+//! morph_pass.apply(&mut encoder, &mut morph, &meshes, face);
+//! ```
+//! Where positions and normals go inside each output vertex is read from the mesh's
+//! registered vertex layout: position at `@location(0)`, normal (optional) at
+//! `@location(1)`, both `Float32x3`. This matches every built-in layout.
+//!
+//! Weights can be animated with [`ChannelValues::Weights`](crate::animation::ChannelValues::Weights)
+//! channels and [`AnimationClip::sample_weights`](crate::animation::AnimationClip::sample_weights).
+use wgpu::util::DeviceExt;
+use wgpu::*;
+use crate::gpu_util;
+use crate::meshes::{MeshHandle, MeshManager};
+use crate::labels::namespaced;
+
+const MORPH_SHADER: &str = r#"
+struct MorphParams {
+    vertex_count: u32,
+    target_count: u32,
+    stride: u32,
+    position_offset: u32,
+    // 0xffffffff if the output has no normals.
+    normal_offset: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: MorphParams;
+// Position and normal of every vertex.
+@group(0) @binding(1) var<storage, read> base: array<vec4<f32>>;
+// Position and normal deltas of every vertex, target after target.
+@group(0) @binding(2) var<storage, read> deltas: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read> weights: array<f32>;
+@group(0) @binding(4) var<storage, read_write> output: array<f32>;
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.vertex_count {
+        return;
+    }
+    var position = base[id.x * 2u].xyz;
+    var normal = base[id.x * 2u + 1u].xyz;
+    for (var t = 0u; t < params.target_count; t++) {
+        let weight = weights[t];
+        if weight != 0.0 {
+            let delta = (t * params.vertex_count + id.x) * 2u;
+            position += deltas[delta].xyz * weight;
+            normal += deltas[delta + 1u].xyz * weight;
+        }
+    }
+
+    let p = id.x * params.stride + params.position_offset;
+    output[p + 0u] = position.x;
+    output[p + 1u] = position.y;
+    output[p + 2u] = position.z;
+    if params.normal_offset != 0xffffffffu {
+        let n = id.x * params.stride + params.normal_offset;
+        let unit = normalize(normal);
+        output[n + 0u] = unit.x;
+        output[n + 1u] = unit.y;
+        output[n + 2u] = unit.z;
+    }
+}
+"#;
+
+/// Per-vertex deltas of one morph target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphTarget {
+    pub position_deltas: Vec<[f32; 3]>,
+    /// Empty if the target doesn't change normals.
+    pub normal_deltas: Vec<[f32; 3]>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct MorphParams {
+    vertex_count: u32,
+    target_count: u32,
+    stride: u32,
+    position_offset: u32,
+    normal_offset: u32,
+    _padding: [u32; 3],
+}
+
+/// GPU-side base vertices, target deltas and weights of one morphable mesh.
+pub struct MorphMesh {
+    base: Buffer,
+    deltas: Buffer,
+    weights: Buffer,
+    params: Buffer,
+    vertex_count: u32,
+    target_count: u32,
+    /// Bind group and the output buffer it was created for.
+    bind_group: Option<(Buffer, BindGroup)>,
+}
+
+impl MorphMesh {
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn target_count(&self) -> u32 {
+        self.target_count
+    }
+
+    /// Upload the target weights for the next [`MorphPass::apply`].
+    ///
+    /// Extra weights are ignored; missing ones keep their previous value.
+    pub fn set_weights(&self, queue: &Queue, weights: &[f32]) {
+        let count = weights.len().min(self.target_count as usize);
+        if count > 0 {
+            queue.write_buffer(&self.weights, 0, bytemuck::cast_slice(&weights[..count]));
+        }
+    }
+}
+
+/// Compute pass applying [`MorphMesh`]es to mesh manager vertex buffers.
+pub struct MorphPass {
+    device: Device,
+    queue: Queue,
+    layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl MorphPass {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let module = gpu_util::shader(device, "morph shader", MORPH_SHADER);
+        let layout = gpu_util::bind_group_layout(device, "morph layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(2, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(3, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(4, ShaderStages::COMPUTE, false),
+        ]);
+        let pipeline = gpu_util::compute_pipeline(device, "morph", &module, "main", &[&layout]);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            layout,
+            pipeline,
+        }
+    }
+
+    /// Upload a morphable mesh. All weights start at zero.
+    ///
+    /// ## Panics
+    /// Panics if `targets` is empty, or if `normals` or a target's deltas don't have
+    /// one entry per position (target normal deltas may also be empty).
+    pub fn create_mesh(&self, label: &str, positions: &[[f32; 3]], normals: &[[f32; 3]], targets: &[MorphTarget]) -> MorphMesh {
+        assert!(!targets.is_empty(), "Morph mesh '{}' needs at least one target", label);
+        assert_eq!(positions.len(), normals.len(), "Morph mesh '{}' needs one normal per position", label);
+        let _span = trace_span!("create_morph_mesh", label, vertices = positions.len(), targets = targets.len());
+
+        let base: Vec<[f32; 4]> = positions
+            .iter()
+            .zip(normals)
+            .flat_map(|(p, n)| [[p[0], p[1], p[2], 1.0], [n[0], n[1], n[2], 0.0]])
+            .collect();
+        let mut deltas: Vec<[f32; 4]> = Vec::with_capacity(base.len() * targets.len());
+        for (index, target) in targets.iter().enumerate() {
+            assert!(
+                target.position_deltas.len() == positions.len()
+                    && (target.normal_deltas.is_empty() || target.normal_deltas.len() == positions.len()),
+                "Morph target {} of '{}' doesn't have one delta per vertex",
+                index,
+                label
+            );
+            for (vertex, p) in target.position_deltas.iter().enumerate() {
+                let n = target.normal_deltas.get(vertex).copied().unwrap_or([0.0; 3]);
+                deltas.push([p[0], p[1], p[2], 0.0]);
+                deltas.push([n[0], n[1], n[2], 0.0]);
+            }
+        }
+
+        let storage = |label: &str, contents: &[u8]| {
+            self.device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some(&namespaced(label)),
+                contents,
+                usage: BufferUsages::STORAGE,
+            })
+        };
+        MorphMesh {
+            base: storage(label, bytemuck::cast_slice(&base)),
+            deltas: storage("morph target deltas", bytemuck::cast_slice(&deltas)),
+            weights: gpu_util::buffer(
+                &self.device,
+                "morph weights",
+                targets.len() as u64 * 4,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            ),
+            params: gpu_util::buffer(&self.device, "morph params", size_of::<MorphParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST),
+            vertex_count: positions.len() as u32,
+            target_count: targets.len() as u32,
+            bind_group: None,
+        }
+    }
+
+    /// Record morphing `mesh` with its current weights into the vertex buffer of `output`.
+    ///
+    /// ## Panics
+    /// Panics if `output` is unknown, has a different vertex count, or its layout has
+    /// no `Float32x3` position at `@location(0)`. Its vertex buffer needs `STORAGE`
+    /// usage, see [`MeshManager::upload_with_usage`].
+    pub fn apply(&self, encoder: &mut CommandEncoder, mesh: &mut MorphMesh, meshes: &MeshManager, output: MeshHandle) {
+        let target = meshes.get(output).unwrap_or_else(|| panic!("{:?} is not in the mesh manager", output));
+        assert_eq!(
+            target.vertex_count(),
+            mesh.vertex_count,
+            "{:?} has {} vertices, the morph mesh {}",
+            output,
+            target.vertex_count(),
+            mesh.vertex_count
+        );
+        let layout = meshes.layouts().layout(target.layout());
+        let offset = |location: u32| {
+            layout
+                .attributes
+                .iter()
+                .find(|a| a.shader_location == location && a.format == VertexFormat::Float32x3)
+                .map(|a| a.offset as u32 / 4)
+        };
+        let position_offset = offset(0).unwrap_or_else(|| {
+            panic!("Vertex layout `{}` has no Float32x3 position at location 0", meshes.layouts().name(target.layout()))
+        });
+        let params = MorphParams {
+            vertex_count: mesh.vertex_count,
+            target_count: mesh.target_count,
+            stride: layout.array_stride as u32 / 4,
+            position_offset,
+            normal_offset: offset(1).unwrap_or(u32::MAX),
+            _padding: [0; 3],
+        };
+        self.queue.write_buffer(&mesh.params, 0, bytemuck::bytes_of(&params));
+
+        let buffer = target.vertex_buffer();
+        if mesh.bind_group.as_ref().is_none_or(|(cached, _)| cached != buffer) {
+            let bind_group = gpu_util::bind_group(&self.device, "morph", &self.layout, &[
+                mesh.params.as_entire_binding(),
+                mesh.base.as_entire_binding(),
+                mesh.deltas.as_entire_binding(),
+                mesh.weights.as_entire_binding(),
+                buffer.as_entire_binding(),
+            ]);
+            mesh.bind_group = Some((buffer.clone(), bind_group));
+        }
+
+        let (_, bind_group) = mesh.bind_group.as_ref().unwrap();
+        gpu_util::dispatch(encoder, "morph", &self.pipeline, &[bind_group], [mesh.vertex_count.div_ceil(64), 1, 1]);
+    }
+}
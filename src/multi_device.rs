@@ -0,0 +1,196 @@
+//! Render managers for several devices sharing CPU-side asset data.
+//!
+//! Each device (an integrated and a discrete GPU, or one adapter per window) gets
+//! its own [`RenderManager`] with its own pipelines, bind groups and textures.
+//! CPU-side data, like decoded texture pixels, lives once in [`SharedAssets`] and
+//! is uploaded to each device on first use.
+//!
+//! GPU objects handed out by [`MultiDeviceManager`] are wrapped in [`DeviceBound`],
+//! which remembers the device they belong to, so a texture view of one device
+//! can't silently end up in a bind group of another.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+use wgpu::{Device, Queue, TextureView};
+use crate::diagnostics::entry_id;
+use crate::renderer::RenderManager;
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+/// Identifies the device a manager or resource belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceId(u64);
+
+impl DeviceId {
+    /// Id of `device`. Clones of the same device share the id.
+    pub fn of(device: &Device) -> Self {
+        Self(entry_id(device))
+    }
+}
+
+/// A GPU object tagged with the device it was created on.
+#[derive(Debug, Clone)]
+pub struct DeviceBound<T> {
+    device: DeviceId,
+    value: T,
+}
+
+impl<T> DeviceBound<T> {
+    pub(crate) fn new(device: DeviceId, value: T) -> Self {
+        Self { device, value }
+    }
+
+    /// The device this object belongs to.
+    pub fn device(&self) -> DeviceId {
+        self.device
+    }
+
+    /// The object, if it belongs to `device`.
+    pub fn try_get(&self, device: DeviceId) -> Option<&T> {
+        (self.device == device).then_some(&self.value)
+    }
+
+    /// The object, for use with `manager`.
+    ///
+    /// ## Panics
+    /// Panics if the object was created on a different device than `manager`'s.
+    pub fn get(&self, manager: &RenderManager) -> &T {
+        let device = manager.device_id();
+        self.try_get(device).unwrap_or_else(|| {
+            panic!("Resource of device {:?} used with a manager of device {:?}", self.device, device)
+        })
+    }
+}
+
+/// CPU-side asset data shared by all devices.
+///
+/// Thread-safe; share it through an `Arc`.
+#[derive(Default)]
+pub struct SharedAssets {
+    textures: RwLock<HashMap<String, Arc<TextureRequest>>>,
+}
+
+impl SharedAssets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register texture data under `name`, replacing previous data.
+    ///
+    /// Devices that already uploaded the old data keep it until
+    /// [`MultiDeviceManager::reload_texture`] is called.
+    pub fn insert_texture(&self, name: impl Into<String>, request: TextureRequest) {
+        self.textures
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.into(), Arc::new(request));
+    }
+
+    /// Texture data registered under `name`.
+    pub fn texture(&self, name: &str) -> Option<Arc<TextureRequest>> {
+        self.textures.read().unwrap_or_else(PoisonError::into_inner).get(name).cloned()
+    }
+
+    /// Remove the texture data registered under `name`.
+    pub fn remove_texture(&self, name: &str) -> bool {
+        self.textures.write().unwrap_or_else(PoisonError::into_inner).remove(name).is_some()
+    }
+}
+
+struct DeviceContext {
+    id: DeviceId,
+    manager: RenderManager,
+    textures: HashMap<String, LoadedTexture>,
+}
+
+/// One [`RenderManager`] per device, sharing a single [`SharedAssets`] store.
+pub struct MultiDeviceManager {
+    assets: Arc<SharedAssets>,
+    contexts: Vec<DeviceContext>,
+}
+
+impl MultiDeviceManager {
+    pub fn new(assets: Arc<SharedAssets>) -> Self {
+        Self {
+            assets,
+            contexts: Vec::new(),
+        }
+    }
+
+    /// The shared CPU-side asset store.
+    pub fn assets(&self) -> &Arc<SharedAssets> {
+        &self.assets
+    }
+
+    /// Create a manager for `device`. Adding the same device twice returns the existing id.
+    pub fn add_device(&mut self, device: &Device, queue: &Queue, texture_shader_dir: PathBuf) -> DeviceId {
+        let id = DeviceId::of(device);
+        if !self.contexts.iter().any(|c| c.id == id) {
+            self.contexts.push(DeviceContext {
+                id,
+                manager: RenderManager::new(device, queue, texture_shader_dir),
+                textures: HashMap::new(),
+            });
+        }
+        id
+    }
+
+    /// Drop the manager and all GPU resources of a device.
+    pub fn remove_device(&mut self, id: DeviceId) -> bool {
+        let before = self.contexts.len();
+        self.contexts.retain(|c| c.id != id);
+        self.contexts.len() != before
+    }
+
+    /// Ids of all devices, in insertion order.
+    pub fn devices(&self) -> Vec<DeviceId> {
+        self.contexts.iter().map(|c| c.id).collect()
+    }
+
+    /// The manager of a device.
+    pub fn manager(&mut self, id: DeviceId) -> Option<&mut RenderManager> {
+        self.context(id).map(|c| &mut c.manager)
+    }
+
+    /// View of the shared texture `name` on device `id`, uploading it on first use.
+    ///
+    /// Returns `None` for unknown devices or texture names.
+    pub fn texture(&mut self, id: DeviceId, name: &str) -> Option<DeviceBound<TextureView>> {
+        let assets = self.assets.clone();
+        let context = self.context(id)?;
+        if !context.textures.contains_key(name) {
+            let request = assets.texture(name)?;
+            let _span = trace_span!("upload_shared_texture", name);
+            let texture = create_texture(context.manager.device(), context.manager.queue(), &request);
+            context.textures.insert(name.to_string(), texture);
+        }
+        Some(DeviceBound::new(id, context.textures[name].view.clone()))
+    }
+
+    /// Drop the uploaded copies of texture `name` on every device.
+    ///
+    /// The next [`texture`](Self::texture) call uploads the current data again.
+    pub fn reload_texture(&mut self, name: &str) {
+        for context in &mut self.contexts {
+            context.textures.remove(name);
+        }
+    }
+
+    /// Enable or disable a shader define on every device,
+    /// see [`RenderManager::update_define`].
+    pub fn update_define(&mut self, define: &str, enabled: bool) {
+        for context in &mut self.contexts {
+            context.manager.update_define(define.to_string(), enabled);
+        }
+    }
+
+    /// Call [`RenderManager::begin_frame`] on every device.
+    pub fn begin_frame(&mut self) {
+        for context in &mut self.contexts {
+            context.manager.begin_frame();
+        }
+    }
+
+    fn context(&mut self, id: DeviceId) -> Option<&mut DeviceContext> {
+        self.contexts.iter_mut().find(|c| c.id == id)
+    }
+}
@@ -0,0 +1,241 @@
+//! Wavefront OBJ/MTL import (feature `obj`).
+//!
+//! [`load_obj`] reads an `.obj` file and the `.mtl` libraries it references. Every
+//! object becomes one mesh in the [`MeshManager`] (layout
+//! [`POSITION_NORMAL_UV_TANGENT`](VertexLayoutId::POSITION_NORMAL_UV_TANGENT)), and
+//! the diffuse, normal (`norm` / `map_Bump`) and specular maps of the materials are
+//! loaded as textures, each file once:
+//! ```ignore
+//! let scene = load_obj(&device, &queue, &mut meshes, "assets/house.obj")?;
+//! let options = meshes.layouts().pipeline_options(VertexLayoutId::POSITION_NORMAL_UV_TANGENT, base_options);
+//! // Inside a render pass
+//! for object in &scene.meshes {
+//!     render_manager.render_with_textures(&scene.views(object), shader_path, &options, &[&camera], &mut pass);
+//!     meshes.draw(&mut pass, object.mesh, &options, 0..1);
+//! }
+//! ```
+//!
+//! Faces are triangulated, missing normals are computed from the triangles and
+//! tangents from the UVs. UVs are flipped to the top-left origin of wgpu textures.
+//!
+//! ## Material textures
+//! [`ObjScene::views`] binds, in this order, the diffuse (sRGB), normal and specular
+//! maps. Missing maps are replaced by a 1x1 white texture (and a flat normal), so
+//! every material has the same bind group layout.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use wgpu::{Device, Queue, TextureFormat, TextureView};
+use crate::meshes::{MeshHandle, MeshManager, TangentVertex, VertexLayoutId};
+use crate::primitives::{vertex_normals, MeshData};
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+/// An OBJ file, material library or texture that could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjError {
+    /// Path of the file.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to import {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// A material of an MTL library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    /// `Kd`, white if missing.
+    pub diffuse: [f32; 3],
+    /// `Ks`, black if missing.
+    pub specular: [f32; 3],
+    /// `Ns`.
+    pub shininess: f32,
+    /// `d`, the opacity.
+    pub dissolve: f32,
+    /// Index into [`ObjScene::textures`].
+    pub diffuse_texture: Option<usize>,
+    /// Index into [`ObjScene::textures`].
+    pub normal_texture: Option<usize>,
+    /// Index into [`ObjScene::textures`].
+    pub specular_texture: Option<usize>,
+}
+
+/// One object of an OBJ file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjMesh {
+    pub name: String,
+    pub mesh: MeshHandle,
+    /// Index into [`ObjScene::materials`].
+    pub material: Option<usize>,
+}
+
+/// Everything imported from an OBJ file.
+pub struct ObjScene {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<ObjMaterial>,
+    pub textures: Vec<LoadedTexture>,
+    white: LoadedTexture,
+    flat_normal: LoadedTexture,
+}
+
+impl ObjScene {
+    /// Diffuse, normal and specular views of the material of `mesh`, with fallbacks.
+    pub fn views(&self, mesh: &ObjMesh) -> [&TextureView; 3] {
+        let material = mesh.material.map(|m| &self.materials[m]);
+        let texture = |index: Option<usize>| index.map(|i| &self.textures[i].view);
+        [
+            texture(material.and_then(|m| m.diffuse_texture)).unwrap_or(&self.white.view),
+            texture(material.and_then(|m| m.normal_texture)).unwrap_or(&self.flat_normal.view),
+            texture(material.and_then(|m| m.specular_texture)).unwrap_or(&self.white.view),
+        ]
+    }
+}
+
+/// Import an `.obj` file with its material libraries and texture maps.
+///
+/// Material libraries and maps are resolved relative to the OBJ file.
+pub fn load_obj(device: &Device, queue: &Queue, meshes: &mut MeshManager, path: impl AsRef<Path>) -> Result<ObjScene, ObjError> {
+    let source = read_obj(path.as_ref())?;
+    Ok(upload_obj(device, queue, meshes, source))
+}
+
+/// An OBJ file parsed and its maps decoded on a loader thread of the `AssetServer` (feature `assets`).
+pub struct ObjSource {
+    label: String,
+    models: Vec<tobj::Model>,
+    materials: Vec<ObjMaterial>,
+    textures: Vec<TextureRequest>,
+}
+
+/// The file reading and image decoding half of [`load_obj`], without GPU access.
+pub(crate) fn read_obj(path: &Path) -> Result<ObjSource, ObjError> {
+    let label = path.display().to_string();
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+    let (models, materials) = tobj::load_obj(path, &options).map_err(|e| error(&label, e))?;
+    let materials = materials.map_err(|e| error(&label, e))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    // Maps used as diffuse color are sRGB, normal and specular maps linear.
+    let mut loaded: HashMap<(PathBuf, bool), usize> = HashMap::new();
+    let mut textures = Vec::new();
+    let mut load_map = |map: &Option<String>, srgb: bool| -> Result<Option<usize>, ObjError> {
+        let Some(map) = map else {
+            return Ok(None);
+        };
+        let map_path = directory.join(map.trim());
+        if let Some(&index) = loaded.get(&(map_path.clone(), srgb)) {
+            return Ok(Some(index));
+        }
+        let map_label = map_path.display().to_string();
+        let image = image::open(&map_path).map_err(|e| error(&map_label, e))?.to_rgba8();
+        let mut request = TextureRequest::rgba8(map_label, image.width(), image.height(), image.into_raw());
+        if !srgb {
+            request.format = TextureFormat::Rgba8Unorm;
+        }
+        textures.push(request);
+        loaded.insert((map_path, srgb), textures.len() - 1);
+        Ok(Some(textures.len() - 1))
+    };
+    let materials = materials
+        .iter()
+        .map(|material| {
+            Ok(ObjMaterial {
+                name: material.name.clone(),
+                diffuse: material.diffuse.unwrap_or([1.0; 3]),
+                specular: material.specular.unwrap_or([0.0; 3]),
+                shininess: material.shininess.unwrap_or(0.0),
+                dissolve: material.dissolve.unwrap_or(1.0),
+                diffuse_texture: load_map(&material.diffuse_texture, true)?,
+                normal_texture: load_map(&material.normal_texture, false)?,
+                specular_texture: load_map(&material.specular_texture, false)?,
+            })
+        })
+        .collect::<Result<Vec<_>, ObjError>>()?;
+    Ok(ObjSource {
+        label,
+        models,
+        materials,
+        textures,
+    })
+}
+
+/// Create the textures and meshes of a [`read_obj`] result.
+pub(crate) fn upload_obj(device: &Device, queue: &Queue, meshes: &mut MeshManager, source: ObjSource) -> ObjScene {
+    let ObjSource {
+        label,
+        models,
+        materials,
+        textures,
+    } = source;
+    let textures = textures.iter().map(|request| create_texture(device, queue, request)).collect();
+    let meshes = models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .map(|model| {
+            let mesh = &model.mesh;
+            let positions: Vec<[f32; 3]> = mesh.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect();
+            let normals: Vec<[f32; 3]> = if mesh.normals.len() == mesh.positions.len() {
+                mesh.normals.chunks_exact(3).map(|n| [n[0], n[1], n[2]]).collect()
+            } else {
+                vertex_normals(&positions, &mesh.indices)
+            };
+            let uvs: Vec<[f32; 2]> = if mesh.texcoords.len() / 2 == positions.len() {
+                mesh.texcoords.chunks_exact(2).map(|t| [t[0], 1.0 - t[1]]).collect()
+            } else {
+                vec![[0.0; 2]; positions.len()]
+            };
+            let mut data = MeshData {
+                vertices: positions
+                    .iter()
+                    .zip(&normals)
+                    .zip(&uvs)
+                    .map(|((&position, &normal), &uv)| TangentVertex {
+                        position,
+                        normal,
+                        uv,
+                        tangent: [0.0; 4],
+                    })
+                    .collect(),
+                indices: mesh.indices.clone(),
+            }
+            .compute_tangents();
+            let mesh_label = format!("{} {}", label, model.name);
+            meshes.prepare_import(&mesh_label, &mut data);
+            ObjMesh {
+                mesh: data.upload(meshes, &mesh_label),
+                material: mesh.material_id,
+                name: model.name,
+            }
+        })
+        .collect();
+
+    let pixel = |name: &str, rgba: [u8; 4], format| {
+        let mut request = TextureRequest::rgba8(format!("{} {}", label, name), 1, 1, rgba.to_vec());
+        request.format = format;
+        create_texture(device, queue, &request)
+    };
+    ObjScene {
+        meshes,
+        materials,
+        textures,
+        white: pixel("white", [255; 4], TextureFormat::Rgba8UnormSrgb),
+        flat_normal: pixel("flat normal", [128, 128, 255, 255], TextureFormat::Rgba8Unorm),
+    }
+}
+
+fn error(label: &str, message: impl fmt::Display) -> ObjError {
+    ObjError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,260 @@
+//! Per-object data in one storage buffer.
+//!
+//! An [`ObjectTable`] keeps the transform, material index and flags of every object in
+//! a single `array<ObjectData>` that shaders index by object ID, typically taken from
+//! `instance_index` or a GPU-built instance list (see
+//! [`OcclusionCuller`](crate::occlusion::OcclusionCuller)). One bind group serves every
+//! draw, and only the slots changed since the last [`upload`](ObjectTable::upload)
+//! are written:
+//! ```ignore
+//! let car = objects.insert(ObjectData::new(transform, car_material));
+//! // Every frame
+//! objects.set_transform(car, moved);
+//! objects.upload(); // writes the car's slot only
+//! pass.set_bind_group(2, objects.bind_group(), &[]);
+//! meshes.draw(&mut pass, car_mesh, &options, car.index()..car.index() + 1);
+//! ```
+//! Paste [`OBJECT_TABLE_WGSL`] into the shader for the matching declaration.
+use wgpu::*;
+use crate::gpu_util::{self, invert, IDENTITY};
+
+/// WGSL declaration of [`ObjectData`] at `@group(2) @binding(0)`.
+pub const OBJECT_TABLE_WGSL: &str = r#"
+struct ObjectData {
+    model: mat4x4<f32>,
+    // Inverse transpose of `model`, for normals.
+    normal_matrix: mat4x4<f32>,
+    material: u32,
+    flags: u32,
+};
+
+const OBJECT_VISIBLE: u32 = 1u;
+
+@group(2) @binding(0) var<storage, read> objects: array<ObjectData>;
+"#;
+
+/// Set on every live object; removed slots have no flags.
+pub const OBJECT_VISIBLE: u32 = 1;
+
+/// Slots between two dirty ranges that are uploaded anyway to save a write.
+const MERGE_GAP: u32 = 8;
+
+/// One slot of the table, see [`OBJECT_TABLE_WGSL`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ObjectData {
+    pub model: [[f32; 4]; 4],
+    pub normal_matrix: [[f32; 4]; 4],
+    /// Index into the application's material table.
+    pub material: u32,
+    /// [`OBJECT_VISIBLE`] and application-defined bits above it.
+    pub flags: u32,
+    pub _pad: [u32; 2],
+}
+
+impl ObjectData {
+    /// A visible object with `model` as world matrix.
+    pub fn new(model: [[f32; 4]; 4], material: u32) -> Self {
+        Self {
+            model,
+            normal_matrix: normal_matrix(&model),
+            material,
+            flags: OBJECT_VISIBLE,
+            _pad: [0; 2],
+        }
+    }
+}
+
+impl Default for ObjectData {
+    fn default() -> Self {
+        Self::new(IDENTITY, 0)
+    }
+}
+
+/// Slot of an object in an [`ObjectTable`]; reused after the object is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectId(u32);
+
+impl ObjectId {
+    /// Index of the object in the shader's `objects` array.
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+/// Storage buffer of [`ObjectData`] updated incrementally.
+pub struct ObjectTable {
+    device: Device,
+    queue: Queue,
+    objects: Vec<ObjectData>,
+    live: Vec<bool>,
+    free: Vec<u32>,
+    /// Slots changed since the last upload, unsorted and possibly repeated.
+    dirty: Vec<u32>,
+    buffer: Buffer,
+    capacity: u32,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+}
+
+impl ObjectTable {
+    /// Create a table with room for `capacity` objects; it grows as needed.
+    pub fn new(device: &Device, queue: &Queue, capacity: u32) -> Self {
+        let capacity = capacity.max(1);
+        let layout = gpu_util::bind_group_layout(device, "object table layout", &[gpu_util::storage_entry(
+            0,
+            ShaderStages::VERTEX | ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+            true,
+        )]);
+        let (buffer, bind_group) = create_table(device, &layout, capacity);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            objects: Vec::new(),
+            live: Vec::new(),
+            free: Vec::new(),
+            dirty: Vec::new(),
+            buffer,
+            capacity,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn insert(&mut self, data: ObjectData) -> ObjectId {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = data;
+                self.live[index as usize] = true;
+                index
+            }
+            None => {
+                self.objects.push(data);
+                self.live.push(true);
+                self.objects.len() as u32 - 1
+            }
+        };
+        self.dirty.push(index);
+        ObjectId(index)
+    }
+
+    /// Free the slot of an object. The slot keeps no flags until it is reused.
+    pub fn remove(&mut self, id: ObjectId) {
+        if self.get(id).is_none() {
+            return;
+        }
+        self.objects[id.0 as usize] = bytemuck::Zeroable::zeroed();
+        self.live[id.0 as usize] = false;
+        self.free.push(id.0);
+        self.dirty.push(id.0);
+    }
+
+    pub fn get(&self, id: ObjectId) -> Option<&ObjectData> {
+        self.live.get(id.0 as usize).is_some_and(|&live| live).then(|| &self.objects[id.0 as usize])
+    }
+
+    /// Replace all data of an object.
+    pub fn set(&mut self, id: ObjectId, data: ObjectData) {
+        *self.slot(id) = data;
+    }
+
+    /// Move an object, recomputing its normal matrix.
+    pub fn set_transform(&mut self, id: ObjectId, model: [[f32; 4]; 4]) {
+        let slot = self.slot(id);
+        slot.model = model;
+        slot.normal_matrix = normal_matrix(&model);
+    }
+
+    pub fn set_material(&mut self, id: ObjectId, material: u32) {
+        self.slot(id).material = material;
+    }
+
+    pub fn set_flags(&mut self, id: ObjectId, flags: u32) {
+        self.slot(id).flags = flags;
+    }
+
+    /// Write the changed slots, growing the buffer (and rewriting everything) if needed.
+    ///
+    /// Slots are written with `Queue::write_buffer`, so upload once per submission.
+    pub fn upload(&mut self) {
+        let count = self.objects.len() as u32;
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            trace_event!(capacity = self.capacity, "grew object table");
+            (self.buffer, self.bind_group) = create_table(&self.device, &self.layout, self.capacity);
+            self.dirty.clear();
+            self.queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.objects));
+            return;
+        }
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        self.dirty.sort_unstable();
+        self.dirty.dedup();
+        let _span = trace_span!("object_table_upload", dirty = self.dirty.len());
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for &index in &self.dirty {
+            match ranges.last_mut() {
+                Some((_, end)) if index <= *end + MERGE_GAP => *end = index + 1,
+                _ => ranges.push((index, index + 1)),
+            }
+        }
+        for (start, end) in ranges {
+            let offset = start as u64 * size_of::<ObjectData>() as u64;
+            self.queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&self.objects[start as usize..end as usize]));
+        }
+        self.dirty.clear();
+    }
+
+    /// Number of live objects.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of slots, live or free; the length of the shader's array in use.
+    pub fn slot_count(&self) -> u32 {
+        self.objects.len() as u32
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    /// Bind group with the table at binding 0; replaced when [`upload`](Self::upload) grows the buffer.
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// ## Panics
+    /// Panics if the object was removed.
+    fn slot(&mut self, id: ObjectId) -> &mut ObjectData {
+        assert!(self.get(id).is_some(), "Object {id:?} was removed");
+        self.dirty.push(id.0);
+        &mut self.objects[id.0 as usize]
+    }
+}
+
+fn create_table(device: &Device, layout: &BindGroupLayout, capacity: u32) -> (Buffer, BindGroup) {
+    let buffer = gpu_util::buffer(
+        device,
+        "object table",
+        capacity as u64 * size_of::<ObjectData>() as u64,
+        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+    );
+    let bind_group = gpu_util::bind_group(device, "object table", layout, &[buffer.as_entire_binding()]);
+    (buffer, bind_group)
+}
+
+fn normal_matrix(model: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let inverse = invert(model);
+    std::array::from_fn(|c| std::array::from_fn(|r| inverse[r][c]))
+}
@@ -0,0 +1,588 @@
+//! Hierarchical-Z occlusion culling.
+//!
+//! [`HiZBuffer`] turns the depth buffer of a depth prepass into a mip chain where
+//! every texel holds the farthest depth of the area it covers. [`OcclusionCuller`]
+//! tests bounding spheres against that chain on the GPU and writes the surviving
+//! instance indices plus a `draw_indexed_indirect` argument buffer, so occluded
+//! instances never reach the vertex shader.
+//!
+//! ## Frame flow
+//! 1. Depth prepass (your own pass)
+//! 2. [`HiZBuffer::build`] from the prepass depth
+//! 3. [`OcclusionCuller::cull`] per instanced mesh
+//! 4. Draw with [`OcclusionCuller::draw`]; the vertex shader reads the real instance
+//!    index as `visible_instances[instance_index]`. To draw many culled meshes with
+//!    one multi-draw, copy the arguments into a
+//!    [`MultiDrawBatches`](crate::indirect::MultiDrawBatches) batch instead.
+//!
+//! ## Shader binding layout of the culling pass (group 0)
+//! - `@binding(0)`: cull parameters (uniform)
+//! - `@binding(1)`: instance bounding spheres, `array<vec4<f32>>` as `xyz = center, w = radius`
+//! - `@binding(2)`: visible instance indices (output)
+//! - `@binding(3)`: indirect draw arguments (output)
+//! - `@binding(4)`: Hi-Z texture
+use wgpu::*;
+use crate::gpu_util;
+use crate::labels::namespaced;
+
+const HIZ_COPY_SHADER: &str = r#"
+@group(0) @binding(0) var depth: texture_depth_2d;
+@group(0) @binding(1) var dst: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let d = textureLoad(depth, vec2<i32>(id.xy), 0);
+    textureStore(dst, vec2<i32>(id.xy), vec4<f32>(d, 0.0, 0.0, 0.0));
+}
+"#;
+
+const HIZ_DOWNSAMPLE_SHADER: &str = r#"
+struct HiZParams {
+    reversed_z: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+};
+
+@group(0) @binding(0) var src: texture_2d<f32>;
+@group(0) @binding(1) var dst: texture_storage_2d<r32float, write>;
+@group(0) @binding(2) var<uniform> params: HiZParams;
+
+fn farthest(a: f32, b: f32) -> f32 {
+    if params.reversed_z != 0u {
+        return min(a, b);
+    }
+    return max(a, b);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dst_size = textureDimensions(dst);
+    if id.x >= dst_size.x || id.y >= dst_size.y {
+        return;
+    }
+    let src_size = textureDimensions(src);
+
+    // Odd source sizes: the last row/column also covers the leftover texel.
+    var extent = vec2<u32>(1u, 1u);
+    if (src_size.x & 1u) == 1u && id.x == dst_size.x - 1u {
+        extent.x = 2u;
+    }
+    if (src_size.y & 1u) == 1u && id.y == dst_size.y - 1u {
+        extent.y = 2u;
+    }
+
+    let base = id.xy * 2u;
+    var depth = textureLoad(src, vec2<i32>(min(base, src_size - 1u)), 0).r;
+    for (var y = 0u; y <= extent.y; y++) {
+        for (var x = 0u; x <= extent.x; x++) {
+            let p = min(base + vec2<u32>(x, y), src_size - 1u);
+            depth = farthest(depth, textureLoad(src, vec2<i32>(p), 0).r);
+        }
+    }
+    textureStore(dst, vec2<i32>(id.xy), vec4<f32>(depth, 0.0, 0.0, 0.0));
+}
+"#;
+
+const OCCLUSION_CULL_SHADER: &str = r#"
+struct CullParams {
+    view_proj: mat4x4<f32>,
+    viewport: vec2<f32>,
+    instance_count: u32,
+    reversed_z: u32,
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    mip_count: u32,
+};
+
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: CullParams;
+@group(0) @binding(1) var<storage, read> bounds: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> visible: array<u32>;
+@group(0) @binding(3) var<storage, read_write> args: DrawArgs;
+@group(0) @binding(4) var hiz: texture_2d<f32>;
+
+fn farthest(a: f32, b: f32) -> f32 {
+    if params.reversed_z != 0u {
+        return min(a, b);
+    }
+    return max(a, b);
+}
+
+fn is_visible(sphere: vec4<f32>) -> bool {
+    var ndc_min = vec3<f32>(1e30);
+    var ndc_max = vec3<f32>(-1e30);
+    for (var i = 0u; i < 8u; i++) {
+        let corner = vec3<f32>(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u),
+        );
+        let clip = params.view_proj * vec4<f32>(sphere.xyz + corner * sphere.w, 1.0);
+        if clip.w <= 0.0 {
+            // Crosses the camera plane, never cull.
+            return true;
+        }
+        let ndc = clip.xyz / clip.w;
+        ndc_min = min(ndc_min, ndc);
+        ndc_max = max(ndc_max, ndc);
+    }
+
+    if ndc_max.x < -1.0 || ndc_min.x > 1.0 || ndc_max.y < -1.0 || ndc_min.y > 1.0 {
+        return false;
+    }
+
+    let uv_min = clamp(vec2<f32>(ndc_min.x, -ndc_max.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let uv_max = clamp(vec2<f32>(ndc_max.x, -ndc_min.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let size_px = (uv_max - uv_min) * params.viewport;
+    // At this level the rectangle covers at most 2x2 texels.
+    let level = min(u32(ceil(log2(max(max(size_px.x, size_px.y), 1.0)))), params.mip_count - 1u);
+    let level_size = textureDimensions(hiz, level);
+    let p0 = min(vec2<u32>(uv_min * vec2<f32>(level_size)), level_size - 1u);
+    let p1 = min(vec2<u32>(uv_max * vec2<f32>(level_size)), level_size - 1u);
+
+    var occluder = textureLoad(hiz, vec2<i32>(p0), i32(level)).r;
+    occluder = farthest(occluder, textureLoad(hiz, vec2<i32>(i32(p1.x), i32(p0.y)), i32(level)).r);
+    occluder = farthest(occluder, textureLoad(hiz, vec2<i32>(i32(p0.x), i32(p1.y)), i32(level)).r);
+    occluder = farthest(occluder, textureLoad(hiz, vec2<i32>(p1), i32(level)).r);
+
+    if params.reversed_z != 0u {
+        return ndc_max.z >= occluder;
+    }
+    return ndc_min.z <= occluder;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x == 0u {
+        args.index_count = params.index_count;
+        args.first_index = params.first_index;
+        args.base_vertex = params.base_vertex;
+        args.first_instance = 0u;
+    }
+    if id.x >= params.instance_count {
+        return;
+    }
+    if is_visible(bounds[id.x]) {
+        let slot = atomicAdd(&args.instance_count, 1u);
+        visible[slot] = id.x;
+    }
+}
+"#;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct HiZParams {
+    reversed_z: u32,
+    _pad: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    view_proj: [[f32; 4]; 4],
+    viewport: [f32; 2],
+    instance_count: u32,
+    reversed_z: u32,
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    mip_count: u32,
+}
+
+/// Farthest-depth mip chain built from a depth buffer.
+///
+/// Stored as `R32Float`, one mip per halving of the depth buffer size.
+/// Only single-sampled depth buffers are supported; resolve MSAA depth first with the
+/// [`DepthResolver`](crate::depth_resolve::DepthResolver), using [`DepthResolveMode::Farthest`](crate::depth_resolve::DepthResolveMode::Farthest).
+pub struct HiZBuffer {
+    texture: Texture,
+    view: TextureView,
+    mip_views: Vec<TextureView>,
+    reversed_z: bool,
+    copy_pipeline: ComputePipeline,
+    copy_layout: BindGroupLayout,
+    downsample_pipeline: ComputePipeline,
+    downsample_layout: BindGroupLayout,
+    downsample_bind_groups: Vec<BindGroup>,
+    params: Buffer,
+    /// Depth view the copy bind group was created for.
+    copy_bind_group: Option<(TextureView, BindGroup)>,
+    device: Device,
+}
+
+impl HiZBuffer {
+    /// Create a Hi-Z buffer for a `width` x `height` depth buffer.
+    ///
+    /// `reversed_z` selects which end of the depth range counts as far.
+    pub fn new(device: &Device, queue: &Queue, width: u32, height: u32, reversed_z: bool) -> Self {
+        let copy_module = gpu_util::shader(device, "hi-z copy shader", HIZ_COPY_SHADER);
+        let copy_layout = gpu_util::bind_group_layout(device, "hi-z copy layout", &[
+            gpu_util::texture_entry(0, ShaderStages::COMPUTE, TextureSampleType::Depth, TextureViewDimension::D2),
+            gpu_util::storage_texture_entry(1, ShaderStages::COMPUTE, TextureFormat::R32Float, StorageTextureAccess::WriteOnly, TextureViewDimension::D2),
+        ]);
+        let copy_pipeline = gpu_util::compute_pipeline(device, "hi-z copy", &copy_module, "main", &[&copy_layout]);
+
+        let downsample_module = gpu_util::shader(device, "hi-z downsample shader", HIZ_DOWNSAMPLE_SHADER);
+        let downsample_layout = gpu_util::bind_group_layout(device, "hi-z downsample layout", &[
+            gpu_util::texture_entry(0, ShaderStages::COMPUTE, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+            gpu_util::storage_texture_entry(1, ShaderStages::COMPUTE, TextureFormat::R32Float, StorageTextureAccess::WriteOnly, TextureViewDimension::D2),
+            gpu_util::uniform_entry(2, ShaderStages::COMPUTE),
+        ]);
+        let downsample_pipeline = gpu_util::compute_pipeline(device, "hi-z downsample", &downsample_module, "main", &[&downsample_layout]);
+
+        let params = gpu_util::buffer(device, "hi-z params", size_of::<HiZParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        queue.write_buffer(&params, 0, bytemuck::bytes_of(&HiZParams { reversed_z: reversed_z as u32, _pad: [0; 3] }));
+
+        let (texture, view, mip_views) = create_hiz_texture(device, width, height);
+        let downsample_bind_groups = create_downsample_bind_groups(device, &downsample_layout, &mip_views, &params);
+
+        Self {
+            texture,
+            view,
+            mip_views,
+            reversed_z,
+            copy_pipeline,
+            copy_layout,
+            downsample_pipeline,
+            downsample_layout,
+            downsample_bind_groups,
+            params,
+            copy_bind_group: None,
+            device: device.clone(),
+        }
+    }
+
+    /// Recreate the mip chain for a new depth buffer size.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = self.texture.size();
+        if size.width == width && size.height == height {
+            return;
+        }
+        let (texture, view, mip_views) = create_hiz_texture(&self.device, width, height);
+        self.texture = texture;
+        self.view = view;
+        self.mip_views = mip_views;
+        self.copy_bind_group = None;
+        self.downsample_bind_groups =
+            create_downsample_bind_groups(&self.device, &self.downsample_layout, &self.mip_views, &self.params);
+    }
+
+    /// Record the passes building the mip chain from `depth_view`.
+    ///
+    /// `depth_view` must have the size passed to [`new`](Self::new) / [`resize`](Self::resize).
+    pub fn build(&mut self, encoder: &mut CommandEncoder, depth_view: &TextureView) {
+        let _span = trace_span!("hiz_build", mips = self.mip_views.len());
+        if self.copy_bind_group.as_ref().is_none_or(|(view, _)| view != depth_view) {
+            let bind_group = gpu_util::bind_group(&self.device, "hi-z copy", &self.copy_layout, &[
+                BindingResource::TextureView(depth_view),
+                BindingResource::TextureView(&self.mip_views[0]),
+            ]);
+            self.copy_bind_group = Some((depth_view.clone(), bind_group));
+        }
+
+        let size = self.texture.size();
+        let (_, copy_bind_group) = self.copy_bind_group.as_ref().unwrap();
+        gpu_util::dispatch(encoder, "hi-z copy", &self.copy_pipeline, &[copy_bind_group], [
+            size.width.div_ceil(8),
+            size.height.div_ceil(8),
+            1,
+        ]);
+
+        for (i, bind_group) in self.downsample_bind_groups.iter().enumerate() {
+            let workgroups = downsample_workgroups(size, i as u32 + 1);
+            gpu_util::dispatch(encoder, "hi-z downsample", &self.downsample_pipeline, &[bind_group], workgroups);
+        }
+    }
+
+    /// View over the full mip chain.
+    pub fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    /// Size of mip 0 in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        let size = self.texture.size();
+        (size.width, size.height)
+    }
+
+    /// Number of mip levels.
+    pub fn mip_count(&self) -> u32 {
+        self.mip_views.len() as u32
+    }
+
+    /// Whether larger depth values are closer to the camera.
+    pub fn reversed_z(&self) -> bool {
+        self.reversed_z
+    }
+}
+
+/// Workgroups of the downsample pass writing `mip`, one thread per texel of the mip.
+fn downsample_workgroups(size: Extent3d, mip: u32) -> [u32; 3] {
+    let w = (size.width >> mip).max(1);
+    let h = (size.height >> mip).max(1);
+    [w.div_ceil(8), h.div_ceil(8), 1]
+}
+
+fn create_hiz_texture(device: &Device, width: u32, height: u32) -> (Texture, TextureView, Vec<TextureView>) {
+    let size = Extent3d {
+        width: width.max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+    };
+    let mip_level_count = size.max_mips(TextureDimension::D2);
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced("hi-z buffer")),
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::R32Float,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let mip_views = (0..mip_level_count)
+        .map(|mip| {
+            texture.create_view(&TextureViewDescriptor {
+                label: Some(&namespaced("hi-z mip")),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect();
+    (texture, view, mip_views)
+}
+
+fn create_downsample_bind_groups(device: &Device, layout: &BindGroupLayout, mip_views: &[TextureView], params: &Buffer) -> Vec<BindGroup> {
+    mip_views
+        .windows(2)
+        .map(|mips| {
+            gpu_util::bind_group(device, "hi-z downsample", layout, &[
+                BindingResource::TextureView(&mips[0]),
+                BindingResource::TextureView(&mips[1]),
+                params.as_entire_binding(),
+            ])
+        })
+        .collect()
+}
+
+/// Index range of the mesh drawn with the culled instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DrawIndexedRange {
+    pub index_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+}
+
+/// GPU occlusion test of instance bounding spheres against a [`HiZBuffer`].
+///
+/// Owns the visible instance list and the indirect argument buffer of one
+/// instanced draw. Use one culler per instanced mesh; its parameters are
+/// written with `Queue::write_buffer`, so a culler can be used once per submission.
+pub struct OcclusionCuller {
+    device: Device,
+    queue: Queue,
+    pipeline: ComputePipeline,
+    layout: BindGroupLayout,
+    params: Buffer,
+    visible: Buffer,
+    args: Buffer,
+    capacity: u32,
+    bind_group: Option<(Buffer, TextureView, BindGroup)>,
+}
+
+impl OcclusionCuller {
+    /// Create a culler for up to `max_instances` instances.
+    pub fn new(device: &Device, queue: &Queue, max_instances: u32) -> Self {
+        let module = gpu_util::shader(device, "occlusion cull shader", OCCLUSION_CULL_SHADER);
+        let layout = gpu_util::bind_group_layout(device, "occlusion cull layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(2, ShaderStages::COMPUTE, false),
+            gpu_util::storage_entry(3, ShaderStages::COMPUTE, false),
+            gpu_util::texture_entry(4, ShaderStages::COMPUTE, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+        ]);
+        let pipeline = gpu_util::compute_pipeline(device, "occlusion cull", &module, "main", &[&layout]);
+
+        let params = gpu_util::buffer(device, "occlusion cull params", size_of::<CullParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let (visible, args) = create_cull_buffers(device, max_instances);
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            pipeline,
+            layout,
+            params,
+            visible,
+            args,
+            capacity: max_instances,
+            bind_group: None,
+        }
+    }
+
+    /// Record the occlusion test of `instance_count` bounding spheres from `bounds`.
+    ///
+    /// `bounds` holds one `vec4<f32>` per instance (`xyz` = world-space center,
+    /// `w` = radius) and needs `STORAGE` usage. Grows the output buffers if needed.
+    pub fn cull(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        hiz: &HiZBuffer,
+        bounds: &Buffer,
+        instance_count: u32,
+        view_proj: [[f32; 4]; 4],
+        mesh: DrawIndexedRange,
+    ) {
+        let _span = trace_span!("occlusion_cull", instance_count);
+        if instance_count > self.capacity {
+            let (visible, args) = create_cull_buffers(&self.device, instance_count);
+            self.visible = visible;
+            self.args = args;
+            self.capacity = instance_count;
+            self.bind_group = None;
+        }
+
+        let (width, height) = hiz.size();
+        let params = CullParams {
+            view_proj,
+            viewport: [width as f32, height as f32],
+            instance_count,
+            reversed_z: hiz.reversed_z() as u32,
+            index_count: mesh.index_count,
+            first_index: mesh.first_index,
+            base_vertex: mesh.base_vertex,
+            mip_count: hiz.mip_count(),
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+
+        let stale = self
+            .bind_group
+            .as_ref()
+            .is_none_or(|(buffer, view, _)| buffer != bounds || view != hiz.view());
+        if stale {
+            let bind_group = gpu_util::bind_group(&self.device, "occlusion cull", &self.layout, &[
+                self.params.as_entire_binding(),
+                bounds.as_entire_binding(),
+                self.visible.as_entire_binding(),
+                self.args.as_entire_binding(),
+                BindingResource::TextureView(hiz.view()),
+            ]);
+            self.bind_group = Some((bounds.clone(), hiz.view().clone(), bind_group));
+        }
+
+        encoder.clear_buffer(&self.args, 0, None);
+        let (_, _, bind_group) = self.bind_group.as_ref().unwrap();
+        gpu_util::dispatch(encoder, "occlusion cull", &self.pipeline, &[bind_group], [instance_count.div_ceil(64), 1, 1]);
+    }
+
+    /// Indices of the visible instances, `array<u32>` in draw order.
+    pub fn visible_instances(&self) -> &Buffer {
+        &self.visible
+    }
+
+    /// `DrawIndexedIndirectArgs` filled by [`cull`](Self::cull).
+    pub fn indirect_args(&self) -> &Buffer {
+        &self.args
+    }
+
+    /// Issue the culled draw. Pipeline, bind groups and buffers must already be set.
+    pub fn draw(&self, pass: &mut RenderPass) {
+        pass.draw_indexed_indirect(&self.args, 0);
+    }
+}
+
+fn create_cull_buffers(device: &Device, max_instances: u32) -> (Buffer, Buffer) {
+    let visible = gpu_util::buffer(device, "visible instances", max_instances as u64 * 4, BufferUsages::STORAGE);
+    let args = gpu_util::buffer(
+        device,
+        "occlusion indirect args",
+        20,
+        BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+    );
+    (visible, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::RangeInclusive;
+
+    /// Source texels read for destination texel `x` along one axis, as in
+    /// `HIZ_DOWNSAMPLE_SHADER`.
+    fn footprint(src: u32, dst: u32, x: u32) -> RangeInclusive<u32> {
+        let extent = if src & 1 == 1 && x == dst - 1 { 2 } else { 1 };
+        let base = x * 2;
+        base.min(src - 1)..=(base + extent).min(src - 1)
+    }
+
+    fn downsample(src: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        let (src_w, src_h) = (src[0].len() as u32, src.len() as u32);
+        let (dst_w, dst_h) = ((src_w >> 1).max(1), (src_h >> 1).max(1));
+        (0..dst_h)
+            .map(|y| {
+                (0..dst_w)
+                    .map(|x| {
+                        footprint(src_h, dst_h, y)
+                            .flat_map(|sy| footprint(src_w, dst_w, x).map(move |sx| src[sy as usize][sx as usize]))
+                            .fold(f32::MIN, f32::max)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn odd_sizes_cover_every_source_texel() {
+        for src in 1..=67u32 {
+            let dst = (src >> 1).max(1);
+            let mut covered = vec![false; src as usize];
+            for x in 0..dst {
+                for texel in footprint(src, dst, x) {
+                    covered[texel as usize] = true;
+                }
+            }
+            assert!(covered.iter().all(|&c| c), "texels of a {}-wide mip dropped", src);
+        }
+    }
+
+    #[test]
+    fn farthest_depth_survives_to_the_last_mip() {
+        // The farthest depth sits in the last row and column, the ones truncating
+        // halving would drop.
+        for (width, height) in [(5, 3), (7, 7), (33, 17), (1, 9)] {
+            let mut depth = vec![vec![0.25f32; width]; height];
+            depth[height - 1][width - 1] = 0.75;
+            let mut mip = depth;
+            while mip.len() > 1 || mip[0].len() > 1 {
+                mip = downsample(&mip);
+            }
+            assert_eq!(mip[0][0], 0.75, "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn downsample_dispatches_cover_every_mip() {
+        let size = Extent3d { width: 1921, height: 1081, depth_or_array_layers: 1 };
+        for mip in 1..size.max_mips(TextureDimension::D2) {
+            let mip_size = size.mip_level_size(mip, TextureDimension::D2);
+            let [x, y, _] = downsample_workgroups(size, mip);
+            assert!(x * 8 >= mip_size.width && (x - 1) * 8 < mip_size.width, "mip {}", mip);
+            assert!(y * 8 >= mip_size.height && (y - 1) * 8 < mip_size.height, "mip {}", mip);
+        }
+        assert_eq!(downsample_workgroups(size, 11), [1, 1, 1]);
+    }
+}
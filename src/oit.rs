@@ -0,0 +1,304 @@
+//! Weighted blended order-independent transparency.
+//!
+//! Transparent materials drawn with [`PipelineOptions::with_oit`] render into the
+//! two [`OitTargets`] instead of the scene color target: an accumulation target
+//! summing depth-weighted premultiplied colors, and a revealage target multiplying
+//! the remaining transmittance. The pipeline cache builds these variants with their
+//! dedicated blend states, so transparent geometry can be drawn in any order.
+//! [`composite`](OitTargets::composite) then resolves both targets over the opaque
+//! scene with a fullscreen pass.
+//!
+//! ## Frame flow
+//! ```ignore
+//! // After the opaque pass; depth is loaded read-only, the pass has no MSAA
+//! {
+//!     let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!         color_attachments: &oit.color_attachments(),
+//!         depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+//!             view: &depth_view,
+//!             depth_ops: Some(Operations { load: LoadOp::Load, store: StoreOp::Store }),
+//!             stencil_ops: None,
+//!         }),
+//!         ..Default::default()
+//!     });
+//!     let options = transparent_options.clone().with_oit();
+//!     render_manager.render_with_textures(&[&glass], shader_path, &options, &[&camera], &mut pass);
+//!     // draw transparent meshes
+//! }
+//! // Pass writing the scene color target
+//! oit.composite(&mut pass);
+//! ```
+//!
+//! ## Shader side
+//! The shader is compiled with the `OIT` define, so one material shader can serve
+//! both paths. Paste [`OIT_WGSL`] into it and return [`oit_output`] from the
+//! fragment entry point:
+//! ```wgsl
+//! #ifdef OIT
+//! @fragment
+//! fn fs_main(in: VertexOutput) -> OitOutput {
+//!     return oit_output(shade(in), in.position.z);
+//! }
+//! #else
+//! // regular alpha-blended entry point
+//! #endif
+//! ```
+//!
+//! [`oit_output`]: OIT_WGSL
+use wgpu::*;
+use crate::gpu_util;
+use crate::pipelines::PipelineOptions;
+use crate::labels::namespaced;
+
+/// Format of the accumulation target.
+pub const OIT_ACCUM_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Format of the revealage target.
+pub const OIT_REVEALAGE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+/// WGSL output struct and weighting function for OIT fragment shaders.
+///
+/// `oit_output` expects a straight (not premultiplied) alpha color and the
+/// fragment depth (`position.z`), and uses the depth weight of McGuire and Bavoil.
+pub const OIT_WGSL: &str = r#"
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+};
+
+fn oit_output(color: vec4<f32>, depth: f32) -> OitOutput {
+    let premultiplied = vec4<f32>(color.rgb * color.a, color.a);
+    let distance = 1.0 - depth;
+    let weight = clamp(color.a * max(1e-2, 3e3 * distance * distance * distance), 1e-2, 3e3);
+    var out: OitOutput;
+    out.accum = premultiplied * weight;
+    out.revealage = color.a;
+    return out;
+}
+"#;
+
+const COMPOSITE_SHADER: &str = r#"
+@group(0) @binding(0) var accum_texture: texture_2d<f32>;
+@group(0) @binding(1) var revealage_texture: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4<f32> {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    return vec4<f32>(positions[idx], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let revealage = textureLoad(revealage_texture, pixel, 0).r;
+    if revealage >= 0.999 {
+        discard;
+    }
+    let accum = textureLoad(accum_texture, pixel, 0);
+    let average = accum.rgb / max(accum.a, 1e-5);
+    return vec4<f32>(average, 1.0 - revealage);
+}
+"#;
+
+/// The color targets of OIT pipelines: additive accumulation and multiplicative revealage.
+pub(crate) fn oit_targets() -> Vec<Option<ColorTargetState>> {
+    let additive = BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    };
+    let revealage = BlendComponent {
+        src_factor: BlendFactor::Zero,
+        dst_factor: BlendFactor::OneMinusSrc,
+        operation: BlendOperation::Add,
+    };
+    vec![
+        Some(ColorTargetState {
+            format: OIT_ACCUM_FORMAT,
+            blend: Some(BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+            write_mask: ColorWrites::ALL,
+        }),
+        Some(ColorTargetState {
+            format: OIT_REVEALAGE_FORMAT,
+            blend: Some(BlendState {
+                color: revealage,
+                alpha: revealage,
+            }),
+            write_mask: ColorWrites::ALL,
+        }),
+    ]
+}
+
+/// The OIT variant of `options`: OIT targets, no MSAA, depth test without writes.
+pub(crate) fn oit_variant(options: &PipelineOptions) -> PipelineOptions {
+    let mut variant = options.clone();
+    variant.targets = oit_targets();
+    variant.msaa_samples = 1;
+    if let Some(depth_stencil) = &mut variant.depth_stencil {
+        depth_stencil.depth_write_enabled = false;
+    }
+    variant
+}
+
+/// Accumulation and revealage targets plus the composite pass.
+pub struct OitTargets {
+    device: Device,
+    size: (u32, u32),
+    accum_view: TextureView,
+    revealage_view: TextureView,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    composite_pipeline: RenderPipeline,
+}
+
+impl OitTargets {
+    /// Create the targets and a composite pipeline writing `target_format`.
+    pub fn new(device: &Device, width: u32, height: u32, target_format: TextureFormat) -> Self {
+        let entries = [
+            gpu_util::texture_entry(0, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+            gpu_util::texture_entry(1, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+        ];
+        let layout = gpu_util::bind_group_layout(device, "oit composite layout", &entries);
+        let module = gpu_util::shader(device, "oit composite shader", COMPOSITE_SHADER);
+        let composite_pipeline = create_composite_pipeline(device, &module, &layout, target_format);
+
+        let size = (width.max(1), height.max(1));
+        let (accum_view, revealage_view, bind_group) = create_targets(device, &layout, size);
+        Self {
+            device: device.clone(),
+            size,
+            accum_view,
+            revealage_view,
+            layout,
+            bind_group,
+            composite_pipeline,
+        }
+    }
+
+    /// Recreate the targets for a new size. Does nothing if the size is unchanged.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = (width.max(1), height.max(1));
+        if size == self.size {
+            return;
+        }
+        let (accum_view, revealage_view, bind_group) = create_targets(&self.device, &self.layout, size);
+        self.size = size;
+        self.accum_view = accum_view;
+        self.revealage_view = revealage_view;
+        self.bind_group = bind_group;
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn accum_view(&self) -> &TextureView {
+        &self.accum_view
+    }
+
+    pub fn revealage_view(&self) -> &TextureView {
+        &self.revealage_view
+    }
+
+    /// Color attachments of the transparent pass: accumulation cleared to zero,
+    /// revealage cleared to one (fully revealed).
+    pub fn color_attachments(&self) -> [Option<RenderPassColorAttachment<'_>>; 2] {
+        let attachment = |view, clear| {
+            Some(RenderPassColorAttachment {
+                view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(clear),
+                    store: StoreOp::Store,
+                },
+            })
+        };
+        [
+            attachment(&self.accum_view, Color::TRANSPARENT),
+            attachment(&self.revealage_view, Color::WHITE),
+        ]
+    }
+
+    /// Blend the resolved transparent layer over the pass target with a fullscreen draw.
+    ///
+    /// The pass must write a single-sampled target of the format given to
+    /// [`new`](Self::new), without depth attachment.
+    pub fn composite(&self, pass: &mut RenderPass) {
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..4, 0..1);
+    }
+}
+
+fn create_targets(device: &Device, layout: &BindGroupLayout, (width, height): (u32, u32)) -> (TextureView, TextureView, BindGroup) {
+    let create = |label, format| {
+        device
+            .create_texture(&TextureDescriptor {
+                label: Some(&namespaced(label)),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default())
+    };
+    let accum_view = create("oit accum", OIT_ACCUM_FORMAT);
+    let revealage_view = create("oit revealage", OIT_REVEALAGE_FORMAT);
+    let bind_group = gpu_util::bind_group(device, "oit composite", layout, &[
+        BindingResource::TextureView(&accum_view),
+        BindingResource::TextureView(&revealage_view),
+    ]);
+    (accum_view, revealage_view, bind_group)
+}
+
+fn create_composite_pipeline(device: &Device, module: &ShaderModule, layout: &BindGroupLayout, format: TextureFormat) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("oit composite pipeline layout")),
+        bind_group_layouts: &[layout],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("oit composite pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
@@ -0,0 +1,438 @@
+//! Outlines around selected objects.
+//!
+//! [`SelectionOutline`] renders the selected meshes into a mask target, grows the
+//! mask with a jump flood (JFA) so every pixel knows its nearest selected pixel,
+//! and draws a colored outline of configurable width around the selection:
+//! ```ignore
+//! let options = outline.mask_options(PipelineOptions::default().with_vertex_layout(layout));
+//! {
+//!     let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!         color_attachments: &[outline.mask_attachment()],
+//!         ..Default::default()
+//!     });
+//!     render_manager.render_with_textures(&[], mask_shader, &options, &[&camera], &mut pass);
+//!     // draw the selected meshes
+//! }
+//! outline.update(&mut encoder);
+//! // Pass writing the final color target
+//! outline.composite(&mut pass);
+//! ```
+//!
+//! The mask pipelines go through the regular pipeline cache; the mask shader only
+//! needs to write a non-zero value to `@location(0)`. The mask pass has no depth
+//! attachment, so outlines stay visible through occluders. The flood runs
+//! `log2(width)` compute passes, so the cost grows with the outline width, not
+//! with the screen area covered by the selection.
+use wgpu::*;
+use wgpu::util::DeviceExt;
+use crate::gpu_util;
+use crate::pipelines::PipelineOptions;
+use crate::labels::namespaced;
+
+/// Format of the selection mask.
+pub const OUTLINE_MASK_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+/// Nearest seed pixel per pixel, `-1` where there is none yet.
+const SEED_FORMAT: TextureFormat = TextureFormat::Rg32Float;
+
+const SEED_SHADER: &str = r#"
+@group(0) @binding(0) var mask: texture_2d<f32>;
+@group(0) @binding(1) var seeds: texture_storage_2d<rg32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(mask);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    var seed = vec2<f32>(-1.0);
+    if textureLoad(mask, vec2<i32>(id.xy), 0).r > 0.0 {
+        seed = vec2<f32>(id.xy);
+    }
+    textureStore(seeds, vec2<i32>(id.xy), vec4<f32>(seed, 0.0, 0.0));
+}
+"#;
+
+const JUMP_SHADER: &str = r#"
+struct Jump {
+    step: i32,
+};
+
+@group(0) @binding(0) var<uniform> jump: Jump;
+@group(0) @binding(1) var source: texture_2d<f32>;
+@group(0) @binding(2) var destination: texture_storage_2d<rg32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(source));
+    let pixel = vec2<i32>(id.xy);
+    if pixel.x >= size.x || pixel.y >= size.y {
+        return;
+    }
+    var best = vec2<f32>(-1.0);
+    var best_distance = 1e30;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = pixel + vec2<i32>(x, y) * jump.step;
+            if any(neighbor < vec2<i32>(0)) || any(neighbor >= size) {
+                continue;
+            }
+            let seed = textureLoad(source, neighbor, 0).xy;
+            if seed.x < 0.0 {
+                continue;
+            }
+            let offset = seed - vec2<f32>(pixel);
+            let dist = dot(offset, offset);
+            if dist < best_distance {
+                best = seed;
+                best_distance = dist;
+            }
+        }
+    }
+    textureStore(destination, pixel, vec4<f32>(best, 0.0, 0.0));
+}
+"#;
+
+const COMPOSITE_SHADER: &str = r#"
+struct OutlineParams {
+    color: vec4<f32>,
+    width: f32,
+    _pad0: f32,
+    _pad1: f32,
+    _pad2: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: OutlineParams;
+@group(0) @binding(1) var mask: texture_2d<f32>;
+@group(0) @binding(2) var seeds: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4<f32> {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    return vec4<f32>(positions[idx], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let seed = textureLoad(seeds, pixel, 0).xy;
+    if seed.x < 0.0 || textureLoad(mask, pixel, 0).r > 0.0 {
+        discard;
+    }
+    let dist = length(seed - vec2<f32>(pixel));
+    // One pixel of antialiasing at the outer edge.
+    let coverage = clamp(params.width - dist + 0.5, 0.0, 1.0);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(params.color.rgb, params.color.a * coverage);
+}
+"#;
+
+/// Appearance of a [`SelectionOutline`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineSettings {
+    /// Straight alpha color of the outline.
+    pub color: [f32; 4],
+    /// Outline width in pixels.
+    pub width: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.6, 0.1, 1.0],
+            width: 3.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineParams {
+    color: [f32; 4],
+    width: f32,
+    _pad: [f32; 3],
+}
+
+/// Size-dependent targets and bind groups.
+struct OutlineTargets {
+    size: (u32, u32),
+    mask_view: TextureView,
+    seed_bind_group: BindGroup,
+    /// One bind group per jump, alternating between the two seed targets.
+    jump_bind_groups: Vec<BindGroup>,
+    composite_bind_group: BindGroup,
+}
+
+/// Selection mask, jump flood and outline composite.
+pub struct SelectionOutline {
+    device: Device,
+    queue: Queue,
+    settings: OutlineSettings,
+    params: Buffer,
+    seed_layout: BindGroupLayout,
+    seed_pipeline: ComputePipeline,
+    jump_layout: BindGroupLayout,
+    jump_pipeline: ComputePipeline,
+    composite_layout: BindGroupLayout,
+    composite_pipeline: RenderPipeline,
+    targets: OutlineTargets,
+}
+
+impl SelectionOutline {
+    /// Create the outline targets and a composite pipeline writing `target_format`.
+    pub fn new(device: &Device, queue: &Queue, width: u32, height: u32, target_format: TextureFormat, settings: OutlineSettings) -> Self {
+        let seed_module = gpu_util::shader(device, "outline seed shader", SEED_SHADER);
+        let seed_layout = gpu_util::bind_group_layout(device, "outline seed layout", &[
+            gpu_util::texture_entry(0, ShaderStages::COMPUTE, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+            gpu_util::storage_texture_entry(1, ShaderStages::COMPUTE, SEED_FORMAT, StorageTextureAccess::WriteOnly, TextureViewDimension::D2),
+        ]);
+        let seed_pipeline = gpu_util::compute_pipeline(device, "outline seed", &seed_module, "main", &[&seed_layout]);
+
+        let jump_module = gpu_util::shader(device, "outline jump flood shader", JUMP_SHADER);
+        let jump_layout = gpu_util::bind_group_layout(device, "outline jump flood layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::texture_entry(1, ShaderStages::COMPUTE, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+            gpu_util::storage_texture_entry(2, ShaderStages::COMPUTE, SEED_FORMAT, StorageTextureAccess::WriteOnly, TextureViewDimension::D2),
+        ]);
+        let jump_pipeline = gpu_util::compute_pipeline(device, "outline jump flood", &jump_module, "main", &[&jump_layout]);
+
+        let composite_module = gpu_util::shader(device, "outline composite shader", COMPOSITE_SHADER);
+        let composite_layout = gpu_util::bind_group_layout(device, "outline composite layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::FRAGMENT),
+            gpu_util::texture_entry(1, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+            gpu_util::texture_entry(2, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: false }, TextureViewDimension::D2),
+        ]);
+        let composite_pipeline = create_composite_pipeline(device, &composite_module, &composite_layout, target_format);
+
+        let params = gpu_util::buffer(device, "outline params", size_of::<OutlineParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let mut outline = Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            settings,
+            targets: create_targets(device, &params, &seed_layout, &jump_layout, &composite_layout, (width.max(1), height.max(1)), settings.width),
+            params,
+            seed_layout,
+            seed_pipeline,
+            jump_layout,
+            jump_pipeline,
+            composite_layout,
+            composite_pipeline,
+        };
+        outline.set_settings(settings);
+        outline
+    }
+
+    /// Recreate the targets for a new size. Does nothing if the size is unchanged.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = (width.max(1), height.max(1));
+        if size != self.targets.size {
+            self.rebuild(size);
+        }
+    }
+
+    pub fn settings(&self) -> &OutlineSettings {
+        &self.settings
+    }
+
+    /// Change color and width. A width needing a different number of jumps
+    /// rebuilds the jump bind groups.
+    pub fn set_settings(&mut self, settings: OutlineSettings) {
+        let rebuild = jump_steps(settings.width) != jump_steps(self.settings.width);
+        self.settings = settings;
+        if rebuild {
+            self.rebuild(self.targets.size);
+        }
+        let params = OutlineParams {
+            color: settings.color,
+            width: settings.width.max(0.0),
+            _pad: [0.0; 3],
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+    }
+
+    /// The selection mask, [`OUTLINE_MASK_FORMAT`].
+    pub fn mask_view(&self) -> &TextureView {
+        &self.targets.mask_view
+    }
+
+    /// Color attachment of the mask pass, cleared to zero.
+    pub fn mask_attachment(&self) -> Option<RenderPassColorAttachment<'_>> {
+        Some(RenderPassColorAttachment {
+            view: &self.targets.mask_view,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::TRANSPARENT),
+                store: StoreOp::Store,
+            },
+        })
+    }
+
+    /// Mask pass variant of `base`: the mask target without blending, no depth, no MSAA.
+    ///
+    /// Vertex layouts, topology and culling of `base` are kept.
+    pub fn mask_options(&self, base: PipelineOptions) -> PipelineOptions {
+        PipelineOptions {
+            msaa_samples: 1,
+            depth_stencil: None,
+            targets: vec![Some(ColorTargetState {
+                format: OUTLINE_MASK_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+            vertex_only: false,
+            oit: false,
+            ..base
+        }
+    }
+
+    /// Record the jump flood over the mask rendered this frame.
+    pub fn update(&self, encoder: &mut CommandEncoder) {
+        let _span = trace_span!("selection_outline");
+        let (width, height) = self.targets.size;
+        let workgroups = [width.div_ceil(8), height.div_ceil(8), 1];
+        gpu_util::dispatch(encoder, "outline seed", &self.seed_pipeline, &[&self.targets.seed_bind_group], workgroups);
+        for bind_group in &self.targets.jump_bind_groups {
+            gpu_util::dispatch(encoder, "outline jump flood", &self.jump_pipeline, &[bind_group], workgroups);
+        }
+    }
+
+    /// Blend the outline over the pass target with a fullscreen draw.
+    ///
+    /// The pass must write a single-sampled target of the format given to
+    /// [`new`](Self::new), with the size of the outline targets.
+    pub fn composite(&self, pass: &mut RenderPass) {
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &self.targets.composite_bind_group, &[]);
+        pass.draw(0..4, 0..1);
+    }
+
+    fn rebuild(&mut self, size: (u32, u32)) {
+        self.targets = create_targets(
+            &self.device,
+            &self.params,
+            &self.seed_layout,
+            &self.jump_layout,
+            &self.composite_layout,
+            size,
+            self.settings.width,
+        );
+    }
+}
+
+/// Jump distances of the flood: powers of two from the outline width down to 1.
+fn jump_steps(width: f32) -> Vec<u32> {
+    let start = (width.max(1.0).ceil() as u32).next_power_of_two();
+    std::iter::successors(Some(start), |step| (*step > 1).then_some(step / 2)).collect()
+}
+
+fn create_targets(
+    device: &Device,
+    params: &Buffer,
+    seed_layout: &BindGroupLayout,
+    jump_layout: &BindGroupLayout,
+    composite_layout: &BindGroupLayout,
+    (width, height): (u32, u32),
+    outline_width: f32,
+) -> OutlineTargets {
+    let create = |label, format, usage| {
+        device
+            .create_texture(&TextureDescriptor {
+                label: Some(&namespaced(label)),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: usage | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default())
+    };
+    let mask_view = create("outline mask", OUTLINE_MASK_FORMAT, TextureUsages::RENDER_ATTACHMENT);
+    let seeds = [
+        create("outline seeds a", SEED_FORMAT, TextureUsages::STORAGE_BINDING),
+        create("outline seeds b", SEED_FORMAT, TextureUsages::STORAGE_BINDING),
+    ];
+
+    let seed_bind_group = gpu_util::bind_group(device, "outline seed", seed_layout, &[
+        BindingResource::TextureView(&mask_view),
+        BindingResource::TextureView(&seeds[0]),
+    ]);
+    let steps = jump_steps(outline_width);
+    let jump_bind_groups = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let jump = device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some(&namespaced("outline jump")),
+                contents: bytemuck::cast_slice(&[*step, 0, 0, 0]),
+                usage: BufferUsages::UNIFORM,
+            });
+            gpu_util::bind_group(device, "outline jump flood", jump_layout, &[
+                jump.as_entire_binding(),
+                BindingResource::TextureView(&seeds[i % 2]),
+                BindingResource::TextureView(&seeds[(i + 1) % 2]),
+            ])
+        })
+        .collect();
+    let composite_bind_group = gpu_util::bind_group(device, "outline composite", composite_layout, &[
+        params.as_entire_binding(),
+        BindingResource::TextureView(&mask_view),
+        BindingResource::TextureView(&seeds[steps.len() % 2]),
+    ]);
+    OutlineTargets {
+        size: (width, height),
+        mask_view,
+        seed_bind_group,
+        jump_bind_groups,
+        composite_bind_group,
+    }
+}
+
+fn create_composite_pipeline(device: &Device, module: &ShaderModule, layout: &BindGroupLayout, format: TextureFormat) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("outline composite pipeline layout")),
+        bind_group_layouts: &[layout],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("outline composite pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
@@ -0,0 +1,117 @@
+//! Helpers for recording draw lists on several threads.
+//!
+//! The draw list is split into contiguous chunks, one per thread. Each thread
+//! records its chunk into its own `CommandEncoder` or `RenderBundle`, and the
+//! results are returned in draw list order, so submitting or executing them
+//! reproduces the order of a single-threaded recording.
+//!
+//! Bind groups are read from a [`SharedMaterialBindGroups`](crate::concurrent::SharedMaterialBindGroups)
+//! captured by the recording closure:
+//!
+//! ```ignore
+//! let materials = render_manager.shared_materials();
+//! let bundles = encode_bundles_parallel(device, &bundle_desc, &draws, 4, |bundle, chunk| {
+//!     bundle.set_pipeline(&pipeline);
+//!     for draw in chunk {
+//!         bundle.set_bind_group(0, &materials.get_or_create(&draw.views, None), &[]);
+//!         bundle.draw(0..draw.vertex_count, 0..1);
+//!     }
+//! });
+//! pass.execute_bundles(bundles.iter());
+//! ```
+use wgpu::*;
+use crate::labels::namespaced;
+
+/// Size of the chunks `items` is split into so that at most `threads` chunks exist.
+fn chunk_size(len: usize, threads: usize) -> usize {
+    len.div_ceil(threads.max(1)).max(1)
+}
+
+/// Record `items` into one command buffer per thread.
+///
+/// `record` is called once per chunk with a fresh encoder labelled `"{label} {chunk}"`.
+/// The returned command buffers are in chunk order; submit them in a single
+/// `Queue::submit` call (or use [`encode_and_submit_parallel`]) to keep draw order.
+pub fn encode_parallel<T, F>(device: &Device, label: &str, items: &[T], threads: usize, record: F) -> Vec<CommandBuffer>
+where
+    T: Sync,
+    F: Fn(&mut CommandEncoder, &[T]) + Sync,
+{
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let _span = trace_span!("encode_parallel", label, items = items.len(), threads);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size(items.len(), threads))
+            .enumerate()
+            .map(|(i, chunk)| {
+                let record = &record;
+                // named here, the namespace belongs to the calling thread
+                let label = namespaced(&format!("{} {}", label, i)).into_owned();
+                scope.spawn(move || {
+                    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some(&label) });
+                    record(&mut encoder, chunk);
+                    encoder.finish()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Parallel encoding thread panicked"))
+            .collect()
+    })
+}
+
+/// [`encode_parallel`] followed by a single in-order submission to `queue`.
+pub fn encode_and_submit_parallel<T, F>(device: &Device, queue: &Queue, label: &str, items: &[T], threads: usize, record: F)
+where
+    T: Sync,
+    F: Fn(&mut CommandEncoder, &[T]) + Sync,
+{
+    let buffers = encode_parallel(device, label, items, threads, record);
+    if !buffers.is_empty() {
+        queue.submit(buffers);
+    }
+}
+
+/// Record `items` into one render bundle per thread.
+///
+/// Every bundle is created from `descriptor`, which must match the render pass
+/// the bundles are executed in. Returns bundles in chunk order, ready for
+/// `RenderPass::execute_bundles`.
+pub fn encode_bundles_parallel<T, F>(
+    device: &Device,
+    descriptor: &RenderBundleEncoderDescriptor,
+    items: &[T],
+    threads: usize,
+    record: F,
+) -> Vec<RenderBundle>
+where
+    T: Sync,
+    F: Fn(&mut RenderBundleEncoder, &[T]) + Sync,
+{
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let _span = trace_span!("encode_bundles_parallel", items = items.len(), threads);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size(items.len(), threads))
+            .map(|chunk| {
+                let record = &record;
+                scope.spawn(move || {
+                    let mut bundle = device.create_render_bundle_encoder(descriptor);
+                    record(&mut bundle, chunk);
+                    bundle.finish(&RenderBundleDescriptor { label: descriptor.label })
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Parallel bundle encoding thread panicked"))
+            .collect()
+    })
+}
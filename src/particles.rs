@@ -0,0 +1,446 @@
+//! GPU particle pools simulated and drawn entirely on the GPU.
+//!
+//! A [`ParticlePool`] is a fixed-size storage buffer of particles. New particles are
+//! written into it by the emit pass in ring-buffer order, overwriting the oldest
+//! slots once the pool is full. The update pass integrates velocity and gravity and
+//! ages every particle; dead particles are skipped by the render pass.
+//!
+//! Particles are drawn as camera-facing billboards, textured through the same
+//! material bind groups as [`RenderManager::render_with_textures`].
+//!
+//! ## Frame flow
+//! ```ignore
+//! particles.emit(&mut encoder, &mut pool, 32, &emitter);
+//! particles.update(&mut encoder, &pool, dt, [0.0, -9.81, 0.0]);
+//! // Inside a render pass
+//! particles.render(&mut render_manager, &mut pass, &pool, &spark_view, &camera_buffer, &options);
+//! ```
+//!
+//! ## Camera uniform
+//! ```wgsl
+//! struct ParticleCamera {
+//!     view_proj: mat4x4<f32>,
+//!     right: vec4<f32>, // camera right vector in world space, w unused
+//!     up: vec4<f32>,    // camera up vector in world space, w unused
+//! };
+//! ```
+use std::collections::HashMap;
+use std::path::Path;
+use wgpu::*;
+use crate::gpu_util;
+use crate::pipelines::{build_render_pipeline, PipelineOptions};
+use crate::renderer::RenderManager;
+use crate::labels::namespaced;
+
+const PARTICLE_SIM_SHADER: &str = r#"
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+    color: vec4<f32>,
+    size: f32,
+    _pad0: f32,
+    _pad1: f32,
+    _pad2: f32,
+};
+
+struct SimParams {
+    origin: vec3<f32>,
+    count: u32,
+    velocity: vec3<f32>,
+    spread: f32,
+    gravity: vec3<f32>,
+    dt: f32,
+    color: vec4<f32>,
+    lifetime: f32,
+    size: f32,
+    first: u32,
+    seed: u32,
+    capacity: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: SimParams;
+@group(0) @binding(1) var<storage, read_write> particles: array<Particle>;
+
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn rand(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed) / 4294967295.0;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn emit(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.count {
+        return;
+    }
+    var seed = hash(params.seed ^ (id.x * 1664525u));
+    let dir = normalize(vec3<f32>(rand(&seed), rand(&seed), rand(&seed)) * 2.0 - 1.0 + vec3<f32>(1e-5));
+
+    var p: Particle;
+    p.position = params.origin;
+    p.age = 0.0;
+    p.velocity = params.velocity + dir * params.spread * rand(&seed);
+    p.lifetime = params.lifetime * (0.75 + 0.5 * rand(&seed));
+    p.color = params.color;
+    p.size = params.size;
+    particles[(params.first + id.x) % params.capacity] = p;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn update(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.capacity {
+        return;
+    }
+    var p = particles[id.x];
+    if p.age >= p.lifetime {
+        return;
+    }
+    p.velocity += params.gravity * params.dt;
+    p.position += p.velocity * params.dt;
+    p.age += params.dt;
+    particles[id.x] = p;
+}
+"#;
+
+const PARTICLE_RENDER_SHADER: &str = r#"
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+    color: vec4<f32>,
+    size: f32,
+    _pad0: f32,
+    _pad1: f32,
+    _pad2: f32,
+};
+
+struct ParticleCamera {
+    view_proj: mat4x4<f32>,
+    right: vec4<f32>,
+    up: vec4<f32>,
+};
+
+struct VsOut {
+    @builtin(position) clip: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var material_sampler: sampler;
+@group(0) @binding(1) var particle_texture: texture_2d<f32>;
+@group(1) @binding(0) var<storage, read> particles: array<Particle>;
+@group(1) @binding(1) var<uniform> camera: ParticleCamera;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, @builtin(instance_index) instance: u32) -> VsOut {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let p = particles[instance];
+    var out: VsOut;
+    if p.age >= p.lifetime {
+        // Degenerate triangle, nothing is rasterized.
+        out.clip = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        out.uv = vec2<f32>(0.0);
+        out.color = vec4<f32>(0.0);
+        return out;
+    }
+    let corner = corners[vertex];
+    let world = p.position + (camera.right.xyz * corner.x + camera.up.xyz * corner.y) * p.size;
+    out.clip = camera.view_proj * vec4<f32>(world, 1.0);
+    out.uv = corner * vec2<f32>(0.5, -0.5) + 0.5;
+    out.color = vec4<f32>(p.color.rgb, p.color.a * (1.0 - p.age / p.lifetime));
+    return out;
+}
+
+@fragment
+fn fs_main(in: VsOut) -> @location(0) vec4<f32> {
+    return textureSample(particle_texture, material_sampler, in.uv) * in.color;
+}
+"#;
+
+/// Size of one particle in the pool buffer.
+pub const PARTICLE_SIZE: u64 = 64;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimParams {
+    origin: [f32; 3],
+    count: u32,
+    velocity: [f32; 3],
+    spread: f32,
+    gravity: [f32; 3],
+    dt: f32,
+    color: [f32; 4],
+    lifetime: f32,
+    size: f32,
+    first: u32,
+    seed: u32,
+    capacity: u32,
+    _pad: [u32; 3],
+}
+
+/// Describes the particles spawned by one [`ParticleSystem::emit`] call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmitterSettings {
+    /// Spawn position in world space.
+    pub origin: [f32; 3],
+    /// Base velocity of every particle.
+    pub velocity: [f32; 3],
+    /// Maximum random speed added in a random direction.
+    pub spread: f32,
+    /// Lifetime in seconds, randomized by ±25%.
+    pub lifetime: f32,
+    /// Billboard half-size in world units.
+    pub size: f32,
+    /// Color multiplied with the particle texture. Alpha fades out over the lifetime.
+    pub color: [f32; 4],
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            origin: [0.0; 3],
+            velocity: [0.0, 1.0, 0.0],
+            spread: 0.5,
+            lifetime: 2.0,
+            size: 0.1,
+            color: [1.0; 4],
+        }
+    }
+}
+
+/// A fixed-size pool of GPU particles.
+///
+/// Created by [`ParticleSystem::create_pool`]. Bind groups of the pool are created
+/// once and reused by every emit, update and render call.
+pub struct ParticlePool {
+    particles: Buffer,
+    capacity: u32,
+    /// Next slot written by the emit pass.
+    cursor: u32,
+    emitted: u64,
+    emit_params: Buffer,
+    update_params: Buffer,
+    emit_bind_group: BindGroup,
+    update_bind_group: BindGroup,
+    /// Render bind group and the camera buffer it was created for.
+    render_bind_group: Option<(Buffer, BindGroup)>,
+}
+
+impl ParticlePool {
+    /// Maximum number of live particles.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Total number of particles emitted into this pool.
+    pub fn emitted(&self) -> u64 {
+        self.emitted
+    }
+
+    /// The particle storage buffer, e.g. for custom render or collision passes.
+    ///
+    /// Each particle is [`PARTICLE_SIZE`] bytes, laid out as in the `Particle` struct
+    /// described in the module docs.
+    pub fn buffer(&self) -> &Buffer {
+        &self.particles
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct ParticlePipelineKey {
+    msaa_samples: u32,
+    depth: Option<(TextureFormat, bool, CompareFunction)>,
+    targets: Vec<Option<ColorTargetState>>,
+}
+
+/// Emit, update and billboard passes for [`ParticlePool`]s.
+pub struct ParticleSystem {
+    device: Device,
+    queue: Queue,
+    sim_layout: BindGroupLayout,
+    emit_pipeline: ComputePipeline,
+    update_pipeline: ComputePipeline,
+    render_module: ShaderModule,
+    render_layout: BindGroupLayout,
+    render_pipelines: HashMap<ParticlePipelineKey, RenderPipeline>,
+}
+
+impl ParticleSystem {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let sim_module = gpu_util::shader(device, "particle sim shader", PARTICLE_SIM_SHADER);
+        let sim_layout = gpu_util::bind_group_layout(device, "particle sim layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, false),
+        ]);
+        let emit_pipeline = gpu_util::compute_pipeline(device, "particle emit", &sim_module, "emit", &[&sim_layout]);
+        let update_pipeline = gpu_util::compute_pipeline(device, "particle update", &sim_module, "update", &[&sim_layout]);
+
+        let render_module = gpu_util::shader(device, "particle render shader", PARTICLE_RENDER_SHADER);
+        let render_layout = gpu_util::bind_group_layout(device, "particle render layout", &[
+            gpu_util::storage_entry(0, ShaderStages::VERTEX, true),
+            gpu_util::uniform_entry(1, ShaderStages::VERTEX),
+        ]);
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            sim_layout,
+            emit_pipeline,
+            update_pipeline,
+            render_module,
+            render_layout,
+            render_pipelines: HashMap::new(),
+        }
+    }
+
+    /// Create a pool for up to `capacity` live particles. All particles start dead.
+    pub fn create_pool(&self, capacity: u32) -> ParticlePool {
+        let capacity = capacity.max(1);
+        let particles = self.device.create_buffer(&BufferDescriptor {
+            label: Some(&namespaced("particle pool")),
+            size: capacity as u64 * PARTICLE_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_usage = BufferUsages::UNIFORM | BufferUsages::COPY_DST;
+        let emit_params = gpu_util::buffer(&self.device, "particle emit params", size_of::<SimParams>() as u64, params_usage);
+        let update_params = gpu_util::buffer(&self.device, "particle update params", size_of::<SimParams>() as u64, params_usage);
+        let emit_bind_group = gpu_util::bind_group(&self.device, "particle emit", &self.sim_layout, &[
+            emit_params.as_entire_binding(),
+            particles.as_entire_binding(),
+        ]);
+        let update_bind_group = gpu_util::bind_group(&self.device, "particle update", &self.sim_layout, &[
+            update_params.as_entire_binding(),
+            particles.as_entire_binding(),
+        ]);
+        trace_event!(capacity, "created particle pool");
+
+        ParticlePool {
+            particles,
+            capacity,
+            cursor: 0,
+            emitted: 0,
+            emit_params,
+            update_params,
+            emit_bind_group,
+            update_bind_group,
+            render_bind_group: None,
+        }
+    }
+
+    /// Record spawning `count` particles, overwriting the oldest ones when the pool is full.
+    ///
+    /// Parameters are uploaded with `Queue::write_buffer`, so only the last `emit`
+    /// per pool before a submission takes effect.
+    pub fn emit(&self, encoder: &mut CommandEncoder, pool: &mut ParticlePool, count: u32, settings: &EmitterSettings) {
+        let count = count.min(pool.capacity);
+        if count == 0 {
+            return;
+        }
+        let params = SimParams {
+            origin: settings.origin,
+            count,
+            velocity: settings.velocity,
+            spread: settings.spread,
+            color: settings.color,
+            lifetime: settings.lifetime,
+            size: settings.size,
+            first: pool.cursor,
+            seed: pool.emitted as u32 ^ 0x9e37_79b9,
+            capacity: pool.capacity,
+            ..Default::default()
+        };
+        self.queue.write_buffer(&pool.emit_params, 0, bytemuck::bytes_of(&params));
+        gpu_util::dispatch(encoder, "particle emit", &self.emit_pipeline, &[&pool.emit_bind_group], [count.div_ceil(64), 1, 1]);
+
+        pool.cursor = (pool.cursor + count) % pool.capacity;
+        pool.emitted += count as u64;
+    }
+
+    /// Record advancing every live particle of `pool` by `dt` seconds under `gravity`.
+    pub fn update(&self, encoder: &mut CommandEncoder, pool: &ParticlePool, dt: f32, gravity: [f32; 3]) {
+        let params = SimParams {
+            gravity,
+            dt,
+            capacity: pool.capacity,
+            ..Default::default()
+        };
+        self.queue.write_buffer(&pool.update_params, 0, bytemuck::bytes_of(&params));
+        gpu_util::dispatch(encoder, "particle update", &self.update_pipeline, &[&pool.update_bind_group], [
+            pool.capacity.div_ceil(64),
+            1,
+            1,
+        ]);
+    }
+
+    /// Draw the live particles of `pool` as textured billboards.
+    ///
+    /// `texture` is bound through the material bind group cache of `manager`.
+    /// `options` supplies targets, blending, depth and MSAA; topology, vertex layouts
+    /// and shadows are ignored. Particles are usually drawn with alpha blending
+    /// and depth writes disabled.
+    pub fn render(
+        &mut self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        pool: &mut ParticlePool,
+        texture: &TextureView,
+        camera: &Buffer,
+        options: &PipelineOptions,
+    ) {
+        let material_layout = manager.material_layout(&[texture], &[]);
+
+        let key = ParticlePipelineKey {
+            msaa_samples: options.msaa_samples,
+            depth: options
+                .depth_stencil
+                .as_ref()
+                .map(|d| (d.format, d.depth_write_enabled, d.depth_compare)),
+            targets: options.targets.clone(),
+        };
+        let pipeline = self.render_pipelines.entry(key).or_insert_with(|| {
+            let _span = trace_span!("particle_pipeline_miss");
+            let options = PipelineOptions {
+                topology: PrimitiveTopology::TriangleList,
+                vertex_layouts: Vec::new(),
+                cull_mode: None,
+                vertex_only: false,
+                shadow: None,
+                ..options.clone()
+            };
+            build_render_pipeline(
+                &self.device,
+                &self.render_module,
+                Path::new("particles"),
+                &[&material_layout, &self.render_layout],
+                &options,
+            )
+        });
+        pass.set_pipeline(pipeline);
+
+        if pool.render_bind_group.as_ref().is_none_or(|(buffer, _)| buffer != camera) {
+            let bind_group = gpu_util::bind_group(&self.device, "particle render", &self.render_layout, &[
+                pool.particles.as_entire_binding(),
+                camera.as_entire_binding(),
+            ]);
+            pool.render_bind_group = Some((camera.clone(), bind_group));
+        }
+
+        pass.set_bind_group(0, &manager.material_bind_group(&[texture], &[]), &[]);
+        pass.set_bind_group(1, &pool.render_bind_group.as_ref().unwrap().1, &[]);
+        pass.draw(0..6, 0..pool.capacity);
+    }
+}
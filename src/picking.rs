@@ -0,0 +1,309 @@
+//! GPU picking through an object ID target.
+//!
+//! A [`Picker`] owns an `R32Uint` ID target with its own depth buffer. Every frame,
+//! objects that can be picked are [`register`](Picker::register)ed, which hands out a
+//! nonzero ID, and drawn into the target by a shader writing that ID. A
+//! [`request`](Picker::request) for a pixel is copied out after the ID pass and read
+//! back asynchronously; a later [`poll`](Picker::poll) (also done by
+//! [`begin_frame`](Picker::begin_frame)) calls the callback with the handle that was
+//! registered under the ID, or `None` for the background.
+//!
+//! ## Frame flow
+//! ```ignore
+//! picker.begin_frame();
+//! if clicked {
+//!     picker.request(cursor_x, cursor_y, |picked: Option<MeshHandle>| println!("picked {picked:?}"));
+//! }
+//! let options = picker.pipeline_options(PipelineOptions::default().with_vertex_layout(layout));
+//! {
+//!     let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!         color_attachments: &[Some(picker.color_attachment())],
+//!         depth_stencil_attachment: Some(picker.depth_attachment()),
+//!         ..Default::default()
+//!     });
+//!     for object in &objects {
+//!         let id = picker.register(object.mesh);
+//!         // Pass `id` to the shader, e.g. through a uniform or instance data
+//!     }
+//! }
+//! picker.resolve(&mut encoder);
+//! queue.submit([encoder.finish()]);
+//! ```
+//!
+//! ## ID pass output
+//! - `@location(0)`: `u32` ID from [`register`](Picker::register); 0 ([`PICK_NONE`]) is the background
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use wgpu::*;
+use crate::pipelines::PipelineOptions;
+use crate::labels::namespaced;
+
+/// Format of the ID target.
+pub const PICK_ID_FORMAT: TextureFormat = TextureFormat::R32Uint;
+
+/// Format of the depth buffer of the ID pass.
+pub const PICK_DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// ID of pixels without an object.
+pub const PICK_NONE: u32 = 0;
+
+const READBACK_PENDING: u8 = 0;
+const READBACK_MAPPED: u8 = 1;
+const READBACK_FAILED: u8 = 2;
+
+/// Called with the picked handle, `None` for the background or a failed readback.
+pub type PickCallback<T> = Box<dyn FnOnce(Option<T>)>;
+
+struct PickTarget {
+    texture: Texture,
+    view: TextureView,
+}
+
+/// Readback of the requests resolved in one frame.
+struct PickReadback<T> {
+    buffer: Buffer,
+    /// Handles registered in that frame, ID `i + 1` at index `i`.
+    handles: Vec<T>,
+    callbacks: Vec<PickCallback<T>>,
+    state: Arc<AtomicU8>,
+}
+
+/// ID target, per-frame handle table and asynchronous pixel readback.
+pub struct Picker<T> {
+    device: Device,
+    size: (u32, u32),
+    reversed_z: bool,
+    id: PickTarget,
+    depth: PickTarget,
+    handles: Vec<T>,
+    requests: Vec<([u32; 2], PickCallback<T>)>,
+    in_flight: Vec<PickReadback<T>>,
+}
+
+impl<T: Clone + 'static> Picker<T> {
+    /// Create the targets; `reversed_z` clears depth to 0 and tests with `Greater`.
+    pub fn new(device: &Device, width: u32, height: u32, reversed_z: bool) -> Self {
+        let size = (width.max(1), height.max(1));
+        Self {
+            device: device.clone(),
+            size,
+            reversed_z,
+            id: create_target(device, "pick ids", PICK_ID_FORMAT, size),
+            depth: create_target(device, "pick depth", PICK_DEPTH_FORMAT, size),
+            handles: Vec::new(),
+            requests: Vec::new(),
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Recreate the targets for a new size. Does nothing if the size is unchanged.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = (width.max(1), height.max(1));
+        if size == self.size {
+            return;
+        }
+        self.size = size;
+        self.id = create_target(&self.device, "pick ids", PICK_ID_FORMAT, size);
+        self.depth = create_target(&self.device, "pick depth", PICK_DEPTH_FORMAT, size);
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn id_view(&self) -> &TextureView {
+        &self.id.view
+    }
+
+    pub fn depth_view(&self) -> &TextureView {
+        &self.depth.view
+    }
+
+    /// Deliver finished picks and start a new handle table.
+    ///
+    /// IDs of the previous frame are no longer valid after this.
+    pub fn begin_frame(&mut self) {
+        self.poll();
+        self.handles.clear();
+    }
+
+    /// Hand out the ID under which `handle` is drawn this frame.
+    pub fn register(&mut self, handle: T) -> u32 {
+        self.handles.push(handle);
+        self.handles.len() as u32
+    }
+
+    /// Pick the pixel at `x, y` of this frame's ID pass. `callback` runs from a later
+    /// [`poll`](Self::poll) once the pixel has been read back; pixels outside the
+    /// target pick `None`.
+    pub fn request(&mut self, x: u32, y: u32, callback: impl FnOnce(Option<T>) + 'static) {
+        self.requests.push(([x, y], Box::new(callback)));
+    }
+
+    /// Copy the requested pixels out of the ID target and schedule their readback for
+    /// when `encoder` is submitted. Record after the ID pass.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let (width, height) = self.size;
+        let mut callbacks = Vec::with_capacity(self.requests.len());
+        let mut pixels = Vec::with_capacity(self.requests.len());
+        for ([x, y], callback) in self.requests.drain(..) {
+            if x < width && y < height {
+                pixels.push([x, y]);
+                callbacks.push(callback);
+            } else {
+                callback(None);
+            }
+        }
+        if pixels.is_empty() {
+            return;
+        }
+        let _span = trace_span!("pick_resolve", requests = pixels.len());
+
+        // Every pixel gets its own row-aligned slot of the readback buffer.
+        let slot = COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some(&namespaced("pick readback")),
+            size: slot * pixels.len() as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        for (i, [x, y]) in pixels.into_iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                TexelCopyTextureInfo {
+                    texture: &self.id.texture,
+                    mip_level: 0,
+                    origin: Origin3d { x, y, z: 0 },
+                    aspect: TextureAspect::All,
+                },
+                TexelCopyBufferInfo {
+                    buffer: &buffer,
+                    layout: TexelCopyBufferLayout {
+                        offset: i as u64 * slot,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+            );
+        }
+
+        let state = Arc::new(AtomicU8::new(READBACK_PENDING));
+        let mapped = state.clone();
+        encoder.map_buffer_on_submit(&buffer, MapMode::Read, 0..buffer.size(), move |result| {
+            let value = if result.is_ok() { READBACK_MAPPED } else { READBACK_FAILED };
+            mapped.store(value, Ordering::Release);
+        });
+        self.in_flight.push(PickReadback {
+            buffer,
+            handles: std::mem::take(&mut self.handles),
+            callbacks,
+            state,
+        });
+    }
+
+    /// Call the callbacks of every pick whose readback finished.
+    pub fn poll(&mut self) {
+        if self.in_flight.is_empty() {
+            return;
+        }
+        let _ = self.device.poll(PollType::Poll);
+
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            let state = self.in_flight[i].state.load(Ordering::Acquire);
+            if state == READBACK_PENDING {
+                i += 1;
+                continue;
+            }
+            let readback = self.in_flight.remove(i);
+            let ids: Vec<u32> = if state == READBACK_MAPPED {
+                let data = readback.buffer.get_mapped_range(0..readback.buffer.size());
+                data.chunks(COPY_BYTES_PER_ROW_ALIGNMENT as usize)
+                    .map(|slot| u32::from_le_bytes([slot[0], slot[1], slot[2], slot[3]]))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            for (j, callback) in readback.callbacks.into_iter().enumerate() {
+                let picked = ids
+                    .get(j)
+                    .filter(|&&id| id != PICK_NONE)
+                    .and_then(|&id| readback.handles.get(id as usize - 1).cloned());
+                callback(picked);
+            }
+        }
+    }
+
+    /// Number of picks waiting for their readback.
+    pub fn pending(&self) -> usize {
+        self.requests.len() + self.in_flight.iter().map(|readback| readback.callbacks.len()).sum::<usize>()
+    }
+
+    /// Color attachment of the ID pass, cleared to [`PICK_NONE`].
+    pub fn color_attachment(&self) -> RenderPassColorAttachment<'_> {
+        RenderPassColorAttachment {
+            view: &self.id.view,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::TRANSPARENT),
+                store: StoreOp::Store,
+            },
+        }
+    }
+
+    /// Depth attachment of the ID pass, cleared to the far plane.
+    pub fn depth_attachment(&self) -> RenderPassDepthStencilAttachment<'_> {
+        RenderPassDepthStencilAttachment {
+            view: &self.depth.view,
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(if self.reversed_z { 0.0 } else { 1.0 }),
+                store: StoreOp::Discard,
+            }),
+            stencil_ops: None,
+        }
+    }
+
+    /// ID pass variant of `base`: the `R32Uint` target without blending, depth testing
+    /// with writes, no MSAA.
+    ///
+    /// Vertex layouts, topology and culling of `base` are kept.
+    pub fn pipeline_options(&self, base: PipelineOptions) -> PipelineOptions {
+        PipelineOptions {
+            msaa_samples: 1,
+            depth_stencil: Some(DepthStencilState {
+                format: PICK_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: if self.reversed_z { CompareFunction::Greater } else { CompareFunction::Less },
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            targets: vec![Some(ColorTargetState {
+                format: PICK_ID_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+            vertex_only: false,
+            ..base
+        }
+    }
+}
+
+fn create_target(device: &Device, label: &str, format: TextureFormat, (width, height): (u32, u32)) -> PickTarget {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced(label)),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    PickTarget { texture, view }
+}
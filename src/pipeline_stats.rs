@@ -0,0 +1,253 @@
+// pipeline_stats.rs
+//! Pipeline statistics queries for passes recorded by the crate.
+//!
+//! Counts vertex, primitive, fragment and compute invocations per scope and
+//! aggregates them per label (shader path, material or pipeline name), which
+//! makes overdraw hotspots easy to spot. Like the [`GpuProfiler`](crate::profiler::GpuProfiler),
+//! results are read back asynchronously a few frames later.
+use std::collections::HashMap;
+use wgpu::*;
+use crate::queries::QueryRing;
+
+/// Statistics recorded per query, in the order `wgpu` writes them.
+const STATISTICS_TYPES: PipelineStatisticsTypes = PipelineStatisticsTypes::VERTEX_SHADER_INVOCATIONS
+    .union(PipelineStatisticsTypes::CLIPPER_INVOCATIONS)
+    .union(PipelineStatisticsTypes::CLIPPER_PRIMITIVES_OUT)
+    .union(PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS)
+    .union(PipelineStatisticsTypes::COMPUTE_SHADER_INVOCATIONS);
+
+const VALUES_PER_QUERY: u32 = 5;
+
+/// Pipeline statistics of all scopes sharing a label during one frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Label the scopes were recorded with.
+    pub label: String,
+    /// Number of scopes aggregated into this entry.
+    pub scopes: u32,
+    /// Vertex shader invocations.
+    pub vertex_invocations: u64,
+    /// Primitives that reached the clipper.
+    pub clipper_invocations: u64,
+    /// Primitives that survived clipping and were rasterized.
+    pub primitives_out: u64,
+    /// Fragment shader invocations.
+    pub fragment_invocations: u64,
+    /// Compute shader invocations.
+    pub compute_invocations: u64,
+}
+
+impl PipelineStats {
+    /// Average fragment shader invocations per rasterized primitive.
+    ///
+    /// Large values on geometry that should be small on screen hint at overdraw
+    /// or missing depth rejection.
+    pub fn fragments_per_primitive(&self) -> f64 {
+        if self.primitives_out == 0 {
+            0.0
+        } else {
+            self.fragment_invocations as f64 / self.primitives_out as f64
+        }
+    }
+
+    /// Fragment shader invocations per pixel of a `width` x `height` target.
+    ///
+    /// Values above `1.0` mean the label shaded some pixels more than once.
+    pub fn overdraw(&self, width: u32, height: u32) -> f64 {
+        let pixels = width as u64 * height as u64;
+        if pixels == 0 {
+            0.0
+        } else {
+            self.fragment_invocations as f64 / pixels as f64
+        }
+    }
+}
+
+/// Pipeline statistics query reserved for a single scope.
+///
+/// Obtained from [`PipelineStatistics::begin_scope`]. Wrap the work to measure in
+/// `begin_pipeline_statistics_query` / `end_pipeline_statistics_query` using
+/// [`query_set`](Self::query_set) and [`index`](Self::index), or use
+/// [`PipelineStatistics::begin_render_scope`] which does that for you.
+#[derive(Debug, Clone)]
+pub struct StatisticsQuery {
+    query_set: QuerySet,
+    index: u32,
+}
+
+impl StatisticsQuery {
+    /// Query set the statistics are written to.
+    pub fn query_set(&self) -> &QuerySet {
+        &self.query_set
+    }
+
+    /// Index of the query inside [`query_set`](Self::query_set).
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub(crate) fn begin_compute(&self, pass: &mut ComputePass) {
+        pass.begin_pipeline_statistics_query(&self.query_set, self.index);
+    }
+
+    pub(crate) fn begin_render(&self, pass: &mut RenderPass) {
+        pass.begin_pipeline_statistics_query(&self.query_set, self.index);
+    }
+}
+
+/// Built-in pipeline statistics collector.
+///
+/// Each frame:
+/// 1. [`begin_frame`](Self::begin_frame) collects finished results and picks a free slot
+/// 2. [`begin_scope`](Self::begin_scope) / [`begin_render_scope`](Self::begin_render_scope)
+///    reserve one query per measured scope
+/// 3. [`resolve`](Self::resolve) copies the queries into a readback buffer,
+///    which is mapped automatically once the encoder is submitted
+///
+/// Results are aggregated per label and sorted by fragment invocations,
+/// most expensive first.
+///
+/// ## Requirements
+/// The device must be created with [`Features::PIPELINE_STATISTICS_QUERY`].
+/// [`PipelineStatistics::new`] returns `None` otherwise.
+///
+/// ## Managed passes
+/// When enabled through [`RenderManager::enable_pipeline_statistics`](crate::renderer::RenderManager::enable_pipeline_statistics),
+/// compute dispatches, procedural texture generation and fullscreen debug draws are
+/// measured automatically. Draws issued after
+/// [`RenderManager::render_with_textures`](crate::renderer::RenderManager::render_with_textures)
+/// are yours, so wrap them in [`begin_render_scope`](Self::begin_render_scope) /
+/// [`end_render_scope`](Self::end_render_scope) with the material or shader as label.
+///
+/// Pipeline statistics queries cannot be nested: only one scope may be open per pass.
+pub struct PipelineStatistics {
+    ring: QueryRing,
+    render_scope_open: bool,
+    latest: Vec<PipelineStats>,
+    latest_frame: Option<u64>,
+}
+
+impl PipelineStatistics {
+    /// Create a new collector able to measure up to `max_scopes` scopes per frame.
+    ///
+    /// Returns `None` if the device was not created with [`Features::PIPELINE_STATISTICS_QUERY`].
+    pub fn new(device: &Device, max_scopes: u32) -> Option<Self> {
+        if !device.features().contains(Features::PIPELINE_STATISTICS_QUERY) {
+            return None;
+        }
+        Some(Self {
+            ring: QueryRing::new(
+                device,
+                "pipeline statistics",
+                QueryType::PipelineStatistics(STATISTICS_TYPES),
+                max_scopes,
+                1,
+                VALUES_PER_QUERY,
+            ),
+            render_scope_open: false,
+            latest: Vec::new(),
+            latest_frame: None,
+        })
+    }
+
+    /// Start collecting statistics for a new frame.
+    ///
+    /// Collects results of previously resolved frames that finished on the GPU.
+    /// If all slots are still in flight, this frame is not measured and
+    /// [`begin_scope`](Self::begin_scope) returns `None` until the next frame.
+    pub fn begin_frame(&mut self, frame_index: u64) {
+        self.render_scope_open = false;
+        for frame in self.ring.begin_frame(frame_index) {
+            let mut by_label: HashMap<String, PipelineStats> = HashMap::new();
+            for (label, values) in frame.labels.into_iter().zip(frame.values.chunks_exact(VALUES_PER_QUERY as usize)) {
+                let stats = by_label.entry(label.clone()).or_insert_with(|| PipelineStats {
+                    label,
+                    ..Default::default()
+                });
+                stats.scopes += 1;
+                stats.vertex_invocations += values[0];
+                stats.clipper_invocations += values[1];
+                stats.primitives_out += values[2];
+                stats.fragment_invocations += values[3];
+                stats.compute_invocations += values[4];
+            }
+
+            let mut stats: Vec<PipelineStats> = by_label.into_values().collect();
+            stats.sort_by(|a, b| {
+                b.fragment_invocations
+                    .cmp(&a.fragment_invocations)
+                    .then(b.compute_invocations.cmp(&a.compute_invocations))
+                    .then(a.label.cmp(&b.label))
+            });
+            self.latest = stats;
+            self.latest_frame = Some(frame.frame_index);
+        }
+    }
+
+    /// Reserve a query for a scope labelled `label`.
+    ///
+    /// Returns `None` if the frame is not being measured or the
+    /// per-frame scope budget is exhausted.
+    pub fn begin_scope(&mut self, label: &str) -> Option<StatisticsQuery> {
+        let (query_set, index) = self.ring.reserve(label)?;
+        Some(StatisticsQuery { query_set, index })
+    }
+
+    /// Reserve a query and begin it on `pass`.
+    ///
+    /// Every draw recorded until [`end_render_scope`](Self::end_render_scope) is
+    /// counted under `label`. Returns `false` (and records nothing) if the frame
+    /// is not being measured, the budget is exhausted or a scope is already open.
+    pub fn begin_render_scope(&mut self, pass: &mut RenderPass, label: &str) -> bool {
+        if self.render_scope_open {
+            return false;
+        }
+        let Some(query) = self.begin_scope(label) else {
+            return false;
+        };
+        query.begin_render(pass);
+        self.render_scope_open = true;
+        true
+    }
+
+    /// End the scope opened by [`begin_render_scope`](Self::begin_render_scope).
+    ///
+    /// Does nothing if no scope is open.
+    pub fn end_render_scope(&mut self, pass: &mut RenderPass) {
+        if self.render_scope_open {
+            pass.end_pipeline_statistics_query();
+            self.render_scope_open = false;
+        }
+    }
+
+    /// Whether a render scope is currently open.
+    pub fn render_scope_open(&self) -> bool {
+        self.render_scope_open
+    }
+
+    /// Resolve this frame's queries into the readback buffer.
+    ///
+    /// Must be recorded into an encoder that is submitted **after** every
+    /// measured pass of the frame. The readback buffer is mapped automatically
+    /// once that encoder is submitted.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        self.ring.resolve(encoder);
+    }
+
+    /// Aggregated statistics of the most recently completed frame, most fragment invocations first.
+    ///
+    /// Empty until the first measured frame finished on the GPU.
+    pub fn results(&self) -> &[PipelineStats] {
+        &self.latest
+    }
+
+    /// Frame index the values in [`results`](Self::results) belong to.
+    pub fn results_frame(&self) -> Option<u64> {
+        self.latest_frame
+    }
+
+    /// Maximum number of scopes that can be measured per frame.
+    pub fn max_scopes(&self) -> u32 {
+        self.ring.reservations_per_frame()
+    }
+}
@@ -1,10 +1,16 @@
 // pipelines.rs
 #![allow(dead_code)]
+use std::borrow::Cow;
 use std::collections::{HashMap};
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::{Path, PathBuf};
 use wgpu::*;
-use crate::shader_preprocessing::compile_wgsl;
+use crate::debug_modes::{debug_targets, effective_mode, DebugRenderMode};
+use crate::meshes::VertexLayoutId;
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, Tracked};
+use crate::error::CrmError;
+use crate::shader_preprocessing::try_compile_wgsl;
+use crate::labels::namespaced;
 
 /// Options required to enable shadow sampling in a render pipeline.
 ///
@@ -28,6 +34,22 @@ pub struct ShadowOptions {
     pub view: TextureView,
 }
 
+/// How a material texture is bound.
+///
+/// Set per texture with [`PipelineOptions::with_texture_access`]; textures without
+/// an explicit access are sampled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TextureAccess {
+    /// `texture_2d<T>` / `texture_multisampled_2d<T>`, sample type detected from the format.
+    #[default]
+    Sampled,
+    /// `texture_storage_2d<format, access>` with the format of the view.
+    ///
+    /// The view must cover a single mip level and its texture needs
+    /// `TextureUsages::STORAGE_BINDING`.
+    Storage(StorageTextureAccess),
+}
+
 /// Configuration object for creating a render pipeline.
 ///
 /// `PipelineOptions` describes fixed-function and layout-related state
@@ -44,7 +66,8 @@ pub struct ShadowOptions {
 /// ### Group 0: Material + textures
 /// - `@binding(0)`: trilinear sampler
 /// - `@binding(1..n)`: material textures as
-///   `texture_2d<f32>` or `texture_multisampled_2d<f32>`
+///   `texture_2d<f32>` or `texture_multisampled_2d<f32>`, or
+///   `texture_storage_2d` for textures with [`TextureAccess::Storage`]
 /// - `@binding(n + 1)`: (optional) shadow comparison sampler
 /// - `@binding(n + 2)`: (optional) shadow map as
 ///   `texture_depth_2d_array`
@@ -70,6 +93,11 @@ pub struct PipelineOptions {
     /// Vertex buffer layouts consumed by the vertex shader.
     pub vertex_layouts: Vec<VertexBufferLayout<'static>>,
 
+    /// Registered layout `vertex_layouts` was built from, part of the pipeline cache key.
+    ///
+    /// Set by [`VertexLayoutRegistry::pipeline_options`](crate::meshes::VertexLayoutRegistry::pipeline_options).
+    pub vertex_layout_id: Option<VertexLayoutId>,
+
     /// Optional face culling mode.
     pub cull_mode: Option<Face>,
 
@@ -85,6 +113,21 @@ pub struct PipelineOptions {
 
     /// Optional shadow sampling configuration.
     pub shadow: Option<ShadowOptions>,
+
+    /// Binding type of each material texture, in texture order.
+    ///
+    /// Missing entries default to [`TextureAccess::Sampled`].
+    pub texture_access: Vec<TextureAccess>,
+
+    /// Selects the weighted-blended order-independent transparency variant.
+    ///
+    /// See [`with_oit`](Self::with_oit).
+    pub oit: bool,
+
+    /// Per-material debug render mode, overriding the global one.
+    ///
+    /// See [`debug_modes`](crate::debug_modes).
+    pub debug_mode: Option<DebugRenderMode>,
 }
 
 impl Default for PipelineOptions {
@@ -108,6 +151,10 @@ impl Default for PipelineOptions {
             targets: vec![],
             vertex_only: false,
             shadow: None,
+            texture_access: vec![],
+            oit: false,
+            debug_mode: None,
+            vertex_layout_id: None,
         }
     }
 }
@@ -156,6 +203,35 @@ impl PipelineOptions {
         self
     }
 
+    /// Binds material texture `index` with the given access instead of as sampled texture.
+    ///
+    /// Use [`TextureAccess::Storage`] for textures the shader writes
+    /// (`texture_storage_2d`).
+    pub fn with_texture_access(mut self, index: usize, access: TextureAccess) -> Self {
+        if self.texture_access.len() <= index {
+            self.texture_access.resize(index + 1, TextureAccess::Sampled);
+        }
+        self.texture_access[index] = access;
+        self
+    }
+
+    /// Selects the weighted-blended order-independent transparency variant.
+    ///
+    /// The pipeline renders into the accumulation and revealage targets of
+    /// [`OitTargets`](crate::oit::OitTargets) with their dedicated blend states
+    /// instead of `targets`, never writes depth, and compiles the shader with the
+    /// `OIT` define, so one transparent material shader can serve both paths.
+    pub fn with_oit(mut self) -> Self {
+        self.oit = true;
+        self
+    }
+
+    /// Draws this material with `mode` regardless of the global debug mode.
+    pub fn with_debug_mode(mut self, mode: DebugRenderMode) -> Self {
+        self.debug_mode = Some(mode);
+        self
+    }
+
     /// Configures the pipeline as vertex-only.
     ///
     /// This disables the fragment stage entirely and is typically used
@@ -192,6 +268,9 @@ struct PipelineKey {
     depth_stencil: Option<DepthStencilKey>,
     cull_mode: Option<Face>,
     depth_only: bool,
+    targets: Vec<Option<ColorTargetState>>,
+    debug_mode: Option<DebugRenderMode>,
+    vertex_layout_id: Option<VertexLayoutId>,
     defines_hash: u64,
 }
 
@@ -207,8 +286,15 @@ struct ShaderKey {
 pub struct PipelineCache {
     device: Device,
     shaders: HashMap<ShaderKey, ShaderEntry>,
-    pipelines: HashMap<PipelineKey, RenderPipeline>,
-    pub(crate) uniform_layouts: HashMap<usize, BindGroupLayout>,
+    pipelines: HashMap<PipelineKey, Tracked<RenderPipeline>>,
+    uniform_layouts: HashMap<usize, Tracked<BindGroupLayout>>,
+    debug_mode: Option<DebugRenderMode>,
+    /// Built-in fragment shaders of the debug render modes.
+    debug_shaders: HashMap<DebugRenderMode, ShaderModule>,
+    frame: u64,
+    /// Shader/layout combinations already checked by [`validate_layouts`](Self::validate_layouts).
+    #[cfg(debug_assertions)]
+    validated: std::collections::HashSet<u64>,
 }
 
 impl PipelineCache {
@@ -218,6 +304,11 @@ impl PipelineCache {
             shaders: HashMap::new(),
             pipelines: HashMap::new(),
             uniform_layouts: HashMap::new(),
+            debug_mode: None,
+            debug_shaders: HashMap::new(),
+            frame: 0,
+            #[cfg(debug_assertions)]
+            validated: std::collections::HashSet::new(),
         }
     }
 
@@ -225,29 +316,27 @@ impl PipelineCache {
         &self.device
     }
 
+    /// Set the frame index recorded on cache hits and insertions.
+    pub(crate) fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
     /// Get or create a uniform bind group layout for N uniform buffers.
     pub(crate) fn uniform_layout(&mut self, buffer_count: usize) -> &BindGroupLayout {
-        if !self.uniform_layouts.contains_key(&buffer_count) {
-            let entries: Vec<BindGroupLayoutEntry> = (0..buffer_count)
-                .map(|i| BindGroupLayoutEntry {
-                    binding: i as u32,
-                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                })
-                .collect();
+        if let Some(entry) = self.uniform_layouts.get_mut(&buffer_count) {
+            entry.touch(self.frame);
+        } else {
+            trace_event!(buffer_count, "created uniform bind group layout");
+            let entries = uniform_layout_entries(buffer_count);
 
             let layout = self.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some(&format!("uniform layout ({})", buffer_count)),
+                label: Some(&namespaced(&format!("uniform layout ({})", buffer_count))),
                 entries: &entries,
             });
-            self.uniform_layouts.insert(buffer_count, layout);
+            let label = format!("{} uniform buffers", buffer_count);
+            self.uniform_layouts.insert(buffer_count, Tracked::new(layout, self.frame, label));
         }
-        self.uniform_layouts.get(&buffer_count).unwrap()
+        &self.uniform_layouts.get(&buffer_count).unwrap().value
     }
 
     /// Create a bind group from uniform buffers.
@@ -263,7 +352,7 @@ impl PipelineCache {
             .collect();
 
         self.device.create_bind_group(&BindGroupDescriptor {
-            label: Some(label),
+            label: Some(&namespaced(label)),
             layout,
             entries: &entries,
         })
@@ -276,7 +365,16 @@ impl PipelineCache {
         bind_group_layouts: &[&BindGroupLayout],
         options: &PipelineOptions,
         defines: &HashMap<String, bool>,
-    ) -> &RenderPipeline {
+    ) -> Result<&RenderPipeline, CrmError> {
+        let oit_options;
+        let options = if options.oit {
+            oit_options = crate::oit::oit_variant(options);
+            &oit_options
+        } else {
+            options
+        };
+        let defines = pipeline_defines(defines, options);
+        let defines = &*defines;
         let layout_hash = hash_layouts(bind_group_layouts, &options.vertex_layouts);
 
         let key = PipelineKey {
@@ -287,48 +385,163 @@ impl PipelineCache {
             depth_stencil: options.depth_stencil.as_ref().map(|d| d.into()),
             cull_mode: options.cull_mode,
             depth_only: options.vertex_only,
+            targets: options.targets.clone(),
+            debug_mode: effective_mode(options, self.debug_mode, self.device.features()),
+            vertex_layout_id: options.vertex_layout_id,
             defines_hash: hash_defines(defines)
         };
 
-        if !self.pipelines.contains_key(&key) {
-            self.load_shader(shader_path, defines);
+        if let Some(entry) = self.pipelines.get_mut(&key) {
+            entry.touch(self.frame);
+        } else {
+            let _span = trace_span!("render_pipeline_miss", shader = %shader_path.display());
+            self.load_shader(shader_path, defines)?;
+            if let Some(mode) = key.debug_mode {
+                self.load_debug_shader(mode);
+            }
             let pipeline = self.create_pipeline(&key, bind_group_layouts, options, defines);
-            self.pipelines.insert(key.clone(), pipeline);
+            let label = shader_path.display().to_string();
+            self.pipelines.insert(key.clone(), Tracked::new(pipeline, self.frame, label));
         }
 
-        self.pipelines.get(&key).unwrap()
+        Ok(&self.pipelines.get(&key).unwrap().value)
+    }
+
+    /// Debug-build check that the bind groups built from `groups` fit the shader.
+    ///
+    /// Compares every binding used by `vs_main` (and `fs_main` unless the pipeline is
+    /// depth-only) against the layout entries of the matching group: presence, binding
+    /// type, texture dimension, sample type, multisampling and stage visibility.
+    /// The `vs_main` inputs are compared against the vertex layouts of `options`.
+    /// Each shader/layout combination is checked once.
+    ///
+    /// ## Panics
+    /// Panics with a readable diff of the offending bindings or vertex attributes
+    /// instead of wgpu's validation error. A shader that fails to preprocess is left
+    /// to [`get_or_create`](Self::get_or_create) to report.
+    #[cfg(debug_assertions)]
+    pub(crate) fn validate_layouts(
+        &mut self,
+        shader_path: &Path,
+        options: &PipelineOptions,
+        defines: &HashMap<String, bool>,
+        groups: &[&[BindGroupLayoutEntry]],
+        group_names: &[String],
+    ) {
+        let defines = pipeline_defines(defines, options);
+        let defines = &*defines;
+        let mut hasher = DefaultHasher::new();
+        shader_path.hash(&mut hasher);
+        hash_defines(defines).hash(&mut hasher);
+        options.vertex_only.hash(&mut hasher);
+        groups.hash(&mut hasher);
+        options.vertex_layouts.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.validated.contains(&hash) {
+            return;
+        }
+        let Ok(source) = crate::shader_preprocessing::preprocess_file(shader_path, defines) else {
+            return;
+        };
+        self.validated.insert(hash);
+        let entry_points: &[&str] = if options.vertex_only { &["vs_main"] } else { &["vs_main", "fs_main"] };
+        if let Err(report) = crate::validation::check_bindings(&source, entry_points, groups, group_names) {
+            panic!(
+                "Bind groups are incompatible with shader {}:\n{}",
+                shader_path.display(),
+                report
+            );
+        }
+        if let Err(report) = crate::validation::check_vertex_inputs(&source, &options.vertex_layouts) {
+            panic!(
+                "Vertex layout{} is incompatible with shader {}:\n{}",
+                options.vertex_layout_id.map_or(String::new(), |id| format!(" {:?}", id)),
+                shader_path.display(),
+                report
+            );
+        }
     }
 
     /// Reload shaders from disk. Pipelines using reloaded shaders will be recreated on next use.
-    pub(crate) fn reload_shaders(&mut self, paths: &[PathBuf], defines: &HashMap<String, bool>) {
+    ///
+    /// If any shader fails to compile, nothing is replaced and the old shaders and
+    /// pipelines stay in use.
+    pub(crate) fn reload_shaders(&mut self, paths: &[PathBuf], defines: &HashMap<String, bool>) -> Result<(), CrmError> {
+        let defines_hash = hash_defines(defines);
+        let mut reloaded = Vec::new();
         for path in paths {
             let shader_key = ShaderKey {
                 shader_path: path.clone(),
-                defines_hash: hash_defines(defines)
+                defines_hash,
             };
             if self.shaders.contains_key(&shader_key) {
-                self.load_shader(path, defines);
+                let module = try_compile_wgsl(&self.device, path, defines)?;
+                reloaded.push((shader_key, ShaderEntry { module }));
             }
         }
+        self.shaders.extend(reloaded);
+        // Variants with pipeline-specific defines (e.g. OIT) are recompiled on next use.
+        self.shaders
+            .retain(|key, _| key.defines_hash == defines_hash || !paths.contains(&key.shader_path));
+        let before = self.pipelines.len();
         self.pipelines.retain(|key, _| !paths.contains(&key.shader_path));
+        trace_evict!("render_pipelines", before - self.pipelines.len());
+        Ok(())
+    }
+
+    /// Drop a shader and every pipeline created from it, without recompiling.
+    /// Returns the number of pipelines removed.
+    pub(crate) fn release_shader(&mut self, path: &Path) -> usize {
+        self.shaders.retain(|key, _| key.shader_path != path);
+        let before = self.pipelines.len();
+        self.pipelines.retain(|key, _| key.shader_path != path);
+        let removed = before - self.pipelines.len();
+        trace_evict!("render_pipelines", removed);
+        removed
     }
 
     /// Clear all cached pipelines and shaders.
     pub(crate) fn clear(&mut self) {
+        trace_evict!("shader_modules", self.shaders.len());
+        trace_evict!("render_pipelines", self.pipelines.len());
         self.shaders.clear();
         self.pipelines.clear();
     }
 
-    fn load_shader(&mut self, path: &Path, defines: &HashMap<String, bool>) {
+    /// Append diagnostics for all cached pipelines and uniform layouts.
+    pub(crate) fn collect_entries(&self, out: &mut Vec<CacheEntryInfo>) {
+        for (key, entry) in &self.pipelines {
+            out.push(entry.info(CacheKind::RenderPipeline, entry_id(key), format!("{:?}", key), 0));
+        }
+        for (count, entry) in &self.uniform_layouts {
+            out.push(entry.info(CacheKind::UniformLayout, entry_id(count), format!("{} x uniform buffer", count), 0));
+        }
+    }
+
+    /// Remove a single entry by its diagnostics id. Returns `true` if it existed.
+    pub(crate) fn evict(&mut self, kind: CacheKind, id: u64) -> bool {
+        let removed = match kind {
+            CacheKind::RenderPipeline => evict_by_id(&mut self.pipelines, id),
+            CacheKind::UniformLayout => evict_by_id(&mut self.uniform_layouts, id),
+            _ => false,
+        };
+        if removed {
+            trace_evict!(kind.name(), 1usize);
+        }
+        removed
+    }
+
+    fn load_shader(&mut self, path: &Path, defines: &HashMap<String, bool>) -> Result<(), CrmError> {
         let shader_key = ShaderKey {
             shader_path: path.to_path_buf(),
             defines_hash: hash_defines(defines)
         };
         if self.shaders.contains_key(&shader_key) {
-            return;
+            return Ok(());
         }
-        let module = compile_wgsl(&self.device, path, defines);
+        let module = try_compile_wgsl(&self.device, path, defines)?;
         self.shaders.insert(shader_key, ShaderEntry { module });
+        Ok(())
     }
 
     fn create_pipeline(
@@ -343,53 +556,122 @@ impl PipelineCache {
             defines_hash: hash_defines(defines)
         };
         let shader = &self.shaders.get(&shader_key).unwrap().module;
+        let debug_fragment = key.debug_mode.and_then(|mode| self.debug_shaders.get(&mode));
+        build_render_pipeline_with(&self.device, shader, &key.shader_path, bind_group_layouts, options, key.debug_mode, debug_fragment)
+    }
 
-        let pipeline_layout = self.device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some(&format!("{} layout", key.shader_path.display())),
-            bind_group_layouts,
-            immediate_size: 0,
-        });
+    fn load_debug_shader(&mut self, mode: DebugRenderMode) {
+        if self.debug_shaders.contains_key(&mode) {
+            return;
+        }
+        if let Some(source) = mode.fragment_source() {
+            let module = self.device.create_shader_module(ShaderModuleDescriptor {
+                label: Some(&namespaced(&format!("{:?} debug shader", mode))),
+                source: ShaderSource::Wgsl(source.into()),
+            });
+            self.debug_shaders.insert(mode, module);
+        }
+    }
 
-        let fragment = if options.vertex_only {
-            None
-        } else {
-            Some(FragmentState {
-                module: shader,
-                entry_point: Some("fs_main"),
-                targets: &options.targets.iter().cloned().collect::<Vec<_>>(),
-                compilation_options: Default::default(),
-            })
-        };
+    /// Set the debug render mode of all pipelines without their own.
+    pub(crate) fn set_debug_mode(&mut self, mode: Option<DebugRenderMode>) {
+        self.debug_mode = mode;
+    }
 
-        self.device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some(&format!("{} Pipeline", key.shader_path.display())),
-            layout: Some(&pipeline_layout),
-            vertex: VertexState {
-                module: shader,
-                entry_point: Some("vs_main"),
-                buffers: &options.vertex_layouts,
-                compilation_options: Default::default(),
-            },
-            fragment,
-            primitive: PrimitiveState {
-                topology: options.topology,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: options.cull_mode,
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: options.depth_stencil.clone(),
-            multisample: MultisampleState {
-                count: options.msaa_samples,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+    pub(crate) fn debug_mode(&self) -> Option<DebugRenderMode> {
+        self.debug_mode
+    }
+}
+
+/// Create a render pipeline for an already compiled shader module.
+///
+/// Entry points are `vs_main` and `fs_main` (the latter is skipped for depth-only pipelines).
+pub(crate) fn build_render_pipeline(
+    device: &Device,
+    shader: &ShaderModule,
+    shader_path: &Path,
+    bind_group_layouts: &[&BindGroupLayout],
+    options: &PipelineOptions,
+) -> RenderPipeline {
+    build_render_pipeline_with(device, shader, shader_path, bind_group_layouts, options, None, None)
+}
+
+/// [`build_render_pipeline`] drawn with a debug render mode. `debug_fragment`
+/// replaces the fragment stage for modes that have their own.
+fn build_render_pipeline_with(
+    device: &Device,
+    shader: &ShaderModule,
+    shader_path: &Path,
+    bind_group_layouts: &[&BindGroupLayout],
+    options: &PipelineOptions,
+    debug_mode: Option<DebugRenderMode>,
+    debug_fragment: Option<&ShaderModule>,
+) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced(&format!("{} layout", shader_path.display()))),
+        bind_group_layouts,
+        immediate_size: 0,
+    });
+
+    let targets = match debug_fragment {
+        Some(_) => debug_targets(&options.targets),
+        None => options.targets.to_vec(),
+    };
+    let fragment = if options.vertex_only {
+        None
+    } else {
+        Some(FragmentState {
+            module: debug_fragment.unwrap_or(shader),
+            entry_point: Some("fs_main"),
+            targets: &targets,
+            compilation_options: Default::default(),
+        })
+    };
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced(&format!("{} Pipeline", shader_path.display()))),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &options.vertex_layouts,
+            compilation_options: Default::default(),
+        },
+        fragment,
+        primitive: PrimitiveState {
+            topology: options.topology,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: options.cull_mode,
+            polygon_mode: debug_mode.map_or(PolygonMode::Fill, |mode| mode.polygon_mode()),
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: options.depth_stencil.clone(),
+        multisample: MultisampleState {
+            count: options.msaa_samples,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+/// Layout entries of the uniform bind group layout for `buffer_count` buffers.
+pub(crate) fn uniform_layout_entries(buffer_count: usize) -> Vec<BindGroupLayoutEntry> {
+    (0..buffer_count)
+        .map(|i| BindGroupLayoutEntry {
+            binding: i as u32,
+            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
             },
-            cache: None,
-            multiview_mask: None,
+            count: None,
         })
-    }
+        .collect()
 }
 
 fn hash_layouts(bgls: &[&BindGroupLayout], vertex_layouts: &[VertexBufferLayout]) -> u64 {
@@ -403,6 +685,19 @@ fn hash_layouts(bgls: &[&BindGroupLayout], vertex_layouts: &[VertexBufferLayout]
     }
     hasher.finish()
 }
+
+/// The defines a pipeline's shader is compiled with: the global defines, plus `OIT`
+/// for order-independent transparency variants.
+fn pipeline_defines<'a>(defines: &'a HashMap<String, bool>, options: &PipelineOptions) -> Cow<'a, HashMap<String, bool>> {
+    if options.oit {
+        let mut defines = defines.clone();
+        defines.insert("OIT".to_string(), true);
+        Cow::Owned(defines)
+    } else {
+        Cow::Borrowed(defines)
+    }
+}
+
 pub fn hash_defines(defines: &HashMap<String, bool>) -> u64 { // stable: hashes the values as well, or else shaders wouldn't be updated on change!
     // Use a small stack vec for sorting keys
     let mut keys: Vec<_> = defines.keys().collect();
@@ -0,0 +1,258 @@
+//! Procedural primitive meshes.
+//!
+//! Each generator returns [`MeshData`] with normals, UVs and tangents in the
+//! [`VertexLayoutId::POSITION_NORMAL_UV_TANGENT`] layout, centered at the origin
+//! with `+Y` up. Faces wind counter-clockwise seen from outside, so back-face
+//! culling works as usual:
+//! ```ignore
+//! let sphere = primitives::uv_sphere(0.5, 32, 16).upload(&mut meshes, "sphere");
+//! let options = meshes.layouts().pipeline_options(VertexLayoutId::POSITION_NORMAL_UV_TANGENT, base);
+//! ```
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use crate::meshes::{MeshHandle, MeshManager, TangentVertex, VertexLayoutId};
+
+/// CPU-side vertices and indices of a generated mesh.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<TangentVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// Upload into `meshes` with the [`VertexLayoutId::POSITION_NORMAL_UV_TANGENT`] layout.
+    pub fn upload(&self, meshes: &mut MeshManager, label: &str) -> MeshHandle {
+        meshes.upload(label, VertexLayoutId::POSITION_NORMAL_UV_TANGENT, &self.vertices, &self.indices)
+    }
+
+    /// Append a `columns` x `rows` grid of quads; `vertex(i, j)` returns position,
+    /// normal and UV of grid vertex `(i, j)`.
+    ///
+    /// The surface must be parameterized so that the outward normal points along
+    /// `d/dj x d/di`, which keeps the winding counter-clockwise from outside.
+    fn grid(&mut self, columns: u32, rows: u32, vertex: impl Fn(u32, u32) -> ([f32; 3], [f32; 3], [f32; 2])) {
+        let base = self.vertices.len() as u32;
+        for j in 0..=rows {
+            for i in 0..=columns {
+                let (position, normal, uv) = vertex(i, j);
+                self.vertices.push(TangentVertex {
+                    position,
+                    normal,
+                    uv,
+                    tangent: [0.0; 4],
+                });
+            }
+        }
+        for j in 0..rows {
+            for i in 0..columns {
+                let a = base + j * (columns + 1) + i;
+                let b = a + 1;
+                let c = a + columns + 1;
+                let d = c + 1;
+                self.indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+    }
+
+    /// Fill in the tangents from the UV derivatives of the triangles around each vertex.
+    pub(crate) fn compute_tangents(mut self) -> Self {
+        let mut tangents = vec![[0.0f32; 3]; self.vertices.len()];
+        let mut bitangents = vec![[0.0f32; 3]; self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| self.vertices[i as usize]);
+            let e1 = sub(b.position, a.position);
+            let e2 = sub(c.position, a.position);
+            let (du1, dv1) = (b.uv[0] - a.uv[0], b.uv[1] - a.uv[1]);
+            let (du2, dv2) = (c.uv[0] - a.uv[0], c.uv[1] - a.uv[1]);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() <= f32::EPSILON {
+                continue;
+            }
+            let r = 1.0 / det;
+            let tangent = scale(sub(scale(e1, dv2), scale(e2, dv1)), r);
+            let bitangent = scale(sub(scale(e2, du1), scale(e1, du2)), r);
+            for &i in triangle {
+                tangents[i as usize] = add(tangents[i as usize], tangent);
+                bitangents[i as usize] = add(bitangents[i as usize], bitangent);
+            }
+        }
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            let n = vertex.normal;
+            // Gram-Schmidt against the normal, with a fallback for degenerate UVs.
+            let mut t = sub(tangents[i], scale(n, dot(n, tangents[i])));
+            if dot(t, t) <= f32::EPSILON {
+                let axis = if n[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+                t = sub(axis, scale(n, dot(n, axis)));
+            }
+            let t = normalize(t);
+            let w = if dot(cross(n, t), bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+            vertex.tangent = [t[0], t[1], t[2], w];
+        }
+        self
+    }
+}
+
+/// Axis-aligned cube with edge length `size`, one UV square per face.
+pub fn cube(size: f32) -> MeshData {
+    let h = size * 0.5;
+    // Normal, then the face's U and V axes with U x V = normal.
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    let mut data = MeshData::default();
+    for (normal, u, v) in faces {
+        // Row 0 is the top edge (+V), so texture V grows downwards.
+        data.grid(1, 1, |i, j| {
+            let (x, y) = (i as f32 * 2.0 - 1.0, 1.0 - j as f32 * 2.0);
+            let position = add(scale(normal, h), add(scale(u, x * h), scale(v, y * h)));
+            (position, normal, [i as f32, j as f32])
+        });
+    }
+    data.compute_tangents()
+}
+
+/// Square in the XZ plane facing `+Y`, split into `subdivisions` quads per side.
+pub fn plane(size: f32, subdivisions: u32) -> MeshData {
+    let n = subdivisions.max(1);
+    let mut data = MeshData::default();
+    data.grid(n, n, |i, j| {
+        let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+        ([(u - 0.5) * size, 0.0, (v - 0.5) * size], [0.0, 1.0, 0.0], [u, v])
+    });
+    data.compute_tangents()
+}
+
+/// Sphere with `segments` slices around `Y` and `rings` stacks from pole to pole.
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let mut data = MeshData::default();
+    data.grid(segments, rings, |i, j| {
+        let (u, v) = (i as f32 / segments as f32, j as f32 / rings as f32);
+        let normal = spherical(u * TAU, v * PI);
+        (scale(normal, radius), normal, [u, v])
+    });
+    data.compute_tangents()
+}
+
+/// Cone with its apex at `+height / 2` and a capped base at `-height / 2`.
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let h = height * 0.5;
+    let slant = (height * height + radius * radius).sqrt().max(f32::EPSILON);
+    let mut data = MeshData::default();
+    // Side: one row from the apex (duplicated per segment) to the rim.
+    data.grid(segments, 1, |i, j| {
+        let u = i as f32 / segments as f32;
+        let (sin, cos) = (u * TAU).sin_cos();
+        let rim = j as f32;
+        let normal = [height * cos / slant, radius / slant, -height * sin / slant];
+        ([radius * rim * cos, h - height * rim, -radius * rim * sin], normal, [u, rim])
+    });
+    // Base cap, from the rim towards the center, with planar UVs.
+    data.grid(segments, 1, |i, j| {
+        let (sin, cos) = (i as f32 / segments as f32 * TAU).sin_cos();
+        let ring = 1.0 - j as f32;
+        (
+            [radius * ring * cos, -h, -radius * ring * sin],
+            [0.0, -1.0, 0.0],
+            [0.5 + 0.5 * ring * cos, 0.5 + 0.5 * ring * sin],
+        )
+    });
+    data.compute_tangents()
+}
+
+/// Capsule along `Y`: a cylinder of `height` between two hemispheres of `radius`.
+///
+/// `rings` is the number of stacks per hemisphere. V runs from the top to the
+/// bottom pole proportionally to the profile length.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let h = height * 0.5;
+    let length = PI * radius + height;
+    let mut data = MeshData::default();
+    // Rows 0..=rings cover the top hemisphere, the rest the bottom one; the cell
+    // between them is the cylinder.
+    data.grid(segments, 2 * rings + 1, |i, j| {
+        let u = i as f32 / segments as f32;
+        let (theta, center, arc) = if j <= rings {
+            let theta = j as f32 / rings as f32 * FRAC_PI_2;
+            (theta, h, theta * radius)
+        } else {
+            let theta = FRAC_PI_2 + (j - rings - 1) as f32 / rings as f32 * FRAC_PI_2;
+            (theta, -h, theta * radius + height)
+        };
+        let normal = spherical(u * TAU, theta);
+        let position = add(scale(normal, radius), [0.0, center, 0.0]);
+        (position, normal, [u, arc / length])
+    });
+    data.compute_tangents()
+}
+
+/// Torus around `Y` with ring radius `major_radius` and tube radius `minor_radius`.
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> MeshData {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    let mut data = MeshData::default();
+    data.grid(major_segments, minor_segments, |i, j| {
+        let (u, v) = (i as f32 / major_segments as f32, j as f32 / minor_segments as f32);
+        let (sin_phi, cos_phi) = (u * TAU).sin_cos();
+        let (sin_theta, cos_theta) = (v * TAU).sin_cos();
+        let radial = [cos_phi, 0.0, -sin_phi];
+        let normal = add(scale(radial, cos_theta), [0.0, -sin_theta, 0.0]);
+        let position = add(scale(radial, major_radius), scale(normal, minor_radius));
+        (position, normal, [u, v])
+    });
+    data.compute_tangents()
+}
+
+/// Area-weighted vertex normals of an indexed triangle list, for imported meshes without normals.
+#[cfg_attr(not(any(feature = "gltf", feature = "obj")), allow(dead_code))]
+pub(crate) fn vertex_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![[0.0f32; 3]; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| positions[i as usize]);
+        let n = cross(sub(b, a), sub(c, a));
+        for &i in triangle {
+            normals[i as usize] = add(normals[i as usize], n);
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| if dot(n, n) > 0.0 { normalize(n) } else { [0.0, 1.0, 0.0] })
+        .collect()
+}
+
+/// Unit vector at longitude `phi` and polar angle `theta` from `+Y`.
+fn spherical(phi: f32, theta: f32) -> [f32; 3] {
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    [sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let len = dot(a, a).sqrt();
+    if len > 0.0 { scale(a, 1.0 / len) } else { a }
+}
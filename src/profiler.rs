@@ -0,0 +1,157 @@
+// profiler.rs
+//! GPU timestamp profiler for passes recorded by the crate.
+//!
+//! Uses `wgpu` timestamp queries written at the beginning and end of each pass.
+//! Results are resolved at the end of a frame and read back asynchronously,
+//! so they become available a few frames later without ever stalling the GPU.
+use wgpu::*;
+use crate::queries::QueryRing;
+
+/// GPU duration of a single profiled pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuPassTiming {
+    /// Label the pass was recorded with.
+    pub label: String,
+    /// GPU time spent between the beginning and end of the pass, in milliseconds.
+    pub duration_ms: f64,
+}
+
+/// Timestamp query indices reserved for a single pass.
+///
+/// Obtained from [`GpuProfiler::begin_pass`]. Convert it into the matching
+/// `wgpu` descriptor field with [`compute_writes`](Self::compute_writes)
+/// or [`render_writes`](Self::render_writes).
+#[derive(Debug, Clone)]
+pub struct PassTimestamps {
+    query_set: QuerySet,
+    begin: u32,
+    end: u32,
+}
+
+impl PassTimestamps {
+    /// Timestamp writes for `ComputePassDescriptor::timestamp_writes`.
+    pub fn compute_writes(&self) -> ComputePassTimestampWrites<'_> {
+        ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(self.begin),
+            end_of_pass_write_index: Some(self.end),
+        }
+    }
+
+    /// Timestamp writes for `RenderPassDescriptor::timestamp_writes`.
+    pub fn render_writes(&self) -> RenderPassTimestampWrites<'_> {
+        RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(self.begin),
+            end_of_pass_write_index: Some(self.end),
+        }
+    }
+}
+
+/// Built-in GPU profiler based on timestamp queries.
+///
+/// The profiler keeps a small ring of query sets so that several frames can be
+/// in flight at once. Each frame:
+/// 1. [`begin_frame`](Self::begin_frame) collects finished results and picks a free slot
+/// 2. [`begin_pass`](Self::begin_pass) reserves two timestamps per pass
+/// 3. [`resolve`](Self::resolve) copies the queries into a readback buffer,
+///    which is mapped automatically once the encoder is submitted
+///
+/// Results of a frame become available through [`results`](Self::results)
+/// typically 1-3 frames after it was recorded.
+///
+/// ## Requirements
+/// The device must be created with [`Features::TIMESTAMP_QUERY`].
+/// [`GpuProfiler::new`] returns `None` otherwise.
+///
+/// ## Managed passes
+/// When enabled through [`RenderManager::enable_gpu_profiler`](crate::renderer::RenderManager::enable_gpu_profiler),
+/// compute dispatches and procedural texture generation passes are profiled automatically.
+/// Your own passes can be profiled by passing [`PassTimestamps::render_writes`] into
+/// your `RenderPassDescriptor`.
+pub struct GpuProfiler {
+    ring: QueryRing,
+    timestamp_period: f32,
+    latest: Vec<GpuPassTiming>,
+    latest_frame: Option<u64>,
+}
+
+impl GpuProfiler {
+    /// Create a new profiler able to time up to `max_passes` passes per frame.
+    ///
+    /// Returns `None` if the device was not created with [`Features::TIMESTAMP_QUERY`].
+    pub fn new(device: &Device, queue: &Queue, max_passes: u32) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        Some(Self {
+            ring: QueryRing::new(device, "gpu profiler", QueryType::Timestamp, max_passes, 2, 1),
+            timestamp_period: queue.get_timestamp_period(),
+            latest: Vec::new(),
+            latest_frame: None,
+        })
+    }
+
+    /// Start profiling a new frame.
+    ///
+    /// Collects results of previously resolved frames that finished on the GPU.
+    /// If all slots are still in flight, this frame is not profiled and
+    /// [`begin_pass`](Self::begin_pass) returns `None` until the next frame.
+    pub fn begin_frame(&mut self, frame_index: u64) {
+        // Frames come back oldest-first, so `latest` ends up holding the newest one.
+        for frame in self.ring.begin_frame(frame_index) {
+            self.latest = frame
+                .labels
+                .into_iter()
+                .enumerate()
+                .map(|(pass, label)| {
+                    let ticks = frame.values[pass * 2 + 1].saturating_sub(frame.values[pass * 2]);
+                    GpuPassTiming {
+                        label,
+                        duration_ms: ticks as f64 * self.timestamp_period as f64 / 1_000_000.0,
+                    }
+                })
+                .collect();
+            self.latest_frame = Some(frame.frame_index);
+        }
+    }
+
+    /// Reserve timestamps for a pass labelled `label`.
+    ///
+    /// Returns `None` if the frame is not being profiled or the
+    /// per-frame pass budget is exhausted.
+    pub fn begin_pass(&mut self, label: &str) -> Option<PassTimestamps> {
+        let (query_set, first) = self.ring.reserve(label)?;
+        Some(PassTimestamps {
+            query_set,
+            begin: first,
+            end: first + 1,
+        })
+    }
+
+    /// Resolve this frame's queries into the readback buffer.
+    ///
+    /// Must be recorded into an encoder that is submitted **after** every
+    /// profiled pass of the frame. The readback buffer is mapped automatically
+    /// once that encoder is submitted.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        self.ring.resolve(encoder);
+    }
+
+    /// Timings of the most recently completed frame.
+    ///
+    /// Empty until the first profiled frame finished on the GPU.
+    pub fn results(&self) -> &[GpuPassTiming] {
+        &self.latest
+    }
+
+    /// Frame index the values in [`results`](Self::results) belong to.
+    pub fn results_frame(&self) -> Option<u64> {
+        self.latest_frame
+    }
+
+    /// Maximum number of passes that can be profiled per frame.
+    pub fn max_passes(&self) -> u32 {
+        self.ring.reservations_per_frame()
+    }
+}
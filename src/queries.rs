@@ -0,0 +1,193 @@
+// queries.rs
+//! Ring of query sets with asynchronous readback, shared by the GPU profiler
+//! and the pipeline statistics collector.
+//!
+//! Each frame gets its own query set, resolve buffer and readback buffer so that
+//! several frames can be in flight. Readback buffers are mapped automatically on
+//! submission of the resolving encoder and collected at the next frame start.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use wgpu::*;
+use crate::labels::namespaced;
+
+/// Number of frames that may be in flight before a ring starts skipping frames.
+const QUERY_FRAMES_IN_FLIGHT: usize = 3;
+
+const SLOT_IDLE: u8 = 0;
+const SLOT_PENDING: u8 = 1;
+const SLOT_MAPPED: u8 = 2;
+const SLOT_FAILED: u8 = 3;
+
+struct QuerySlot {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Buffer,
+    /// One label per reservation, in reservation order.
+    labels: Vec<String>,
+    used_queries: u32,
+    frame_index: u64,
+    state: Arc<AtomicU8>,
+}
+
+/// Results read back for a single frame.
+pub(crate) struct QueryFrame {
+    pub(crate) frame_index: u64,
+    /// One label per reservation, in reservation order.
+    pub(crate) labels: Vec<String>,
+    /// Raw query values, `values_per_query` values per query.
+    pub(crate) values: Vec<u64>,
+}
+
+pub(crate) struct QueryRing {
+    device: Device,
+    slots: Vec<QuerySlot>,
+    queries_per_frame: u32,
+    queries_per_reservation: u32,
+    values_per_query: u32,
+    current: Option<usize>,
+    next_slot: usize,
+}
+
+impl QueryRing {
+    /// `queries_per_reservation` queries are handed out per [`reserve`](Self::reserve) call,
+    /// each query resolves to `values_per_query` `u64` values.
+    pub(crate) fn new(
+        device: &Device,
+        label: &str,
+        ty: QueryType,
+        reservations_per_frame: u32,
+        queries_per_reservation: u32,
+        values_per_query: u32,
+    ) -> Self {
+        let queries_per_frame = reservations_per_frame.max(1) * queries_per_reservation;
+        let buffer_size = queries_per_frame as u64 * values_per_query as u64 * QUERY_SIZE as u64;
+
+        let slots = (0..QUERY_FRAMES_IN_FLIGHT)
+            .map(|i| QuerySlot {
+                query_set: device.create_query_set(&QuerySetDescriptor {
+                    label: Some(&namespaced(&format!("{} queries {}", label, i))),
+                    ty,
+                    count: queries_per_frame,
+                }),
+                resolve_buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some(&namespaced(&format!("{} resolve {}", label, i))),
+                    size: buffer_size,
+                    usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                readback_buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some(&namespaced(&format!("{} readback {}", label, i))),
+                    size: buffer_size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                labels: Vec::new(),
+                used_queries: 0,
+                frame_index: 0,
+                state: Arc::new(AtomicU8::new(SLOT_IDLE)),
+            })
+            .collect();
+
+        Self {
+            device: device.clone(),
+            slots,
+            queries_per_frame,
+            queries_per_reservation,
+            values_per_query,
+            current: None,
+            next_slot: 0,
+        }
+    }
+
+    /// Maximum number of reservations per frame.
+    pub(crate) fn reservations_per_frame(&self) -> u32 {
+        self.queries_per_frame / self.queries_per_reservation
+    }
+
+    /// Collect finished frames (oldest first) and pick a free slot for `frame_index`.
+    ///
+    /// If every slot is still in flight, reservations fail until the next frame.
+    pub(crate) fn begin_frame(&mut self, frame_index: u64) -> Vec<QueryFrame> {
+        let finished = self.collect();
+
+        let slot = &mut self.slots[self.next_slot];
+        if slot.state.load(Ordering::Acquire) == SLOT_IDLE {
+            slot.labels.clear();
+            slot.used_queries = 0;
+            slot.frame_index = frame_index;
+            self.current = Some(self.next_slot);
+            self.next_slot = (self.next_slot + 1) % self.slots.len();
+        } else {
+            self.current = None;
+        }
+        finished
+    }
+
+    /// Reserve the next `queries_per_reservation` queries of this frame.
+    ///
+    /// Returns the query set and the index of the first reserved query.
+    pub(crate) fn reserve(&mut self, label: &str) -> Option<(QuerySet, u32)> {
+        let slot = &mut self.slots[self.current?];
+        if slot.used_queries + self.queries_per_reservation > self.queries_per_frame {
+            return None;
+        }
+        let first = slot.used_queries;
+        slot.used_queries += self.queries_per_reservation;
+        slot.labels.push(label.to_string());
+        Some((slot.query_set.clone(), first))
+    }
+
+    /// Resolve this frame's queries and schedule the readback for when `encoder` is submitted.
+    pub(crate) fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let Some(slot_index) = self.current.take() else {
+            return;
+        };
+        let slot = &self.slots[slot_index];
+        if slot.used_queries == 0 {
+            return;
+        }
+
+        let byte_count = slot.used_queries as u64 * self.values_per_query as u64 * QUERY_SIZE as u64;
+        encoder.resolve_query_set(&slot.query_set, 0..slot.used_queries, &slot.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&slot.resolve_buffer, 0, &slot.readback_buffer, 0, byte_count);
+
+        slot.state.store(SLOT_PENDING, Ordering::Release);
+        let state = slot.state.clone();
+        encoder.map_buffer_on_submit(&slot.readback_buffer, MapMode::Read, 0..byte_count, move |result| {
+            let value = if result.is_ok() { SLOT_MAPPED } else { SLOT_FAILED };
+            state.store(value, Ordering::Release);
+        });
+    }
+
+    fn collect(&mut self) -> Vec<QueryFrame> {
+        let _ = self.device.poll(PollType::Poll);
+
+        let mut finished: Vec<usize> = (0..self.slots.len())
+            .filter(|&i| {
+                let state = self.slots[i].state.load(Ordering::Acquire);
+                state == SLOT_MAPPED || state == SLOT_FAILED
+            })
+            .collect();
+        finished.sort_by_key(|&i| self.slots[i].frame_index);
+
+        let mut frames = Vec::with_capacity(finished.len());
+        for i in finished {
+            let slot = &mut self.slots[i];
+            if slot.state.load(Ordering::Acquire) == SLOT_MAPPED {
+                let byte_count = slot.used_queries as u64 * self.values_per_query as u64 * QUERY_SIZE as u64;
+                let values = {
+                    let data = slot.readback_buffer.get_mapped_range(0..byte_count);
+                    bytemuck::cast_slice::<u8, u64>(&data).to_vec()
+                };
+                slot.readback_buffer.unmap();
+                frames.push(QueryFrame {
+                    frame_index: slot.frame_index,
+                    labels: std::mem::take(&mut slot.labels),
+                    values,
+                });
+            }
+            slot.state.store(SLOT_IDLE, Ordering::Release);
+        }
+        frames
+    }
+}
@@ -0,0 +1,263 @@
+//! Asynchronous texture readback.
+//!
+//! A [`TextureReadback`] copies whole textures into mappable buffers when the copying
+//! encoder is submitted and hands the pixels back from a later [`poll`](TextureReadback::poll),
+//! without stalling the frame. At most `max_in_flight` copies are pending at a time;
+//! further [`copy`](TextureReadback::copy) calls are refused until older ones finish,
+//! which throttles readback to what the GPU and bus keep up with. Buffers of finished
+//! copies are reused.
+//! ```ignore
+//! let mut readback = TextureReadback::new(&device, 3);
+//! // After rendering into `texture`
+//! readback.copy(&mut encoder, &texture);
+//! queue.submit([encoder.finish()]);
+//! // Later, e.g. next frame
+//! for image in readback.poll() {
+//!     save(image.width, image.height, &image.data);
+//! }
+//! ```
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use wgpu::*;
+use crate::labels::namespaced;
+
+const READBACK_PENDING: u8 = 0;
+const READBACK_MAPPED: u8 = 1;
+const READBACK_FAILED: u8 = 2;
+
+/// Pixels of a texture read back by a [`TextureReadback`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadbackImage {
+    /// Value returned by the [`copy`](TextureReadback::copy) that produced this image.
+    pub id: u64,
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    /// Tightly packed rows, top to bottom, in the texture's format.
+    pub data: Vec<u8>,
+}
+
+impl ReadbackImage {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        self.format.block_copy_size(None).unwrap_or(0)
+    }
+
+    /// Bytes of the pixel at `x, y`, or `None` outside the image.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<&[u8]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let size = self.bytes_per_pixel() as usize;
+        let start = (y as usize * self.width as usize + x as usize) * size;
+        self.data.get(start..start + size)
+    }
+
+    /// The pixels as 8-bit sRGB RGBA, e.g. for writing PNGs.
+    ///
+    /// Supports `Rgba8`/`Bgra8` formats as they are and `Rgba16Float` as linear
+    /// (scRGB) color, clamped to SDR; `None` for other formats.
+    pub fn to_rgba8(&self) -> Option<Vec<u8>> {
+        match self.format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(self.data.clone()),
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+                Some(self.data.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect())
+            }
+            TextureFormat::Rgba16Float => Some(
+                self.data
+                    .chunks_exact(8)
+                    .flat_map(|p| {
+                        let channel = |i: usize| f16_to_f32(u16::from_le_bytes([p[2 * i], p[2 * i + 1]]));
+                        let srgb = |linear: f32| (linear_to_srgb(linear.clamp(0.0, 1.0)) * 255.0 + 0.5) as u8;
+                        let alpha = (channel(3).clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+                        [srgb(channel(0)), srgb(channel(1)), srgb(channel(2)), alpha]
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+struct ReadbackSlot {
+    buffer: Buffer,
+    id: u64,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    padded_bytes_per_row: u32,
+    state: Arc<AtomicU8>,
+}
+
+/// Throttled ring of texture-to-buffer copies with asynchronous mapping.
+pub struct TextureReadback {
+    device: Device,
+    max_in_flight: usize,
+    in_flight: VecDeque<ReadbackSlot>,
+    free: Vec<Buffer>,
+    next_id: u64,
+}
+
+impl TextureReadback {
+    pub fn new(device: &Device, max_in_flight: usize) -> Self {
+        Self {
+            device: device.clone(),
+            max_in_flight: max_in_flight.max(1),
+            in_flight: VecDeque::new(),
+            free: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Copy mip 0 of `texture` for readback when `encoder` is submitted. Record after the
+    /// texture was written; it needs `COPY_SRC` usage.
+    ///
+    /// Returns the id of the resulting [`ReadbackImage`], or `None` if `max_in_flight`
+    /// copies are still pending or the format cannot be copied as a whole (depth-stencil,
+    /// compressed formats).
+    pub fn copy(&mut self, encoder: &mut CommandEncoder, texture: &Texture) -> Option<u64> {
+        if self.is_full() {
+            return None;
+        }
+        let format = texture.format();
+        let bytes_per_pixel = format.block_copy_size(None)?;
+        if format.block_dimensions() != (1, 1) {
+            return None;
+        }
+        let (width, height) = (texture.width(), texture.height());
+        let padded_bytes_per_row = (width * bytes_per_pixel).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let size = padded_bytes_per_row as u64 * height as u64;
+
+        let buffer = match self.free.iter().position(|buffer| buffer.size() >= size) {
+            Some(index) => self.free.swap_remove(index),
+            None => {
+                trace_event!(width, height, "created readback buffer");
+                self.device.create_buffer(&BufferDescriptor {
+                    label: Some(&namespaced("texture readback")),
+                    size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            }
+        };
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+
+        let state = Arc::new(AtomicU8::new(READBACK_PENDING));
+        let mapped = state.clone();
+        encoder.map_buffer_on_submit(&buffer, MapMode::Read, 0..size, move |result| {
+            let value = if result.is_ok() { READBACK_MAPPED } else { READBACK_FAILED };
+            mapped.store(value, Ordering::Release);
+        });
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.in_flight.push_back(ReadbackSlot {
+            buffer,
+            id,
+            width,
+            height,
+            format,
+            padded_bytes_per_row,
+            state,
+        });
+        Some(id)
+    }
+
+    /// Images of every finished copy, oldest first. Failed copies are dropped.
+    pub fn poll(&mut self) -> Vec<ReadbackImage> {
+        if self.in_flight.is_empty() {
+            return Vec::new();
+        }
+        let _ = self.device.poll(PollType::Poll);
+        self.collect()
+    }
+
+    /// Block until every pending copy finished and return their images.
+    ///
+    /// Only finishes copies whose encoder was submitted.
+    pub fn wait(&mut self) -> Vec<ReadbackImage> {
+        if self.in_flight.is_empty() {
+            return Vec::new();
+        }
+        let _ = self.device.poll(PollType::wait_indefinitely());
+        self.collect()
+    }
+
+    /// Number of copies waiting for their readback.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Whether [`copy`](Self::copy) currently refuses new copies.
+    pub fn is_full(&self) -> bool {
+        self.in_flight.len() >= self.max_in_flight
+    }
+
+    fn collect(&mut self) -> Vec<ReadbackImage> {
+        let mut images = Vec::new();
+        while let Some(slot) = self.in_flight.front() {
+            let state = slot.state.load(Ordering::Acquire);
+            if state == READBACK_PENDING {
+                break;
+            }
+            let slot = self.in_flight.pop_front().expect("front was checked");
+            if state == READBACK_FAILED {
+                trace_event!(id = slot.id, "texture readback failed");
+                continue;
+            }
+
+            let row = (slot.width * slot.format.block_copy_size(None).unwrap_or(0)) as usize;
+            let size = slot.padded_bytes_per_row as u64 * slot.height as u64;
+            let mut data = Vec::with_capacity(row * slot.height as usize);
+            {
+                let mapped = slot.buffer.get_mapped_range(0..size);
+                for padded in mapped.chunks(slot.padded_bytes_per_row as usize) {
+                    data.extend_from_slice(&padded[..row]);
+                }
+            }
+            slot.buffer.unmap();
+            if self.free.len() < self.max_in_flight {
+                self.free.push(slot.buffer);
+            }
+            images.push(ReadbackImage {
+                id: slot.id,
+                width: slot.width,
+                height: slot.height,
+                format: slot.format,
+                data,
+            });
+        }
+        images
+    }
+}
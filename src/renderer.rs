@@ -1,14 +1,44 @@
 use std::collections::{HashMap};
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::{Path, PathBuf};
-use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Queue, RenderPass, TextureView};
-use crate::bind_groups::{LayoutKey, MaterialBindGroups};
+use std::sync::Arc;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandBuffer, CommandEncoder, Device, Features, Queue, RenderPass, TextureView};
+use crate::bind_group_builder::{BindGroupBuilder, BuiltBindGroup};
+use crate::bind_groups::MaterialBindGroups;
+use crate::cache_key::{CacheKey, ErasedKey};
+use crate::concurrent::SharedMaterialBindGroups;
+use crate::debug_modes::DebugRenderMode;
+use crate::compute_bind_groups::{validate_buffer_sizes, ComputeBindGroups, ComputeBinding};
 use crate::compute_system::{BufferSet, ComputePipelineOptions, ComputeSystem};
+use crate::diagnostics::{entry_id, evict_by_id, CacheEntryInfo, CacheKind, StaleEntryCallback, StaleEntryConfig, StaleEntryDetector, Tracked};
+use crate::error::CrmError;
 use crate::fullscreen::{DebugVisualization, DepthDebugParams, FullscreenRenderer};
 use crate::generator::{TextureGenerator, TextureKey};
-use crate::pipelines::{PipelineCache, PipelineOptions};
+use crate::handles::{BindGroupHandles, BindGroupId, MaterialRequest};
+use crate::hot_reload::{AssetKind, ReloadEvent, ReloadStage};
+use crate::multi_device::DeviceId;
+use crate::pipeline_stats::PipelineStatistics;
+use crate::pipelines::{PipelineCache, PipelineOptions, TextureAccess};
+use crate::profiler::GpuProfiler;
+use crate::submission::{SubmissionManager, SubmissionTicket};
+use crate::textures::{try_create_texture, LoadedTexture, TextureRequest};
+use crate::typed_bind_group::TypedBindGroup;
+#[cfg(feature = "native")]
+use crate::workers::ResourceWorkers;
 
-#[derive(Clone, Hash, PartialEq, Eq)]
+/// Whether `path` lies in `dir`, comparing canonical paths if the plain ones differ,
+/// so a relative path matches an absolute directory and the other way around.
+fn is_within(path: &Path, dir: &Path) -> bool {
+    if path.starts_with(dir) {
+        return true;
+    }
+    match (path.canonicalize(), dir.canonicalize()) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 struct UniformBindGroupKey(u64);
 
 impl UniformBindGroupKey {
@@ -55,9 +85,50 @@ pub struct RenderManager {
     pipeline_cache: PipelineCache,
     fullscreen: FullscreenRenderer,
     materials: MaterialBindGroups,
+    shared_materials: Arc<SharedMaterialBindGroups>,
     compute_system: ComputeSystem,
-    uniform_bind_groups: HashMap<UniformBindGroupKey, BindGroup>,
+    builder_bind_groups: ComputeBindGroups,
+    uniform_bind_groups: HashMap<UniformBindGroupKey, Tracked<BindGroup>>,
+    staged_uniform_bind_groups: HashMap<UniformBindGroupKey, Tracked<BindGroup>>,
+    handles: BindGroupHandles,
+    staging: Option<StagedMutations>,
     defines: HashMap<String, bool>,
+    profiler: Option<GpuProfiler>,
+    statistics: Option<PipelineStatistics>,
+    frame_index: u64,
+    capture: FrameCaptureState,
+    stale_detector: Option<StaleEntryDetector>,
+    submissions: SubmissionManager,
+    batch_submissions: bool,
+    /// Textures uploaded through the manager, with the CPU data to upload them again in
+    /// [`recreate`](Self::recreate).
+    textures: HashMap<String, (TextureRequest, LoadedTexture)>,
+}
+
+/// A bind group of a built-in subsystem bound after the material and uniform groups.
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+pub(crate) struct ExtraBindGroup<'a> {
+    /// Group name used in layout validation reports.
+    pub(crate) name: &'a str,
+    pub(crate) entries: &'a [wgpu::BindGroupLayoutEntry],
+    pub(crate) layout: &'a BindGroupLayout,
+    pub(crate) bind_group: &'a BindGroup,
+}
+
+/// Cache mutations requested while deferred mutations are enabled, applied in `begin_frame`.
+#[derive(Default)]
+struct StagedMutations {
+    evictions: Vec<(CacheKind, u64)>,
+    invalidate_bind_groups: bool,
+    clear_all: bool,
+}
+
+/// Progress of a programmatic graphics debugger capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameCaptureState {
+    Idle,
+    Requested,
+    Capturing,
 }
 
 impl RenderManager {
@@ -83,9 +154,22 @@ impl RenderManager {
             pipeline_cache,
             fullscreen,
             materials,
+            shared_materials: Arc::new(SharedMaterialBindGroups::new(device.clone())),
             compute_system,
+            builder_bind_groups: ComputeBindGroups::with_kinds(device.clone(), CacheKind::BuilderLayout, CacheKind::BuilderBindGroup),
             uniform_bind_groups: HashMap::new(),
+            staged_uniform_bind_groups: HashMap::new(),
+            handles: BindGroupHandles::new(),
+            staging: None,
             defines: HashMap::new(),
+            profiler: None,
+            statistics: None,
+            frame_index: 0,
+            capture: FrameCaptureState::Idle,
+            stale_detector: None,
+            submissions: SubmissionManager::new(device, queue),
+            batch_submissions: false,
+            textures: HashMap::new(),
         }
     }
 
@@ -94,11 +178,69 @@ impl RenderManager {
         &self.device
     }
 
+    /// Identifies the device of this manager, see [`DeviceBound`](crate::multi_device::DeviceBound).
+    pub fn device_id(&self) -> DeviceId {
+        DeviceId::of(&self.device)
+    }
+
     /// Returns a reference to the underlying `wgpu::Queue`.
     pub fn queue(&self) -> &Queue {
         &self.queue
     }
 
+    /// Thread-safe material bind group cache for parallel encoding.
+    ///
+    /// Produces the same bind groups as [`render_with_textures`](Self::render_with_textures)
+    /// but can be used from several threads at once through `&self`. The returned `Arc`
+    /// can be cloned into worker threads; its entries show up in
+    /// [`cache_entries`](Self::cache_entries) and are cleared by
+    /// [`invalidate_bind_groups`](Self::invalidate_bind_groups).
+    pub fn shared_materials(&self) -> Arc<SharedMaterialBindGroups> {
+        self.shared_materials.clone()
+    }
+
+    /// Spawn `threads` background workers for creating resources off the render thread.
+    ///
+    /// The workers share the device, queue, current shader defines and the
+    /// [`shared_materials`](Self::shared_materials) cache with this manager.
+    /// Requests return [`Pending`](crate::workers::Pending) handles that resolve once ready.
+    #[cfg(feature = "native")]
+    pub fn spawn_resource_workers(&self, threads: usize) -> ResourceWorkers {
+        let mut workers = ResourceWorkers::new(&self.device, &self.queue, self.shared_materials.clone(), threads);
+        workers.set_defines(self.defines.clone());
+        workers
+    }
+
+    /// Create the texture described by `request` and keep its CPU data, so
+    /// [`recreate`](Self::recreate) uploads it again on a new device. The texture is
+    /// looked up by its label, a request with a label already in use replaces that texture.
+    ///
+    /// # Panics
+    /// If the device cannot create the texture, see [`try_upload_texture`](Self::try_upload_texture).
+    pub fn upload_texture(&mut self, request: TextureRequest) -> &LoadedTexture {
+        let label = request.label.clone();
+        self.try_upload_texture(request).unwrap_or_else(|e| panic!("{}: {}", label, e))
+    }
+
+    /// [`upload_texture`](Self::upload_texture), failing like
+    /// [`try_create_texture`](crate::textures::try_create_texture).
+    pub fn try_upload_texture(&mut self, request: TextureRequest) -> Result<&LoadedTexture, CrmError> {
+        let texture = try_create_texture(&self.device, &self.queue, &request)?;
+        let label = request.label.clone();
+        self.textures.insert(label.clone(), (request, texture));
+        Ok(&self.textures[&label].1)
+    }
+
+    /// The texture uploaded with `label` through [`upload_texture`](Self::upload_texture).
+    pub fn texture(&self, label: &str) -> Option<&LoadedTexture> {
+        self.textures.get(label).map(|(_, texture)| texture)
+    }
+
+    /// Drop the texture uploaded with `label` and its CPU data. Returns the texture, if any.
+    pub fn release_texture(&mut self, label: &str) -> Option<LoadedTexture> {
+        self.textures.remove(label).map(|(_, texture)| texture)
+    }
+
     /// Access the procedural texture generator.
     ///
     /// This allows manual creation, inspection, or reuse of generated
@@ -131,6 +273,422 @@ impl RenderManager {
         &mut self.compute_system
     }
 
+    /// Index of the current frame, advanced by [`begin_frame`](Self::begin_frame).
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    /// Mark the start of a new frame.
+    ///
+    /// Call this once per frame before recording any work through the manager.
+    /// Frame boundaries are used by the GPU profiler and pipeline statistics to collect finished results.
+    pub fn begin_frame(&mut self) {
+        self.advance_capture();
+        self.apply_staged_mutations();
+        self.frame_index += 1;
+        self.propagate_frame();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin_frame(self.frame_index);
+        }
+        if let Some(statistics) = &mut self.statistics {
+            statistics.begin_frame(self.frame_index);
+        }
+        if self.stale_detector.as_ref().is_some_and(|d| d.is_due(self.frame_index)) {
+            let entries = self.cache_entries();
+            if let Some(detector) = &mut self.stale_detector {
+                detector.check(self.frame_index, entries);
+            }
+        }
+    }
+
+    fn propagate_frame(&mut self) {
+        self.generator.set_frame(self.frame_index);
+        self.pipeline_cache.set_frame(self.frame_index);
+        self.fullscreen.set_frame(self.frame_index);
+        self.materials.set_frame(self.frame_index);
+        self.shared_materials.set_frame(self.frame_index);
+        self.compute_system.set_frame(self.frame_index);
+        self.builder_bind_groups.set_frame(self.frame_index);
+        self.handles.set_frame(self.frame_index);
+    }
+
+    /// Rebuild every managed resource on a new device, e.g. after a device loss.
+    ///
+    /// All caches are recreated on `device`: procedural textures are regenerated
+    /// immediately from their [`TextureKey`]s and textures of
+    /// [`upload_texture`](Self::upload_texture) are uploaded again from their CPU data,
+    /// while pipelines, layouts and bind groups are recreated lazily on their next use.
+    /// Shader defines, the frame counter, deferred mutation and submission batching
+    /// modes, the stale entry detector and the GPU profiler / pipeline statistics (if the
+    /// new device supports them) carry over.
+    ///
+    /// Look uploaded textures up again with [`texture`](Self::texture), the views held
+    /// before belong to the old device. Call `AssetServer::recreate` to load assets
+    /// again. Bind groups also reference your own texture views and buffers, so recreate
+    /// those on the new device before rendering again. Handles obtained from
+    /// [`shared_materials`](Self::shared_materials) still point at the old device;
+    /// request them again. Pending submissions of the old device are dropped.
+    ///
+    /// # Panics
+    /// If an uploaded texture cannot be created on the new device, see
+    /// [`try_recreate`](Self::try_recreate).
+    ///
+    /// ## Example
+    /// ```ignore
+    /// device.set_device_lost_callback(move |_, _| lost.store(true, Ordering::Relaxed));
+    /// // later, on the render thread
+    /// if lost.load(Ordering::Relaxed) {
+    ///     let (device, queue) = request_new_device();
+    ///     render_manager.recreate(&device, &queue);
+    /// }
+    /// ```
+    pub fn recreate(&mut self, device: &Device, queue: &Queue) {
+        self.try_recreate(device, queue).unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`recreate`](Self::recreate), failing with the error of the first uploaded texture
+    /// the new device cannot create, e.g. a BCn texture on a device without
+    /// `TEXTURE_COMPRESSION_BC`. Everything else is recreated and the remaining textures
+    /// are uploaded regardless, the failed ones are dropped.
+    pub fn try_recreate(&mut self, device: &Device, queue: &Queue) -> Result<(), CrmError> {
+        let _span = trace_span!("recreate_render_manager");
+        let textures = std::mem::take(&mut self.textures);
+        let texture_keys = self.generator.cached_keys();
+        let profiler_passes = self.profiler.as_ref().map(GpuProfiler::max_passes);
+        let statistics_scopes = self.statistics.as_ref().map(PipelineStatistics::max_scopes);
+        let deferred = self.staging.is_some();
+        let batching = self.batch_submissions;
+
+        let mut fresh = RenderManager::new(device, queue, self.generator.shader_dir().clone());
+        fresh.defines = std::mem::take(&mut self.defines);
+        fresh.pipeline_cache.set_debug_mode(self.pipeline_cache.debug_mode());
+        fresh.frame_index = self.frame_index;
+        fresh.stale_detector = self.stale_detector.take();
+        // The fresh handle table has a new owner, ids of old-device bind groups stop resolving.
+        *self = fresh;
+
+        self.propagate_frame();
+        self.set_deferred_cache_mutations(deferred);
+        self.set_submission_batching(batching);
+        if let Some(max_passes) = profiler_passes {
+            self.enable_gpu_profiler(max_passes);
+        }
+        if let Some(max_scopes) = statistics_scopes {
+            self.enable_pipeline_statistics(max_scopes);
+        }
+        trace_event!(textures = texture_keys.len(), "regenerating procedural textures");
+        for key in &texture_keys {
+            self.generator.get_or_create(key);
+        }
+        trace_event!(textures = textures.len(), "uploading retained textures");
+        let mut result = Ok(());
+        for (_, (request, _)) in textures {
+            if let Err(e) = self.try_upload_texture(request) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Mark the end of the current frame.
+    ///
+    /// `encoder` must be submitted **after** every other encoder that recorded
+    /// work through the manager this frame, typically your last encoder of the frame.
+    /// GPU profiler and pipeline statistics queries are resolved into it.
+    pub fn end_frame(&mut self, encoder: &mut CommandEncoder) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.resolve(encoder);
+        }
+        if let Some(statistics) = &mut self.statistics {
+            statistics.resolve(encoder);
+        }
+    }
+
+    /// Capture the next frame in an attached graphics debugger.
+    ///
+    /// The capture starts at the next [`begin_frame`](Self::begin_frame) and stops at the
+    /// `begin_frame` after that, once the GPU has finished the captured frame, so
+    /// everything you record and submit in between ends up in the capture.
+    /// Use this to grab exactly the frame where a cached bind group misbehaves.
+    ///
+    /// Supported debuggers are the ones `wgpu` can drive at runtime:
+    /// RenderDoc (launch the app through RenderDoc) and Xcode's Metal capture.
+    /// Without an attached debugger this is a no-op. `wgpu` API traces cannot be
+    /// toggled at runtime; enable them through the `trace` feature and device
+    /// descriptor instead.
+    pub fn capture_next_frame(&mut self) {
+        if self.capture == FrameCaptureState::Idle {
+            self.capture = FrameCaptureState::Requested;
+        }
+    }
+
+    /// Returns `true` while a capture was requested or is in progress.
+    pub fn is_capturing(&self) -> bool {
+        self.capture != FrameCaptureState::Idle
+    }
+
+    fn advance_capture(&mut self) {
+        match self.capture {
+            FrameCaptureState::Idle => {}
+            FrameCaptureState::Requested => {
+                trace_event!(frame = self.frame_index + 1, "starting graphics debugger capture");
+                // SAFETY: the state machine guarantees no other capture started by us is active.
+                unsafe { self.device.start_graphics_debugger_capture() };
+                self.capture = FrameCaptureState::Capturing;
+            }
+            FrameCaptureState::Capturing => {
+                // Let the captured frame finish on the GPU before closing the capture.
+                let _ = self.device.poll(wgpu::PollType::wait_indefinitely());
+                // SAFETY: a capture was started by us in the previous `begin_frame`.
+                unsafe { self.device.stop_graphics_debugger_capture() };
+                self.capture = FrameCaptureState::Idle;
+            }
+        }
+    }
+
+    /// Batch the crate's own submissions into the [`SubmissionManager`].
+    ///
+    /// While enabled, procedural texture generation and compute dispatches without an
+    /// external encoder no longer call `Queue::submit` themselves. Their command buffers
+    /// are queued ahead of the next buffer passed to [`submit`](Self::submit) and sent
+    /// with it on [`flush_submissions`](Self::flush_submissions).
+    ///
+    /// Submit your own encoders through [`submit`](Self::submit) too, otherwise they may
+    /// reach the GPU before the textures they sample were generated.
+    /// Disabling submits everything that is still pending.
+    pub fn set_submission_batching(&mut self, enabled: bool) {
+        self.batch_submissions = enabled;
+        self.generator.set_batch_submissions(enabled);
+        self.compute_system.set_batch_submissions(enabled);
+        if !enabled {
+            self.flush_submissions();
+        }
+    }
+
+    /// Returns `true` if the crate's own submissions are batched.
+    pub fn submission_batching(&self) -> bool {
+        self.batch_submissions
+    }
+
+    /// Queue `buffer` for the next [`flush_submissions`](Self::flush_submissions).
+    ///
+    /// Internal work recorded before this call is queued ahead of it.
+    pub fn submit(&mut self, buffer: CommandBuffer) -> SubmissionTicket {
+        self.collect_internal_submissions();
+        self.submissions.push(buffer)
+    }
+
+    /// Submit everything queued so far in a single `Queue::submit` call.
+    pub fn flush_submissions(&mut self) -> Option<wgpu::SubmissionIndex> {
+        self.collect_internal_submissions();
+        self.submissions.flush()
+    }
+
+    /// Access the submission manager, e.g. to [`wait`](SubmissionManager::wait) on a ticket.
+    pub fn submissions(&mut self) -> &mut SubmissionManager {
+        &mut self.submissions
+    }
+
+    fn collect_internal_submissions(&mut self) {
+        for buffer in self.generator.take_command_buffers() {
+            self.submissions.push(buffer);
+        }
+        for buffer in self.compute_system.take_command_buffers() {
+            self.submissions.push(buffer);
+        }
+    }
+
+    /// Snapshot of every entry in every internal cache.
+    ///
+    /// Includes pipelines, layouts, bind groups and generated textures together with
+    /// the frame they were created and last used in, and their estimated memory.
+    /// Frames are counted by [`begin_frame`](Self::begin_frame).
+    pub fn cache_entries(&self) -> Vec<CacheEntryInfo> {
+        let mut entries = Vec::new();
+        self.pipeline_cache.collect_entries(&mut entries);
+        self.materials.collect_entries(&mut entries);
+        self.shared_materials.collect_entries(&mut entries);
+        for (key, entry) in self.uniform_bind_groups.iter().chain(&self.staged_uniform_bind_groups) {
+            entries.push(entry.info(CacheKind::UniformBindGroup, entry_id(key), format!("{:?}", key), 0));
+        }
+        self.compute_system.collect_entries(&mut entries);
+        self.builder_bind_groups.collect_entries(&mut entries);
+        self.generator.collect_entries(&mut entries);
+        self.fullscreen.collect_entries(&mut entries);
+        entries
+    }
+
+    /// Serialize the contents of every cache into pretty-printed JSON (feature `serde`).
+    ///
+    /// The dump contains the current frame, the active shader defines, per-cache totals
+    /// and every entry with its key description, label, estimated memory and the frames
+    /// it was created and last used in. Attach it to bug reports or diff two dumps to
+    /// see which resources appeared between them. It parses back into a
+    /// [`CacheStateDump`](crate::diagnostics::CacheStateDump).
+    #[cfg(feature = "serde")]
+    pub fn dump_state(&self) -> String {
+        let dump = crate::diagnostics::CacheStateDump::new(self.frame_index, &self.defines, self.cache_entries());
+        serde_json::to_string_pretty(&dump).expect("cache state dump is always serializable")
+    }
+
+    /// Evict a single cache entry, identified by [`CacheEntryInfo::kind`] and [`CacheEntryInfo::id`].
+    ///
+    /// The resource is recreated on its next use. Returns `true` if the entry existed.
+    /// With [deferred mutations](Self::set_deferred_cache_mutations) the entry is only
+    /// removed at the next [`begin_frame`](Self::begin_frame).
+    pub fn evict_cache_entry(&mut self, kind: CacheKind, id: u64) -> bool {
+        if let Some(staging) = &mut self.staging {
+            staging.evictions.push((kind, id));
+            return self.cache_entries().iter().any(|e| e.kind == kind && e.id == id);
+        }
+        self.evict_now(kind, id)
+    }
+
+    /// Stage cache insertions and evictions until the next frame boundary.
+    ///
+    /// While enabled, bind groups created during a frame are kept in a staging area,
+    /// and [`evict_cache_entry`](Self::evict_cache_entry),
+    /// [`invalidate_bind_groups`](Self::invalidate_bind_groups) and
+    /// [`clear_all`](Self::clear_all) are recorded instead of executed. Everything is
+    /// applied at the start of [`begin_frame`](Self::begin_frame), so the set of cached
+    /// bind groups the render thread sees never shrinks mid-frame, e.g. while a debug UI
+    /// evicts entries in between draws.
+    ///
+    /// Disabling applies all staged mutations immediately.
+    pub fn set_deferred_cache_mutations(&mut self, enabled: bool) {
+        self.materials.set_deferred(enabled);
+        self.compute_system.set_deferred(enabled);
+        self.builder_bind_groups.set_deferred(enabled);
+        if enabled {
+            self.staging.get_or_insert_with(StagedMutations::default);
+        } else {
+            self.apply_staged_mutations();
+            self.staging = None;
+        }
+    }
+
+    /// Returns `true` if cache mutations are staged until the next frame boundary.
+    pub fn deferred_cache_mutations(&self) -> bool {
+        self.staging.is_some()
+    }
+
+    fn apply_staged_mutations(&mut self) {
+        let Some(staged) = self.staging.as_mut().map(std::mem::take) else {
+            return;
+        };
+        if staged.clear_all {
+            self.clear_all_now();
+        } else if staged.invalidate_bind_groups {
+            self.invalidate_bind_groups_now();
+        }
+        self.materials.commit_staged();
+        self.compute_system.commit_staged();
+        self.builder_bind_groups.commit_staged();
+        self.uniform_bind_groups.extend(self.staged_uniform_bind_groups.drain());
+        for (kind, id) in staged.evictions {
+            self.evict_now(kind, id);
+        }
+    }
+
+    fn evict_now(&mut self, kind: CacheKind, id: u64) -> bool {
+        match kind {
+            CacheKind::RenderPipeline | CacheKind::UniformLayout => self.pipeline_cache.evict(kind, id),
+            CacheKind::MaterialLayout | CacheKind::MaterialBindGroup => {
+                self.materials.evict(kind, id) | self.shared_materials.evict(kind, id)
+            }
+            CacheKind::KeyedMaterialBindGroup => self.materials.evict(kind, id),
+            CacheKind::UniformBindGroup => {
+                evict_by_id(&mut self.uniform_bind_groups, id) | evict_by_id(&mut self.staged_uniform_bind_groups, id)
+            }
+            CacheKind::ComputePipeline | CacheKind::ComputeLayout | CacheKind::ComputeBindGroup => {
+                self.compute_system.evict(kind, id)
+            }
+            CacheKind::BuilderLayout | CacheKind::BuilderBindGroup => self.builder_bind_groups.evict(kind, id),
+            CacheKind::ProceduralTexture => self.generator.evict(kind, id),
+            CacheKind::FullscreenPipeline | CacheKind::FullscreenBindGroup => self.fullscreen.evict(kind, id),
+        }
+    }
+
+    /// Enable the stale cache entry detector.
+    ///
+    /// Every `config.check_interval` frames, all caches are scanned for entries that
+    /// were never requested again after being created, or that have not been used
+    /// for `config.idle_after` frames. Newly found entries are passed to `callback`;
+    /// each entry is reported once until it is used again.
+    ///
+    /// Entries that are created but never reused are the typical symptom of a cache
+    /// key built from something that changes every frame (e.g. a freshly created
+    /// texture view), which makes the cache grow without bound.
+    ///
+    /// Intended for debug builds. Frames are counted by [`begin_frame`](Self::begin_frame).
+    ///
+    /// ## Example
+    /// ```ignore
+    /// render_manager.enable_stale_entry_detection(StaleEntryConfig::default(), Box::new(|stale| {
+    ///     for s in stale {
+    ///         eprintln!("stale {}: {} ({:?})", s.entry.kind, s.entry.label, s.reason);
+    ///     }
+    /// }));
+    /// ```
+    pub fn enable_stale_entry_detection(&mut self, config: StaleEntryConfig, callback: StaleEntryCallback) {
+        self.stale_detector = Some(StaleEntryDetector::new(config, callback));
+    }
+
+    /// Disable the stale cache entry detector.
+    pub fn disable_stale_entry_detection(&mut self) {
+        self.stale_detector = None;
+    }
+
+    /// Enable the built-in GPU timestamp profiler.
+    ///
+    /// Compute dispatches and procedural texture generation passes recorded
+    /// through the manager are timed automatically, up to `max_passes` per frame.
+    /// Results are available a few frames later via [`gpu_profiler`](Self::gpu_profiler).
+    ///
+    /// Returns `false` if the device lacks [`wgpu::Features::TIMESTAMP_QUERY`].
+    pub fn enable_gpu_profiler(&mut self, max_passes: u32) -> bool {
+        self.profiler = GpuProfiler::new(&self.device, &self.queue, max_passes);
+        self.profiler.is_some()
+    }
+
+    /// Disable the GPU profiler and drop its query sets.
+    pub fn disable_gpu_profiler(&mut self) {
+        self.profiler = None;
+    }
+
+    /// Access the GPU profiler, if enabled.
+    ///
+    /// Use [`GpuProfiler::begin_pass`] to time your own passes as well.
+    pub fn gpu_profiler(&mut self) -> Option<&mut GpuProfiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Enable pipeline statistics queries.
+    ///
+    /// Compute dispatches, procedural texture generation passes and fullscreen debug
+    /// draws recorded through the manager are measured automatically, up to
+    /// `max_scopes` per frame, and aggregated per label. Results are available a few
+    /// frames later via [`pipeline_statistics`](Self::pipeline_statistics).
+    ///
+    /// Returns `false` if the device lacks [`wgpu::Features::PIPELINE_STATISTICS_QUERY`].
+    pub fn enable_pipeline_statistics(&mut self, max_scopes: u32) -> bool {
+        self.statistics = PipelineStatistics::new(&self.device, max_scopes);
+        self.statistics.is_some()
+    }
+
+    /// Disable pipeline statistics queries and drop their query sets.
+    pub fn disable_pipeline_statistics(&mut self) {
+        self.statistics = None;
+    }
+
+    /// Access the pipeline statistics collector, if enabled.
+    ///
+    /// Use [`PipelineStatistics::begin_render_scope`] around your own draws,
+    /// labelled by material or shader, to find overdraw hotspots.
+    pub fn pipeline_statistics(&mut self) -> Option<&mut PipelineStatistics> {
+        self.statistics.as_mut()
+    }
+
     /// Render using procedurally generated textures.
     ///
     /// This method resolves textures using the internal
@@ -164,7 +722,7 @@ impl RenderManager {
     /// compile-time preprocessing layer (`#ifdef`, `#include`) on top of
     /// standard WGSL before passing it to wgpu.
     /// ## Example
-    /// ```no_run
+    /// ```ignore
     /// // Inside a render pass
     /// render_manager.render_with_textures(
     ///     &texture_keys.as_slice(),  // Texture Keys
@@ -182,16 +740,31 @@ impl RenderManager {
         uniforms: &[&Buffer],
         pass: &mut RenderPass,
     ) {
+        self.try_render(texture_keys, shader_path, options, uniforms, pass)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`render`](Self::render), returning a [`CrmError`] instead of panicking when the
+    /// shader or a material layout cannot be created. Nothing is recorded into `pass`
+    /// on failure.
+    pub fn try_render(
+        &mut self,
+        texture_keys: &[TextureKey],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
         // Cloning TextureView is cheap — it's just a handle to the underlying GPU object.
         let mut owned_views: Vec<TextureView> = Vec::with_capacity(texture_keys.len());
         for key in texture_keys {
-            let v_ref = self.generator.get_or_create(key);
+            let v_ref = self.generator.get_or_create_profiled(key, self.profiler.as_mut(), self.statistics.as_mut())?;
             owned_views.push(v_ref.clone());
         }
 
         let view_refs: Vec<&TextureView> = owned_views.iter().collect();
 
-        self.render_with_textures(&view_refs, shader_path, options, uniforms, pass);
+        self.try_render_with_textures(&view_refs, shader_path, options, uniforms, pass)
     }
 
     /// Render using pre-existing texture views.
@@ -207,9 +780,16 @@ impl RenderManager {
     ///
     /// Like [`render`](Self::render), this does not issue a draw call.
     ///
+    /// ## Validation
+    /// In debug builds, the first use of each shader/layout combination checks the
+    /// material and uniform layouts against the bindings the shader actually uses and
+    /// panics with a readable per-binding diff on mismatch, instead of wgpu's
+    /// pipeline layout validation error.
+    ///
     /// ## Shader Binding layout
     /// - `@group(0) @binding(0)`: trilinear sampler
     /// - `@group(0) @binding(0..n)`: textures as texture_2d<f32> or texture_multisampled_2d<f32>
+    ///   (or texture_storage_2d for textures marked with `PipelineOptions::with_texture_access`)
     /// - `@group(0) @binding(n+1)`: (optional) shadow_sampler
     /// - `@group(0) @binding(n+2)`: (optional) shadow textures as texture_depth_2d_array
     /// - `@group(1) @binding(0..n)`: uniforms, in the same order as input
@@ -218,7 +798,7 @@ impl RenderManager {
     /// compile-time preprocessing layer (`#ifdef`, `#include`) on top of
     /// standard WGSL before passing it to wgpu.
     /// ## Example
-    /// ```no_run
+    /// ```ignore
     /// // Inside a render pass
     /// render_manager.render_with_textures(
     ///     &texture_views.as_slice(), // Texture Views
@@ -228,7 +808,6 @@ impl RenderManager {
     ///     &mut render_pass,          // Render Pass
     /// );
     /// ```
-
     pub fn render_with_textures(
         &mut self,
         texture_views: &[&TextureView],
@@ -237,56 +816,187 @@ impl RenderManager {
         uniforms: &[&Buffer],
         pass: &mut RenderPass,
     ) {
+        self.render_with_extra_groups(texture_views, shader_path, options, uniforms, &[], pass);
+    }
+
+    /// [`render_with_textures`](Self::render_with_textures), returning a [`CrmError`]
+    /// instead of panicking:
+    /// - [`CrmError::UnsupportedFormat`] if a view cannot be sampled (or used as a
+    ///   storage texture) on this device
+    /// - [`CrmError::Io`] or [`CrmError::Shader`] if the shader cannot be read or parsed
+    ///
+    /// Nothing is recorded into `pass` on failure, so the caller can fall back to
+    /// another shader or texture. Layout mismatches found by the debug-build
+    /// validation still panic, they are bugs rather than runtime conditions.
+    pub fn try_render_with_textures(
+        &mut self,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        self.try_render_with_extra_groups(texture_views, shader_path, options, uniforms, &[], pass)
+    }
+
+    /// [`render_with_textures`](Self::render_with_textures) with the material bind group
+    /// cached under `key` instead of the views, see [`cache_key`](crate::cache_key).
+    /// `texture_views` are only used when `key` misses.
+    pub fn render_with_material_key<K: CacheKey>(
+        &mut self,
+        key: &K,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) {
+        self.try_render_with_material_key(key, texture_views, shader_path, options, uniforms, pass)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`render_with_material_key`](Self::render_with_material_key), failing like
+    /// [`try_render_with_textures`](Self::try_render_with_textures).
+    pub fn try_render_with_material_key<K: CacheKey>(
+        &mut self,
+        key: &K,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        self.try_render_material(Some(key), texture_views, shader_path, options, uniforms, &[], pass)
+    }
+
+    /// [`render_with_textures`](Self::render_with_textures) with additional bind groups
+    /// from built-in subsystems (e.g. clustered lights) bound from `@group(2)` on.
+    ///
+    /// With extra groups, `@group(1)` is always bound, as an empty group if there are
+    /// no uniforms.
+    pub(crate) fn render_with_extra_groups(
+        &mut self,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        extra: &[ExtraBindGroup],
+        pass: &mut RenderPass,
+    ) {
+        self.try_render_with_extra_groups(texture_views, shader_path, options, uniforms, extra, pass)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    pub(crate) fn try_render_with_extra_groups(
+        &mut self,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        extra: &[ExtraBindGroup],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        self.try_render_material(None, texture_views, shader_path, options, uniforms, extra, pass)
+    }
+
+    /// Material bind group from `key` if given, from the views otherwise.
+    #[allow(clippy::too_many_arguments)]
+    fn try_render_material(
+        &mut self,
+        key: Option<&(dyn ErasedKey + 'static)>,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        extra: &[ExtraBindGroup],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
         // Shadow pulled explicitly from pipeline options
         let shadow = options.shadow.as_ref().map(|s| (&s.sampler, &s.view));
         let has_shadow = shadow.is_some();
 
+        // Keyed materials carry their layout, so hits never hash the views
+        let keyed = match key {
+            Some(key) => {
+                let material = self.materials.get_or_create_keyed(key, texture_views, &options.texture_access, shadow)?;
+                Some((material.layout.clone(), material.bind_group.clone()))
+            }
+            None => None,
+        };
+
         // Ensure material layout exists and clone handle
-        let _ = self.materials.layout(texture_views, has_shadow);
-        let material_layout_handle = self
-            .materials
-            .layouts
-            .get(&LayoutKey::from_views(texture_views, has_shadow))
-            .expect("material layout must exist")
-            .clone();
+        let material_layout_handle = match &keyed {
+            Some((layout, _)) => layout.clone(),
+            None => self.materials.layout(texture_views, &options.texture_access, has_shadow)?.clone(),
+        };
 
         // Uniform layout
         let uniform_count = uniforms.len();
-        let mut owned_bgls: Vec<BindGroupLayout> =
-            Vec::with_capacity(if uniform_count > 0 { 2 } else { 1 });
+        let bind_uniforms = uniform_count > 0 || !extra.is_empty();
+        let mut owned_bgls: Vec<BindGroupLayout> = Vec::with_capacity(2 + extra.len());
 
         owned_bgls.push(material_layout_handle);
 
-        if uniform_count > 0 {
-            let _ = self.pipeline_cache.uniform_layout(uniform_count);
-            let uniform_layout_handle = self
-                .pipeline_cache
-                .uniform_layouts
-                .get(&uniform_count)
-                .expect("uniform layout must exist")
-                .clone();
+        if bind_uniforms {
+            let uniform_layout_handle = self.pipeline_cache.uniform_layout(uniform_count).clone();
             owned_bgls.push(uniform_layout_handle);
         }
+        owned_bgls.extend(extra.iter().map(|group| group.layout.clone()));
 
         // Local references only
         let bind_group_layout_refs: Vec<&BindGroupLayout> = owned_bgls.iter().collect();
 
+        #[cfg(debug_assertions)]
+        {
+            // Keyed hits validate against the entries stored with the key, not the views
+            let material_entries = match key.and_then(|key| self.materials.keyed_entries(key)) {
+                Some(entries) => entries.to_vec(),
+                None => self.materials.layout_entries(texture_views, &options.texture_access, has_shadow)?.to_vec(),
+            };
+            let uniform_entries = crate::pipelines::uniform_layout_entries(uniform_count);
+            let mut groups: Vec<&[wgpu::BindGroupLayoutEntry]> = vec![&material_entries];
+            let mut group_names = vec![format!(
+                "material: {} texture(s){}",
+                texture_views.len(),
+                if has_shadow { " + shadow" } else { "" }
+            )];
+            if bind_uniforms {
+                groups.push(&uniform_entries);
+                group_names.push(format!("uniforms: {} buffer(s)", uniform_count));
+            }
+            for group in extra {
+                groups.push(group.entries);
+                group_names.push(group.name.to_string());
+            }
+            self.pipeline_cache.validate_layouts(shader_path, options, &self.defines, &groups, &group_names);
+        }
+
         // Pipeline
         let pipeline_ref = self
             .pipeline_cache
-            .get_or_create(shader_path, &bind_group_layout_refs, options, &self.defines);
+            .get_or_create(shader_path, &bind_group_layout_refs, options, &self.defines)?;
         let pipeline = pipeline_ref.clone();
         pass.set_pipeline(&pipeline);
 
         // Material bind group
-        let material_bg = self.materials.get_or_create(texture_views, shadow);
-        pass.set_bind_group(0, material_bg, &[]);
+        match &keyed {
+            Some((_, bind_group)) => pass.set_bind_group(0, bind_group, &[]),
+            None => {
+                let material_bg = self.materials.get_or_create(texture_views, &options.texture_access, shadow)?;
+                pass.set_bind_group(0, material_bg, &[]);
+            }
+        }
 
         // Uniform bind group
-        if uniform_count > 0 {
+        if bind_uniforms {
             let uniform_bg = self.get_or_create_uniform_bind_group(uniforms);
             pass.set_bind_group(1, uniform_bg, &[]);
         }
+
+        for (i, group) in extra.iter().enumerate() {
+            pass.set_bind_group(2 + i as u32, group.bind_group, &[]);
+        }
+        Ok(())
     }
 
 
@@ -309,12 +1019,160 @@ impl RenderManager {
         options: &PipelineOptions,
         pass: &mut RenderPass,
     ) {
-        let pipeline = self.pipeline_cache.get_or_create(shader_path, bind_group_layouts, options, &self.defines);
+        self.try_render_with_layouts(shader_path, bind_group_layouts, bind_groups, options, pass)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`render_with_layouts`](Self::render_with_layouts), returning a [`CrmError`] if
+    /// the shader cannot be read or parsed.
+    pub fn try_render_with_layouts(
+        &mut self,
+        shader_path: &Path,
+        bind_group_layouts: &[&BindGroupLayout],
+        bind_groups: &[&BindGroup],
+        options: &PipelineOptions,
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        let pipeline = self.pipeline_cache.get_or_create(shader_path, bind_group_layouts, options, &self.defines)?;
         pass.set_pipeline(pipeline);
 
         for (i, bg) in bind_groups.iter().enumerate() {
             pass.set_bind_group(i as u32, *bg, &[]);
         }
+        Ok(())
+    }
+
+    /// Start an explicit bind group, binding by binding, when the slice-of-views
+    /// auto-detection of [`render_with_textures`](Self::render_with_textures) is not
+    /// expressive enough. See [`BindGroupBuilder`] for an example.
+    pub fn bind_group(&mut self) -> BindGroupBuilder<'_> {
+        BindGroupBuilder::new(&mut self.builder_bind_groups, self.device.features())
+    }
+
+    /// The cached layout of a [`TypedBindGroup`].
+    pub fn typed_layout<T: TypedBindGroup>(&mut self) -> BindGroupLayout {
+        self.builder_bind_groups.layout(&T::layout_entries(), T::LABEL).clone()
+    }
+
+    /// The cached layout and bind group of `resources`, see [`TypedBindGroup`].
+    pub fn typed_bind_group<T: TypedBindGroup>(&mut self, resources: &T) -> BuiltBindGroup {
+        self.try_typed_bind_group(resources).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`typed_bind_group`](Self::typed_bind_group), failing with [`CrmError::BufferTooSmall`]
+    /// if a buffer is smaller than the `min_binding_size` of its binding.
+    pub fn try_typed_bind_group<T: TypedBindGroup>(&mut self, resources: &T) -> Result<BuiltBindGroup, CrmError> {
+        let entries = T::layout_entries();
+        let bindings: Vec<ComputeBinding> = resources.resources().into_iter().map(ComputeBinding::from).collect();
+        validate_buffer_sizes(&entries, &bindings, T::LABEL)?;
+        let layout = self.builder_bind_groups.layout(&entries, T::LABEL).clone();
+        let bind_group = self.builder_bind_groups.get_or_create(&layout, &bindings, T::LABEL).clone();
+        Ok(BuiltBindGroup { layout, bind_group })
+    }
+
+    /// The cached material layout for `texture_views`, without shadow bindings.
+    ///
+    /// Material layouts are visible to fragment and compute shaders, so custom compute
+    /// pipelines can use the same auto-generated layout as
+    /// [`render_with_textures`](Self::render_with_textures). `access` selects storage
+    /// texture bindings per view, e.g. `TextureAccess::Storage(StorageTextureAccess::WriteOnly)`
+    /// for a `texture_storage_2d<rgba8unorm, write>` output.
+    pub fn material_layout(&mut self, texture_views: &[&TextureView], access: &[TextureAccess]) -> BindGroupLayout {
+        self.try_material_layout(texture_views, access).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`material_layout`](Self::material_layout), failing with
+    /// [`CrmError::UnsupportedFormat`] for views that cannot be bound as requested.
+    pub fn try_material_layout(&mut self, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BindGroupLayout, CrmError> {
+        self.materials.layout(texture_views, access, false).cloned()
+    }
+
+    /// The cached material bind group matching [`material_layout`](Self::material_layout).
+    pub fn material_bind_group(&mut self, texture_views: &[&TextureView], access: &[TextureAccess]) -> BindGroup {
+        self.try_material_bind_group(texture_views, access).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`material_bind_group`](Self::material_bind_group), failing like
+    /// [`try_material_layout`](Self::try_material_layout).
+    pub fn try_material_bind_group(&mut self, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BindGroup, CrmError> {
+        self.materials.get_or_create(texture_views, access, None).cloned()
+    }
+
+    /// The material layout and bind group cached under `key`, created from
+    /// `texture_views` on a miss, for custom passes next to
+    /// [`render_with_material_key`](Self::render_with_material_key).
+    pub fn keyed_material_bind_group<K: CacheKey>(&mut self, key: &K, texture_views: &[&TextureView], access: &[TextureAccess]) -> BuiltBindGroup {
+        self.try_keyed_material_bind_group(key, texture_views, access).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`keyed_material_bind_group`](Self::keyed_material_bind_group), failing like
+    /// [`try_material_layout`](Self::try_material_layout).
+    pub fn try_keyed_material_bind_group<K: CacheKey>(&mut self, key: &K, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BuiltBindGroup, CrmError> {
+        let material = self.materials.get_or_create_keyed(key, texture_views, access, None)?;
+        Ok(BuiltBindGroup {
+            layout: material.layout.clone(),
+            bind_group: material.bind_group.clone(),
+        })
+    }
+
+    /// Handle to `bind_group` for [`resolve`](Self::resolve), e.g. one from the
+    /// [`BindGroupBuilder`] or a [`TypedBindGroup`], see [`handles`](crate::handles).
+    pub fn register_bind_group(&mut self, bind_group: &BindGroup) -> BindGroupId {
+        self.handles.insert(bind_group)
+    }
+
+    /// Handle to the cached [`material_bind_group`](Self::material_bind_group) of
+    /// `texture_views`, see [`handles`](crate::handles).
+    pub fn material_bind_group_id(&mut self, texture_views: &[&TextureView], access: &[TextureAccess]) -> BindGroupId {
+        self.try_material_bind_group_id(texture_views, access).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`material_bind_group_id`](Self::material_bind_group_id), failing like
+    /// [`try_material_layout`](Self::try_material_layout).
+    pub fn try_material_bind_group_id(&mut self, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BindGroupId, CrmError> {
+        let bind_group = self.materials.get_or_create(texture_views, access, None)?;
+        Ok(self.handles.insert(bind_group))
+    }
+
+    /// Handles to the material bind groups of a whole set of materials, in request
+    /// order, hashing each material once and creating all missing layouts and bind
+    /// groups together, see [`handles`](crate::handles).
+    pub fn get_or_create_many(&mut self, requests: &[MaterialRequest]) -> Vec<BindGroupId> {
+        self.try_get_or_create_many(requests).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`get_or_create_many`](Self::get_or_create_many), failing like
+    /// [`try_material_layout`](Self::try_material_layout).
+    pub fn try_get_or_create_many(&mut self, requests: &[MaterialRequest]) -> Result<Vec<BindGroupId>, CrmError> {
+        let bind_groups = self.materials.get_or_create_many(requests)?;
+        Ok(bind_groups.iter().map(|bind_group| self.handles.insert(bind_group)).collect())
+    }
+
+    /// Handle to the cached uniform bind group of `uniforms`, laid out like `@group(1)`
+    /// of [`render_with_textures`](Self::render_with_textures).
+    pub fn uniform_bind_group_id(&mut self, uniforms: &[&Buffer]) -> BindGroupId {
+        let bind_group = self.get_or_create_uniform_bind_group(uniforms).clone();
+        self.handles.insert(&bind_group)
+    }
+
+    /// The bind group behind `id`.
+    ///
+    /// # Panics
+    /// If `id` was handed out before the last [`begin_frame`](Self::begin_frame) or by
+    /// another manager, see [`try_resolve`](Self::try_resolve).
+    pub fn resolve(&self, id: BindGroupId) -> &BindGroup {
+        self.try_resolve(id).unwrap_or_else(|| {
+            if self.handles.owns(id) {
+                panic!("{:?} is from an earlier frame than {}, handles are valid until the next begin_frame", id, self.handles.frame())
+            }
+            panic!("{:?} was handed out by another RenderManager, or before recreate", id)
+        })
+    }
+
+    /// The bind group behind `id`, `None` if `id` is from an earlier frame, another
+    /// manager or from before [`recreate`](Self::recreate).
+    pub fn try_resolve(&self, id: BindGroupId) -> Option<&BindGroup> {
+        self.handles.get(id)
     }
 
     /// Render a fullscreen debug visualization of a texture.
@@ -332,7 +1190,28 @@ impl RenderManager {
         target_view: &TextureView,
         pass: &mut RenderPass,
     ) {
-        self.fullscreen.render(texture, visualization_type, target_view, pass);
+        self.try_render_fullscreen_debug(texture, visualization_type, target_view, pass)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`render_fullscreen_debug`](Self::render_fullscreen_debug), failing with
+    /// [`CrmError::UnsupportedFormat`] if `texture` cannot be sampled on this device.
+    pub fn try_render_fullscreen_debug(
+        &mut self,
+        texture: &TextureView,
+        visualization_type: DebugVisualization,
+        target_view: &TextureView,
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        let scope_open = self
+            .statistics
+            .as_mut()
+            .is_some_and(|s| s.begin_render_scope(pass, &format!("fullscreen {:?}", visualization_type)));
+        let result = self.fullscreen.try_render(texture, visualization_type, target_view, pass);
+        if scope_open && let Some(statistics) = &mut self.statistics {
+            statistics.end_render_scope(pass);
+        }
+        result
     }
 
     /// Execute a compute shader, optionally using an existing command encoder.
@@ -386,6 +1265,8 @@ impl RenderManager {
     /// ## Notes
     /// - Shader entry point must be `main`
     /// - Dispatch size comes from `options.dispatch_size`
+    /// - If the GPU profiler is enabled, the pass is timed under `label`
+    /// - If pipeline statistics are enabled, the dispatch is measured under `label`
     ///
     /// ## WGSL expectations
     /// - Entry point: `@compute @workgroup_size(...) fn main()`
@@ -396,17 +1277,39 @@ impl RenderManager {
     /// WGSL shaders are compiled via [`compile_wgsl()`](crate::shader_preprocessing::compile_wgsl), which adds a small
     /// compile-time preprocessing layer (`#ifdef`, `#include`) on top of
     /// standard WGSL before passing it to wgpu.
+    #[allow(clippy::too_many_arguments)]
     pub fn compute(
         &mut self,
         encoder: Option<&mut CommandEncoder>,
         label: &str,
         input_views: Vec<&TextureView>,
         output_views: Vec<&TextureView>,
-        shader_path: &PathBuf,
+        shader_path: &Path,
         options: ComputePipelineOptions,
         buffer_sets: &[BufferSet],
     ) {
-        self.compute_system.compute(encoder, label, input_views, output_views, shader_path, options, buffer_sets, &self.defines);
+        self.try_compute(encoder, label, input_views, output_views, shader_path, options, buffer_sets)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`compute`](Self::compute), returning a [`CrmError`] instead of panicking when the
+    /// shader cannot be read or parsed, a buffer is neither uniform nor storage, or
+    /// the dispatch size exceeds `max_compute_workgroups_per_dimension`. Nothing is
+    /// dispatched on failure.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_compute(
+        &mut self,
+        encoder: Option<&mut CommandEncoder>,
+        label: &str,
+        input_views: Vec<&TextureView>,
+        output_views: Vec<&TextureView>,
+        shader_path: &Path,
+        options: ComputePipelineOptions,
+        buffer_sets: &[BufferSet],
+    ) -> Result<(), CrmError> {
+        let timestamps = self.profiler.as_mut().and_then(|p| p.begin_pass(label));
+        let statistics = self.statistics.as_mut().and_then(|s| s.begin_scope(label));
+        self.compute_system.compute(encoder, label, input_views, output_views, shader_path, options, buffer_sets, &self.defines, timestamps, statistics)
     }
 
     /// Enables or disables a compile-time shader define.
@@ -443,6 +1346,30 @@ impl RenderManager {
     /// compile-time preprocessing layer (`#ifdef`, `#include`) on top of
     /// standard WGSL before passing it to wgpu.
     pub fn update_define(&mut self, define: String, enabled: bool) { self.defines.insert(define, enabled); }
+    /// Draw all materials without their own [`debug_mode`](PipelineOptions::debug_mode)
+    /// with `mode`, or normally again with `None`.
+    ///
+    /// Unlike defines, this takes effect immediately: the pipeline cache builds (or
+    /// reuses) the variant of every pipeline requested from now on. See
+    /// [`debug_modes`](crate::debug_modes) for the available modes.
+    ///
+    /// ## Panics
+    /// Panics for [`DebugRenderMode::Wireframe`] if the device lacks
+    /// [`Features::POLYGON_MODE_LINE`].
+    pub fn set_debug_mode(&mut self, mode: Option<DebugRenderMode>) {
+        if mode == Some(DebugRenderMode::Wireframe) {
+            assert!(
+                self.device.features().contains(Features::POLYGON_MODE_LINE),
+                "DebugRenderMode::Wireframe requires Features::POLYGON_MODE_LINE"
+            );
+        }
+        self.pipeline_cache.set_debug_mode(mode);
+    }
+
+    /// The global debug render mode set by [`set_debug_mode`](Self::set_debug_mode).
+    pub fn debug_mode(&self) -> Option<DebugRenderMode> {
+        self.pipeline_cache.debug_mode()
+    }
     /// Update parameters used for depth texture visualization.
     ///
     /// These parameters affect subsequent calls to
@@ -451,14 +1378,27 @@ impl RenderManager {
         self.fullscreen.update_depth_params(params);
     }
 
-    /// Clear cached material and uniform bind groups.
+    /// Clear cached material, uniform and compute bind groups.
     ///
     /// Call this after window resize, swapchain recreation,
     /// or when underlying textures are replaced.
     pub fn invalidate_bind_groups(&mut self) {
+        if let Some(staging) = &mut self.staging {
+            staging.invalidate_bind_groups = true;
+            return;
+        }
+        self.invalidate_bind_groups_now();
+    }
+
+    fn invalidate_bind_groups_now(&mut self) {
+        trace_evict!("uniform_bind_groups", self.uniform_bind_groups.len() + self.staged_uniform_bind_groups.len());
         self.materials.clear();
+        self.shared_materials.clear();
         self.fullscreen.invalidate_bind_groups();
+        self.compute_system.invalidate_bind_groups();
+        self.builder_bind_groups.clear();
         self.uniform_bind_groups.clear();
+        self.staged_uniform_bind_groups.clear();
     }
 
     /// Reload render shaders from disk.
@@ -466,9 +1406,11 @@ impl RenderManager {
     /// Existing pipelines using these shaders will be recreated
     /// on next use.
     ///
-    /// Useful for shader hot-reloading
-    pub fn reload_render_shaders(&mut self, paths: &[PathBuf]) {
-        self.pipeline_cache.reload_shaders(paths, &self.defines);
+    /// Useful for shader hot-reloading. If a shader fails to compile, e.g. because it
+    /// was saved mid-edit, the error is returned and the previous shaders and pipelines
+    /// stay in use.
+    pub fn reload_render_shaders(&mut self, paths: &[PathBuf]) -> Result<(), CrmError> {
+        self.pipeline_cache.reload_shaders(paths, &self.defines)
     }
 
     /// Reload procedural texture shaders and clear the texture cache.
@@ -476,23 +1418,80 @@ impl RenderManager {
         self.generator.reload_shaders();
     }
 
+    /// Drop the cached material bind groups of exactly `texture_views`, in this manager and
+    /// its [`shared_materials`](Self::shared_materials), e.g. when the material is unloaded.
+    /// Bind groups created with any [`texture_access`](PipelineOptions::texture_access) and
+    /// with or without shadow bindings are dropped. Returns `true` if any existed.
+    pub fn release_material(&mut self, texture_views: &[&TextureView]) -> bool {
+        let local = self.materials.evict_views(texture_views);
+        let shared = self.shared_materials.evict_views(texture_views);
+        local | shared
+    }
+
+    /// Drop the material bind group cached under `key`, e.g. when the material changes
+    /// its textures or is unloaded. Returns `true` if it existed.
+    pub fn release_material_key<K: CacheKey>(&mut self, key: &K) -> bool {
+        self.materials.evict_key(key)
+    }
+
+    /// Drop a render shader and every pipeline created from it. Unlike
+    /// [`reload_render_shaders`](Self::reload_render_shaders) nothing is recompiled until
+    /// the shader is used again. Returns the number of pipelines removed.
+    pub fn release_shader(&mut self, path: &Path) -> usize {
+        self.pipeline_cache.release_shader(path)
+    }
+
+    /// React to a [`ReloadEvent`] of the hot-reload bus: a changed shader is reloaded as
+    /// with [`reload_render_shaders`](Self::reload_render_shaders), or with
+    /// [`reload_texture_shaders`](Self::reload_texture_shaders) if it lies in the
+    /// procedural texture shader directory. Other events are ignored.
+    ///
+    /// Returns `true` if the event was handled, or the compile error of a render shader.
+    pub fn handle_reload(&mut self, event: &ReloadEvent) -> Result<bool, CrmError> {
+        if event.kind != AssetKind::Shader || event.stage != ReloadStage::Changed {
+            return Ok(false);
+        }
+        if is_within(&event.path, self.generator.shader_dir()) {
+            self.reload_texture_shaders();
+        } else {
+            self.reload_render_shaders(std::slice::from_ref(&event.path))?;
+        }
+        Ok(true)
+    }
+
     /// Clear all internal caches.
     ///
     /// This includes pipelines, generated textures, and bind groups.
     pub fn clear_all(&mut self) {
+        if let Some(staging) = &mut self.staging {
+            staging.clear_all = true;
+            return;
+        }
+        self.clear_all_now();
+    }
+
+    fn clear_all_now(&mut self) {
         self.pipeline_cache.clear();
         self.generator.clear_cache();
-        self.invalidate_bind_groups();
+        self.invalidate_bind_groups_now();
     }
 
     fn get_or_create_uniform_bind_group(&mut self, uniforms: &[&Buffer]) -> &BindGroup {
         let key = UniformBindGroupKey::from_buffers(uniforms);
 
-        if !self.uniform_bind_groups.contains_key(&key) {
-            let bg = self.pipeline_cache.create_uniform_bind_group(uniforms, "uniform bind group");
-            self.uniform_bind_groups.insert(key.clone(), bg);
+        if let Some(entry) = self.uniform_bind_groups.get_mut(&key) {
+            entry.touch(self.frame_index);
+            return &self.uniform_bind_groups.get(&key).unwrap().value;
+        }
+        if let Some(entry) = self.staged_uniform_bind_groups.get_mut(&key) {
+            entry.touch(self.frame_index);
+            return &self.staged_uniform_bind_groups.get(&key).unwrap().value;
         }
 
-        self.uniform_bind_groups.get(&key).unwrap()
+        trace_event!(buffers = uniforms.len(), "created uniform bind group");
+        let bg = self.pipeline_cache.create_uniform_bind_group(uniforms, "uniform bind group");
+        let label = format!("{} uniform buffers", uniforms.len());
+        let map = if self.staging.is_some() { &mut self.staged_uniform_bind_groups } else { &mut self.uniform_bind_groups };
+        &map.entry(key).or_insert(Tracked::new(bg, self.frame_index, label)).value
     }
 }
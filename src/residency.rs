@@ -0,0 +1,161 @@
+//! Memory budget for GPU assets with priority-based eviction.
+//!
+//! A [`ResidencyManager`] tracks the size, [`Priority`] and last use of resident assets.
+//! Once they exceed the budget, [`evictions`](ResidencyManager::evictions) picks what to
+//! drop: lowest priority first, and within a priority the least recently used. UI
+//! textures therefore outlive nearby world assets, which outlive distant ones, and
+//! [`Priority::PINNED`] assets are never evicted:
+//! ```ignore
+//! let mut residency = ResidencyManager::new(512 << 20);
+//! residency.insert(texture_id, texture_memory, Priority::DISTANT);
+//! // when the player walks closer
+//! residency.set_priority(&texture_id, Priority::NEARBY);
+//! // every frame, for drawn assets
+//! residency.touch(&texture_id);
+//! for id in residency.evictions() {
+//!     textures.remove(&id);
+//! }
+//! ```
+//! The `AssetServer` uses one with `AssetServer::set_memory_budget`.
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How much an asset is worth keeping. Higher survives longer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(pub u8);
+
+impl Priority {
+    /// Far away or rarely visible world assets.
+    pub const DISTANT: Priority = Priority(64);
+    /// World assets around the camera.
+    pub const NEARBY: Priority = Priority(128);
+    /// Interface textures and fonts, visible every frame.
+    pub const UI: Priority = Priority(192);
+    /// Never evicted, even over budget.
+    pub const PINNED: Priority = Priority(255);
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::NEARBY
+    }
+}
+
+struct Resident {
+    bytes: u64,
+    priority: Priority,
+    last_used: AtomicU64,
+}
+
+/// Sizes, priorities and last uses of resident assets, under a memory budget.
+pub struct ResidencyManager<K> {
+    budget: u64,
+    resident: HashMap<K, Resident>,
+    resident_bytes: u64,
+    /// Use counter ordering [`touch`](Self::touch)es.
+    clock: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone> ResidencyManager<K> {
+    /// A manager evicting down to `budget` bytes.
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            resident: HashMap::new(),
+            resident_bytes: 0,
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Change the budget. Lowering it takes effect with the next [`evictions`](Self::evictions).
+    pub fn set_budget(&mut self, bytes: u64) {
+        self.budget = bytes;
+    }
+
+    /// Track a resident asset, as just used. Replaces an earlier entry of `key`.
+    pub fn insert(&mut self, key: K, bytes: u64, priority: Priority) {
+        self.remove(&key);
+        let last_used = AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed));
+        self.resident_bytes += bytes;
+        self.resident.insert(key, Resident { bytes, priority, last_used });
+    }
+
+    /// Stop tracking `key`, e.g. when the asset was dropped by the application.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(resident) = self.resident.remove(key) else {
+            return false;
+        };
+        self.resident_bytes -= resident.bytes;
+        true
+    }
+
+    /// Returns `false` if `key` is not resident.
+    pub fn set_priority(&mut self, key: &K, priority: Priority) -> bool {
+        self.resident.get_mut(key).map(|resident| resident.priority = priority).is_some()
+    }
+
+    pub fn priority(&self, key: &K) -> Option<Priority> {
+        self.resident.get(key).map(|resident| resident.priority)
+    }
+
+    /// Mark `key` as used now. Takes `&self`, so lookups that only borrow can record uses.
+    pub fn touch(&self, key: &K) {
+        if let Some(resident) = self.resident.get(key) {
+            resident.last_used.store(self.clock.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.resident.contains_key(key)
+    }
+
+    /// Total size of the resident assets.
+    pub fn resident_bytes(&self) -> u64 {
+        self.resident_bytes
+    }
+
+    /// Whether the resident assets exceed the budget.
+    pub fn is_over_budget(&self) -> bool {
+        self.resident_bytes > self.budget
+    }
+
+    /// Untrack assets until the rest fits the budget, lowest priority and least recently
+    /// used first, and return them for the caller to free. Pinned assets are kept, so
+    /// the result may still be over budget.
+    pub fn evictions(&mut self) -> Vec<K> {
+        if !self.is_over_budget() {
+            return Vec::new();
+        }
+        let mut candidates: Vec<(Priority, u64, K)> = self
+            .resident
+            .iter()
+            .filter(|(_, resident)| resident.priority != Priority::PINNED)
+            .map(|(key, resident)| (resident.priority, resident.last_used.load(Ordering::Relaxed), key.clone()))
+            .collect();
+        candidates.sort_by_key(|(priority, last_used, _)| (*priority, *last_used));
+        let mut evicted = Vec::new();
+        for (_, _, key) in candidates {
+            if !self.is_over_budget() {
+                break;
+            }
+            self.remove(&key);
+            evicted.push(key);
+        }
+        trace_evict!("asset_residency", evicted.len());
+        evicted
+    }
+
+    /// Number of resident assets.
+    pub fn len(&self) -> usize {
+        self.resident.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resident.is_empty()
+    }
+}
@@ -0,0 +1,377 @@
+//! Transform hierarchy with per-node draw uniforms.
+//!
+//! A [`SceneGraph`] stores nodes with a local transform relative to their parent.
+//! Changing a transform or a parent marks the node dirty;
+//! [`update_transforms`](SceneGraph::update_transforms) recomputes the world matrix of
+//! dirty nodes and their descendants only. [`upload`](SceneGraph::upload) then pushes a
+//! [`NodeUniform`] per drawable node into a [`UniformRing`] and remembers its dynamic
+//! offset:
+//! ```ignore
+//! let mut ring = UniformRing::new(&device, &queue, "nodes", size_of::<NodeUniform>() as u64, 256);
+//! let car = scene.add(None, JointTransform::IDENTITY);
+//! let wheel = scene.add(Some(car), wheel_offset);
+//! scene.set_mesh(wheel, Some(wheel_mesh));
+//!
+//! scene.set_local(car, car_transform);
+//! scene.upload(&mut ring); // updates dirty transforms first
+//! // Inside a render pass, with `ring.bind_group_layout()` at group 2 of the pipeline
+//! for (mesh, offset) in scene.draws() {
+//!     pass.set_bind_group(2, ring.bind_group(), &[offset]);
+//!     meshes.draw(&mut pass, mesh, &options, 0..1);
+//! }
+//! ```
+//! Paste [`NODE_WGSL`] into the shader for the matching declaration.
+use crate::animation::JointTransform;
+use crate::gpu_util::{invert, mul_matrices, IDENTITY};
+use crate::meshes::MeshHandle;
+use crate::uniform_ring::UniformRing;
+
+/// WGSL declaration of [`NodeUniform`] at `@group(2) @binding(0)`.
+pub const NODE_WGSL: &str = r#"
+struct Node {
+    model: mat4x4<f32>,
+    // Inverse transpose of `model`, for normals.
+    normal_matrix: mat4x4<f32>,
+};
+
+@group(2) @binding(0) var<uniform> node: Node;
+"#;
+
+/// Draw uniform of one node, see [`NODE_WGSL`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct NodeUniform {
+    pub model: [[f32; 4]; 4],
+    pub normal_matrix: [[f32; 4]; 4],
+}
+
+/// Handle to a node of a [`SceneGraph`]; stale after the node is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+struct Node {
+    generation: u32,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: JointTransform,
+    world: [[f32; 4]; 4],
+    dirty: bool,
+    mesh: Option<MeshHandle>,
+    offset: Option<u32>,
+}
+
+/// Nodes with parent/child transforms.
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    /// Whether the slot of the same index holds a live node.
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    roots: Vec<NodeId>,
+    /// Whether any node changed since the last update.
+    dirty: bool,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node under `parent` (or as a root).
+    ///
+    /// ## Panics
+    /// Panics if `parent` was removed.
+    pub fn add(&mut self, parent: Option<NodeId>, local: JointTransform) -> NodeId {
+        if let Some(parent) = parent {
+            assert!(self.contains(parent), "Parent node {parent:?} was removed");
+        }
+        let node = |generation| Node {
+            generation,
+            parent,
+            children: Vec::new(),
+            local,
+            world: IDENTITY,
+            dirty: true,
+            mesh: None,
+            offset: None,
+        };
+        let id = match self.free.pop() {
+            Some(index) => {
+                let generation = self.nodes[index as usize].generation.wrapping_add(1);
+                self.nodes[index as usize] = node(generation);
+                self.alive[index as usize] = true;
+                NodeId { index, generation }
+            }
+            None => {
+                self.nodes.push(node(0));
+                self.alive.push(true);
+                NodeId { index: self.nodes.len() as u32 - 1, generation: 0 }
+            }
+        };
+        match parent {
+            Some(parent) => self.nodes[parent.index as usize].children.push(id),
+            None => self.roots.push(id),
+        }
+        self.dirty = true;
+        id
+    }
+
+    /// Remove a node and all its descendants.
+    pub fn remove(&mut self, id: NodeId) {
+        if !self.contains(id) {
+            return;
+        }
+        self.detach(id);
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let node = &mut self.nodes[id.index as usize];
+            stack.append(&mut node.children);
+            node.mesh = None;
+            node.offset = None;
+            self.alive[id.index as usize] = false;
+            self.free.push(id.index);
+        }
+    }
+
+    /// Whether `id` refers to a live node.
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.alive.get(id.index as usize).is_some_and(|&alive| alive) && self.nodes[id.index as usize].generation == id.generation
+    }
+
+    /// Move a node (with its subtree) under `parent`, or make it a root.
+    ///
+    /// ## Panics
+    /// Panics if a node was removed or if `parent` is inside the subtree of `id`.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        assert!(self.contains(id), "Node {id:?} was removed");
+        if let Some(parent) = parent {
+            assert!(self.contains(parent), "Parent node {parent:?} was removed");
+            let mut ancestor = Some(parent);
+            while let Some(node) = ancestor {
+                assert!(node != id, "Node {id:?} cannot be parented to its own descendant");
+                ancestor = self.nodes[node.index as usize].parent;
+            }
+        }
+        self.detach(id);
+        match parent {
+            Some(parent) => self.nodes[parent.index as usize].children.push(id),
+            None => self.roots.push(id),
+        }
+        let node = &mut self.nodes[id.index as usize];
+        node.parent = parent;
+        node.dirty = true;
+        self.dirty = true;
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.node(id).children
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Transform relative to the parent.
+    pub fn local(&self, id: NodeId) -> &JointTransform {
+        &self.node(id).local
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: JointTransform) {
+        let node = self.node_mut(id);
+        node.local = local;
+        node.dirty = true;
+        self.dirty = true;
+    }
+
+    /// World matrix as of the last [`update_transforms`](Self::update_transforms).
+    pub fn world(&self, id: NodeId) -> &[[f32; 4]; 4] {
+        &self.node(id).world
+    }
+
+    /// Mesh drawn at this node, `None` for a pure transform node.
+    pub fn mesh(&self, id: NodeId) -> Option<MeshHandle> {
+        self.node(id).mesh
+    }
+
+    pub fn set_mesh(&mut self, id: NodeId, mesh: Option<MeshHandle>) {
+        self.node_mut(id).mesh = mesh;
+    }
+
+    /// Recompute the world matrices of dirty nodes and their descendants.
+    pub fn update_transforms(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        let _span = trace_span!("scene_update_transforms", nodes = self.nodes.len());
+        let mut stack: Vec<(NodeId, [[f32; 4]; 4], bool)> = self.roots.iter().rev().map(|&id| (id, IDENTITY, false)).collect();
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.index as usize];
+            let changed = parent_changed || node.dirty;
+            if changed {
+                node.world = mul_matrices(&parent_world, &node.local.matrix());
+                node.dirty = false;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().rev().map(|&child| (child, world, changed)));
+        }
+        self.dirty = false;
+    }
+
+    /// Update transforms and push a [`NodeUniform`] for every node with a mesh into
+    /// `ring`, then flush it. The ring is reset first, so it should belong to this graph.
+    pub fn upload(&mut self, ring: &mut UniformRing) {
+        self.update_transforms();
+        ring.reset();
+        for (node, &alive) in self.nodes.iter_mut().zip(&self.alive) {
+            node.offset = match node.mesh {
+                Some(_) if alive => {
+                    let normal_matrix = transpose(&invert(&node.world));
+                    Some(ring.push(&NodeUniform { model: node.world, normal_matrix }))
+                }
+                _ => None,
+            };
+        }
+        ring.flush();
+    }
+
+    /// Dynamic offset of the node's uniform from the last [`upload`](Self::upload).
+    pub fn draw_offset(&self, id: NodeId) -> Option<u32> {
+        self.node(id).offset
+    }
+
+    /// Mesh and dynamic offset of every node uploaded by the last [`upload`](Self::upload).
+    pub fn draws(&self) -> impl Iterator<Item = (MeshHandle, u32)> + '_ {
+        self.nodes
+            .iter()
+            .zip(&self.alive)
+            .filter(|(_, alive)| **alive)
+            .filter_map(|(node, _)| Some((node.mesh?, node.offset?)))
+    }
+
+    /// Number of live nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Unlink a node from its parent's children or the roots.
+    fn detach(&mut self, id: NodeId) {
+        let siblings = match self.nodes[id.index as usize].parent {
+            Some(parent) => &mut self.nodes[parent.index as usize].children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != id);
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        assert!(self.contains(id), "Node {id:?} was removed");
+        &self.nodes[id.index as usize]
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        assert!(self.contains(id), "Node {id:?} was removed");
+        &mut self.nodes[id.index as usize]
+    }
+}
+
+fn transpose(m: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    std::array::from_fn(|c| std::array::from_fn(|r| m[r][c]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(x: f32, y: f32, z: f32) -> JointTransform {
+        JointTransform { translation: [x, y, z], ..JointTransform::IDENTITY }
+    }
+
+    fn translation(m: &[[f32; 4]; 4]) -> [f32; 3] {
+        [m[3][0], m[3][1], m[3][2]]
+    }
+
+    #[test]
+    fn world_matrices_compose_down_the_hierarchy() {
+        let mut scene = SceneGraph::new();
+        let car = scene.add(None, offset(10.0, 0.0, 0.0));
+        let wheel = scene.add(Some(car), offset(1.0, -0.5, 0.0));
+        let bolt = scene.add(Some(wheel), offset(0.0, 0.0, 0.25));
+        scene.update_transforms();
+        assert_eq!(translation(scene.world(bolt)), [11.0, -0.5, 0.25]);
+
+        scene.set_local(car, offset(-10.0, 0.0, 0.0));
+        scene.update_transforms();
+        assert_eq!(translation(scene.world(wheel)), [-9.0, -0.5, 0.0]);
+        assert_eq!(translation(scene.world(bolt)), [-9.0, -0.5, 0.25]);
+    }
+
+    #[test]
+    fn reparenting_moves_the_subtree() {
+        let mut scene = SceneGraph::new();
+        let left = scene.add(None, offset(-5.0, 0.0, 0.0));
+        let right = scene.add(None, offset(5.0, 0.0, 0.0));
+        let arm = scene.add(Some(left), offset(0.0, 1.0, 0.0));
+        let hand = scene.add(Some(arm), offset(0.0, 1.0, 0.0));
+        scene.update_transforms();
+
+        scene.set_parent(arm, Some(right));
+        assert_eq!(scene.parent(arm), Some(right));
+        assert!(scene.children(left).is_empty());
+        assert_eq!(scene.children(right), [arm]);
+        scene.update_transforms();
+        assert_eq!(translation(scene.world(hand)), [5.0, 2.0, 0.0]);
+
+        scene.set_parent(arm, None);
+        assert_eq!(scene.roots(), [left, right, arm]);
+        scene.update_transforms();
+        assert_eq!(translation(scene.world(hand)), [0.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be parented to its own descendant")]
+    fn reparenting_under_a_descendant_panics() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add(None, JointTransform::IDENTITY);
+        let child = scene.add(Some(root), JointTransform::IDENTITY);
+        let grandchild = scene.add(Some(child), JointTransform::IDENTITY);
+        scene.set_parent(root, Some(grandchild));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be parented to its own descendant")]
+    fn reparenting_under_itself_panics() {
+        let mut scene = SceneGraph::new();
+        let node = scene.add(None, JointTransform::IDENTITY);
+        scene.set_parent(node, Some(node));
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_subtree_and_stales_ids() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add(None, JointTransform::IDENTITY);
+        let child = scene.add(Some(root), JointTransform::IDENTITY);
+        let grandchild = scene.add(Some(child), JointTransform::IDENTITY);
+        let sibling = scene.add(Some(root), JointTransform::IDENTITY);
+
+        scene.remove(child);
+        assert_eq!(scene.len(), 2);
+        assert!(!scene.contains(child) && !scene.contains(grandchild));
+        assert_eq!(scene.children(root), [sibling]);
+
+        // Reused slots get a new generation, old ids stay stale.
+        let reused = scene.add(None, JointTransform::IDENTITY);
+        assert!(scene.contains(reused));
+        assert!(!scene.contains(child) && !scene.contains(grandchild));
+        assert_eq!(scene.len(), 3);
+    }
+}
@@ -0,0 +1,178 @@
+//! Scene save/load (feature `serde`).
+//!
+//! A [`SceneFile`] is the serializable form of a [`SceneGraph`]: the node hierarchy with
+//! local transforms, and mesh and material references by asset path instead of GPU
+//! handles. Loading resolves every path once through [`SceneAssets`], which loads or
+//! looks up the GPU resources, and rebuilds the graph:
+//! ```ignore
+//! let file = SceneFile::from_graph(&scene, |mesh| mesh_paths.get(&mesh).cloned(), |node| material_paths.get(&node).cloned());
+//! file.save("levels/garage.scene.json")?;
+//!
+//! let loaded = SceneFile::load("levels/garage.scene.json")?.instantiate(&mut assets)?;
+//! let scene = loaded.graph;
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use crate::animation::JointTransform;
+use crate::meshes::MeshHandle;
+use crate::scene::{NodeId, SceneGraph};
+
+/// A scene file that could not be read, written or instantiated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SceneError {
+    /// Path of the scene file, or of the asset that failed to resolve.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load scene {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Resolves asset paths of a scene file into GPU resources.
+pub trait SceneAssets {
+    /// Whatever the application binds as a material, e.g. a `BindGroup`.
+    type Material: Clone;
+
+    fn mesh(&mut self, path: &str) -> Result<MeshHandle, String>;
+
+    fn material(&mut self, path: &str) -> Result<Self::Material, String>;
+}
+
+/// One node of a [`SceneFile`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SceneFileNode {
+    /// Index of the parent node; parents come before their children.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    pub translation: [f32; 3],
+    /// Unit quaternion, `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<String>,
+}
+
+/// Serializable scene: nodes, transforms and asset paths.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SceneFile {
+    pub nodes: Vec<SceneFileNode>,
+}
+
+/// A [`SceneFile`] rebuilt on the GPU.
+pub struct LoadedScene<M> {
+    pub graph: SceneGraph,
+    /// Node of every file node, in file order.
+    pub nodes: Vec<NodeId>,
+    pub materials: HashMap<NodeId, M>,
+}
+
+impl SceneFile {
+    /// Describe `graph`, naming meshes with `mesh_path` and node materials with
+    /// `material_path`. Meshes without a path are not saved.
+    pub fn from_graph(
+        graph: &SceneGraph,
+        mesh_path: impl Fn(MeshHandle) -> Option<String>,
+        material_path: impl Fn(NodeId) -> Option<String>,
+    ) -> Self {
+        let mut nodes = Vec::with_capacity(graph.len());
+        let mut stack: Vec<(NodeId, Option<usize>)> = graph.roots().iter().rev().map(|&id| (id, None)).collect();
+        while let Some((id, parent)) = stack.pop() {
+            let local = graph.local(id);
+            nodes.push(SceneFileNode {
+                parent,
+                translation: local.translation,
+                rotation: local.rotation,
+                scale: local.scale,
+                mesh: graph.mesh(id).and_then(&mesh_path),
+                material: material_path(id),
+            });
+            let index = nodes.len() - 1;
+            stack.extend(graph.children(id).iter().rev().map(|&child| (child, Some(index))));
+        }
+        Self { nodes }
+    }
+
+    /// Rebuild the scene, resolving each distinct asset path once.
+    pub fn instantiate<A: SceneAssets>(&self, assets: &mut A) -> Result<LoadedScene<A::Material>, SceneError> {
+        let _span = trace_span!("scene_instantiate", nodes = self.nodes.len());
+        let mut graph = SceneGraph::new();
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        let mut materials = HashMap::new();
+        let mut mesh_cache: HashMap<&str, MeshHandle> = HashMap::new();
+        let mut material_cache: HashMap<&str, A::Material> = HashMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let parent = match node.parent {
+                Some(parent) if parent < index => Some(nodes[parent]),
+                Some(parent) => return Err(error("scene", format!("node {index} has parent {parent}, which does not come before it"))),
+                None => None,
+            };
+            let id = graph.add(parent, JointTransform {
+                translation: node.translation,
+                rotation: node.rotation,
+                scale: node.scale,
+            });
+            if let Some(path) = &node.mesh {
+                let mesh = match mesh_cache.get(path.as_str()) {
+                    Some(&mesh) => mesh,
+                    None => {
+                        let mesh = assets.mesh(path).map_err(|message| error(path, message))?;
+                        mesh_cache.insert(path, mesh);
+                        mesh
+                    }
+                };
+                graph.set_mesh(id, Some(mesh));
+            }
+            if let Some(path) = &node.material {
+                let material = match material_cache.get(path.as_str()) {
+                    Some(material) => material.clone(),
+                    None => {
+                        let material = assets.material(path).map_err(|message| error(path, message))?;
+                        material_cache.insert(path, material.clone());
+                        material
+                    }
+                };
+                materials.insert(id, material);
+            }
+            nodes.push(id);
+        }
+        Ok(LoadedScene { graph, nodes, materials })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("scene files always serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SceneError> {
+        serde_json::from_str(json).map_err(|e| error("scene", e))
+    }
+
+    /// Write the scene as JSON.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()).map_err(|e| error(&path.display().to_string(), e))
+    }
+
+    /// Read a scene written by [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let json = std::fs::read_to_string(path).map_err(|e| error(&label, e))?;
+        serde_json::from_str(&json).map_err(|e| error(&label, e))
+    }
+}
+
+fn error(label: &str, message: impl fmt::Display) -> SceneError {
+    SceneError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::{HashMap};
 use std::path::Path;
-use wgpu::{Device, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+use wgpu::{naga, Device, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+use crate::error::CrmError;
+use crate::labels::namespaced;
 
 /// Compiles a WGSL shader with a lightweight preprocessing step.
 ///
@@ -76,137 +78,155 @@ use wgpu::{Device, ShaderModule, ShaderModuleDescriptor, ShaderSource};
 ///
 /// Panics if the shader file or any included file cannot be read.
 /// Also panics if any point of the preprocessing fails or the define doesn't exist if defined in the shader.
+/// [`try_compile_wgsl`] returns these failures instead.
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
 pub fn compile_wgsl(
     device: &Device,
     path: &Path,
     defines: &HashMap<String, bool>,
 ) -> ShaderModule {
-    let source = std::fs::read_to_string(path).unwrap_or_else(|e| {
-        panic!("Failed to read shader file {}: {}", path.display(), e)
-    });
-
-    let mut conditional_stack: Vec<bool> = vec![];
-    let processed = preprocess_wgsl(path, &source, defines, &mut conditional_stack);
+    try_compile_wgsl(device, path, defines).unwrap_or_else(|e| panic!("{}", e))
+}
 
-    if !conditional_stack.is_empty() {
-        panic!(
-            "Unbalanced preprocessing directives in shader {} (or one of its #included files): open #ifdef/#ifndef without matching #endif",
-            path.display()
-        );
-    }
+/// [`compile_wgsl`], returning a [`CrmError::Io`] for unreadable files and a
+/// [`CrmError::Shader`] for preprocessing and WGSL syntax errors instead of panicking.
+///
+/// The source is parsed before it reaches wgpu, so a broken shader, e.g. one being
+/// edited during hot reload, can be rejected and the last good module kept.
+pub fn try_compile_wgsl(
+    device: &Device,
+    path: &Path,
+    defines: &HashMap<String, bool>,
+) -> Result<ShaderModule, CrmError> {
+    let _span = trace_span!("compile_wgsl", shader = %path.display());
+    let processed = preprocess_file(path, defines)?;
+    parse_wgsl(path, &processed)?;
 
     let label_str = path
         .to_str()
-        .unwrap_or_else(|| panic!("Shader path {} is not valid UTF-8", path.display()));
+        .ok_or_else(|| shader_error(path, "path is not valid UTF-8"))?;
 
-    device.create_shader_module(ShaderModuleDescriptor {
-        label: Some(label_str),
+    Ok(device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(&namespaced(label_str)),
         source: ShaderSource::Wgsl(processed.into()),
+    }))
+}
+
+/// Check that `source` is syntactically valid WGSL, reporting errors with their source
+/// location.
+pub(crate) fn parse_wgsl(path: &Path, source: &str) -> Result<(), CrmError> {
+    naga::front::wgsl::parse_str(source)
+        .map(|_| ())
+        .map_err(|e| shader_error(path, e.emit_to_string(source)))
+}
+
+fn shader_error(path: &Path, message: impl std::fmt::Display) -> CrmError {
+    CrmError::Shader {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    }
+}
+
+fn read_source(path: &Path) -> Result<String, CrmError> {
+    std::fs::read_to_string(path).map_err(|e| CrmError::Io {
+        path: path.to_path_buf(),
+        message: e.to_string(),
     })
 }
 
+/// Read a shader file and run the preprocessor over it, returning plain WGSL.
+///
+/// Fails under the same conditions as [`try_compile_wgsl`], except for WGSL syntax errors.
+pub(crate) fn preprocess_file(path: &Path, defines: &HashMap<String, bool>) -> Result<String, CrmError> {
+    let source = read_source(path)?;
+
+    let mut conditional_stack: Vec<bool> = vec![];
+    let processed = preprocess_wgsl(path, &source, defines, &mut conditional_stack)?;
+
+    if !conditional_stack.is_empty() {
+        return Err(shader_error(
+            path,
+            "unbalanced preprocessing directives (or in one of its #included files): open #ifdef/#ifndef without matching #endif",
+        ));
+    }
+    Ok(processed)
+}
+
 fn preprocess_wgsl(
     path: &Path,
     src: &str,
     defines: &HashMap<String, bool>,
     stack: &mut Vec<bool>,
-) -> String {
-    let processed_lines: Vec<String> = src
-        .lines()
-        .enumerate()
-        .flat_map(|(i, line)| {
-            let line_num = i + 1;
-            let t = line.trim();
-
-            // --- #ifdef ---
-            if let Some(rest) = t.strip_prefix("#ifdef ") {
-                let name = rest.trim();
-                let value = *defines.get(name).expect(&format!(
-                    "{}:{}: Unknown preprocessing define '{}' in #ifdef",
-                    path.display(),
-                    line_num,
-                    name
-                ));
-                stack.push(value);
-                return vec![];
-            }
-
-            // --- #ifndef ---
-            if let Some(rest) = t.strip_prefix("#ifndef ") {
-                let name = rest.trim();
-                let value = *defines.get(name).expect(&format!(
-                    "{}:{}: Unknown preprocessing define '{}' in #ifndef",
-                    path.display(),
-                    line_num,
-                    name
-                ));
-                stack.push(!value);
-                return vec![];
-            }
-
-            // --- #else ---
-            if t.starts_with("#else") {
-                let v = stack.last_mut().expect(&format!(
-                    "{}:{}: #else without matching #ifdef/#ifndef",
-                    path.display(),
-                    line_num
-                ));
-                *v = !*v;
-                return vec![];
-            }
-
-            // --- #endif ---
-            if t.starts_with("#endif") {
-                if stack.pop().is_none() {
-                    panic!(
-                        "{}:{}: #endif without matching #ifdef/#ifndef",
-                        path.display(),
-                        line_num
-                    );
-                }
-                return vec![];
-            }
-
-            // --- Skip lines in inactive blocks ---
-            if stack.iter().any(|&v| !v) {
-                return vec![];
+) -> Result<String, CrmError> {
+    let mut processed_lines: Vec<String> = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let line_num = i + 1;
+        let t = line.trim();
+        let line_error = |message: String| shader_error(path, format!("line {}: {}", line_num, message));
+
+        // --- #ifdef ---
+        if let Some(rest) = t.strip_prefix("#ifdef ") {
+            let name = rest.trim();
+            let value = *defines
+                .get(name)
+                .ok_or_else(|| line_error(format!("unknown preprocessing define '{}' in #ifdef", name)))?;
+            stack.push(value);
+            continue;
+        }
+
+        // --- #ifndef ---
+        if let Some(rest) = t.strip_prefix("#ifndef ") {
+            let name = rest.trim();
+            let value = *defines
+                .get(name)
+                .ok_or_else(|| line_error(format!("unknown preprocessing define '{}' in #ifndef", name)))?;
+            stack.push(!value);
+            continue;
+        }
+
+        // --- #else ---
+        if t.starts_with("#else") {
+            let v = stack
+                .last_mut()
+                .ok_or_else(|| line_error("#else without matching #ifdef/#ifndef".to_string()))?;
+            *v = !*v;
+            continue;
+        }
+
+        // --- #endif ---
+        if t.starts_with("#endif") {
+            if stack.pop().is_none() {
+                return Err(line_error("#endif without matching #ifdef/#ifndef".to_string()));
             }
+            continue;
+        }
+
+        // --- Skip lines in inactive blocks ---
+        if stack.iter().any(|&v| !v) {
+            continue;
+        }
+
+        // --- #include ---
+        if let Some(rest) = t.strip_prefix("#include \"") {
+            let p = rest
+                .strip_suffix('"')
+                .ok_or_else(|| line_error("malformed #include directive: missing closing quote".to_string()))?;
+
+            let parent = path
+                .parent()
+                .ok_or_else(|| line_error("cannot resolve relative #include: shader path has no parent directory".to_string()))?;
+            let inc_path = parent.join(p);
+
+            let inc_source = read_source(&inc_path)?;
+            let inc_processed = preprocess_wgsl(&inc_path, &inc_source, defines, stack)?;
+
+            processed_lines.extend(inc_processed.lines().map(|s| s.to_string()));
+            continue;
+        }
+
+        // --- Normal line ---
+        processed_lines.push(line.to_string());
+    }
 
-            // --- #include ---
-            if let Some(rest) = t.strip_prefix("#include \"") {
-                let p = rest.strip_suffix('"').expect(&format!(
-                    "{}:{}: Malformed #include directive: missing closing quote",
-                    path.display(),
-                    line_num
-                ));
-
-                let parent = path.parent().expect("Cannot resolve relative #include: shader path has no parent directory");
-                let mut inc_path = parent.to_path_buf();
-                inc_path.push(p);
-
-                let inc_source = std::fs::read_to_string(&inc_path).unwrap_or_else(|e| {
-                    panic!(
-                        "{}:{}: Failed to read included shader \"{}\" (resolved to {}): {}",
-                        path.display(),
-                        line_num,
-                        p,
-                        inc_path.display(),
-                        e
-                    );
-                });
-
-                let inc_processed = preprocess_wgsl(&inc_path, &inc_source, defines, stack);
-
-                return inc_processed
-                    .lines()
-                    .map(|s| s.to_string())
-                    .collect::<Vec<_>>();
-            }
-
-            // --- Normal line ---
-            vec![line.to_string()]
-        })
-        .collect();
-
-    processed_lines.join("\n") + "\n"
+    Ok(processed_lines.join("\n") + "\n")
 }
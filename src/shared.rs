@@ -0,0 +1,212 @@
+//! A [`RenderManager`] whose lookups take `&self`.
+//!
+//! Every cache lookup of the [`RenderManager`] takes `&mut self`, so a manager stored
+//! inside a larger renderer struct has to be borrowed mutably by whoever draws, and
+//! two helpers holding `&self` of that struct cannot both use it mid-encode.
+//! [`SharedRenderManager`] keeps the manager behind a lock and returns owned,
+//! cheaply cloned wgpu handles instead of references:
+//! ```ignore
+//! struct WorldRenderer {
+//!     render_manager: SharedRenderManager,
+//!     terrain: TerrainPass,
+//!     water: WaterPass,
+//! }
+//!
+//! impl WorldRenderer {
+//!     fn draw(&self, pass: &mut RenderPass) {
+//!         // both passes only need `&self.render_manager`
+//!         self.terrain.draw(&self.render_manager, pass);
+//!         self.water.draw(&self.render_manager, pass);
+//!     }
+//! }
+//! ```
+//! The lock is held only for the duration of one call. Everything not mirrored here
+//! is reached through [`lock`](SharedRenderManager::lock); calling back into the
+//! shared manager while that guard is alive deadlocks.
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, RenderPass, TextureView};
+use crate::bind_group_builder::BuiltBindGroup;
+use crate::cache_key::CacheKey;
+use crate::error::CrmError;
+use crate::handles::{BindGroupId, MaterialRequest};
+use crate::pipelines::{PipelineOptions, TextureAccess};
+use crate::renderer::RenderManager;
+use crate::typed_bind_group::TypedBindGroup;
+
+/// A [`RenderManager`] behind a lock, see the [module docs](self).
+pub struct SharedRenderManager {
+    inner: Mutex<RenderManager>,
+}
+
+impl SharedRenderManager {
+    pub fn new(render_manager: RenderManager) -> Self {
+        Self {
+            inner: Mutex::new(render_manager),
+        }
+    }
+
+    /// The wrapped manager, for everything without a `&self` variant here.
+    pub fn lock(&self) -> MutexGuard<'_, RenderManager> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn into_inner(self) -> RenderManager {
+        self.inner.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// See [`RenderManager::begin_frame`].
+    pub fn begin_frame(&self) {
+        self.lock().begin_frame();
+    }
+
+    /// See [`RenderManager::end_frame`].
+    pub fn end_frame(&self, encoder: &mut CommandEncoder) {
+        self.lock().end_frame(encoder);
+    }
+
+    pub fn frame_index(&self) -> u64 {
+        self.lock().frame_index()
+    }
+
+    /// See [`RenderManager::render_with_textures`].
+    pub fn render_with_textures(
+        &self,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) {
+        self.lock().render_with_textures(texture_views, shader_path, options, uniforms, pass);
+    }
+
+    /// See [`RenderManager::try_render_with_textures`].
+    pub fn try_render_with_textures(
+        &self,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        self.lock().try_render_with_textures(texture_views, shader_path, options, uniforms, pass)
+    }
+
+    /// See [`RenderManager::render_with_material_key`].
+    pub fn render_with_material_key<K: CacheKey>(
+        &self,
+        key: &K,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) {
+        self.lock().render_with_material_key(key, texture_views, shader_path, options, uniforms, pass);
+    }
+
+    /// See [`RenderManager::try_render_with_material_key`].
+    pub fn try_render_with_material_key<K: CacheKey>(
+        &self,
+        key: &K,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+        pass: &mut RenderPass,
+    ) -> Result<(), CrmError> {
+        self.lock().try_render_with_material_key(key, texture_views, shader_path, options, uniforms, pass)
+    }
+
+    /// See [`RenderManager::material_layout`].
+    pub fn material_layout(&self, texture_views: &[&TextureView], access: &[TextureAccess]) -> BindGroupLayout {
+        self.lock().material_layout(texture_views, access)
+    }
+
+    /// See [`RenderManager::try_material_layout`].
+    pub fn try_material_layout(&self, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BindGroupLayout, CrmError> {
+        self.lock().try_material_layout(texture_views, access)
+    }
+
+    /// See [`RenderManager::material_bind_group`].
+    pub fn material_bind_group(&self, texture_views: &[&TextureView], access: &[TextureAccess]) -> BindGroup {
+        self.lock().material_bind_group(texture_views, access)
+    }
+
+    /// See [`RenderManager::try_material_bind_group`].
+    pub fn try_material_bind_group(&self, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BindGroup, CrmError> {
+        self.lock().try_material_bind_group(texture_views, access)
+    }
+
+    /// See [`RenderManager::keyed_material_bind_group`].
+    pub fn keyed_material_bind_group<K: CacheKey>(&self, key: &K, texture_views: &[&TextureView], access: &[TextureAccess]) -> BuiltBindGroup {
+        self.lock().keyed_material_bind_group(key, texture_views, access)
+    }
+
+    /// See [`RenderManager::try_keyed_material_bind_group`].
+    pub fn try_keyed_material_bind_group<K: CacheKey>(&self, key: &K, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BuiltBindGroup, CrmError> {
+        self.lock().try_keyed_material_bind_group(key, texture_views, access)
+    }
+
+    /// See [`RenderManager::typed_layout`].
+    pub fn typed_layout<T: TypedBindGroup>(&self) -> BindGroupLayout {
+        self.lock().typed_layout::<T>()
+    }
+
+    /// See [`RenderManager::typed_bind_group`].
+    pub fn typed_bind_group<T: TypedBindGroup>(&self, resources: &T) -> BuiltBindGroup {
+        self.lock().typed_bind_group(resources)
+    }
+
+    /// See [`RenderManager::try_typed_bind_group`].
+    pub fn try_typed_bind_group<T: TypedBindGroup>(&self, resources: &T) -> Result<BuiltBindGroup, CrmError> {
+        self.lock().try_typed_bind_group(resources)
+    }
+
+    /// See [`RenderManager::register_bind_group`].
+    pub fn register_bind_group(&self, bind_group: &BindGroup) -> BindGroupId {
+        self.lock().register_bind_group(bind_group)
+    }
+
+    /// See [`RenderManager::material_bind_group_id`].
+    pub fn material_bind_group_id(&self, texture_views: &[&TextureView], access: &[TextureAccess]) -> BindGroupId {
+        self.lock().material_bind_group_id(texture_views, access)
+    }
+
+    /// See [`RenderManager::try_material_bind_group_id`].
+    pub fn try_material_bind_group_id(&self, texture_views: &[&TextureView], access: &[TextureAccess]) -> Result<BindGroupId, CrmError> {
+        self.lock().try_material_bind_group_id(texture_views, access)
+    }
+
+    /// See [`RenderManager::uniform_bind_group_id`].
+    pub fn uniform_bind_group_id(&self, uniforms: &[&Buffer]) -> BindGroupId {
+        self.lock().uniform_bind_group_id(uniforms)
+    }
+
+    /// See [`RenderManager::get_or_create_many`].
+    pub fn get_or_create_many(&self, requests: &[MaterialRequest]) -> Vec<BindGroupId> {
+        self.lock().get_or_create_many(requests)
+    }
+
+    /// See [`RenderManager::try_get_or_create_many`].
+    pub fn try_get_or_create_many(&self, requests: &[MaterialRequest]) -> Result<Vec<BindGroupId>, CrmError> {
+        self.lock().try_get_or_create_many(requests)
+    }
+
+    /// The bind group behind `id`, see [`RenderManager::resolve`].
+    pub fn resolve(&self, id: BindGroupId) -> BindGroup {
+        self.lock().resolve(id).clone()
+    }
+
+    /// See [`RenderManager::try_resolve`].
+    pub fn try_resolve(&self, id: BindGroupId) -> Option<BindGroup> {
+        self.lock().try_resolve(id).cloned()
+    }
+}
+
+impl From<RenderManager> for SharedRenderManager {
+    fn from(render_manager: RenderManager) -> Self {
+        Self::new(render_manager)
+    }
+}
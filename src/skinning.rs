@@ -0,0 +1,237 @@
+//! Compute-based skeletal skinning.
+//!
+//! [`SkinningPass`] deforms the vertices of a [`SkinnedMesh`] by up to four joints
+//! each and writes positions and normals into a regular vertex buffer. The rest of
+//! the pipeline then draws that buffer like any static mesh, so skinned meshes work
+//! with every render path of the crate without a skinning variant of each shader.
+//!
+//! The skin matrix of a joint is `pose[joint] * inverse_bind[joint]`. Inverse bind
+//! matrices are uploaded once per mesh, poses every frame with [`SkinnedMesh::set_pose`].
+//!
+//! ## Output layout
+//! Each output vertex gets `position: vec3<f32>` followed by `normal: vec3<f32>`
+//! (24 bytes), written at [`SkinOutput::offset`] inside a vertex of
+//! [`SkinOutput::stride`] bytes. This allows writing into interleaved buffers that
+//! also hold UVs or tangents. The output buffer needs `STORAGE | VERTEX` usage.
+use wgpu::util::DeviceExt;
+use wgpu::*;
+use crate::gpu_util;
+use crate::labels::namespaced;
+
+const SKINNING_SHADER: &str = r#"
+struct SkinVertex {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+};
+
+struct SkinParams {
+    vertex_count: u32,
+    stride: u32,
+    offset: u32,
+    joint_count: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: SkinParams;
+@group(0) @binding(1) var<storage, read> vertices: array<SkinVertex>;
+@group(0) @binding(2) var<storage, read> inverse_bind: array<mat4x4<f32>>;
+@group(0) @binding(3) var<storage, read> pose: array<mat4x4<f32>>;
+@group(0) @binding(4) var<storage, read_write> output: array<f32>;
+
+fn skin_matrix(joint: u32) -> mat4x4<f32> {
+    let j = min(joint, params.joint_count - 1u);
+    return pose[j] * inverse_bind[j];
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.vertex_count {
+        return;
+    }
+    let v = vertices[id.x];
+    let skin = skin_matrix(v.joints.x) * v.weights.x
+        + skin_matrix(v.joints.y) * v.weights.y
+        + skin_matrix(v.joints.z) * v.weights.z
+        + skin_matrix(v.joints.w) * v.weights.w;
+
+    let position = (skin * vec4<f32>(v.position.xyz, 1.0)).xyz;
+    let normal = normalize((skin * vec4<f32>(v.normal.xyz, 0.0)).xyz);
+
+    let base = id.x * params.stride + params.offset;
+    output[base + 0u] = position.x;
+    output[base + 1u] = position.y;
+    output[base + 2u] = position.z;
+    output[base + 3u] = normal.x;
+    output[base + 4u] = normal.y;
+    output[base + 5u] = normal.z;
+}
+"#;
+
+/// Bind-pose vertex of a skinned mesh, as stored on the GPU.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    /// Bind-pose position, `w` unused.
+    pub position: [f32; 4],
+    /// Bind-pose normal, `w` unused.
+    pub normal: [f32; 4],
+    /// Indices of up to four influencing joints.
+    pub joints: [u32; 4],
+    /// Weights of `joints`, summing to 1.
+    pub weights: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkinParams {
+    vertex_count: u32,
+    stride: u32,
+    offset: u32,
+    joint_count: u32,
+}
+
+/// Where skinned positions and normals are written inside each output vertex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkinOutput {
+    /// Size of one output vertex in bytes, a multiple of 4.
+    pub stride: u32,
+    /// Byte offset of the position inside a vertex, a multiple of 4.
+    pub offset: u32,
+}
+
+impl SkinOutput {
+    /// Position and normal of a [`TangentVertex`](crate::meshes::TangentVertex) buffer.
+    /// Tangents keep their bind-pose value.
+    pub const TANGENT_VERTEX: Self = Self { stride: 48, offset: 0 };
+}
+
+impl Default for SkinOutput {
+    /// Tightly packed position + normal.
+    fn default() -> Self {
+        Self { stride: 24, offset: 0 }
+    }
+}
+
+/// GPU-side data of one skinned mesh.
+pub struct SkinnedMesh {
+    vertices: Buffer,
+    vertex_count: u32,
+    inverse_bind: Buffer,
+    pose: Buffer,
+    joint_count: u32,
+    params: Buffer,
+    /// Bind group and the output buffer it was created for.
+    bind_group: Option<(Buffer, BindGroup)>,
+}
+
+impl SkinnedMesh {
+    /// Number of vertices written by each skinning dispatch.
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    /// Number of joints of the skeleton.
+    pub fn joint_count(&self) -> u32 {
+        self.joint_count
+    }
+
+    /// Upload the joint transforms (model space, column-major) for the next dispatch.
+    ///
+    /// Extra matrices are ignored; missing ones keep their previous value.
+    pub fn set_pose(&self, queue: &Queue, joints: &[[[f32; 4]; 4]]) {
+        let count = joints.len().min(self.joint_count as usize);
+        queue.write_buffer(&self.pose, 0, bytemuck::cast_slice(&joints[..count]));
+    }
+}
+
+/// Compute pass deforming [`SkinnedMesh`]es into vertex buffers.
+pub struct SkinningPass {
+    device: Device,
+    queue: Queue,
+    layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl SkinningPass {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let module = gpu_util::shader(device, "skinning shader", SKINNING_SHADER);
+        let layout = gpu_util::bind_group_layout(device, "skinning layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_entry(1, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(2, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(3, ShaderStages::COMPUTE, true),
+            gpu_util::storage_entry(4, ShaderStages::COMPUTE, false),
+        ]);
+        let pipeline = gpu_util::compute_pipeline(device, "skinning", &module, "main", &[&layout]);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            layout,
+            pipeline,
+        }
+    }
+
+    /// Upload a skinned mesh. The pose starts as the bind pose.
+    ///
+    /// ## Panics
+    /// Panics if `inverse_bind` is empty.
+    pub fn create_mesh(&self, label: &str, vertices: &[SkinVertex], inverse_bind: &[[[f32; 4]; 4]]) -> SkinnedMesh {
+        assert!(!inverse_bind.is_empty(), "Skinned mesh '{}' needs at least one joint", label);
+        let _span = trace_span!("create_skinned_mesh", label, vertices = vertices.len(), joints = inverse_bind.len());
+
+        let vertex_buffer = self.device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some(&namespaced(label)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: BufferUsages::STORAGE,
+        });
+        let inverse_bind_buffer = self.device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some(&namespaced("inverse bind matrices")),
+            contents: bytemuck::cast_slice(inverse_bind),
+            usage: BufferUsages::STORAGE,
+        });
+        let bind_pose: Vec<[[f32; 4]; 4]> = inverse_bind.iter().map(gpu_util::invert_affine).collect();
+        let pose = self.device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some(&namespaced("joint pose")),
+            contents: bytemuck::cast_slice(&bind_pose),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        let params = gpu_util::buffer(&self.device, "skinning params", size_of::<SkinParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+
+        SkinnedMesh {
+            vertices: vertex_buffer,
+            vertex_count: vertices.len() as u32,
+            inverse_bind: inverse_bind_buffer,
+            pose,
+            joint_count: inverse_bind.len() as u32,
+            params,
+            bind_group: None,
+        }
+    }
+
+    /// Record skinning `mesh` with its current pose into `output`.
+    pub fn skin(&self, encoder: &mut CommandEncoder, mesh: &mut SkinnedMesh, output: &Buffer, layout: SkinOutput) {
+        debug_assert!(layout.stride % 4 == 0 && layout.offset % 4 == 0, "Skin output stride and offset must be multiples of 4");
+        let params = SkinParams {
+            vertex_count: mesh.vertex_count,
+            stride: layout.stride / 4,
+            offset: layout.offset / 4,
+            joint_count: mesh.joint_count,
+        };
+        self.queue.write_buffer(&mesh.params, 0, bytemuck::bytes_of(&params));
+
+        if mesh.bind_group.as_ref().is_none_or(|(buffer, _)| buffer != output) {
+            let bind_group = gpu_util::bind_group(&self.device, "skinning", &self.layout, &[
+                mesh.params.as_entire_binding(),
+                mesh.vertices.as_entire_binding(),
+                mesh.inverse_bind.as_entire_binding(),
+                mesh.pose.as_entire_binding(),
+                output.as_entire_binding(),
+            ]);
+            mesh.bind_group = Some((output.clone(), bind_group));
+        }
+
+        let (_, bind_group) = mesh.bind_group.as_ref().unwrap();
+        gpu_util::dispatch(encoder, "skinning", &self.pipeline, &[bind_group], [mesh.vertex_count.div_ceil(64), 1, 1]);
+    }
+}
@@ -0,0 +1,534 @@
+//! Skybox rendering from a cubemap or a procedural sky, and the environment binding
+//! for PBR materials.
+//!
+//! A [`Skybox`] always draws from a cubemap: either one supplied with
+//! [`set_cubemap`](Skybox::set_cubemap) / [`load_cubemap`](Skybox::load_cubemap), or
+//! one baked by a compute pass from [`ProceduralSky`] parameters. The sky is drawn as
+//! a fullscreen pass at the far plane with depth testing and no depth writes, so it
+//! only covers pixels no geometry was drawn to. Draw it after the opaque geometry.
+//!
+//! The same cubemap is exposed to materials: [`render_material`](Skybox::render_material)
+//! binds it as `@group(2)` next to the material and uniform groups (paste
+//! [`SKY_ENVIRONMENT_WGSL`] into the shader), and
+//! [`environment_bind_group`](Skybox::environment_bind_group) serves custom pipelines.
+//!
+//! ## Frame flow
+//! ```ignore
+//! skybox.set_procedural(ProceduralSky { sun_direction: sun, ..Default::default() });
+//! // Inside the main pass, after opaque geometry
+//! skybox.render(&mut pass, &SkyCamera { inverse_view_proj, position }, &SkyTarget {
+//!     format: TextureFormat::Rgba16Float,
+//!     depth_format: Some(TextureFormat::Depth32Float),
+//!     ..Default::default()
+//! });
+//! ```
+//!
+//! The camera uniform is written with `Queue::write_buffer`, so draw the skybox once
+//! per submission.
+use std::collections::HashMap;
+use std::path::Path;
+use wgpu::util::DeviceExt;
+use wgpu::*;
+use crate::gpu_util;
+use crate::pipelines::PipelineOptions;
+use crate::renderer::{ExtraBindGroup, RenderManager};
+use crate::labels::namespaced;
+
+const SKY_BAKE_SHADER: &str = r#"
+struct ProceduralSky {
+    zenith_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    ground_color: vec4<f32>,
+    sun_direction: vec4<f32>, // xyz towards the sun, w = angular radius
+    sun_color: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> sky: ProceduralSky;
+@group(0) @binding(1) var faces: texture_storage_2d_array<rgba16float, write>;
+
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { return vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { return vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { return vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(faces);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    let dir = normalize(face_direction(id.z, uv));
+
+    var color: vec3<f32>;
+    if dir.y >= 0.0 {
+        color = mix(sky.horizon_color.rgb, sky.zenith_color.rgb, sqrt(dir.y));
+    } else {
+        color = mix(sky.horizon_color.rgb, sky.ground_color.rgb, sqrt(-dir.y));
+    }
+    let sun_cos = dot(dir, normalize(sky.sun_direction.xyz));
+    let radius_cos = cos(sky.sun_direction.w);
+    let edge_cos = cos(sky.sun_direction.w * 1.5);
+    color += sky.sun_color.rgb * smoothstep(edge_cos, radius_cos, sun_cos);
+
+    textureStore(faces, vec2<i32>(id.xy), i32(id.z), vec4<f32>(color, 1.0));
+}
+"#;
+
+const SKY_RENDER_SHADER: &str = r#"
+struct SkyUniform {
+    inverse_view_proj: mat4x4<f32>,
+    camera_position: vec3<f32>,
+    far_depth: f32,
+    intensity: f32,
+    _pad0: f32,
+    _pad1: f32,
+    _pad2: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: SkyUniform;
+@group(0) @binding(1) var sky_sampler: sampler;
+@group(0) @binding(2) var sky_texture: texture_cube<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    var out: VertexOutput;
+    out.position = vec4<f32>(positions[idx], camera.far_depth, 1.0);
+    out.ndc = positions[idx];
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Any depth inside the frustum lies on the view ray of this pixel.
+    let world = camera.inverse_view_proj * vec4<f32>(in.ndc, 0.5, 1.0);
+    let dir = world.xyz / world.w - camera.camera_position;
+    return vec4<f32>(textureSample(sky_texture, sky_sampler, dir).rgb * camera.intensity, 1.0);
+}
+"#;
+
+/// WGSL declarations for shaders drawn with [`Skybox::render_material`].
+pub const SKY_ENVIRONMENT_WGSL: &str = r#"
+@group(2) @binding(0) var environment_sampler: sampler;
+@group(2) @binding(1) var environment_texture: texture_cube<f32>;
+
+fn sample_environment(direction: vec3<f32>) -> vec3<f32> {
+    return textureSample(environment_texture, environment_sampler, direction).rgb;
+}
+"#;
+
+/// Parameters of the baked procedural sky.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProceduralSky {
+    pub zenith_color: [f32; 3],
+    pub horizon_color: [f32; 3],
+    pub ground_color: [f32; 3],
+    /// Direction towards the sun. Does not need to be normalized.
+    pub sun_direction: [f32; 3],
+    /// Angular radius of the sun disk in radians.
+    pub sun_radius: f32,
+    /// Linear HDR color of the sun disk, added on top of the sky.
+    pub sun_color: [f32; 3],
+    /// Edge length of the baked cubemap faces.
+    pub resolution: u32,
+}
+
+impl Default for ProceduralSky {
+    fn default() -> Self {
+        Self {
+            zenith_color: [0.15, 0.35, 0.8],
+            horizon_color: [0.7, 0.8, 0.95],
+            ground_color: [0.25, 0.22, 0.2],
+            sun_direction: [0.3, 0.8, 0.5],
+            sun_radius: 0.02,
+            sun_color: [20.0, 18.0, 15.0],
+            resolution: 256,
+        }
+    }
+}
+
+/// Camera used to reconstruct view rays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyCamera {
+    /// Inverse of `projection * view`, column-major.
+    pub inverse_view_proj: [[f32; 4]; 4],
+    /// World-space camera position.
+    pub position: [f32; 3],
+}
+
+/// Render target description, also the key of the cached sky pipelines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SkyTarget {
+    pub format: TextureFormat,
+    pub depth_format: Option<TextureFormat>,
+    pub msaa_samples: u32,
+    /// Depth buffer cleared to 0.0 with a `Greater` compare instead of 1.0 / `Less`.
+    pub reversed_z: bool,
+}
+
+impl Default for SkyTarget {
+    fn default() -> Self {
+        Self {
+            format: TextureFormat::Rgba16Float,
+            depth_format: None,
+            msaa_samples: 1,
+            reversed_z: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProceduralSkyUniform {
+    zenith_color: [f32; 4],
+    horizon_color: [f32; 4],
+    ground_color: [f32; 4],
+    sun_direction: [f32; 4],
+    sun_color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    far_depth: f32,
+    intensity: f32,
+    _pad: [f32; 3],
+}
+
+/// Sky pass and environment cubemap.
+pub struct Skybox {
+    device: Device,
+    queue: Queue,
+    sampler: Sampler,
+    /// Cube view of the current environment.
+    environment: TextureView,
+    intensity: f32,
+    camera: Buffer,
+    bake_layout: BindGroupLayout,
+    bake_pipeline: ComputePipeline,
+    bake_params: Buffer,
+    render_module: ShaderModule,
+    render_layout: BindGroupLayout,
+    render_bind_group: BindGroup,
+    pipelines: HashMap<SkyTarget, RenderPipeline>,
+    environment_entries: Vec<BindGroupLayoutEntry>,
+    environment_layout: BindGroupLayout,
+    environment_bind_group: BindGroup,
+}
+
+impl Skybox {
+    /// Create a skybox showing the default [`ProceduralSky`].
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let bake_module = gpu_util::shader(device, "sky bake shader", SKY_BAKE_SHADER);
+        let bake_layout = gpu_util::bind_group_layout(device, "sky bake layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::COMPUTE),
+            gpu_util::storage_texture_entry(
+                1,
+                ShaderStages::COMPUTE,
+                TextureFormat::Rgba16Float,
+                StorageTextureAccess::WriteOnly,
+                TextureViewDimension::D2Array,
+            ),
+        ]);
+        let bake_pipeline = gpu_util::compute_pipeline(device, "sky bake", &bake_module, "main", &[&bake_layout]);
+        let bake_params = gpu_util::buffer(
+            device,
+            "sky bake params",
+            size_of::<ProceduralSkyUniform>() as u64,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        );
+
+        let render_module = gpu_util::shader(device, "sky shader", SKY_RENDER_SHADER);
+        let render_layout = gpu_util::bind_group_layout(device, "sky layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::VERTEX | ShaderStages::FRAGMENT),
+            gpu_util::sampler_entry(1, ShaderStages::FRAGMENT, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(2, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: true }, TextureViewDimension::Cube),
+        ]);
+        let environment_entries = vec![
+            gpu_util::sampler_entry(0, ShaderStages::FRAGMENT | ShaderStages::COMPUTE, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(
+                1,
+                ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                TextureSampleType::Float { filterable: true },
+                TextureViewDimension::Cube,
+            ),
+        ];
+        let environment_layout = gpu_util::bind_group_layout(device, "environment layout", &environment_entries);
+
+        let camera = gpu_util::buffer(device, "sky camera", size_of::<SkyUniform>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some(&namespaced("sky sampler")),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: MipmapFilterMode::Linear,
+            ..Default::default()
+        });
+
+        // Placeholder until the first bake below replaces it.
+        let environment = create_cubemap(device, "sky environment", 1, TextureFormat::Rgba16Float, TextureUsages::TEXTURE_BINDING)
+            .create_view(&cube_view_descriptor(&namespaced("cubemap view")));
+        let render_bind_group = gpu_util::bind_group(device, "sky", &render_layout, &[
+            camera.as_entire_binding(),
+            BindingResource::Sampler(&sampler),
+            BindingResource::TextureView(&environment),
+        ]);
+        let environment_bind_group = gpu_util::bind_group(device, "environment", &environment_layout, &[
+            BindingResource::Sampler(&sampler),
+            BindingResource::TextureView(&environment),
+        ]);
+
+        let mut skybox = Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            sampler,
+            environment,
+            intensity: 1.0,
+            camera,
+            bake_layout,
+            bake_pipeline,
+            bake_params,
+            render_module,
+            render_layout,
+            render_bind_group,
+            pipelines: HashMap::new(),
+            environment_entries,
+            environment_layout,
+            environment_bind_group,
+        };
+        skybox.set_procedural(ProceduralSky::default());
+        skybox
+    }
+
+    /// Bake `sky` into a new environment cubemap and show it.
+    ///
+    /// The bake is submitted immediately, so call this when the sky changes, not every frame.
+    pub fn set_procedural(&mut self, sky: ProceduralSky) {
+        let _span = trace_span!("sky_bake", resolution = sky.resolution);
+        let size = sky.resolution.max(1);
+        let texture = create_cubemap(
+            &self.device,
+            "procedural sky",
+            size,
+            TextureFormat::Rgba16Float,
+            TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        );
+        let params = ProceduralSkyUniform {
+            zenith_color: extend(sky.zenith_color, 0.0),
+            horizon_color: extend(sky.horizon_color, 0.0),
+            ground_color: extend(sky.ground_color, 0.0),
+            sun_direction: extend(sky.sun_direction, sky.sun_radius),
+            sun_color: extend(sky.sun_color, 0.0),
+        };
+        self.queue.write_buffer(&self.bake_params, 0, bytemuck::bytes_of(&params));
+
+        let faces = texture.create_view(&TextureViewDescriptor {
+            label: Some(&namespaced("procedural sky faces")),
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let bind_group = gpu_util::bind_group(&self.device, "sky bake", &self.bake_layout, &[
+            self.bake_params.as_entire_binding(),
+            BindingResource::TextureView(&faces),
+        ]);
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor { label: Some(&namespaced("sky bake")) });
+        gpu_util::dispatch(&mut encoder, "sky bake", &self.bake_pipeline, &[&bind_group], [size.div_ceil(8), size.div_ceil(8), 6]);
+        self.queue.submit([encoder.finish()]);
+
+        self.set_cubemap(&texture.create_view(&cube_view_descriptor(&namespaced("cubemap view"))));
+    }
+
+    /// Show an existing cubemap, e.g. one loaded by the application.
+    ///
+    /// `view` must have `TextureViewDimension::Cube` and a filterable float format.
+    pub fn set_cubemap(&mut self, view: &TextureView) {
+        self.environment = view.clone();
+        self.render_bind_group = gpu_util::bind_group(&self.device, "sky", &self.render_layout, &[
+            self.camera.as_entire_binding(),
+            BindingResource::Sampler(&self.sampler),
+            BindingResource::TextureView(&self.environment),
+        ]);
+        self.environment_bind_group = gpu_util::bind_group(&self.device, "environment", &self.environment_layout, &[
+            BindingResource::Sampler(&self.sampler),
+            BindingResource::TextureView(&self.environment),
+        ]);
+    }
+
+    /// Upload six square RGBA8 (sRGB) faces in `+X, -X, +Y, -Y, +Z, -Z` order and show them.
+    ///
+    /// ## Panics
+    /// Panics if a face is not `size * size * 4` bytes.
+    pub fn load_cubemap(&mut self, size: u32, faces: [&[u8]; 6]) {
+        for face in faces {
+            assert_eq!(face.len(), (size * size * 4) as usize, "Cubemap face must be {0}x{0} RGBA8", size);
+        }
+        let data: Vec<u8> = faces.concat();
+        let texture = self.device.create_texture_with_data(
+            &self.queue,
+            &cubemap_descriptor(&namespaced("skybox cubemap"), size, TextureFormat::Rgba8UnormSrgb, TextureUsages::TEXTURE_BINDING),
+            util::TextureDataOrder::LayerMajor,
+            &data,
+        );
+        self.set_cubemap(&texture.create_view(&cube_view_descriptor(&namespaced("cubemap view"))));
+    }
+
+    /// Brightness multiplier of the drawn sky. Does not affect the environment binding.
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// Cube view of the current environment.
+    pub fn environment_view(&self) -> &TextureView {
+        &self.environment
+    }
+
+    /// Layout of the environment bind group: filtering sampler at binding 0,
+    /// `texture_cube<f32>` at binding 1.
+    pub fn environment_layout(&self) -> &BindGroupLayout {
+        &self.environment_layout
+    }
+
+    /// Bind group of the current environment, see [`environment_layout`](Self::environment_layout).
+    pub fn environment_bind_group(&self) -> &BindGroup {
+        &self.environment_bind_group
+    }
+
+    /// Draw the sky into `pass`.
+    pub fn render(&mut self, pass: &mut RenderPass, camera: &SkyCamera, target: &SkyTarget) {
+        let uniform = SkyUniform {
+            inverse_view_proj: camera.inverse_view_proj,
+            camera_position: camera.position,
+            far_depth: if target.reversed_z { 0.0 } else { 1.0 },
+            intensity: self.intensity,
+            _pad: [0.0; 3],
+        };
+        self.queue.write_buffer(&self.camera, 0, bytemuck::bytes_of(&uniform));
+
+        let pipeline = self.pipelines.entry(*target).or_insert_with(|| {
+            let _span = trace_span!("sky_pipeline_miss");
+            create_sky_pipeline(&self.device, &self.render_module, &self.render_layout, target)
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.render_bind_group, &[]);
+        pass.draw(0..4, 0..1);
+    }
+
+    /// Set pipeline and bind groups like
+    /// [`render_with_textures`](RenderManager::render_with_textures), with the
+    /// environment bound as `@group(2)`.
+    ///
+    /// Like `render_with_textures`, this does not issue a draw call.
+    pub fn render_material(
+        &self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+    ) {
+        let environment = ExtraBindGroup {
+            name: "environment",
+            entries: &self.environment_entries,
+            layout: &self.environment_layout,
+            bind_group: &self.environment_bind_group,
+        };
+        manager.render_with_extra_groups(texture_views, shader_path, options, uniforms, &[environment], pass);
+    }
+}
+
+fn create_sky_pipeline(device: &Device, module: &ShaderModule, layout: &BindGroupLayout, target: &SkyTarget) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("sky pipeline layout")),
+        bind_group_layouts: &[layout],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("sky pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format: target.format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: target.depth_format.map(|format| DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare: if target.reversed_z { CompareFunction::GreaterEqual } else { CompareFunction::LessEqual },
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: target.msaa_samples,
+            ..Default::default()
+        },
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+fn cubemap_descriptor(label: &str, size: u32, format: TextureFormat, usage: TextureUsages) -> TextureDescriptor<'_> {
+    TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage,
+        view_formats: &[],
+    }
+}
+
+fn create_cubemap(device: &Device, label: &str, size: u32, format: TextureFormat, usage: TextureUsages) -> Texture {
+    device.create_texture(&cubemap_descriptor(&namespaced(label), size, format, usage))
+}
+
+fn cube_view_descriptor(label: &str) -> TextureViewDescriptor<'_> {
+    TextureViewDescriptor {
+        label: Some(label),
+        dimension: Some(TextureViewDimension::Cube),
+        ..Default::default()
+    }
+}
+
+fn extend(v: [f32; 3], w: f32) -> [f32; 4] {
+    [v[0], v[1], v[2], w]
+}
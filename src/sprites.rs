@@ -0,0 +1,495 @@
+//! Batched 2D sprites.
+//!
+//! A [`SpriteBatcher`] collects textured quads with position, size, rotation around a
+//! pivot, atlas UVs and tint, and draws them with as few draws and bind group switches
+//! as possible. Sprites are sorted by [`layer`](Sprite::layer); within a layer they are
+//! grouped by texture (e.g. atlas page) and scissor rectangle, all instances go into one
+//! shared vertex buffer, and every group becomes one instanced draw. Texture bind groups
+//! come from the [`RenderManager`] material cache, so a texture used by several
+//! batchers or frames is bound with the same bind group.
+//!
+//! UI panels and buttons are drawn as [nine-patches](SpriteBatcher::submit_nine_slice):
+//! the corners of the image keep their size, the edges stretch along one axis and the
+//! center along both, according to the border widths in a [`NineSlice`].
+//!
+//! Sprites of one layer may be reordered between textures, so sprites that must overlap
+//! in order belong on different layers. Positions and sizes are in pixels with the
+//! origin at the top left of the target.
+//! ```ignore
+//! let mut sprites = SpriteBatcher::new(&device, &queue, surface.ui_format());
+//! // Every frame
+//! sprites.submit(&atlas_page, Sprite::new([200.0, 120.0], [64.0, 64.0]).with_uv_rect(frame_uv).with_rotation(angle));
+//! sprites.set_scissor(Some([0, 0, 320, 240]));
+//! sprites.submit(&minimap, Sprite::new([0.0, 0.0], [320.0, 240.0]).with_origin([0.0, 0.0]).with_layer(1));
+//! sprites.set_scissor(None);
+//! sprites.submit_nine_slice(&ui, &NineSlice::new([48.0, 48.0], [12.0; 4]), Sprite::new([40.0, 40.0], [300.0, 120.0]).with_origin([0.0, 0.0]).with_uv_rect(panel_uv));
+//! sprites.upload(&mut render_manager, width, height);
+//! // Inside a render pass writing the target
+//! sprites.render(&mut pass);
+//! sprites.clear();
+//! ```
+use std::collections::HashMap;
+use wgpu::*;
+use crate::gpu_util;
+use crate::renderer::RenderManager;
+use crate::labels::namespaced;
+
+const SPRITE_SHADER: &str = r#"
+struct Screen {
+    size: vec2<f32>,
+    _pad: vec2<f32>,
+};
+
+struct SpriteInstance {
+    @location(0) rect: vec4<f32>,
+    @location(1) uv_rect: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) pivot: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var s_sprite: sampler;
+@group(0) @binding(1) var t_sprite: texture_2d<f32>;
+@group(1) @binding(0) var<uniform> screen: Screen;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32, sprite: SpriteInstance) -> VertexOutput {
+    let corner = vec2<f32>(f32(idx & 1u), f32(idx >> 1u));
+    let local = (corner - sprite.pivot.xy) * sprite.rect.zw;
+    let cs = sprite.pivot.zw;
+    let pixel = sprite.rect.xy + vec2<f32>(local.x * cs.x - local.y * cs.y, local.x * cs.y + local.y * cs.x);
+    var out: VertexOutput;
+    out.position = vec4<f32>(pixel / screen.size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = mix(sprite.uv_rect.xy, sprite.uv_rect.zw, corner);
+    out.color = sprite.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_sprite, s_sprite, in.uv) * in.color;
+}
+"#;
+
+const SPRITE_ATTRIBUTES: [VertexAttribute; 4] =
+    wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Float32x4];
+
+const SPRITE_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<SpriteInstance>() as u64,
+    step_mode: VertexStepMode::Instance,
+    attributes: &SPRITE_ATTRIBUTES,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteInstance {
+    /// Pivot position and size in pixels.
+    rect: [f32; 4],
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+    /// Pivot within the quad, then cosine and sine of the rotation.
+    pivot: [f32; 4],
+}
+
+/// One textured quad submitted to a [`SpriteBatcher`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    /// Where the [`origin`](Self::origin) lands, in pixels.
+    pub position: [f32; 2],
+    /// Width and height in pixels.
+    pub size: [f32; 2],
+    /// Clockwise rotation around the origin in radians.
+    pub rotation: f32,
+    /// Pivot of position and rotation within the quad, from `[0, 0]` (top left) to
+    /// `[1, 1]` (bottom right).
+    pub origin: [f32; 2],
+    /// UVs of the top left and bottom right corner, e.g. a frame in an atlas. Swap them
+    /// to flip the sprite.
+    pub uv_rect: [f32; 4],
+    /// Multiplied with the texture color, straight (not premultiplied) RGBA.
+    pub color: [f32; 4],
+    /// Lower layers are drawn first.
+    pub layer: i32,
+}
+
+impl Sprite {
+    /// An untinted, unrotated sprite of the whole texture centered on `position`.
+    pub fn new(position: [f32; 2], size: [f32; 2]) -> Self {
+        Self {
+            position,
+            size,
+            rotation: 0.0,
+            origin: [0.5, 0.5],
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            color: [1.0; 4],
+            layer: 0,
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_origin(mut self, origin: [f32; 2]) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_uv_rect(mut self, uv_rect: [f32; 4]) -> Self {
+        self.uv_rect = uv_rect;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+/// Border metadata of a nine-patch image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NineSlice {
+    /// Size in texels of the image, the part of the texture a sprite's
+    /// [`uv_rect`](Sprite::uv_rect) covers.
+    pub image_size: [f32; 2],
+    /// Left, top, right and bottom border widths in texels of the image.
+    pub border: [f32; 4],
+    /// Pixels per border texel, e.g. the UI scale factor.
+    pub border_scale: f32,
+}
+
+impl NineSlice {
+    pub fn new(image_size: [f32; 2], border: [f32; 4]) -> Self {
+        Self {
+            image_size,
+            border,
+            border_scale: 1.0,
+        }
+    }
+
+    pub fn with_border_scale(mut self, border_scale: f32) -> Self {
+        self.border_scale = border_scale;
+        self
+    }
+
+    /// The up to nine patches of `sprite` drawn with these borders. Borders wider than the
+    /// sprite shrink proportionally; empty patches are left out.
+    pub fn patches(&self, sprite: &Sprite) -> Vec<Sprite> {
+        let [left, top, right, bottom] = self.border.map(|border| border.max(0.0) * self.border_scale);
+        let [u0, v0, u1, v1] = sprite.uv_rect;
+        let [image_width, image_height] = self.image_size.map(|size| size.max(1.0));
+        let columns = slice_edges(sprite.size[0], left, right);
+        let rows = slice_edges(sprite.size[1], top, bottom);
+        let u = [0.0, self.border[0] / image_width, 1.0 - self.border[2] / image_width, 1.0].map(|t| u0 + (u1 - u0) * t);
+        let v = [0.0, self.border[1] / image_height, 1.0 - self.border[3] / image_height, 1.0].map(|t| v0 + (v1 - v0) * t);
+        // Every patch keeps the sprite's pivot, so the panel rotates as one piece.
+        let pivot = [sprite.origin[0] * sprite.size[0], sprite.origin[1] * sprite.size[1]];
+
+        let mut patches = Vec::with_capacity(9);
+        for row in 0..3 {
+            for column in 0..3 {
+                let size = [columns[column + 1] - columns[column], rows[row + 1] - rows[row]];
+                if size[0] <= 0.0 || size[1] <= 0.0 {
+                    continue;
+                }
+                patches.push(Sprite {
+                    size,
+                    origin: [(pivot[0] - columns[column]) / size[0], (pivot[1] - rows[row]) / size[1]],
+                    uv_rect: [u[column], v[row], u[column + 1], v[row + 1]],
+                    ..*sprite
+                });
+            }
+        }
+        patches
+    }
+}
+
+/// Start, inner edges and end of the three slices of `length` with borders `start` and
+/// `end`, shrunk proportionally if they do not fit.
+fn slice_edges(length: f32, start: f32, end: f32) -> [f32; 4] {
+    let length = length.max(0.0);
+    let fit = if start + end > length { length / (start + end) } else { 1.0 };
+    [0.0, start * fit, length - end * fit, length]
+}
+
+struct QueuedSprite {
+    texture: usize,
+    scissor: usize,
+    sprite: Sprite,
+}
+
+struct Batch {
+    texture: usize,
+    scissor: Option<[u32; 4]>,
+    first_instance: u32,
+    instance_count: u32,
+}
+
+/// Sorts and groups submitted sprites into instanced draws per texture.
+pub struct SpriteBatcher {
+    device: Device,
+    queue: Queue,
+    format: TextureFormat,
+    /// Built on the first upload, from the material layout of the first texture.
+    pipeline: Option<RenderPipeline>,
+    screen: Buffer,
+    screen_bind_group: BindGroup,
+    textures: Vec<TextureView>,
+    texture_lookup: HashMap<TextureView, usize>,
+    /// Bind group of every texture, resolved in [`upload`](Self::upload).
+    bind_groups: Vec<BindGroup>,
+    scissors: Vec<Option<[u32; 4]>>,
+    queued: Vec<QueuedSprite>,
+    batches: Vec<Batch>,
+    target_size: (u32, u32),
+    instances: Buffer,
+    instance_capacity: u64,
+    uploaded: bool,
+}
+
+impl SpriteBatcher {
+    /// Create a batcher drawing into targets of `target_format`.
+    pub fn new(device: &Device, queue: &Queue, target_format: TextureFormat) -> Self {
+        let screen = gpu_util::buffer(device, "sprite screen", 16, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let screen_layout = screen_layout(device);
+        let screen_bind_group = gpu_util::bind_group(device, "sprite screen", &screen_layout, &[screen.as_entire_binding()]);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            format: target_format,
+            pipeline: None,
+            screen,
+            screen_bind_group,
+            textures: Vec::new(),
+            texture_lookup: HashMap::new(),
+            bind_groups: Vec::new(),
+            scissors: vec![None],
+            queued: Vec::new(),
+            batches: Vec::new(),
+            target_size: (1, 1),
+            instances: instance_buffer(device, 256),
+            instance_capacity: 256,
+            uploaded: false,
+        }
+    }
+
+    /// Clip sprites submitted from now on to `x, y, width, height` in pixels, or not at
+    /// all with `None`.
+    pub fn set_scissor(&mut self, scissor: Option<[u32; 4]>) {
+        if self.scissors.last() != Some(&scissor) {
+            self.scissors.push(scissor);
+        }
+    }
+
+    /// Queue `sprite` with `texture`, a filterable 2D float texture such as an atlas page.
+    pub fn submit(&mut self, texture: &TextureView, sprite: Sprite) {
+        let texture = match self.texture_lookup.get(texture) {
+            Some(&index) => index,
+            None => {
+                self.textures.push(texture.clone());
+                self.texture_lookup.insert(texture.clone(), self.textures.len() - 1);
+                self.textures.len() - 1
+            }
+        };
+        self.queued.push(QueuedSprite {
+            texture,
+            scissor: self.scissors.len() - 1,
+            sprite,
+        });
+        self.uploaded = false;
+    }
+
+    /// Queue `sprite` as a nine-patch of its [`uv_rect`](Sprite::uv_rect) with the
+    /// borders of `slice`. The patches share the texture, so they land in one batch.
+    pub fn submit_nine_slice(&mut self, texture: &TextureView, slice: &NineSlice, sprite: Sprite) {
+        for patch in slice.patches(&sprite) {
+            self.submit(texture, patch);
+        }
+    }
+
+    /// Sort and group the submitted sprites, resolve texture bind groups from the
+    /// material cache and upload the instances for a target of `width` x `height` pixels.
+    ///
+    /// Written with `Queue::write_buffer`, so upload once per submission.
+    pub fn upload(&mut self, render_manager: &mut RenderManager, width: u32, height: u32) {
+        let _span = trace_span!("sprite_upload", sprites = self.queued.len());
+        self.target_size = (width.max(1), height.max(1));
+        let screen = [self.target_size.0 as f32, self.target_size.1 as f32, 0.0, 0.0];
+        self.queue.write_buffer(&self.screen, 0, bytemuck::cast_slice(&screen));
+
+        if self.pipeline.is_none() && let Some(texture) = self.textures.first() {
+            let material_layout = render_manager.material_layout(&[texture], &[]);
+            self.pipeline = Some(create_pipeline(&self.device, &material_layout, self.format));
+        }
+        self.bind_groups = self
+            .textures
+            .iter()
+            .map(|texture| render_manager.material_bind_group(&[texture], &[]))
+            .collect();
+
+        // Stable, so sprites sharing a layer, scissor and texture keep their order.
+        self.queued
+            .sort_by_key(|queued| (queued.sprite.layer, queued.scissor, queued.texture));
+        self.batches.clear();
+        let mut instances = Vec::with_capacity(self.queued.len());
+        for queued in &self.queued {
+            let scissor = self.scissors[queued.scissor];
+            match self.batches.last_mut() {
+                Some(batch) if batch.texture == queued.texture && batch.scissor == scissor => batch.instance_count += 1,
+                _ => self.batches.push(Batch {
+                    texture: queued.texture,
+                    scissor,
+                    first_instance: instances.len() as u32,
+                    instance_count: 1,
+                }),
+            }
+            instances.push(instance(&queued.sprite));
+        }
+
+        let count = instances.len() as u64;
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instances = instance_buffer(&self.device, self.instance_capacity);
+        }
+        if !instances.is_empty() {
+            self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&instances));
+        }
+        self.uploaded = true;
+    }
+
+    /// Draw every batch of the last [`upload`](Self::upload). Leaves the scissor rectangle
+    /// covering the whole target.
+    ///
+    /// ## Panics
+    /// Panics if sprites were submitted since the last upload.
+    pub fn render(&self, pass: &mut RenderPass) {
+        assert!(self.uploaded, "SpriteBatcher::render called before upload");
+        let Some(pipeline) = &self.pipeline else {
+            return;
+        };
+        if self.batches.is_empty() {
+            return;
+        }
+        let (width, height) = self.target_size;
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(1, &self.screen_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instances.slice(..));
+        let mut bound_texture = None;
+        let mut bound_scissor = None;
+        for batch in &self.batches {
+            if bound_texture != Some(batch.texture) {
+                pass.set_bind_group(0, &self.bind_groups[batch.texture], &[]);
+                bound_texture = Some(batch.texture);
+            }
+            let [x, y, w, h] = batch.scissor.map_or([0, 0, width, height], |rect| clamp_scissor(rect, width, height));
+            if bound_scissor != Some([x, y, w, h]) {
+                pass.set_scissor_rect(x, y, w, h);
+                bound_scissor = Some([x, y, w, h]);
+            }
+            if w > 0 && h > 0 {
+                pass.draw(0..4, batch.first_instance..batch.first_instance + batch.instance_count);
+            }
+        }
+        if bound_scissor != Some([0, 0, width, height]) {
+            pass.set_scissor_rect(0, 0, width, height);
+        }
+    }
+
+    /// Remove all submitted sprites and reset the scissor, keeping the instance buffer.
+    pub fn clear(&mut self) {
+        self.textures.clear();
+        self.texture_lookup.clear();
+        self.bind_groups.clear();
+        self.scissors.clear();
+        self.scissors.push(None);
+        self.queued.clear();
+        self.batches.clear();
+        self.uploaded = false;
+    }
+
+    /// Number of sprites submitted since the last [`clear`](Self::clear).
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Number of draws issued by [`render`](Self::render).
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+}
+
+fn instance(sprite: &Sprite) -> SpriteInstance {
+    let (sin, cos) = sprite.rotation.sin_cos();
+    SpriteInstance {
+        rect: [sprite.position[0], sprite.position[1], sprite.size[0], sprite.size[1]],
+        uv_rect: sprite.uv_rect,
+        color: sprite.color,
+        pivot: [sprite.origin[0], sprite.origin[1], cos, sin],
+    }
+}
+
+/// `rect` limited to the target, as scissor rectangles must be.
+fn clamp_scissor([x, y, w, h]: [u32; 4], width: u32, height: u32) -> [u32; 4] {
+    let (x, y) = (x.min(width), y.min(height));
+    [x, y, w.min(width - x), h.min(height - y)]
+}
+
+fn screen_layout(device: &Device) -> BindGroupLayout {
+    gpu_util::bind_group_layout(device, "sprite screen layout", &[gpu_util::uniform_entry(0, ShaderStages::VERTEX)])
+}
+
+fn create_pipeline(device: &Device, material_layout: &BindGroupLayout, format: TextureFormat) -> RenderPipeline {
+    let module = gpu_util::shader(device, "sprite shader", SPRITE_SHADER);
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("sprite pipeline layout")),
+        bind_group_layouts: &[material_layout, &screen_layout(device)],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("sprite pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            buffers: &[SPRITE_LAYOUT],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+fn instance_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(device, "sprite instances", capacity * SPRITE_LAYOUT.array_stride, BufferUsages::VERTEX | BufferUsages::COPY_DST)
+}
@@ -0,0 +1,369 @@
+//! Screen-space reflections.
+//!
+//! [`ScreenSpaceReflections::render`] draws a fullscreen pass that, per pixel,
+//! reflects the view ray about the G-buffer normal and marches it through the
+//! depth buffer. Hits sample the previous frame's color, which the pass keeps in
+//! its own history texture ([`capture_history`](ScreenSpaceReflections::capture_history)).
+//! Misses, rays leaving the screen and rough surfaces fall back to the environment
+//! cubemap. The result is weighted by a Schlick Fresnel term and added onto the
+//! lit color target (additive blending).
+//!
+//! ## Frame flow
+//! ```ignore
+//! // Deferred lighting into `hdr`
+//! gbuffer.render_lighting(&mut render_manager, &mut pass, lighting_shader, &options, &[&lights]);
+//! ssr.render(&mut pass, &SsrInputs::from_gbuffer(&gbuffer, &skybox), &camera);
+//! drop(pass);
+//! // After all passes writing `hdr`, keep it for next frame's reflections
+//! ssr.capture_history(&mut encoder, &hdr_texture);
+//! ```
+//!
+//! ## G-buffer conventions
+//! Normals are read as world-space `xyz` of the normal target, metallic and
+//! roughness from the `r` and `g` channels of the material target. Depth uses the
+//! standard (non-reversed) `0..1` range, with 1.0 as the far plane.
+use wgpu::*;
+use crate::gbuffer::GBuffer;
+use crate::gpu_util;
+use crate::skybox::Skybox;
+use crate::labels::namespaced;
+
+const SSR_SHADER: &str = r#"
+struct SsrParams {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    camera_position: vec3<f32>,
+    max_distance: f32,
+    thickness: f32,
+    max_roughness: f32,
+    intensity: f32,
+    max_steps: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: SsrParams;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var depth_texture: texture_depth_2d;
+@group(0) @binding(3) var normal_texture: texture_2d<f32>;
+@group(0) @binding(4) var material_texture: texture_2d<f32>;
+@group(0) @binding(5) var history_texture: texture_2d<f32>;
+@group(0) @binding(6) var environment_texture: texture_cube<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    var positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>(-1.0,  1.0),
+        vec2<f32>( 1.0,  1.0),
+    );
+    var out: VertexOutput;
+    out.position = vec4<f32>(positions[idx], 0.0, 1.0);
+    out.uv = positions[idx] * vec2<f32>(0.5, -0.5) + 0.5;
+    return out;
+}
+
+fn world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = params.inverse_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let pixel = vec2<i32>(in.position.xy);
+    let depth = textureLoad(depth_texture, pixel, 0);
+    if depth >= 1.0 {
+        return vec4<f32>(0.0);
+    }
+
+    let position = world_position(in.uv, depth);
+    let normal = normalize(textureLoad(normal_texture, pixel, 0).xyz);
+    let material = textureLoad(material_texture, pixel, 0);
+    let metallic = material.r;
+    let roughness = material.g;
+
+    let view_dir = normalize(position - params.camera_position);
+    let ray = reflect(view_dir, normal);
+    let environment = textureSampleLevel(environment_texture, linear_sampler, ray, 0.0).rgb;
+
+    // Rough surfaces blur reflections beyond what a single ray can show.
+    let ssr_weight = 1.0 - smoothstep(params.max_roughness * 0.5, params.max_roughness, roughness);
+    var reflection = environment;
+    if ssr_weight > 0.0 {
+        let step_length = params.max_distance / f32(params.max_steps);
+        for (var i = 1u; i <= params.max_steps; i++) {
+            let p = position + ray * step_length * f32(i);
+            let clip = params.view_proj * vec4<f32>(p, 1.0);
+            if clip.w <= 0.0 {
+                break;
+            }
+            let ndc = clip.xyz / clip.w;
+            let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+            if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+                break;
+            }
+            let scene_depth = textureLoad(depth_texture, vec2<i32>(uv * size), 0);
+            let scene = world_position(uv, scene_depth);
+            let behind = distance(p, params.camera_position) - distance(scene, params.camera_position);
+            if behind > 0.0 && behind < params.thickness {
+                // Fade out towards the screen border, where the history has no data.
+                let edge = min(min(uv.x, 1.0 - uv.x), min(uv.y, 1.0 - uv.y));
+                let hit = textureSampleLevel(history_texture, linear_sampler, uv, 0.0).rgb;
+                reflection = mix(environment, hit, ssr_weight * smoothstep(0.0, 0.1, edge));
+                break;
+            }
+        }
+    }
+
+    let f0 = mix(0.04, 1.0, metallic);
+    let cos_theta = clamp(dot(-view_dir, normal), 0.0, 1.0);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+    return vec4<f32>(reflection * fresnel * (1.0 - roughness) * params.intensity, 0.0);
+}
+"#;
+
+/// Tuning of [`ScreenSpaceReflections`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsrSettings {
+    /// Number of ray-march steps per pixel.
+    pub max_steps: u32,
+    /// World-space length of the reflected ray.
+    pub max_distance: f32,
+    /// How far (world units) a ray may pass behind the depth buffer and still hit.
+    pub thickness: f32,
+    /// Roughness at and above which only the environment map is reflected.
+    pub max_roughness: f32,
+    pub intensity: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        Self {
+            max_steps: 64,
+            max_distance: 30.0,
+            thickness: 0.3,
+            max_roughness: 0.6,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Camera the reflections are traced with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsrCamera {
+    /// `projection * view`, column-major.
+    pub view_proj: [[f32; 4]; 4],
+    /// Inverse of `view_proj`.
+    pub inverse_view_proj: [[f32; 4]; 4],
+    /// World-space camera position.
+    pub position: [f32; 3],
+}
+
+/// Scene inputs of the reflection pass.
+#[derive(Debug, Clone, Copy)]
+pub struct SsrInputs<'a> {
+    /// Single-sampled scene depth.
+    pub depth: &'a TextureView,
+    pub normal: &'a TextureView,
+    pub material: &'a TextureView,
+    /// Cube view used where no screen-space hit is found.
+    pub environment: &'a TextureView,
+}
+
+impl<'a> SsrInputs<'a> {
+    /// Inputs from a G-buffer, with the skybox cubemap as environment.
+    pub fn from_gbuffer(gbuffer: &'a GBuffer, skybox: &'a Skybox) -> Self {
+        Self {
+            depth: gbuffer.depth_view(),
+            normal: gbuffer.normal_view(),
+            material: gbuffer.material_view(),
+            environment: skybox.environment_view(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsrParams {
+    view_proj: [[f32; 4]; 4],
+    inverse_view_proj: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    max_distance: f32,
+    thickness: f32,
+    max_roughness: f32,
+    intensity: f32,
+    max_steps: u32,
+}
+
+/// Screen-space reflection pass with its previous-frame color history.
+pub struct ScreenSpaceReflections {
+    device: Device,
+    queue: Queue,
+    settings: SsrSettings,
+    params: Buffer,
+    sampler: Sampler,
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    history: Texture,
+    history_view: TextureView,
+    /// Bind group and the (depth, normal, material, environment) views it was created for.
+    bind_group: Option<([TextureView; 4], BindGroup)>,
+}
+
+impl ScreenSpaceReflections {
+    /// Create the pass for color targets of `target_format`.
+    pub fn new(device: &Device, queue: &Queue, target_format: TextureFormat, settings: SsrSettings) -> Self {
+        let module = gpu_util::shader(device, "ssr shader", SSR_SHADER);
+        let float = TextureSampleType::Float { filterable: true };
+        let layout = gpu_util::bind_group_layout(device, "ssr layout", &[
+            gpu_util::uniform_entry(0, ShaderStages::FRAGMENT),
+            gpu_util::sampler_entry(1, ShaderStages::FRAGMENT, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(2, ShaderStages::FRAGMENT, TextureSampleType::Depth, TextureViewDimension::D2),
+            gpu_util::texture_entry(3, ShaderStages::FRAGMENT, float, TextureViewDimension::D2),
+            gpu_util::texture_entry(4, ShaderStages::FRAGMENT, float, TextureViewDimension::D2),
+            gpu_util::texture_entry(5, ShaderStages::FRAGMENT, float, TextureViewDimension::D2),
+            gpu_util::texture_entry(6, ShaderStages::FRAGMENT, float, TextureViewDimension::Cube),
+        ]);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(&namespaced("ssr pipeline layout")),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(&namespaced("ssr pipeline")),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(ColorTargetState {
+                    format: target_format,
+                    blend: Some(BlendState {
+                        color: BlendComponent {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::One,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha: BlendComponent::REPLACE,
+                    }),
+                    write_mask: ColorWrites::COLOR,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+        let history = create_history(device, 1, 1, target_format);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            settings,
+            params: gpu_util::buffer(device, "ssr params", size_of::<SsrParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST),
+            sampler: gpu_util::linear_sampler(device, "ssr sampler"),
+            layout,
+            pipeline,
+            history_view: history.create_view(&TextureViewDescriptor::default()),
+            history,
+            bind_group: None,
+        }
+    }
+
+    pub fn settings(&self) -> &SsrSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SsrSettings) {
+        self.settings = settings;
+    }
+
+    /// Record copying the final color of this frame into the history texture.
+    ///
+    /// `color` needs `TextureUsages::COPY_SRC`, and should have the target format
+    /// passed to [`new`](Self::new). The history is resized to match `color`.
+    pub fn capture_history(&mut self, encoder: &mut CommandEncoder, color: &Texture) {
+        if color.size() != self.history.size() || color.format() != self.history.format() {
+            self.history = create_history(&self.device, color.width(), color.height(), color.format());
+            self.history_view = self.history.create_view(&TextureViewDescriptor::default());
+            self.bind_group = None;
+        }
+        encoder.copy_texture_to_texture(color.as_image_copy(), self.history.as_image_copy(), color.size());
+    }
+
+    /// The previous frame's color, as captured by [`capture_history`](Self::capture_history).
+    pub fn history_view(&self) -> &TextureView {
+        &self.history_view
+    }
+
+    /// Draw reflections additively into the color target of `pass`.
+    ///
+    /// Parameters are written with `Queue::write_buffer`, so render once per submission.
+    pub fn render(&mut self, pass: &mut RenderPass, inputs: &SsrInputs, camera: &SsrCamera) {
+        let params = SsrParams {
+            view_proj: camera.view_proj,
+            inverse_view_proj: camera.inverse_view_proj,
+            camera_position: camera.position,
+            max_distance: self.settings.max_distance,
+            thickness: self.settings.thickness,
+            max_roughness: self.settings.max_roughness,
+            intensity: self.settings.intensity,
+            max_steps: self.settings.max_steps.max(1),
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+
+        let views = [inputs.depth, inputs.normal, inputs.material, inputs.environment];
+        let stale = self
+            .bind_group
+            .as_ref()
+            .is_none_or(|(cached, _)| cached.iter().zip(views).any(|(a, b)| a != b));
+        if stale {
+            let bind_group = gpu_util::bind_group(&self.device, "ssr", &self.layout, &[
+                self.params.as_entire_binding(),
+                BindingResource::Sampler(&self.sampler),
+                BindingResource::TextureView(inputs.depth),
+                BindingResource::TextureView(inputs.normal),
+                BindingResource::TextureView(inputs.material),
+                BindingResource::TextureView(&self.history_view),
+                BindingResource::TextureView(inputs.environment),
+            ]);
+            self.bind_group = Some((views.map(TextureView::clone), bind_group));
+        }
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group.as_ref().unwrap().1, &[]);
+        pass.draw(0..4, 0..1);
+    }
+}
+
+fn create_history(device: &Device, width: u32, height: u32, format: TextureFormat) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced("ssr history")),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
@@ -0,0 +1,134 @@
+//! Batched queue submissions with fence-style waiting.
+//!
+//! Every `Queue::submit` has a fixed CPU and driver cost. [`SubmissionManager`]
+//! collects command buffers from the crate's internal passes and from your own
+//! encoders and submits them together in a single call per [`flush`](SubmissionManager::flush).
+//!
+//! Each pushed command buffer returns a [`SubmissionTicket`] naming the batch it
+//! belongs to. Tickets can be polled with [`is_complete`](SubmissionManager::is_complete)
+//! or waited on with [`wait`](SubmissionManager::wait), like a fence.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wgpu::{CommandBuffer, CommandEncoder, Device, PollType, Queue, SubmissionIndex};
+
+/// Number of submitted batches whose [`SubmissionIndex`] is remembered.
+const SUBMISSION_HISTORY: usize = 64;
+
+/// Identifies the batch a command buffer was submitted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubmissionTicket(u64);
+
+/// Collects command buffers and submits them in batches.
+///
+/// Command buffers are submitted in push order.
+pub struct SubmissionManager {
+    device: Device,
+    queue: Queue,
+    pending: Vec<CommandBuffer>,
+    /// Ticket of the batch currently being collected.
+    batch: u64,
+    submitted: VecDeque<(u64, SubmissionIndex)>,
+    /// Number of leading batches the GPU has finished.
+    completed: Arc<AtomicU64>,
+    max_batch_len: usize,
+}
+
+impl SubmissionManager {
+    /// Create a manager that only submits on [`flush`](Self::flush).
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            pending: Vec::new(),
+            batch: 0,
+            submitted: VecDeque::with_capacity(SUBMISSION_HISTORY),
+            completed: Arc::new(AtomicU64::new(0)),
+            max_batch_len: usize::MAX,
+        }
+    }
+
+    /// Flush automatically once `len` command buffers are pending.
+    pub fn with_max_batch_len(mut self, len: usize) -> Self {
+        self.max_batch_len = len.max(1);
+        self
+    }
+
+    /// Queue a command buffer for the next submission.
+    pub fn push(&mut self, buffer: CommandBuffer) -> SubmissionTicket {
+        let ticket = SubmissionTicket(self.batch);
+        self.pending.push(buffer);
+        if self.pending.len() >= self.max_batch_len {
+            self.flush();
+        }
+        ticket
+    }
+
+    /// Finish `encoder` and queue it for the next submission.
+    pub fn push_encoder(&mut self, encoder: CommandEncoder) -> SubmissionTicket {
+        self.push(encoder.finish())
+    }
+
+    /// Number of command buffers waiting for the next flush.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Submit all pending command buffers in one `Queue::submit` call.
+    ///
+    /// Returns `None` if nothing was pending.
+    pub fn flush(&mut self) -> Option<SubmissionIndex> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let ticket = self.batch;
+        trace_event!(batch = ticket, command_buffers = self.pending.len(), "submitting batch");
+        let index = self.queue.submit(self.pending.drain(..));
+
+        let completed = self.completed.clone();
+        self.queue.on_submitted_work_done(move || {
+            completed.fetch_max(ticket + 1, Ordering::AcqRel);
+        });
+
+        if self.submitted.len() == SUBMISSION_HISTORY {
+            self.submitted.pop_front();
+        }
+        self.submitted.push_back((ticket, index.clone()));
+        self.batch += 1;
+        Some(index)
+    }
+
+    /// The submission index of the batch `ticket` belongs to, once it was flushed.
+    ///
+    /// Only the last 64 batches are remembered.
+    pub fn submission_index(&self, ticket: SubmissionTicket) -> Option<&SubmissionIndex> {
+        self.submitted.iter().find(|(batch, _)| *batch == ticket.0).map(|(_, index)| index)
+    }
+
+    /// Returns `true` once the GPU finished the batch `ticket` belongs to.
+    ///
+    /// Completion is only observed while the device is polled. Nothing polls it for
+    /// you: call `device.poll(PollType::Poll)` once per frame, or block in
+    /// [`wait`](Self::wait). `RenderManager::begin_frame` only polls while the profiler
+    /// or pipeline statistics are enabled.
+    pub fn is_complete(&self, ticket: SubmissionTicket) -> bool {
+        self.completed.load(Ordering::Acquire) > ticket.0
+    }
+
+    /// Block until the GPU finished the batch `ticket` belongs to.
+    ///
+    /// Flushes first if the batch is still being collected. Returns `false` if
+    /// polling the device failed, e.g. because the device was lost.
+    pub fn wait(&mut self, ticket: SubmissionTicket) -> bool {
+        if self.is_complete(ticket) {
+            return true;
+        }
+        if ticket.0 == self.batch {
+            self.flush();
+        }
+        let index = self.submission_index(ticket).cloned();
+        self.device
+            .poll(PollType::Wait { submission_index: index, timeout: None })
+            .is_ok()
+    }
+}
@@ -0,0 +1,462 @@
+//! Swapchain configuration, resizing and recovery.
+//!
+//! A [`SurfaceManager`] owns a window surface and the screen-sized targets that go
+//! with it. It negotiates the surface format (sRGB preferred unless configured otherwise) and present mode from
+//! the adapter's capabilities, reconfigures on [`resize`](SurfaceManager::resize) and
+//! when [`acquire`](SurfaceManager::acquire) finds the surface outdated or lost, and
+//! skips frames while the window is minimized. The present mode can be switched at
+//! runtime with [`set_present_mode`](SurfaceManager::set_present_mode) or
+//! [`set_vsync`](SurfaceManager::set_vsync), e.g. from a settings menu.
+//!
+//! With [`SurfaceOptions::hdr_output`] it picks an scRGB swapchain where available;
+//! pass `tonemap_output` (feature `postfx`) to the
+//! `Tonemapper` so it writes HDR there and SDR elsewhere.
+//!
+//! ## sRGB and linear views
+//! Whether colors end up gamma encoded once, twice (washed out) or never (too dark)
+//! depends on the view a pass writes to, not only on the swapchain format. Both
+//! variants of the swapchain format are allowed as view formats, and passes pick theirs
+//! by [`OutputEncoding`]: the scene, which shades in linear space, writes through an
+//! sRGB view ([`scene_view`](SurfaceManager::scene_view)) so the hardware encodes on
+//! write; UI libraries that blend already encoded colors, like egui, write through a
+//! linear view ([`ui_view`](SurfaceManager::ui_view)) so nothing is encoded again.
+//! Pipelines must target the matching [`scene_format`](SurfaceManager::scene_format) or
+//! [`ui_format`](SurfaceManager::ui_format). This also works on surfaces that only
+//! offer non-sRGB formats, as browsers do.
+//!
+//! The depth buffer and optional HDR target are resized with the surface. Other
+//! screen-sized subsystems implement [`ResizeTarget`] and follow with
+//! [`resize_targets`](SurfaceManager::resize_targets):
+//! ```ignore
+//! let mut surface = SurfaceManager::new(&adapter, &device, instance.create_surface(window)?, width, height, SurfaceOptions::default());
+//! // On a window resize event
+//! surface.resize(new_width, new_height);
+//! // Every frame
+//! surface.resize_targets(&mut [&mut gbuffer, &mut hiz, &mut picker]);
+//! let Some(frame) = surface.acquire() else { return };
+//! let view = surface.scene_view(&frame);
+//! // Render into `view` with `surface.depth_view()`
+//! frame.present();
+//! ```
+use wgpu::*;
+#[cfg(feature = "postfx")]
+use crate::exposure::TonemapOutput;
+#[cfg(feature = "postfx")]
+use crate::gbuffer::GBuffer;
+#[cfg(feature = "gpu_driven")]
+use crate::occlusion::HiZBuffer;
+use crate::oit::OitTargets;
+#[cfg(feature = "postfx")]
+use crate::outline::SelectionOutline;
+#[cfg(feature = "debug_draw")]
+use crate::picking::Picker;
+use crate::labels::namespaced;
+
+/// Swapchain format of HDR output, scRGB.
+pub const HDR_SURFACE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// How colors written by a pass are stored in the swapchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputEncoding {
+    /// The pass writes linear color through an sRGB view, encoded by the hardware.
+    Srgb,
+    /// The pass writes through a linear view, storing its output unchanged; for colors
+    /// that are already gamma encoded.
+    Linear,
+}
+
+/// Screen-sized resources that follow the surface size.
+pub trait ResizeTarget {
+    /// Recreate for a new size; should do nothing if the size is unchanged.
+    fn resize(&mut self, width: u32, height: u32);
+}
+
+#[cfg(feature = "postfx")]
+impl ResizeTarget for GBuffer {
+    fn resize(&mut self, width: u32, height: u32) {
+        GBuffer::resize(self, width, height);
+    }
+}
+
+impl ResizeTarget for OitTargets {
+    fn resize(&mut self, width: u32, height: u32) {
+        OitTargets::resize(self, width, height);
+    }
+}
+
+#[cfg(feature = "postfx")]
+impl ResizeTarget for SelectionOutline {
+    fn resize(&mut self, width: u32, height: u32) {
+        SelectionOutline::resize(self, width, height);
+    }
+}
+
+#[cfg(feature = "gpu_driven")]
+impl ResizeTarget for HiZBuffer {
+    fn resize(&mut self, width: u32, height: u32) {
+        HiZBuffer::resize(self, width, height);
+    }
+}
+
+#[cfg(feature = "debug_draw")]
+impl<T: Clone + 'static> ResizeTarget for Picker<T> {
+    fn resize(&mut self, width: u32, height: u32) {
+        Picker::resize(self, width, height);
+    }
+}
+
+/// How a [`SurfaceManager`] configures the swapchain and its targets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceOptions {
+    /// Wait for vertical blank (`Fifo`); otherwise `Mailbox`, then `Immediate`, when supported.
+    pub vsync: bool,
+    /// Format of the managed depth buffer, `None` for no depth buffer.
+    pub depth_format: Option<TextureFormat>,
+    /// Format of a managed HDR render target, e.g. `Rgba16Float` to tonemap from.
+    pub hdr_format: Option<TextureFormat>,
+    /// Frames the CPU may queue ahead of the display.
+    pub max_frame_latency: u32,
+    /// Use an `Rgba16Float` (scRGB) swapchain when the surface offers one, falling back
+    /// to SDR otherwise; check [`SurfaceManager::is_hdr`].
+    ///
+    /// Compositors that treat such swapchains as extended linear sRGB (DX12 and Metal)
+    /// show values above 1.0 as HDR.
+    pub hdr_output: bool,
+    /// Add `COPY_SRC` to the swapchain usage when supported, so presented frames can be
+    /// read back by a [`FrameCapture`](crate::capture::FrameCapture).
+    pub capture: bool,
+    /// Prefer an sRGB swapchain format. Only changes what a plain
+    /// `create_view(&Default::default())` does; passes using
+    /// [`SurfaceManager::view`] get their encoding either way.
+    pub srgb_format: bool,
+    /// Encoding of [`SurfaceManager::scene_view`].
+    pub scene_encoding: OutputEncoding,
+    /// Encoding of [`SurfaceManager::ui_view`].
+    pub ui_encoding: OutputEncoding,
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            depth_format: Some(TextureFormat::Depth32Float),
+            hdr_format: None,
+            max_frame_latency: 2,
+            hdr_output: false,
+            capture: false,
+            srgb_format: true,
+            scene_encoding: OutputEncoding::Srgb,
+            ui_encoding: OutputEncoding::Linear,
+        }
+    }
+}
+
+struct ScreenTarget {
+    texture: Texture,
+    view: TextureView,
+}
+
+/// A configured surface with its depth and HDR targets.
+pub struct SurfaceManager<'window> {
+    device: Device,
+    surface: Surface<'window>,
+    capabilities: SurfaceCapabilities,
+    config: SurfaceConfiguration,
+    options: SurfaceOptions,
+    depth: Option<ScreenTarget>,
+    hdr: Option<ScreenTarget>,
+    /// Set by a suboptimal frame; the surface is reconfigured before the next acquire.
+    reconfigure: bool,
+}
+
+impl<'window> SurfaceManager<'window> {
+    /// Configure `surface` for a window of `width` x `height` pixels.
+    pub fn new(adapter: &Adapter, device: &Device, surface: Surface<'window>, width: u32, height: u32, options: SurfaceOptions) -> Self {
+        let capabilities = surface.get_capabilities(adapter);
+        let hdr = options.hdr_output && capabilities.formats.contains(&HDR_SURFACE_FORMAT);
+        let format = capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|&format| if hdr { format == HDR_SURFACE_FORMAT } else { format.is_srgb() == options.srgb_format })
+            .or_else(|| capabilities.formats.first().copied())
+            .unwrap_or(TextureFormat::Bgra8UnormSrgb);
+        let mut usage = TextureUsages::RENDER_ATTACHMENT;
+        if options.capture && capabilities.usages.contains(TextureUsages::COPY_SRC) {
+            usage |= TextureUsages::COPY_SRC;
+        }
+        let config = SurfaceConfiguration {
+            usage,
+            format,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: present_mode(&capabilities, options.vsync),
+            desired_maximum_frame_latency: options.max_frame_latency,
+            alpha_mode: capabilities.alpha_modes.first().copied().unwrap_or(CompositeAlphaMode::Auto),
+            view_formats: view_formats(format),
+        };
+        let mut manager = Self {
+            device: device.clone(),
+            surface,
+            capabilities,
+            config,
+            options,
+            depth: None,
+            hdr: None,
+            reconfigure: false,
+        };
+        if width > 0 && height > 0 {
+            manager.configure();
+        }
+        manager
+    }
+
+    /// Reconfigure for a new window size. A zero size (minimized window) pauses
+    /// [`acquire`](Self::acquire) until the next nonzero resize.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            self.config.width = 0;
+            self.config.height = 0;
+            return;
+        }
+        if (width, height) == (self.config.width, self.config.height) {
+            return;
+        }
+        self.config.width = width;
+        self.config.height = height;
+        self.configure();
+    }
+
+    /// Resize `targets` to the surface size; cheap when the size is unchanged.
+    pub fn resize_targets(&self, targets: &mut [&mut dyn ResizeTarget]) {
+        if self.is_paused() {
+            return;
+        }
+        for target in targets {
+            target.resize(self.config.width, self.config.height);
+        }
+    }
+
+    /// Next frame to render into, `None` while minimized or when no frame is available
+    /// (the frame is skipped). Outdated and lost surfaces are reconfigured and retried once.
+    pub fn acquire(&mut self) -> Option<SurfaceTexture> {
+        if self.is_paused() {
+            return None;
+        }
+        if std::mem::take(&mut self.reconfigure) {
+            self.configure();
+        }
+        let frame = match self.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(SurfaceError::Outdated | SurfaceError::Lost) => {
+                trace_event!("surface outdated or lost, reconfiguring");
+                self.configure();
+                self.surface.get_current_texture().ok()?
+            }
+            Err(error) => {
+                trace_event!(%error, "skipped frame");
+                let _ = error;
+                return None;
+            }
+        };
+        self.reconfigure = frame.suboptimal;
+        Some(frame)
+    }
+
+    /// Whether the window is minimized.
+    pub fn is_paused(&self) -> bool {
+        self.config.width == 0 || self.config.height == 0
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    /// Format of the swapchain textures; the sRGB and linear variants are both allowed
+    /// as view formats.
+    pub fn format(&self) -> TextureFormat {
+        self.config.format
+    }
+
+    /// View format writing with `encoding`. HDR swapchains have no sRGB variant and are
+    /// always linear.
+    pub fn view_format(&self, encoding: OutputEncoding) -> TextureFormat {
+        match encoding {
+            OutputEncoding::Srgb => self.config.format.add_srgb_suffix(),
+            OutputEncoding::Linear => self.config.format.remove_srgb_suffix(),
+        }
+    }
+
+    /// Target format for scene pipelines, see [`SurfaceOptions::scene_encoding`].
+    pub fn scene_format(&self) -> TextureFormat {
+        self.view_format(self.options.scene_encoding)
+    }
+
+    /// Target format for UI pipelines, see [`SurfaceOptions::ui_encoding`].
+    pub fn ui_format(&self) -> TextureFormat {
+        self.view_format(self.options.ui_encoding)
+    }
+
+    /// View of `frame` writing with `encoding`.
+    pub fn view(&self, frame: &SurfaceTexture, encoding: OutputEncoding) -> TextureView {
+        frame.texture.create_view(&TextureViewDescriptor {
+            label: Some(&namespaced("surface view")),
+            format: Some(self.view_format(encoding)),
+            ..Default::default()
+        })
+    }
+
+    /// View of `frame` for scene passes.
+    pub fn scene_view(&self, frame: &SurfaceTexture) -> TextureView {
+        self.view(frame, self.options.scene_encoding)
+    }
+
+    /// View of `frame` for UI passes.
+    pub fn ui_view(&self, frame: &SurfaceTexture) -> TextureView {
+        self.view(frame, self.options.ui_encoding)
+    }
+
+    /// Whether the swapchain is the HDR format requested by [`SurfaceOptions::hdr_output`].
+    pub fn is_hdr(&self) -> bool {
+        self.config.format == HDR_SURFACE_FORMAT
+    }
+
+    /// Tonemapper output for this surface: HDR with the given brightness if the swapchain
+    /// is HDR, SDR otherwise.
+    #[cfg(feature = "postfx")]
+    pub fn tonemap_output(&self, paper_white_nits: f32, max_nits: f32) -> TonemapOutput {
+        if self.is_hdr() {
+            TonemapOutput::Hdr { paper_white_nits, max_nits }
+        } else {
+            TonemapOutput::Sdr
+        }
+    }
+
+    pub fn config(&self) -> &SurfaceConfiguration {
+        &self.config
+    }
+
+    pub fn capabilities(&self) -> &SurfaceCapabilities {
+        &self.capabilities
+    }
+
+    pub fn options(&self) -> &SurfaceOptions {
+        &self.options
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.config.present_mode
+    }
+
+    /// Present modes of this surface; `AutoVsync` and `AutoNoVsync` are always accepted too.
+    pub fn supported_present_modes(&self) -> &[PresentMode] {
+        &self.capabilities.present_modes
+    }
+
+    /// Switch to `mode` and reconfigure the surface. Returns `false` and keeps the
+    /// current mode if the surface doesn't support it.
+    ///
+    /// Call between frames: the previously acquired frame must have been presented or dropped.
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> bool {
+        let supported = matches!(mode, PresentMode::AutoVsync | PresentMode::AutoNoVsync) || self.capabilities.present_modes.contains(&mode);
+        if !supported {
+            trace_event!(?mode, "unsupported present mode");
+            return false;
+        }
+        if mode != self.config.present_mode {
+            self.config.present_mode = mode;
+            self.options.vsync = matches!(mode, PresentMode::Fifo | PresentMode::FifoRelaxed | PresentMode::AutoVsync);
+            if !self.is_paused() {
+                self.surface.configure(&self.device, &self.config);
+            }
+        }
+        true
+    }
+
+    /// Turn vsync on (`Fifo`) or off (the lowest latency supported mode), see
+    /// [`SurfaceOptions::vsync`].
+    pub fn set_vsync(&mut self, vsync: bool) {
+        let mode = present_mode(&self.capabilities, vsync);
+        self.set_present_mode(mode);
+        self.options.vsync = vsync;
+    }
+
+    pub fn surface(&self) -> &Surface<'window> {
+        &self.surface
+    }
+
+    /// The managed depth buffer, if [`SurfaceOptions::depth_format`] is set.
+    pub fn depth_view(&self) -> Option<&TextureView> {
+        self.depth.as_ref().map(|target| &target.view)
+    }
+
+    /// The managed HDR target, if [`SurfaceOptions::hdr_format`] is set.
+    pub fn hdr_view(&self) -> Option<&TextureView> {
+        self.hdr.as_ref().map(|target| &target.view)
+    }
+
+    pub fn hdr_texture(&self) -> Option<&Texture> {
+        self.hdr.as_ref().map(|target| &target.texture)
+    }
+
+    /// Depth attachment of the managed depth buffer cleared to 1.0, if there is one.
+    pub fn depth_attachment(&self) -> Option<RenderPassDepthStencilAttachment<'_>> {
+        self.depth.as_ref().map(|target| RenderPassDepthStencilAttachment {
+            view: &target.view,
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(1.0),
+                store: StoreOp::Store,
+            }),
+            stencil_ops: None,
+        })
+    }
+
+    fn configure(&mut self) {
+        let _span = trace_span!("surface_configure", width = self.config.width, height = self.config.height);
+        self.surface.configure(&self.device, &self.config);
+        let size = (self.config.width, self.config.height);
+        let target = |format, label, usage| create_target(&self.device, label, format, size, usage);
+        self.depth = self.options.depth_format.map(|format| {
+            target(format, "surface depth", TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
+        });
+        self.hdr = self.options.hdr_format.map(|format| {
+            target(format, "surface hdr", TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
+        });
+    }
+}
+
+/// `Fifo` for vsync (always supported), otherwise the lowest latency supported mode.
+fn present_mode(capabilities: &SurfaceCapabilities, vsync: bool) -> PresentMode {
+    if vsync {
+        return PresentMode::Fifo;
+    }
+    [PresentMode::Mailbox, PresentMode::Immediate]
+        .into_iter()
+        .find(|mode| capabilities.present_modes.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// The other sRGB variant of `format`, so both encodings can be used for views.
+fn view_formats(format: TextureFormat) -> Vec<TextureFormat> {
+    let other = if format.is_srgb() { format.remove_srgb_suffix() } else { format.add_srgb_suffix() };
+    if other == format { Vec::new() } else { vec![other] }
+}
+
+fn create_target(device: &Device, label: &str, format: TextureFormat, (width, height): (u32, u32), usage: TextureUsages) -> ScreenTarget {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced(label)),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    ScreenTarget { texture, view }
+}
@@ -0,0 +1,542 @@
+//! Heightmap terrain with CDLOD chunks and splat-map materials.
+//!
+//! A [`Terrain`] uploads a [`Heightmap`] as an `R32Float` texture and draws the
+//! terrain as instances of one shared grid mesh from the [`MeshManager`]. Every frame
+//! [`update`](Terrain::update) walks a quadtree over the terrain and selects chunks by
+//! camera distance (CDLOD): each level covers twice the distance of the previous one
+//! with chunks twice the size, and vertices morph towards the next coarser grid near
+//! the end of their range, so there are no cracks or popping between levels.
+//!
+//! ## Frame flow
+//! ```ignore
+//! terrain.update([camera.x, camera.y, camera.z]);
+//! let options = terrain.pipeline_options(&meshes, PipelineOptions::default());
+//! // Inside a render pass
+//! terrain.render(&mut render_manager, &mut pass, &material.views(), shader_path, &options, &[&camera]);
+//! terrain.draw(&mut pass, &meshes, &options);
+//! ```
+//!
+//! ## Shader side
+//! The terrain uniform and heightmap are bound as `@group(2)`. Paste [`TERRAIN_WGSL`]
+//! into the shader and build vertices with `terrain_vertex`:
+//! ```wgsl
+//! @vertex
+//! fn vs_main(@location(0) grid: vec3<f32>, @location(8) rect: vec4<f32>, @location(9) level: f32) -> VertexOutput {
+//!     let v = terrain_vertex(grid.xz, rect, level);
+//!     // v.world_position, v.normal, v.uv
+//! }
+//! ```
+//! A [`TerrainMaterial`] binds a splat map and a texture array of up to four layers
+//! as the material (`@group(0)`), blended with `terrain_splat` from [`TERRAIN_SPLAT_WGSL`].
+use std::path::Path;
+use wgpu::*;
+use crate::gpu_util;
+use crate::meshes::{MeshHandle, MeshManager, PositionVertex, VertexLayoutId};
+use crate::pipelines::PipelineOptions;
+use crate::renderer::{ExtraBindGroup, RenderManager};
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+/// WGSL bindings and vertex helpers of [`Terrain`], bound as `@group(2)`.
+pub const TERRAIN_WGSL: &str = r#"
+struct TerrainParams {
+    origin: vec2<f32>,
+    size: vec2<f32>,
+    camera_position: vec3<f32>,
+    height_scale: f32,
+    grid_resolution: f32,
+    lod_distance: f32,
+    heightmap_size: vec2<f32>,
+};
+
+struct TerrainVertex {
+    world_position: vec3<f32>,
+    normal: vec3<f32>,
+    // Position on the heightmap, 0..1 over the whole terrain.
+    uv: vec2<f32>,
+};
+
+@group(2) @binding(0) var<uniform> terrain: TerrainParams;
+@group(2) @binding(1) var terrain_heightmap: texture_2d<f32>;
+
+fn terrain_texel(texel: vec2<i32>) -> f32 {
+    let max_texel = vec2<i32>(terrain.heightmap_size) - vec2<i32>(1);
+    return textureLoad(terrain_heightmap, clamp(texel, vec2<i32>(0), max_texel), 0).r;
+}
+
+// Bilinear height at `uv`; R32Float can't be filtered by a sampler everywhere.
+fn terrain_height(uv: vec2<f32>) -> f32 {
+    let texel = uv * terrain.heightmap_size - 0.5;
+    let base = vec2<i32>(floor(texel));
+    let f = fract(texel);
+    let top = mix(terrain_texel(base), terrain_texel(base + vec2<i32>(1, 0)), f.x);
+    let bottom = mix(terrain_texel(base + vec2<i32>(0, 1)), terrain_texel(base + vec2<i32>(1, 1)), f.x);
+    return mix(top, bottom, f.y) * terrain.height_scale;
+}
+
+fn terrain_normal(uv: vec2<f32>) -> vec3<f32> {
+    let texel_step = 1.0 / terrain.heightmap_size;
+    let dx = terrain_height(uv + vec2<f32>(texel_step.x, 0.0)) - terrain_height(uv - vec2<f32>(texel_step.x, 0.0));
+    let dz = terrain_height(uv + vec2<f32>(0.0, texel_step.y)) - terrain_height(uv - vec2<f32>(0.0, texel_step.y));
+    let world_step = 2.0 * texel_step * terrain.size;
+    return normalize(vec3<f32>(-dx / world_step.x, 1.0, -dz / world_step.y));
+}
+
+// `grid` is the 0..1 position inside the chunk, `rect` the chunk origin and size and
+// `level` its LOD level, all from the instance data.
+fn terrain_vertex(grid: vec2<f32>, rect: vec4<f32>, level: f32) -> TerrainVertex {
+    let range = terrain.lod_distance * exp2(level);
+    let xz = rect.xy + grid * rect.zw;
+    let height = terrain_height((xz - terrain.origin) / terrain.size);
+    let dist = distance(vec3<f32>(xz.x, height, xz.y), terrain.camera_position);
+
+    // Morph odd grid vertices onto the coarser grid over the last 30% of the range.
+    let morph = clamp((dist - 0.7 * range) / (0.3 * range), 0.0, 1.0);
+    let odd = fract(grid * terrain.grid_resolution * 0.5) * 2.0 / terrain.grid_resolution;
+    let morphed = rect.xy + (grid - odd * morph) * rect.zw;
+
+    let uv = (morphed - terrain.origin) / terrain.size;
+    return TerrainVertex(vec3<f32>(morphed.x, terrain_height(uv), morphed.y), terrain_normal(uv), uv);
+}
+"#;
+
+/// WGSL material bindings and splat blending of [`TerrainMaterial`].
+pub const TERRAIN_SPLAT_WGSL: &str = r#"
+@group(0) @binding(0) var terrain_sampler: sampler;
+@group(0) @binding(1) var terrain_splat_map: texture_2d<f32>;
+@group(0) @binding(2) var terrain_layers: texture_2d_array<f32>;
+
+// Blend the layers by the splat map weights at `uv`, repeating layers `tiling` times.
+fn terrain_splat(uv: vec2<f32>, tiling: f32) -> vec4<f32> {
+    let weights = textureSample(terrain_splat_map, terrain_sampler, uv);
+    let layer_uv = uv * tiling;
+    var color = vec4<f32>(0.0);
+    let count = min(textureNumLayers(terrain_layers), 4u);
+    for (var i = 0u; i < count; i++) {
+        color += textureSample(terrain_layers, terrain_sampler, layer_uv, i) * weights[i];
+    }
+    return color / max(dot(weights, vec4<f32>(1.0)), 1e-4);
+}
+"#;
+
+const CHUNK_ATTRIBUTES: [VertexAttribute; 2] = wgpu::vertex_attr_array![8 => Float32x4, 9 => Float32];
+
+/// Per-instance vertex layout of terrain chunks: [`TerrainChunk`] at locations 8 and 9.
+pub const TERRAIN_CHUNK_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<TerrainChunk>() as u64,
+    step_mode: VertexStepMode::Instance,
+    attributes: &CHUNK_ATTRIBUTES,
+};
+
+/// Height samples of a terrain, row by row from the `-Z` edge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    /// Heights in `0..1`, scaled by [`TerrainSettings::height_scale`].
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// ## Panics
+    /// Panics if `heights` doesn't have `width * height` samples.
+    pub fn new(width: u32, height: u32, heights: Vec<f32>) -> Self {
+        assert_eq!(heights.len(), (width * height) as usize, "Heightmap needs {}x{} samples", width, height);
+        Self { width, height, heights }
+    }
+
+    /// Heightmap from 16-bit samples, as stored in most terrain PNG/RAW files.
+    pub fn from_r16(width: u32, height: u32, samples: &[u16]) -> Self {
+        Self::new(width, height, samples.iter().map(|&s| s as f32 / u16::MAX as f32).collect())
+    }
+
+    /// Bilinear sample at `uv` in `0..1`.
+    pub fn sample(&self, uv: [f32; 2]) -> f32 {
+        let x = (uv[0] * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (uv[1] * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let at = |x: u32, y: u32| self.heights[(y * self.width + x) as usize];
+        let (fx, fy) = (x.fract(), y.fract());
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+        top + (bottom - top) * fy
+    }
+}
+
+/// Size and level of detail of a [`Terrain`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainSettings {
+    /// World-space XZ position of the heightmap's first sample.
+    pub origin: [f32; 2],
+    /// World-space XZ extent.
+    pub size: [f32; 2],
+    /// World-space height of a heightmap value of 1.
+    pub height_scale: f32,
+    /// Quads per chunk side, a power of two.
+    pub grid_resolution: u32,
+    /// Number of LOD levels; the whole terrain is one chunk at the coarsest.
+    pub lod_levels: u32,
+    /// Distance covered by the finest level; every level doubles it.
+    pub lod_distance: f32,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            origin: [0.0; 2],
+            size: [1024.0; 2],
+            height_scale: 100.0,
+            grid_resolution: 32,
+            lod_levels: 6,
+            lod_distance: 32.0,
+        }
+    }
+}
+
+/// Instance data of one selected chunk.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainChunk {
+    /// World-space XZ origin and size.
+    pub rect: [f32; 4],
+    /// LOD level, 0 being the finest.
+    pub level: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TerrainParams {
+    origin: [f32; 2],
+    size: [f32; 2],
+    camera_position: [f32; 3],
+    height_scale: f32,
+    grid_resolution: f32,
+    lod_distance: f32,
+    heightmap_size: [f32; 2],
+}
+
+/// A heightmap terrain drawn as CDLOD chunks.
+pub struct Terrain {
+    device: Device,
+    queue: Queue,
+    settings: TerrainSettings,
+    heightmap: Heightmap,
+    heightmap_texture: LoadedTexture,
+    /// `[min, max]` heights of the quadtree nodes, finest level first, row-major per level.
+    bounds: Vec<Vec<[f32; 2]>>,
+    grid: MeshHandle,
+    chunks: Vec<TerrainChunk>,
+    instances: Buffer,
+    instance_capacity: u64,
+    params: Buffer,
+    entries: Vec<BindGroupLayoutEntry>,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+}
+
+impl Terrain {
+    /// Upload `heightmap` and the chunk grid mesh.
+    ///
+    /// ## Panics
+    /// Panics if `settings.grid_resolution` is not a power of two or `settings.lod_levels` is 0.
+    pub fn new(device: &Device, queue: &Queue, meshes: &mut MeshManager, heightmap: Heightmap, settings: TerrainSettings) -> Self {
+        assert!(settings.grid_resolution.is_power_of_two(), "Terrain grid resolution must be a power of two");
+        assert!(settings.lod_levels > 0, "Terrain needs at least one LOD level");
+        let _span = trace_span!("create_terrain", width = heightmap.width, height = heightmap.height);
+
+        let mut request = TextureRequest::rgba8(
+            "terrain heightmap",
+            heightmap.width,
+            heightmap.height,
+            bytemuck::cast_slice(&heightmap.heights).to_vec(),
+        );
+        request.format = TextureFormat::R32Float;
+        let heightmap_texture = create_texture(device, queue, &request);
+
+        let resolution = settings.grid_resolution;
+        let vertices: Vec<PositionVertex> = (0..=resolution)
+            .flat_map(|z| {
+                (0..=resolution).map(move |x| PositionVertex {
+                    position: [x as f32 / resolution as f32, 0.0, z as f32 / resolution as f32],
+                })
+            })
+            .collect();
+        let row = resolution + 1;
+        let indices: Vec<u32> = (0..resolution)
+            .flat_map(|z| {
+                (0..resolution).flat_map(move |x| {
+                    let i = z * row + x;
+                    [i, i + row, i + 1, i + 1, i + row, i + row + 1]
+                })
+            })
+            .collect();
+        let grid = meshes.upload("terrain grid", VertexLayoutId::POSITION, &vertices, &indices);
+
+        let params = gpu_util::buffer(device, "terrain params", size_of::<TerrainParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let entries = vec![
+            gpu_util::uniform_entry(0, ShaderStages::VERTEX_FRAGMENT),
+            gpu_util::texture_entry(
+                1,
+                ShaderStages::VERTEX_FRAGMENT,
+                TextureSampleType::Float { filterable: false },
+                TextureViewDimension::D2,
+            ),
+        ];
+        let layout = gpu_util::bind_group_layout(device, "terrain layout", &entries);
+        let bind_group = gpu_util::bind_group(device, "terrain", &layout, &[
+            params.as_entire_binding(),
+            BindingResource::TextureView(&heightmap_texture.view),
+        ]);
+
+        let bounds = height_bounds(&heightmap, settings.lod_levels);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            settings,
+            heightmap,
+            heightmap_texture,
+            bounds,
+            grid,
+            chunks: Vec::new(),
+            instances: chunk_buffer(device, 64),
+            instance_capacity: 64,
+            params,
+            entries,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn settings(&self) -> &TerrainSettings {
+        &self.settings
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    pub fn heightmap_view(&self) -> &TextureView {
+        &self.heightmap_texture.view
+    }
+
+    /// World-space terrain height at `x`, `z`, clamped to the terrain edges.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let uv = [
+            (x - self.settings.origin[0]) / self.settings.size[0],
+            (z - self.settings.origin[1]) / self.settings.size[1],
+        ];
+        self.heightmap.sample(uv) * self.settings.height_scale
+    }
+
+    /// Select the chunks for a camera at `camera_position` and upload them.
+    ///
+    /// Parameters and instances are written with `Queue::write_buffer`, so update once
+    /// per submission.
+    pub fn update(&mut self, camera_position: [f32; 3]) {
+        let _span = trace_span!("terrain_update");
+        self.chunks.clear();
+        let root = self.settings.lod_levels - 1;
+        self.select(camera_position, root, 0, 0);
+
+        let count = self.chunks.len() as u64;
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instances = chunk_buffer(&self.device, self.instance_capacity);
+        }
+        self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&self.chunks));
+
+        let params = TerrainParams {
+            origin: self.settings.origin,
+            size: self.settings.size,
+            camera_position,
+            height_scale: self.settings.height_scale,
+            grid_resolution: self.settings.grid_resolution as f32,
+            lod_distance: self.settings.lod_distance,
+            heightmap_size: [self.heightmap.width as f32, self.heightmap.height as f32],
+        };
+        self.queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+    }
+
+    /// Chunks selected by the last [`update`](Self::update).
+    pub fn chunks(&self) -> &[TerrainChunk] {
+        &self.chunks
+    }
+
+    /// `base` for the terrain grid mesh with the chunk instance layout added.
+    pub fn pipeline_options(&self, meshes: &MeshManager, base: PipelineOptions) -> PipelineOptions {
+        meshes
+            .layouts()
+            .pipeline_options(VertexLayoutId::POSITION, base)
+            .with_vertex_layout(TERRAIN_CHUNK_LAYOUT)
+    }
+
+    /// Layout of the terrain bind group, for pipelines built with
+    /// [`render_with_layouts`](RenderManager::render_with_layouts).
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    /// Bind group with the terrain params and heightmap at bindings 0 and 1, see [`TERRAIN_WGSL`].
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Set pipeline and bind groups like
+    /// [`render_with_textures`](RenderManager::render_with_textures), with the
+    /// terrain bound as `@group(2)`.
+    ///
+    /// Like `render_with_textures`, this does not issue a draw call.
+    pub fn render(
+        &self,
+        manager: &mut RenderManager,
+        pass: &mut RenderPass,
+        texture_views: &[&TextureView],
+        shader_path: &Path,
+        options: &PipelineOptions,
+        uniforms: &[&Buffer],
+    ) {
+        let terrain = ExtraBindGroup {
+            name: "terrain",
+            entries: &self.entries,
+            layout: &self.layout,
+            bind_group: &self.bind_group,
+        };
+        manager.render_with_extra_groups(texture_views, shader_path, options, uniforms, &[terrain], pass);
+    }
+
+    /// Draw all selected chunks with one instanced draw, after [`render`](Self::render).
+    pub fn draw(&self, pass: &mut RenderPass, meshes: &MeshManager, options: &PipelineOptions) {
+        if self.chunks.is_empty() {
+            return;
+        }
+        pass.set_vertex_buffer(1, self.instances.slice(..));
+        meshes.draw(pass, self.grid, options, 0..self.chunks.len() as u32);
+    }
+
+    /// Emit node (`x`, `z`) of `level`, or its children if the camera is within the
+    /// range of the next finer level.
+    fn select(&mut self, camera: [f32; 3], level: u32, x: u32, z: u32) {
+        let nodes = 1u32 << (self.settings.lod_levels - 1 - level);
+        let size = [self.settings.size[0] / nodes as f32, self.settings.size[1] / nodes as f32];
+        let origin = [
+            self.settings.origin[0] + x as f32 * size[0],
+            self.settings.origin[1] + z as f32 * size[1],
+        ];
+        let [min, max] = self.bounds[level as usize][(z * nodes + x) as usize];
+        let closest = [
+            camera[0].clamp(origin[0], origin[0] + size[0]),
+            camera[1].max(min * self.settings.height_scale).min(max * self.settings.height_scale),
+            camera[2].clamp(origin[1], origin[1] + size[1]),
+        ];
+        let d = [camera[0] - closest[0], camera[1] - closest[1], camera[2] - closest[2]];
+        let dist = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+
+        if level > 0 && dist < self.settings.lod_distance * (1u32 << (level - 1)) as f32 {
+            for (cx, cz) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                self.select(camera, level - 1, x * 2 + cx, z * 2 + cz);
+            }
+        } else {
+            self.chunks.push(TerrainChunk {
+                rect: [origin[0], origin[1], size[0], size[1]],
+                level: level as f32,
+            });
+        }
+    }
+}
+
+/// Splat map and layer textures of a terrain.
+pub struct TerrainMaterial {
+    splat: LoadedTexture,
+    layers: LoadedTexture,
+}
+
+impl TerrainMaterial {
+    /// Create the material from an RGBA8 splat map (one layer weight per channel) and
+    /// up to four sRGB RGBA8 layer images of `layer_size`.
+    ///
+    /// ## Panics
+    /// Panics if there are no or more than four layers, or a layer has the wrong size.
+    pub fn new(device: &Device, queue: &Queue, splat_size: [u32; 2], splat: Vec<u8>, layer_size: [u32; 2], layers: &[&[u8]]) -> Self {
+        assert!((1..=4).contains(&layers.len()), "Terrain materials have 1 to 4 layers, got {}", layers.len());
+        let layer_bytes = (layer_size[0] * layer_size[1] * 4) as usize;
+        assert!(
+            layers.iter().all(|layer| layer.len() == layer_bytes),
+            "Terrain layers must be {}x{} RGBA8 images",
+            layer_size[0],
+            layer_size[1]
+        );
+
+        let mut splat_request = TextureRequest::rgba8("terrain splat map", splat_size[0], splat_size[1], splat);
+        splat_request.format = TextureFormat::Rgba8Unorm;
+
+        // A single layer still gets an array view, so shaders always see texture_2d_array.
+        let layer_count = layers.len().max(2) as u32;
+        let mut data = layers.concat();
+        data.resize(layer_bytes * layer_count as usize, 0);
+        let mut layers_request = TextureRequest::rgba8("terrain layers", layer_size[0], layer_size[1], data);
+        layers_request.size.depth_or_array_layers = layer_count;
+
+        Self {
+            splat: create_texture(device, queue, &splat_request),
+            layers: create_texture(device, queue, &layers_request),
+        }
+    }
+
+    /// Splat map and layer array views, in the order of [`TERRAIN_SPLAT_WGSL`].
+    pub fn views(&self) -> [&TextureView; 2] {
+        [&self.splat.view, &self.layers.view]
+    }
+}
+
+/// Min/max heights of every quadtree node.
+fn height_bounds(heightmap: &Heightmap, levels: u32) -> Vec<Vec<[f32; 2]>> {
+    let finest = 1u32 << (levels - 1);
+    let mut bounds = vec![vec![[f32::MAX, f32::MIN]; (finest * finest) as usize]];
+    let node_x = |x: u32| (x * finest / heightmap.width).min(finest - 1);
+    let node_y = |y: u32| (y * finest / heightmap.height).min(finest - 1);
+    for y in 0..heightmap.height {
+        for x in 0..heightmap.width {
+            // Samples on a node border belong to both nodes.
+            let h = heightmap.heights[(y * heightmap.width + x) as usize];
+            for ny in [node_y(y), node_y(y.saturating_sub(1))] {
+                for nx in [node_x(x), node_x(x.saturating_sub(1))] {
+                    let node = &mut bounds[0][(ny * finest + nx) as usize];
+                    node[0] = node[0].min(h);
+                    node[1] = node[1].max(h);
+                }
+            }
+        }
+    }
+    // Nodes smaller than a texel contain no sample.
+    for (i, node) in bounds[0].iter_mut().enumerate() {
+        if node[0] > node[1] {
+            let (x, z) = (i as u32 % finest, i as u32 / finest);
+            let h = heightmap.sample([(x as f32 + 0.5) / finest as f32, (z as f32 + 0.5) / finest as f32]);
+            *node = [h, h];
+        }
+    }
+    for level in 1..levels {
+        let nodes = finest >> level;
+        let finer = &bounds[level as usize - 1];
+        let merged = (0..nodes * nodes)
+            .map(|i| {
+                let (x, z) = (i % nodes, i / nodes);
+                [(0, 0), (1, 0), (0, 1), (1, 1)].iter().fold([f32::MAX, f32::MIN], |acc, (cx, cz)| {
+                    let child = finer[((z * 2 + cz) * nodes * 2 + x * 2 + cx) as usize];
+                    [acc[0].min(child[0]), acc[1].max(child[1])]
+                })
+            })
+            .collect();
+        bounds.push(merged);
+    }
+    bounds
+}
+
+fn chunk_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(
+        device,
+        "terrain chunks",
+        capacity * size_of::<TerrainChunk>() as u64,
+        BufferUsages::VERTEX | BufferUsages::COPY_DST,
+    )
+}
@@ -0,0 +1,144 @@
+//! Test support: devices without a window and assertions on cache creations (feature `testing`).
+//!
+//! [`noop_device`] is a device that validates every call but executes nothing, it
+//! needs no adapter and works on any CI machine. [`gpu_device`] is a real headless
+//! device, falling back to a software adapter, for tests that read rendered images
+//! back. Both are enough to exercise the caches of a [`RenderManager`]:
+//! ```ignore
+//! let (device, queue) = noop_device();
+//! let mut render_manager = RenderManager::new(&device, &queue, "shaders".into());
+//! let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+//!
+//! let before = CacheSnapshot::take(&render_manager);
+//! render_manager.material_bind_group(&[&albedo], &[]);
+//! render_manager.material_bind_group(&[&albedo], &[]);
+//! let created = before.created_since(&render_manager);
+//! assert_eq!(created.layouts(), 1);
+//! assert_eq!(created.bind_groups(), 1);
+//! created.assert_created(CacheKind::MaterialBindGroup, 1);
+//! ```
+use std::collections::{HashMap, HashSet};
+use wgpu::{Device, DeviceDescriptor, Extent3d, Instance, Queue, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
+use crate::diagnostics::CacheKind;
+use crate::headless::request_headless_device;
+use crate::labels::namespaced;
+use crate::renderer::RenderManager;
+
+/// A device and queue of wgpu's no-op backend: resources are created and validated,
+/// but nothing runs on a GPU and readbacks return zeroes.
+pub fn noop_device() -> (Device, Queue) {
+    Device::noop(&DeviceDescriptor {
+        label: Some(&namespaced("wgpu_render_manager test device")),
+        ..Default::default()
+    })
+}
+
+/// A real headless device, preferring a software adapter so results match across
+/// machines. `None` if no adapter is available, so tests can skip instead of failing.
+pub fn gpu_device() -> Option<(Device, Queue)> {
+    let instance = Instance::default();
+    pollster::block_on(request_headless_device(&instance, true))
+        .or_else(|_| pollster::block_on(request_headless_device(&instance, false)))
+        .ok()
+        .map(|(_, device, queue)| (device, queue))
+}
+
+/// View of a new `width` x `height` texture that can be sampled, stored to and
+/// rendered into, as far as `format` allows.
+pub fn test_texture(device: &Device, width: u32, height: u32, format: TextureFormat) -> TextureView {
+    let mut usage = TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST;
+    let format_features = format.guaranteed_format_features(device.features());
+    usage |= format_features.allowed_usages & (TextureUsages::RENDER_ATTACHMENT | TextureUsages::STORAGE_BINDING);
+    device
+        .create_texture(&TextureDescriptor {
+            label: Some(&namespaced("test texture")),
+            size: Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
+
+/// The entries of every cache of a [`RenderManager`] at one point in time, to count
+/// what was created after it.
+#[derive(Debug, Clone)]
+pub struct CacheSnapshot {
+    entries: HashSet<(CacheKind, u64)>,
+}
+
+impl CacheSnapshot {
+    pub fn take(render_manager: &RenderManager) -> Self {
+        Self {
+            entries: render_manager.cache_entries().into_iter().map(|e| (e.kind, e.id)).collect(),
+        }
+    }
+
+    /// Entries cached now that were not cached when the snapshot was taken.
+    ///
+    /// Entries evicted and recreated in between look unchanged, take snapshots around
+    /// the calls under test only.
+    pub fn created_since(&self, render_manager: &RenderManager) -> CreatedEntries {
+        let mut counts = HashMap::new();
+        for entry in render_manager.cache_entries() {
+            if !self.entries.contains(&(entry.kind, entry.id)) {
+                *counts.entry(entry.kind).or_insert(0) += 1;
+            }
+        }
+        CreatedEntries { counts }
+    }
+}
+
+/// Number of cache entries created per [`CacheKind`], see [`CacheSnapshot::created_since`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreatedEntries {
+    counts: HashMap<CacheKind, usize>,
+}
+
+impl CreatedEntries {
+    pub fn get(&self, kind: CacheKind) -> usize {
+        self.counts.get(&kind).copied().unwrap_or(0)
+    }
+
+    /// Created bind group layouts of all caches.
+    pub fn layouts(&self) -> usize {
+        self.sum(&[CacheKind::UniformLayout, CacheKind::MaterialLayout, CacheKind::ComputeLayout, CacheKind::BuilderLayout])
+    }
+
+    /// Created bind groups of all caches.
+    pub fn bind_groups(&self) -> usize {
+        self.sum(&[
+            CacheKind::MaterialBindGroup,
+            CacheKind::KeyedMaterialBindGroup,
+            CacheKind::UniformBindGroup,
+            CacheKind::ComputeBindGroup,
+            CacheKind::BuilderBindGroup,
+            CacheKind::FullscreenBindGroup,
+        ])
+    }
+
+    /// Created render, compute and fullscreen pipelines.
+    pub fn pipelines(&self) -> usize {
+        self.sum(&[CacheKind::RenderPipeline, CacheKind::ComputePipeline, CacheKind::FullscreenPipeline])
+    }
+
+    /// Total number of created entries.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// # Panics
+    /// If not exactly `expected` entries of `kind` were created, listing all counts.
+    #[track_caller]
+    pub fn assert_created(&self, kind: CacheKind, expected: usize) {
+        let actual = self.get(kind);
+        assert!(actual == expected, "expected {} new {}, {} were created ({:?})", expected, kind, actual, self.counts);
+    }
+
+    fn sum(&self, kinds: &[CacheKind]) -> usize {
+        kinds.iter().map(|&kind| self.get(kind)).sum()
+    }
+}
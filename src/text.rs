@@ -0,0 +1,735 @@
+//! Text rendering through a glyph atlas (feature `text`).
+//!
+//! Fonts are rasterized with `fontdue` on first use of each glyph and size into an
+//! `R8Unorm` [glyph atlas](TextRenderer::atlas_view) managed by the [`TextRenderer`].
+//! Queued text is laid out (alignment, wrapping, line breaks) in
+//! [`prepare`](TextRenderer::prepare) and drawn as one instanced draw of glyph quads with
+//! the renderer's own pipeline and bind group. When the atlas fills up it grows, up to
+//! 4096x4096, after which it is cleared and refilled with the glyphs still in use.
+//!
+//! ## Signed distance fields
+//! A renderer created with [`new_sdf`](TextRenderer::new_sdf) stores every glyph once, as
+//! a signed distance field generated from a 48 px rasterization, and scales it to any
+//! size with crisp edges. Its shader permutation also draws
+//! [outlines](TextStyle::with_outline) and soft [drop shadows](TextStyle::with_shadow)
+//! from the same field. Below roughly 14 px, bitmap glyphs look sharper.
+//!
+//! Positions and sizes are in pixels with the origin at the top left of the target.
+//! ```ignore
+//! let mut text = TextRenderer::new(&device, &queue, surface.ui_format());
+//! let font = text.add_font(include_bytes!("fonts/Inter.ttf"))?;
+//! // Every frame
+//! text.queue(font, "Score: 1200", [16.0, 16.0], &TextStyle::new(24.0));
+//! text.queue(font, &dialog, [400.0, 500.0], &TextStyle::new(18.0).with_align(TextAlign::Center).with_max_width(480.0));
+//! text.prepare(width, height);
+//! // Inside a render pass writing the target
+//! text.render(&mut pass);
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use wgpu::*;
+use crate::gpu_util;
+use crate::labels::namespaced;
+
+const TEXT_SHADER: &str = r#"
+struct Screen {
+    size: vec2<f32>,
+    _pad: vec2<f32>,
+};
+
+struct GlyphInstance {
+    @location(0) rect: vec4<f32>,
+    @location(1) uv_rect: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) outline_color: vec4<f32>,
+    @location(4) params: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) outline_color: vec4<f32>,
+    @location(3) params: vec4<f32>,
+};
+
+@group(0) @binding(0) var s_atlas: sampler;
+@group(0) @binding(1) var t_atlas: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> screen: Screen;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32, glyph: GlyphInstance) -> VertexOutput {
+    let corner = vec2<f32>(f32(idx & 1u), f32(idx >> 1u));
+    let pixel = glyph.rect.xy + corner * glyph.rect.zw;
+    var out: VertexOutput;
+    out.position = vec4<f32>(pixel / screen.size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = mix(glyph.uv_rect.xy, glyph.uv_rect.zw, corner);
+    out.color = glyph.color;
+    out.outline_color = glyph.outline_color;
+    out.params = glyph.params;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let value = textureSample(t_atlas, s_atlas, in.uv).r;
+    if !SDF {
+        return vec4<f32>(in.color.rgb, in.color.a * value);
+    }
+    // 0.5 is the glyph edge; params.x is the outline width, params.y extra softness,
+    // both in distance units.
+    let edge = max(fwidth(value) * 0.5, 0.0001) + in.params.y;
+    let fill = smoothstep(0.5 - edge, 0.5 + edge, value);
+    let outer = smoothstep(0.5 - in.params.x - edge, 0.5 - in.params.x + edge, value);
+    let color = mix(in.outline_color, in.color, fill);
+    return vec4<f32>(color.rgb, color.a * outer);
+}
+"#;
+
+/// Initial width and height of the glyph atlas.
+const INITIAL_ATLAS_SIZE: u32 = 512;
+const MAX_ATLAS_SIZE: u32 = 4096;
+/// Empty pixels around every glyph, so bilinear filtering never bleeds into neighbours.
+const GLYPH_PADDING: u32 = 1;
+/// Size distance field glyphs are rasterized at.
+const SDF_BASE_SIZE: u32 = 48;
+/// Distance in base size pixels covered on either side of a glyph edge, which limits
+/// outline width and shadow softness.
+const SDF_SPREAD: u32 = 6;
+
+const GLYPH_ATTRIBUTES: [VertexAttribute; 5] =
+    wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Float32x4, 4 => Float32x4];
+
+const GLYPH_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: size_of::<GlyphInstance>() as u64,
+    step_mode: VertexStepMode::Instance,
+    attributes: &GLYPH_ATTRIBUTES,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    /// x, y, width, height in pixels.
+    rect: [f32; 4],
+    /// Atlas UVs of the top left and bottom right corner.
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+    /// Distance field outline color, blended inside the outline width.
+    outline_color: [f32; 4],
+    /// Outline width and extra edge softness in distance units.
+    params: [f32; 4],
+}
+
+/// A font that could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextError {
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load font {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for TextError {}
+
+/// Handle to a font added with [`TextRenderer::add_font`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontId(usize);
+
+/// Horizontal alignment of the lines of a text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Size, color and layout of queued text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+    /// Font size in pixels.
+    pub size: f32,
+    /// Straight (not premultiplied) RGBA.
+    pub color: [f32; 4],
+    /// Without `max_width`, the position is the left edge, center or right edge of every
+    /// line; with it, lines are aligned within `x..x + max_width`.
+    pub align: TextAlign,
+    /// Wrap lines at spaces to stay within this width. Words longer than the width
+    /// overflow.
+    pub max_width: Option<f32>,
+    /// Multiplier of the font's line spacing.
+    pub line_height: f32,
+    /// Outline around the glyphs, only drawn by distance field renderers.
+    pub outline: Option<TextOutline>,
+    /// Shadow behind the glyphs, only drawn by distance field renderers.
+    pub shadow: Option<TextShadow>,
+}
+
+/// Outline of distance field text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextOutline {
+    /// Width in pixels outside the glyph edge, at most 6 px per 48 px of font size.
+    pub width: f32,
+    pub color: [f32; 4],
+}
+
+/// Drop shadow of distance field text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextShadow {
+    /// Offset from the text in pixels.
+    pub offset: [f32; 2],
+    /// Blur of the shadow edge in pixels, limited like [`TextOutline::width`].
+    pub softness: f32,
+    pub color: [f32; 4],
+}
+
+impl TextStyle {
+    /// White, left-aligned text of `size` pixels.
+    pub fn new(size: f32) -> Self {
+        Self {
+            size,
+            color: [1.0; 4],
+            align: TextAlign::Left,
+            max_width: None,
+            line_height: 1.0,
+            outline: None,
+            shadow: None,
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_line_height(mut self, line_height: f32) -> Self {
+        self.line_height = line_height;
+        self
+    }
+
+    pub fn with_outline(mut self, width: f32, color: [f32; 4]) -> Self {
+        self.outline = Some(TextOutline { width, color });
+        self
+    }
+
+    pub fn with_shadow(mut self, offset: [f32; 2], softness: f32, color: [f32; 4]) -> Self {
+        self.shadow = Some(TextShadow { offset, softness, color });
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: FontId,
+    character: char,
+    size_px: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AtlasGlyph {
+    /// Top left corner in the atlas, `None` for glyphs without pixels (whitespace).
+    origin: Option<[u32; 2]>,
+    size: [u32; 2],
+    /// Offset of the bitmap's top left corner from the pen position on the baseline.
+    offset: [f32; 2],
+}
+
+/// The atlas ran out of space during layout.
+struct AtlasFull;
+
+/// Shelf-packed `R8Unorm` glyph texture.
+struct GlyphAtlas {
+    texture: Texture,
+    view: TextureView,
+    size: u32,
+    /// Store distance fields instead of coverage.
+    sdf: bool,
+    cursor: [u32; 2],
+    row_height: u32,
+    glyphs: HashMap<GlyphKey, AtlasGlyph>,
+}
+
+impl GlyphAtlas {
+    fn new(device: &Device, size: u32, sdf: bool) -> Self {
+        trace_event!(size, sdf, "created glyph atlas");
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(&namespaced("glyph atlas")),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Self {
+            texture,
+            view,
+            size,
+            sdf,
+            cursor: [GLYPH_PADDING; 2],
+            row_height: 0,
+            glyphs: HashMap::new(),
+        }
+    }
+
+    /// Reserve a `width` x `height` rectangle, starting a new shelf when the current
+    /// one is full.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        if self.cursor[0] + width + GLYPH_PADDING > self.size {
+            self.cursor = [GLYPH_PADDING, self.cursor[1] + self.row_height + GLYPH_PADDING];
+            self.row_height = 0;
+        }
+        if self.cursor[1] + height + GLYPH_PADDING > self.size || width + 2 * GLYPH_PADDING > self.size {
+            return None;
+        }
+        let origin = self.cursor;
+        self.cursor[0] += width + GLYPH_PADDING;
+        self.row_height = self.row_height.max(height);
+        Some(origin)
+    }
+
+    /// The glyph of `key`, rasterized into the atlas on first use.
+    fn glyph(&mut self, queue: &Queue, fonts: &[fontdue::Font], key: GlyphKey) -> Result<AtlasGlyph, AtlasFull> {
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Ok(*glyph);
+        }
+        let (metrics, mut pixels) = fonts[key.font.0].rasterize(key.character, key.size_px as f32);
+        let (mut width, mut height) = (metrics.width as u32, metrics.height as u32);
+        let mut offset = [metrics.xmin as f32, -(metrics.ymin as f32 + height as f32)];
+        if self.sdf && width > 0 && height > 0 {
+            pixels = distance_field(&pixels, width, height, SDF_SPREAD);
+            (width, height) = (width + 2 * SDF_SPREAD, height + 2 * SDF_SPREAD);
+            offset = offset.map(|o| o - SDF_SPREAD as f32);
+        }
+        let origin = if width == 0 || height == 0 {
+            None
+        } else {
+            let origin = self.allocate(width, height).ok_or(AtlasFull)?;
+            queue.write_texture(
+                TexelCopyTextureInfo {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: Origin3d { x: origin[0], y: origin[1], z: 0 },
+                    aspect: TextureAspect::All,
+                },
+                &pixels,
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width),
+                    rows_per_image: Some(height),
+                },
+                Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+            Some(origin)
+        };
+        let glyph = AtlasGlyph {
+            origin,
+            size: [width, height],
+            offset,
+        };
+        self.glyphs.insert(key, glyph);
+        Ok(glyph)
+    }
+}
+
+struct QueuedText {
+    font: FontId,
+    text: String,
+    position: [f32; 2],
+    style: TextStyle,
+}
+
+/// Glyph atlas, text layout and a batched glyph quad renderer.
+pub struct TextRenderer {
+    device: Device,
+    queue: Queue,
+    fonts: Vec<fontdue::Font>,
+    atlas: GlyphAtlas,
+    layout: BindGroupLayout,
+    module: ShaderModule,
+    target_format: TextureFormat,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    screen: Buffer,
+    bind_group: BindGroup,
+    queued: Vec<QueuedText>,
+    instances: Buffer,
+    instance_capacity: u64,
+    instance_count: u32,
+}
+
+impl TextRenderer {
+    /// Create a renderer drawing into targets of `target_format`.
+    pub fn new(device: &Device, queue: &Queue, target_format: TextureFormat) -> Self {
+        Self::with_mode(device, queue, target_format, false)
+    }
+
+    /// Create a renderer drawing distance field glyphs, which scale to any size and
+    /// support outlines and shadows.
+    pub fn new_sdf(device: &Device, queue: &Queue, target_format: TextureFormat) -> Self {
+        Self::with_mode(device, queue, target_format, true)
+    }
+
+    fn with_mode(device: &Device, queue: &Queue, target_format: TextureFormat, sdf: bool) -> Self {
+        let source = format!("const SDF: bool = {sdf};\n{TEXT_SHADER}");
+        let module = gpu_util::shader(device, "text shader", &source);
+        let layout = gpu_util::bind_group_layout(device, "text layout", &[
+            gpu_util::sampler_entry(0, ShaderStages::FRAGMENT, SamplerBindingType::Filtering),
+            gpu_util::texture_entry(1, ShaderStages::FRAGMENT, TextureSampleType::Float { filterable: true }, TextureViewDimension::D2),
+            gpu_util::uniform_entry(2, ShaderStages::VERTEX),
+        ]);
+        let pipeline = create_pipeline(device, &module, &layout, target_format, None);
+        let sampler = gpu_util::linear_sampler(device, "text sampler");
+        let screen = gpu_util::buffer(device, "text screen", 16, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let atlas = GlyphAtlas::new(device, INITIAL_ATLAS_SIZE, sdf);
+        let bind_group = create_bind_group(device, &layout, &sampler, &atlas.view, &screen);
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            fonts: Vec::new(),
+            atlas,
+            layout,
+            module,
+            target_format,
+            pipeline,
+            sampler,
+            screen,
+            bind_group,
+            queued: Vec::new(),
+            instances: instance_buffer(device, 256),
+            instance_capacity: 256,
+            instance_count: 0,
+        }
+    }
+
+    /// Draw into passes with a depth attachment of `depth_format`. Text ignores and
+    /// keeps the depth, so it is always on top.
+    pub fn with_depth_format(mut self, depth_format: Option<TextureFormat>) -> Self {
+        self.pipeline = create_pipeline(&self.device, &self.module, &self.layout, self.target_format, depth_format);
+        self
+    }
+
+    /// Load a TrueType or OpenType font.
+    pub fn add_font(&mut self, bytes: &[u8]) -> Result<FontId, TextError> {
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default()).map_err(|message| TextError {
+            label: format!("#{}", self.fonts.len()),
+            message: message.to_string(),
+        })?;
+        self.fonts.push(font);
+        Ok(FontId(self.fonts.len() - 1))
+    }
+
+    /// Queue `text` with its top left corner (or alignment anchor, see
+    /// [`TextStyle::align`]) at `position` for the next [`prepare`](Self::prepare).
+    pub fn queue(&mut self, font: FontId, text: &str, position: [f32; 2], style: &TextStyle) {
+        self.queued.push(QueuedText {
+            font,
+            text: text.to_string(),
+            position,
+            style: *style,
+        });
+    }
+
+    /// Width and height `text` would take up with `style`.
+    pub fn measure(&self, font: FontId, text: &str, style: &TextStyle) -> [f32; 2] {
+        let font = &self.fonts[font.0];
+        let lines = break_lines(font, text, style.size, style.max_width);
+        let width = lines.iter().map(|&(_, width)| width).fold(0.0, f32::max);
+        [width, lines.len() as f32 * line_advance(font, style)]
+    }
+
+    /// Lay out all queued text, rasterize missing glyphs and upload the glyph quads for
+    /// a target of `width` x `height` pixels. Clears the queue.
+    ///
+    /// Written with `Queue::write_buffer`/`write_texture`, so prepare once per submission.
+    pub fn prepare(&mut self, width: u32, height: u32) {
+        let _span = trace_span!("text_prepare", texts = self.queued.len());
+        let screen = [width.max(1) as f32, height.max(1) as f32, 0.0, 0.0];
+        self.queue.write_buffer(&self.screen, 0, bytemuck::cast_slice(&screen));
+
+        let queued = std::mem::take(&mut self.queued);
+        let mut instances = Vec::new();
+        let mut cleared = false;
+        loop {
+            instances.clear();
+            let laid_out = queued
+                .iter()
+                .try_for_each(|text| layout_text(&self.queue, &self.fonts, &mut self.atlas, text, &mut instances));
+            if laid_out.is_ok() {
+                break;
+            }
+            // Out of space: grow, or start over once with only the glyphs of this frame.
+            if self.atlas.size >= MAX_ATLAS_SIZE {
+                if cleared {
+                    trace_event!("text does not fit into the glyph atlas");
+                    instances.clear();
+                    break;
+                }
+                cleared = true;
+            }
+            let size = (self.atlas.size * 2).min(MAX_ATLAS_SIZE);
+            self.atlas = GlyphAtlas::new(&self.device, size, self.atlas.sdf);
+            self.bind_group = create_bind_group(&self.device, &self.layout, &self.sampler, &self.atlas.view, &self.screen);
+        }
+
+        let count = instances.len() as u64;
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instances = instance_buffer(&self.device, self.instance_capacity);
+        }
+        if !instances.is_empty() {
+            self.queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&instances));
+        }
+        self.instance_count = instances.len() as u32;
+    }
+
+    /// Draw the text of the last [`prepare`](Self::prepare).
+    pub fn render(&self, pass: &mut RenderPass) {
+        if self.instance_count == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instances.slice(..));
+        pass.draw(0..4, 0..self.instance_count);
+    }
+
+    /// Whether glyphs are drawn from distance fields, see [`new_sdf`](Self::new_sdf).
+    pub fn is_sdf(&self) -> bool {
+        self.atlas.sdf
+    }
+
+    /// The glyph atlas, e.g. to inspect it in a debug view.
+    pub fn atlas_view(&self) -> &TextureView {
+        &self.atlas.view
+    }
+
+    /// Number of glyph quads drawn by [`render`](Self::render), including shadows.
+    pub fn glyph_count(&self) -> u32 {
+        self.instance_count
+    }
+}
+
+/// Append the glyph quads of `text` to `instances`.
+fn layout_text(
+    queue: &Queue,
+    fonts: &[fontdue::Font],
+    atlas: &mut GlyphAtlas,
+    text: &QueuedText,
+    instances: &mut Vec<GlyphInstance>,
+) -> Result<(), AtlasFull> {
+    let font = &fonts[text.font.0];
+    let style = &text.style;
+    // Distance field glyphs are rasterized once and scaled.
+    let (size_px, scale) = if atlas.sdf {
+        (SDF_BASE_SIZE, style.size / SDF_BASE_SIZE as f32)
+    } else {
+        (style.size.round().max(1.0) as u32, 1.0)
+    };
+    // One distance unit spans twice the spread, in screen pixels.
+    let distance_unit = 2.0 * SDF_SPREAD as f32 * scale;
+    let (outline_width, outline_color) = match style.outline {
+        Some(outline) if atlas.sdf => (outline.width / distance_unit, outline.color),
+        _ => (0.0, style.color),
+    };
+    let first = instances.len();
+    let ascent = font.horizontal_line_metrics(style.size).map_or(style.size, |metrics| metrics.ascent);
+    let advance = line_advance(font, style);
+    let atlas_size = atlas.size as f32;
+
+    for (i, (line, width)) in break_lines(font, &text.text, style.size, style.max_width).into_iter().enumerate() {
+        let x = match (style.align, style.max_width) {
+            (TextAlign::Left, _) => text.position[0],
+            (TextAlign::Center, Some(max_width)) => text.position[0] + (max_width - width) * 0.5,
+            (TextAlign::Center, None) => text.position[0] - width * 0.5,
+            (TextAlign::Right, Some(max_width)) => text.position[0] + max_width - width,
+            (TextAlign::Right, None) => text.position[0] - width,
+        };
+        let baseline = (text.position[1] + ascent + i as f32 * advance).round();
+        let mut pen = x.round();
+        for character in line.chars() {
+            let glyph = atlas.glyph(queue, fonts, GlyphKey { font: text.font, character, size_px })?;
+            if let Some([u, v]) = glyph.origin {
+                let [w, h] = glyph.size;
+                instances.push(GlyphInstance {
+                    rect: [
+                        pen + glyph.offset[0] * scale,
+                        baseline + glyph.offset[1] * scale,
+                        w as f32 * scale,
+                        h as f32 * scale,
+                    ],
+                    uv_rect: [
+                        u as f32 / atlas_size,
+                        v as f32 / atlas_size,
+                        (u + w) as f32 / atlas_size,
+                        (v + h) as f32 / atlas_size,
+                    ],
+                    color: style.color,
+                    outline_color,
+                    params: [outline_width.min(0.5), 0.0, 0.0, 0.0],
+                });
+            }
+            pen += font.metrics(character, style.size).advance_width;
+        }
+    }
+    // Shadows go before all glyphs of the text, so they never cover a neighbour.
+    if let Some(shadow) = style.shadow.filter(|_| atlas.sdf) {
+        let softness = shadow.softness / distance_unit;
+        let shadows: Vec<GlyphInstance> = instances[first..]
+            .iter()
+            .map(|glyph| GlyphInstance {
+                rect: [glyph.rect[0] + shadow.offset[0], glyph.rect[1] + shadow.offset[1], glyph.rect[2], glyph.rect[3]],
+                color: shadow.color,
+                outline_color: shadow.color,
+                params: [glyph.params[0], softness.min(0.5), 0.0, 0.0],
+                ..*glyph
+            })
+            .collect();
+        instances.splice(first..first, shadows);
+    }
+    Ok(())
+}
+
+/// Signed distance field of a coverage bitmap, padded by `spread` on every side.
+/// 128 is the edge; values fall from 255 at `spread` pixels inside to 0 at `spread`
+/// pixels outside.
+fn distance_field(coverage: &[u8], width: u32, height: u32, spread: u32) -> Vec<u8> {
+    let (width, height, spread) = (width as i32, height as i32, spread as i32);
+    let inside = |x: i32, y: i32| x >= 0 && y >= 0 && x < width && y < height && coverage[(y * width + x) as usize] >= 128;
+    let (padded_width, padded_height) = (width + 2 * spread, height + 2 * spread);
+    let mut field = Vec::with_capacity((padded_width * padded_height) as usize);
+    for py in 0..padded_height {
+        for px in 0..padded_width {
+            let (x, y) = (px - spread, py - spread);
+            let state = inside(x, y);
+            // Nearest texel on the other side of the edge; the edge lies halfway.
+            let mut nearest = (spread * spread) as f32;
+            for dy in -spread..=spread {
+                for dx in -spread..=spread {
+                    let squared = (dx * dx + dy * dy) as f32;
+                    if squared < nearest && inside(x + dx, y + dy) != state {
+                        nearest = squared;
+                    }
+                }
+            }
+            let distance = (nearest.sqrt() - 0.5).clamp(0.0, spread as f32);
+            let signed = if state { distance } else { -distance };
+            field.push((128.0 + signed / spread as f32 * 127.0).round().clamp(0.0, 255.0) as u8);
+        }
+    }
+    field
+}
+
+/// Vertical distance between baselines.
+fn line_advance(font: &fontdue::Font, style: &TextStyle) -> f32 {
+    let spacing = font.horizontal_line_metrics(style.size).map_or(style.size * 1.2, |metrics| metrics.new_line_size);
+    spacing * style.line_height
+}
+
+/// Split `text` at line breaks and, with `max_width`, at the last space that keeps a
+/// line within it. Returns every line with its width.
+fn break_lines<'a>(font: &fontdue::Font, text: &'a str, size: f32, max_width: Option<f32>) -> Vec<(&'a str, f32)> {
+    let width_of = |line: &str| line.chars().map(|c| font.metrics(c, size).advance_width).sum::<f32>();
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let Some(max_width) = max_width else {
+            lines.push((paragraph, width_of(paragraph)));
+            continue;
+        };
+        let (mut line_start, mut line_end, mut position) = (0, 0, 0);
+        for word in paragraph.split(' ') {
+            let (word_start, word_end) = (position, position + word.len());
+            position = word_end + 1;
+            if word_start > line_start && width_of(&paragraph[line_start..word_end]) > max_width {
+                let line = &paragraph[line_start..line_end];
+                lines.push((line, width_of(line)));
+                line_start = word_start;
+            }
+            line_end = word_end;
+        }
+        let line = &paragraph[line_start..line_end];
+        lines.push((line, width_of(line)));
+    }
+    lines
+}
+
+fn create_pipeline(
+    device: &Device,
+    module: &ShaderModule,
+    layout: &BindGroupLayout,
+    target_format: TextureFormat,
+    depth_format: Option<TextureFormat>,
+) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("text pipeline layout")),
+        bind_group_layouts: &[layout],
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("text pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            buffers: &[GLYPH_LAYOUT],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format: target_format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: depth_format.map(|format| DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+fn create_bind_group(device: &Device, layout: &BindGroupLayout, sampler: &Sampler, atlas: &TextureView, screen: &Buffer) -> BindGroup {
+    gpu_util::bind_group(device, "text", layout, &[
+        BindingResource::Sampler(sampler),
+        BindingResource::TextureView(atlas),
+        screen.as_entire_binding(),
+    ])
+}
+
+fn instance_buffer(device: &Device, capacity: u64) -> Buffer {
+    gpu_util::buffer(device, "text glyphs", capacity * GLYPH_LAYOUT.array_stride, BufferUsages::VERTEX | BufferUsages::COPY_DST)
+}
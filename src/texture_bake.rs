@@ -0,0 +1,509 @@
+//! Baked textures: block-compressed, mipmapped, ready to upload without decoding.
+//!
+//! A [`BakedTexture`] holds every mip level in its final GPU format and is stored as a
+//! small binary file (`.rmtex`, see [`BakedTexture::to_bytes`]). Loading one is a file
+//! read and one `write_texture` per mip, so shipping builds never decode PNGs:
+//! ```ignore
+//! let baked = BakedTexture::from_bytes("brick", &std::fs::read("assets/brick.rmtex")?)?;
+//! let texture = baked.upload(&device, &queue, "brick")?;
+//! ```
+//! The `AssetServer` loads `.rmtex` files the same way.
+//!
+//! Baking (feature `bake`) runs offline, from a build script or an editor. It generates
+//! mips with a gamma-correct box filter and compresses them to BC1/3/4/5/7 or ASTC 4x4
+//! with the ISPC texture compressor:
+//! ```ignore
+//! // build.rs
+//! for source in ["textures/brick.png", "textures/grass.png"] {
+//!     bake_file(source, Path::new(source).with_extension("rmtex"), &BakeOptions::new(BakeFormat::Bc7))?;
+//! }
+//! bake_file("textures/brick_normal.png", "textures/brick_normal.rmtex", &BakeOptions::new(BakeFormat::Bc5).with_srgb(false))?;
+//! ```
+//! [`bake_image_cached`] bakes at startup instead, keeping results in a
+//! [`DiskCache`](crate::disk_cache::DiskCache) so only changed images are baked again.
+use std::fmt;
+#[cfg(feature = "native")]
+use std::fs::File;
+#[cfg(feature = "native")]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(any(feature = "bake", feature = "native"))]
+use std::path::Path;
+use wgpu::*;
+#[cfg(feature = "bake")]
+use crate::disk_cache::{CacheKey, DiskCache};
+use crate::textures::{check_texture, LoadedTexture};
+use crate::labels::namespaced;
+
+const MAGIC: &[u8; 4] = b"RMTX";
+const VERSION: u32 = 1;
+
+/// A baked texture that could not be read, written or uploaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BakeError {
+    /// Path or label of the texture.
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for BakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to bake {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for BakeError {}
+
+fn error(label: &str, message: impl fmt::Display) -> BakeError {
+    BakeError {
+        label: label.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Formats a baked texture can be stored in, with their file codes.
+const FORMATS: [(TextureFormat, u32); 16] = [
+    (TextureFormat::Rgba8Unorm, 1),
+    (TextureFormat::Rgba8UnormSrgb, 2),
+    (TextureFormat::Bc1RgbaUnorm, 3),
+    (TextureFormat::Bc1RgbaUnormSrgb, 4),
+    (TextureFormat::Bc3RgbaUnorm, 5),
+    (TextureFormat::Bc3RgbaUnormSrgb, 6),
+    (TextureFormat::Bc4RUnorm, 7),
+    (TextureFormat::Bc5RgUnorm, 8),
+    (TextureFormat::Bc7RgbaUnorm, 9),
+    (TextureFormat::Bc7RgbaUnormSrgb, 10),
+    (TextureFormat::Astc { block: AstcBlock::B4x4, channel: AstcChannel::Unorm }, 11),
+    (TextureFormat::Astc { block: AstcBlock::B4x4, channel: AstcChannel::UnormSrgb }, 12),
+    (TextureFormat::Rgba16Float, 13),
+    (TextureFormat::Rg11b10Ufloat, 14),
+    (TextureFormat::Etc2Rgba8Unorm, 15),
+    (TextureFormat::Etc2Rgba8UnormSrgb, 16),
+];
+
+/// A texture with all mip levels in their GPU format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BakedTexture {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed data of each mip level, largest first.
+    pub mips: Vec<Vec<u8>>,
+}
+
+impl BakedTexture {
+    /// A single-mip `Rgba8UnormSrgb` texture, e.g. a decoded image loaded as is.
+    pub fn rgba8(width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            format: TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            mips: vec![data],
+        }
+    }
+
+    pub fn size(&self) -> Extent3d {
+        Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    /// Device features needed to sample the format, if any.
+    pub fn required_features(&self) -> Features {
+        self.format.required_features()
+    }
+
+    /// Size of the texture data in bytes.
+    pub fn byte_size(&self) -> usize {
+        self.mips.iter().map(Vec::len).sum()
+    }
+
+    /// Serialize to the `.rmtex` layout: magic, version, format code, width, height and
+    /// mip count as little-endian `u32`s, then each mip as a `u64` length and its bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let code = FORMATS
+            .iter()
+            .find(|(format, _)| *format == self.format)
+            .map(|(_, code)| *code)
+            .expect("baked textures only use formats listed in FORMATS");
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.mips.len() * 8 + self.byte_size());
+        bytes.extend_from_slice(MAGIC);
+        for value in [VERSION, code, self.width, self.height, self.mips.len() as u32] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for mip in &self.mips {
+            bytes.extend_from_slice(&(mip.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(mip);
+        }
+        bytes
+    }
+
+    /// Parse a `.rmtex` file written by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(label: &str, bytes: &[u8]) -> Result<Self, BakeError> {
+        let mut cursor = bytes;
+        let (format, width, height, mip_count) = read_header(&mut cursor, label)?;
+        let mut next = |len: usize| take(&mut cursor, len, label);
+        let mips = (0..mip_count)
+            .map(|_| {
+                let len = u64::from_le_bytes(next(8)?.try_into().expect("8 bytes")) as usize;
+                Ok(next(len)?.to_vec())
+            })
+            .collect::<Result<Vec<_>, BakeError>>()?;
+        Ok(Self {
+            format,
+            width,
+            height,
+            mips,
+        })
+    }
+
+    /// Create a sampled texture with all mips. Fails if the device lacks the features of
+    /// the format, e.g. BCn on mobile or ASTC on most desktops.
+    pub fn upload(&self, device: &Device, queue: &Queue, label: &str) -> Result<LoadedTexture, BakeError> {
+        let size = self.size();
+        let usage = TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST;
+        check_texture(device, size, self.format, usage).map_err(|e| error(label, e))?;
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(&namespaced(label)),
+            size,
+            mip_level_count: self.mips.len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.format,
+            usage,
+            view_formats: &[],
+        });
+        for (level, data) in self.mips.iter().enumerate() {
+            write_mip(queue, &texture, level as u32, data);
+        }
+        trace_event!(label, mips = self.mips.len(), format = ?self.format, "uploaded baked texture");
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Ok(LoadedTexture { texture, view })
+    }
+}
+
+/// Upload tightly packed data of one mip level of a 2D texture.
+pub(crate) fn write_mip(queue: &Queue, texture: &Texture, level: u32, data: &[u8]) {
+    let format = texture.format();
+    let (block_w, block_h) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+    let mip_size = texture.size().mip_level_size(level, TextureDimension::D2);
+    queue.write_texture(
+        TexelCopyTextureInfo {
+            texture,
+            mip_level: level,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        data,
+        TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(mip_size.width.div_ceil(block_w) * block_size),
+            rows_per_image: Some(mip_size.height.div_ceil(block_h)),
+        },
+        mip_size.physical_size(format),
+    );
+}
+
+/// Size of the `.rmtex` header before the first mip.
+const HEADER_SIZE: usize = 24;
+
+/// Parse the header: format, width, height and mip count.
+fn read_header(cursor: &mut &[u8], label: &str) -> Result<(TextureFormat, u32, u32, u32), BakeError> {
+    if take(cursor, 4, label)? != MAGIC {
+        return Err(error(label, "not a baked texture"));
+    }
+    let mut header = [0u32; 5];
+    for value in &mut header {
+        *value = u32::from_le_bytes(take(cursor, 4, label)?.try_into().expect("4 bytes"));
+    }
+    let [version, code, width, height, mip_count] = header;
+    if version != VERSION {
+        return Err(error(label, format!("unsupported version {}", version)));
+    }
+    let format = FORMATS
+        .iter()
+        .find(|(_, c)| *c == code)
+        .map(|(format, _)| *format)
+        .ok_or_else(|| error(label, format!("unknown format code {}", code)))?;
+    if mip_count == 0 {
+        return Err(error(label, "no mip levels"));
+    }
+    Ok((format, width, height, mip_count))
+}
+
+/// Where each mip level of a `.rmtex` file is, for reading levels one at a time.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BakedLayout {
+    pub(crate) format: TextureFormat,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Byte offset and length of each mip level, largest first.
+    pub(crate) mips: Vec<(u64, u64)>,
+}
+
+#[cfg(feature = "native")]
+impl BakedLayout {
+    /// Read the header and the mip lengths of a file, skipping the data.
+    pub(crate) fn read(path: &Path) -> Result<Self, BakeError> {
+        let label = &path.display().to_string();
+        let io = |e: std::io::Error| error(label, e);
+        let mut reader = File::open(path).map_err(io)?;
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(io)?;
+        let (format, width, height, mip_count) = read_header(&mut &header[..], label)?;
+        let end = reader.seek(SeekFrom::End(0)).map_err(io)?;
+        let mut offset = HEADER_SIZE as u64;
+        let mut mips = Vec::with_capacity(mip_count as usize);
+        for _ in 0..mip_count {
+            let mut len = [0u8; 8];
+            reader.seek(SeekFrom::Start(offset)).map_err(io)?;
+            reader.read_exact(&mut len).map_err(io)?;
+            let len = u64::from_le_bytes(len);
+            offset += 8;
+            if offset + len > end {
+                return Err(error(label, "unexpected end of file"));
+            }
+            mips.push((offset, len));
+            offset += len;
+        }
+        Ok(Self { format, width, height, mips })
+    }
+
+    /// The data of one mip level, read from the file the layout was read from.
+    pub(crate) fn read_mip(&self, path: &Path, level: u32) -> Result<Vec<u8>, BakeError> {
+        let label = &path.display().to_string();
+        let io = |e: std::io::Error| error(label, e);
+        let (offset, len) = self.mips[level as usize];
+        let mut reader = File::open(path).map_err(io)?;
+        let mut data = vec![0; len as usize];
+        reader.seek(SeekFrom::Start(offset)).map_err(io)?;
+        reader.read_exact(&mut data).map_err(io)?;
+        Ok(data)
+    }
+}
+
+/// Split `len` bytes off the front of `cursor`.
+fn take<'a>(cursor: &mut &'a [u8], len: usize, label: &str) -> Result<&'a [u8], BakeError> {
+    let bytes: &'a [u8] = cursor;
+    if bytes.len() < len {
+        return Err(error(label, "unexpected end of file"));
+    }
+    let (head, tail) = bytes.split_at(len);
+    *cursor = tail;
+    Ok(head)
+}
+
+/// Compressed format of a bake.
+#[cfg(feature = "bake")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BakeFormat {
+    /// Uncompressed RGBA8, mips only.
+    Rgba8,
+    /// 4 bpp RGB with 1-bit alpha.
+    Bc1,
+    /// 8 bpp RGBA.
+    Bc3,
+    /// 4 bpp single channel, e.g. roughness or height. Takes the red channel.
+    Bc4,
+    /// 8 bpp two channels, for tangent-space normal maps. Takes red and green.
+    Bc5,
+    /// 8 bpp high quality RGBA.
+    Bc7,
+    /// 8 bpp RGBA for mobile and Apple GPUs.
+    Astc4x4,
+}
+
+#[cfg(feature = "bake")]
+impl BakeFormat {
+    fn texture_format(self, srgb: bool) -> TextureFormat {
+        let (linear, srgb_format) = match self {
+            BakeFormat::Rgba8 => (TextureFormat::Rgba8Unorm, TextureFormat::Rgba8UnormSrgb),
+            BakeFormat::Bc1 => (TextureFormat::Bc1RgbaUnorm, TextureFormat::Bc1RgbaUnormSrgb),
+            BakeFormat::Bc3 => (TextureFormat::Bc3RgbaUnorm, TextureFormat::Bc3RgbaUnormSrgb),
+            BakeFormat::Bc4 => (TextureFormat::Bc4RUnorm, TextureFormat::Bc4RUnorm),
+            BakeFormat::Bc5 => (TextureFormat::Bc5RgUnorm, TextureFormat::Bc5RgUnorm),
+            BakeFormat::Bc7 => (TextureFormat::Bc7RgbaUnorm, TextureFormat::Bc7RgbaUnormSrgb),
+            BakeFormat::Astc4x4 => (
+                TextureFormat::Astc { block: AstcBlock::B4x4, channel: AstcChannel::Unorm },
+                TextureFormat::Astc { block: AstcBlock::B4x4, channel: AstcChannel::UnormSrgb },
+            ),
+        };
+        if srgb { srgb_format } else { linear }
+    }
+}
+
+/// How to bake a texture.
+#[cfg(feature = "bake")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BakeOptions {
+    pub format: BakeFormat,
+    /// Color data: filter mips in linear space and store an sRGB format. Defaults to `true`,
+    /// turn it off for normal, roughness and other data maps.
+    pub srgb: bool,
+    /// Generate the full mip chain. Defaults to `true`.
+    pub mipmaps: bool,
+}
+
+#[cfg(feature = "bake")]
+impl BakeOptions {
+    pub fn new(format: BakeFormat) -> Self {
+        Self {
+            format,
+            srgb: true,
+            mipmaps: true,
+        }
+    }
+
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    pub fn with_mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+}
+
+/// Bake tightly packed RGBA8 pixels.
+#[cfg(feature = "bake")]
+pub fn bake_rgba8(width: u32, height: u32, rgba: &[u8], options: &BakeOptions) -> BakedTexture {
+    let _span = trace_span!("texture_bake", width, height, format = ?options.format);
+    assert_eq!(rgba.len(), (width * height * 4) as usize, "RGBA8 data must be width * height * 4 bytes");
+    let mut levels = vec![(width, height, rgba.to_vec())];
+    while options.mipmaps {
+        let (w, h, data) = levels.last().expect("starts with mip 0");
+        if *w == 1 && *h == 1 {
+            break;
+        }
+        levels.push(downsample(*w, *h, data, options.srgb));
+    }
+    BakedTexture {
+        format: options.format.texture_format(options.srgb),
+        width,
+        height,
+        mips: levels
+            .iter()
+            .map(|(w, h, data)| compress(*w, *h, data, options.format))
+            .collect(),
+    }
+}
+
+/// Decode an image file of any format the `image` crate reads and bake it.
+#[cfg(feature = "bake")]
+pub fn bake_image(path: impl AsRef<Path>, options: &BakeOptions) -> Result<BakedTexture, BakeError> {
+    let path = path.as_ref();
+    let image = image::open(path).map_err(|e| error(&path.display().to_string(), e))?.to_rgba8();
+    Ok(bake_rgba8(image.width(), image.height(), image.as_raw(), options))
+}
+
+/// [`bake_image`], reusing the result of an earlier bake of the same file content with
+/// the same options from `cache`. For baking at startup in development builds.
+#[cfg(feature = "bake")]
+pub fn bake_image_cached(path: impl AsRef<Path>, options: &BakeOptions, cache: &DiskCache) -> Result<BakedTexture, BakeError> {
+    let path = path.as_ref();
+    let label = path.display().to_string();
+    let source = std::fs::read(path).map_err(|e| error(&label, e))?;
+    let key = CacheKey::new("rmtex bake v1").with(&source).with(format!("{:?}", options).as_bytes());
+    if let Some(cached) = cache.get(key)
+        && let Ok(baked) = BakedTexture::from_bytes(&label, &cached)
+    {
+        return Ok(baked);
+    }
+    let image = image::load_from_memory(&source).map_err(|e| error(&label, e))?.to_rgba8();
+    let baked = bake_rgba8(image.width(), image.height(), image.as_raw(), options);
+    if let Err(error) = cache.put(key, &baked.to_bytes()) {
+        trace_warn!(label = %label, %error, "failed to cache baked texture");
+        let _ = error;
+    }
+    Ok(baked)
+}
+
+/// Bake `source` and write it to `destination` as `.rmtex`.
+#[cfg(feature = "bake")]
+pub fn bake_file(source: impl AsRef<Path>, destination: impl AsRef<Path>, options: &BakeOptions) -> Result<(), BakeError> {
+    let destination = destination.as_ref();
+    let baked = bake_image(source, options)?;
+    std::fs::write(destination, baked.to_bytes()).map_err(|e| error(&destination.display().to_string(), e))
+}
+
+/// Halve a level with a 2x2 box filter, in linear space for sRGB color. Odd edges
+/// clamp, so 1-texel-wide levels keep shrinking along the other axis.
+#[cfg(feature = "bake")]
+fn downsample(width: u32, height: u32, rgba: &[u8], srgb: bool) -> (u32, u32, Vec<u8>) {
+    let to_linear = |v: u8, channel: usize| {
+        let v = v as f32 / 255.0;
+        if srgb && channel < 3 {
+            if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+        } else {
+            v
+        }
+    };
+    let to_stored = |v: f32, channel: usize| {
+        let v = if srgb && channel < 3 {
+            if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+        } else {
+            v
+        };
+        (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+    };
+    let (new_w, new_h) = ((width / 2).max(1), (height / 2).max(1));
+    let mut out = Vec::with_capacity((new_w * new_h * 4) as usize);
+    for y in 0..new_h {
+        for x in 0..new_w {
+            for channel in 0..4 {
+                let mut sum = 0.0;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(width - 1);
+                    let sy = (y * 2 + dy).min(height - 1);
+                    sum += to_linear(rgba[((sy * width + sx) * 4) as usize + channel], channel);
+                }
+                out.push(to_stored(sum * 0.25, channel));
+            }
+        }
+    }
+    (new_w, new_h, out)
+}
+
+/// Compress one level, padding it to whole 4x4 blocks by repeating edge texels.
+#[cfg(feature = "bake")]
+fn compress(width: u32, height: u32, rgba: &[u8], format: BakeFormat) -> Vec<u8> {
+    use intel_tex_2::{astc, bc1, bc3, bc4, bc5, bc7, RSurface, RgSurface, RgbaSurface};
+    if format == BakeFormat::Rgba8 {
+        return rgba.to_vec();
+    }
+    let (padded_w, padded_h) = (width.div_ceil(4) * 4, height.div_ceil(4) * 4);
+    let texel = |x: u32, y: u32| {
+        let i = ((y.min(height - 1) * width + x.min(width - 1)) * 4) as usize;
+        &rgba[i..i + 4]
+    };
+    let channels = |count: usize| -> Vec<u8> {
+        (0..padded_h)
+            .flat_map(|y| (0..padded_w).flat_map(move |x| texel(x, y)[..count].to_vec()))
+            .collect()
+    };
+    match format {
+        BakeFormat::Bc4 => {
+            let data = channels(1);
+            bc4::compress_blocks(&RSurface { width: padded_w, height: padded_h, stride: padded_w, data: &data })
+        }
+        BakeFormat::Bc5 => {
+            let data = channels(2);
+            bc5::compress_blocks(&RgSurface { width: padded_w, height: padded_h, stride: padded_w * 2, data: &data })
+        }
+        _ => {
+            let data = channels(4);
+            let surface = RgbaSurface { width: padded_w, height: padded_h, stride: padded_w * 4, data: &data };
+            match format {
+                BakeFormat::Bc1 => bc1::compress_blocks(&surface),
+                BakeFormat::Bc3 => bc3::compress_blocks(&surface),
+                BakeFormat::Bc7 => bc7::compress_blocks(&bc7::alpha_basic_settings(), &surface),
+                BakeFormat::Astc4x4 => astc::compress_blocks(&astc::alpha_fast_settings(4, 4), &surface),
+                BakeFormat::Rgba8 | BakeFormat::Bc4 | BakeFormat::Bc5 => unreachable!("handled above"),
+            }
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Progressive texture loading, coarse mips first.
+//!
+//! A [`StreamingTexture`] is created with only its smallest mips (the mip tail) and is
+//! bindable right away, blurry. Larger mips are uploaded one at a time as they arrive.
+//! Its [`view`](StreamingTexture::view) always covers exactly the resident mips, so the
+//! GPU never samples a level that was not uploaded yet. Each upload replaces the view,
+//! and because material bind groups are cached by their views, the next
+//! `render_with_textures` with [`view`](StreamingTexture::view) picks up a bind group
+//! with the new detail without any bookkeeping.
+//!
+//! `TextureStreamer` (feature `native`) feeds streaming textures from `.rmtex` files,
+//! reading one mip at a time on the `ResourceWorkers` and uploading at most a byte
+//! budget per frame:
+//! ```ignore
+//! let mut streamer = TextureStreamer::new(&device, &queue, render_manager.spawn_resource_workers(1));
+//! let terrain = streamer.stream("textures/terrain.rmtex")?;
+//!
+//! // every frame
+//! streamer.update();
+//! render_manager.render_with_textures(&[streamer.view(terrain).unwrap()], shader, &options, &[&camera], &mut pass);
+//! ```
+//!
+//! Bind groups of replaced views stay cached until evicted, at most one per mip level.
+//! Materials that release them explicitly pass the view returned by
+//! [`upload_mip`](StreamingTexture::upload_mip) to
+//! [`RenderManager::release_material`](crate::renderer::RenderManager::release_material).
+#[cfg(feature = "native")]
+use std::collections::HashMap;
+#[cfg(feature = "native")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "native")]
+use std::sync::Arc;
+use wgpu::*;
+#[cfg(feature = "native")]
+use crate::texture_bake::{BakeError, BakedLayout};
+use crate::texture_bake::write_mip;
+#[cfg(feature = "native")]
+use crate::workers::{Pending, ResourceWorkers};
+use crate::labels::namespaced;
+
+/// A texture whose mips become resident from the smallest to the largest.
+pub struct StreamingTexture {
+    texture: Texture,
+    view: TextureView,
+    label: String,
+    /// First resident mip level, all larger ones are missing.
+    resident: u32,
+}
+
+impl StreamingTexture {
+    /// Create a sampled texture with `mip_count` levels and upload its mip tail. `tail`
+    /// holds the tightly packed data of the smallest levels, largest first, ending at
+    /// the last level.
+    ///
+    /// # Panics
+    /// If `tail` is empty or longer than `mip_count`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        mip_count: u32,
+        tail: &[Vec<u8>],
+    ) -> Self {
+        assert!(!tail.is_empty() && tail.len() as u32 <= mip_count, "mip tail must hold 1 to mip_count levels");
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(&namespaced(label)),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let resident = mip_count - tail.len() as u32;
+        for (i, data) in tail.iter().enumerate() {
+            write_mip(queue, &texture, resident + i as u32, data);
+        }
+        let view = resident_view(&texture, label, resident);
+        trace_event!(label, resident, mip_count, "created streaming texture");
+        Self {
+            texture,
+            view,
+            label: label.to_string(),
+            resident,
+        }
+    }
+
+    /// Upload the next larger mip, the level [`next_mip`](Self::next_mip) names, and make
+    /// it visible. Returns the replaced view.
+    ///
+    /// # Panics
+    /// If all mips are already resident.
+    pub fn upload_mip(&mut self, queue: &Queue, data: &[u8]) -> TextureView {
+        let level = self.next_mip().expect("all mips are already resident");
+        write_mip(queue, &self.texture, level, data);
+        self.resident = level;
+        trace_event!(label = %self.label, level, "streamed texture mip");
+        std::mem::replace(&mut self.view, resident_view(&self.texture, &self.label, level))
+    }
+
+    /// View of the resident mips. Changes with every upload, so fetch it every frame.
+    pub fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// First resident mip level, 0 once complete.
+    pub fn resident_mip(&self) -> u32 {
+        self.resident
+    }
+
+    /// The level [`upload_mip`](Self::upload_mip) uploads next, `None` once complete.
+    pub fn next_mip(&self) -> Option<u32> {
+        self.resident.checked_sub(1)
+    }
+
+    pub fn mip_count(&self) -> u32 {
+        self.texture.mip_level_count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.resident == 0
+    }
+}
+
+/// First mip level of at most `tail_size` texels per side, or the last level if none is
+/// that small.
+#[cfg(feature = "native")]
+fn first_tail_mip(width: u32, height: u32, mip_count: u32, tail_size: u32) -> u32 {
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    (0..mip_count)
+        .find(|level| {
+            let mip = size.mip_level_size(*level, TextureDimension::D2);
+            mip.width.max(mip.height) <= tail_size
+        })
+        .unwrap_or(mip_count - 1)
+}
+
+fn resident_view(texture: &Texture, label: &str, base_mip_level: u32) -> TextureView {
+    texture.create_view(&TextureViewDescriptor {
+        label: Some(&namespaced(label)),
+        base_mip_level,
+        ..Default::default()
+    })
+}
+
+/// A texture of a [`TextureStreamer`].
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamId(u64);
+
+#[cfg(feature = "native")]
+struct Stream {
+    texture: StreamingTexture,
+    path: PathBuf,
+    layout: Arc<BakedLayout>,
+    /// Read of the next mip.
+    pending: Option<Pending<Result<Vec<u8>, BakeError>>>,
+    error: Option<BakeError>,
+}
+
+/// Streams `.rmtex` files into [`StreamingTexture`]s.
+#[cfg(feature = "native")]
+pub struct TextureStreamer {
+    device: Device,
+    queue: Queue,
+    workers: ResourceWorkers,
+    streams: HashMap<StreamId, Stream>,
+    next_id: u64,
+    tail_size: u32,
+    frame_budget: u64,
+}
+
+#[cfg(feature = "native")]
+impl TextureStreamer {
+    /// Read mips on `workers`. The mip tail defaults to levels of at most 64 texels per
+    /// side, the upload budget to 4 MiB per frame.
+    pub fn new(device: &Device, queue: &Queue, workers: ResourceWorkers) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            workers,
+            streams: HashMap::new(),
+            next_id: 0,
+            tail_size: 64,
+            frame_budget: 4 << 20,
+        }
+    }
+
+    /// Largest mip, in texels per side, uploaded by [`stream`](Self::stream) right away.
+    pub fn with_tail_size(mut self, texels: u32) -> Self {
+        self.tail_size = texels;
+        self
+    }
+
+    /// Bytes uploaded per [`update`](Self::update). A mip larger than the budget is still
+    /// uploaded when it is the first of its frame.
+    pub fn with_frame_budget(mut self, bytes: u64) -> Self {
+        self.frame_budget = bytes;
+        self
+    }
+
+    /// Read the mip tail of a `.rmtex` file and create its texture, then stream the
+    /// remaining mips in [`update`](Self::update).
+    pub fn stream(&mut self, path: impl AsRef<Path>) -> Result<StreamId, BakeError> {
+        let path = path.as_ref();
+        let layout = BakedLayout::read(path)?;
+        let mip_count = layout.mips.len() as u32;
+        let first_tail = first_tail_mip(layout.width, layout.height, mip_count, self.tail_size);
+        let tail = (first_tail..mip_count)
+            .map(|level| layout.read_mip(path, level))
+            .collect::<Result<Vec<_>, _>>()?;
+        let label = path.display().to_string();
+        let texture = StreamingTexture::new(&self.device, &self.queue, &label, layout.format, layout.width, layout.height, mip_count, &tail);
+        let id = StreamId(self.next_id);
+        self.next_id += 1;
+        self.streams.insert(
+            id,
+            Stream {
+                texture,
+                path: path.to_path_buf(),
+                layout: Arc::new(layout),
+                pending: None,
+                error: None,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Upload the mips read since the last call within the frame budget and start
+    /// reading the next ones. Call once per frame. Returns the number of mips uploaded.
+    pub fn update(&mut self) -> usize {
+        let _span = trace_span!("texture_streaming", streams = self.streams.len());
+        let mut budget = self.frame_budget;
+        let mut uploaded = 0;
+        for stream in self.streams.values_mut() {
+            if let Some(pending) = stream.pending.take_if(|pending| pending.is_done()) {
+                match pending.get() {
+                    // Over budget: keep it for the next frame.
+                    Some(Ok(data)) if uploaded > 0 && data.len() as u64 > budget => stream.pending = Some(pending.clone()),
+                    Some(Ok(data)) => {
+                        stream.texture.upload_mip(&self.queue, data);
+                        budget = budget.saturating_sub(data.len() as u64);
+                        uploaded += 1;
+                    }
+                    Some(Err(e)) => stream.error = Some(e.clone()),
+                    None => {
+                        stream.error = Some(BakeError {
+                            label: stream.path.display().to_string(),
+                            message: "mip reader panicked".to_string(),
+                        })
+                    }
+                }
+            }
+            if stream.pending.is_none()
+                && stream.error.is_none()
+                && let Some(level) = stream.texture.next_mip()
+            {
+                let path = stream.path.clone();
+                let layout = stream.layout.clone();
+                stream.pending = Some(self.workers.spawn(&stream.texture.label, move |_, _| layout.read_mip(&path, level)));
+            }
+        }
+        uploaded
+    }
+
+    pub fn texture(&self, id: StreamId) -> Option<&StreamingTexture> {
+        self.streams.get(&id).map(|stream| &stream.texture)
+    }
+
+    /// Current view of the resident mips, see [`StreamingTexture::view`].
+    pub fn view(&self, id: StreamId) -> Option<&TextureView> {
+        self.texture(id).map(StreamingTexture::view)
+    }
+
+    pub fn is_complete(&self, id: StreamId) -> bool {
+        self.texture(id).is_some_and(StreamingTexture::is_complete)
+    }
+
+    /// Why streaming stopped early, if it did. The texture stays usable at its last
+    /// resident mip.
+    pub fn error(&self, id: StreamId) -> Option<&BakeError> {
+        self.streams.get(&id)?.error.as_ref()
+    }
+
+    /// Stop streaming and take the texture as it is.
+    pub fn remove(&mut self, id: StreamId) -> Option<StreamingTexture> {
+        self.streams.remove(&id).map(|stream| stream.texture)
+    }
+
+    /// Number of textures still streaming.
+    pub fn pending_count(&self) -> usize {
+        self.streams
+            .values()
+            .filter(|stream| !stream.texture.is_complete() && stream.error.is_none())
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+}
+
+#[cfg(all(test, any(feature = "native", feature = "testing")))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn mip_tail_starts_at_the_tail_size() {
+        // 1024 x 512 has 11 levels, level 4 is 64 x 32.
+        assert_eq!(first_tail_mip(1024, 512, 11, 64), 4);
+        assert_eq!(first_tail_mip(1024, 512, 11, 63), 5);
+        assert_eq!(first_tail_mip(48, 48, 6, 64), 0);
+        // Nothing is small enough: only the last level.
+        assert_eq!(first_tail_mip(1024, 512, 11, 0), 10);
+        assert_eq!(first_tail_mip(1024, 512, 3, 64), 2);
+    }
+
+    #[cfg(feature = "testing")]
+    fn streaming_texture(tail: &[Vec<u8>]) -> (Queue, StreamingTexture) {
+        let (device, queue) = crate::testing::noop_device();
+        let texture = StreamingTexture::new(&device, &queue, "streaming test", TextureFormat::Rgba8Unorm, 4, 4, 3, tail);
+        (queue, texture)
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn mips_become_resident_largest_last() {
+        let (queue, mut texture) = streaming_texture(&[vec![0; 4]]);
+        assert_eq!((texture.mip_count(), texture.resident_mip(), texture.next_mip()), (3, 2, Some(1)));
+        assert_eq!(texture.view().texture().mip_level_count(), 3);
+
+        let first = texture.view().clone();
+        let replaced = texture.upload_mip(&queue, &[0; 16]);
+        assert_eq!(replaced, first);
+        assert_ne!(*texture.view(), first);
+        assert_eq!((texture.resident_mip(), texture.next_mip()), (1, Some(0)));
+        assert!(!texture.is_complete());
+
+        texture.upload_mip(&queue, &[0; 64]);
+        assert_eq!((texture.resident_mip(), texture.next_mip()), (0, None));
+        assert!(texture.is_complete());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn a_longer_tail_starts_further_up() {
+        let (_queue, texture) = streaming_texture(&[vec![0; 16], vec![0; 4]]);
+        assert_eq!((texture.resident_mip(), texture.next_mip()), (1, Some(0)));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    #[should_panic(expected = "all mips are already resident")]
+    fn uploading_past_the_largest_mip_panics() {
+        let (queue, mut texture) = streaming_texture(&[vec![0; 64], vec![0; 16], vec![0; 4]]);
+        assert!(texture.is_complete());
+        texture.upload_mip(&queue, &[0; 64]);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    #[should_panic(expected = "mip tail must hold 1 to mip_count levels")]
+    fn empty_tail_panics() {
+        streaming_texture(&[]);
+    }
+}
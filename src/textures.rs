@@ -0,0 +1,114 @@
+//! Plain texture creation shared by the loaders.
+use wgpu::*;
+use crate::error::CrmError;
+use crate::labels::namespaced;
+
+/// A texture to create, and optionally fill with data.
+#[derive(Debug, Clone)]
+pub struct TextureRequest {
+    pub label: String,
+    pub size: Extent3d,
+    pub format: TextureFormat,
+    pub usage: TextureUsages,
+    /// Tightly packed texel data of mip 0, uploaded with `Queue::write_texture`.
+    pub data: Option<Vec<u8>>,
+}
+
+impl TextureRequest {
+    /// Sampled 2D `Rgba8UnormSrgb` texture filled with `data`.
+    pub fn rgba8(label: impl Into<String>, width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            label: label.into(),
+            size: Extent3d { width, height, depth_or_array_layers: 1 },
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            data: Some(data),
+        }
+    }
+}
+
+/// A loaded texture together with its default view.
+#[derive(Debug, Clone)]
+pub struct LoadedTexture {
+    pub texture: Texture,
+    pub view: TextureView,
+}
+
+/// Create the texture described by `request` and upload its data, if any.
+///
+/// # Panics
+/// If the device cannot create the texture, see [`try_create_texture`].
+pub fn create_texture(device: &Device, queue: &Queue, request: &TextureRequest) -> LoadedTexture {
+    try_create_texture(device, queue, request).unwrap_or_else(|e| panic!("{}: {}", request.label, e))
+}
+
+/// [`create_texture`], checking the request against the device first. Fails with
+/// [`CrmError::MissingFeature`] for formats the device lacks features for (e.g. BCn on
+/// most mobile GPUs), [`CrmError::UnsupportedFormat`] for usages the format does not
+/// guarantee and [`CrmError::LimitExceeded`] for sizes above the device limits.
+pub fn try_create_texture(device: &Device, queue: &Queue, request: &TextureRequest) -> Result<LoadedTexture, CrmError> {
+    check_texture(device, request.size, request.format, request.usage)?;
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced(&request.label)),
+        size: request.size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: request.format,
+        usage: request.usage,
+        view_formats: &[],
+    });
+    if let Some(data) = &request.data {
+        let (block_w, block_h) = request.format.block_dimensions();
+        let block_size = request.format.block_copy_size(None).unwrap_or(4);
+        queue.write_texture(
+            texture.as_image_copy(),
+            data,
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(request.size.width.div_ceil(block_w) * block_size),
+                rows_per_image: Some(request.size.height.div_ceil(block_h)),
+            },
+            request.size,
+        );
+    }
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    Ok(LoadedTexture { texture, view })
+}
+
+/// Check that `device` can create a 2D texture of `size`, `format` and `usage`.
+pub(crate) fn check_texture(device: &Device, size: Extent3d, format: TextureFormat, usage: TextureUsages) -> Result<(), CrmError> {
+    let features = device.features();
+    let required = format.required_features();
+    if !features.contains(required) {
+        return Err(CrmError::MissingFeature {
+            features: required - features,
+            needed_for: format!("texture format {:?}", format),
+        });
+    }
+    // Adapter specific formats may allow more, which only the adapter can tell.
+    let allowed = format.guaranteed_format_features(features).allowed_usages;
+    if !allowed.contains(usage) && !features.contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+        return Err(CrmError::UnsupportedFormat {
+            format,
+            usage: format!("{:?}", usage - allowed),
+        });
+    }
+    let limits = device.limits();
+    let side = size.width.max(size.height);
+    if side > limits.max_texture_dimension_2d {
+        return Err(CrmError::LimitExceeded {
+            limit: "max_texture_dimension_2d",
+            requested: side as u64,
+            max: limits.max_texture_dimension_2d as u64,
+        });
+    }
+    if size.depth_or_array_layers > limits.max_texture_array_layers {
+        return Err(CrmError::LimitExceeded {
+            limit: "max_texture_array_layers",
+            requested: size.depth_or_array_layers as u64,
+            max: limits.max_texture_array_layers as u64,
+        });
+    }
+    Ok(())
+}
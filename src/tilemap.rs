@@ -0,0 +1,493 @@
+//! Chunked 2D tilemaps.
+//!
+//! A [`Tilemap`] splits an unbounded grid of tiles into square chunks. Every chunk keeps
+//! its tiles in a storage buffer, one `u32` per tile, and is drawn with one instanced
+//! draw per atlas page it uses: every instance is one tile slot, expanded into a quad
+//! in the vertex shader, and slots that are empty or on another page collapse to
+//! nothing. Only chunks overlapping the view are drawn, and only changed chunks are
+//! uploaded again.
+//!
+//! Atlas pages are textures laid out as a grid of equally sized tiles, numbered row
+//! by row; they are bound through the [`RenderManager`] material bind group cache.
+//! ```ignore
+//! let mut map = Tilemap::new(&device, &queue, surface.scene_format(), 32, [16.0, 16.0]);
+//! let terrain = map.add_page(&terrain_atlas, 16, 16);
+//! map.set_tile(10, 4, Some(Tile::new(terrain, 37)));
+//! // Every frame
+//! map.set_view(camera_offset, 2.0, [width, height]);
+//! map.upload(&mut render_manager);
+//! // Inside a render pass writing the target
+//! map.render(&mut pass);
+//! ```
+//! Tile `(x, y)` covers `x * tile_size..(x + 1) * tile_size` in world pixels, y down.
+use std::collections::HashMap;
+use wgpu::*;
+use crate::gpu_util;
+use crate::renderer::RenderManager;
+use crate::labels::namespaced;
+
+const TILEMAP_SHADER: &str = r#"
+struct MapView {
+    /// World position of the top left corner of the target, in pixels.
+    offset: vec2<f32>,
+    /// Target size in pixels.
+    screen_size: vec2<f32>,
+    tile_size: vec2<f32>,
+    zoom: f32,
+    _pad: f32,
+};
+
+struct Chunk {
+    /// Tile coordinates of the chunk's first tile.
+    origin: vec2<i32>,
+    size: u32,
+    _pad: u32,
+    /// Grid of every atlas page, packed as columns | rows << 16.
+    pages: array<vec4<u32>, 64>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) @interpolate(flat) cell: vec4<f32>,
+};
+
+@group(0) @binding(0) var s_tiles: sampler;
+@group(0) @binding(1) var t_tiles: texture_2d<f32>;
+@group(1) @binding(0) var<uniform> view: MapView;
+@group(2) @binding(0) var<uniform> chunk: Chunk;
+@group(2) @binding(1) var<storage, read> tiles: array<u32>;
+
+const EMPTY: u32 = 0xffffffffu;
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32, @builtin(instance_index) instance: u32) -> VertexOutput {
+    var out: VertexOutput;
+    // Instances of page p start at p * size * size.
+    let slots = chunk.size * chunk.size;
+    let page = instance / slots;
+    let slot = instance % slots;
+    let tile = tiles[slot];
+    if tile == EMPTY || (tile >> 24u) != page {
+        out.position = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        return out;
+    }
+    let index = tile & 0xffffffu;
+    let packed = chunk.pages[page / 4u][page % 4u];
+    let grid = vec2<u32>(packed & 0xffffu, packed >> 16u);
+    let corner = vec2<f32>(f32(idx & 1u), f32(idx >> 1u));
+    let coords = vec2<f32>(chunk.origin + vec2<i32>(vec2<u32>(slot % chunk.size, slot / chunk.size)));
+    let pixel = ((coords + corner) * view.tile_size - view.offset) * view.zoom;
+    out.position = vec4<f32>(pixel / view.screen_size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    let cell_min = vec2<f32>(f32(index % grid.x), f32(index / grid.x)) / vec2<f32>(grid);
+    let cell_max = cell_min + 1.0 / vec2<f32>(grid);
+    out.uv = mix(cell_min, cell_max, corner);
+    out.cell = vec4<f32>(cell_min, cell_max);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Stay half a texel inside the tile, so filtering never picks up its neighbours.
+    let half_texel = 0.5 / vec2<f32>(textureDimensions(t_tiles));
+    let uv = clamp(in.uv, in.cell.xy + half_texel, in.cell.zw - half_texel);
+    return textureSample(t_tiles, s_tiles, uv);
+}
+"#;
+
+/// Atlas pages a tilemap can use, the most the tile encoding and chunk uniform hold.
+pub const MAX_TILE_PAGES: usize = 256;
+/// Largest tile index within a page.
+pub const MAX_TILE_INDEX: u32 = 0xff_fffe;
+
+const EMPTY_TILE: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct MapViewUniform {
+    offset: [f32; 2],
+    screen_size: [f32; 2],
+    tile_size: [f32; 2],
+    zoom: f32,
+    _pad: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkUniform {
+    origin: [i32; 2],
+    size: u32,
+    _pad: u32,
+    /// Grid of every page, packed as columns | rows << 16.
+    pages: [u32; MAX_TILE_PAGES],
+}
+
+/// Handle to an atlas page added with [`Tilemap::add_page`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TilePage(u8);
+
+/// One tile: an atlas page and the index of the tile on it, row by row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tile {
+    pub page: TilePage,
+    pub index: u32,
+}
+
+impl Tile {
+    pub fn new(page: TilePage, index: u32) -> Self {
+        Self { page, index }
+    }
+
+    fn encode(tile: Option<Tile>) -> u32 {
+        match tile {
+            Some(tile) => {
+                assert!(tile.index <= MAX_TILE_INDEX, "tile index {} exceeds MAX_TILE_INDEX", tile.index);
+                ((tile.page.0 as u32) << 24) | tile.index
+            }
+            None => EMPTY_TILE,
+        }
+    }
+
+    fn decode(value: u32) -> Option<Tile> {
+        (value != EMPTY_TILE).then(|| Tile {
+            page: TilePage((value >> 24) as u8),
+            index: value & 0xff_ffff,
+        })
+    }
+}
+
+struct AtlasPage {
+    view: TextureView,
+    columns: u32,
+    rows: u32,
+}
+
+struct TileChunk {
+    tiles: Vec<u32>,
+    /// Number of tiles per page, to skip draws of unused pages.
+    page_counts: Vec<u32>,
+    uniform: Buffer,
+    storage: Buffer,
+    bind_group: BindGroup,
+    dirty: bool,
+}
+
+/// Chunked tile grid drawn with one instanced draw per chunk and atlas page.
+pub struct Tilemap {
+    device: Device,
+    queue: Queue,
+    format: TextureFormat,
+    chunk_size: u32,
+    tile_size: [f32; 2],
+    pipeline: Option<RenderPipeline>,
+    view_buffer: Buffer,
+    view_bind_group: BindGroup,
+    chunk_layout: BindGroupLayout,
+    view: MapViewUniform,
+    pages: Vec<AtlasPage>,
+    /// Bind group of every page, resolved in [`upload`](Self::upload).
+    page_bind_groups: Vec<BindGroup>,
+    chunks: HashMap<[i32; 2], TileChunk>,
+}
+
+impl Tilemap {
+    /// Create a map of `chunk_size` x `chunk_size` tile chunks with tiles of `tile_size`
+    /// world pixels, drawn into targets of `target_format`.
+    pub fn new(device: &Device, queue: &Queue, target_format: TextureFormat, chunk_size: u32, tile_size: [f32; 2]) -> Self {
+        let view_buffer = gpu_util::buffer(device, "tilemap view", size_of::<MapViewUniform>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+        let view_bind_group = gpu_util::bind_group(device, "tilemap view", &view_layout(device), &[view_buffer.as_entire_binding()]);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            format: target_format,
+            chunk_size: chunk_size.max(1),
+            tile_size,
+            pipeline: None,
+            view_buffer,
+            view_bind_group,
+            chunk_layout: chunk_layout(device),
+            view: MapViewUniform {
+                offset: [0.0; 2],
+                screen_size: [1.0; 2],
+                tile_size,
+                zoom: 1.0,
+                _pad: 0.0,
+            },
+            pages: Vec::new(),
+            page_bind_groups: Vec::new(),
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Add an atlas page of `columns` x `rows` tiles; `texture` must be a filterable 2D
+    /// float texture.
+    ///
+    /// ## Panics
+    /// Panics if the map already has [`MAX_TILE_PAGES`] pages.
+    pub fn add_page(&mut self, texture: &TextureView, columns: u32, rows: u32) -> TilePage {
+        assert!(self.pages.len() < MAX_TILE_PAGES, "a tilemap holds at most {MAX_TILE_PAGES} atlas pages");
+        self.pages.push(AtlasPage {
+            view: texture.clone(),
+            columns: columns.clamp(1, 0xffff),
+            rows: rows.clamp(1, 0xffff),
+        });
+        for chunk in self.chunks.values_mut() {
+            chunk.page_counts.push(0);
+            chunk.dirty = true;
+        }
+        TilePage((self.pages.len() - 1) as u8)
+    }
+
+    /// Set or clear the tile at tile coordinates `(x, y)`.
+    pub fn set_tile(&mut self, x: i32, y: i32, tile: Option<Tile>) {
+        let (key, slot) = self.locate(x, y);
+        if tile.is_none() && !self.chunks.contains_key(&key) {
+            return;
+        }
+        let value = Tile::encode(tile);
+        let chunk = self.chunk_mut(key);
+        let previous = std::mem::replace(&mut chunk.tiles[slot], value);
+        if previous == value {
+            return;
+        }
+        if let Some(previous) = Tile::decode(previous) {
+            chunk.page_counts[previous.page.0 as usize] -= 1;
+        }
+        if let Some(tile) = tile {
+            chunk.page_counts[tile.page.0 as usize] += 1;
+        }
+        chunk.dirty = true;
+    }
+
+    pub fn tile(&self, x: i32, y: i32) -> Option<Tile> {
+        let (key, slot) = self.locate(x, y);
+        self.chunks.get(&key).and_then(|chunk| Tile::decode(chunk.tiles[slot]))
+    }
+
+    /// Drop the chunk containing tile `(x, y)` with all its tiles, e.g. when streaming.
+    pub fn remove_chunk_at(&mut self, x: i32, y: i32) {
+        let (key, _) = self.locate(x, y);
+        self.chunks.remove(&key);
+    }
+
+    /// Remove all tiles and chunks, keeping the atlas pages.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Show the map from world pixel `offset` (the top left corner of the target)
+    /// magnified by `zoom` on a target of `screen_size` pixels.
+    pub fn set_view(&mut self, offset: [f32; 2], zoom: f32, screen_size: [u32; 2]) {
+        self.view.offset = offset;
+        self.view.zoom = zoom.max(f32::EPSILON);
+        self.view.screen_size = screen_size.map(|s| s.max(1) as f32);
+    }
+
+    /// Upload changed chunks and the view, and resolve the page bind groups.
+    ///
+    /// Written with `Queue::write_buffer`, so upload once per submission.
+    pub fn upload(&mut self, render_manager: &mut RenderManager) {
+        let _span = trace_span!("tilemap_upload", chunks = self.chunks.len());
+        self.queue.write_buffer(&self.view_buffer, 0, bytemuck::bytes_of(&self.view));
+        if self.pipeline.is_none() && let Some(page) = self.pages.first() {
+            let material_layout = render_manager.material_layout(&[&page.view], &[]);
+            self.pipeline = Some(create_pipeline(&self.device, &[&material_layout, &view_layout(&self.device), &self.chunk_layout], self.format));
+        }
+        self.page_bind_groups = self
+            .pages
+            .iter()
+            .map(|page| render_manager.material_bind_group(&[&page.view], &[]))
+            .collect();
+
+        let mut pages = [0; MAX_TILE_PAGES];
+        for (packed, page) in pages.iter_mut().zip(&self.pages) {
+            *packed = page.columns | (page.rows << 16);
+        }
+        for (&[cx, cy], chunk) in self.chunks.iter_mut().filter(|(_, chunk)| chunk.dirty) {
+            let uniform = ChunkUniform {
+                origin: [cx * self.chunk_size as i32, cy * self.chunk_size as i32],
+                size: self.chunk_size,
+                _pad: 0,
+                pages,
+            };
+            self.queue.write_buffer(&chunk.uniform, 0, bytemuck::bytes_of(&uniform));
+            self.queue.write_buffer(&chunk.storage, 0, bytemuck::cast_slice(&chunk.tiles));
+            chunk.dirty = false;
+        }
+    }
+
+    /// Draw the chunks overlapping the view of the last [`upload`](Self::upload).
+    pub fn render(&self, pass: &mut RenderPass) {
+        let Some(pipeline) = &self.pipeline else {
+            return;
+        };
+        let chunk_extent = self.tile_size.map(|size| size * self.chunk_size as f32);
+        let min = [0, 1].map(|i| (self.view.offset[i] / chunk_extent[i]).floor() as i32);
+        let max = [0, 1].map(|i| ((self.view.offset[i] + self.view.screen_size[i] / self.view.zoom) / chunk_extent[i]).floor() as i32);
+        let slots = self.chunk_size * self.chunk_size;
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(1, &self.view_bind_group, &[]);
+        let mut bound_page = None;
+        for (&[cx, cy], chunk) in &self.chunks {
+            if cx < min[0] || cx > max[0] || cy < min[1] || cy > max[1] {
+                continue;
+            }
+            pass.set_bind_group(2, &chunk.bind_group, &[]);
+            for (page, _) in chunk.page_counts.iter().enumerate().filter(|&(_, &count)| count > 0) {
+                if bound_page != Some(page) {
+                    pass.set_bind_group(0, &self.page_bind_groups[page], &[]);
+                    bound_page = Some(page);
+                }
+                let first = page as u32 * slots;
+                pass.draw(0..4, first..first + slots);
+            }
+        }
+    }
+
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    pub fn tile_size(&self) -> [f32; 2] {
+        self.tile_size
+    }
+
+    /// Number of chunks holding tiles.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Chunk key and slot within the chunk of tile `(x, y)`.
+    fn locate(&self, x: i32, y: i32) -> ([i32; 2], usize) {
+        let size = self.chunk_size as i32;
+        let key = [x.div_euclid(size), y.div_euclid(size)];
+        let slot = (y.rem_euclid(size) * size + x.rem_euclid(size)) as usize;
+        (key, slot)
+    }
+
+    fn chunk_mut(&mut self, key: [i32; 2]) -> &mut TileChunk {
+        let (device, layout, size, page_count) = (&self.device, &self.chunk_layout, self.chunk_size, self.pages.len());
+        self.chunks.entry(key).or_insert_with(|| {
+            trace_event!(x = key[0], y = key[1], "created tilemap chunk");
+            let slots = (size * size) as usize;
+            let uniform = gpu_util::buffer(device, "tilemap chunk", size_of::<ChunkUniform>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+            let storage = gpu_util::buffer(device, "tilemap chunk tiles", slots as u64 * 4, BufferUsages::STORAGE | BufferUsages::COPY_DST);
+            let bind_group = gpu_util::bind_group(device, "tilemap chunk", layout, &[uniform.as_entire_binding(), storage.as_entire_binding()]);
+            TileChunk {
+                tiles: vec![EMPTY_TILE; slots],
+                page_counts: vec![0; page_count],
+                uniform,
+                storage,
+                bind_group,
+                dirty: true,
+            }
+        })
+    }
+}
+
+fn view_layout(device: &Device) -> BindGroupLayout {
+    gpu_util::bind_group_layout(device, "tilemap view layout", &[gpu_util::uniform_entry(0, ShaderStages::VERTEX)])
+}
+
+fn chunk_layout(device: &Device) -> BindGroupLayout {
+    gpu_util::bind_group_layout(device, "tilemap chunk layout", &[
+        gpu_util::uniform_entry(0, ShaderStages::VERTEX),
+        gpu_util::storage_entry(1, ShaderStages::VERTEX, true),
+    ])
+}
+
+fn create_pipeline(device: &Device, layouts: &[&BindGroupLayout], format: TextureFormat) -> RenderPipeline {
+    let module = gpu_util::shader(device, "tilemap shader", TILEMAP_SHADER);
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(&namespaced("tilemap pipeline layout")),
+        bind_group_layouts: layouts,
+        immediate_size: 0,
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(&namespaced("tilemap pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_round_trip_through_the_encoding() {
+        for tile in [Tile::new(TilePage(0), 0), Tile::new(TilePage(3), 17), Tile::new(TilePage(255), MAX_TILE_INDEX)] {
+            let value = Tile::encode(Some(tile));
+            assert_ne!(value, EMPTY_TILE);
+            assert_eq!(Tile::decode(value), Some(tile));
+        }
+        assert_eq!(Tile::encode(None), EMPTY_TILE);
+        assert_eq!(Tile::decode(EMPTY_TILE), None);
+    }
+
+    #[test]
+    fn page_and_index_use_separate_bits() {
+        assert_eq!(Tile::encode(Some(Tile::new(TilePage(2), 5))), 0x0200_0005);
+        assert_eq!(Tile::encode(Some(Tile::new(TilePage(1), MAX_TILE_INDEX))), 0x01ff_fffe);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds MAX_TILE_INDEX")]
+    fn oversized_tile_index_panics() {
+        Tile::encode(Some(Tile::new(TilePage(0), MAX_TILE_INDEX + 1)));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn tiles_land_in_euclidean_chunks() {
+        let (device, queue) = crate::testing::noop_device();
+        let atlas = crate::testing::test_texture(&device, 16, 16, TextureFormat::Rgba8UnormSrgb);
+        let mut map = Tilemap::new(&device, &queue, TextureFormat::Rgba8UnormSrgb, 8, [16.0, 16.0]);
+        let (grass, water) = (map.add_page(&atlas, 4, 4), map.add_page(&atlas, 4, 4));
+
+        map.set_tile(-1, -1, Some(Tile::new(grass, 3)));
+        map.set_tile(7, 7, Some(Tile::new(water, 1)));
+        map.set_tile(8, 0, Some(Tile::new(grass, 2)));
+        assert_eq!(map.chunk_count(), 3);
+        assert_eq!(map.locate(-1, -1), ([-1, -1], 63));
+        assert_eq!(map.tile(-1, -1), Some(Tile::new(grass, 3)));
+        assert_eq!(map.tile(-9, -1), None);
+
+        // Page counts follow replacements and clears.
+        map.set_tile(7, 7, Some(Tile::new(grass, 0)));
+        assert_eq!(map.chunks[&[0, 0]].page_counts, [1, 0]);
+        map.set_tile(7, 7, None);
+        assert_eq!(map.chunks[&[0, 0]].page_counts, [0, 0]);
+
+        // Clearing a tile never creates a chunk.
+        map.set_tile(100, 100, None);
+        assert_eq!(map.chunk_count(), 3);
+        map.remove_chunk_at(-5, -3);
+        assert_eq!(map.tile(-1, -1), None);
+        assert_eq!(map.chunk_count(), 2);
+    }
+}
@@ -0,0 +1,53 @@
+// trace.rs
+//! Crate-internal instrumentation macros.
+//!
+//! With the `tracing` feature enabled these forward to the `tracing` crate,
+//! otherwise they compile to nothing, so call sites never need their own `#[cfg]`.
+//!
+//! Every cache miss, layout/bind group creation, texture upload and eviction
+//! goes through here, which makes frame hitches attributable to the exact
+//! resource that was created in that frame.
+
+/// Emits a `DEBUG` level event, e.g. for a bind group or layout being created.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "wgpu_render_manager", $($arg)*);
+    };
+}
+
+/// Emits a `WARN` level event, for failures that are recovered from but worth knowing
+/// about, e.g. a disk cache entry that could not be written.
+macro_rules! trace_warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(target: "wgpu_render_manager", $($arg)*);
+    };
+}
+
+/// Emits a `DEBUG` level event for resources leaving a cache.
+macro_rules! trace_evict {
+    ($cache:expr, $count:expr) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "wgpu_render_manager", cache = $cache, evicted = $count, "cache eviction");
+        #[cfg(not(feature = "tracing"))]
+        let _ = ($cache, $count);
+    };
+}
+
+/// Enters a `DEBUG` level span that lasts until the returned guard is dropped.
+///
+/// Used around expensive work like pipeline compilation or texture generation.
+macro_rules! trace_span {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let guard = tracing::debug_span!(target: "wgpu_render_manager", $($arg)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        let guard = $crate::trace::NoSpan;
+        guard
+    }};
+}
+
+/// Stand-in guard returned by [`trace_span!`] when the `tracing` feature is off.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
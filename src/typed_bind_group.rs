@@ -0,0 +1,105 @@
+//! Bind groups declared as Rust structs.
+//!
+//! A [`TypedBindGroup`] knows its layout, its resources and the WGSL declarations of
+//! its bindings, so the layout cache and the shader are generated from one place.
+//! With the `derive` feature, `#[derive(BindGroupLayout)]` implements it from field
+//! attributes, numbering bindings in field order:
+//! ```ignore
+//! #[derive(BindGroupLayout)]
+//! struct WaterBindings<'a> {
+//!     #[sampler]
+//!     linear_sampler: &'a Sampler,
+//!     #[texture]
+//!     normal_map: &'a TextureView,
+//!     #[texture(sample = "depth")]
+//!     scene_depth: &'a TextureView,
+//!     #[uniform(ty = "WaterParams", visibility = "vertex | fragment")]
+//!     params: &'a Buffer,
+//! }
+//!
+//! // prepended to the shader source, or checked against it
+//! let declarations = WaterBindings::wgsl(0);
+//! // @group(0) @binding(0) var linear_sampler: sampler;
+//! // @group(0) @binding(1) var normal_map: texture_2d<f32>;
+//! // ...
+//!
+//! let water = render_manager.typed_bind_group(&WaterBindings { linear_sampler: &sampler, normal_map: &normal, scene_depth: &depth, params: &params });
+//! render_manager.render_with_layouts(shader, &[&water.layout], &[&water.bind_group], &options, &mut pass);
+//! ```
+//! `#[uniform(ty = "WaterParams", min_size = 48)]` sets the `min_binding_size` of the
+//! binding, [`RenderManager::try_typed_bind_group`](crate::renderer::RenderManager::try_typed_bind_group)
+//! rejects smaller buffers with [`CrmError::BufferTooSmall`](crate::error::CrmError::BufferTooSmall).
+//! `#[uniform(ty = "DrawParams", dynamic, size = 64)]` binds `size` bytes of the buffer
+//! at the offset passed to `set_bind_group`, for per-draw data in one buffer.
+//!
+//! Layouts and bind groups share the cache of the
+//! [`BindGroupBuilder`](crate::bind_group_builder::BindGroupBuilder).
+use std::sync::Arc;
+use wgpu::{BindGroupLayoutEntry, Buffer, Sampler, TextureView};
+#[cfg(feature = "derive")]
+pub use wgpu_render_manager_derive::BindGroupLayout;
+use crate::compute_bind_groups::ComputeBinding;
+
+/// The `wgpu` used by derived implementations.
+#[doc(hidden)]
+pub use wgpu;
+
+/// A resource bound by a [`TypedBindGroup`].
+#[derive(Debug, Clone, Copy)]
+pub enum BindingRef<'a> {
+    Sampler(&'a Sampler),
+    View(&'a TextureView),
+    Buffer(&'a Buffer),
+    /// A buffer bound `size` bytes at a time, at a dynamic offset.
+    DynamicBuffer(&'a Buffer, u64),
+}
+
+impl<'a> From<BindingRef<'a>> for ComputeBinding<'a> {
+    fn from(binding: BindingRef<'a>) -> Self {
+        match binding {
+            BindingRef::Sampler(sampler) => ComputeBinding::Sampler(sampler),
+            BindingRef::View(view) => ComputeBinding::View(view),
+            BindingRef::Buffer(buffer) => ComputeBinding::Buffer(buffer),
+            BindingRef::DynamicBuffer(buffer, size) => ComputeBinding::DynamicBuffer(buffer, size),
+        }
+    }
+}
+
+/// A struct of resources forming one bind group.
+pub trait TypedBindGroup {
+    /// Label of the layout and bind groups.
+    const LABEL: &'static str;
+
+    /// Layout entries, numbered from 0.
+    fn layout_entries() -> Vec<BindGroupLayoutEntry>;
+
+    /// WGSL declarations of the bindings in `@group(group)`, one per line.
+    fn wgsl(group: u32) -> String;
+
+    /// The bound resources, in binding order.
+    fn resources(&self) -> Vec<BindingRef<'_>>;
+}
+
+/// A field that holds a `T`, by value, by reference or shared. Lets derived
+/// implementations accept all three and reject fields of the wrong resource type.
+pub trait Resource<T> {
+    fn resource(&self) -> &T;
+}
+
+impl<T> Resource<T> for T {
+    fn resource(&self) -> &T {
+        self
+    }
+}
+
+impl<T> Resource<T> for &T {
+    fn resource(&self) -> &T {
+        self
+    }
+}
+
+impl<T> Resource<T> for Arc<T> {
+    fn resource(&self) -> &T {
+        self
+    }
+}
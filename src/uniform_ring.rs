@@ -0,0 +1,253 @@
+//! Per-draw uniforms in one buffer, bound with dynamic offsets.
+//!
+//! A [`UniformRing`] collects many small uniform blocks of the same size during a
+//! frame, uploads them with a single `write_buffer` and binds them through one bind
+//! group whose offset changes per draw. This replaces one buffer and bind group per
+//! object:
+//! ```ignore
+//! ring.reset();
+//! let offsets: Vec<u32> = objects.iter().map(|o| ring.push(&o.uniform)).collect();
+//! ring.flush();
+//! // Inside a render pass, with the ring layout at group 2 of the pipeline
+//! for (object, offset) in objects.iter().zip(offsets) {
+//!     pass.set_bind_group(2, ring.bind_group(), &[offset]);
+//!     meshes.draw(&mut pass, object.mesh, &options, 0..1);
+//! }
+//! ```
+//! The ring can also be one binding of a larger bind group, next to textures, with
+//! [`BindGroupBuilder::uniform_ring`](crate::bind_group_builder::BindGroupBuilder::uniform_ring).
+//! Buffers managed by hand use [`DynamicOffsets`] for the aligned offsets of their blocks.
+use wgpu::*;
+use crate::error::CrmError;
+use crate::gpu_util;
+
+/// Offsets of equally sized blocks in one buffer, aligned for binding them with dynamic offsets.
+///
+/// ```ignore
+/// let offsets = DynamicOffsets::new(&device, BufferBindingType::Uniform, size_of::<DrawParams>() as u64);
+/// let buffer = gpu_util::buffer(&device, "draw params", offsets.buffer_size(draws.len() as u64), BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+/// for (i, draw) in draws.iter().enumerate() {
+///     queue.write_buffer(&buffer, offsets.offset(i as u32) as u64, bytemuck::bytes_of(&draw.params));
+/// }
+/// let params = render_manager.bind_group().dynamic_uniform(&buffer, offsets.binding_size()).build();
+/// for (i, draw) in draws.iter().enumerate() {
+///     pass.set_bind_group(2, &params.bind_group, &[offsets.offset(i as u32)]);
+///     // draw
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DynamicOffsets {
+    binding_size: u64,
+    stride: u64,
+}
+
+impl DynamicOffsets {
+    /// Blocks of `binding_size` bytes bound as `ty`, spaced at the device's
+    /// `min_uniform_buffer_offset_alignment` or `min_storage_buffer_offset_alignment`.
+    pub fn new(device: &Device, ty: BufferBindingType, binding_size: u64) -> Self {
+        let limits = device.limits();
+        let alignment = match ty {
+            BufferBindingType::Uniform => limits.min_uniform_buffer_offset_alignment,
+            BufferBindingType::Storage { .. } => limits.min_storage_buffer_offset_alignment,
+        } as u64;
+        Self {
+            binding_size,
+            stride: binding_size.div_ceil(alignment) * alignment,
+        }
+    }
+
+    /// Size of one block as seen by the shader.
+    pub fn binding_size(&self) -> u64 {
+        self.binding_size
+    }
+
+    /// Distance between consecutive blocks, `binding_size` rounded up to the alignment.
+    pub fn stride(&self) -> u64 {
+        self.stride
+    }
+
+    /// Dynamic offset of block `index`.
+    ///
+    /// ## Panics
+    /// Panics if the offset does not fit in a `u32`.
+    pub fn offset(&self, index: u32) -> u32 {
+        u32::try_from(index as u64 * self.stride).expect("dynamic offset exceeds u32")
+    }
+
+    /// Buffer size that holds `count` blocks.
+    pub fn buffer_size(&self, count: u64) -> u64 {
+        count * self.stride
+    }
+}
+
+/// Uniform blocks of one size, packed at the device's dynamic offset alignment.
+pub struct UniformRing {
+    device: Device,
+    queue: Queue,
+    label: String,
+    /// Size of one block as seen by the shader.
+    binding_size: u64,
+    /// Distance between blocks, `binding_size` rounded up to the offset alignment.
+    stride: u64,
+    staging: Vec<u8>,
+    buffer: Buffer,
+    capacity: u64,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+}
+
+impl UniformRing {
+    /// Create a ring for blocks of `binding_size` bytes with room for `capacity` blocks.
+    /// It grows when more blocks are pushed.
+    pub fn new(device: &Device, queue: &Queue, label: &str, binding_size: u64, capacity: u64) -> Self {
+        let stride = DynamicOffsets::new(device, BufferBindingType::Uniform, binding_size).stride();
+        let capacity = capacity.max(1);
+        let layout = gpu_util::bind_group_layout(device, label, &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: BufferSize::new(binding_size),
+            },
+            count: None,
+        }]);
+        let buffer = ring_buffer(device, label, stride * capacity);
+        let bind_group = ring_bind_group(device, label, &layout, &buffer, binding_size);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            label: label.to_string(),
+            binding_size,
+            stride,
+            staging: Vec::new(),
+            buffer,
+            capacity,
+            layout,
+            bind_group,
+        }
+    }
+
+    /// Append a block and return its dynamic offset.
+    ///
+    /// ## Panics
+    /// Panics if `value` is larger than the binding size, or if the offset does not fit
+    /// in a `u32`, see [`try_push`](Self::try_push).
+    pub fn push<T: bytemuck::Pod>(&mut self, value: &T) -> u32 {
+        self.push_bytes(bytemuck::bytes_of(value))
+    }
+
+    /// [`push`](Self::push) for raw bytes; shorter blocks are zero-padded.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> u32 {
+        self.try_push_bytes(bytes).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// [`push`](Self::push), failing with [`CrmError::LimitExceeded`] once the ring holds
+    /// more blocks than `u32` dynamic offsets can address.
+    ///
+    /// ## Panics
+    /// Panics if `value` is larger than the binding size.
+    pub fn try_push<T: bytemuck::Pod>(&mut self, value: &T) -> Result<u32, CrmError> {
+        self.try_push_bytes(bytemuck::bytes_of(value))
+    }
+
+    /// [`try_push`](Self::try_push) for raw bytes; shorter blocks are zero-padded.
+    pub fn try_push_bytes(&mut self, bytes: &[u8]) -> Result<u32, CrmError> {
+        assert!(
+            bytes.len() as u64 <= self.binding_size,
+            "Uniform block of {} bytes pushed to ring '{}' of {}-byte blocks",
+            bytes.len(),
+            self.label,
+            self.binding_size
+        );
+        let offset = self.staging.len() as u64;
+        let dynamic_offset = u32::try_from(offset).map_err(|_| CrmError::LimitExceeded {
+            limit: "dynamic offset",
+            requested: offset,
+            max: u32::MAX as u64,
+        })?;
+        self.staging.extend_from_slice(bytes);
+        self.staging.resize((offset + self.stride) as usize, 0);
+        Ok(dynamic_offset)
+    }
+
+    /// Upload all pushed blocks, growing the buffer if needed.
+    ///
+    /// Blocks are written with `Queue::write_buffer`, so flush once per submission.
+    pub fn flush(&mut self) {
+        let count = self.len() as u64;
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            trace_event!(capacity = self.capacity, "grew uniform ring");
+            self.buffer = ring_buffer(&self.device, &self.label, self.stride * self.capacity);
+            self.bind_group = ring_bind_group(&self.device, &self.label, &self.layout, &self.buffer, self.binding_size);
+        }
+        if !self.staging.is_empty() {
+            self.queue.write_buffer(&self.buffer, 0, &self.staging);
+        }
+    }
+
+    /// Remove all blocks, typically at the start of a frame.
+    pub fn reset(&mut self) {
+        self.staging.clear();
+    }
+
+    /// Number of pushed blocks.
+    pub fn len(&self) -> usize {
+        (self.staging.len() as u64 / self.stride) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.staging.is_empty()
+    }
+
+    pub fn binding_size(&self) -> u64 {
+        self.binding_size
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    /// Bind group with the ring at binding 0; valid until the next growing [`flush`](Self::flush).
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+fn ring_buffer(device: &Device, label: &str, size: u64) -> Buffer {
+    gpu_util::buffer(device, label, size, BufferUsages::UNIFORM | BufferUsages::COPY_DST)
+}
+
+fn ring_bind_group(device: &Device, label: &str, layout: &BindGroupLayout, buffer: &Buffer, binding_size: u64) -> BindGroup {
+    gpu_util::bind_group(device, label, layout, &[BindingResource::Buffer(BufferBinding {
+        buffer,
+        offset: 0,
+        size: BufferSize::new(binding_size),
+    })])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_are_aligned_strides() {
+        let offsets = DynamicOffsets { binding_size: 80, stride: 80u64.div_ceil(256) * 256 };
+        assert_eq!(offsets.stride(), 256);
+        assert_eq!(offsets.offset(0), 0);
+        assert_eq!(offsets.offset(3), 768);
+        assert_eq!(offsets.buffer_size(4), 1024);
+    }
+
+    #[test]
+    #[should_panic(expected = "dynamic offset exceeds u32")]
+    fn offset_beyond_u32_panics() {
+        let offsets = DynamicOffsets { binding_size: 256, stride: 256 };
+        offsets.offset(u32::MAX / 128);
+    }
+}
@@ -0,0 +1,336 @@
+// validation.rs
+//! Debug-build check that the bind group layouts the crate builds match what a
+//! shader actually uses.
+//!
+//! wgpu reports mismatches as e.g. `Shader global ResourceBinding { group: 0, binding: 2 }
+//! is not available in the pipeline layout`, which says little about *why*. This module
+//! reflects the preprocessed WGSL with naga and compares every binding used by the
+//! pipeline's entry points against the layout entries, producing a readable diff.
+//! The inputs of `vs_main` are checked against the vertex buffer layouts the same way.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use wgpu::naga;
+use wgpu::{BindGroupLayoutEntry, BindingType, BufferBindingType, SamplerBindingType, ShaderStages, StorageTextureAccess, TextureSampleType, TextureViewDimension, VertexBufferLayout, VertexFormat};
+
+/// A binding as declared by the shader, reduced to what must match the layout.
+struct ShaderBinding {
+    name: String,
+    ty: String,
+    stages: ShaderStages,
+}
+
+/// Compare the bindings used by `entry_points` of `source` with `groups`.
+///
+/// `groups[i]` are the layout entries bound at `@group(i)`, `group_names[i]` a short
+/// description used in the report. Returns the readable diff on mismatch.
+/// Shaders that fail to parse are not checked here; wgpu reports those itself.
+pub(crate) fn check_bindings(
+    source: &str,
+    entry_points: &[&str],
+    groups: &[&[BindGroupLayoutEntry]],
+    group_names: &[String],
+) -> Result<(), String> {
+    let Some(used) = shader_bindings(source, entry_points) else {
+        return Ok(());
+    };
+
+    let mut problems = Vec::new();
+    for ((group, binding), expected) in &used {
+        let location = format!("@group({}) @binding({}) `{}`", group, binding, expected.name);
+        let Some(entries) = groups.get(*group as usize) else {
+            problems.push(format!(
+                "{}: shader expects {}, but the pipeline only has {} bind group(s)",
+                location,
+                expected.ty,
+                groups.len()
+            ));
+            continue;
+        };
+        let Some(entry) = entries.iter().find(|e| e.binding == *binding) else {
+            problems.push(format!("{}: shader expects {}, bind group has no binding {}", location, expected.ty, binding));
+            continue;
+        };
+
+        let actual = layout_type_name(entry);
+        if actual != expected.ty {
+            problems.push(format!("{}: shader expects {}, bind group has {}", location, expected.ty, actual));
+        }
+        if !entry.visibility.contains(expected.stages) {
+            problems.push(format!(
+                "{}: used in {:?}, but the bind group entry is only visible to {:?}",
+                location, expected.stages, entry.visibility
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let mut report = String::new();
+    for problem in &problems {
+        let _ = writeln!(report, "  - {}", problem);
+    }
+    for (group, entries) in groups.iter().enumerate() {
+        let name = group_names.get(group).map(String::as_str).unwrap_or("bind group");
+        let _ = writeln!(report, "  bind group {} ({}):", group, name);
+        for entry in entries.iter() {
+            let _ = writeln!(report, "    @binding({}): {} [{:?}]", entry.binding, layout_type_name(entry), entry.visibility);
+        }
+    }
+    Err(report)
+}
+
+/// Bindings used by the given entry points, keyed by `(group, binding)`.
+fn shader_bindings(source: &str, entry_points: &[&str]) -> Option<BTreeMap<(u32, u32), ShaderBinding>> {
+    let module = naga::front::wgsl::parse_str(source).ok()?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .ok()?;
+
+    let mut used: BTreeMap<(u32, u32), ShaderBinding> = BTreeMap::new();
+    for (index, entry_point) in module.entry_points.iter().enumerate() {
+        if !entry_points.contains(&entry_point.name.as_str()) {
+            continue;
+        }
+        let stage = match entry_point.stage {
+            naga::ShaderStage::Vertex => ShaderStages::VERTEX,
+            naga::ShaderStage::Fragment => ShaderStages::FRAGMENT,
+            naga::ShaderStage::Compute => ShaderStages::COMPUTE,
+            _ => continue,
+        };
+        let function_info = info.get_entry_point(index);
+
+        for (handle, global) in module.global_variables.iter() {
+            let Some(binding) = &global.binding else {
+                continue;
+            };
+            if function_info[handle].is_empty() {
+                continue;
+            }
+            used.entry((binding.group, binding.binding))
+                .or_insert_with(|| ShaderBinding {
+                    name: global.name.clone().unwrap_or_default(),
+                    ty: shader_type_name(&module, global),
+                    stages: ShaderStages::empty(),
+                })
+                .stages |= stage;
+        }
+    }
+    Some(used)
+}
+
+/// Compare the `@location` inputs of `vs_main` in `source` with the attributes of
+/// `vertex_layouts`: every input needs an attribute at its location with the same
+/// scalar kind (float, signed or unsigned integer).
+///
+/// Returns the readable diff on mismatch. Shaders that fail to parse are not checked.
+pub(crate) fn check_vertex_inputs(source: &str, vertex_layouts: &[VertexBufferLayout]) -> Result<(), String> {
+    let Ok(module) = naga::front::wgsl::parse_str(source) else {
+        return Ok(());
+    };
+    let Some(entry_point) = module.entry_points.iter().find(|e| e.name == "vs_main") else {
+        return Ok(());
+    };
+
+    let mut inputs = Vec::new();
+    for argument in &entry_point.function.arguments {
+        match (&argument.binding, &module.types[argument.ty].inner) {
+            (Some(naga::Binding::Location { location, .. }), inner) => {
+                inputs.push((*location, argument.name.clone().unwrap_or_default(), inner.clone()));
+            }
+            (None, naga::TypeInner::Struct { members, .. }) => {
+                for member in members {
+                    if let Some(naga::Binding::Location { location, .. }) = member.binding {
+                        inputs.push((location, member.name.clone().unwrap_or_default(), module.types[member.ty].inner.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let attributes: Vec<_> = vertex_layouts.iter().flat_map(|layout| layout.attributes.iter()).collect();
+    let mut problems = Vec::new();
+    for (location, name, inner) in &inputs {
+        let expected = input_type_name(inner);
+        let Some(attribute) = attributes.iter().find(|a| a.shader_location == *location) else {
+            problems.push(format!("@location({}) `{}`: shader expects {}, no vertex attribute at location {}", location, name, expected, location));
+            continue;
+        };
+        let kind = match inner {
+            naga::TypeInner::Scalar(scalar) | naga::TypeInner::Vector { scalar, .. } => scalar.kind,
+            _ => continue,
+        };
+        if kind != format_kind(attribute.format) {
+            problems.push(format!("@location({}) `{}`: shader expects {}, vertex attribute is {:?}", location, name, expected, attribute.format));
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let mut report = String::new();
+    for problem in &problems {
+        let _ = writeln!(report, "  - {}", problem);
+    }
+    for (index, layout) in vertex_layouts.iter().enumerate() {
+        let _ = writeln!(report, "  vertex buffer {} (stride {}):", index, layout.array_stride);
+        for attribute in layout.attributes {
+            let _ = writeln!(report, "    @location({}): {:?} at offset {}", attribute.shader_location, attribute.format, attribute.offset);
+        }
+    }
+    Err(report)
+}
+
+/// WGSL spelling of a vertex input type.
+fn input_type_name(inner: &naga::TypeInner) -> String {
+    let scalar_name = |scalar: &naga::Scalar| match scalar.kind {
+        naga::ScalarKind::Sint => "i32",
+        naga::ScalarKind::Uint => "u32",
+        naga::ScalarKind::Float if scalar.width == 2 => "f16",
+        _ => "f32",
+    };
+    match inner {
+        naga::TypeInner::Scalar(scalar) => scalar_name(scalar).to_string(),
+        naga::TypeInner::Vector { size, scalar } => format!("vec{}<{}>", *size as u8, scalar_name(scalar)),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Scalar kind a vertex format is read as in the shader.
+fn format_kind(format: VertexFormat) -> naga::ScalarKind {
+    use VertexFormat::*;
+    match format {
+        Uint8 | Uint8x2 | Uint8x4 | Uint16 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => naga::ScalarKind::Uint,
+        Sint8 | Sint8x2 | Sint8x4 | Sint16 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => naga::ScalarKind::Sint,
+        _ => naga::ScalarKind::Float,
+    }
+}
+
+/// WGSL spelling of a shader global's binding type.
+fn shader_type_name(module: &naga::Module, global: &naga::GlobalVariable) -> String {
+    match global.space {
+        naga::AddressSpace::Uniform => return "var<uniform>".to_string(),
+        naga::AddressSpace::Storage { access } => {
+            return if access.contains(naga::StorageAccess::STORE) {
+                "var<storage, read_write>".to_string()
+            } else {
+                "var<storage, read>".to_string()
+            };
+        }
+        _ => {}
+    }
+    handle_type_name(module, &module.types[global.ty].inner)
+}
+
+fn handle_type_name(module: &naga::Module, inner: &naga::TypeInner) -> String {
+    match inner {
+        naga::TypeInner::Sampler { comparison: true } => "sampler_comparison".to_string(),
+        naga::TypeInner::Sampler { comparison: false } => "sampler".to_string(),
+        naga::TypeInner::Image { dim, arrayed, class } => {
+            let dim = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => "1d",
+                (naga::ImageDimension::D2, false) => "2d",
+                (naga::ImageDimension::D2, true) => "2d_array",
+                (naga::ImageDimension::D3, _) => "3d",
+                (naga::ImageDimension::Cube, false) => "cube",
+                (naga::ImageDimension::Cube, true) => "cube_array",
+            };
+            match class {
+                naga::ImageClass::Sampled { kind, multi } => {
+                    let scalar = match kind {
+                        naga::ScalarKind::Sint => "i32",
+                        naga::ScalarKind::Uint => "u32",
+                        _ => "f32",
+                    };
+                    texture_name(dim, *multi, false, scalar)
+                }
+                naga::ImageClass::Depth { multi } => texture_name(dim, *multi, true, ""),
+                naga::ImageClass::Storage { format, access } => {
+                    let access = if access.contains(naga::StorageAccess::ATOMIC) {
+                        "atomic"
+                    } else if access.contains(naga::StorageAccess::LOAD | naga::StorageAccess::STORE) {
+                        "read_write"
+                    } else if access.contains(naga::StorageAccess::STORE) {
+                        "write"
+                    } else {
+                        "read"
+                    };
+                    format!("texture_storage_{}<{}, {}>", dim, format!("{:?}", format).to_lowercase(), access)
+                }
+                naga::ImageClass::External => "texture_external".to_string(),
+            }
+        }
+        naga::TypeInner::BindingArray { base, size } => {
+            let base = handle_type_name(module, &module.types[*base].inner);
+            match size {
+                naga::ArraySize::Constant(n) => format!("binding_array<{}, {}>", base, n),
+                _ => format!("binding_array<{}>", base),
+            }
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+/// WGSL spelling of the type a layout entry accepts.
+fn layout_type_name(entry: &BindGroupLayoutEntry) -> String {
+    let base = match entry.ty {
+        BindingType::Buffer { ty: BufferBindingType::Uniform, .. } => "var<uniform>".to_string(),
+        BindingType::Buffer { ty: BufferBindingType::Storage { read_only: true }, .. } => "var<storage, read>".to_string(),
+        BindingType::Buffer { ty: BufferBindingType::Storage { read_only: false }, .. } => "var<storage, read_write>".to_string(),
+        BindingType::Sampler(SamplerBindingType::Comparison) => "sampler_comparison".to_string(),
+        BindingType::Sampler(_) => "sampler".to_string(),
+        BindingType::Texture { sample_type, view_dimension, multisampled } => {
+            let dim = dimension_name(view_dimension);
+            match sample_type {
+                TextureSampleType::Depth => texture_name(dim, multisampled, true, ""),
+                TextureSampleType::Sint => texture_name(dim, multisampled, false, "i32"),
+                TextureSampleType::Uint => texture_name(dim, multisampled, false, "u32"),
+                TextureSampleType::Float { .. } => texture_name(dim, multisampled, false, "f32"),
+            }
+        }
+        BindingType::StorageTexture { access, format, view_dimension } => {
+            let access = match access {
+                StorageTextureAccess::ReadOnly => "read",
+                StorageTextureAccess::WriteOnly => "write",
+                StorageTextureAccess::ReadWrite => "read_write",
+                StorageTextureAccess::Atomic => "atomic",
+            };
+            format!(
+                "texture_storage_{}<{}, {}>",
+                dimension_name(view_dimension),
+                format!("{:?}", format).to_lowercase(),
+                access
+            )
+        }
+        BindingType::ExternalTexture => "texture_external".to_string(),
+        ref other => format!("{:?}", other),
+    };
+    match entry.count {
+        Some(count) => format!("binding_array<{}, {}>", base, count),
+        None => base,
+    }
+}
+
+fn dimension_name(dimension: TextureViewDimension) -> &'static str {
+    match dimension {
+        TextureViewDimension::D1 => "1d",
+        TextureViewDimension::D2 => "2d",
+        TextureViewDimension::D2Array => "2d_array",
+        TextureViewDimension::Cube => "cube",
+        TextureViewDimension::CubeArray => "cube_array",
+        TextureViewDimension::D3 => "3d",
+    }
+}
+
+fn texture_name(dim: &str, multisampled: bool, depth: bool, scalar: &str) -> String {
+    let prefix = if depth { "texture_depth" } else { "texture" };
+    let multi = if multisampled { "_multisampled" } else { "" };
+    if depth {
+        format!("{}{}_{}", prefix, multi, dim)
+    } else {
+        format!("{}{}_{}<{}>", prefix, multi, dim, scalar)
+    }
+}
@@ -0,0 +1,154 @@
+//! Vector path tessellation (feature `lyon`).
+//!
+//! [`VectorMesh`] turns lyon paths, with the same move/line/quadratic/cubic/arc commands
+//! as SVG path data, into filled and stroked triangles, and uploads them as one mesh of
+//! layout [`POSITION_NORMAL_UV`](VertexLayoutId::POSITION_NORMAL_UV). Curves are
+//! flattened once at upload time, so icons, UI shapes and map features stay crisp
+//! without texture resolution limits:
+//! ```ignore
+//! let mut builder = lyon::path::Path::svg_builder();
+//! builder.move_to(point(10.0, 10.0));
+//! builder.quadratic_bezier_to(point(60.0, -20.0), point(110.0, 10.0));
+//! builder.line_to(point(110.0, 60.0));
+//! builder.close();
+//! let path = builder.build();
+//!
+//! let mut shape = VectorMesh::new().with_tolerance(0.25);
+//! shape.fill(&path)?;
+//! shape.stroke(&path, 2.0)?;
+//! let mesh = shape.upload(&mut meshes, "road_sign");
+//! // Inside a render pass
+//! meshes.draw(&mut pass, mesh, &options, 0..1);
+//! ```
+//!
+//! Paths are placed in the XY plane at z = 0, in path units (pixels for UI, world units
+//! for maps), with normals along +Z. UVs are the XY coordinates, so a material can
+//! scale them to tile a pattern. Fill and stroke triangles have no consistent winding;
+//! draw them without back-face culling.
+use std::fmt;
+use lyon::path::Path;
+use lyon::tessellation::{
+    BuffersBuilder, FillOptions, FillTessellator, FillVertex, StrokeOptions, StrokeTessellator, StrokeVertex,
+    VertexBuffers,
+};
+use crate::meshes::{MeshHandle, MeshManager, MeshVertex, VertexLayoutId};
+
+/// A path that lyon could not tessellate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorError {
+    /// `"fill of path N"` or `"stroke of path N"`, N counting the paths added to the [`VectorMesh`].
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to tessellate {}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for VectorError {}
+
+fn error(label: String, message: impl fmt::Display) -> VectorError {
+    VectorError { label, message: message.to_string() }
+}
+
+fn vertex(position: lyon::math::Point) -> MeshVertex {
+    MeshVertex {
+        position: [position.x, position.y, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        uv: [position.x, position.y],
+    }
+}
+
+/// Triangles of any number of filled and stroked paths, uploaded as one mesh.
+pub struct VectorMesh {
+    geometry: VertexBuffers<MeshVertex, u32>,
+    tolerance: f32,
+    paths: usize,
+    fill: FillTessellator,
+    stroke: StrokeTessellator,
+}
+
+impl Default for VectorMesh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VectorMesh {
+    /// Flattens curves with lyon's default tolerance of 0.1 path units.
+    pub fn new() -> Self {
+        Self {
+            geometry: VertexBuffers::new(),
+            tolerance: FillOptions::DEFAULT_TOLERANCE,
+            paths: 0,
+            fill: FillTessellator::new(),
+            stroke: StrokeTessellator::new(),
+        }
+    }
+
+    /// Maximum distance between a curve and its flattened segments used by
+    /// [`fill`](Self::fill) and [`stroke`](Self::stroke). Smaller is smoother and costs
+    /// more triangles.
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Fill the inside of `path` by the non-zero rule. Open sub-paths are closed.
+    pub fn fill(&mut self, path: &Path) -> Result<(), VectorError> {
+        let options = FillOptions::tolerance(self.tolerance);
+        self.fill_with(path, &options)
+    }
+
+    /// Fill with explicit lyon options, e.g. the even-odd rule for shapes with holes.
+    pub fn fill_with(&mut self, path: &Path, options: &FillOptions) -> Result<(), VectorError> {
+        let label = format!("fill of path {}", self.paths);
+        self.paths += 1;
+        let mut builder = BuffersBuilder::new(&mut self.geometry, |v: FillVertex| vertex(v.position()));
+        self.fill
+            .tessellate_path(path, options, &mut builder)
+            .map_err(|e| error(label, format!("{e:?}")))
+    }
+
+    /// Outline `path` with lines of `width` path units, with miter joins and butt caps.
+    pub fn stroke(&mut self, path: &Path, width: f32) -> Result<(), VectorError> {
+        let options = StrokeOptions::tolerance(self.tolerance).with_line_width(width);
+        self.stroke_with(path, &options)
+    }
+
+    /// Stroke with explicit lyon options for joins, caps and miter limits.
+    pub fn stroke_with(&mut self, path: &Path, options: &StrokeOptions) -> Result<(), VectorError> {
+        let label = format!("stroke of path {}", self.paths);
+        self.paths += 1;
+        let mut builder = BuffersBuilder::new(&mut self.geometry, |v: StrokeVertex| vertex(v.position()));
+        self.stroke
+            .tessellate_path(path, options, &mut builder)
+            .map_err(|e| error(label, format!("{e:?}")))
+    }
+
+    pub fn vertices(&self) -> &[MeshVertex] {
+        &self.geometry.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.geometry.indices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.geometry.indices.is_empty()
+    }
+
+    /// Drop all triangles to tessellate a new shape with the same tessellators.
+    pub fn clear(&mut self) {
+        self.geometry.vertices.clear();
+        self.geometry.indices.clear();
+        self.paths = 0;
+    }
+
+    pub fn upload(&self, meshes: &mut MeshManager, label: &str) -> MeshHandle {
+        let _span = trace_span!("vector_mesh_upload", label = label, triangles = self.geometry.indices.len() / 3);
+        meshes.upload(label, VertexLayoutId::POSITION_NORMAL_UV, &self.geometry.vertices, &self.geometry.indices)
+    }
+}
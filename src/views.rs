@@ -0,0 +1,227 @@
+//! Multiple views per frame: split-screen, picture-in-picture, editor viewports.
+//!
+//! A [`ViewSet`] holds one [`CameraManager`] per view, so every view has its own camera
+//! buffer and bind group and updating one view never overwrites the camera another
+//! view's draws are still waiting to read (`write_buffer` contents apply to the whole
+//! submission). Per-object data that doesn't depend on the camera, e.g. a
+//! [`UniformRing`](crate::uniform_ring::UniformRing) filled by a
+//! [`SceneGraph`](crate::scene::SceneGraph), is uploaded once and shared by all views.
+//!
+//! Views rendering into the same target are drawn in one pass, each restricted to its
+//! [`Viewport`]; views with separate targets simply begin their own pass:
+//! ```ignore
+//! let mut views = ViewSet::new(&device, &queue);
+//! let players: Vec<ViewId> = Viewport::split_screen(width, height, 2).into_iter().map(|v| views.add(v)).collect();
+//! // Every frame
+//! for (&view, player) in players.iter().zip(&players_state) {
+//!     views.update(view, &player.camera);
+//! }
+//! // Inside a render pass
+//! for &view in &players {
+//!     views.apply(view, &mut pass);
+//!     render_manager.render_with_textures(&[&albedo], shader_path, &options, &[views.camera(view).buffer()], &mut pass);
+//!     // draw meshes
+//! }
+//! ```
+use wgpu::*;
+use crate::camera::{CameraData, CameraManager, CAMERA_GROUP};
+
+/// Rectangle of a render target in pixels, with its depth range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl Viewport {
+    /// The whole target.
+    pub fn full(width: u32, height: u32) -> Self {
+        Self::new(0, 0, width, height)
+    }
+
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width: width.max(1),
+            height: height.max(1),
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    /// Split a target into `count` views: side by side for two, a 2x2 grid for three
+    /// or four, and a near-square grid beyond that.
+    pub fn split_screen(width: u32, height: u32, count: u32) -> Vec<Self> {
+        let count = count.max(1);
+        let columns = match count {
+            1 => 1,
+            2 => 2,
+            n => (n as f32).sqrt().ceil() as u32,
+        };
+        let rows = count.div_ceil(columns);
+        (0..count)
+            .map(|i| {
+                let (column, row) = (i % columns, i / columns);
+                let x = width * column / columns;
+                let y = height * row / rows;
+                Self::new(x, y, width * (column + 1) / columns - x, height * (row + 1) / rows - y)
+            })
+            .collect()
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    /// Whether the pixel at `x, y` of the target lies inside, e.g. to route input.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+
+    /// Restrict rasterization of `pass` to this rectangle.
+    pub fn apply(&self, pass: &mut RenderPass) {
+        pass.set_viewport(self.x as f32, self.y as f32, self.width as f32, self.height as f32, self.min_depth, self.max_depth);
+        pass.set_scissor_rect(self.x, self.y, self.width, self.height);
+    }
+}
+
+/// Handle to a view of a [`ViewSet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ViewId(usize);
+
+struct View {
+    viewport: Viewport,
+    camera: CameraManager,
+    enabled: bool,
+}
+
+/// Views with their own viewport and camera.
+pub struct ViewSet {
+    device: Device,
+    queue: Queue,
+    views: Vec<Option<View>>,
+}
+
+impl ViewSet {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            views: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, viewport: Viewport) -> ViewId {
+        let view = View {
+            viewport,
+            camera: CameraManager::new(&self.device, &self.queue),
+            enabled: true,
+        };
+        match self.views.iter().position(Option::is_none) {
+            Some(index) => {
+                self.views[index] = Some(view);
+                ViewId(index)
+            }
+            None => {
+                self.views.push(Some(view));
+                ViewId(self.views.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: ViewId) {
+        if let Some(view) = self.views.get_mut(id.0) {
+            *view = None;
+        }
+    }
+
+    pub fn viewport(&self, id: ViewId) -> &Viewport {
+        &self.view(id).viewport
+    }
+
+    /// Move or resize a view, e.g. after the target was resized.
+    pub fn set_viewport(&mut self, id: ViewId, viewport: Viewport) {
+        self.view_mut(id).viewport = viewport;
+    }
+
+    /// Scale every viewport from a target of `old` size to `new`, keeping split-screen
+    /// layouts after the target was resized.
+    pub fn rescale(&mut self, old: (u32, u32), new: (u32, u32)) {
+        let scale = |value: u32, old: u32, new: u32| (value as u64 * new as u64 / old.max(1) as u64) as u32;
+        for view in self.views.iter_mut().flatten() {
+            let viewport = view.viewport;
+            let (x, y) = (scale(viewport.x, old.0, new.0), scale(viewport.y, old.1, new.1));
+            let right = scale(viewport.x + viewport.width, old.0, new.0);
+            let bottom = scale(viewport.y + viewport.height, old.1, new.1);
+            view.viewport = Viewport {
+                min_depth: viewport.min_depth,
+                max_depth: viewport.max_depth,
+                ..Viewport::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+            };
+        }
+    }
+
+    /// Skip a view in [`ids`](Self::ids) without losing its camera history.
+    pub fn set_enabled(&mut self, id: ViewId, enabled: bool) {
+        self.view_mut(id).enabled = enabled;
+    }
+
+    pub fn camera(&self, id: ViewId) -> &CameraManager {
+        &self.view(id).camera
+    }
+
+    /// The camera of a view, e.g. to enable jitter or reset its history.
+    pub fn camera_mut(&mut self, id: ViewId) -> &mut CameraManager {
+        &mut self.view_mut(id).camera
+    }
+
+    /// Write the camera of a view for the next frame, sized to its viewport.
+    ///
+    /// Written with `Queue::write_buffer`, so update each view once per submission.
+    pub fn update(&mut self, id: ViewId, camera: &CameraData) {
+        let view = self.view_mut(id);
+        let size = view.viewport.size();
+        view.camera.update(camera, size);
+    }
+
+    /// Set the viewport and scissor of a view and bind its camera at [`CAMERA_GROUP`].
+    ///
+    /// Draws through [`render_with_textures`](crate::renderer::RenderManager::render_with_textures)
+    /// bind their own group 1 and should pass the view's camera buffer as first uniform instead.
+    pub fn apply(&self, id: ViewId, pass: &mut RenderPass) {
+        let view = self.view(id);
+        view.viewport.apply(pass);
+        pass.set_bind_group(CAMERA_GROUP, view.camera.bind_group(), &[]);
+    }
+
+    /// Enabled views in creation order.
+    pub fn ids(&self) -> impl Iterator<Item = ViewId> + '_ {
+        self.views
+            .iter()
+            .enumerate()
+            .filter(|(_, view)| view.as_ref().is_some_and(|view| view.enabled))
+            .map(|(index, _)| ViewId(index))
+    }
+
+    /// The enabled view containing the pixel at `x, y`, the last one for overlaps.
+    pub fn view_at(&self, x: u32, y: u32) -> Option<ViewId> {
+        self.ids().filter(|&id| self.viewport(id).contains(x, y)).last()
+    }
+
+    fn view(&self, id: ViewId) -> &View {
+        self.views.get(id.0).and_then(Option::as_ref).expect("View was removed")
+    }
+
+    fn view_mut(&mut self, id: ViewId) -> &mut View {
+        self.views.get_mut(id.0).and_then(Option::as_mut).expect("View was removed")
+    }
+}
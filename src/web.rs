@@ -0,0 +1,170 @@
+//! WebAssembly / WebGPU support (feature `web`, `wasm32` only).
+//!
+//! Browsers give this crate neither threads nor a filesystem, so this module provides
+//! the async counterparts of native setup: device creation without blocking and
+//! texture loading over `fetch`, decoded by the browser through `createImageBitmap`.
+//!
+//! Build with `default-features = false, features = ["web"]` to leave out the
+//! thread-based subsystems of the `native` feature ([`workers`](crate::workers),
+//! [`parallel`](crate::parallel)).
+//!
+//! Shaders passed by path are read with `std::fs`, which is unavailable in the
+//! browser. Fetch them with [`fetch_text`] and create the modules yourself, then
+//! render through [`RenderManager::render_with_layouts`](crate::renderer::RenderManager::render_with_layouts).
+use std::fmt;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use wgpu::*;
+use crate::textures::LoadedTexture;
+use crate::labels::namespaced;
+
+/// Errors of the async web helpers.
+#[derive(Debug, Clone)]
+pub enum WebError {
+    /// No `window` object, e.g. when running inside a worker.
+    NoWindow,
+    /// The request failed before a response arrived.
+    Fetch(String),
+    /// The server answered with a non-2xx status.
+    Status { url: String, status: u16 },
+    /// The response body could not be read or decoded as an image.
+    Decode(String),
+    /// No WebGPU adapter is available.
+    NoAdapter,
+    /// The adapter refused to create a device.
+    RequestDevice(RequestDeviceError),
+}
+
+impl fmt::Display for WebError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebError::NoWindow => f.write_str("no window object available"),
+            WebError::Fetch(e) => write!(f, "fetch failed: {}", e),
+            WebError::Status { url, status } => write!(f, "fetching {} returned HTTP {}", url, status),
+            WebError::Decode(e) => write!(f, "failed to decode response: {}", e),
+            WebError::NoAdapter => f.write_str("no WebGPU adapter available"),
+            WebError::RequestDevice(e) => write!(f, "failed to request device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WebError {}
+
+fn js_error(value: JsValue) -> String {
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}
+
+/// Request a high-performance adapter and a device with the adapter's limits.
+///
+/// Async and thread-free, so it can be awaited from `wasm_bindgen_futures::spawn_local`.
+pub async fn request_device(instance: &Instance, surface: Option<&Surface<'_>>) -> Result<(Adapter, Device, Queue), WebError> {
+    let adapter = instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: surface,
+        })
+        .await
+        .map_err(|_| WebError::NoAdapter)?;
+
+    let (device, queue) = adapter
+        .request_device(&DeviceDescriptor {
+            label: Some(&namespaced("wgpu_render_manager device")),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        })
+        .await
+        .map_err(WebError::RequestDevice)?;
+
+    Ok((adapter, device, queue))
+}
+
+async fn fetch(url: &str) -> Result<web_sys::Response, WebError> {
+    let window = web_sys::window().ok_or(WebError::NoWindow)?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(|e| WebError::Fetch(js_error(e)))?
+        .dyn_into()
+        .map_err(|e| WebError::Fetch(js_error(e)))?;
+
+    if !response.ok() {
+        return Err(WebError::Status { url: url.to_string(), status: response.status() });
+    }
+    Ok(response)
+}
+
+/// Fetch `url` and return the response body.
+pub async fn fetch_bytes(url: &str) -> Result<Vec<u8>, WebError> {
+    let response = fetch(url).await?;
+    let body = response.array_buffer().map_err(|e| WebError::Decode(js_error(e)))?;
+    let buffer = JsFuture::from(body).await.map_err(|e| WebError::Decode(js_error(e)))?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Fetch `url` and return the response body as text, e.g. a WGSL shader.
+pub async fn fetch_text(url: &str) -> Result<String, WebError> {
+    let response = fetch(url).await?;
+    let body = response.text().map_err(|e| WebError::Decode(js_error(e)))?;
+    let text = JsFuture::from(body).await.map_err(|e| WebError::Decode(js_error(e)))?;
+    text.as_string().ok_or_else(|| WebError::Decode(format!("{} is not text", url)))
+}
+
+/// Fetch an image (PNG, JPEG, WebP, ... whatever the browser decodes) into an
+/// `Rgba8UnormSrgb` texture.
+///
+/// Decoding happens off the main thread inside the browser via `createImageBitmap`,
+/// the upload uses `Queue::copy_external_image_to_texture`.
+pub async fn load_texture(device: &Device, queue: &Queue, url: &str) -> Result<LoadedTexture, WebError> {
+    let window = web_sys::window().ok_or(WebError::NoWindow)?;
+    let response = fetch(url).await?;
+    let blob: web_sys::Blob = JsFuture::from(response.blob().map_err(|e| WebError::Decode(js_error(e)))?)
+        .await
+        .map_err(|e| WebError::Decode(js_error(e)))?
+        .dyn_into()
+        .map_err(|e| WebError::Decode(js_error(e)))?;
+    let bitmap_promise = window
+        .create_image_bitmap_with_blob(&blob)
+        .map_err(|e| WebError::Decode(js_error(e)))?;
+    let bitmap: web_sys::ImageBitmap = JsFuture::from(bitmap_promise)
+        .await
+        .map_err(|e| WebError::Decode(js_error(e)))?
+        .dyn_into()
+        .map_err(|e| WebError::Decode(js_error(e)))?;
+
+    let size = Extent3d {
+        width: bitmap.width(),
+        height: bitmap.height(),
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(&namespaced(url)),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        // RENDER_ATTACHMENT is required by copy_external_image_to_texture
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    queue.copy_external_image_to_texture(
+        &CopyExternalImageSourceInfo {
+            source: ExternalImageSource::ImageBitmap(bitmap),
+            origin: Origin2d::ZERO,
+            flip_y: false,
+        },
+        CopyExternalImageDestInfo {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+            color_space: PredefinedColorSpace::Srgb,
+            premultiplied_alpha: false,
+        },
+        size,
+    );
+
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    Ok(LoadedTexture { texture, view })
+}
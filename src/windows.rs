@@ -0,0 +1,139 @@
+//! Rendering to several windows with one device.
+//!
+//! A [`WindowSet`] keeps one [`RenderWindow`] per window: its own swapchain and depth
+//! buffer in a [`SurfaceManager`], its own cameras and viewports in a [`ViewSet`] and
+//! its own [`TargetPool`] for intermediate targets of the window's size. Everything else
+//! is shared: pipelines, bind groups and textures cached by the one
+//! [`RenderManager`](crate::renderer::RenderManager), meshes and materials. Pipelines
+//! are cached per target format, so windows with different swapchain formats simply
+//! use different pipeline variants.
+//!
+//! Windows are identified by any key, e.g. winit's `WindowId`:
+//! ```ignore
+//! let mut windows = WindowSet::new(&adapter, &device, &queue);
+//! windows.add(main_id, instance.create_surface(main.clone())?, 1280, 720, SurfaceOptions::default());
+//! windows.add(tool_id, instance.create_surface(tool.clone())?, 640, 480, SurfaceOptions::default());
+//! // On WindowEvent::Resized
+//! windows.resize(&window_id, size.width, size.height);
+//! // Every frame
+//! for (id, window) in windows.iter_mut() {
+//!     let Some(frame) = window.surface.acquire() else { continue };
+//!     let view = frame.texture.create_view(&TextureViewDescriptor::default());
+//!     // Render each of window.views with the shared render manager, then present
+//! }
+//! ```
+use std::collections::HashMap;
+use std::hash::Hash;
+use wgpu::*;
+use crate::headless::TargetPool;
+use crate::surface::{SurfaceManager, SurfaceOptions};
+use crate::views::{ViewId, ViewSet, Viewport};
+
+/// Per-window state of a [`WindowSet`].
+pub struct RenderWindow<'window> {
+    /// Swapchain and depth buffer of the window.
+    pub surface: SurfaceManager<'window>,
+    /// Cameras and viewports of the window, initially one view covering it.
+    pub views: ViewSet,
+    /// Intermediate targets of the window, emptied when it is resized.
+    pub targets: TargetPool,
+    main_view: ViewId,
+    /// Last nonzero size, which the viewports are laid out for.
+    layout_size: (u32, u32),
+}
+
+impl RenderWindow<'_> {
+    /// The view created with the window, covering all of it unless changed.
+    pub fn main_view(&self) -> ViewId {
+        self.main_view
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.surface.size()
+    }
+}
+
+/// Windows sharing one device, keyed by `K`.
+pub struct WindowSet<'window, K> {
+    adapter: Adapter,
+    device: Device,
+    queue: Queue,
+    windows: HashMap<K, RenderWindow<'window>>,
+}
+
+impl<'window, K: Hash + Eq + Clone> WindowSet<'window, K> {
+    pub fn new(adapter: &Adapter, device: &Device, queue: &Queue) -> Self {
+        Self {
+            adapter: adapter.clone(),
+            device: device.clone(),
+            queue: queue.clone(),
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Configure `surface` for a window of `width` x `height` pixels, replacing a window
+    /// with the same key. The surface must be compatible with the shared adapter.
+    pub fn add(&mut self, key: K, surface: Surface<'window>, width: u32, height: u32, options: SurfaceOptions) -> &mut RenderWindow<'window> {
+        let _span = trace_span!("window_add", width, height);
+        let surface = SurfaceManager::new(&self.adapter, &self.device, surface, width, height, options);
+        let mut views = ViewSet::new(&self.device, &self.queue);
+        let main_view = views.add(Viewport::full(width, height));
+        let window = RenderWindow {
+            surface,
+            views,
+            targets: TargetPool::new(&self.device),
+            main_view,
+            layout_size: (width.max(1), height.max(1)),
+        };
+        self.windows.insert(key.clone(), window);
+        self.windows.get_mut(&key).expect("window was just inserted")
+    }
+
+    /// Drop a window with its swapchain, views and targets, e.g. on `CloseRequested`.
+    pub fn remove(&mut self, key: &K) -> Option<RenderWindow<'window>> {
+        self.windows.remove(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&RenderWindow<'window>> {
+        self.windows.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut RenderWindow<'window>> {
+        self.windows.get_mut(key)
+    }
+
+    /// Resize the swapchain and depth buffer of a window, scale its viewports and drop
+    /// its pooled targets of the old size. A zero size pauses the window.
+    pub fn resize(&mut self, key: &K, width: u32, height: u32) {
+        let Some(window) = self.windows.get_mut(key) else {
+            return;
+        };
+        window.surface.resize(width, height);
+        if window.surface.is_paused() || window.layout_size == (width, height) {
+            return;
+        }
+        window.views.rescale(window.layout_size, (width, height));
+        window.layout_size = (width, height);
+        window.targets.clear();
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.windows.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &RenderWindow<'window>)> {
+        self.windows.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut RenderWindow<'window>)> {
+        self.windows.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+}
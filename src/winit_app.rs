@@ -0,0 +1,268 @@
+//! winit window and frame loop (feature `winit`).
+//!
+//! [`run`] opens a window, creates the device, a [`SurfaceManager`], a
+//! [`RenderManager`] and a [`MeshManager`], and drives a [`WinitApp`] from winit's
+//! event loop: resize events reconfigure the surface, every frame is bracketed by
+//! [`begin_frame`](RenderManager::begin_frame)/[`end_frame`](RenderManager::end_frame)
+//! and submitted through the manager. A textured triangle needs little more than its
+//! shader:
+//! ```ignore
+//! struct Triangle { mesh: MeshHandle, options: PipelineOptions, texture: TextureKey }
+//!
+//! impl WinitApp for Triangle {
+//!     fn init(context: &mut AppContext) -> Self {
+//!         let mesh = context.meshes.upload("triangle", VertexLayoutId::POSITION_NORMAL_UV, &VERTICES, &[]);
+//!         let options = context.meshes.layouts()
+//!             .pipeline_options(VertexLayoutId::POSITION_NORMAL_UV, PipelineOptions::default())
+//!             .with_target(context.surface.scene_format().into());
+//!         let texture = TextureKey::new("checker", TextureParams::default(), 256);
+//!         Self { mesh, options, texture }
+//!     }
+//!
+//!     fn render(&mut self, context: &mut AppContext, target: &TextureView, encoder: &mut CommandEncoder) {
+//!         let texture = context.render_manager.generator().get_or_create(&self.texture).clone();
+//!         let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+//!             color_attachments: &[Some(RenderPassColorAttachment {
+//!                 view: target,
+//!                 depth_slice: None,
+//!                 resolve_target: None,
+//!                 ops: Operations { load: LoadOp::Clear(Color::BLACK), store: StoreOp::Store },
+//!             })],
+//!             ..Default::default()
+//!         });
+//!         context.render_manager.render_with_textures(&[&texture], Path::new("triangle.wgsl"), &self.options, &[], &mut pass);
+//!         context.meshes.draw(&mut pass, self.mesh, &self.options, 0..1);
+//!     }
+//! }
+//!
+//! fn main() -> Result<(), WinitError> {
+//!     winit_app::run::<Triangle>(WinitOptions::new("Triangle", "shaders"))
+//! }
+//! ```
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalSize;
+use winit::error::{EventLoopError, OsError};
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::window::{Window, WindowId};
+use wgpu::*;
+use crate::meshes::MeshManager;
+use crate::renderer::RenderManager;
+use crate::surface::{SurfaceManager, SurfaceOptions};
+use crate::labels::namespaced;
+
+/// Errors of [`run`].
+#[derive(Debug)]
+pub enum WinitError {
+    EventLoop(EventLoopError),
+    CreateWindow(OsError),
+    CreateSurface(CreateSurfaceError),
+    /// No adapter can present to the window.
+    NoAdapter,
+    RequestDevice(RequestDeviceError),
+}
+
+impl fmt::Display for WinitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WinitError::EventLoop(e) => write!(f, "event loop failed: {}", e),
+            WinitError::CreateWindow(e) => write!(f, "failed to create window: {}", e),
+            WinitError::CreateSurface(e) => write!(f, "failed to create surface: {}", e),
+            WinitError::NoAdapter => f.write_str("no adapter can present to the window"),
+            WinitError::RequestDevice(e) => write!(f, "failed to request device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WinitError {}
+
+/// Window and renderer setup of [`run`].
+#[derive(Debug, Clone)]
+pub struct WinitOptions {
+    pub title: String,
+    /// Initial inner size in physical pixels.
+    pub size: (u32, u32),
+    /// Directory of the shaders passed by path to the [`RenderManager`].
+    pub shader_dir: PathBuf,
+    pub surface: SurfaceOptions,
+    pub required_features: Features,
+}
+
+impl WinitOptions {
+    /// A 1280x720 window with default surface options.
+    pub fn new(title: &str, shader_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            title: title.to_string(),
+            size: (1280, 720),
+            shader_dir: shader_dir.into(),
+            surface: SurfaceOptions::default(),
+            required_features: Features::empty(),
+        }
+    }
+}
+
+/// Everything created by [`run`], handed to the [`WinitApp`].
+pub struct AppContext {
+    pub window: Arc<Window>,
+    pub instance: Instance,
+    pub adapter: Adapter,
+    pub device: Device,
+    pub queue: Queue,
+    pub surface: SurfaceManager<'static>,
+    pub render_manager: RenderManager,
+    pub meshes: MeshManager,
+}
+
+/// An application driven by [`run`].
+pub trait WinitApp: Sized {
+    /// Create the application once the window and device exist.
+    fn init(context: &mut AppContext) -> Self;
+
+    /// Advance the application by `dt` seconds, before the frame is acquired.
+    fn update(&mut self, context: &mut AppContext, dt: f32) {
+        let _ = (context, dt);
+    }
+
+    /// Record the frame into `encoder`, drawing into the swapchain view `target`, a
+    /// [`scene_view`](SurfaceManager::scene_view).
+    fn render(&mut self, context: &mut AppContext, target: &TextureView, encoder: &mut CommandEncoder);
+
+    /// Handle a window event; resizing and closing are already taken care of.
+    fn window_event(&mut self, context: &mut AppContext, event: &WindowEvent) {
+        let _ = (context, event);
+    }
+}
+
+/// Open a window and run `A` until the window is closed.
+pub fn run<A: WinitApp>(options: WinitOptions) -> Result<(), WinitError> {
+    let event_loop = EventLoop::new().map_err(WinitError::EventLoop)?;
+    let mut runner = Runner::<A> {
+        options,
+        state: None,
+        error: None,
+        last_frame: Instant::now(),
+    };
+    event_loop.run_app(&mut runner).map_err(WinitError::EventLoop)?;
+    runner.error.map_or(Ok(()), Err)
+}
+
+struct Runner<A> {
+    options: WinitOptions,
+    state: Option<(AppContext, A)>,
+    /// Setup error, returned by [`run`] after the event loop exits.
+    error: Option<WinitError>,
+    last_frame: Instant,
+}
+
+impl<A: WinitApp> Runner<A> {
+    fn create_context(&self, event_loop: &ActiveEventLoop) -> Result<AppContext, WinitError> {
+        let (width, height) = self.options.size;
+        let attributes = Window::default_attributes()
+            .with_title(self.options.title.clone())
+            .with_inner_size(PhysicalSize::new(width, height));
+        let window = Arc::new(event_loop.create_window(attributes).map_err(WinitError::CreateWindow)?);
+
+        let instance = Instance::default();
+        let surface = instance.create_surface(window.clone()).map_err(WinitError::CreateSurface)?;
+        let adapter = pollster::block_on(instance.request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: Some(&surface),
+        }))
+        .map_err(|_| WinitError::NoAdapter)?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&DeviceDescriptor {
+            label: Some(&namespaced("wgpu_render_manager device")),
+            required_features: self.options.required_features,
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .map_err(WinitError::RequestDevice)?;
+
+        let size = window.inner_size();
+        let surface = SurfaceManager::new(&adapter, &device, surface, size.width, size.height, self.options.surface);
+        Ok(AppContext {
+            render_manager: RenderManager::new(&device, &queue, self.options.shader_dir.clone()),
+            meshes: MeshManager::new(&device),
+            window,
+            instance,
+            adapter,
+            device,
+            queue,
+            surface,
+        })
+    }
+
+    fn frame(&mut self) {
+        let Some((context, app)) = &mut self.state else {
+            return;
+        };
+        let now = Instant::now();
+        let dt = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        context.render_manager.begin_frame();
+        app.update(context, dt);
+        let Some(frame) = context.surface.acquire() else {
+            return;
+        };
+        let view = context.surface.scene_view(&frame);
+        let mut encoder = context.device.create_command_encoder(&CommandEncoderDescriptor { label: Some(&namespaced("frame")) });
+        app.render(context, &view, &mut encoder);
+        context.render_manager.end_frame(&mut encoder);
+        context.render_manager.submit(encoder.finish());
+        context.render_manager.flush_submissions();
+        context.window.pre_present_notify();
+        frame.present();
+    }
+}
+
+impl<A: WinitApp> ApplicationHandler for Runner<A> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.state.is_some() {
+            return;
+        }
+        match self.create_context(event_loop) {
+            Ok(mut context) => {
+                let app = A::init(&mut context);
+                self.state = Some((context, app));
+                self.last_frame = Instant::now();
+            }
+            Err(error) => {
+                self.error = Some(error);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
+        match &event {
+            WindowEvent::CloseRequested => {
+                event_loop.exit();
+                return;
+            }
+            WindowEvent::Resized(size) => {
+                if let Some((context, _)) = &mut self.state {
+                    context.surface.resize(size.width, size.height);
+                }
+            }
+            WindowEvent::RedrawRequested => {
+                self.frame();
+                return;
+            }
+            _ => {}
+        }
+        if let Some((context, app)) = &mut self.state {
+            app.window_event(context, &event);
+        }
+    }
+
+    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some((context, _)) = &self.state {
+            context.window.request_redraw();
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Background creation of GPU resources on worker threads.
+//!
+//! Shader compilation, pipeline creation and texture uploads can take several
+//! milliseconds each. [`ResourceWorkers`] moves them off the render thread:
+//! every request returns a [`Pending`] handle immediately, which resolves once a
+//! worker finished the resource. Until then, draw with a placeholder.
+//!
+//! ```ignore
+//! let workers = render_manager.spawn_resource_workers(2);
+//! let albedo = workers.create_texture(TextureRequest::rgba8("albedo", 1024, 1024, pixels));
+//!
+//! // every frame
+//! let view = albedo.get().map(|t| &t.view).unwrap_or(&placeholder_view);
+//! ```
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, PoisonError};
+use std::thread::JoinHandle;
+use wgpu::*;
+use crate::concurrent::SharedMaterialBindGroups;
+use crate::labels::{self, LabelNamespace};
+use crate::pipelines::{build_render_pipeline, PipelineOptions};
+use crate::shader_preprocessing::compile_wgsl;
+use crate::textures::{create_texture, LoadedTexture, TextureRequest};
+
+type Job = Box<dyn FnOnce(&Device, &Queue) + Send>;
+
+struct PendingSlot<T> {
+    /// The resource, or the panic message of the job.
+    value: OnceLock<Result<T, String>>,
+    done: Mutex<bool>,
+    ready: Condvar,
+}
+
+/// Handle to a resource that is being created on a worker thread.
+///
+/// Cheap to clone, all clones resolve at the same time.
+pub struct Pending<T> {
+    slot: Arc<PendingSlot<T>>,
+}
+
+impl<T> Clone for Pending<T> {
+    fn clone(&self) -> Self {
+        Self { slot: self.slot.clone() }
+    }
+}
+
+impl<T> Pending<T> {
+    fn new() -> Self {
+        Self {
+            slot: Arc::new(PendingSlot {
+                value: OnceLock::new(),
+                done: Mutex::new(false),
+                ready: Condvar::new(),
+            }),
+        }
+    }
+
+    fn complete(&self, value: Result<T, String>) {
+        let _ = self.slot.value.set(value);
+        *self.slot.done.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.slot.ready.notify_all();
+    }
+
+    /// Returns `true` once the worker finished, successfully or not.
+    pub fn is_done(&self) -> bool {
+        self.slot.value.get().is_some()
+    }
+
+    /// Returns `true` if creating the resource panicked on the worker,
+    /// e.g. because a shader file could not be read.
+    pub fn is_failed(&self) -> bool {
+        matches!(self.slot.value.get(), Some(Err(_)))
+    }
+
+    /// Why creating the resource failed, `None` while it is pending or if it succeeded.
+    pub fn error(&self) -> Option<&str> {
+        self.slot.value.get().and_then(|value| value.as_ref().err()).map(String::as_str)
+    }
+
+    /// The resource, or `None` while it is pending or if creation failed.
+    pub fn get(&self) -> Option<&T> {
+        self.slot.value.get().and_then(|value| value.as_ref().ok())
+    }
+
+    /// The resource, or `placeholder` until it is ready.
+    pub fn get_or<'a>(&'a self, placeholder: &'a T) -> &'a T {
+        self.get().unwrap_or(placeholder)
+    }
+
+    /// Block until the worker finished. Returns `None` if creation failed.
+    pub fn wait(&self) -> Option<&T> {
+        let mut done = self.slot.done.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*done {
+            done = self.slot.ready.wait(done).unwrap_or_else(PoisonError::into_inner);
+        }
+        drop(done);
+        self.get()
+    }
+}
+
+/// Pool of threads creating textures, bind groups and pipelines in the background.
+///
+/// Jobs run in submission order across the pool. A job that panics (for example a
+/// shader that fails to preprocess) resolves its [`Pending`] as failed, with the panic
+/// message as its [`error`](Pending::error), instead of killing the worker.
+///
+/// Dropping the pool finishes all queued jobs and joins the threads.
+pub struct ResourceWorkers {
+    sender: Option<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+    materials: Arc<SharedMaterialBindGroups>,
+    defines: HashMap<String, bool>,
+}
+
+impl ResourceWorkers {
+    /// Spawn `threads` workers (at least one).
+    ///
+    /// Material bind groups are created through `materials`, so they are shared
+    /// with every other user of that cache.
+    pub fn new(device: &Device, queue: &Queue, materials: Arc<SharedMaterialBindGroups>, threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                let device = device.clone();
+                let queue = queue.clone();
+                std::thread::Builder::new()
+                    .name(format!("resource worker {}", i))
+                    .spawn(move || loop {
+                        let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                        match job {
+                            Ok(job) => job(&device, &queue),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Failed to spawn resource worker thread")
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            threads,
+            materials,
+            defines: HashMap::new(),
+        }
+    }
+
+    /// Shader defines used by [`compile_shader`](Self::compile_shader) and
+    /// [`create_render_pipeline`](Self::create_render_pipeline).
+    pub fn set_defines(&mut self, defines: HashMap<String, bool>) {
+        self.defines = defines;
+    }
+
+    /// Number of worker threads.
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Run `create` on a worker thread and return a handle to its result.
+    pub fn spawn<T, F>(&self, label: &str, create: F) -> Pending<T>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(&Device, &Queue) -> T + Send + 'static,
+    {
+        let pending = Pending::new();
+        let handle = pending.clone();
+        let label = label.to_string();
+        let namespace = labels::current();
+        let job: Job = Box::new(move |device, queue| {
+            let _span = trace_span!("resource_worker_job", label = %label);
+            let _namespace = LabelNamespace::restore(namespace);
+            let result = catch_unwind(AssertUnwindSafe(|| create(device, queue)));
+            handle.complete(result.map_err(|panic| format!("resource worker job '{}' panicked: {}", label, panic_message(&*panic))));
+        });
+        self.sender
+            .as_ref()
+            .expect("resource workers are running until dropped")
+            .send(job)
+            .expect("resource worker threads exited unexpectedly");
+        pending
+    }
+
+    /// Preprocess and compile a WGSL shader, see [`compile_wgsl`].
+    pub fn compile_shader(&self, path: PathBuf) -> Pending<ShaderModule> {
+        let defines = self.defines.clone();
+        self.spawn(&path.display().to_string(), move |device, _| compile_wgsl(device, &path, &defines))
+    }
+
+    /// Compile a shader and create a render pipeline with the given layouts.
+    ///
+    /// The pipeline follows the same conventions as the ones created by the
+    /// [`PipelineCache`](crate::pipelines::PipelineCache) (`vs_main` / `fs_main`).
+    pub fn create_render_pipeline(
+        &self,
+        shader_path: PathBuf,
+        bind_group_layouts: Vec<BindGroupLayout>,
+        options: PipelineOptions,
+    ) -> Pending<RenderPipeline> {
+        let defines = self.defines.clone();
+        self.spawn(&shader_path.display().to_string(), move |device, _| {
+            let shader = compile_wgsl(device, &shader_path, &defines);
+            let layouts: Vec<&BindGroupLayout> = bind_group_layouts.iter().collect();
+            build_render_pipeline(device, &shader, &shader_path, &layouts, &options)
+        })
+    }
+
+    /// Create a texture and upload its data.
+    pub fn create_texture(&self, request: TextureRequest) -> Pending<LoadedTexture> {
+        let label = request.label.clone();
+        self.spawn(&label, move |device, queue| create_texture(device, queue, &request))
+    }
+
+    /// Create a material bind group in the shared material cache.
+    ///
+    /// See [`SharedMaterialBindGroups::get_or_create`] for the binding layout.
+    pub fn create_material_bind_group(
+        &self,
+        texture_views: Vec<TextureView>,
+        shadow: Option<(Sampler, TextureView)>,
+    ) -> Pending<BindGroup> {
+        let materials = self.materials.clone();
+        self.spawn("material bind group", move |_, _| {
+            let views: Vec<&TextureView> = texture_views.iter().collect();
+            materials.get_or_create(&views, shadow.as_ref().map(|(s, v)| (s, v)))
+        })
+    }
+}
+
+/// The message of a panic payload, as passed to `panic!`.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+impl Drop for ResourceWorkers {
+    fn drop(&mut self) {
+        // Closing the channel lets every worker drain the queue and exit.
+        self.sender.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use wgpu::{Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Device, Queue, StorageTextureAccess, TextureFormat};
+use wgpu_render_manager::diagnostics::CacheKind;
+use wgpu_render_manager::error::CrmError;
+use wgpu_render_manager::pipelines::TextureAccess;
+use wgpu_render_manager::renderer::RenderManager;
+use wgpu_render_manager::testing::{noop_device, test_texture, CacheSnapshot};
+use wgpu_render_manager::uniform_ring::{DynamicOffsets, UniformRing};
+
+fn render_manager() -> (Device, Queue, RenderManager) {
+    let (device, queue) = noop_device();
+    let render_manager = RenderManager::new(&device, &queue, "shaders".into());
+    (device, queue, render_manager)
+}
+
+fn uniform_buffer(device: &Device, size: u64) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("test uniforms"),
+        size,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[test]
+fn material_bind_groups_are_created_once() {
+    let (device, _queue, mut rm) = render_manager();
+    let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+    let normal = test_texture(&device, 4, 4, TextureFormat::Rgba8Unorm);
+
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&albedo, &normal], &[]);
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::MaterialLayout, 1);
+    created.assert_created(CacheKind::MaterialBindGroup, 1);
+
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&albedo, &normal], &[]);
+    rm.material_layout(&[&albedo, &normal], &[]);
+    assert_eq!(before.created_since(&rm).total(), 0);
+}
+
+#[test]
+fn views_of_the_same_formats_share_a_layout() {
+    let (device, _queue, mut rm) = render_manager();
+    let first = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+    let second = test_texture(&device, 8, 8, TextureFormat::Rgba8UnormSrgb);
+
+    rm.material_bind_group(&[&first], &[]);
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&second], &[]);
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::MaterialLayout, 0);
+    created.assert_created(CacheKind::MaterialBindGroup, 1);
+}
+
+#[test]
+fn release_material_drops_every_access_variant() {
+    let (device, _queue, mut rm) = render_manager();
+    let target = test_texture(&device, 4, 4, TextureFormat::Rgba8Unorm);
+    let storage = [TextureAccess::Storage(StorageTextureAccess::WriteOnly)];
+
+    rm.material_bind_group(&[&target], &[]);
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&target], &storage);
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::MaterialLayout, 1);
+    created.assert_created(CacheKind::MaterialBindGroup, 1);
+
+    assert!(rm.release_material(&[&target]));
+    assert!(!rm.release_material(&[&target]));
+    let remaining = rm.cache_entries().iter().filter(|e| e.kind == CacheKind::MaterialBindGroup).count();
+    assert_eq!(remaining, 0);
+}
+
+#[test]
+fn keyed_bind_groups_hit_by_key() {
+    let (device, _queue, mut rm) = render_manager();
+    let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+    let other = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+
+    let before = CacheSnapshot::take(&rm);
+    let first = rm.keyed_material_bind_group(&("water", 0u32), &[&albedo], &[]);
+    let second = rm.keyed_material_bind_group(&("water", 0u32), &[&albedo], &[]);
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::KeyedMaterialBindGroup, 1);
+    created.assert_created(CacheKind::MaterialBindGroup, 0);
+    assert_eq!(first.bind_group, second.bind_group);
+
+    // A hit returns the cached bind group even for different views.
+    let before = CacheSnapshot::take(&rm);
+    rm.keyed_material_bind_group(&("water", 0u32), &[&other], &[]);
+    assert_eq!(before.created_since(&rm).total(), 0);
+
+    assert!(rm.release_material_key(&("water", 0u32)));
+    let before = CacheSnapshot::take(&rm);
+    rm.keyed_material_bind_group(&("water", 0u32), &[&other], &[]);
+    rm.keyed_material_bind_group(&("water", 1u32), &[&other], &[]);
+    before.created_since(&rm).assert_created(CacheKind::KeyedMaterialBindGroup, 1);
+}
+
+#[test]
+fn uniform_bind_groups_are_keyed_by_buffers() {
+    let (device, _queue, mut rm) = render_manager();
+    let camera = uniform_buffer(&device, 64);
+    let light = uniform_buffer(&device, 64);
+
+    let before = CacheSnapshot::take(&rm);
+    rm.uniform_bind_group_id(&[&camera]);
+    rm.uniform_bind_group_id(&[&camera]);
+    before.created_since(&rm).assert_created(CacheKind::UniformBindGroup, 1);
+
+    let before = CacheSnapshot::take(&rm);
+    rm.uniform_bind_group_id(&[&light]);
+    rm.uniform_bind_group_id(&[&camera, &light]);
+    before.created_since(&rm).assert_created(CacheKind::UniformBindGroup, 2);
+}
+
+#[test]
+fn builder_bind_groups_are_cached() {
+    let (device, _queue, mut rm) = render_manager();
+    let params = uniform_buffer(&device, 64);
+    let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+
+    let before = CacheSnapshot::take(&rm);
+    let first = rm.bind_group().uniform(&params).texture(&albedo).build();
+    let second = rm.bind_group().uniform(&params).texture(&albedo).build();
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::BuilderLayout, 1);
+    created.assert_created(CacheKind::BuilderBindGroup, 1);
+    assert_eq!(first.layout, second.layout);
+    assert_eq!(first.bind_group, second.bind_group);
+
+    let before = CacheSnapshot::take(&rm);
+    rm.bind_group().texture(&albedo).uniform(&params).build();
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::BuilderLayout, 1);
+    created.assert_created(CacheKind::BuilderBindGroup, 1);
+}
+
+#[test]
+fn builder_rejects_invalid_bindings() {
+    let (device, _queue, mut rm) = render_manager();
+    let params = uniform_buffer(&device, 64);
+    let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+
+    let before = CacheSnapshot::take(&rm);
+    let result = rm.bind_group().texture(&albedo).min_binding_size(16).try_build();
+    assert!(matches!(result, Err(CrmError::InvalidBinding(_))), "{:?}", result.err());
+    let result = rm.bind_group().uniform(&params).min_binding_size(256).try_build();
+    assert!(matches!(result, Err(CrmError::BufferTooSmall { .. })), "{:?}", result.err());
+    assert_eq!(before.created_since(&rm).total(), 0);
+
+    assert!(rm.bind_group().uniform(&params).min_binding_size(64).try_build().is_ok());
+}
+
+#[test]
+fn deferred_mutations_stage_until_begin_frame() {
+    let (device, _queue, mut rm) = render_manager();
+    let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+    let camera = uniform_buffer(&device, 64);
+    rm.set_deferred_cache_mutations(true);
+
+    // Staged entries are visible and hit before they are committed.
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&albedo], &[]);
+    rm.material_bind_group(&[&albedo], &[]);
+    rm.uniform_bind_group_id(&[&camera]);
+    rm.uniform_bind_group_id(&[&camera]);
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::MaterialBindGroup, 1);
+    created.assert_created(CacheKind::UniformBindGroup, 1);
+
+    // Invalidation waits for the frame boundary.
+    rm.invalidate_bind_groups();
+    assert_eq!(rm.cache_entries().iter().filter(|e| e.kind == CacheKind::MaterialBindGroup).count(), 1);
+    rm.begin_frame();
+    assert!(rm.cache_entries().iter().all(|e| e.kind != CacheKind::MaterialBindGroup && e.kind != CacheKind::UniformBindGroup));
+
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&albedo], &[]);
+    rm.begin_frame();
+    rm.material_bind_group(&[&albedo], &[]);
+    before.created_since(&rm).assert_created(CacheKind::MaterialBindGroup, 1);
+}
+
+#[test]
+fn invalidation_recreates_bind_groups_but_keeps_layouts() {
+    let (device, _queue, mut rm) = render_manager();
+    let albedo = test_texture(&device, 4, 4, TextureFormat::Rgba8UnormSrgb);
+    let params = uniform_buffer(&device, 64);
+    rm.material_bind_group(&[&albedo], &[]);
+    rm.bind_group().uniform(&params).build();
+
+    rm.invalidate_bind_groups();
+    let before = CacheSnapshot::take(&rm);
+    rm.material_bind_group(&[&albedo], &[]);
+    rm.bind_group().uniform(&params).build();
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::MaterialBindGroup, 1);
+    created.assert_created(CacheKind::BuilderBindGroup, 1);
+    assert_eq!(created.layouts(), 0);
+}
+
+#[test]
+fn uniform_ring_offsets_advance_by_stride() {
+    let (device, queue) = noop_device();
+    let mut ring = UniformRing::new(&device, &queue, "test ring", 64, 2);
+    let layout = DynamicOffsets::new(&device, BufferBindingType::Uniform, 64);
+
+    let offsets: Vec<u32> = (0..4u32).map(|i| ring.try_push(&[i; 16]).unwrap()).collect();
+    assert_eq!(offsets, (0..4).map(|i| layout.offset(i)).collect::<Vec<_>>());
+    assert_eq!(ring.len(), 4);
+
+    // Flushing past the capacity grows the buffer to fit every block.
+    ring.flush();
+    assert!(ring.buffer().size() >= layout.buffer_size(4));
+
+    ring.reset();
+    assert!(ring.is_empty());
+    assert_eq!(ring.push(&[0u32; 16]), 0);
+}
+
+#[test]
+fn uniform_ring_bind_group_is_cached_by_the_builder() {
+    let (device, queue) = noop_device();
+    let mut rm = RenderManager::new(&device, &queue, "shaders".into());
+    let mut ring = UniformRing::new(&device, &queue, "test ring", 64, 4);
+    ring.push(&[1.0f32; 16]);
+    ring.flush();
+
+    let before = CacheSnapshot::take(&rm);
+    rm.bind_group().uniform_ring(&ring).build();
+    rm.bind_group().uniform_ring(&ring).build();
+    let created = before.created_since(&rm);
+    created.assert_created(CacheKind::BuilderLayout, 1);
+    created.assert_created(CacheKind::BuilderBindGroup, 1);
+}
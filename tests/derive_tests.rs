@@ -0,0 +1,139 @@
+use wgpu::{BindingType, Buffer, BufferBindingType, BufferSize, Sampler, SamplerBindingType, ShaderStages, StorageTextureAccess, TextureFormat, TextureSampleType, TextureView, TextureViewDimension};
+use wgpu_render_manager::typed_bind_group::{BindGroupLayout, TypedBindGroup};
+
+#[allow(dead_code)]
+#[derive(BindGroupLayout)]
+struct WaterBindings<'a> {
+    #[sampler]
+    linear_sampler: &'a Sampler,
+    #[texture]
+    normal_map: &'a TextureView,
+    #[texture(sample = "depth")]
+    scene_depth: &'a TextureView,
+    #[uniform(ty = "WaterParams", visibility = "vertex | fragment", min_size = 48)]
+    params: &'a Buffer,
+}
+
+#[allow(dead_code)]
+#[derive(BindGroupLayout)]
+struct SimulationBindings {
+    #[storage(ty = "array<Particle>")]
+    particles: Buffer,
+    #[storage(ty = "array<u32>", read_only, visibility = "compute")]
+    indices: Buffer,
+    #[uniform(ty = "DrawParams", dynamic, size = 64)]
+    draw: Buffer,
+    #[storage_texture(format = "rgba16float", dimension = "2d_array")]
+    output: TextureView,
+    #[sampler(comparison)]
+    shadow_sampler: Sampler,
+    #[texture(sample = "unfilterable", dimension = "cube")]
+    environment: TextureView,
+    #[texture(multisampled)]
+    msaa_color: TextureView,
+}
+
+#[test]
+fn layout_entries_follow_field_order_and_attributes() {
+    let entries = WaterBindings::layout_entries();
+    assert_eq!(entries.iter().map(|e| e.binding).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    assert_eq!(entries[0].ty, BindingType::Sampler(SamplerBindingType::Filtering));
+    assert_eq!(
+        entries[1].ty,
+        BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        }
+    );
+    assert_eq!(
+        entries[2].ty,
+        BindingType::Texture {
+            sample_type: TextureSampleType::Depth,
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        }
+    );
+    assert_eq!(
+        entries[3].ty,
+        BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(48),
+        }
+    );
+    assert_eq!(entries[3].visibility, ShaderStages::VERTEX_FRAGMENT);
+    assert_eq!(entries[0].visibility, ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE);
+    assert!(entries.iter().all(|e| e.count.is_none()));
+    assert_eq!(WaterBindings::LABEL, "WaterBindings");
+}
+
+#[test]
+fn writable_bindings_are_hidden_from_vertex_shaders() {
+    let entries = SimulationBindings::layout_entries();
+    assert_eq!(
+        entries[0].ty,
+        BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }
+    );
+    assert_eq!(entries[0].visibility, ShaderStages::FRAGMENT | ShaderStages::COMPUTE);
+    assert_eq!(entries[1].visibility, ShaderStages::COMPUTE);
+    assert_eq!(
+        entries[2].ty,
+        BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: BufferSize::new(64),
+        }
+    );
+    assert_eq!(
+        entries[3].ty,
+        BindingType::StorageTexture {
+            access: StorageTextureAccess::WriteOnly,
+            format: TextureFormat::Rgba16Float,
+            view_dimension: TextureViewDimension::D2Array,
+        }
+    );
+    assert_eq!(entries[3].visibility, ShaderStages::FRAGMENT | ShaderStages::COMPUTE);
+    assert_eq!(entries[4].ty, BindingType::Sampler(SamplerBindingType::Comparison));
+    assert_eq!(
+        entries[5].ty,
+        BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::Cube,
+            multisampled: false,
+        }
+    );
+    assert_eq!(
+        entries[6].ty,
+        BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: true,
+        }
+    );
+}
+
+#[test]
+fn wgsl_declares_every_binding_in_the_group() {
+    assert_eq!(
+        WaterBindings::wgsl(2),
+        "@group(2) @binding(0) var linear_sampler: sampler;\n\
+         @group(2) @binding(1) var normal_map: texture_2d<f32>;\n\
+         @group(2) @binding(2) var scene_depth: texture_depth_2d;\n\
+         @group(2) @binding(3) var<uniform> params: WaterParams;\n"
+    );
+    assert_eq!(
+        SimulationBindings::wgsl(0),
+        "@group(0) @binding(0) var<storage, read_write> particles: array<Particle>;\n\
+         @group(0) @binding(1) var<storage, read> indices: array<u32>;\n\
+         @group(0) @binding(2) var<uniform> draw: DrawParams;\n\
+         @group(0) @binding(3) var output: texture_storage_2d_array<rgba16float, write>;\n\
+         @group(0) @binding(4) var shadow_sampler: sampler_comparison;\n\
+         @group(0) @binding(5) var environment: texture_cube<f32>;\n\
+         @group(0) @binding(6) var msaa_color: texture_multisampled_2d<f32>;\n"
+    );
+}